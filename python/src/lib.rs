@@ -0,0 +1,152 @@
+//! Python bindings: `process_path` generates items for files, `generate` runs the whole
+//! pipeline like the CLI, and `write` sends items to any sink.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use clap::ValueEnum;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use tokio::runtime::Runtime;
+use dataset_builder::config::SinkConfig;
+use dataset_builder::{writer, Config, DatasetBuilder, ProcessedItem, RecordFormat};
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start the tokio runtime"))
+}
+
+fn runtime_error(e: impl std::fmt::Display) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// Converts anything serializable to Python objects through `json.loads`.
+fn to_python<T: serde::Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(runtime_error)?;
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Loads `config` (or the default config file in the working directory) and applies
+/// the keyword arguments on top of it, like the CLI flags.
+fn builder(
+    config: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    model: Option<&str>,
+    endpoint: Option<&str>,
+    profile: Option<&str>,
+) -> PyResult<DatasetBuilder> {
+    let mut builder = DatasetBuilder::from_config(Config::load(config.as_deref()).map_err(runtime_error)?);
+    if let Some(dir) = output_dir {
+        builder = builder.with_output_dir(dir);
+    }
+    if let Some(model) = model {
+        builder = builder.with_model(model);
+    }
+    if let Some(endpoint) = endpoint {
+        builder = builder.with_endpoint(endpoint);
+    }
+    if let Some(profile) = profile {
+        builder = builder.with_profile(profile);
+    }
+    builder.config().validate().map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(builder)
+}
+
+/// Markdown and text files at `path`, which may be a file or a directory, in name order.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_files(&entry, files)?;
+        } else if entry.extension().is_some_and(|ext| ext == "md" || ext == "txt") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Generates items for a file, or every Markdown and text file under a directory, and
+/// returns them as a list of dicts. Each file's items are also saved next to it as
+/// `<name>_qa.jsonl`, and files that already have enough items are not regenerated.
+#[pyfunction]
+#[pyo3(signature = (path, *, config=None, model=None, endpoint=None, profile=None))]
+fn process_path(
+    py: Python<'_>,
+    path: PathBuf,
+    config: Option<PathBuf>,
+    model: Option<&str>,
+    endpoint: Option<&str>,
+    profile: Option<&str>,
+) -> PyResult<PyObject> {
+    let generator = builder(config, None, model, endpoint, profile)?.generator().map_err(runtime_error)?;
+    let mut files = Vec::new();
+    collect_files(&path, &mut files)?;
+    let items = py
+        .allow_threads(|| {
+            runtime().block_on(async {
+                let mut items = Vec::new();
+                for file in &files {
+                    items.extend(generator.generate_file(file).await?.0);
+                }
+                anyhow::Ok(items)
+            })
+        })
+        .map_err(runtime_error)?;
+    to_python(py, &items)
+}
+
+/// Collects `sources` (URLs, GitHub URLs or local paths) into the output directory and
+/// generates a dataset from them, like `llm_dataset_builder generate`. Without sources,
+/// the files already in the output directory are used. Returns the run report as a
+/// dict, or None when there was nothing to process.
+#[pyfunction]
+#[pyo3(signature = (sources=Vec::new(), *, output_dir=None, config=None, model=None, endpoint=None, profile=None))]
+fn generate(
+    py: Python<'_>,
+    sources: Vec<String>,
+    output_dir: Option<PathBuf>,
+    config: Option<PathBuf>,
+    model: Option<&str>,
+    endpoint: Option<&str>,
+    profile: Option<&str>,
+) -> PyResult<PyObject> {
+    let builder = sources
+        .iter()
+        .fold(builder(config, output_dir, model, endpoint, profile)?, |builder, source| builder.with_source(source));
+    let report = py.allow_threads(|| runtime().block_on(builder.run())).map_err(runtime_error)?;
+    to_python(py, &report)
+}
+
+/// Writes items (dicts with at least `question` and `answer`) to a sink given like the
+/// `--sink` flag: `jsonl:PATH`, `parquet:PATH`, `arrow:PATH`, `sqlite:PATH`, `http:URL` or
+/// `stdout`. `format` (`qa`, `sharegpt` or `openai`) applies to JSONL, HTTP and stdout
+/// sinks.
+#[pyfunction]
+#[pyo3(signature = (items, sink, *, format=None))]
+fn write(py: Python<'_>, items: &Bound<'_, PyAny>, sink: &str, format: Option<&str>) -> PyResult<()> {
+    let json: String = py.import("json")?.call_method1("dumps", (items,))?.extract()?;
+    let items: Vec<ProcessedItem> = serde_json::from_str(&json).map_err(|e| PyValueError::new_err(format!("Invalid items: {}", e)))?;
+    let mut sink = SinkConfig::from_str(sink).map_err(PyValueError::new_err)?;
+    if let Some(format) = format {
+        let format = RecordFormat::from_str(format, true).map_err(PyValueError::new_err)?;
+        match &mut sink {
+            SinkConfig::Jsonl { format: f, .. } | SinkConfig::Http { format: f, .. } | SinkConfig::Stdout { format: f, .. } => *f = format,
+            _ => return Err(PyValueError::new_err("format only applies to jsonl, http and stdout sinks")),
+        }
+    }
+    let writer = writer::from_config(&sink, Path::new(""));
+    py.allow_threads(|| runtime().block_on(writer.write(&items))).map_err(runtime_error)
+}
+
+#[pymodule]
+fn llm_dataset_builder(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(process_path, m)?)?;
+    m.add_function(wrap_pyfunction!(generate, m)?)?;
+    m.add_function(wrap_pyfunction!(write, m)?)?;
+    Ok(())
+}
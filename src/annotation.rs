@@ -0,0 +1,281 @@
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+use crate::dataset;
+use crate::processor::{ItemSource, ProcessedItem};
+use crate::review::Decision;
+
+/// Annotation platforms items can be sent to for human review.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AnnotationTool {
+    /// Argilla 2.x; the project is the dataset id and the dataset needs `question` and
+    /// `answer` text fields
+    Argilla,
+    /// Label Studio; the project is the numeric project id
+    LabelStudio,
+}
+
+/// Connection to one project (Argilla dataset or Label Studio project).
+pub struct AnnotationClient {
+    tool: AnnotationTool,
+    client: Client,
+    url: String,
+    project: String,
+    api_key: String,
+}
+
+const ARGILLA_PAGE_SIZE: usize = 500;
+
+impl AnnotationClient {
+    pub fn new(tool: AnnotationTool, url: &str, project: &str, api_key: &str) -> Self {
+        Self {
+            tool,
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            project: project.to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.tool {
+            AnnotationTool::Argilla => request.header("X-Argilla-Api-Key", &self.api_key),
+            AnnotationTool::LabelStudio => request.header("Authorization", format!("Token {}", self.api_key)),
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Value> {
+        let response = self.authorized(request).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!("{:?} API error ({}): {}", self.tool, status, body));
+        }
+        Ok(if body.trim().is_empty() { Value::Null } else { serde_json::from_str(&body)? })
+    }
+
+    /// Uploads items as records to annotate. The item's position in the dataset is sent
+    /// along so imports can be traced back to it.
+    pub async fn export(&self, items: &[ProcessedItem]) -> Result<()> {
+        match self.tool {
+            AnnotationTool::Argilla => {
+                for (chunk_index, chunk) in items.chunks(ARGILLA_PAGE_SIZE).enumerate() {
+                    let records: Vec<Value> = chunk
+                        .iter()
+                        .enumerate()
+                        .map(|(i, item)| {
+                            json!({
+                                "external_id": (chunk_index * ARGILLA_PAGE_SIZE + i).to_string(),
+                                "fields": { "question": item.question, "answer": item.answer },
+                                "metadata": Self::metadata(item),
+                            })
+                        })
+                        .collect();
+                    let url = format!("{}/api/v1/datasets/{}/records/bulk", self.url, self.project);
+                    self.send(self.client.post(url).json(&json!({ "items": records }))).await?;
+                    debug!("Uploaded {} records to Argilla", records.len());
+                }
+            }
+            AnnotationTool::LabelStudio => {
+                let tasks: Vec<Value> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let mut data = Self::metadata(item);
+                        data.insert("question".to_string(), json!(item.question));
+                        data.insert("answer".to_string(), json!(item.answer));
+                        data.insert("item_index".to_string(), json!(i));
+                        json!({ "data": data })
+                    })
+                    .collect();
+                let url = format!("{}/api/projects/{}/import", self.url, self.project);
+                self.send(self.client.post(url).json(&tasks)).await?;
+            }
+        }
+        info!("Exported {} items to {:?} project {}", items.len(), self.tool, self.project);
+        Ok(())
+    }
+
+    /// Extra fields, the citation and the source file, as flat metadata both platforms
+    /// can display.
+    fn metadata(item: &ProcessedItem) -> serde_json::Map<String, Value> {
+        let mut metadata = item.extra.clone();
+        if let Some(citation) = &item.citation {
+            metadata.insert("citation".to_string(), json!(citation));
+        }
+        if let Some(id) = &item.id {
+            metadata.insert("id".to_string(), json!(id));
+        }
+        if let Some(source) = &item.source {
+            metadata.insert("source_file".to_string(), json!(source.file));
+            metadata.insert("source_section".to_string(), json!(source.section));
+            if let Some(chunk) = &source.chunk {
+                metadata.insert("source_chunk".to_string(), json!(chunk));
+            }
+            if let Some(image) = &source.image {
+                metadata.insert("source_image".to_string(), json!(image));
+            }
+            if let Some(modified) = &source.modified {
+                metadata.insert("source_modified".to_string(), json!(modified));
+            }
+        }
+        metadata
+    }
+
+    /// Downloads annotated records. Accepted records come back as items, with any
+    /// corrected question or answer applied; rejected and unannotated records are left
+    /// out.
+    pub async fn import(&self) -> Result<Vec<ProcessedItem>> {
+        let records = match self.tool {
+            AnnotationTool::Argilla => self.argilla_records().await?,
+            AnnotationTool::LabelStudio => self.label_studio_records().await?,
+        };
+        let mut items = Vec::new();
+        let mut rejected = 0;
+        for (decision, mut item) in records {
+            match decision {
+                Decision::Accepted => {
+                    Decision::Accepted.apply(&mut item);
+                    dataset::identify(&mut item);
+                    items.push(item);
+                }
+                Decision::Rejected => rejected += 1,
+                Decision::Pending => {}
+            }
+        }
+        info!("Imported {} accepted items from {:?} project {} ({} rejected)", items.len(), self.tool, self.project, rejected);
+        Ok(items)
+    }
+
+    /// Rebuilds an item from its text fields and the metadata written by `export`.
+    fn item_from(fields: &Value, metadata: &Value) -> ProcessedItem {
+        let text = |key: &str| fields.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+        let mut extra = metadata.as_object().cloned().unwrap_or_default();
+        for key in ["question", "answer", "item_index"] {
+            extra.remove(key);
+        }
+        let id = match extra.remove("id") {
+            Some(Value::String(id)) => Some(id),
+            _ => None,
+        };
+        let chunk = match extra.remove("source_chunk") {
+            Some(Value::String(chunk)) => Some(chunk),
+            _ => None,
+        };
+        let image = match extra.remove("source_image") {
+            Some(Value::String(image)) => Some(image),
+            _ => None,
+        };
+        let modified = match extra.remove("source_modified") {
+            Some(Value::String(modified)) => modified.parse().ok(),
+            _ => None,
+        };
+        let source = match (extra.remove("source_file"), extra.remove("source_section")) {
+            (Some(Value::String(file)), Some(section)) => section.as_u64().map(|section| ItemSource {
+                file,
+                section: section as usize,
+                chunk,
+                image,
+                modified,
+            }),
+            _ => None,
+        };
+        let citation = match extra.remove("citation") {
+            Some(Value::String(citation)) => Some(citation),
+            _ => None,
+        };
+        ProcessedItem {
+            id,
+            question: text("question"),
+            answer: text("answer"),
+            citation,
+            source,
+            hash: None,
+            extra,
+            embedding: None,
+            source_overlap: None,
+        }
+    }
+
+    async fn argilla_records(&self) -> Result<Vec<(Decision, ProcessedItem)>> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        loop {
+            let url = format!(
+                "{}/api/v1/datasets/{}/records?include=responses&offset={}&limit={}",
+                self.url, self.project, offset, ARGILLA_PAGE_SIZE
+            );
+            let page = self.send(self.client.get(url)).await?;
+            let page_items = page.get("items").and_then(Value::as_array).cloned().unwrap_or_default();
+            for record in &page_items {
+                let mut item = Self::item_from(&record["fields"], &record["metadata"]);
+                let responses = record.get("responses").and_then(Value::as_array).cloned().unwrap_or_default();
+                let status = |wanted: &str| responses.iter().find(|r| r.get("status").and_then(Value::as_str) == Some(wanted));
+                let decision = if let Some(response) = status("submitted") {
+                    // Questions named `question` or `answer` hold corrections
+                    for (field, target) in [("question", &mut item.question), ("answer", &mut item.answer)] {
+                        if let Some(value) = response.pointer(&format!("/values/{}/value", field)).and_then(Value::as_str) {
+                            *target = value.to_string();
+                        }
+                    }
+                    Decision::Accepted
+                } else if status("discarded").is_some() {
+                    Decision::Rejected
+                } else {
+                    Decision::Pending
+                };
+                records.push((decision, item));
+            }
+            if page_items.len() < ARGILLA_PAGE_SIZE {
+                return Ok(records);
+            }
+            offset += page_items.len();
+        }
+    }
+
+    async fn label_studio_records(&self) -> Result<Vec<(Decision, ProcessedItem)>> {
+        let url = format!("{}/api/projects/{}/export?exportType=JSON", self.url, self.project);
+        let tasks = self.send(self.client.get(url)).await?;
+        let tasks = tasks.as_array().ok_or_else(|| anyhow!("Unexpected Label Studio export format"))?;
+
+        Ok(tasks
+            .iter()
+            .map(|task| {
+                let mut item = Self::item_from(&task["data"], &task["data"]);
+                let annotation = task
+                    .get("annotations")
+                    .and_then(Value::as_array)
+                    .and_then(|annotations| {
+                        annotations
+                            .iter()
+                            .rev()
+                            .find(|a| !a.get("was_cancelled").and_then(Value::as_bool).unwrap_or(false))
+                    });
+                let Some(annotation) = annotation else {
+                    return (Decision::Pending, item);
+                };
+
+                let mut decision = Decision::Accepted;
+                for result in annotation.get("result").and_then(Value::as_array).into_iter().flatten() {
+                    let value = result.get("value").unwrap_or(&Value::Null);
+                    // A choice of "Reject" rejects the item; textareas named `question` or
+                    // `answer` hold corrections
+                    if let Some(choices) = value.get("choices").and_then(Value::as_array) {
+                        if choices.iter().any(|c| c.as_str().is_some_and(|c| c.eq_ignore_ascii_case("reject"))) {
+                            decision = Decision::Rejected;
+                        }
+                    }
+                    let corrected = value.pointer("/text/0").and_then(Value::as_str);
+                    match (result.get("from_name").and_then(Value::as_str), corrected) {
+                        (Some("question"), Some(text)) => item.question = text.to_string(),
+                        (Some("answer"), Some(text)) => item.answer = text.to_string(),
+                        _ => {}
+                    }
+                }
+                (decision, item)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,71 @@
+use std::ops::RangeInclusive;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Field recording the preset an item's answer was generated for.
+pub const FIELD: &str = "answer_length";
+
+pub const FIT_SYSTEM_PROMPT: &str = "You rewrite answers in a question and answer dataset to a required length. \
+    Keep the facts of the answer and the content, adding detail from the content when the answer must be longer \
+    and leaving out the least important details when it must be shorter. Reply with the answer only.";
+
+/// How long generated answers should be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnswerLength {
+    /// One short sentence, for chat
+    Terse,
+    /// A few sentences
+    Standard,
+    /// Thorough explanations with examples, for documentation completion
+    Detailed,
+}
+
+impl AnswerLength {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AnswerLength::Terse => "terse",
+            AnswerLength::Standard => "standard",
+            AnswerLength::Detailed => "detailed",
+        }
+    }
+
+    /// Words an answer of this length has.
+    pub fn words(self) -> RangeInclusive<usize> {
+        match self {
+            AnswerLength::Terse => 1..=30,
+            AnswerLength::Standard => 10..=120,
+            AnswerLength::Detailed => 60..=300,
+        }
+    }
+
+    /// The `answer_length` prompt variable.
+    pub fn instruction(self) -> &'static str {
+        match self {
+            AnswerLength::Terse => "Keep every answer to one short sentence of at most 30 words.",
+            AnswerLength::Standard => "Answer each question in a few sentences, 10 to 120 words.",
+            AnswerLength::Detailed => {
+                "Give thorough answers of 60 to 300 words that explain how and why, with an example or the exact \
+                 settings or commands when the content has them."
+            }
+        }
+    }
+
+    pub fn fits(self, answer: &str) -> bool {
+        self.words().contains(&answer.split_whitespace().count())
+    }
+
+    /// The user message asking for `answer` rewritten to this length.
+    pub fn fit_prompt(self, question: &str, answer: &str, section: &str) -> String {
+        let words = self.words();
+        format!(
+            "Rewrite the answer in {} to {} words. {}\n\nQuestion: {}\nAnswer: {}\n\nContent:\n{}",
+            words.start(),
+            words.end(),
+            self.instruction(),
+            question,
+            answer,
+            section
+        )
+    }
+}
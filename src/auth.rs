@@ -0,0 +1,249 @@
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use reqwest::RequestBuilder;
+use url::Url;
+use crate::config::{AuthConfig, HostCredentials};
+
+/// A `login` and `password` of a netrc file.
+#[derive(Debug, Clone)]
+struct Login {
+    login: String,
+    password: String,
+}
+
+/// The `machine` entries of a netrc file, and its `default` one.
+#[derive(Debug, Clone, Default)]
+struct Netrc {
+    machines: Vec<(String, Login)>,
+    default: Option<Login>,
+}
+
+/// The credentials a source sends with its requests: those set for the host in
+/// `[auth.hosts]`, or else the host's netrc login.
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    hosts: Vec<(String, HostCredentials)>,
+    netrc: Netrc,
+}
+
+impl Auth {
+    /// The credentials of `config`, reading its netrc file.
+    pub fn load(config: &AuthConfig) -> Result<Self> {
+        let netrc = match &config.netrc {
+            Some(path) => read_netrc(path)?,
+            None => Netrc::default(),
+        };
+        Ok(Self {
+            hosts: config.hosts.iter().map(|(host, credentials)| (host.to_lowercase(), credentials.clone())).collect(),
+            netrc,
+        })
+    }
+
+    /// The `[auth.hosts]` entry for `url`: the one naming its host (with its port, or
+    /// without), or else the longest `*.domain` it is under.
+    fn host(&self, url: &Url) -> Option<&HostCredentials> {
+        let host = url.host_str()?.to_lowercase();
+        let with_port = url.port().map(|port| format!("{}:{}", host, port));
+        let exact = self.hosts.iter().find(|(name, _)| Some(name) == with_port.as_ref()).or_else(|| self.hosts.iter().find(|(name, _)| *name == host));
+        let wildcard = || {
+            self.hosts
+                .iter()
+                .filter(|(name, _)| name.strip_prefix('*').is_some_and(|domain| domain.starts_with('.') && host.ends_with(domain)))
+                .max_by_key(|(name, _)| name.len())
+        };
+        exact.or_else(wildcard).map(|(_, credentials)| credentials)
+    }
+
+    /// `request`, to `url`, with the credentials for its host. Nothing is attached to
+    /// URLs other than https, and the netrc `default` login only goes to hosts with an
+    /// `[auth.hosts]` entry, so a crawl following a link elsewhere can't leak it.
+    pub fn apply(&self, request: RequestBuilder, url: &Url) -> RequestBuilder {
+        if url.scheme() != "https" {
+            return request;
+        }
+        let host = url.host_str().unwrap_or("").to_lowercase();
+        let configured = self.host(url);
+        let login = || {
+            self.netrc
+                .machines
+                .iter()
+                .find(|(machine, _)| machine.eq_ignore_ascii_case(&host))
+                .map(|(_, login)| login)
+                .or(configured.and(self.netrc.default.as_ref()))
+        };
+        let Some(credentials) = configured else {
+            return match login() {
+                Some(login) => request.basic_auth(&login.login, Some(&login.password)),
+                None => request,
+            };
+        };
+        let request = match (&credentials.bearer, &credentials.username) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(username)) => request.basic_auth(username, credentials.password.as_ref()),
+            // Cookies alone, with the netrc login if there is one
+            (None, None) => match login() {
+                Some(login) => request.basic_auth(&login.login, Some(&login.password)),
+                None => request,
+            },
+        };
+        match credentials.cookies.is_empty() {
+            true => request,
+            false => {
+                let cookies: Vec<String> = credentials.cookies.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+                request.header("Cookie", cookies.join("; "))
+            }
+        }
+    }
+}
+
+/// The netrc file at `path`.
+fn read_netrc(path: &Path) -> Result<Netrc> {
+    let text = std::fs::read_to_string(path).map_err(|e| anyhow!("Failed to read netrc file {:?}: {}", path, e))?;
+    // Macro definitions run to the next blank line, and aren't credentials
+    let mut macro_definition = false;
+    let mut tokens = Vec::new();
+    for line in text.lines() {
+        if macro_definition {
+            macro_definition = !line.trim().is_empty();
+            continue;
+        }
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for token in line.split_whitespace() {
+            if token == "macdef" {
+                macro_definition = true;
+                break;
+            }
+            tokens.push(token);
+        }
+    }
+
+    let mut netrc = Netrc::default();
+    // The entry being read: its machine (`None` for the default one) and login
+    let mut entry: Option<(Option<String>, String, String)> = None;
+    let mut finish = |entry: Option<(Option<String>, String, String)>| match entry {
+        Some((Some(machine), login, password)) => netrc.machines.push((machine, Login { login, password })),
+        Some((None, login, password)) => netrc.default = Some(Login { login, password }),
+        None => {}
+    };
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => finish(entry.replace((Some(tokens.next().unwrap_or("").to_string()), String::new(), String::new()))),
+            "default" => finish(entry.replace((None, String::new(), String::new()))),
+            "login" | "password" | "account" => {
+                let value = tokens.next().unwrap_or("").to_string();
+                if let Some((_, login, password)) = &mut entry {
+                    match token {
+                        "login" => *login = value,
+                        "password" => *password = value,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(entry);
+    Ok(netrc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use reqwest::Client;
+    use reqwest::header::{AUTHORIZATION, COOKIE, HeaderName};
+
+    const NETRC: &str = "# work machines\n\
+        machine wiki.example.com login alice password s3cret\n\
+        macdef init\n\
+        login mallory password macro\n\
+        \n\
+        machine Docs.Example.com\n  login bob\n  account ops\n  password hunter2\n\
+        default login anonymous password guest\n";
+
+    /// `auth` with a netrc file holding `NETRC`.
+    fn auth(hosts: &[(&str, HostCredentials)]) -> Auth {
+        let path = std::env::temp_dir().join(format!("llmds-netrc-{:016x}", rand::random::<u64>()));
+        fs::write(&path, NETRC).unwrap();
+        let config = AuthConfig {
+            netrc: Some(path.clone()),
+            hosts: hosts.iter().map(|(host, credentials)| (host.to_string(), credentials.clone())).collect(),
+        };
+        let auth = Auth::load(&config).unwrap();
+        fs::remove_file(path).unwrap();
+        auth
+    }
+
+    fn bearer(token: &str) -> HostCredentials {
+        HostCredentials { bearer: Some(token.to_string()), ..HostCredentials::default() }
+    }
+
+    /// The `Authorization` and `Cookie` headers `auth` sends to `url`.
+    fn headers(auth: &Auth, url: &str) -> (Option<String>, Option<String>) {
+        let url = Url::parse(url).unwrap();
+        let request = auth.apply(Client::new().get(url.clone()), &url).build().unwrap();
+        let header = |name: HeaderName| request.headers().get(name).map(|value| value.to_str().unwrap().to_string());
+        (header(AUTHORIZATION), header(COOKIE))
+    }
+
+    fn basic(login: &str, password: &str) -> Option<String> {
+        Some(format!("Basic {}", BASE64.encode(format!("{}:{}", login, password))))
+    }
+
+    #[test]
+    fn netrc_entries_are_read() {
+        let netrc = auth(&[]).netrc;
+        let machines: Vec<(&str, &str, &str)> =
+            netrc.machines.iter().map(|(machine, login)| (machine.as_str(), login.login.as_str(), login.password.as_str())).collect();
+        assert_eq!(machines, [("wiki.example.com", "alice", "s3cret"), ("Docs.Example.com", "bob", "hunter2")]);
+        let default = netrc.default.unwrap();
+        assert_eq!((default.login.as_str(), default.password.as_str()), ("anonymous", "guest"));
+        assert!(Auth::load(&AuthConfig { netrc: Some("/nonexistent/netrc".into()), hosts: BTreeMap::new() }).is_err());
+    }
+
+    #[test]
+    fn hosts_match_exactly_or_by_the_longest_wildcard() {
+        let auth = auth(&[
+            ("Wiki.Example.com", bearer("wiki")),
+            ("wiki.example.com:8443", bearer("port")),
+            ("*.example.com", bearer("example")),
+            ("*.corp.example.com", bearer("corp")),
+        ]);
+        let token = |url: &str| auth.host(&Url::parse(url).unwrap()).and_then(|credentials| credentials.bearer.clone());
+        assert_eq!(token("https://wiki.example.com/page").as_deref(), Some("wiki"));
+        assert_eq!(token("https://wiki.example.com:8443/page").as_deref(), Some("port"));
+        assert_eq!(token("https://git.corp.example.com/").as_deref(), Some("corp"));
+        assert_eq!(token("https://blog.example.com/").as_deref(), Some("example"));
+        assert_eq!(token("https://example.com/"), None);
+        assert_eq!(token("https://notexample.com/"), None);
+    }
+
+    #[test]
+    fn credentials_are_sent_over_https_only() {
+        let cookies = HostCredentials {
+            cookies: BTreeMap::from([("session".to_string(), "abc".to_string())]),
+            ..HostCredentials::default()
+        };
+        let auth = auth(&[("api.example.com", bearer("token")), ("sso.example.com", cookies)]);
+        assert_eq!(headers(&auth, "https://api.example.com/"), (Some("Bearer token".to_string()), None));
+        assert_eq!(headers(&auth, "https://wiki.example.com/"), (basic("alice", "s3cret"), None));
+        assert_eq!(headers(&auth, "https://docs.example.com/"), (basic("bob", "hunter2"), None));
+        assert_eq!(headers(&auth, "https://sso.example.com/"), (basic("anonymous", "guest"), Some("session=abc".to_string())));
+        for url in ["http://api.example.com/", "http://wiki.example.com/", "http://sso.example.com/", "ftp://wiki.example.com/"] {
+            assert_eq!(headers(&auth, url), (None, None), "{}", url);
+        }
+    }
+
+    #[test]
+    fn the_default_login_only_goes_to_configured_hosts() {
+        let auth = auth(&[]);
+        assert_eq!(headers(&auth, "https://elsewhere.example.org/"), (None, None));
+        assert_eq!(headers(&auth, "https://wiki.example.com/"), (basic("alice", "s3cret"), None));
+    }
+}
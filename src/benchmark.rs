@@ -0,0 +1,411 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use anyhow::{Result, anyhow};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+use crate::compression;
+use crate::config::{self, Config};
+use crate::dataset;
+use crate::metrics::Metrics;
+use crate::pipeline::{ChunkStage, FileWork, ParseStage, Stage};
+use crate::plugins::Plugins;
+use crate::preflight;
+use crate::processor::{OllamaProcessor, ProcessedItem};
+use crate::score::Scorer;
+
+/// How one model or prompt did on the sample sections.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Scorecard {
+    pub name: String,
+    pub sections: usize,
+    /// Sections that failed even after retries and fallbacks.
+    pub failed_sections: usize,
+    pub items: usize,
+    /// Mean judge rating (1-5) of the items that could be rated.
+    pub mean_score: Option<f64>,
+    pub requests: u64,
+    pub parse_failures: u64,
+    /// Time spent generating, without the judge's.
+    pub seconds: f64,
+    pub completion_tokens: u64,
+}
+
+impl Scorecard {
+    /// Share of requests whose response couldn't be parsed into items.
+    pub fn parse_failure_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.parse_failures as f64 / self.requests as f64
+    }
+
+    pub fn seconds_per_section(&self) -> f64 {
+        self.seconds / self.sections.max(1) as f64
+    }
+
+    pub fn tokens_per_second(&self) -> f64 {
+        if self.seconds == 0.0 {
+            return 0.0;
+        }
+        self.completion_tokens as f64 / self.seconds
+    }
+}
+
+/// A generated item with the judge's rating, `None` when it couldn't be rated.
+#[derive(Debug, Clone, Serialize)]
+pub struct RatedItem {
+    #[serde(flatten)]
+    pub item: ProcessedItem,
+    pub score: Option<u8>,
+}
+
+/// What the two prompts of a comparison generated from one sample section.
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionPair {
+    pub file: PathBuf,
+    /// 1-based, as in logs.
+    pub section: usize,
+    pub heading: String,
+    pub a: Vec<RatedItem>,
+    pub b: Vec<RatedItem>,
+}
+
+/// Result of `compare_prompts`: the items of both prompts side by side, and their totals.
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptComparison {
+    pub scorecards: [Scorecard; 2],
+    pub sections: Vec<SectionPair>,
+}
+
+/// Generates from the same sections of `sample` (a file or directory) with each of
+/// `models`, rates every item with the `judge` model and returns how each model did.
+/// At most `max_sections` sections are drawn from the sample, at random (with the
+/// configured seed) when it has more.
+pub async fn run(config: &Config, models: &[String], judge: &str, sample: &Path, max_sections: usize) -> Result<Vec<Scorecard>> {
+    if config.preflight {
+        preflight::check_models(&config.endpoint, models.iter().map(String::as_str).chain([judge])).await?;
+    }
+    let mut works = sample_sections(config, sample, max_sections).await?;
+    info!("Benchmarking {} models on {} sections from {} files", models.len(), section_count(&works), works.len());
+
+    let scorer = Scorer::new(config).with_model(judge);
+    let mut scorecards = Vec::new();
+    for model in models {
+        // Profiles may name their own model; the benchmark overrides it
+        for settings in works.iter_mut().filter_map(|work| work.settings.as_mut()) {
+            settings.model = model.clone();
+        }
+        let mut config = config.clone();
+        config.model = model.clone();
+        scorecards.push(evaluate(config, model, &works, &scorer).await.0);
+    }
+    Ok(scorecards)
+}
+
+/// Generates from the same sections of `sample` with each of two user prompt templates,
+/// rates every item with the `judge` model and returns the items of both side by side.
+/// The system prompts, models and everything else come from each file's settings.
+pub async fn compare_prompts(config: &Config, prompts: [&Path; 2], judge: &str, sample: &Path, max_sections: usize) -> Result<PromptComparison> {
+    let mut templates = Vec::new();
+    for path in prompts {
+        let template = config::read_template(path)?;
+        config::validate_templates([template.as_str()])?;
+        templates.push(template);
+    }
+    if config.preflight {
+        preflight::check(config).await?;
+        preflight::check_models(&config.endpoint, [judge]).await?;
+    }
+    let mut works = sample_sections(config, sample, max_sections).await?;
+    info!("Comparing prompts on {} sections from {} files", section_count(&works), works.len());
+
+    let scorer = Scorer::new(config).with_model(judge);
+    let mut scorecards = Vec::new();
+    let mut outputs = Vec::new();
+    for (path, template) in prompts.iter().zip(templates) {
+        for settings in works.iter_mut().filter_map(|work| work.settings.as_mut()) {
+            settings.prompt.user = template.clone();
+        }
+        let (scorecard, sections) = evaluate(config.clone(), &path.display().to_string(), &works, &scorer).await;
+        scorecards.push(scorecard);
+        outputs.push(sections);
+    }
+
+    let (b, a) = (outputs.pop().unwrap_or_default(), outputs.pop().unwrap_or_default());
+    let sections = works
+        .iter()
+        .flat_map(|work| work.sections.iter().map(move |section| (work, section)))
+        .zip(a.into_iter().zip(b))
+        .map(|((work, section), (a, b))| SectionPair {
+            file: work.relative_path.clone(),
+            section: section.index + 1,
+            heading: section.text.lines().find(|line| !line.trim().is_empty()).unwrap_or_default().trim().to_string(),
+            a,
+            b,
+        })
+        .collect();
+    let scorecards = scorecards.try_into().map_err(|_| anyhow!("Expected two prompts"))?;
+    Ok(PromptComparison { scorecards, sections })
+}
+
+fn section_count(works: &[FileWork]) -> usize {
+    works.iter().map(|work| work.sections.len()).sum()
+}
+
+/// Parses and chunks the text files of the sample like `generate` does, keeping at most
+/// `max_sections` sections over all files. Overrides and `.llmds.toml` files resolve
+/// relative to the sample directory.
+async fn sample_sections(config: &Config, sample: &Path, max_sections: usize) -> Result<Vec<FileWork>> {
+    let root = if sample.is_dir() { sample } else { sample.parent().unwrap_or(Path::new(".")) };
+    let mut config = config.clone();
+    config.output_dir = root.to_string_lossy().to_string();
+    // Every section is generated from, so there is no point in streaming
+    config.stream_threshold_mb = u64::MAX / (1024 * 1024);
+    config.sampling.limit_sections = None;
+    let config = Arc::new(config);
+    let parse = ParseStage::new(config.clone(), Arc::new(Plugins::load(&config.plugins)?))?;
+    let chunk = ChunkStage::new(config.clone());
+
+    let mut extensions = vec!["md".to_string(), "txt".to_string()];
+    extensions.extend(config.plugins.iter().flat_map(|p| p.extensions.iter().map(|ext| ext.to_lowercase())));
+    let mut works = Vec::new();
+    for entry in WalkDir::new(sample).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_text = compression::inner_path(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.contains(&ext.to_lowercase()));
+        if !entry.file_type().is_file() || !is_text {
+            continue;
+        }
+        let mut work = FileWork::new(path, root);
+        parse.process(&mut work).await?;
+        if work.stats.skipped.is_some() {
+            continue;
+        }
+        chunk.process(&mut work).await?;
+        works.push(work);
+    }
+
+    // Draw the sample from all sections at once, so large files don't crowd out the rest
+    let mut picks: Vec<(usize, usize)> = works
+        .iter()
+        .enumerate()
+        .flat_map(|(file, work)| (0..work.sections.len()).map(move |section| (file, section)))
+        .collect();
+    if picks.len() > max_sections {
+        let mut rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        picks.shuffle(&mut rng);
+        picks.truncate(max_sections);
+        for (file, work) in works.iter_mut().enumerate() {
+            let mut index = 0;
+            work.sections.retain(|_| {
+                index += 1;
+                picks.contains(&(file, index - 1))
+            });
+        }
+    }
+    works.retain(|work| !work.sections.is_empty());
+    if works.is_empty() {
+        return Err(anyhow!("No sections to generate from in {:?}", sample));
+    }
+    Ok(works)
+}
+
+/// Generates items for every sample section with `config` and the settings of `works`,
+/// measuring failures and speed, and rates them with `scorer`. Returns the items of each
+/// section, in order.
+async fn evaluate(config: Config, name: &str, works: &[FileWork], scorer: &Scorer) -> (Scorecard, Vec<Vec<RatedItem>>) {
+    let metrics = Arc::new(Metrics::new());
+    let processor = OllamaProcessor::new(Arc::new(config), metrics.clone());
+    let mut scorecard = Scorecard {
+        name: name.to_string(),
+        ..Scorecard::default()
+    };
+    let mut generated = Vec::new();
+
+    let started = Instant::now();
+    for work in works {
+        for section in &work.sections {
+            scorecard.sections += 1;
+            match processor.generate_section(work, section).await {
+                Ok(items) => {
+                    debug!("{} generated {} items for section {} of {:?}", name, items.len(), section.index + 1, work.path);
+                    scorecard.items += items.len();
+                    generated.push((work.path.clone(), items));
+                }
+                Err(e) => {
+                    warn!("{} failed on section {} of {:?}: {}", name, section.index + 1, work.path, e);
+                    scorecard.failed_sections += 1;
+                    generated.push((work.path.clone(), Vec::new()));
+                }
+            }
+        }
+    }
+    scorecard.seconds = started.elapsed().as_secs_f64();
+    scorecard.requests = metrics.requests.get();
+    scorecard.parse_failures = metrics.parse_failures.get();
+    scorecard.completion_tokens = processor.usage().total().completion_tokens;
+    info!("{} generated {} items in {:.1}s; rating them", name, scorecard.items, scorecard.seconds);
+
+    let mut sections = Vec::new();
+    let mut scores = Vec::new();
+    for (path, items) in generated {
+        let mut rated = Vec::new();
+        for item in items {
+            let score = match scorer.score(&path, &item.question, &item.answer).await {
+                Ok(score) => {
+                    scores.push(score as f64);
+                    Some(score)
+                }
+                Err(e) => {
+                    warn!("Failed to rate an item of {}: {}", name, e);
+                    None
+                }
+            };
+            rated.push(RatedItem { item, score });
+        }
+        sections.push(rated);
+    }
+    scorecard.mean_score = mean(&scores);
+    (scorecard, sections)
+}
+
+fn mean(scores: &[f64]) -> Option<f64> {
+    (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64)
+}
+
+/// Prints the scorecards as a table, one row per model or prompt, with `label` heading
+/// the name column.
+pub fn print_table(label: &str, scorecards: &[Scorecard]) {
+    let width = scorecards.iter().map(|card| card.name.len()).max().unwrap_or(0).max(label.len());
+    println!(
+        "{:<width$}  {:>5}  {:>5}  {:>14}  {:>15}  {:>9}  {:>8}",
+        label, "Items", "Score", "Parse failures", "Failed sections", "s/section", "Tokens/s"
+    );
+    for card in scorecards {
+        println!(
+            "{:<width$}  {:>5}  {:>5}  {:>13.1}%  {:>15}  {:>9.1}  {:>8.1}",
+            card.name,
+            card.items,
+            format_score(card.mean_score),
+            card.parse_failure_rate() * 100.0,
+            format!("{}/{}", card.failed_sections, card.sections),
+            card.seconds_per_section(),
+            card.tokens_per_second(),
+        );
+    }
+}
+
+fn format_score(score: Option<f64>) -> String {
+    score.map(|score| format!("{:.2}", score)).unwrap_or_else(|| "-".to_string())
+}
+
+/// Prints the items of both prompts side by side like `diff --side-by-side`, section by
+/// section: items asking the same question share a row marked `|`, the others are
+/// marked `<` (only A) or `>` (only B). Ends with the totals and which prompt won more
+/// sections.
+pub fn print_comparison(comparison: &PromptComparison) {
+    let terminal_width = ratatui::crossterm::terminal::size().map(|(width, _)| width as usize).unwrap_or(120);
+    let column = (terminal_width.saturating_sub(3) / 2).max(30);
+    let [card_a, card_b] = &comparison.scorecards;
+    let (mut a_wins, mut b_wins, mut ties) = (0, 0, 0);
+
+    for pair in &comparison.sections {
+        let (a_score, b_score) = (section_score(&pair.a), section_score(&pair.b));
+        match a_score.partial_cmp(&b_score) {
+            Some(Ordering::Greater) => a_wins += 1,
+            Some(Ordering::Less) => b_wins += 1,
+            _ => ties += 1,
+        }
+        println!("{}", "=".repeat(column * 2 + 3));
+        println!("{} section {}: {}", pair.file.display(), pair.section, pair.heading);
+        print_row(
+            &[format!("A ({}): {} items, score {}", card_a.name, pair.a.len(), format_score(a_score))],
+            &[format!("B ({}): {} items, score {}", card_b.name, pair.b.len(), format_score(b_score))],
+            ' ',
+            column,
+        );
+        println!("{}", "-".repeat(column * 2 + 3));
+
+        let mut unmatched: Vec<&RatedItem> = pair.b.iter().collect();
+        for a in &pair.a {
+            let key = dataset::question_key(&a.item);
+            match unmatched.iter().position(|b| dataset::question_key(&b.item) == key) {
+                Some(index) => {
+                    let b = unmatched.remove(index);
+                    print_row(&item_lines(a, column), &item_lines(b, column), '|', column);
+                }
+                None => print_row(&item_lines(a, column), &[], '<', column),
+            }
+        }
+        for b in unmatched {
+            print_row(&[], &item_lines(b, column), '>', column);
+        }
+    }
+
+    println!("{}", "=".repeat(column * 2 + 3));
+    print_table("Prompt", &comparison.scorecards);
+    println!("A rated higher on {} sections, B on {}, tied on {}", a_wins, b_wins, ties);
+}
+
+fn section_score(items: &[RatedItem]) -> Option<f64> {
+    mean(&items.iter().filter_map(|rated| rated.score).map(f64::from).collect::<Vec<_>>())
+}
+
+/// An item as lines of at most `width` characters, followed by a blank line.
+fn item_lines(rated: &RatedItem, width: usize) -> Vec<String> {
+    let mut lines = wrap(&format!("Q: {}", rated.item.question), width);
+    lines.extend(wrap(&format!("A: {}", rated.item.answer), width));
+    lines.push(format!("[score {}]", rated.score.map(|score| score.to_string()).unwrap_or_else(|| "-".to_string())));
+    lines.push(String::new());
+    lines
+}
+
+fn print_row(left: &[String], right: &[String], marker: char, width: usize) {
+    for i in 0..left.len().max(right.len()) {
+        let left = left.get(i).map(String::as_str).unwrap_or_default();
+        let right = right.get(i).map(String::as_str).unwrap_or_default();
+        let padding = width.saturating_sub(left.chars().count());
+        let line = format!("{}{} {} {}", left, " ".repeat(padding), marker, right);
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Wraps text at word boundaries, breaking words longer than a line.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            let word: String = word.into_iter().collect();
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
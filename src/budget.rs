@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::info;
+use crate::config::{Config, WeightConfig};
+
+/// Bytes per word of a typical Markdown file, for estimating a file's words from its size.
+const BYTES_PER_WORD: f64 = 6.0;
+
+/// A cap on the items a whole run keeps, from `sampling.max_total_items`. Files'
+/// question targets are scaled down so the run's estimated questions fit the cap;
+/// once it is reached, the remaining sections and files are skipped and items beyond
+/// it are dropped.
+pub struct ItemBudget {
+    max: usize,
+    words_per_question: f64,
+    questions_per_file: Option<usize>,
+    /// `[[weights]]`, matched against paths relative to `output_dir`.
+    weights: Vec<WeightConfig>,
+    output_dir: PathBuf,
+    kept: AtomicUsize,
+    /// Estimated questions of the files planned so far, and the share of each file's
+    /// questions that fits the cap.
+    plan: Mutex<(f64, f64)>,
+}
+
+impl ItemBudget {
+    /// The budget of a run, or `None` when it has no cap.
+    pub fn new(config: &Config) -> Option<Self> {
+        let max = config.sampling.max_total_items?;
+        Some(Self {
+            max,
+            words_per_question: config.density.words_per_question,
+            questions_per_file: config.density.questions_per_file,
+            weights: config.weights.clone(),
+            output_dir: PathBuf::from(&config.output_dir),
+            kept: AtomicUsize::new(0),
+            plan: Mutex::new((0.0, 1.0)),
+        })
+    }
+
+    /// Adds `files` to the run's estimated questions, from their sizes (or the fixed
+    /// number of questions per file) times their weight, and scales the files' targets
+    /// to fit the cap. Oversampling weights repeat items rather than generating more, so
+    /// they leave the estimate alone.
+    pub fn plan(&self, files: &[PathBuf]) {
+        let questions: f64 = files.iter().map(|file| self.estimate(file)).sum();
+        let mut plan = self.plan.lock().unwrap();
+        plan.0 += questions;
+        plan.1 = (self.max as f64 / plan.0.max(1.0)).min(1.0);
+        if plan.1 < 1.0 {
+            info!("Scaling question targets to {:.0}% to stay within {} items (about {:.0} planned)", plan.1 * 100.0, self.max, plan.0);
+        }
+    }
+
+    /// The questions `file` is expected to yield.
+    fn estimate(&self, file: &Path) -> f64 {
+        let questions = match self.questions_per_file {
+            Some(count) => count as f64,
+            None => match fs::metadata(file) {
+                Ok(metadata) => metadata.len() as f64 / BYTES_PER_WORD / self.words_per_question,
+                Err(_) => return 0.0,
+            },
+        };
+        let relative = file.strip_prefix(&self.output_dir).unwrap_or(file);
+        match WeightConfig::find(&self.weights, relative) {
+            Some(weight) if !weight.oversample => questions * weight.weight,
+            _ => questions,
+        }
+    }
+
+    /// `questions` scaled to the run's share, at least one.
+    pub fn scaled(&self, questions: usize) -> usize {
+        let scale = self.plan.lock().unwrap().1;
+        match questions {
+            0 => 0,
+            questions => ((questions as f64 * scale).ceil() as usize).max(1),
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.kept.load(Ordering::Relaxed) >= self.max
+    }
+
+    /// Takes up to `count` items from the budget; returns how many of them fit.
+    pub fn take(&self, count: usize) -> usize {
+        let mut taken = 0;
+        let _ = self.kept.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |kept| {
+            taken = count.min(self.max.saturating_sub(kept));
+            Some(kept + taken)
+        });
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SamplingConfig;
+
+    fn weight(path: &str, weight: f64, oversample: bool) -> WeightConfig {
+        WeightConfig { path: path.to_string(), weight, oversample }
+    }
+
+    fn budget(max: usize) -> ItemBudget {
+        let config = Config {
+            sampling: SamplingConfig {
+                max_total_items: Some(max),
+                ..SamplingConfig::default()
+            },
+            ..Config::default()
+        };
+        ItemBudget::new(&config).unwrap()
+    }
+
+    #[test]
+    fn runs_without_a_cap_have_no_budget() {
+        assert!(ItemBudget::new(&Config::default()).is_none());
+    }
+
+    #[test]
+    fn takes_what_is_left() {
+        let budget = budget(5);
+        assert_eq!(budget.take(3), 3);
+        assert!(!budget.is_exhausted());
+        assert_eq!(budget.take(3), 2);
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.take(1), 0);
+    }
+
+    #[test]
+    fn scales_targets_to_fit_the_plan() {
+        let mut config = Config::default();
+        config.sampling.max_total_items = Some(10);
+        config.density.questions_per_file = Some(10);
+        let budget = ItemBudget::new(&config).unwrap();
+        assert_eq!(budget.scaled(7), 7);
+        budget.plan(&[PathBuf::from("a.md"), PathBuf::from("b.md"), PathBuf::from("c.md"), PathBuf::from("d.md")]);
+        // 40 planned questions for 10 items: a quarter of each target, at least one
+        assert_eq!(budget.scaled(8), 2);
+        assert_eq!(budget.scaled(1), 1);
+        assert_eq!(budget.scaled(0), 0);
+    }
+
+    #[test]
+    fn weights_scale_the_estimate() {
+        let mut config = Config {
+            output_dir: "docs".to_string(),
+            weights: vec![weight("api/**", 3.0, false), weight("blog/**", 0.5, false), weight("faq/**", 4.0, true)],
+            ..Config::default()
+        };
+        config.sampling.max_total_items = Some(10);
+        config.density.questions_per_file = Some(10);
+        let budget = ItemBudget::new(&config).unwrap();
+        budget.plan(&[PathBuf::from("docs/api/a.md"), PathBuf::from("docs/blog/b.md"), PathBuf::from("docs/faq/c.md")]);
+        // 30 + 5 + 10 planned questions; oversampling repeats items instead
+        assert_eq!(budget.plan.lock().unwrap().0, 45.0);
+        assert_eq!(budget.scaled(9), 2);
+    }
+}
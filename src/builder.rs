@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Result;
+use crate::budget::ItemBudget;
+use crate::config::{ChunkingConfig, Config, FilterConfig, OutputFormat, SteeringConfig};
+use crate::datasource::DataSource;
+use crate::error::Error;
+use crate::filter::ItemFilter;
+use crate::generate;
+use crate::hooks::{Event, Hooks};
+use crate::metrics::Metrics;
+use crate::pipeline::{FileWork, Pipeline};
+use crate::processor::{OllamaProcessor, ProcessedItem};
+use crate::progress::Progress;
+use crate::report::{FileStats, RunReport};
+use crate::usage::TokenUsage;
+
+/// Entry point for embedding dataset generation: configure a run, add sources and run
+/// it like the `generate` command would.
+#[derive(Debug, Clone, Default)]
+pub struct DatasetBuilder {
+    config: Config,
+    sources: Vec<String>,
+    hooks: Hooks,
+}
+
+impl DatasetBuilder {
+    /// A builder with the built-in defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A builder starting from a loaded configuration.
+    pub fn from_config(config: Config) -> Self {
+        Self {
+            hooks: Hooks::new(&config.hooks),
+            config,
+            sources: Vec::new(),
+        }
+    }
+
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.config.endpoint = endpoint.to_string();
+        self
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.config.model = model.to_string();
+        self
+    }
+
+    /// Directory sources are collected into and results are written to.
+    pub fn with_output_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.config.output_dir = dir.as_ref().display().to_string();
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Content profile used for every file instead of selecting one through the rules.
+    pub fn with_profile(mut self, profile: &str) -> Self {
+        self.config.profile = Some(profile.to_string());
+        self
+    }
+
+    pub fn with_questions_per_file(mut self, count: usize) -> Self {
+        self.config.density.questions_per_file = Some(count);
+        self
+    }
+
+    pub fn with_output_format(mut self, format: OutputFormat) -> Self {
+        self.config.output.format = format;
+        self
+    }
+
+    /// Adds a URL, GitHub URL or local path to collect files from. Without sources, the
+    /// files already in the output directory are processed.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.sources.push(source.to_string());
+        self
+    }
+
+    /// Calls `callback` for every item, file and run event, alongside any hook commands
+    /// from the configuration.
+    pub fn with_hook<F: Fn(&Event) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.hooks = self.hooks.with_callback(callback);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// A generator using this builder's configuration. Fails when a configured plugin
+    /// can't be loaded.
+    pub fn generator(&self) -> Result<Generator> {
+        Generator::with_hooks(self.config.clone(), self.hooks.clone())
+    }
+
+    /// Collects the sources and generates a dataset into the output directory. Returns
+    /// `None` when there was nothing to process.
+    pub async fn run(self) -> Result<Option<RunReport>, Error> {
+        self.config.validate()?;
+        let sources = self
+            .sources
+            .iter()
+            .map(|source| generate::parse_source(source, &self.config))
+            .collect::<Result<Vec<Box<dyn DataSource>>>>()?;
+        generate::run_sources(&self.config, sources, Arc::new(Progress::new(true)), None, self.hooks).await
+    }
+}
+
+/// Splits documents into the sections that are sent to the model.
+#[derive(Debug, Clone, Default)]
+pub struct Chunker {
+    chunking: ChunkingConfig,
+    limit_sections: Option<usize>,
+}
+
+impl Chunker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deepest heading level (`#` = 1) that starts a new section.
+    pub fn with_heading_level(mut self, level: usize) -> Self {
+        self.chunking.heading_level = level;
+        self
+    }
+
+    /// Whether each section starts with the headings of the sections it is nested in.
+    pub fn with_parent_headings(mut self, enabled: bool) -> Self {
+        self.chunking.parent_headings = enabled;
+        self
+    }
+
+    pub fn with_limit_sections(mut self, limit: usize) -> Self {
+        self.limit_sections = Some(limit);
+        self
+    }
+
+    pub fn split(&self, content: &str) -> Vec<String> {
+        let mut sections = OllamaProcessor::split_into_sections(content, &self.chunking);
+        if let Some(limit) = self.limit_sections {
+            sections.truncate(limit);
+        }
+        sections
+    }
+}
+
+/// Generates items for single files with the configured model, profiles and filters.
+pub struct Generator {
+    processor: Arc<OllamaProcessor>,
+    pipeline: Pipeline,
+    output_dir: PathBuf,
+}
+
+impl Generator {
+    pub fn new(config: Config) -> Result<Self> {
+        let hooks = Hooks::new(&config.hooks);
+        Self::with_hooks(config, hooks)
+    }
+
+    /// A generator that reports item events to `hooks` instead of the configured ones.
+    pub fn with_hooks(config: Config, hooks: Hooks) -> Result<Self> {
+        let output_dir = PathBuf::from(&config.output_dir);
+        let config = Arc::new(config);
+        let (progress, metrics) = (Arc::new(Progress::new(true)), Arc::new(Metrics::new()));
+        let processor = Arc::new(
+            OllamaProcessor::new(config.clone(), metrics.clone())
+                .with_failure_archive(output_dir.join("failures")),
+        );
+        // `sampling.max_total_items` caps the items of every file this generator makes
+        let budget = ItemBudget::new(&config).map(Arc::new);
+        Ok(Self {
+            pipeline: Pipeline::standard(config, processor.clone(), progress, metrics, Arc::new(hooks), budget)?,
+            processor,
+            output_dir,
+        })
+    }
+
+    /// Items for one file, which is also saved next to it as `<name>_qa.jsonl`. Files
+    /// that already have enough items are not regenerated.
+    pub async fn generate_file<P: AsRef<Path>>(&self, path: P) -> Result<(Vec<ProcessedItem>, FileStats), Error> {
+        let work = self.pipeline.process(FileWork::new(path.as_ref(), &self.output_dir)).await;
+        match work.error {
+            Some(e) => Err(e),
+            None => Ok((work.items, work.stats)),
+        }
+    }
+
+    /// Tokens used so far by this generator.
+    pub fn usage(&self) -> TokenUsage {
+        self.processor.usage().total()
+    }
+}
+
+/// Drops items that are too short, match an excluded pattern or topic, or, with
+/// `require_keyword`, mention none of the keywords.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    filters: FilterConfig,
+    steering: SteeringConfig,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_min_question_words(mut self, words: usize) -> Self {
+        self.filters.min_question_words = words;
+        self
+    }
+
+    pub fn with_min_answer_words(mut self, words: usize) -> Self {
+        self.filters.min_answer_words = words;
+        self
+    }
+
+    /// Regex matched against question and answer.
+    pub fn with_exclude_pattern(mut self, pattern: &str) -> Self {
+        self.filters.exclude_patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn with_exclude_topic(mut self, topic: &str) -> Self {
+        self.steering.exclude_topics.push(topic.to_string());
+        self
+    }
+
+    pub fn with_required_keywords(mut self, keywords: &[&str]) -> Self {
+        self.steering.keywords = keywords.iter().map(|k| k.to_string()).collect();
+        self.steering.require_keyword = true;
+        self
+    }
+
+    /// The items that pass, in their original order.
+    pub fn apply(&self, items: Vec<ProcessedItem>) -> Result<Vec<ProcessedItem>> {
+        let filter = ItemFilter::new(&self.filters, &self.steering)?;
+        Ok(items.into_iter().filter(|item| filter.accepts(item)).collect())
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde_json::json;
+use walkdir::WalkDir;
+use crate::config::{Config, LOCAL_CONFIG_FILE, RELEASE_NOTES_PROFILE};
+use crate::frontmatter;
+use crate::report::RunReport;
+
+/// Name of the dataset card written to the output directory. Not `README.md`, which
+/// would clash with source files copied there; it becomes the README on the Hub.
+pub const DATASET_CARD_FILE: &str = "DATASET_CARD.md";
+
+/// Source files listed in the card; the rest are only counted.
+const MAX_LISTED_SOURCES: usize = 100;
+
+/// A dataset file and the number of items in it.
+#[derive(Debug, Clone)]
+pub struct Split {
+    pub name: String,
+    pub file: String,
+    pub items: usize,
+}
+
+/// Writes a dataset card for a finished run to the output directory: Hugging Face Hub
+/// metadata, record fields, splits, sources, the model and prompts used, and the known
+/// limitations of the data. The whole combined file is the `train` split until `split`
+/// updates the card.
+pub fn write(config: &Config, report: &RunReport) -> Result<PathBuf> {
+    let splits = [Split {
+        name: "train".to_string(),
+        file: config.output.combined_file(),
+        items: report.totals.items,
+    }];
+    let path = Path::new(&config.output_dir).join(DATASET_CARD_FILE);
+    fs::write(&path, render(config, report, &splits))?;
+    Ok(path)
+}
+
+/// Replaces the splits of the card in `dir`, if there is one, after `split` wrote new
+/// split files there. Returns whether a card was updated.
+pub fn update_splits(dir: &Path, splits: &[Split]) -> Result<bool> {
+    let path = dir.join(DATASET_CARD_FILE);
+    if !path.is_file() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(&path)?;
+    let (metadata, body) = frontmatter::parse(&content);
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.insert("configs".into(), serde_yaml::to_value(hub_configs(splits))?);
+    let total = splits.iter().map(|split| split.items).sum();
+    metadata.insert("size_categories".into(), serde_yaml::to_value([size_category(total)])?);
+
+    // The splits section runs up to the next second-level heading
+    let body = match body.find("## Splits\n") {
+        Some(start) => {
+            let end = body[start + 1..].find("\n## ").map(|end| start + 1 + end + 1).unwrap_or(body.len());
+            format!("{}{}{}", &body[..start], splits_section(splits), &body[end..])
+        }
+        None => format!("{}\n{}", body, splits_section(splits)),
+    };
+    fs::write(&path, format!("---\n{}---\n{}", serde_yaml::to_string(&metadata)?, body))?;
+    Ok(true)
+}
+
+fn render(config: &Config, report: &RunReport, splits: &[Split]) -> String {
+    let output_dir = Path::new(&config.output_dir);
+    let name = output_dir
+        .canonicalize()
+        .ok()
+        .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "dataset".to_string());
+    // Files per content profile
+    let mut profiles: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for file in &report.files {
+        if let Some(profile) = &file.stats.profile {
+            let entry = profiles.entry(profile).or_default();
+            entry.0 += 1;
+            entry.1 += file.items;
+        }
+    }
+    let mut models = vec![report.model.clone()];
+    for profile in profiles.keys() {
+        if let Some(model) = config.profiles.get(*profile).and_then(|profile| profile.model.clone()) {
+            if !models.contains(&model) {
+                models.push(model);
+            }
+        }
+    }
+
+    let metadata = json!({
+        "pretty_name": name,
+        "task_categories": ["question-answering", "text-generation"],
+        "size_categories": [size_category(report.totals.items)],
+        "tags": ["synthetic"],
+        "configs": hub_configs(splits),
+    });
+    let mut card = format!("---\n{}---\n\n", serde_yaml::to_string(&metadata).unwrap_or_default());
+    card.push_str(&format!("# {}\n\n", name));
+    let models = models.iter().map(|model| format!("`{}`", model)).collect::<Vec<_>>().join(", ");
+    card.push_str(&format!(
+        "Question/answer pairs generated from {} source files with {} by \
+         [LLM Dataset Builder](https://github.com/technovangelist/llm_dataset_builder).\n\n",
+        report.totals.files - report.totals.skipped_files,
+        models
+    ));
+
+    // Fields of every record
+    card.push_str("## Dataset Structure\n\nEach record is a JSON object with:\n\n");
+    card.push_str("- `question`: the generated question\n- `answer`: its answer, based only on the source section\n");
+    if config.output.citations {
+        card.push_str("- `citation`: verbatim quote from the source section that supports the answer\n");
+    }
+    card.push_str("- `id`: the item's stable id, and `hash`: a hash of its question and answer\n");
+    card.push_str("- `source`: the source `file`, the index of its `section` the item was generated from and the section's `chunk` id\n");
+    for field in config.output.schema.fields.keys() {
+        card.push_str(&format!("- `{}`\n", field));
+    }
+    if profiles.contains_key(RELEASE_NOTES_PROFILE) {
+        card.push_str("- `version`, `release_date`, `change_type`: release the item is about, for items from release notes\n");
+    }
+    card.push('\n');
+    card.push_str(&splits_section(splits));
+
+    card.push_str("## Sources\n\n| File | Profile | Items |\n|------|---------|-------|\n");
+    for file in report.files.iter().take(MAX_LISTED_SOURCES) {
+        let path = file.path.strip_prefix(output_dir).unwrap_or(&file.path);
+        let items = match (&file.error, &file.stats.skipped) {
+            (Some(_), _) => "failed".to_string(),
+            (_, Some(reason)) => format!("skipped ({})", reason),
+            _ => file.items.to_string(),
+        };
+        card.push_str(&format!("| `{}` | {} | {} |\n", path.display(), file.stats.profile.as_deref().unwrap_or("-"), items));
+    }
+    if report.files.len() > MAX_LISTED_SOURCES {
+        card.push_str(&format!("\n... and {} more files.\n", report.files.len() - MAX_LISTED_SOURCES));
+    }
+    card.push('\n');
+
+    card.push_str("## Generation\n\n");
+    card.push_str(&format!("- Model: {}\n", models));
+    if let Some(seed) = report.seed {
+        card.push_str(&format!("- Seed: {}\n", seed));
+    }
+    card.push_str(&format!(
+        "- Items: {} kept, {} dropped by the quality filters\n",
+        report.totals.items, report.totals.rejected_items
+    ));
+    card.push_str(&format!("- Sections: {} ({} failed)\n", report.totals.sections, report.totals.failed_sections));
+    for (profile, (files, items)) in &profiles {
+        let prompt = config.profile_prompt(profile);
+        card.push_str(&format!("\n### Prompts: `{}` ({} files, {} items)\n\n", profile, files, items));
+        card.push_str(&format!("System prompt:\n\n```jinja\n{}\n```\n\n", prompt.system.trim()));
+        card.push_str(&format!("User prompt:\n\n```jinja\n{}\n```\n", prompt.user.trim()));
+    }
+    card.push('\n');
+
+    card.push_str("## Known Limitations\n\n");
+    for limitation in limitations(config, report) {
+        card.push_str(&format!("- {}\n", limitation));
+    }
+    card
+}
+
+fn limitations(config: &Config, report: &RunReport) -> Vec<String> {
+    let totals = &report.totals;
+    let mut limitations = vec![
+        "Questions and answers were written by a language model and haven't all been reviewed by people; \
+         some may be wrong, incomplete or oddly phrased."
+            .to_string(),
+        "Coverage follows the source documents: topics they don't cover are missing, and outdated sources \
+         give outdated answers."
+            .to_string(),
+    ];
+    if !config.output.citations {
+        limitations.push("Answers weren't checked against quotes from their source, so unsupported claims aren't filtered out.".to_string());
+    }
+    if totals.failed_sections > 0 || totals.failed_files > 0 {
+        limitations.push(format!(
+            "{} sections and {} files failed to generate; their content isn't covered.",
+            totals.failed_sections, totals.failed_files
+        ));
+    }
+    if totals.skipped_files > 0 {
+        limitations.push(format!("{} files were skipped, e.g. because they are binary.", totals.skipped_files));
+    }
+    let sampling = &config.sampling;
+    if sampling.limit_files.is_some() || sampling.limit_sections.is_some() || sampling.sample.is_some() {
+        limitations.push("Only a sample of the sources was used (`[sampling]`).".to_string());
+    }
+    let reused = report.files.iter().filter(|file| file.stats.reused_existing).count();
+    if reused > 0 {
+        limitations.push(format!("{} files reuse items from an earlier run, which may have used other settings.", reused));
+    }
+    let has_local_configs = WalkDir::new(&config.output_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .any(|entry| entry.file_name() == LOCAL_CONFIG_FILE);
+    if !config.overrides.is_empty() || has_local_configs {
+        limitations.push(format!(
+            "Some directories use their own prompts or settings (`[[overrides]]` or `{}`); the prompts above are the top-level ones.",
+            LOCAL_CONFIG_FILE
+        ));
+    }
+    limitations
+}
+
+fn splits_section(splits: &[Split]) -> String {
+    let mut section = String::from("## Splits\n\n| Split | File | Items |\n|-------|------|-------|\n");
+    for split in splits {
+        section.push_str(&format!("| {} | `{}` | {} |\n", split.name, split.file, split.items));
+    }
+    section.push('\n');
+    section
+}
+
+/// The `configs` metadata telling the Hub which file holds which split.
+fn hub_configs(splits: &[Split]) -> serde_json::Value {
+    let data_files: Vec<_> = splits.iter().map(|split| json!({ "split": split.name, "path": split.file })).collect();
+    json!([{ "config_name": "default", "data_files": data_files }])
+}
+
+/// The Hub's size bucket for a number of items.
+fn size_category(items: usize) -> &'static str {
+    match items {
+        0..1_000 => "n<1K",
+        1_000..10_000 => "1K<n<10K",
+        10_000..100_000 => "10K<n<100K",
+        100_000..1_000_000 => "100K<n<1M",
+        _ => "1M<n<10M",
+    }
+}
@@ -0,0 +1,298 @@
+use std::path::Path;
+use std::sync::LazyLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::compression;
+
+/// A heading naming the whole document as release notes or a changelog.
+static TITLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^#+\s+(release notes|change ?log|release history|what's new)\b").unwrap()
+});
+
+/// Heading text naming a release: `[0.4.2] - 2024-05-01`, `v0.4.2 (May 1, 2024)`,
+/// `Release 0.4.2`, `Version 1.0-rc1`.
+static VERSION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^(?:release(?:\s+notes)?(?:\s+for)?\s*[:\-–—]?\s*|version\s*[:\-–—]?\s*)?\[?(?:v(?:ersion)?\s*)?(\d+\.\d+(?:\.\d+)*(?:-(?:alpha|beta|rc|pre|preview|dev)[0-9a-z.]*)?)\]?(?:$|[\s:(\-–—,])",
+    )
+    .unwrap()
+});
+
+/// `[0.4.2]: https://github.com/...`
+static LINK_REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\[[^\]]+\]:\s").unwrap());
+
+static ISO_DATE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(\d{4})-(\d{2})-(\d{2})\b").unwrap());
+
+/// `May 1, 2024` or `1 May 2024`, with full or abbreviated month names.
+static WRITTEN_DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(?:([a-z]{3,9})\.?\s+(\d{1,2})(?:st|nd|rd|th)?,?\s+(\d{4})|(\d{1,2})(?:st|nd|rd|th)?\s+([a-z]{3,9})\.?,?\s+(\d{4}))\b").unwrap()
+});
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Types of changes in a [Keep a Changelog](https://keepachangelog.com) release.
+const CHANGE_TYPES: [&str; 6] = ["Added", "Changed", "Deprecated", "Removed", "Fixed", "Security"];
+
+/// A release named by a changelog heading. `date` is `YYYY-MM-DD`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Release {
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Type of change (`Added`, `Fixed`, ...) of a section holding a single Keep a
+    /// Changelog entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_type: Option<String>,
+}
+
+impl Release {
+    /// The release named by heading text (without the `#` markers), if it names one.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        Some(Self {
+            version: VERSION.captures(text)?[1].to_string(),
+            date: parse_date(text),
+            change_type: None,
+        })
+    }
+
+    /// The release named by a Markdown heading line.
+    pub fn from_heading(line: &str) -> Option<Self> {
+        let text = line.strip_prefix('#')?.trim_start_matches('#');
+        if !text.starts_with(char::is_whitespace) {
+            return None;
+        }
+        Self::parse(text)
+    }
+}
+
+fn parse_date(text: &str) -> Option<String> {
+    if let Some(caps) = ISO_DATE.captures(text) {
+        return Some(format!("{}-{}-{}", &caps[1], &caps[2], &caps[3]));
+    }
+    let caps = WRITTEN_DATE.captures(text)?;
+    let (month, day, year) = match caps.get(1) {
+        Some(month) => (month.as_str(), &caps[2], &caps[3]),
+        None => (&caps[5], &caps[4], &caps[6]),
+    };
+    let month = month.to_lowercase();
+    let month = MONTHS.iter().position(|name| month.starts_with(name))? + 1;
+    Some(format!("{}-{:02}-{:02}", year, month, day.parse::<u32>().ok()?))
+}
+
+/// Whether a document is release notes or a changelog: it has a heading like
+/// "Release Notes" or "Changelog", at least two release headings, or a file name that is
+/// a version (as written by the GitHub releases source).
+pub fn is_changelog(path: &Path, content: &str) -> bool {
+    if file_release(path).is_some() {
+        return true;
+    }
+    let mut releases = 0;
+    for line in content.lines().filter(|line| line.starts_with('#')) {
+        if TITLE.is_match(line) {
+            return true;
+        }
+        if Release::from_heading(line).is_some() {
+            releases += 1;
+            if releases == 2 {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// The release a file is named after, e.g. `v0.4.2.md`.
+fn file_release(path: &Path) -> Option<Release> {
+    let path = compression::inner_path(path);
+    let stem = path.file_stem()?.to_str()?;
+    Release::parse(stem).filter(|release| stem.trim_start_matches(['v', 'V']) == release.version)
+}
+
+/// Follows a changelog's sections in order to tell which release each belongs to.
+#[derive(Debug, Clone)]
+pub struct ReleaseTracker {
+    current: Option<Release>,
+}
+
+impl ReleaseTracker {
+    /// Starts at the release the file is named after, if any.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            current: file_release(path),
+        }
+    }
+
+    /// The release of the next section: the first release heading in it, or otherwise
+    /// the release the previous sections ended in.
+    pub fn section(&mut self, text: &str) -> Option<Release> {
+        let mut releases = text.lines().filter_map(Release::from_heading);
+        match releases.next() {
+            Some(first) => {
+                self.current = Some(releases.next_back().unwrap_or_else(|| first.clone()));
+                Some(first)
+            }
+            None => self.current.clone(),
+        }
+    }
+}
+
+/// A release of a Keep a Changelog file: its heading, its full text, and its entries.
+#[derive(Debug, Clone)]
+pub struct ChangelogVersion {
+    pub release: Release,
+    pub text: String,
+    pub entries: Vec<ChangelogEntry>,
+}
+
+/// One bullet of a release, under one of the types of changes.
+#[derive(Debug, Clone)]
+pub struct ChangelogEntry {
+    pub change_type: String,
+    pub text: String,
+}
+
+/// The sections a Keep a Changelog file is generated from, with their releases: each
+/// version's full text, followed by each of its entries on its own (under its release
+/// and type headings, and with the release's `change_type` set).
+pub fn sections(versions: &[ChangelogVersion]) -> Vec<(String, Release)> {
+    let mut sections = Vec::new();
+    for version in versions {
+        sections.push((version.text.clone(), version.release.clone()));
+        let heading = version.text.lines().next().unwrap_or_default();
+        for entry in &version.entries {
+            sections.push((
+                format!("{}\n### {}\n{}\n", heading, entry.change_type, entry.text),
+                Release {
+                    change_type: Some(entry.change_type.clone()),
+                    ..version.release.clone()
+                },
+            ));
+        }
+    }
+    sections
+}
+
+/// Parses a changelog that follows Keep a Changelog: `## [version] - date` headings with
+/// `### Added`, `### Fixed`, ... lists under them. `None` when the file doesn't follow
+/// the convention. The `[Unreleased]` section is left out, since its changes aren't part
+/// of a version yet.
+pub fn parse_keep_a_changelog(content: &str) -> Option<Vec<ChangelogVersion>> {
+    let mut versions: Vec<ChangelogVersion> = Vec::new();
+    // Inside a released version, as opposed to before the first one, in `[Unreleased]`
+    // or in a trailing section such as link references
+    let mut in_version = false;
+    let mut change_type: Option<String> = None;
+    let mut typed_entries = 0;
+
+    for line in content.lines() {
+        if let Some(heading) = line.strip_prefix("## ") {
+            change_type = None;
+            in_version = false;
+            if let Some(release) = Release::parse(heading) {
+                versions.push(ChangelogVersion {
+                    release,
+                    text: String::new(),
+                    entries: Vec::new(),
+                });
+                in_version = true;
+            }
+        } else if let Some(heading) = line.strip_prefix("### ") {
+            change_type = CHANGE_TYPES
+                .iter()
+                .find(|name| heading.trim().eq_ignore_ascii_case(name))
+                .map(|name| name.to_string());
+        }
+        let Some(version) = versions.last_mut().filter(|_| in_version) else {
+            continue;
+        };
+        // Link reference definitions, such as the compare links at the end of the file
+        if LINK_REFERENCE.is_match(line) {
+            continue;
+        }
+        version.text.push_str(line);
+        version.text.push('\n');
+
+        let Some(change_type) = &change_type else {
+            continue;
+        };
+        let trimmed = line.trim_start();
+        if line.starts_with(['-', '*']) && trimmed[1..].starts_with(' ') {
+            version.entries.push(ChangelogEntry {
+                change_type: change_type.clone(),
+                text: line.to_string(),
+            });
+            typed_entries += 1;
+        } else if !trimmed.is_empty() && line.starts_with(char::is_whitespace) {
+            // Continuation of the entry above, or a nested list
+            if let Some(entry) = version.entries.last_mut() {
+                entry.text.push('\n');
+                entry.text.push_str(line);
+            }
+        }
+    }
+
+    (typed_entries > 0).then_some(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGELOG: &str = "# Changelog\n\n\
+        ## [Unreleased]\n\n### Added\n- Dark mode\n\n\
+        ## [1.1.0] - 2024-05-01\n\n### Added\n- Export to CSV\n  with custom separators\n\n### Fixed\n- Crash on empty files\n\n\
+        ## [1.0.0] - 2024-03-15\n\n### Added\n- First release\n\n\
+        [1.1.0]: https://github.com/acme/tool/compare/v1.0.0...v1.1.0\n";
+
+    fn release(version: &str, date: Option<&str>) -> Release {
+        Release { version: version.to_string(), date: date.map(str::to_string), change_type: None }
+    }
+
+    #[test]
+    fn releases_are_read_from_headings() {
+        assert_eq!(Release::parse("[0.4.2] - 2024-05-01"), Some(release("0.4.2", Some("2024-05-01"))));
+        assert_eq!(Release::parse("v0.4.2 (May 1st, 2024)"), Some(release("0.4.2", Some("2024-05-01"))));
+        assert_eq!(Release::parse("Release 2.0 - 3 Sept 2023"), Some(release("2.0", Some("2023-09-03"))));
+        assert_eq!(Release::parse("Version 1.0-rc1"), Some(release("1.0-rc1", None)));
+        assert_eq!(Release::parse("Installing 1.0"), None);
+        assert_eq!(Release::from_heading("## v1.2.3"), Some(release("1.2.3", None)));
+        assert_eq!(Release::from_heading("##v1.2.3"), None);
+    }
+
+    #[test]
+    fn changelogs_are_told_from_other_docs() {
+        assert!(is_changelog(Path::new("docs/notes.md"), "# Release Notes\n\nText.\n"));
+        assert!(is_changelog(Path::new("docs/notes.md"), "## 1.1.0\n\nText.\n\n## 1.0.0\n\nText.\n"));
+        assert!(is_changelog(Path::new("releases/v0.4.2.md"), "Text.\n"));
+        assert!(!is_changelog(Path::new("docs/install.md"), "# Install\n\n## 1.1.0\n\nText.\n"));
+        assert!(!is_changelog(Path::new("docs/v0.4.2-notes.md"), "Text.\n"));
+    }
+
+    #[test]
+    fn sections_belong_to_the_last_release_before_them() {
+        let mut tracker = ReleaseTracker::new(Path::new("CHANGELOG.md"));
+        assert_eq!(tracker.section("# Changelog\n\nIntro."), None);
+        assert_eq!(tracker.section("## 1.1.0\n\nText.\n\n## 1.0.0\n\nText."), Some(release("1.1.0", None)));
+        assert_eq!(tracker.section("More about 1.0.0."), Some(release("1.0.0", None)));
+        assert_eq!(ReleaseTracker::new(Path::new("v2.0.md")).section("Text."), Some(release("2.0", None)));
+    }
+
+    #[test]
+    fn keep_a_changelog_versions_and_entries_are_parsed() {
+        let versions = parse_keep_a_changelog(CHANGELOG).unwrap();
+        let releases: Vec<&str> = versions.iter().map(|version| version.release.version.as_str()).collect();
+        assert_eq!(releases, ["1.1.0", "1.0.0"]);
+        let entries: Vec<(&str, &str)> = versions[0].entries.iter().map(|entry| (entry.change_type.as_str(), entry.text.as_str())).collect();
+        assert_eq!(entries, [("Added", "- Export to CSV\n  with custom separators"), ("Fixed", "- Crash on empty files")]);
+        assert!(versions[0].text.starts_with("## [1.1.0] - 2024-05-01\n"));
+        assert!(!versions[1].text.contains("compare"));
+        assert!(parse_keep_a_changelog("## 1.0.0\n\n- First release\n").is_none());
+
+        let sections = sections(&versions[1..]);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1].0, "## [1.0.0] - 2024-03-15\n### Added\n- First release\n");
+        assert_eq!(sections[1].1.change_type.as_deref(), Some("Added"));
+        assert_eq!(sections[0].1.change_type, None);
+    }
+}
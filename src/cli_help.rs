@@ -0,0 +1,474 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use tokio::process::Command;
+use tracing::info;
+use crate::compression;
+use crate::datasource::DataSource;
+
+/// Suffix of the files `help:` sources write the help text of a command to.
+pub const HELP_SUFFIX: &str = ".help.txt";
+
+/// Whether `path` documents a command-line tool: help text saved by a `help:` source
+/// (`foo.help.txt`) or a roff man page (`foo.1` to `foo.9`).
+pub fn is_cli_reference(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_lowercase();
+    name.ends_with(HELP_SUFFIX) || is_man_page(&path)
+}
+
+fn is_man_page(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.len() == 1 && ('1'..='9').contains(&ext.chars().next().unwrap_or('0')))
+}
+
+/// A command's documentation: its descriptive sections and one entry per option.
+#[derive(Debug, Default)]
+struct CliReference {
+    name: String,
+    sections: Vec<(String, String)>,
+    /// Flags as written (`-r, --recursive <DIR>`), and their description.
+    options: Vec<(String, String)>,
+}
+
+impl CliReference {
+    fn section(&mut self, heading: &str) {
+        self.sections.push((heading.to_string(), String::new()));
+    }
+
+    /// Adds a line to the current section, opening a `Description` section for text
+    /// before the first heading.
+    fn line(&mut self, line: &str) {
+        if self.sections.is_empty() {
+            if line.trim().is_empty() {
+                return;
+            }
+            self.section("Description");
+        }
+        let text = &mut self.sections.last_mut().unwrap().1;
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    /// Whether the current section ends in a line that filled text runs on into.
+    fn open_line(&self) -> bool {
+        self.sections.last().is_some_and(|(_, text)| !text.is_empty() && !text.ends_with('\n'))
+    }
+
+    /// Adds filled roff text to the current section, running it on into the line before.
+    fn fill(&mut self, text: &str) {
+        if self.open_line() {
+            let section = &mut self.sections.last_mut().unwrap().1;
+            section.push(' ');
+            section.push_str(text);
+        } else {
+            self.line(text);
+            if let Some((_, section)) = self.sections.last_mut() {
+                section.pop();
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.open_line() {
+            self.sections.last_mut().unwrap().1.push('\n');
+        }
+    }
+
+    fn paragraph(&mut self) {
+        self.end_line();
+        if let Some((_, section)) = self.sections.last_mut().filter(|(_, text)| !text.is_empty() && !text.ends_with("\n\n")) {
+            section.push('\n');
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n", self.name);
+        for (heading, text) in &self.sections {
+            let text = text.trim_matches('\n');
+            if !text.trim().is_empty() {
+                markdown.push_str(&format!("## {}\n\n{}\n\n", heading, text));
+            }
+        }
+        for (flags, description) in &self.options {
+            markdown.push_str(&format!("## `{}`\n\n{}\n\n", flags, description.trim()));
+        }
+        markdown
+    }
+}
+
+/// `text`, the help output or man page of a command at `path`, as Markdown titled with
+/// the command's name, with a section for each part of the text and one for each
+/// option, so questions about a flag are generated from its own section.
+pub fn render(path: &Path, text: &str) -> Result<String> {
+    let inner = compression::inner_path(path);
+    let name = inner.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let reference = match is_man_page(&inner) {
+        true => man_page(text, name.rsplit_once('.').map_or(name, |(stem, _)| stem)),
+        false => help_text(text, name.strip_suffix(HELP_SUFFIX).unwrap_or(name)),
+    };
+    if reference.options.is_empty() && reference.sections.is_empty() {
+        return Err(anyhow!("No usage text found"));
+    }
+    Ok(reference.to_markdown())
+}
+
+/// Splits an option line into its flags and the description on the same line, which
+/// starts after a run of two or more spaces.
+fn split_option(line: &str) -> (String, String) {
+    match line.find("  ") {
+        Some(at) => (line[..at].trim().to_string(), line[at..].trim().to_string()),
+        None => (line.trim().to_string(), String::new()),
+    }
+}
+
+/// Parses `--help` output: unindented lines ending in `:` ("Options:", "USAGE:") start
+/// sections, and indented lines starting with `-` are options whose description follows
+/// on the same line or the more indented lines below.
+fn help_text(text: &str, fallback: &str) -> CliReference {
+    let mut reference = CliReference { name: fallback.to_string(), ..CliReference::default() };
+    // Indentation of the option being read
+    let mut option: Option<usize> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.starts_with('-') && trimmed.len() > 1 && (indent > 0 || option.is_some()) {
+            reference.options.push(split_option(trimmed));
+            option = Some(indent);
+            continue;
+        }
+        if let Some(option_indent) = option {
+            if !trimmed.is_empty() && indent > option_indent {
+                let description = &mut reference.options.last_mut().unwrap().1;
+                description.push(' ');
+                description.push_str(trimmed);
+                continue;
+            }
+            option = None;
+        }
+        match trimmed.strip_suffix(':') {
+            Some(heading) if indent == 0 && !heading.is_empty() && !heading.contains("  ") => reference.section(&title_case(heading)),
+            _ => {
+                // "Usage: foo [OPTIONS]" on one line
+                match trimmed.split_once(':') {
+                    Some((heading, rest)) if indent == 0 && heading.eq_ignore_ascii_case("usage") => {
+                        reference.section("Usage");
+                        reference.line(rest.trim());
+                    }
+                    _ => reference.line(line.trim_end()),
+                }
+            }
+        }
+    }
+    let usage = reference.sections.iter().find(|(heading, _)| heading == "Usage");
+    if let Some(name) = usage.and_then(|(_, text)| usage_command(text)) {
+        reference.name = name;
+    }
+    reference
+}
+
+/// The command a usage line is for: its words before the first option or placeholder,
+/// e.g. `git commit` for `git commit [<options>] [--] <pathspec>...`.
+fn usage_command(usage: &str) -> Option<String> {
+    let line = usage.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let words: Vec<&str> = line
+        .split_whitespace()
+        .take_while(|word| !word.starts_with(['[', '<', '-', '{', '(']) && word.chars().any(char::is_lowercase))
+        .collect();
+    let (program, rest) = words.split_first()?;
+    let program = Path::new(program).file_name().and_then(|name| name.to_str()).unwrap_or(program);
+    Some(std::iter::once(program).chain(rest.iter().copied()).collect::<Vec<_>>().join(" "))
+}
+
+/// "OPTIONS" as "Options"; headings that aren't all capitals are kept.
+fn title_case(heading: &str) -> String {
+    let heading = heading.trim();
+    if heading.chars().any(char::is_lowercase) {
+        return heading.to_string();
+    }
+    let mut chars = heading.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars.as_str().to_lowercase().chars()).collect()).unwrap_or_default()
+}
+
+/// Parses a roff man page: `.SH`/`.SS` headings start sections, `.TP` and `.IP`
+/// paragraphs tagged with a flag are options, text lines are joined into paragraphs
+/// (except within `.nf`), font macros and escapes become plain text, and other
+/// requests are dropped.
+fn man_page(text: &str, fallback: &str) -> CliReference {
+    let mut reference = CliReference { name: fallback.to_string(), ..CliReference::default() };
+    let mut in_name = false;
+    // A `.TP` whose tag is the next line
+    let mut tag_next = false;
+    let mut in_option = false;
+    // Inside `.nf`, lines are kept as they are
+    let mut no_fill = false;
+    // Inside a macro definition or `.ig` block, which runs to `..`
+    let mut ignored = false;
+    for line in text.lines() {
+        if ignored {
+            ignored = line.trim_end() != "..";
+            continue;
+        }
+        let (request, args) = match line.strip_prefix('.').or_else(|| line.strip_prefix('\'')) {
+            Some(rest) => {
+                let rest = rest.trim_start();
+                let (request, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                (Some(request), args.trim())
+            }
+            None => (None, line),
+        };
+        // The text of the line, with font macros applied
+        let text = match request {
+            None => unescape(line),
+            Some("B" | "I" | "SM" | "SB") => unescape(&macro_args(args).join(" ")),
+            Some("BR" | "BI" | "IB" | "IR" | "RB" | "RI") => unescape(&macro_args(args).concat()),
+            Some("TH") => {
+                if let Some(name) = macro_args(args).first() {
+                    reference.name = unescape(name).to_lowercase();
+                }
+                continue;
+            }
+            Some("SH" | "SS") => {
+                let heading = unescape(&macro_args(args).join(" "));
+                in_name = heading.eq_ignore_ascii_case("name");
+                reference.section(&title_case(&heading));
+                tag_next = false;
+                in_option = false;
+                continue;
+            }
+            Some("TP") => {
+                tag_next = true;
+                in_option = false;
+                continue;
+            }
+            Some("IP") => {
+                let tag = macro_args(args).first().map(|tag| unescape(tag)).unwrap_or_default();
+                in_option = tag.starts_with('-');
+                if in_option {
+                    reference.options.push((tag, String::new()));
+                }
+                continue;
+            }
+            Some("PP" | "P" | "LP" | "sp") => {
+                in_option = false;
+                reference.paragraph();
+                continue;
+            }
+            Some("de" | "de1" | "am" | "ig") => {
+                ignored = true;
+                continue;
+            }
+            Some("br") => {
+                reference.end_line();
+                continue;
+            }
+            Some("nf" | "EX") => {
+                no_fill = true;
+                continue;
+            }
+            Some("fi" | "EE") => {
+                no_fill = false;
+                reference.end_line();
+                continue;
+            }
+            Some(_) => continue,
+        };
+        if tag_next {
+            tag_next = false;
+            in_option = text.starts_with('-');
+            if in_option {
+                reference.options.push((text, String::new()));
+                continue;
+            }
+        }
+        match in_option {
+            true => {
+                let description = &mut reference.options.last_mut().unwrap().1;
+                description.push(' ');
+                description.push_str(text.trim());
+            }
+            false => {
+                // NAME is "foo \- does things"
+                if in_name {
+                    if let Some((names, _)) = text.split_once(" - ") {
+                        reference.name = names.split(',').next().unwrap_or(names).trim().to_string();
+                    }
+                }
+                if no_fill {
+                    reference.end_line();
+                    reference.line(&text);
+                } else if text.trim().is_empty() {
+                    reference.paragraph();
+                } else {
+                    reference.fill(text.trim());
+                }
+            }
+        }
+    }
+    reference
+}
+
+/// Arguments of a roff request, with double-quoted ones kept together.
+fn macro_args(args: &str) -> Vec<&str> {
+    let mut parsed = Vec::new();
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        let (arg, remaining) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        parsed.push(arg);
+        rest = remaining.trim_start();
+    }
+    parsed
+}
+
+/// `text` with roff escapes replaced: font changes dropped, `\-` as `-` and the common
+/// special characters and quotes; other strings the page defines are left out.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // A comment runs to the end of the line
+            Some('"') => break,
+            Some('f') => match chars.next() {
+                Some('(') => {
+                    chars.next();
+                    chars.next();
+                }
+                Some('[') => while chars.next().is_some_and(|c| c != ']') {},
+                _ => {}
+            },
+            // Special characters `\(xx` and `\[name]`, and strings `\*x`, `\*(xx` and `\*[name]`
+            Some(c @ ('(' | '[' | '*')) => {
+                let open = match c {
+                    '*' => chars.next_if(|&c| c == '(' || c == '['),
+                    other => Some(other),
+                };
+                let name: String = match open {
+                    Some('(') => [chars.next(), chars.next()].into_iter().flatten().collect(),
+                    Some(_) => chars.by_ref().take_while(|&c| c != ']').collect(),
+                    None => chars.next().map(String::from).unwrap_or_default(),
+                };
+                unescaped.push_str(match name.as_str() {
+                    "em" => "—",
+                    "en" => "–",
+                    "aq" => "'",
+                    "dq" => "\"",
+                    "bu" => "•",
+                    "co" => "©",
+                    "lq" | "rq" => "\"",
+                    _ => "",
+                });
+            }
+            Some('e' | '\\') => unescaped.push('\\'),
+            // Zero-width characters and thin spaces
+            Some('&' | 'c' | ':' | '^' | '|') => {}
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// Runs a command with `--help` and saves its output, for the command's CLI usage.
+/// `help:git commit` runs `git commit --help` and writes `git_commit.help.txt`.
+pub struct HelpSource {
+    command: Vec<String>,
+}
+
+impl HelpSource {
+    pub fn new(command: &str) -> Result<Self> {
+        let command: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+        if command.is_empty() {
+            return Err(anyhow!("help: needs a command, e.g. help:git"));
+        }
+        Ok(Self { command })
+    }
+}
+
+#[async_trait]
+impl DataSource for HelpSource {
+    async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        let command = self.command.join(" ");
+        info!("Running {} --help", command);
+        let output = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .arg("--help")
+            .output()
+            .await
+            .map_err(|e| anyhow!("Failed to run {:?}: {}", command, e))?;
+        // Some tools print their help to stderr, or exit with an error after it
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        if text.trim().is_empty() {
+            text = String::from_utf8_lossy(&output.stderr).into_owned();
+        }
+        if text.trim().is_empty() {
+            return Err(anyhow!("{} --help printed nothing ({})", command, output.status));
+        }
+        let name: Vec<&str> = self
+            .command
+            .iter()
+            .map(|part| Path::new(part).file_name().and_then(|name| name.to_str()).unwrap_or(part))
+            .collect();
+        let path = output_dir.join(format!("{}{}", name.join("_"), HELP_SUFFIX));
+        std::fs::write(&path, text)?;
+        info!("Saved the help of {} to {:?}", command, path);
+        Ok(vec![path])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_help_output() {
+        let text = "Copies files.\n\nUsage: /usr/bin/cp [OPTIONS] <SOURCE> <DEST>\n\nOptions:\n  -r, --recursive  Copy directories\n                   and their contents\n  -v               Explain what is done\n\nEXAMPLES:\n  cp -r a b\n";
+        let reference = help_text(text, "fallback");
+        assert_eq!(reference.name, "cp");
+        assert_eq!(
+            reference.options,
+            [
+                ("-r, --recursive".to_string(), "Copy directories and their contents".to_string()),
+                ("-v".to_string(), "Explain what is done".to_string()),
+            ]
+        );
+        let headings: Vec<&str> = reference.sections.iter().map(|(heading, _)| heading.as_str()).collect();
+        assert_eq!(headings, ["Description", "Usage", "Options", "Examples"]);
+        assert_eq!(reference.sections[0].1.trim(), "Copies files.");
+    }
+
+    #[test]
+    fn help_output_without_a_usage_line_keeps_the_file_name() {
+        let reference = help_text("  --force  Overwrite\n", "tool");
+        assert_eq!(reference.name, "tool");
+        assert_eq!(reference.options, [("--force".to_string(), "Overwrite".to_string())]);
+    }
+
+    #[test]
+    fn reads_man_pages() {
+        let text = ".\\\" a comment\n.TH CP 1\n.SH NAME\ncp \\- copy files\n.SH DESCRIPTION\nCopies\n.B SOURCE\nto\n.IR DEST .\n.PP\nSecond paragraph.\n.SH OPTIONS\n.TP\n.BR \\-r \", \" \\-\\-recursive\ncopy directories\nrecursively\n.IP \\-v\nexplain what is done\n.de XX\nignored\n..\n";
+        let reference = man_page(text, "fallback");
+        assert_eq!(reference.name, "cp");
+        let options: Vec<(&str, &str)> = reference.options.iter().map(|(flags, description)| (flags.as_str(), description.trim())).collect();
+        assert_eq!(options, [("-r, --recursive", "copy directories recursively"), ("-v", "explain what is done")]);
+        let description = &reference.sections.iter().find(|(heading, _)| heading == "Description").unwrap().1;
+        assert_eq!(description.trim(), "Copies SOURCE to DEST.\n\nSecond paragraph.");
+    }
+
+    #[test]
+    fn unescapes_roff() {
+        assert_eq!(unescape("\\fBbold\\fR and \\f(CWcode\\fP"), "bold and code");
+        assert_eq!(unescape("\\-\\-all \\(em done"), "--all — done");
+        assert_eq!(unescape("\\[lq]quoted\\[rq] \\*(lqtoo\\*(rq"), "\"quoted\" \"too\"");
+        assert_eq!(unescape("a\\&b\\e c \\\" comment"), "ab\\ c ");
+        assert_eq!(unescape("\\*[unknown]gone"), "gone");
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, warn, Instrument};
+use crate::changelog::Release;
+use crate::config::{Config, FileSettings};
+use crate::error::Error;
+use crate::metrics::Metrics;
+use crate::preflight;
+use crate::processor::{OllamaProcessor, ProcessedItem};
+use crate::usage::TokenUsage;
+
+/// How long a worker may hold a chunk before it is offered to another worker.
+const LEASE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long an idle worker waits before asking the coordinator for work again.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// One section of a file, with everything a worker needs to generate items from it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Chunk {
+    pub id: u64,
+    /// Source file, relative to the coordinator's output directory.
+    pub file: String,
+    pub title: String,
+    /// Categories leading to the file's page in its docs site's navigation.
+    #[serde(default)]
+    pub breadcrumbs: Vec<String>,
+    pub section: String,
+    pub target: usize,
+    pub settings: FileSettings,
+    #[serde(default)]
+    pub release: Option<Release>,
+    /// Questions the section already has when its file is being topped up, which the
+    /// worker asks the model not to repeat.
+    #[serde(default)]
+    pub existing_questions: Option<Vec<String>>,
+}
+
+/// What a worker sends back for a chunk.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ChunkResult {
+    #[serde(default)]
+    pub items: Vec<ProcessedItem>,
+    #[serde(default)]
+    pub usage: TokenUsage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+struct Lease {
+    chunk: Chunk,
+    worker: String,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct QueueState {
+    queued: VecDeque<Chunk>,
+    leased: HashMap<u64, Lease>,
+    waiting: HashMap<u64, oneshot::Sender<ChunkResult>>,
+    last_seen: BTreeMap<String, Instant>,
+}
+
+/// Coordinator side of distributed generation: sections are queued here by the
+/// processor and pulled by workers over the HTTP API.
+#[derive(Default)]
+pub struct ChunkQueue {
+    state: Mutex<QueueState>,
+    next_id: AtomicU64,
+}
+
+impl ChunkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a section and waits for a worker to return its items. The chunk's `id` is
+    /// assigned here.
+    pub async fn dispatch(&self, mut chunk: Chunk) -> ChunkResult {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        chunk.id = id;
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.queued.push_back(chunk);
+            state.waiting.insert(id, sender);
+        }
+        debug!("Queued chunk {} for workers", id);
+        receiver.await.unwrap_or_else(|_| ChunkResult {
+            error: Some(Error::Api("Chunk was dropped by the coordinator".to_string())),
+            ..Default::default()
+        })
+    }
+
+    /// Hands the oldest queued chunk to `worker`. Chunks whose lease ran out are queued
+    /// again first, so work held by a crashed worker isn't lost.
+    pub fn claim(&self, worker: &str) -> Option<Chunk> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.last_seen.insert(worker.to_string(), now);
+
+        let expired: Vec<u64> = state
+            .leased
+            .iter()
+            .filter(|(_, lease)| lease.expires <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some(lease) = state.leased.remove(&id) {
+                warn!("Worker {} did not finish chunk {} in time; requeueing it", lease.worker, id);
+                state.queued.push_front(lease.chunk);
+            }
+        }
+
+        let chunk = state.queued.pop_front()?;
+        state.leased.insert(
+            chunk.id,
+            Lease {
+                chunk: chunk.clone(),
+                worker: worker.to_string(),
+                expires: now + LEASE_TIMEOUT,
+            },
+        );
+        Some(chunk)
+    }
+
+    /// Records a worker's result. Returns false when the chunk is unknown or was
+    /// already completed by another worker.
+    pub fn complete(&self, id: u64, worker: &str, result: ChunkResult) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.last_seen.insert(worker.to_string(), Instant::now());
+        state.leased.remove(&id);
+        state.queued.retain(|chunk| chunk.id != id);
+        match state.waiting.remove(&id) {
+            Some(sender) => sender.send(result).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Queue depth and the workers seen so far, with seconds since their last request.
+    pub fn status(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let workers: BTreeMap<&String, u64> = state
+            .last_seen
+            .iter()
+            .map(|(worker, seen)| (worker, seen.elapsed().as_secs()))
+            .collect();
+        json!({
+            "queued": state.queued.len(),
+            "leased": state.leased.len(),
+            "workers": workers,
+        })
+    }
+}
+
+/// Pulls chunks from the coordinator at `coordinator` and generates items for them with
+/// the local Ollama endpoint, `concurrency` chunks at a time, until the process exits.
+pub async fn run_worker(config: &Config, coordinator: &str, concurrency: usize, name: &str) -> Result<()> {
+    let coordinator = coordinator.trim_end_matches('/').to_string();
+    // Models come with each chunk, so only the server itself can be checked up front
+    if config.preflight {
+        preflight::check_server(&config.endpoint).await?;
+    }
+    let config = Arc::new(config.clone());
+    let metrics = Metrics::global();
+    let client = Client::new();
+    info!("Worker {} pulling chunks from {} ({} at a time)", name, coordinator, concurrency);
+
+    let mut handles = Vec::new();
+    for slot in 0..concurrency {
+        let worker = Worker {
+            config: config.clone(),
+            metrics: metrics.clone(),
+            client: client.clone(),
+            coordinator: coordinator.clone(),
+            name: name.to_string(),
+        };
+        handles.push(tokio::spawn(async move { worker.work().await }.instrument(tracing::info_span!("worker", slot))));
+    }
+    for handle in handles {
+        handle.await?;
+    }
+    Ok(())
+}
+
+struct Worker {
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+    client: Client,
+    coordinator: String,
+    name: String,
+}
+
+impl Worker {
+    async fn work(&self) {
+        loop {
+            match self.claim().await {
+                Ok(Some(chunk)) => {
+                    let id = chunk.id;
+                    let result = self.generate(chunk).instrument(tracing::info_span!("chunk", id)).await;
+                    if let Err(e) = self.report(id, &result).await {
+                        error!("Failed to return chunk {} to the coordinator: {}", id, e);
+                    }
+                }
+                Ok(None) => tokio::time::sleep(POLL_INTERVAL).await,
+                Err(e) => {
+                    warn!("Failed to reach the coordinator: {}", e);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    async fn claim(&self) -> Result<Option<Chunk>> {
+        let response = self
+            .client
+            .post(format!("{}/api/chunks/claim", self.coordinator))
+            .json(&json!({ "worker": self.name }))
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(None),
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            status => Err(anyhow!("Coordinator error ({}): {}", status, response.text().await?)),
+        }
+    }
+
+    async fn generate(&self, chunk: Chunk) -> ChunkResult {
+        info!("Generating from section of {} ({} questions)", chunk.file, chunk.target);
+        // A processor per chunk keeps token usage separate for chunks of the same file
+        let processor = OllamaProcessor::new(self.config.clone(), self.metrics.clone())
+            .with_failure_archive(std::path::Path::new(&self.config.output_dir).join("failures"));
+        let result = processor.generate_chunk(&chunk).await;
+        let usage = processor.usage().total();
+        match result {
+            Ok(items) => ChunkResult { items, usage, error: None },
+            Err(e) => {
+                error!("Error processing chunk {}: {}", chunk.id, e);
+                ChunkResult {
+                    items: Vec::new(),
+                    usage,
+                    error: Some(Error::from(e)),
+                }
+            }
+        }
+    }
+
+    async fn report(&self, id: u64, result: &ChunkResult) -> Result<()> {
+        let response = self
+            .client
+            .post(format!("{}/api/chunks/{}/result", self.coordinator, id))
+            .json(&json!({ "worker": self.name, "result": result }))
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::GONE => {
+                debug!("Chunk {} was already completed elsewhere", id);
+                Ok(())
+            }
+            status => Err(anyhow!("Coordinator error ({}): {}", status, response.text().await?)),
+        }
+    }
+}
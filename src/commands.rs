@@ -0,0 +1,440 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use tracing::{debug, info, warn};
+use crate::card::{self, Split};
+use crate::compression::{self, Compression};
+use crate::config::{Config, CurriculumConfig, SchemaConfig};
+use crate::curriculum;
+use crate::dataset::{self, RecordFormat, RecordWriter, Schema, SystemPrompts};
+use crate::discourse;
+use crate::faq;
+use crate::freshness;
+use crate::pipeline::{self, ParseStage};
+use crate::plugins::Plugins;
+use crate::processor::{ItemSource, ProcessedItem};
+use crate::stack_exchange;
+use crate::tickets;
+
+/// Concatenates datasets into `output`, in the order given. Items of an input that an
+/// earlier input already has, with the same id and hash, are left out.
+pub fn merge(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    let mut items = Vec::new();
+    let mut merged = HashSet::new();
+    for input in inputs {
+        let loaded = dataset::read_items(input)?;
+        let total = loaded.len();
+        let new: Vec<ProcessedItem> = loaded.into_iter().filter(|item| !merged.contains(&(item.id.clone(), item.hash.clone()))).collect();
+        match new.len() < total {
+            true => info!("Read {} items from {:?}, {} of them already merged", total, input, total - new.len()),
+            false => info!("Read {} items from {:?}", total, input),
+        }
+        merged.extend(new.iter().map(|item| (item.id.clone(), item.hash.clone())));
+        items.extend(new);
+    }
+    dataset::write_items(output, &items, dataset::layout_for(output), RecordFormat::Qa)?;
+    info!("Wrote {} items to {:?}", items.len(), output);
+    Ok(())
+}
+
+/// Drops items whose question (ignoring case, whitespace and punctuation) was already
+/// seen, keeping the first occurrence.
+pub fn dedupe(input: &Path, output: &Path) -> Result<()> {
+    let items = dataset::read_items(input)?;
+    let total = items.len();
+    let mut seen = HashSet::new();
+    let unique: Vec<ProcessedItem> = items
+        .into_iter()
+        .filter(|item| seen.insert(dataset::question_key(item)))
+        .collect();
+    dataset::write_items(output, &unique, dataset::layout_for(output), RecordFormat::Qa)?;
+    info!("Removed {} duplicates; wrote {} items to {:?}", total - unique.len(), unique.len(), output);
+    Ok(())
+}
+
+fn word_stats(counts: &[usize]) -> String {
+    if counts.is_empty() {
+        return "-".to_string();
+    }
+    let min = counts.iter().min().unwrap();
+    let max = counts.iter().max().unwrap();
+    let mean = counts.iter().sum::<usize>() as f64 / counts.len() as f64;
+    format!("min {} / mean {:.1} / max {}", min, mean, max)
+}
+
+/// Prints item counts, duplicate questions, answer lengths and extra fields per dataset.
+pub fn stats(inputs: &[PathBuf]) -> Result<()> {
+    for input in inputs {
+        let items = dataset::read_items(input)?;
+        let mut seen = HashSet::new();
+        let duplicates = items.iter().filter(|item| !seen.insert(dataset::question_key(item))).count();
+        let question_words: Vec<usize> = items.iter().map(|i| i.question.split_whitespace().count()).collect();
+        let answer_words: Vec<usize> = items.iter().map(|i| i.answer.split_whitespace().count()).collect();
+        let mut extra_fields: BTreeMap<&str, usize> = BTreeMap::new();
+        for item in &items {
+            for key in item.extra.keys() {
+                *extra_fields.entry(key).or_default() += 1;
+            }
+        }
+
+        println!("{}", input.display());
+        println!("  items:              {}", items.len());
+        println!("  duplicate questions: {}", duplicates);
+        println!("  question words:     {}", word_stats(&question_words));
+        println!("  answer words:       {}", word_stats(&answer_words));
+        for (field, count) in extra_fields {
+            println!("  field {:<13} {} items", format!("{}:", field), count);
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites a dataset in another record format and/or layout, with system turns from
+/// `system_prompts` in the chat formats.
+pub fn convert(input: &Path, output: &Path, format: RecordFormat, system_prompts: &SystemPrompts) -> Result<()> {
+    if format == RecordFormat::Qa && !system_prompts.0.is_empty() {
+        return Err(anyhow!("System prompts only apply to the sharegpt and openai formats"));
+    }
+    let items = dataset::read_items(input)?;
+    let writer = RecordWriter::create(output, dataset::layout_for(output), format)?.with_system_prompts(system_prompts.clone());
+    dataset::write_records(writer, &items)?;
+    info!("Converted {} items to {:?}", items.len(), output);
+    Ok(())
+}
+
+/// Words too common to tell which section an answer came from.
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "can", "do", "for", "from", "has", "have", "how", "if", "in", "is",
+    "it", "its", "not", "of", "on", "or", "so", "that", "the", "then", "this", "to", "was", "what", "when", "which",
+    "with", "you", "your",
+];
+
+/// Share of an answer's words a section needs for the answer to be attributed to it.
+const MIN_SHARED_WORDS: f64 = 0.5;
+
+/// A source file of the items being migrated: its sections, as the pipeline splits it,
+/// and whether its items were taken from it as they are.
+struct SourceFile {
+    sections: Vec<String>,
+    extracted: bool,
+}
+
+impl SourceFile {
+    async fn read(parse: &ParseStage, config: &Config, file: &str) -> Option<Self> {
+        let path = Path::new(&config.output_dir).join(file);
+        let prepared = match parse.prepared_content(file).await {
+            Ok(prepared) => prepared?,
+            Err(e) => {
+                debug!("Can't read the sections of {:?}: {}", file, e);
+                return None;
+            }
+        };
+        Some(Self {
+            extracted: stack_exchange::is_dump(&path)
+                || discourse::is_export(&path)
+                || tickets::is_ticket_export(&path)
+                || (config.faq.extract && !faq::items(&prepared.content, file, &config.faq).is_empty()),
+            sections: prepared.sections(),
+        })
+    }
+
+    /// The section that has the largest share of `answer`'s words, stopwords aside, if
+    /// one has at least half of them; the first of equally good ones.
+    fn section_of(&self, answer: &str) -> Option<usize> {
+        let words = |text: &str| -> HashSet<String> {
+            text.split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .filter(|word| !STOPWORDS.contains(&word.as_str()))
+                .collect()
+        };
+        let answer = words(answer);
+        if answer.is_empty() {
+            return None;
+        }
+        self.sections
+            .iter()
+            .enumerate()
+            .map(|(index, section)| (answer.intersection(&words(section)).count() as f64 / answer.len() as f64, index))
+            .filter(|(share, _)| *share >= MIN_SHARED_WORDS)
+            .max_by(|(a, i), (b, j)| a.total_cmp(b).then(j.cmp(i)))
+            .map(|(_, index)| index)
+    }
+}
+
+/// The file, relative to the output directory, that `input` holds the items of when it
+/// is a per-file `<name>_qa.jsonl` (or `_qa.json`) next to it.
+fn source_of(config: &Config, input: &Path) -> Option<String> {
+    let output_dir = Path::new(&config.output_dir).canonicalize().ok()?;
+    let input = input.canonicalize().ok()?;
+    let candidates: Vec<PathBuf> = std::fs::read_dir(input.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path != &input)
+        .filter(|path| pipeline::qa_path(path, "jsonl") == input || pipeline::qa_path(path, "json") == input)
+        .collect();
+    match candidates.as_slice() {
+        [path] => Some(path.strip_prefix(&output_dir).ok()?.display().to_string()),
+        [] => None,
+        _ => {
+            warn!("Leaving out the sources of items: several files could have produced {:?}", input);
+            None
+        }
+    }
+}
+
+/// Rewrites a dataset in the `to` schema. For `v2`, items of a per-file `_qa` file that
+/// have no source are attributed to the section of the file next to it that shares the
+/// largest share of their answer's words, sources get their chunk id and modification date when
+/// the file still exists, and every item gets an id and hash. `v1` keeps only the
+/// question and answer.
+pub async fn migrate(config: &Config, input: &Path, output: &Path, to: Schema) -> Result<()> {
+    let mut items = dataset::read_stored_items(input)?;
+    if to == Schema::V1 {
+        let items: Vec<ProcessedItem> = items
+            .into_iter()
+            .map(|item| ProcessedItem {
+                id: None,
+                citation: None,
+                source: None,
+                hash: None,
+                extra: Default::default(),
+                ..item
+            })
+            .collect();
+        dataset::write_items(output, &items, dataset::layout_for(output), RecordFormat::Qa)?;
+        info!("Wrote {} items to {:?} as plain question/answer records", items.len(), output);
+        return Ok(());
+    }
+
+    let file = source_of(config, input);
+    let parse = ParseStage::new(Arc::new(config.clone()), Arc::new(Plugins::load(&config.plugins)?))?;
+    let mut sources: HashMap<String, Option<SourceFile>> = HashMap::new();
+    let (mut attributed, mut chunked, mut dated, mut identified) = (0, 0, 0, 0);
+    for item in &mut items {
+        if let (None, Some(file)) = (&item.source, &file) {
+            if !sources.contains_key(file) {
+                sources.insert(file.clone(), SourceFile::read(&parse, config, file).await);
+            }
+            if let Some(section) = sources[file].as_ref().and_then(|source| source.section_of(&item.answer)) {
+                item.source = Some(ItemSource {
+                    file: file.clone(),
+                    section,
+                    chunk: None,
+                    image: None,
+                    modified: None,
+                });
+                attributed += 1;
+            }
+        }
+        if let Some(source) = &mut item.source {
+            if source.chunk.is_none() {
+                if !sources.contains_key(&source.file) {
+                    sources.insert(source.file.clone(), SourceFile::read(&parse, config, &source.file).await);
+                }
+                let text = match &sources[&source.file] {
+                    Some(file) if file.extracted => Some(format!("{}\n\n{}", item.question, item.answer)),
+                    Some(file) => file.sections.get(source.section).cloned(),
+                    None => None,
+                };
+                if let Some(text) = text {
+                    source.chunk = Some(pipeline::chunk_id(&text));
+                    chunked += 1;
+                }
+            }
+            if source.modified.is_none() {
+                source.modified = freshness::modified(&Path::new(&config.output_dir).join(&source.file)).map(freshness::date);
+                dated += usize::from(source.modified.is_some());
+            }
+        }
+        identified += usize::from(item.id.is_none());
+        dataset::identify(item);
+    }
+    dataset::write_items(output, &items, dataset::layout_for(output), RecordFormat::Qa)?;
+    info!(
+        "Wrote {} items to {:?}: {} given ids, {} attributed to a section, {} given chunk ids and {} dated",
+        items.len(), output, identified, attributed, chunked, dated
+    );
+    let missing = items.iter().filter(|item| item.source.is_none()).count();
+    if missing > 0 {
+        warn!("{} items have no source; they keep ids derived from their question alone", missing);
+    }
+    Ok(())
+}
+
+/// Checks that every line of a JSONL dataset parses and is a well-formed `format`
+/// record, printing each problem with its line number. Returns the number of broken
+/// records.
+pub fn validate(input: &Path, format: RecordFormat, required: &[String]) -> Result<usize> {
+    let content = compression::read_to_string(input)
+        .map_err(|e| anyhow!("Failed to read {:?}: {}", input, e))?;
+    let mut errors = 0;
+    let mut records = 0;
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        records += 1;
+        let problems = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(record) => format.check(&record, required),
+            Err(e) => vec![format!("invalid JSON: {}", e)],
+        };
+        if !problems.is_empty() {
+            errors += 1;
+            for problem in problems {
+                println!("{}:{}: {}", input.display(), i + 1, problem);
+            }
+        }
+    }
+    if records == 0 {
+        println!("{}: no records", input.display());
+        errors += 1;
+    }
+    info!("Checked {} records in {:?}: {} invalid", records, input, errors);
+    Ok(errors)
+}
+
+/// Shuffles a dataset and writes `train`, `validation` and `test` files into
+/// `output_dir`, compressed like the input. Ratios are normalized, so they need not sum
+/// to one; empty splits are skipped. The same seed always produces the same split.
+pub fn split(input: &Path, output_dir: &Path, ratios: [f64; 3], seed: Option<u64>) -> Result<()> {
+    if ratios.iter().any(|r| *r < 0.0) || ratios.iter().sum::<f64>() <= 0.0 {
+        return Err(anyhow!("Split ratios must be non-negative and not all zero"));
+    }
+    let mut items = dataset::read_items(input)?;
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    items.shuffle(&mut rng);
+
+    let total: f64 = ratios.iter().sum();
+    let train = (items.len() as f64 * ratios[0] / total).round() as usize;
+    let validation = ((items.len() as f64 * ratios[1] / total).round() as usize).min(items.len() - train);
+    let test = items.split_off(train + validation);
+    let validation = items.split_off(train);
+
+    let mut splits = Vec::new();
+    for (name, part) in [("train", &items), ("validation", &validation), ("test", &test)] {
+        if part.is_empty() {
+            warn!("Split {:?} is empty, not writing it", name);
+            continue;
+        }
+        let file = format!("{}.jsonl{}", name, Compression::for_path(input).map_or("", Compression::extension));
+        let path = output_dir.join(&file);
+        dataset::write_items(&path, part, dataset::layout_for(&path), RecordFormat::Qa)?;
+        info!("Wrote {} items to {:?}", part.len(), path);
+        splits.push(Split { name: name.to_string(), file, items: part.len() });
+    }
+    if card::update_splits(output_dir, &splits)? {
+        info!("Updated the splits in {:?}", output_dir.join(card::DATASET_CARD_FILE));
+    }
+    Ok(())
+}
+
+/// Orders a dataset for curriculum fine-tuning, by difficulty label or length.
+pub fn order(input: &Path, output: &Path, config: &CurriculumConfig, schema: &SchemaConfig) -> Result<()> {
+    let mut items = dataset::read_items(input)?;
+    curriculum::order(&mut items, config, schema)?;
+    dataset::write_items(output, &items, dataset::layout_for(output), RecordFormat::Qa)?;
+    info!("Wrote {} items ordered by {:?} to {:?}", items.len(), config.order, output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "# Setup\n\nInstall the builder with cargo and point it at a running Ollama server.\n\n\
+        # Export\n\nExported datasets are written as JSONL files under the output directory.\n";
+    // Fields in the order datasets are written in, so a round trip gives the same file
+    const V1: &str = "{\"answer\":\"Install it with cargo and point it at a running Ollama server.\",\"question\":\"How is the builder installed?\"}\n\
+        {\"answer\":\"Datasets are written as JSONL files under the output directory.\",\"question\":\"Where do exports go?\"}\n\
+        {\"answer\":\"Sunny.\",\"question\":\"What is the weather?\"}\n";
+
+    /// An output directory holding `docs/guide.md` and its items in the v1 schema.
+    fn output_dir() -> (Config, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("llmds-migrate-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("docs/guide.md"), DOC).unwrap();
+        std::fs::write(dir.join("docs/guide_qa.jsonl"), V1).unwrap();
+        let config = Config {
+            output_dir: dir.display().to_string(),
+            ..Config::default()
+        };
+        (config, dir)
+    }
+
+    #[test]
+    fn answers_are_attributed_to_the_section_with_most_of_their_words() {
+        let file = SourceFile {
+            sections: vec![
+                "# Setup\n\nInstall the builder with cargo.".to_string(),
+                "# Export\n\nExported datasets are written as JSONL files.".to_string(),
+            ],
+            extracted: false,
+        };
+        assert_eq!(file.section_of("The datasets are written as JSONL files."), Some(1));
+        assert_eq!(file.section_of("Install it with cargo."), Some(0));
+        // Sharing only stopwords or too few words attributes nothing
+        assert_eq!(file.section_of("It is with the files of the weather and the moon and the sun."), None);
+        assert_eq!(file.section_of("the and with"), None);
+    }
+
+    #[tokio::test]
+    async fn migrating_to_v2_and_back_restores_the_items() {
+        let (config, dir) = output_dir();
+        let input = dir.join("docs/guide_qa.jsonl");
+        let v2 = dir.join("v2.jsonl");
+        migrate(&config, &input, &v2, Schema::V2).await.unwrap();
+        let items = dataset::read_stored_items(&v2).unwrap();
+        let sections: Vec<Option<usize>> = items.iter().map(|item| item.source.as_ref().map(|source| source.section)).collect();
+        assert_eq!(sections, [Some(0), Some(1), None]);
+        assert!(items.iter().all(|item| item.id.is_some() && item.hash.is_some()));
+        assert!(items[0].source.as_ref().unwrap().chunk.is_some());
+
+        let v1 = dir.join("v1.jsonl");
+        migrate(&config, &v2, &v1, Schema::V1).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&v1).unwrap(), V1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrating_twice_changes_nothing() {
+        let (config, dir) = output_dir();
+        let (once, twice) = (dir.join("once.jsonl"), dir.join("twice.jsonl"));
+        migrate(&config, &dir.join("docs/guide_qa.jsonl"), &once, Schema::V2).await.unwrap();
+        migrate(&config, &once, &twice, Schema::V2).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&once).unwrap(), std::fs::read_to_string(&twice).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ordered_datasets_are_written_easiest_first() {
+        let dir = std::env::temp_dir().join(format!("llmds-order-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("dataset.jsonl");
+        std::fs::write(
+            &input,
+            "{\"question\":\"Why does the sync fail?\",\"answer\":\"The token expired.\",\"difficulty\":\"hard\"}\n\
+             {\"question\":\"What is it?\",\"answer\":\"A builder.\",\"difficulty\":\"easy\"}\n",
+        )
+        .unwrap();
+        let config = CurriculumConfig {
+            order: curriculum::CurriculumOrder::Difficulty,
+            levels: vec!["easy".to_string(), "hard".to_string()],
+            ..CurriculumConfig::default()
+        };
+        let output = dir.join("ordered.jsonl.gz");
+        order(&input, &output, &config, &SchemaConfig::default()).unwrap();
+        let items = dataset::read_stored_items(&output).unwrap();
+        let questions: Vec<&str> = items.iter().map(|item| item.question.as_str()).collect();
+        assert_eq!(questions, ["What is it?", "Why does the sync fail?"]);
+        assert!(items.iter().all(|item| item.id.is_some()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// zstd level for written files: the library default, a good trade-off for JSONL.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression of a file, told by its extension: `.gz` or `.zst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn for_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The extension, with its dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+/// The path without its compression extension, e.g. `docs/intro.md` for
+/// `docs/intro.md.gz`; used to tell what kind of file is inside.
+pub fn inner_path(path: &Path) -> PathBuf {
+    match Compression::for_path(path) {
+        Some(_) => path.with_extension(""),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Opens the file at `path`, decompressing it while it is read.
+pub fn open(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let file = File::open(path)?;
+    Ok(match Compression::for_path(path) {
+        None => Box::new(file),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(file)?),
+    })
+}
+
+/// The decompressed content of the file at `path`.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// The decompressed content of the file at `path`, which must be UTF-8.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// A file being written, compressed according to its extension. `finish` must be called
+/// to complete a compressed file.
+pub enum CompressedFile {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl CompressedFile {
+    /// Creates (or truncates) the file at `path`.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::create_with(path, Compression::for_path(path))
+    }
+
+    /// Creates (or truncates) the file at `path` with `compression`, whatever its
+    /// extension; for temporary files that are renamed once complete.
+    pub fn create_with(path: &Path, compression: Option<Compression>) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(match compression {
+            None => CompressedFile::Plain(file),
+            Some(Compression::Gzip) => CompressedFile::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Some(Compression::Zstd) => CompressedFile::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?),
+        })
+    }
+
+    /// Writes the end of the compressed stream and flushes the file.
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            CompressedFile::Plain(file) => file,
+            CompressedFile::Gzip(encoder) => encoder.finish()?,
+            CompressedFile::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl Write for CompressedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedFile::Plain(file) => file.write(buf),
+            CompressedFile::Gzip(encoder) => encoder.write(buf),
+            CompressedFile::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedFile::Plain(file) => file.flush(),
+            CompressedFile::Gzip(encoder) => encoder.flush(),
+            CompressedFile::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use serde_json::json;
+    use crate::dataset::{self, RecordFormat};
+    use crate::processor::ProcessedItem;
+
+    #[test]
+    fn compressed_datasets_read_back() {
+        let dir = std::env::temp_dir().join(format!("llmds-compression-{:016x}", rand::random::<u64>()));
+        let items: Vec<ProcessedItem> = [("Is it free?", "Yes."), ("How do I export it?", "Run export.")]
+            .iter()
+            .map(|(question, answer)| serde_json::from_value(json!({ "question": question, "answer": answer })).unwrap())
+            .collect();
+        for (name, magic) in [("items.jsonl.gz", &[0x1f, 0x8b][..]), ("items.jsonl.zst", &[0x28, 0xb5, 0x2f, 0xfd][..]), ("items.json.gz", &[0x1f, 0x8b][..])] {
+            let path = dir.join(name);
+            dataset::write_items(&path, &items, dataset::layout_for(&path), RecordFormat::Qa).unwrap();
+            assert!(fs::read(&path).unwrap().starts_with(magic), "{name}");
+            let read = dataset::read_items(&path).unwrap();
+            let pairs: Vec<(&str, &str)> = read.iter().map(|item| (item.question.as_str(), item.answer.as_str())).collect();
+            assert_eq!(pairs, [("Is it free?", "Yes."), ("How do I export it?", "Run export.")], "{name}");
+        }
+        assert!(read_to_string(&dir.join("items.json.gz")).unwrap().starts_with("[\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gzip_members_are_read_in_turn() {
+        let path = std::env::temp_dir().join(format!("llmds-compression-{:016x}.md.gz", rand::random::<u64>()));
+        let mut bytes = Vec::new();
+        for part in ["# Intro\n", "Second member.\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            bytes.extend(encoder.finish().unwrap());
+        }
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "# Intro\nSecond member.\n");
+        assert_eq!(inner_path(&path).extension().and_then(|ext| ext.to_str()), Some("md"));
+        fs::remove_file(path).unwrap();
+    }
+}
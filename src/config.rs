@@ -0,0 +1,2016 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::answer_length::AnswerLength;
+use crate::changelog::{self, ReleaseTracker};
+use crate::cli_help;
+use crate::compression;
+use crate::config_reference;
+use crate::curriculum::{self, CurriculumOrder};
+use crate::dataset::{FieldMapping, RecordFormat, SystemPrompts};
+use crate::deprecation::Deprecations;
+use crate::frontmatter;
+use crate::infrastructure;
+use crate::logs;
+use crate::markdown;
+use crate::noise::NoiseKind;
+use crate::processor::DEFAULT_MODEL;
+use crate::reading_level::ReadingLevel;
+use crate::sql_schema;
+use crate::strip::Stripper;
+use crate::terms::TermNormalizer;
+
+/// Config files looked up in the working directory when `--config` is not given.
+pub const DEFAULT_CONFIG_FILES: &[&str] = &["dataset-builder.toml", "dataset-builder.yaml", "dataset-builder.yml"];
+
+/// Per-directory config file in the output directory, overriding settings for its subtree.
+pub const LOCAL_CONFIG_FILE: &str = ".llmds.toml";
+
+/// Run configuration, read from `dataset-builder.toml` (or `.yaml`). Every field is
+/// optional; command-line flags take precedence over values set here.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub output_dir: String,
+    pub endpoint: String,
+    /// Endpoints tried in order when `endpoint` can't be reached during a run.
+    pub backup_endpoints: Vec<String>,
+    /// Chat requests taking longer than this fail, or go to the next endpoint.
+    pub request_timeout_secs: u64,
+    pub model: String,
+    pub pricing: Option<String>,
+    /// Seed passed to the model and used for sampling, shuffling and splitting.
+    pub seed: Option<u64>,
+    pub plain: bool,
+    pub log_json: bool,
+    pub metrics_addr: Option<SocketAddr>,
+    pub otlp_endpoint: Option<String>,
+    /// Check the endpoint and models before collecting any files.
+    pub preflight: bool,
+    /// Files larger than this many MiB are read section by section instead of at once.
+    pub stream_threshold_mb: u64,
+    pub notify: NotifyConfig,
+    pub hooks: HooksConfig,
+    pub embeddings: EmbeddingsConfig,
+    pub transcription: TranscriptionConfig,
+    pub ocr: OcrConfig,
+    pub prompts: PromptsConfig,
+    pub chunking: ChunkingConfig,
+    pub strip: StripConfig,
+    pub images: ImagesConfig,
+    pub lists: ListsConfig,
+    pub deprecations: DeprecationsConfig,
+    pub section_dedupe: SectionDedupeConfig,
+    pub synthesis: SynthesisConfig,
+    pub refusals: RefusalsConfig,
+    pub sql: SqlConfig,
+    pub tickets: TicketsConfig,
+    pub faq: FaqConfig,
+    pub stack_exchange: StackExchangeConfig,
+    pub discourse: DiscourseConfig,
+    pub help_center: HelpCenterConfig,
+    pub style: StyleConfig,
+    pub crawl: CrawlConfig,
+    pub auth: AuthConfig,
+    pub instructions: InstructionsConfig,
+    pub follow_ups: FollowUpsConfig,
+    pub noise: NoiseConfig,
+    pub filters: FilterConfig,
+    pub output: OutputConfig,
+    pub curriculum: CurriculumConfig,
+    pub answer_length: AnswerLengthConfig,
+    pub reading_level: ReadingLevelConfig,
+    pub density: DensityConfig,
+    pub steering: SteeringConfig,
+    pub terms: TermsConfig,
+    pub sampling: SamplingConfig,
+    pub overrides: Vec<DirectoryOverride>,
+    pub weights: Vec<WeightConfig>,
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// Profile used for every file instead of selecting one through the rules.
+    pub profile: Option<String>,
+    pub rules: Vec<RuleConfig>,
+    pub plugins: Vec<PluginConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output_dir: "output".to_string(),
+            endpoint: "http://localhost:11434".to_string(),
+            backup_endpoints: Vec::new(),
+            request_timeout_secs: 600,
+            model: DEFAULT_MODEL.to_string(),
+            pricing: None,
+            seed: None,
+            plain: false,
+            log_json: false,
+            metrics_addr: None,
+            otlp_endpoint: None,
+            preflight: true,
+            stream_threshold_mb: 64,
+            notify: NotifyConfig::default(),
+            hooks: HooksConfig::default(),
+            embeddings: EmbeddingsConfig::default(),
+            transcription: TranscriptionConfig::default(),
+            ocr: OcrConfig::default(),
+            prompts: PromptsConfig::default(),
+            chunking: ChunkingConfig::default(),
+            strip: StripConfig::default(),
+            images: ImagesConfig::default(),
+            lists: ListsConfig::default(),
+            deprecations: DeprecationsConfig::default(),
+            section_dedupe: SectionDedupeConfig::default(),
+            synthesis: SynthesisConfig::default(),
+            refusals: RefusalsConfig::default(),
+            sql: SqlConfig::default(),
+            tickets: TicketsConfig::default(),
+            faq: FaqConfig::default(),
+            stack_exchange: StackExchangeConfig::default(),
+            discourse: DiscourseConfig::default(),
+            help_center: HelpCenterConfig::default(),
+            style: StyleConfig::default(),
+            crawl: CrawlConfig::default(),
+            auth: AuthConfig::default(),
+            instructions: InstructionsConfig::default(),
+            follow_ups: FollowUpsConfig::default(),
+            noise: NoiseConfig::default(),
+            filters: FilterConfig::default(),
+            output: OutputConfig::default(),
+            curriculum: CurriculumConfig::default(),
+            answer_length: AnswerLengthConfig::default(),
+            reading_level: ReadingLevelConfig::default(),
+            density: DensityConfig::default(),
+            sampling: SamplingConfig::default(),
+            steering: SteeringConfig::default(),
+            terms: TermsConfig::default(),
+            overrides: Vec::new(),
+            weights: Vec::new(),
+            profiles: BTreeMap::new(),
+            profile: None,
+            rules: Vec::new(),
+            plugins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub url: Option<String>,
+    pub template: Option<String>,
+}
+
+/// A WebAssembly module that filters items or turns files of another format into text.
+/// `path` is relative to the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub path: PathBuf,
+    pub kind: PluginKind,
+    /// File extensions a parser handles, without the dot.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginKind {
+    Filter,
+    Parser,
+}
+
+/// Shell commands run on pipeline events; each receives the event as JSON on stdin.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    pub item_generated: Option<String>,
+    pub item_rejected: Option<String>,
+    pub file_completed: Option<String>,
+    pub run_completed: Option<String>,
+    /// Seconds a hook command may run before it is killed.
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            item_generated: None,
+            item_rejected: None,
+            file_completed: None,
+            run_completed: None,
+            timeout_secs: 30,
+        }
+    }
+}
+
+const DOCS_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about technical documentation. \
+    Format your response as JSON. Keep answers concise and factual. \
+    Focus on the technical details and functionality being described.";
+
+const DOCS_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from this documentation. \
+    Focus on key concepts, features, and usage. \
+    {% if breadcrumbs %}The page is under {{ breadcrumbs|join(' > ') }} in the docs' navigation; make every question specific \
+    to that area, naming it where the question would otherwise be vague. {% endif %}\
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if style_examples %} Write answers in the voice and format of these replies from the team, without copying what they say:\n\
+    {% for example in style_examples %}---\n{{ example }}\n{% endfor %}---{% endif %}\
+    {% if deprecations %} The content marks these features as deprecated or removed:\n\
+    {% for notice in deprecations %}- {{ notice }}\n{% endfor %}\
+    Ask whether each of them is still supported, e.g. \"Is ... still supported?\", and answer that it is deprecated or removed, \
+    with the version and the replacement when the content names them. Never present them as the current way to do something.{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const RELEASE_NOTES_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about software release notes. \
+    Format your response as JSON. Keep answers concise and factual. \
+    Focus on the specific changes and improvements in this version.";
+
+const RELEASE_NOTES_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from these release notes. \
+    Focus on specific changes, features, and improvements. \
+    Format as JSON array with 'question' and 'answer' fields. \
+    Questions should be detailed and specific to the version mentioned in the notes.\
+    {% if version %} These notes are for version {{ version }}{% if release_date %}, released {{ release_date }}{% endif %}; \
+    name the version in every question, e.g. \"What changed in {{ version }}?\".{% endif %}\
+    {% if change_type %} The content is a single entry from the {{ change_type }} list; ask about that change.{% endif %}\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const CONFIG_REFERENCE_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about the settings in a product's \
+    configuration reference. Format your response as JSON. Keep answers concise and factual, and only give the defaults, types and \
+    allowed values the content states.";
+
+const CONFIG_REFERENCE_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from this configuration reference. \
+    Ask what each setting does and, when the content gives one, what its default is, naming the setting by its full key, \
+    e.g. \"What does the `server.port` setting do?\" and \"What is the default value of `server.port`?\". \
+    Ask about the allowed values of settings that list them. \
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const CLI_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about how to use a command-line tool, \
+    from its help text or man page. Format your response as JSON. Keep answers concise and factual, write flags and commands \
+    exactly as the content does, and only describe behaviour the content states.";
+
+const CLI_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from this documentation of the {{ doc_title }} command. \
+    Ask what its flags and subcommands do and how to use them for a task, naming the command and the flag, \
+    e.g. \"What does the -r flag of {{ doc_title }} do?\" or \"How do I ... with {{ doc_title }}?\", \
+    and answer with the exact command line when the content gives one. \
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if deprecations %} The content marks these features as deprecated or removed:\n\
+    {% for notice in deprecations %}- {{ notice }}\n{% endfor %}\
+    Ask whether each of them is still supported, e.g. \"Is ... still supported?\", and answer that it is deprecated or removed, \
+    with the version and the replacement when the content names them. Never present them as the current way to do something.{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const INFRASTRUCTURE_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about infrastructure code, \
+    such as Terraform configurations and Kubernetes manifests, for platform engineers. Format your response as JSON. \
+    Keep answers concise and factual, and name resources, values and settings exactly as the code does.";
+
+const INFRASTRUCTURE_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from this infrastructure code. \
+    Ask what it creates and how it is configured, naming the resource or object, \
+    e.g. \"What resources does this module create?\", \"What image does deployment api run?\" or \"What is the replica count of deployment api?\", \
+    and answer from the code and the facts listed with it. \
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const SQL_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about database schemas \
+    for the developers and analysts who query them. Format your response as JSON. \
+    Keep answers concise and factual, and name tables, columns and types exactly as the schema does.";
+
+const SQL_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from this database schema. \
+    Ask about its tables, columns, types and relationships, naming the table, \
+    e.g. \"What columns does the orders table have?\", \"What type is users.email?\" or \"How are orders related to customers?\", \
+    and answer from the columns, keys and indexes listed with each table. \
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const LOGS_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates troubleshooting questions and answers from application logs \
+    for support assistants. Format your response as JSON. \
+    Explain causes only as far as the log lines show them, and keep fixes to the steps that follow from them.";
+
+const LOGS_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique troubleshooting questions and answers from this log. \
+    Ask what causes the error and how to fix it, quoting its message or error code the way a user pasting it would, \
+    e.g. \"What causes error E123 and how do you fix it?\" or \"Why does the service log 'connection refused' on startup?\", \
+    and answer with the cause the log lines point to and the steps to fix it. \
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+const LISTS_SYSTEM_PROMPT: &str = "You are a helpful assistant that generates questions and answers about the lists in technical documentation, \
+    such as feature lists and step-by-step guides. Format your response as JSON. Keep answers concise and factual.";
+
+const LISTS_USER_PROMPT: &str = "Generate exactly {{ target_count }} unique questions and answers from this documentation, which is mostly \
+    {% if ordered_list %}a list of {{ list_items }} steps. Ask one question about the whole procedure, such as \"What are the steps to ...?\", \
+    answered with every step in order, and ask about what individual steps do and why.\
+    {% else %}a list of {{ list_items }} items. Ask one question about the list as a whole, such as \"What ... does ... support?\", \
+    answered with all of its items, and ask about individual items.{% endif %} \
+    Use the text around the list for context. \
+    {% if breadcrumbs %}The page is under {{ breadcrumbs|join(' > ') }} in the docs' navigation; make every question specific \
+    to that area, naming it where the question would otherwise be vague. {% endif %}\
+    Format as JSON array with 'question' and 'answer' fields.\
+    {% if extra_fields %} Also include these fields for each item: {{ extra_fields }}.{% endif %}\
+    {% if keywords %} Make sure the questions cover: {{ keywords|join(', ') }}.{% endif %}\
+    {% if exclude_topics %} Do not ask about: {{ exclude_topics|join(', ') }}.{% endif %}\
+    {% if paraphrase %} Write every answer in your own words; don't copy sentences from the content.{% endif %}\
+    {% if answer_length %} {{ answer_length }}{% endif %}\
+    {% if reading_level %} {{ reading_level }}{% endif %}\
+    {% if style_examples %} Write answers in the voice and format of these replies from the team, without copying what they say:\n\
+    {% for example in style_examples %}---\n{{ example }}\n{% endfor %}---{% endif %}\
+    {% if deprecations %} The content marks these features as deprecated or removed:\n\
+    {% for notice in deprecations %}- {{ notice }}\n{% endfor %}\
+    Ask whether each of them is still supported, e.g. \"Is ... still supported?\", and answer that it is deprecated or removed, \
+    with the version and the replacement when the content names them. Never present them as the current way to do something.{% endif %}\
+    {% if existing_questions %} These questions were already asked; do not repeat them:\n\
+    {% for question in existing_questions %}- {{ question }}\n{% endfor %}{% endif %}\n\
+    Content: {{ section }}";
+
+/// Prompt templates used for a kind of content, rendered with minijinja. Available
+/// variables are `target_count`, `section`, `doc_title`, `breadcrumbs`, `file_name`, `product_name`,
+/// `extra_fields`, `keywords`, `exclude_topics`, `paraphrase`, `answer_length`,
+/// `reading_level`, `existing_questions`, `deprecations`, and for release notes `version`, `release_date` and `change_type`,
+/// and for lists `ordered_list` and `list_items`.
+/// `system_file`/`user_file` load a template from disk (relative to the config file)
+/// and take precedence over the inline templates; anything left empty falls back to
+/// the built-in prompt.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    pub system: String,
+    pub user: String,
+    pub system_file: Option<PathBuf>,
+    pub user_file: Option<PathBuf>,
+}
+
+impl PromptConfig {
+    fn new(system: &str, user: &str) -> Self {
+        Self {
+            system: system.to_string(),
+            user: user.to_string(),
+            system_file: None,
+            user_file: None,
+        }
+    }
+
+    fn resolve(&mut self, base_dir: &Path, default_system: &str, default_user: &str) -> Result<()> {
+        if let Some(file) = &self.system_file {
+            self.system = read_template(&base_dir.join(file))?;
+        }
+        if let Some(file) = &self.user_file {
+            self.user = read_template(&base_dir.join(file))?;
+        }
+        if self.system.is_empty() {
+            self.system = default_system.to_string();
+        }
+        if self.user.is_empty() {
+            self.user = default_user.to_string();
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn validate_templates<'a>(templates: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let env = minijinja::Environment::new();
+    for template in templates {
+        env.template_from_str(template)
+            .map_err(|e| anyhow!("Invalid prompt template: {}", e))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_template(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| anyhow!("Failed to read prompt template {:?}: {}", path, e))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptsConfig {
+    /// Exposed to templates as `product_name`.
+    pub product_name: Option<String>,
+    pub docs: PromptConfig,
+    pub release_notes: PromptConfig,
+    /// For configuration references: JSON schemas and YAML config files.
+    pub config_reference: PromptConfig,
+    /// For the `--help` output and man pages of command-line tools.
+    pub cli: PromptConfig,
+    /// For Terraform configurations and Kubernetes manifests.
+    pub infrastructure: PromptConfig,
+    /// For SQL schemas.
+    pub sql: PromptConfig,
+    /// For application logs, grouped by error.
+    pub logs: PromptConfig,
+    /// Used instead of the profile's prompt for sections that are mostly a list.
+    pub lists: PromptConfig,
+}
+
+impl PromptsConfig {
+    fn resolve(&mut self, base_dir: &Path) -> Result<()> {
+        self.docs.resolve(base_dir, DOCS_SYSTEM_PROMPT, DOCS_USER_PROMPT)?;
+        self.release_notes.resolve(base_dir, RELEASE_NOTES_SYSTEM_PROMPT, RELEASE_NOTES_USER_PROMPT)?;
+        self.config_reference.resolve(base_dir, CONFIG_REFERENCE_SYSTEM_PROMPT, CONFIG_REFERENCE_USER_PROMPT)?;
+        self.cli.resolve(base_dir, CLI_SYSTEM_PROMPT, CLI_USER_PROMPT)?;
+        self.infrastructure.resolve(base_dir, INFRASTRUCTURE_SYSTEM_PROMPT, INFRASTRUCTURE_USER_PROMPT)?;
+        self.sql.resolve(base_dir, SQL_SYSTEM_PROMPT, SQL_USER_PROMPT)?;
+        self.logs.resolve(base_dir, LOGS_SYSTEM_PROMPT, LOGS_USER_PROMPT)?;
+        self.lists.resolve(base_dir, LISTS_SYSTEM_PROMPT, LISTS_USER_PROMPT)
+    }
+
+    fn templates(&self) -> [&str; 16] {
+        [
+            &self.docs.system,
+            &self.docs.user,
+            &self.release_notes.system,
+            &self.release_notes.user,
+            &self.config_reference.system,
+            &self.config_reference.user,
+            &self.cli.system,
+            &self.cli.user,
+            &self.infrastructure.system,
+            &self.infrastructure.user,
+            &self.sql.system,
+            &self.sql.user,
+            &self.logs.system,
+            &self.logs.user,
+            &self.lists.system,
+            &self.lists.user,
+        ]
+    }
+}
+
+impl Default for PromptsConfig {
+    fn default() -> Self {
+        Self {
+            product_name: None,
+            docs: PromptConfig::new(DOCS_SYSTEM_PROMPT, DOCS_USER_PROMPT),
+            release_notes: PromptConfig::new(RELEASE_NOTES_SYSTEM_PROMPT, RELEASE_NOTES_USER_PROMPT),
+            config_reference: PromptConfig::new(CONFIG_REFERENCE_SYSTEM_PROMPT, CONFIG_REFERENCE_USER_PROMPT),
+            cli: PromptConfig::new(CLI_SYSTEM_PROMPT, CLI_USER_PROMPT),
+            infrastructure: PromptConfig::new(INFRASTRUCTURE_SYSTEM_PROMPT, INFRASTRUCTURE_USER_PROMPT),
+            sql: PromptConfig::new(SQL_SYSTEM_PROMPT, SQL_USER_PROMPT),
+            logs: PromptConfig::new(LOGS_SYSTEM_PROMPT, LOGS_USER_PROMPT),
+            lists: PromptConfig::new(LISTS_SYSTEM_PROMPT, LISTS_USER_PROMPT),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkingConfig {
+    /// Deepest heading level (`#` = 1) that starts a new top-level section.
+    pub heading_level: usize,
+    /// Start each section with the headings of the sections it is nested in.
+    pub parent_headings: bool,
+    /// Number of consecutive blank lines that separate paragraphs in the fallback split.
+    pub paragraph_break_lines: usize,
+    /// Re-split sections by headings and then paragraphs when they yield too few questions.
+    pub recursive_fallback: bool,
+    /// Which items of the fallback passes are kept.
+    pub fallback_strategy: FallbackStrategy,
+    /// Sections (and fallback parts) with fewer words outside headings, links and
+    /// frontmatter are skipped.
+    pub min_words: usize,
+    /// Skip sections with no words outside headings, links and frontmatter, such as a
+    /// "See also" list of links.
+    pub skip_link_only: bool,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            heading_level: 2,
+            parent_headings: true,
+            paragraph_break_lines: 2,
+            recursive_fallback: true,
+            fallback_strategy: FallbackStrategy::default(),
+            min_words: 0,
+            skip_link_only: true,
+        }
+    }
+}
+
+/// How a section that yields too few questions is generated again from its parts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FallbackStrategy {
+    /// Keeps every unique question of the whole section and its parts: each heading
+    /// section short of its share is split into paragraphs, until the section's target
+    /// is met.
+    #[default]
+    Union,
+    /// Splits the whole section by headings, then by paragraphs, and keeps the single
+    /// pass (including the first, unsplit one) with the most unique questions.
+    Best,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub min_question_words: usize,
+    pub min_answer_words: usize,
+    /// Regexes; items whose question or answer matches any of them are dropped.
+    pub exclude_patterns: Vec<String>,
+    /// Asks for answers in the model's own words and drops those whose share of words
+    /// copied verbatim from the source section (0-1) is above this.
+    pub max_source_overlap: Option<f64>,
+}
+
+impl FilterConfig {
+    fn validate(&self) -> Result<()> {
+        for pattern in &self.exclude_patterns {
+            Regex::new(pattern).map_err(|e| anyhow!("Invalid exclude pattern {:?}: {}", pattern, e))?;
+        }
+        if self.max_source_overlap.is_some_and(|max| !(0.0..=1.0).contains(&max)) {
+            return Err(anyhow!("filters.max_source_overlap must be between 0 and 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Topics the dataset should emphasise or avoid. Both lists are passed to the prompt
+/// templates; items mentioning an excluded topic are dropped, and with
+/// `require_keyword` so are items that mention none of the keywords.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SteeringConfig {
+    pub keywords: Vec<String>,
+    pub exclude_topics: Vec<String>,
+    pub require_keyword: bool,
+}
+
+/// Canonical spellings of product names, acronyms and other terms, applied to every
+/// generated question and answer. Any capitalization of a `canonical` term, with spaces
+/// or hyphens between its words, becomes that term; `aliases` map other spellings
+/// (`"k8s" = "Kubernetes"`) to theirs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TermsConfig {
+    pub canonical: Vec<String>,
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// Boilerplate removed from every file before it is chunked: sections under Markdown
+/// headings with these titles (any case), matches of the regexes, and in HTML pages the
+/// elements matching the selectors (`tag`, `.class`, `#id`, `tag.class`, `tag#id`).
+/// HTML pages are read as Markdown, of only their main content with `main_content`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StripConfig {
+    pub headings: Vec<String>,
+    pub patterns: Vec<String>,
+    pub selectors: Vec<String>,
+    pub main_content: bool,
+}
+
+impl Default for StripConfig {
+    fn default() -> Self {
+        Self {
+            headings: Vec::new(),
+            patterns: Vec::new(),
+            selectors: Vec::new(),
+            main_content: true,
+        }
+    }
+}
+
+/// Passages about deprecated or removed features, found by `markers` (case-insensitive
+/// regexes). With `mode = "frame"`, the lines mentioning them are passed to the prompt,
+/// which asks for items saying they are no longer supported; with `"skip"`, they are
+/// left out of the file: the blocks with a marker, and whole sections under a heading
+/// with one. `"ignore"` treats them like any other text. Release notes are left alone.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeprecationsConfig {
+    pub mode: DeprecationMode,
+    pub markers: Vec<String>,
+}
+
+impl Default for DeprecationsConfig {
+    fn default() -> Self {
+        Self {
+            mode: DeprecationMode::Frame,
+            markers: [r"\bdeprecated\b", r"\bremoved in\b", r"\bno longer (?:supported|available|maintained)\b", r"\bobsolete\b", r"\bend[- ]of[- ]life\b", r"\bwill be removed\b"]
+                .map(str::to_string)
+                .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeprecationMode {
+    #[default]
+    Frame,
+    Skip,
+    Ignore,
+}
+
+/// Sections that are mostly a bullet or numbered list (a feature list, a step-by-step
+/// guide) of at least `min_items` items are sent with `[prompts.lists]`, which asks
+/// about the list as a whole ("What are the steps to ...?") and about its items.
+/// Release notes keep their own prompt.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListsConfig {
+    pub enabled: bool,
+    pub min_items: usize,
+}
+
+impl Default for ListsConfig {
+    fn default() -> Self {
+        Self { enabled: true, min_items: 3 }
+    }
+}
+
+/// Markdown image references (`![alt](path)`) in sections. Prompts get their alt text
+/// instead unless `strip` is off; with a vision-capable `model`, each referenced image
+/// is also sent to it for `questions_per_image` items of its own, and with `describe`
+/// for a description that becomes an item too and grounds the questions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    pub strip: bool,
+    pub model: Option<String>,
+    pub questions_per_image: usize,
+    pub describe: bool,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            strip: true,
+            model: None,
+            questions_per_image: 2,
+            describe: false,
+        }
+    }
+}
+
+/// Skipping sections that duplicate ones already processed in the run, such as
+/// boilerplate repeated across docs. Sections match when their text is the same
+/// (ignoring case and whitespace) or, with an embedding model set under `[embeddings]`,
+/// when their embeddings are at least `max_similarity` (cosine) similar.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SectionDedupeConfig {
+    pub enabled: bool,
+    pub max_similarity: f32,
+}
+
+impl Default for SectionDedupeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_similarity: 0.95,
+        }
+    }
+}
+
+/// Answering every generated question again from the `top_k` sections of the whole
+/// corpus most similar to it, instead of only the section it was generated from. Needs
+/// an embedding model under `[embeddings]`; sections less than `min_similarity`
+/// (cosine) similar to the question are left out.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SynthesisConfig {
+    pub enabled: bool,
+    pub top_k: usize,
+    pub min_similarity: f32,
+}
+
+impl Default for SynthesisConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top_k: 3,
+            min_similarity: 0.0,
+        }
+    }
+}
+
+/// Refusal items, `fraction` of the items generated, grounded in the `policy` document
+/// the fine-tuned assistant should follow. Off at 0.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RefusalsConfig {
+    pub fraction: f64,
+    pub policy: Option<String>,
+}
+
+/// Natural-language-to-SQL training pairs for SQL schema files, `text_to_sql` per table:
+/// questions about the data answered with a query on the schema. Off at 0.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqlConfig {
+    pub text_to_sql: usize,
+}
+
+/// Reading CSV exports of support tickets: the columns holding each ticket's subject,
+/// body, resolution, status and id (matched ignoring case), and the statuses of resolved
+/// tickets. Tickets without a status column are resolved when they have a resolution.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TicketsConfig {
+    pub subject: String,
+    pub body: String,
+    pub resolution: String,
+    pub status: String,
+    pub id: String,
+    pub resolved: Vec<String>,
+    /// Replace email addresses, phone and card numbers with placeholders.
+    pub redact: bool,
+}
+
+impl Default for TicketsConfig {
+    fn default() -> Self {
+        Self {
+            subject: "subject".to_string(),
+            body: "description".to_string(),
+            resolution: "resolution".to_string(),
+            status: "status".to_string(),
+            id: "id".to_string(),
+            resolved: vec!["solved".to_string(), "resolved".to_string(), "closed".to_string()],
+            redact: true,
+        }
+    }
+}
+
+/// Taking the question and answer pairs of FAQ-style files as they are written instead
+/// of generating questions, for files with at least `min_pairs` of them. With `cleanup`,
+/// links and bold markers are removed and paragraphs joined on one line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaqConfig {
+    pub extract: bool,
+    pub min_pairs: usize,
+    pub cleanup: bool,
+}
+
+impl Default for FaqConfig {
+    fn default() -> Self {
+        Self {
+            extract: false,
+            min_pairs: 3,
+            cleanup: false,
+        }
+    }
+}
+
+/// Reading Stack Exchange posts, from data dumps and API pulls: questions tagged `tag`
+/// (any when unset) paired with their accepted answers, both scoring at least `min_score`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StackExchangeConfig {
+    pub tag: Option<String>,
+    pub min_score: i64,
+}
+
+impl Default for StackExchangeConfig {
+    fn default() -> Self {
+        Self {
+            tag: None,
+            min_score: 1,
+        }
+    }
+}
+
+/// Reading solved topics pulled from Discourse forums: those in `categories` (names or
+/// slugs, any case; all when empty) whose solution has at least `min_likes` likes.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscourseConfig {
+    pub categories: Vec<String>,
+    pub min_likes: u64,
+}
+
+/// Fetching help-center articles from Zendesk and Intercom: the API `token` (with the
+/// agent's `email` for Zendesk, where public articles need neither), and whether to also
+/// import Zendesk macros as answer style references. Credentials are only sent over
+/// https to `*.zendesk.com`, `api*.intercom.io` or the help center at `url`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HelpCenterConfig {
+    pub email: Option<String>,
+    pub token: Option<String>,
+    pub macros: bool,
+    /// A help center on a custom domain the credentials may be sent to.
+    pub url: Option<String>,
+}
+
+/// Replies written the way answers should read, such as a support team's canned
+/// responses. The `examples` closest to a section are shown to the model with it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    pub references: Vec<String>,
+    pub examples: usize,
+}
+
+impl Default for StyleConfig {
+    fn default() -> Self {
+        Self {
+            references: Vec::new(),
+            examples: 3,
+        }
+    }
+}
+
+/// How `crawl:` sources crawl a site: the `user_agent` they identify as, whether they
+/// follow robots.txt, the least `delay_ms` between requests (a longer `Crawl-delay` in
+/// robots.txt wins), the requests in flight at once, and the most pages and megabytes
+/// downloaded per crawl. With `sitemap`, the pages of the site's sitemap are crawled too;
+/// with `incremental`, pages saved by an earlier crawl are only fetched again when they
+/// changed, by their sitemap date or the site's answer to a conditional request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlConfig {
+    pub user_agent: String,
+    pub robots: bool,
+    pub delay_ms: u64,
+    pub concurrency: usize,
+    pub max_pages: usize,
+    pub max_mb: u64,
+    pub sitemap: bool,
+    pub incremental: bool,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: "llm-dataset-builder".to_string(),
+            robots: true,
+            delay_ms: 1000,
+            concurrency: 2,
+            max_pages: 500,
+            max_mb: 100,
+            sitemap: true,
+            incremental: true,
+        }
+    }
+}
+
+/// Credentials URL and `crawl:` sources send, for sites behind basic auth or an SSO
+/// proxy: those of `hosts`, by host name (`*.example.com` for its subdomains), and the
+/// `machine` entries of a netrc-style file for hosts without any. Only sent over https.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub netrc: Option<PathBuf>,
+    pub hosts: BTreeMap<String, HostCredentials>,
+}
+
+/// What is sent to a host: a bearer token or a basic-auth `username` and `password`, and
+/// cookies, such as the session cookie of an SSO proxy.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostCredentials {
+    pub bearer: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub cookies: BTreeMap<String, String>,
+}
+
+/// Rewriting the questions of `fraction` of the items as imperative instructions, so
+/// the dataset covers both phrasings. Off at 0.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstructionsConfig {
+    pub fraction: f64,
+}
+
+/// Follow-up questions and answers for `fraction` of the items, up to `max` each, which
+/// turn them into multi-turn conversations in the chat formats. Off at 0.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FollowUpsConfig {
+    pub fraction: f64,
+    pub max: usize,
+}
+
+impl Default for FollowUpsConfig {
+    fn default() -> Self {
+        Self { fraction: 0.0, max: 2 }
+    }
+}
+
+/// Noisy copies of `fraction` of the items, `variants` each, with the `kinds` of
+/// mistakes users make in their questions. Off at 0.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NoiseConfig {
+    pub fraction: f64,
+    pub variants: usize,
+    pub kinds: Vec<NoiseKind>,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            fraction: 0.0,
+            variants: 1,
+            kinds: vec![NoiseKind::Typos, NoiseKind::Casing, NoiseKind::Punctuation],
+        }
+    }
+}
+
+/// Restricts a run to a slice of the corpus, for trying out prompt or config changes.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingConfig {
+    /// Maximum number of files processed in the whole run.
+    pub limit_files: Option<usize>,
+    /// Maximum number of sections processed per file.
+    pub limit_sections: Option<usize>,
+    /// Fraction of files to process, chosen at random (repeatable with a seed).
+    pub sample: Option<f64>,
+    /// Maximum number of items kept in the whole run; files' question targets are
+    /// scaled down to fit, and the run stops generating once it is reached.
+    pub max_total_items: Option<usize>,
+    /// Files that last changed before this date (from git or their modification time)
+    /// are skipped, so stale documentation stays out of the dataset.
+    pub since: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Jsonl,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    /// Name of the combined dataset file written to the output directory; defaults to
+    /// `all_qa.jsonl` or `all_qa.json` by `format`.
+    pub combined_file: Option<String>,
+    pub schema: SchemaConfig,
+    /// Ask the model for a short verbatim quote supporting each answer, stored as
+    /// `citation`. Items whose quote isn't found in their section are rejected.
+    pub citations: bool,
+    /// Write a `DATASET_CARD.md` describing the run next to the combined file.
+    pub dataset_card: bool,
+    /// Extra destinations the combined dataset is written to, besides `combined_file`.
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Jsonl,
+            combined_file: None,
+            schema: SchemaConfig::default(),
+            citations: false,
+            dataset_card: false,
+            sinks: Vec::new(),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Extension of the files written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+impl OutputConfig {
+    /// Name of the combined dataset file in the output directory.
+    pub fn combined_file(&self) -> String {
+        self.combined_file
+            .clone()
+            .unwrap_or_else(|| format!("all_qa.{}", self.format.extension()))
+    }
+}
+
+/// Answer length preset: the prompt asks for answers of its length, answers that are
+/// off are asked for again up to `reasks` times, and those still off are dropped. Items
+/// record the preset in an `answer_length` field.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnswerLengthConfig {
+    pub preset: Option<AnswerLength>,
+    pub reasks: usize,
+}
+
+impl Default for AnswerLengthConfig {
+    fn default() -> Self {
+        Self { preset: None, reasks: 2 }
+    }
+}
+
+/// Audience answers are written for. The prompt asks for answers at the level, and a
+/// readability check asks again, up to `regenerations` times, for answers whose
+/// Flesch-Kincaid grade is more than `tolerance` grades off the level's range.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadingLevelConfig {
+    pub level: Option<ReadingLevel>,
+    pub tolerance: f64,
+    pub regenerations: usize,
+}
+
+impl Default for ReadingLevelConfig {
+    fn default() -> Self {
+        Self {
+            level: None,
+            tolerance: 2.0,
+            regenerations: 2,
+        }
+    }
+}
+
+/// Order of the combined dataset, for curriculum fine-tuning: easy or short items first.
+/// Difficulty comes from the `field` label, ranked by `levels` (by default the field's
+/// `enum` in `[output.schema.fields]`). With `interleave`, the items of each value of
+/// that field, or of each source file for `source`, are spread evenly through the order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CurriculumConfig {
+    pub order: CurriculumOrder,
+    pub field: String,
+    /// Difficulty labels, easiest first.
+    pub levels: Vec<String>,
+    pub interleave: Option<String>,
+}
+
+impl Default for CurriculumConfig {
+    fn default() -> Self {
+        Self {
+            order: CurriculumOrder::None,
+            field: "difficulty".to_string(),
+            levels: Vec::new(),
+            interleave: None,
+        }
+    }
+}
+
+/// Embeddings of generated questions and, optionally, of the sections they came from.
+/// Disabled unless `model` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddingsConfig {
+    pub model: Option<String>,
+    pub api: EmbeddingApi,
+    /// Defaults to the generation endpoint.
+    pub endpoint: Option<String>,
+    /// Sent as a bearer token to OpenAI-compatible endpoints.
+    pub api_key: Option<String>,
+    pub questions: bool,
+    pub chunks: bool,
+    /// Texts sent per request.
+    pub batch_size: usize,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            model: None,
+            api: EmbeddingApi::Ollama,
+            endpoint: None,
+            api_key: None,
+            questions: true,
+            chunks: false,
+            batch_size: 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingApi {
+    /// `POST /api/embed`
+    Ollama,
+    /// `POST /v1/embeddings`
+    Openai,
+}
+
+/// Speech-to-text for audio sources, which are transcribed before they are chunked.
+/// Disabled unless `endpoint` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranscriptionConfig {
+    pub endpoint: Option<String>,
+    pub api: TranscriptionApi,
+    /// Only sent to OpenAI-compatible endpoints; whisper.cpp uses the model it was started with.
+    pub model: String,
+    /// Sent as a bearer token.
+    pub api_key: Option<String>,
+    /// Spoken language, e.g. `en`; detected when unset.
+    pub language: Option<String>,
+}
+
+impl Default for TranscriptionConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            api: TranscriptionApi::WhisperCpp,
+            model: "whisper-1".to_string(),
+            api_key: None,
+            language: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TranscriptionApi {
+    /// whisper.cpp's server, `POST /inference`
+    WhisperCpp,
+    /// `POST /v1/audio/transcriptions`
+    Openai,
+}
+
+/// Text recognition for scanned PDFs and images of documents, which are read before
+/// they are chunked. Disabled unless `engine` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OcrConfig {
+    pub engine: Option<OcrEngine>,
+    /// The tesseract executable.
+    pub command: String,
+    /// Tesseract's languages, e.g. `eng+deu`.
+    pub language: String,
+    /// Vision model asked for the text, on the endpoint, with the `vision` engine.
+    pub model: Option<String>,
+    /// Also read image files (`.png`, `.jpg`, `.tif` and so on), not just PDFs.
+    pub images: bool,
+    /// Resolution PDF pages are rendered at.
+    pub dpi: u32,
+    /// Files with fewer recognized words are skipped, e.g. diagrams.
+    pub min_words: usize,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self {
+            engine: None,
+            command: "tesseract".to_string(),
+            language: "eng".to_string(),
+            model: None,
+            images: false,
+            dpi: 300,
+            min_words: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrEngine {
+    /// The tesseract CLI
+    Tesseract,
+    /// A vision model on the endpoint
+    Vision,
+}
+
+/// A destination for the generated items. File paths are relative to the output
+/// directory.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    /// JSONL file, or a JSON array for a `.json` path.
+    Jsonl {
+        path: String,
+        #[serde(default)]
+        format: RecordFormat,
+        #[serde(default)]
+        system_prompt: SystemPrompts,
+        #[serde(flatten)]
+        mapping: FieldMapping,
+    },
+    Parquet {
+        path: String,
+    },
+    /// Arrow IPC file with the Parquet columns, or an IPC stream for a `.arrows` path.
+    Arrow {
+        path: String,
+    },
+    /// Table in a SQLite database, replaced on every run.
+    Sqlite {
+        path: String,
+        #[serde(default = "default_sink_table")]
+        table: String,
+    },
+    /// POSTs `{"items": [...]}` batches to a URL, e.g. a review system's import endpoint.
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default = "default_sink_batch_size")]
+        batch_size: usize,
+        #[serde(default)]
+        format: RecordFormat,
+        #[serde(default)]
+        system_prompt: SystemPrompts,
+        #[serde(flatten)]
+        mapping: FieldMapping,
+    },
+    /// JSONL records on standard output.
+    Stdout {
+        #[serde(default)]
+        format: RecordFormat,
+        #[serde(default)]
+        system_prompt: SystemPrompts,
+        #[serde(flatten)]
+        mapping: FieldMapping,
+    },
+}
+
+fn default_sink_table() -> String {
+    "items".to_string()
+}
+
+fn default_sink_batch_size() -> usize {
+    100
+}
+
+impl FromStr for SinkConfig {
+    type Err = String;
+
+    /// Parses the `--sink` shorthand: `jsonl:PATH`, `parquet:PATH`, `arrow:PATH`,
+    /// `sqlite:PATH`, `http:URL` or `stdout`.
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, target) = value.split_once(':').unwrap_or((value, ""));
+        let path = || {
+            if target.is_empty() {
+                Err(format!("{} sink needs a path, e.g. {}:items.{}", kind, kind, kind))
+            } else {
+                Ok(target.to_string())
+            }
+        };
+        match kind {
+            "jsonl" => Ok(SinkConfig::Jsonl {
+                path: path()?,
+                format: RecordFormat::Qa,
+                system_prompt: SystemPrompts::default(),
+                mapping: FieldMapping::default(),
+            }),
+            "parquet" => Ok(SinkConfig::Parquet { path: path()? }),
+            "arrow" => Ok(SinkConfig::Arrow { path: path()? }),
+            "sqlite" => Ok(SinkConfig::Sqlite { path: path()?, table: default_sink_table() }),
+            "http" if target.starts_with("http://") || target.starts_with("https://") => Ok(SinkConfig::Http {
+                url: target.to_string(),
+                headers: BTreeMap::new(),
+                batch_size: default_sink_batch_size(),
+                format: RecordFormat::Qa,
+                system_prompt: SystemPrompts::default(),
+                mapping: FieldMapping::default(),
+            }),
+            "http" => Err("http sink needs a URL, e.g. http:https://example.com/import".to_string()),
+            "stdout" => Ok(SinkConfig::Stdout {
+                format: RecordFormat::Qa,
+                system_prompt: SystemPrompts::default(),
+                mapping: FieldMapping::default(),
+            }),
+            _ => Err(format!("Unknown sink {:?}; expected jsonl, parquet, arrow, sqlite, http or stdout", kind)),
+        }
+    }
+}
+
+/// Extra per-item fields, each given as a JSON schema (e.g. `{ type = "string",
+/// enum = ["basic", "advanced"], description = "..." }`). They are added to the
+/// structured-output schema sent to the model and written to the dataset.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemaConfig {
+    pub fields: BTreeMap<String, serde_json::Value>,
+    /// Extra fields the model must always fill in.
+    pub required: Vec<String>,
+}
+
+impl SchemaConfig {
+    /// Human-readable list of the extra fields for prompts, e.g.
+    /// `category (one of: api, cli), difficulty`; empty when there are none.
+    pub fn describe(&self) -> String {
+        self.fields
+            .iter()
+            .map(|(name, field)| {
+                let mut text = name.clone();
+                if let Some(description) = field.get("description").and_then(|d| d.as_str()) {
+                    text.push_str(&format!(" ({})", description));
+                } else if let Some(values) = field.get("enum").and_then(|e| e.as_array()) {
+                    let values: Vec<String> = values.iter().map(|v| v.as_str().map(str::to_string).unwrap_or_else(|| v.to_string())).collect();
+                    text.push_str(&format!(" (one of: {})", values.join(", ")));
+                }
+                text
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DensityConfig {
+    /// Base number of words of content per generated question.
+    pub words_per_question: f64,
+    /// Extra questions requested on top of the base goal, as a fraction of it.
+    pub buffer_ratio: f64,
+    /// Lower bound on the number of extra questions requested.
+    pub min_buffer: usize,
+    /// Fraction of the base goal an existing file needs to be kept instead of regenerated.
+    pub min_ratio: f64,
+    /// Fixed number of questions per file, replacing the word-count heuristic.
+    pub questions_per_file: Option<usize>,
+}
+
+impl Default for DensityConfig {
+    fn default() -> Self {
+        Self {
+            words_per_question: 10.0,
+            buffer_ratio: 0.25,
+            min_buffer: 2,
+            min_ratio: 0.8,
+            questions_per_file: None,
+        }
+    }
+}
+
+impl DensityConfig {
+    /// Density for a file of `total_words` words. A fixed per-file count is turned into
+    /// the equivalent words-per-question so sections still get proportional targets.
+    pub(crate) fn for_file(mut self, total_words: usize) -> Self {
+        if let Some(count) = self.questions_per_file {
+            self.words_per_question = total_words.max(1) as f64 / count.max(1) as f64;
+        }
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.words_per_question <= 0.0 {
+            return Err(anyhow!("density.words_per_question must be positive"));
+        }
+        if self.buffer_ratio < 0.0 || self.min_ratio < 0.0 {
+            return Err(anyhow!("density.buffer_ratio and density.min_ratio must not be negative"));
+        }
+        if self.questions_per_file == Some(0) {
+            return Err(anyhow!("density.questions_per_file must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Profile names that always exist; their prompts come from `[prompts.docs]`,
+/// `[prompts.release_notes]`, `[prompts.config_reference]`, `[prompts.cli]`,
+/// `[prompts.infrastructure]`, `[prompts.sql]` and `[prompts.logs]` unless the profile
+/// sets its own.
+pub const DOCS_PROFILE: &str = "docs";
+pub const RELEASE_NOTES_PROFILE: &str = "release_notes";
+pub const CONFIG_REFERENCE_PROFILE: &str = "config_reference";
+pub const CLI_PROFILE: &str = "cli";
+pub const INFRASTRUCTURE_PROFILE: &str = "infrastructure";
+pub const SQL_PROFILE: &str = "sql";
+pub const LOGS_PROFILE: &str = "logs";
+
+/// How a class of content is processed. Unset fields fall back to the top-level settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub prompt: Option<PromptConfig>,
+    pub chunking: Option<ChunkingConfig>,
+    pub model: Option<String>,
+    pub density: Option<DensityConfig>,
+}
+
+/// Selects `profile` for files matching every condition that is set: a glob on the path
+/// relative to the output directory, frontmatter key/value pairs, and a regex that at
+/// least one heading line (including its `#` markers) must match. The first matching
+/// rule wins; release notes and changelogs fall back to the `release_notes` profile,
+/// Terraform and Kubernetes manifests to `infrastructure`, other JSON schemas and YAML
+/// files to `config_reference`, help text and man pages to `cli`, `.sql` files to `sql`,
+/// and log files to `logs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub path: Option<String>,
+    #[serde(default)]
+    pub frontmatter: BTreeMap<String, String>,
+    pub heading: Option<String>,
+    pub profile: String,
+}
+
+impl RuleConfig {
+    fn matches(&self, relative_path: &Path, frontmatter: Option<&serde_yaml::Mapping>, content: &str) -> bool {
+        if let Some(path) = &self.path {
+            match Glob::new(path) {
+                Ok(glob) if glob.compile_matcher().is_match(relative_path) => {}
+                _ => return false,
+            }
+        }
+
+        for (key, expected) in &self.frontmatter {
+            let value = frontmatter.and_then(|fm| fm.get(key.as_str())).map(yaml_to_string);
+            if value.as_deref() != Some(expected.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(heading) = &self.heading {
+            let Ok(re) = Regex::new(heading) else {
+                return false;
+            };
+            if !markdown::headings(content).filter_map(|found| found.heading_line(content)).any(|line| re.is_match(&line)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn yaml_to_string(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::String(s) => s.clone(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Number(n) => n.to_string(),
+        other => serde_yaml::to_string(other).unwrap_or_default().trim().to_string(),
+    }
+}
+
+/// Settings for files whose path (relative to the output directory) matches `path`.
+/// Each table that is present replaces the corresponding top-level table.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirectoryOverride {
+    pub path: String,
+    pub prompts: Option<PromptsConfig>,
+    pub chunking: Option<ChunkingConfig>,
+    pub filters: Option<FilterConfig>,
+    pub density: Option<DensityConfig>,
+}
+
+/// Weight of the files whose path (relative to the output directory) matches `path`,
+/// for sources that matter more (or less) to the dataset. A file gets `weight` times
+/// its questions or, with `oversample`, its usual questions with each item written
+/// `weight` times to the combined dataset and sinks. The last matching entry applies.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeightConfig {
+    pub path: String,
+    pub weight: f64,
+    #[serde(default)]
+    pub oversample: bool,
+}
+
+impl WeightConfig {
+    /// The entry of `weights` for the file at `relative_path`: the last one matching it.
+    pub(crate) fn find<'a>(weights: &'a [WeightConfig], relative_path: &Path) -> Option<&'a WeightConfig> {
+        weights
+            .iter()
+            .rev()
+            .find(|weight| Glob::new(&weight.path).is_ok_and(|glob| glob.compile_matcher().is_match(relative_path)))
+    }
+}
+
+/// Settings from a `.llmds.toml` file, for the files in its directory and below. Each
+/// table that is present replaces the one from `[[overrides]]` and from `.llmds.toml`
+/// files further up the tree.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalConfig {
+    pub prompts: Option<PromptsConfig>,
+    pub chunking: Option<ChunkingConfig>,
+    pub filters: Option<FilterConfig>,
+    pub density: Option<DensityConfig>,
+    pub style: Option<StyleConfig>,
+}
+
+impl LocalConfig {
+    /// Loads and validates `path`; prompt files are relative to its directory.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        let mut local: LocalConfig = toml::from_str(&content).map_err(|e| anyhow!("Invalid {:?}: {}", path, e))?;
+        if let Some(prompts) = &mut local.prompts {
+            prompts.resolve(path.parent().unwrap_or(Path::new(".")))?;
+        }
+        local
+            .validate()
+            .map_err(|e| anyhow!("Invalid {:?}: {}", path, e))?;
+        Ok(local)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(prompts) = &self.prompts {
+            validate_templates(prompts.templates())?;
+        }
+        if let Some(filters) = &self.filters {
+            filters.validate()?;
+        }
+        if let Some(density) = &self.density {
+            density.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// The top-level tables as replaced by the overrides that apply to a file.
+struct Overridden {
+    prompts: PromptsConfig,
+    chunking: ChunkingConfig,
+    filters: FilterConfig,
+    density: DensityConfig,
+}
+
+impl Overridden {
+    fn apply(
+        &mut self,
+        prompts: Option<&PromptsConfig>,
+        chunking: Option<&ChunkingConfig>,
+        filters: Option<&FilterConfig>,
+        density: Option<&DensityConfig>,
+    ) {
+        if let Some(prompts) = prompts {
+            self.prompts = prompts.clone();
+        }
+        if let Some(chunking) = chunking {
+            self.chunking = chunking.clone();
+        }
+        if let Some(filters) = filters {
+            self.filters = filters.clone();
+        }
+        if let Some(density) = density {
+            self.density = density.clone();
+        }
+    }
+}
+
+/// Settings resolved for a single file after applying overrides and profile rules.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileSettings {
+    pub profile: String,
+    pub prompt: PromptConfig,
+    /// Prompt for sections that are mostly a list, from `[prompts.lists]`.
+    pub list_prompt: PromptConfig,
+    pub product_name: Option<String>,
+    pub chunking: ChunkingConfig,
+    pub filters: FilterConfig,
+    pub model: String,
+    pub density: DensityConfig,
+    /// Times each item is written to the combined dataset, from an oversampling weight;
+    /// fractions repeat that share of the items once more.
+    pub repeat: f64,
+    pub style: StyleConfig,
+}
+
+impl FileSettings {
+    pub fn is_release_notes(&self) -> bool {
+        self.profile == RELEASE_NOTES_PROFILE
+    }
+
+    pub fn is_config_reference(&self) -> bool {
+        self.profile == CONFIG_REFERENCE_PROFILE
+    }
+
+    pub fn is_cli(&self) -> bool {
+        self.profile == CLI_PROFILE
+    }
+
+    pub fn is_infrastructure(&self) -> bool {
+        self.profile == INFRASTRUCTURE_PROFILE
+    }
+
+    pub fn is_sql(&self) -> bool {
+        self.profile == SQL_PROFILE
+    }
+
+    pub fn is_logs(&self) -> bool {
+        self.profile == LOGS_PROFILE
+    }
+
+    /// Tracks the release of each section, for files processed as release notes.
+    pub fn release_tracker(&self, path: &Path) -> Option<ReleaseTracker> {
+        self.is_release_notes().then(|| ReleaseTracker::new(path))
+    }
+}
+
+impl Config {
+    /// Loads `path`, or the first default config file found in the working directory.
+    /// Without either, the built-in defaults are used.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match DEFAULT_CONFIG_FILES.iter().map(PathBuf::from).find(|p| p.exists()) {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read config file {:?}: {}", path, e))?;
+        let mut config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)?,
+            _ => toml::from_str(&content)?,
+        };
+
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        config.prompts.resolve(base_dir)?;
+        // `~/.netrc` is in the home directory, other relative paths next to the config file
+        if let Some(netrc) = &mut config.auth.netrc {
+            *netrc = match (netrc.strip_prefix("~"), std::env::var_os("HOME")) {
+                (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+                _ => base_dir.join(&*netrc),
+            };
+        }
+        for dir in &mut config.overrides {
+            if let Some(prompts) = &mut dir.prompts {
+                prompts.resolve(base_dir)?;
+            }
+        }
+        for (name, profile) in &mut config.profiles {
+            if let Some(prompt) = &mut profile.prompt {
+                let defaults = PromptsConfig::default();
+                let defaults = match name.as_str() {
+                    RELEASE_NOTES_PROFILE => &defaults.release_notes,
+                    CONFIG_REFERENCE_PROFILE => &defaults.config_reference,
+                    CLI_PROFILE => &defaults.cli,
+                    INFRASTRUCTURE_PROFILE => &defaults.infrastructure,
+                    SQL_PROFILE => &defaults.sql,
+                    LOGS_PROFILE => &defaults.logs,
+                    _ => &defaults.docs,
+                };
+                prompt.resolve(base_dir, &defaults.system, &defaults.user)?;
+            }
+        }
+
+        for plugin in &mut config.plugins {
+            plugin.path = base_dir.join(&plugin.path);
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks values that deserialization alone can't, such as regexes, globs and ranges.
+    pub fn validate(&self) -> Result<()> {
+        if self.request_timeout_secs == 0 {
+            return Err(anyhow!("request_timeout_secs must be at least 1"));
+        }
+        let mut filters = vec![&self.filters];
+        let mut prompts = vec![&self.prompts];
+        let mut densities = vec![&self.density];
+        for dir in &self.overrides {
+            Glob::new(&dir.path).map_err(|e| anyhow!("Invalid override path {:?}: {}", dir.path, e))?;
+            filters.extend(dir.filters.as_ref());
+            prompts.extend(dir.prompts.as_ref());
+            densities.extend(dir.density.as_ref());
+        }
+        for weight in &self.weights {
+            Glob::new(&weight.path).map_err(|e| anyhow!("Invalid weight path {:?}: {}", weight.path, e))?;
+            if weight.weight <= 0.0 || !weight.weight.is_finite() {
+                return Err(anyhow!("weights: weight of {:?} must be positive", weight.path));
+            }
+        }
+        let profile_prompts = self.profiles.values().filter_map(|p| p.prompt.as_ref());
+        validate_templates(
+            prompts
+                .iter()
+                .flat_map(|p| p.templates())
+                .chain(profile_prompts.flat_map(|p| [p.system.as_str(), p.user.as_str()])),
+        )?;
+        if let Some(file) = &self.output.combined_file {
+            let extension = self.output.format.extension();
+            if compression::inner_path(Path::new(file)).extension().and_then(|e| e.to_str()) != Some(extension) {
+                return Err(anyhow!("output.combined_file must end in .{} to match output.format", extension));
+            }
+        }
+        if self.hooks.timeout_secs == 0 {
+            return Err(anyhow!("hooks.timeout_secs must be at least 1"));
+        }
+        for name in &self.output.schema.required {
+            if !self.output.schema.fields.contains_key(name) {
+                return Err(anyhow!("Required output field {:?} is not defined in output.schema.fields", name));
+            }
+        }
+        for sink in &self.output.sinks {
+            match sink {
+                SinkConfig::Http { batch_size: 0, .. } => return Err(anyhow!("output.sinks: batch_size must be at least 1")),
+                SinkConfig::Jsonl { format, system_prompt, mapping, .. }
+                | SinkConfig::Http { format, system_prompt, mapping, .. }
+                | SinkConfig::Stdout { format, system_prompt, mapping, .. } => {
+                    if *format == RecordFormat::Qa && !system_prompt.0.is_empty() {
+                        return Err(anyhow!("output.sinks: system_prompt only applies to the sharegpt and openai formats"));
+                    }
+                    validate_templates(system_prompt.0.iter().map(String::as_str))?;
+                    mapping.check().map_err(|e| anyhow!("output.sinks: {}", e))?
+                }
+                _ => {}
+            }
+        }
+        if self.curriculum.order == CurriculumOrder::Difficulty {
+            curriculum::levels(&self.curriculum, &self.output.schema)?;
+        }
+        if self.terms.aliases.values().any(|term| term.trim().is_empty()) {
+            return Err(anyhow!("terms: aliases must map to a term"));
+        }
+        TermNormalizer::new(&self.terms, None)?;
+        Stripper::new(&self.strip)?;
+        if self.images.questions_per_image == 0 {
+            return Err(anyhow!("images.questions_per_image must be at least 1"));
+        }
+        Deprecations::new(&self.deprecations)?;
+        if self.lists.min_items < 2 {
+            return Err(anyhow!("lists.min_items must be at least 2"));
+        }
+        if !(0.0..=1.0).contains(&self.section_dedupe.max_similarity) {
+            return Err(anyhow!("section_dedupe.max_similarity must be between 0 and 1"));
+        }
+        if self.synthesis.enabled {
+            if self.embeddings.model.is_none() {
+                return Err(anyhow!("synthesis needs an embedding model under [embeddings]"));
+            }
+            if self.synthesis.top_k == 0 {
+                return Err(anyhow!("synthesis.top_k must be at least 1"));
+            }
+            if !(-1.0..=1.0).contains(&self.synthesis.min_similarity) {
+                return Err(anyhow!("synthesis.min_similarity must be between -1 and 1"));
+            }
+        }
+        if !(self.reading_level.tolerance >= 0.0 && self.reading_level.tolerance.is_finite()) {
+            return Err(anyhow!("reading_level.tolerance must be a positive number of grades"));
+        }
+        if !(0.0..=1.0).contains(&self.refusals.fraction) {
+            return Err(anyhow!("refusals.fraction must be between 0 and 1"));
+        }
+        if self.refusals.fraction > 0.0 && self.refusals.policy.is_none() {
+            return Err(anyhow!("refusals needs a policy document"));
+        }
+        if !(0.0..=1.0).contains(&self.instructions.fraction) {
+            return Err(anyhow!("instructions.fraction must be between 0 and 1"));
+        }
+        if !(0.0..=1.0).contains(&self.follow_ups.fraction) {
+            return Err(anyhow!("follow_ups.fraction must be between 0 and 1"));
+        }
+        if self.follow_ups.max == 0 {
+            return Err(anyhow!("follow_ups.max must be at least 1"));
+        }
+        if !(0.0..=1.0).contains(&self.noise.fraction) {
+            return Err(anyhow!("noise.fraction must be between 0 and 1"));
+        }
+        if self.noise.variants == 0 {
+            return Err(anyhow!("noise.variants must be at least 1"));
+        }
+        if self.noise.kinds.is_empty() {
+            return Err(anyhow!("noise.kinds must list at least one kind"));
+        }
+        if self.ocr.engine == Some(OcrEngine::Vision) && self.ocr.model.is_none() {
+            return Err(anyhow!("ocr.model must name a vision model for the vision engine"));
+        }
+        if self.ocr.dpi == 0 {
+            return Err(anyhow!("ocr.dpi must be at least 1"));
+        }
+        if self.crawl.concurrency == 0 {
+            return Err(anyhow!("crawl.concurrency must be at least 1"));
+        }
+        if self.crawl.user_agent.trim().is_empty() {
+            return Err(anyhow!("crawl.user_agent can't be empty"));
+        }
+        for (host, credentials) in &self.auth.hosts {
+            if credentials.bearer.is_some() && credentials.username.is_some() {
+                return Err(anyhow!("auth.hosts.{:?} can't have both a bearer token and a username", host));
+            }
+            if credentials.password.is_some() && credentials.username.is_none() {
+                return Err(anyhow!("auth.hosts.{:?} has a password but no username", host));
+            }
+        }
+        if self.embeddings.batch_size == 0 {
+            return Err(anyhow!("embeddings.batch_size must be at least 1"));
+        }
+        for plugin in &self.plugins {
+            match plugin.kind {
+                PluginKind::Parser if plugin.extensions.is_empty() => {
+                    return Err(anyhow!("Parser plugin {:?} needs at least one extension", plugin.path));
+                }
+                PluginKind::Filter if !plugin.extensions.is_empty() => {
+                    return Err(anyhow!("Filter plugin {:?} can't have extensions", plugin.path));
+                }
+                _ => {}
+            }
+        }
+        if self.sampling.sample.is_some_and(|s| !(s > 0.0 && s <= 1.0)) {
+            return Err(anyhow!("sampling.sample must be in (0, 1]"));
+        }
+        if self.sampling.max_total_items == Some(0) {
+            return Err(anyhow!("sampling.max_total_items must be at least 1"));
+        }
+        densities.extend(self.profiles.values().filter_map(|p| p.density.as_ref()));
+        for density in densities {
+            density.validate()?;
+        }
+        if let Some(profile) = &self.profile {
+            if !self.has_profile(profile) {
+                return Err(anyhow!("Unknown profile {:?}", profile));
+            }
+        }
+        for rule in &self.rules {
+            if !self.has_profile(&rule.profile) {
+                return Err(anyhow!("Rule refers to unknown profile {:?}", rule.profile));
+            }
+            if let Some(path) = &rule.path {
+                Glob::new(path).map_err(|e| anyhow!("Invalid rule path {:?}: {}", path, e))?;
+            }
+            if let Some(heading) = &rule.heading {
+                Regex::new(heading).map_err(|e| anyhow!("Invalid rule heading {:?}: {}", heading, e))?;
+            }
+        }
+        for filter in filters {
+            filter.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is a built-in or configured profile.
+    pub fn has_profile(&self, name: &str) -> bool {
+        [DOCS_PROFILE, RELEASE_NOTES_PROFILE, CONFIG_REFERENCE_PROFILE, CLI_PROFILE, INFRASTRUCTURE_PROFILE, SQL_PROFILE, LOGS_PROFILE]
+            .contains(&name)
+            || self.profiles.contains_key(name)
+    }
+
+    /// Resolves the settings for a file: directory overrides apply first (later ones win),
+    /// then the `.llmds.toml` files from the output directory down to the file's own
+    /// directory, then the forced profile, the file's `llmds.prompt` frontmatter or the
+    /// first matching rule selects the profile, and `llmds.questions` fixes the count.
+    /// Fails when one of those `.llmds.toml` files or the `llmds` frontmatter is invalid.
+    pub fn settings_for(&self, relative_path: &Path, content: &str) -> Result<FileSettings> {
+        let mut base = Overridden {
+            prompts: self.prompts.clone(),
+            chunking: self.chunking.clone(),
+            filters: self.filters.clone(),
+            density: self.density.clone(),
+        };
+
+        for dir in &self.overrides {
+            let matcher: GlobMatcher = match Glob::new(&dir.path) {
+                Ok(glob) => glob.compile_matcher(),
+                Err(_) => continue,
+            };
+            if matcher.is_match(relative_path) {
+                base.apply(dir.prompts.as_ref(), dir.chunking.as_ref(), dir.filters.as_ref(), dir.density.as_ref());
+            }
+        }
+        let mut style = &self.style;
+        let locals = self.local_configs(relative_path)?;
+        for local in &locals {
+            base.apply(local.prompts.as_ref(), local.chunking.as_ref(), local.filters.as_ref(), local.density.as_ref());
+            if let Some(local_style) = &local.style {
+                style = local_style;
+            }
+        }
+        let Overridden { prompts, chunking, filters, density: base_density } = base;
+
+        let frontmatter = frontmatter::parse(content).0;
+        let file = frontmatter::overrides(frontmatter.as_ref())?;
+        if let Some(name) = file.prompt.as_ref().filter(|name| !self.has_profile(name)) {
+            return Err(anyhow!("Unknown profile {:?} in llmds.prompt", name));
+        }
+        let profile_name = self.profile.clone().or(file.prompt).unwrap_or_else(|| {
+            match self.rules.iter().find(|rule| rule.matches(relative_path, frontmatter.as_ref(), content)) {
+                Some(rule) => rule.profile.clone(),
+                None if infrastructure::is_infrastructure(relative_path, content) => INFRASTRUCTURE_PROFILE.to_string(),
+                None if config_reference::is_config_reference(relative_path) => CONFIG_REFERENCE_PROFILE.to_string(),
+                None if cli_help::is_cli_reference(relative_path) => CLI_PROFILE.to_string(),
+                None if sql_schema::is_sql(relative_path) => SQL_PROFILE.to_string(),
+                None if logs::is_log(relative_path) => LOGS_PROFILE.to_string(),
+                None if changelog::is_changelog(relative_path, content) => RELEASE_NOTES_PROFILE.to_string(),
+                None => DOCS_PROFILE.to_string(),
+            }
+        });
+        let profile = self.profiles.get(&profile_name).cloned().unwrap_or_default();
+
+        // A run-wide fixed count (e.g. from --questions-per-file) applies to every profile
+        // that doesn't set its own.
+        let mut density = profile.density.unwrap_or_else(|| base_density.clone());
+        density.questions_per_file = density
+            .questions_per_file
+            .or(base_density.questions_per_file)
+            .or(self.density.questions_per_file);
+        if file.questions.is_some() {
+            density.questions_per_file = file.questions;
+        }
+        let mut density = density.for_file(content.split_whitespace().count());
+        let mut repeat = 1.0;
+        if let Some(weight) = WeightConfig::find(&self.weights, relative_path) {
+            match weight.oversample {
+                true => repeat = weight.weight,
+                false => density.words_per_question /= weight.weight,
+            }
+        }
+
+        let prompt = profile.prompt.unwrap_or_else(|| match profile_name.as_str() {
+            RELEASE_NOTES_PROFILE => prompts.release_notes.clone(),
+            CONFIG_REFERENCE_PROFILE => prompts.config_reference.clone(),
+            CLI_PROFILE => prompts.cli.clone(),
+            INFRASTRUCTURE_PROFILE => prompts.infrastructure.clone(),
+            SQL_PROFILE => prompts.sql.clone(),
+            LOGS_PROFILE => prompts.logs.clone(),
+            _ => prompts.docs.clone(),
+        });
+
+        Ok(FileSettings {
+            profile: profile_name,
+            prompt,
+            list_prompt: prompts.lists.clone(),
+            product_name: prompts.product_name.clone(),
+            chunking: profile.chunking.unwrap_or(chunking),
+            filters,
+            model: profile.model.unwrap_or_else(|| self.model.clone()),
+            density,
+            repeat,
+            style: style.clone(),
+        })
+    }
+
+    /// The prompt templates of a profile, as configured at the top level (directory
+    /// overrides can replace the built-in profiles' templates for their subtree).
+    pub fn profile_prompt(&self, name: &str) -> &PromptConfig {
+        match self.profiles.get(name).and_then(|profile| profile.prompt.as_ref()) {
+            Some(prompt) => prompt,
+            None if name == RELEASE_NOTES_PROFILE => &self.prompts.release_notes,
+            None if name == CONFIG_REFERENCE_PROFILE => &self.prompts.config_reference,
+            None if name == CLI_PROFILE => &self.prompts.cli,
+            None if name == INFRASTRUCTURE_PROFILE => &self.prompts.infrastructure,
+            None if name == SQL_PROFILE => &self.prompts.sql,
+            None if name == LOGS_PROFILE => &self.prompts.logs,
+            None => &self.prompts.docs,
+        }
+    }
+
+    /// The `.llmds.toml` files that apply to a file, outermost first.
+    fn local_configs(&self, relative_path: &Path) -> Result<Vec<LocalConfig>> {
+        // Files outside the output directory only get the config file's settings
+        if relative_path.is_absolute() {
+            return Ok(Vec::new());
+        }
+        let root = Path::new(&self.output_dir);
+        let mut dirs: Vec<&Path> = relative_path.parent().map(|dir| dir.ancestors().collect()).unwrap_or_default();
+        dirs.reverse();
+        dirs.into_iter()
+            .map(|dir| root.join(dir).join(LOCAL_CONFIG_FILE))
+            .filter(|path| path.is_file())
+            .map(|path| LocalConfig::load(&path))
+            .collect()
+    }
+}
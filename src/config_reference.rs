@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+use crate::compression;
+use crate::config::DEFAULT_CONFIG_FILES;
+
+/// Deepest `$ref` chain followed into a schema's definitions, so recursive schemas end.
+const MAX_REF_DEPTH: usize = 8;
+
+/// Whether `path` is a configuration reference: a JSON schema (`config.schema.json`) or a
+/// YAML file, such as an annotated example config. This tool's own config files aren't.
+pub fn is_config_reference(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_lowercase();
+    if DEFAULT_CONFIG_FILES.contains(&name.as_str()) {
+        return false;
+    }
+    name.ends_with(".schema.json") || name.ends_with(".yaml") || name.ends_with(".yml")
+}
+
+/// One setting of a configuration reference.
+#[derive(Debug, Default)]
+struct Setting {
+    /// Full dotted key, e.g. `server.tls.enabled`.
+    key: String,
+    kind: Option<String>,
+    default: Option<String>,
+    allowed: Vec<String>,
+    examples: Vec<String>,
+    required: bool,
+    description: Option<String>,
+}
+
+impl Setting {
+    fn to_markdown(&self) -> String {
+        let mut section = format!("## `{}`\n\n", self.key);
+        if let Some(description) = &self.description {
+            section.push_str(description);
+            section.push_str("\n\n");
+        }
+        if let Some(kind) = &self.kind {
+            section.push_str(&format!("- Type: {}\n", kind));
+        }
+        if self.required {
+            section.push_str("- Required: yes\n");
+        }
+        if let Some(default) = &self.default {
+            section.push_str(&format!("- Default: `{}`\n", default));
+        }
+        if !self.allowed.is_empty() {
+            let allowed: Vec<String> = self.allowed.iter().map(|value| format!("`{}`", value)).collect();
+            section.push_str(&format!("- Allowed values: {}\n", allowed.join(", ")));
+        }
+        if !self.examples.is_empty() {
+            let examples: Vec<String> = self.examples.iter().map(|value| format!("`{}`", value)).collect();
+            section.push_str(&format!("- Examples: {}\n", examples.join(", ")));
+        }
+        section
+    }
+}
+
+/// `text`, the content of the configuration reference at `path`, as Markdown with a
+/// `##` section per setting giving its full key, description, type, default and allowed
+/// values, so every setting is generated from on its own. The values in a YAML file are
+/// its settings' defaults when the file name says so (`config.defaults.yaml`), and
+/// examples otherwise.
+pub fn render(path: &Path, text: &str) -> Result<String> {
+    let inner = compression::inner_path(path);
+    let name = inner.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let stem = name.split('.').next().unwrap_or(name);
+    let (title, description, settings) = match name.to_lowercase().ends_with(".json") {
+        true => {
+            let schema: JsonValue = serde_json::from_str(text).map_err(|e| anyhow!("Invalid JSON schema: {}", e))?;
+            let mut settings = Vec::new();
+            schema_settings(&schema, &schema, "", &mut settings, 0);
+            let title = schema.get("title").and_then(JsonValue::as_str).map(str::to_string);
+            let description = schema.get("description").and_then(JsonValue::as_str).map(str::to_string);
+            (title, description, settings)
+        }
+        false => {
+            let value: YamlValue = serde_yaml::from_str(text).map_err(|e| anyhow!("Invalid YAML: {}", e))?;
+            let comments = yaml_comments(text);
+            let mut settings = Vec::new();
+            yaml_settings(&value, "", &comments, &mut settings);
+            // Values of an example config are examples, unless the file is named for its defaults
+            if !name.to_lowercase().contains("default") {
+                for setting in &mut settings {
+                    setting.examples.extend(setting.default.take());
+                }
+            }
+            (None, comments.get("").cloned(), settings)
+        }
+    };
+    if settings.is_empty() {
+        return Err(anyhow!("No settings found"));
+    }
+    let mut markdown = format!("# {}\n\n", title.unwrap_or_else(|| format!("{} configuration reference", stem)));
+    if let Some(description) = description {
+        markdown.push_str(&description);
+        markdown.push_str("\n\n");
+    }
+    for setting in &settings {
+        markdown.push_str(&setting.to_markdown());
+        markdown.push('\n');
+    }
+    Ok(markdown)
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    match prefix.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", prefix, key),
+    }
+}
+
+/// A JSON value as it would be written in the config: strings unquoted.
+fn json_literal(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `schema` with a local `$ref` (`#/definitions/x`, `#/$defs/x`) resolved against `root`.
+fn resolve<'a>(schema: &'a JsonValue, root: &'a JsonValue) -> &'a JsonValue {
+    match schema.get("$ref").and_then(JsonValue::as_str).and_then(|r| r.strip_prefix('#')) {
+        Some(pointer) => root.pointer(pointer).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+/// Adds a setting for each property of the object `schema`, and of the objects nested in it.
+fn schema_settings(schema: &JsonValue, root: &JsonValue, prefix: &str, settings: &mut Vec<Setting>, depth: usize) {
+    let schema = resolve(schema, root);
+    let Some(properties) = schema.get("properties").and_then(JsonValue::as_object) else {
+        return;
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(JsonValue::as_array)
+        .map(|required| required.iter().filter_map(JsonValue::as_str).collect())
+        .unwrap_or_default();
+    for (key, property) in properties {
+        let path = join_key(prefix, key);
+        let reference = property.get("$ref").is_some();
+        let resolved = resolve(property, root);
+        // Annotations next to a `$ref` describe this use of the definition
+        let field = |name: &str| property.get(name).or_else(|| resolved.get(name));
+        let kind = field("type").map(|kind| match kind {
+            JsonValue::Array(kinds) => kinds.iter().map(json_literal).collect::<Vec<_>>().join(" or "),
+            other => json_literal(other),
+        });
+        let description = field("description").or_else(|| field("title")).and_then(JsonValue::as_str);
+        settings.push(Setting {
+            key: path.clone(),
+            kind,
+            default: field("default").map(json_literal),
+            allowed: field("enum").and_then(JsonValue::as_array).map(|values| values.iter().map(json_literal).collect()).unwrap_or_default(),
+            examples: field("examples").and_then(JsonValue::as_array).map(|values| values.iter().map(json_literal).collect()).unwrap_or_default(),
+            required: required.contains(&key.as_str()),
+            description: description.map(str::to_string),
+        });
+        if !reference || depth < MAX_REF_DEPTH {
+            schema_settings(resolved, root, &path, settings, depth + usize::from(reference));
+        }
+    }
+}
+
+/// A YAML value as it would be written in the config, on one line.
+fn yaml_literal(value: &YamlValue) -> String {
+    match value {
+        YamlValue::String(s) => s.clone(),
+        YamlValue::Null => "null".to_string(),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    }
+}
+
+fn yaml_kind(value: &YamlValue) -> Option<&'static str> {
+    match value {
+        YamlValue::Bool(_) => Some("boolean"),
+        YamlValue::Number(n) if n.is_i64() || n.is_u64() => Some("integer"),
+        YamlValue::Number(_) => Some("number"),
+        YamlValue::String(_) => Some("string"),
+        YamlValue::Sequence(_) => Some("list"),
+        YamlValue::Mapping(_) => Some("object"),
+        _ => None,
+    }
+}
+
+/// Adds a setting for each key of the mapping `value`, with its value as the default.
+/// Mappings are settings themselves only when a comment describes them.
+fn yaml_settings(value: &YamlValue, prefix: &str, comments: &HashMap<String, String>, settings: &mut Vec<Setting>) {
+    let YamlValue::Mapping(mapping) = value else {
+        return;
+    };
+    for (key, value) in mapping {
+        let path = join_key(prefix, &yaml_literal(key));
+        let description = comments.get(&path).cloned();
+        let nested = matches!(value, YamlValue::Mapping(children) if !children.is_empty());
+        if !nested || description.is_some() {
+            settings.push(Setting {
+                key: path.clone(),
+                kind: yaml_kind(value).map(str::to_string),
+                default: (!nested && !value.is_null()).then(|| yaml_literal(value)),
+                description,
+                ..Setting::default()
+            });
+        }
+        yaml_settings(value, &path, comments, settings);
+    }
+}
+
+/// The comments of a YAML file by the dotted key they annotate: the comment lines right
+/// above a key and a comment after its value. Comments at the top of the file, before
+/// a blank line, describe the whole file and are keyed `""`.
+fn yaml_comments(text: &str) -> HashMap<String, String> {
+    let mut comments = HashMap::new();
+    // Keys of the mappings the current line is nested in, with their indentation
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut pending: Vec<String> = Vec::new();
+    let mut seen_key = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            if !seen_key && !pending.is_empty() {
+                comments.insert(String::new(), pending.join(" "));
+            }
+            pending.clear();
+            continue;
+        }
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            pending.push(comment.trim().to_string());
+            continue;
+        }
+        if trimmed == "---" || trimmed.starts_with("- ") || trimmed == "-" {
+            pending.clear();
+            continue;
+        }
+        let Some((key, rest)) = trimmed.split_once(':') else {
+            pending.clear();
+            continue;
+        };
+        if !(rest.is_empty() || rest.starts_with(' ')) {
+            pending.clear();
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        while parents.last().is_some_and(|(parent, _)| *parent >= indent) {
+            parents.pop();
+        }
+        let key = key.trim().trim_matches(['"', '\'']);
+        let prefix = parents.last().map(|(_, path)| path.as_str()).unwrap_or("");
+        let path = join_key(prefix, key);
+        let mut description: Vec<String> = pending.drain(..).filter(|line| !line.is_empty()).collect();
+        if let Some((_, trailing)) = rest.split_once(" #") {
+            description.push(trailing.trim().to_string());
+        }
+        if !description.is_empty() {
+            comments.insert(path.clone(), description.join(" "));
+        }
+        parents.push((indent, path));
+        seen_key = true;
+    }
+    comments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemas_are_rendered_a_section_per_setting() {
+        let schema = r##"{
+            "title": "Server configuration",
+            "type": "object",
+            "required": ["port"],
+            "properties": {
+                "port": {"type": "integer", "default": 8080, "description": "Port to listen on."},
+                "log": {"$ref": "#/$defs/log", "description": "Logging."}
+            },
+            "$defs": {
+                "log": {
+                    "type": "object",
+                    "properties": {"level": {"type": ["string", "null"], "enum": ["info", "debug"], "examples": ["debug"]}}
+                }
+            }
+        }"##;
+        let markdown = render(Path::new("server.schema.json"), schema).unwrap();
+        assert_eq!(
+            markdown,
+            "# Server configuration\n\n\
+             ## `log`\n\nLogging.\n\n- Type: object\n\n\
+             ## `log.level`\n\n- Type: string or null\n- Allowed values: `info`, `debug`\n- Examples: `debug`\n\n\
+             ## `port`\n\nPort to listen on.\n\n- Type: integer\n- Required: yes\n- Default: `8080`\n\n"
+        );
+    }
+
+    #[test]
+    fn yaml_values_are_examples_or_defaults() {
+        let yaml = "# Settings of the agent.\n\n# Where to send metrics.\nendpoint: https://metrics.example.com\nbuffer:\n  size: 512 # entries\n  flush: 1.5\n";
+        assert_eq!(
+            render(Path::new("agent.yaml"), yaml).unwrap(),
+            "# agent configuration reference\n\nSettings of the agent.\n\n\
+             ## `endpoint`\n\nWhere to send metrics.\n\n- Type: string\n- Examples: `https://metrics.example.com`\n\n\
+             ## `buffer.size`\n\nentries\n\n- Type: integer\n- Examples: `512`\n\n\
+             ## `buffer.flush`\n\n- Type: number\n- Examples: `1.5`\n\n"
+        );
+        let defaults = render(Path::new("agent.defaults.yaml.gz"), "retries: 3\n").unwrap();
+        assert!(defaults.contains("- Default: `3`\n"), "{defaults}");
+        assert!(render(Path::new("empty.yaml"), "{}").is_err());
+    }
+
+    #[test]
+    fn references_are_schemas_and_yaml_files() {
+        assert!(is_config_reference(Path::new("docs/config.schema.json")));
+        assert!(is_config_reference(Path::new("deploy/values.yml.gz")));
+        assert!(!is_config_reference(Path::new("package.json")));
+        assert!(!is_config_reference(Path::new(DEFAULT_CONFIG_FILES[0])));
+    }
+}
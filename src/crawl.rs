@@ -0,0 +1,837 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures::stream::{FuturesUnordered, StreamExt};
+use regex::Regex;
+use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+use url::Url;
+use crate::auth::Auth;
+use crate::config::{AuthConfig, CrawlConfig};
+use crate::dataset;
+use crate::datasource::{send_waiting, DataSource};
+use crate::html;
+use crate::pipeline::qa_path;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Redirects followed within the site before giving up on a page.
+const MAX_REDIRECTS: usize = 10;
+
+/// Sitemaps read per crawl, counting those listed in sitemap indexes.
+const MAX_SITEMAPS: usize = 20;
+
+/// Longest query kept in a page's file name; longer ones are hashed.
+const MAX_QUERY_LEN: usize = 64;
+
+/// Where a host's directory keeps what past crawls saved.
+const STATE_FILE: &str = "crawl_state.json";
+
+/// Links to files that aren't pages, which are never requested.
+const SKIPPED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "svg", "webp", "ico", "bmp", "css", "js", "mjs", "map", "json", "xml", "rss", "atom", "pdf",
+    "zip", "gz", "tgz", "tar", "bz2", "xz", "7z", "dmg", "exe", "msi", "deb", "rpm", "apk", "mp3", "mp4", "webm", "mov",
+    "avi", "wav", "ogg", "woff", "woff2", "ttf", "otf", "eot",
+];
+
+/// `<a ...>` tags and their `href`.
+static LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<a\s[^>]*?\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))[^>]*>"#).unwrap()
+});
+
+/// `<url>` and `<sitemap>` entries of a sitemap or sitemap index, and their `<loc>` and
+/// `<lastmod>`.
+static SITEMAP_ENTRY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<(url|sitemap)>(.*?)</(?:url|sitemap)>").unwrap());
+static SITEMAP_LOCATION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<loc>\s*(.*?)\s*</loc>").unwrap());
+static SITEMAP_LASTMOD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<lastmod>\s*(.*?)\s*</lastmod>").unwrap());
+
+/// `<meta name="robots" content="...">`, whose directives a page sets for crawlers.
+static META_ROBOTS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)<meta\s[^>]*\bname\s*=\s*["']?robots\b[^>]*>"#).unwrap());
+
+/// A group of a robots.txt: the agents it names and its `key: value` lines.
+type Group = (Vec<String>, Vec<(String, String)>);
+
+/// The `Allow` and `Disallow` rules of a robots.txt that apply to the crawler, its
+/// `Crawl-delay`, and the sitemaps it lists for every crawler.
+#[derive(Debug, Default)]
+struct Robots {
+    /// Whether each rule allows, the paths it matches, and the length of its pattern,
+    /// which decides between rules matching the same path.
+    rules: Vec<(bool, Regex, usize)>,
+    delay: Option<Duration>,
+    sitemaps: Vec<String>,
+}
+
+impl Robots {
+    /// The rules of `text` for `user_agent`: those of the groups naming its product
+    /// token (ignoring case, and any version after it), or of the `*` groups when none do.
+    fn parse(text: &str, user_agent: &str) -> Self {
+        let product = |agent: &str| agent.split(['/', ' ']).next().unwrap_or(agent).to_lowercase();
+        let token = product(user_agent);
+        let mut robots = Self::default();
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_lowercase(), value.trim().to_string());
+            if key == "sitemap" {
+                robots.sitemaps.push(value);
+            } else if key == "user-agent" {
+                if !in_agents {
+                    groups.push((Vec::new(), Vec::new()));
+                }
+                in_agents = true;
+                groups.last_mut().unwrap().0.push(value.to_lowercase());
+            } else if let Some(group) = groups.last_mut() {
+                in_agents = false;
+                group.1.push((key, value));
+            }
+        }
+        let named = |agent: &String| agent != "*" && product(agent) == token;
+        let specific = groups.iter().any(|(agents, _)| agents.iter().any(named));
+        let lines = groups
+            .iter()
+            .filter(|(agents, _)| agents.iter().any(|agent| if specific { named(agent) } else { agent == "*" }))
+            .flat_map(|(_, lines)| lines);
+        for (key, value) in lines {
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => {
+                    let pattern = regex::escape(value).replace(r"\*", ".*");
+                    let pattern = match pattern.strip_suffix(r"\$") {
+                        Some(anchored) => format!("^{}$", anchored),
+                        None => format!("^{}", pattern),
+                    };
+                    if let Ok(regex) = Regex::new(&pattern) {
+                        robots.rules.push((key == "allow", regex, value.len()));
+                    }
+                }
+                "crawl-delay" => robots.delay = value.parse::<f64>().ok().filter(|delay| *delay >= 0.0).map(Duration::from_secs_f64),
+                _ => {}
+            }
+        }
+        robots
+    }
+
+    /// Rules that disallow the whole site, for a robots.txt that can't be read.
+    fn disallow_all() -> Self {
+        Self {
+            rules: vec![(false, Regex::new("^/").unwrap(), 1)],
+            ..Self::default()
+        }
+    }
+
+    /// Whether `url` may be crawled: the longest matching rule decides, allowing on a
+    /// tie, and anything no rule matches is allowed.
+    fn allows(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        self.rules
+            .iter()
+            .filter(|(_, pattern, _)| pattern.is_match(&path))
+            .max_by_key(|(allow, _, len)| (*len, *allow))
+            .is_none_or(|(allow, _, _)| *allow)
+    }
+}
+
+/// A page as fetched.
+struct Page {
+    /// Where it was found, after redirects.
+    url: Url,
+    body: Vec<u8>,
+    html: bool,
+    modified: Option<SystemTime>,
+    /// From `noindex` and `nofollow` in a robots meta tag or `X-Robots-Tag` header.
+    index: bool,
+    follow: bool,
+    /// The `ETag` and `Last-Modified` headers, to ask whether it changed next time.
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// What asking for a page gave.
+enum Fetched {
+    Page(Page),
+    /// Unchanged since the last crawl saved it.
+    NotModified,
+    /// Not found, or gone for good.
+    Gone,
+    /// Not a text page.
+    Skipped,
+}
+
+/// What the last crawl saved of a page, to tell whether it changed since.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedPage {
+    /// Where it is saved, relative to the output directory.
+    path: PathBuf,
+    html: bool,
+    follow: bool,
+    /// The URL it redirected to, which its links are relative to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    redirect: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    /// Its `<lastmod>` in the site's sitemap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lastmod: Option<String>,
+}
+
+impl SavedPage {
+    /// The page as saved, read back to follow its links without asking for it again.
+    fn read(&self, output_dir: &Path, url: &Url) -> Option<Page> {
+        let body = std::fs::read(output_dir.join(&self.path)).ok()?;
+        Some(Page {
+            url: self.redirect.as_deref().and_then(|redirect| Url::parse(redirect).ok()).unwrap_or_else(|| url.clone()),
+            body,
+            html: self.html,
+            modified: None,
+            index: true,
+            follow: self.follow,
+            etag: self.etag.clone(),
+            last_modified: self.last_modified.clone(),
+        })
+    }
+}
+
+/// The pages past crawls of a host saved, by the URL they were asked for, kept in the
+/// host's directory as `crawl_state.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CrawlState {
+    pages: BTreeMap<String, SavedPage>,
+}
+
+impl CrawlState {
+    /// The state at `path`; empty when there is none or it can't be read.
+    fn load(path: &Path) -> Self {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!("Ignoring {:?}: {}; crawling every page again", path, e);
+            Self::default()
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// How the pages of a crawl compare to the last one.
+#[derive(Debug, Default)]
+struct Changes {
+    new: usize,
+    changed: usize,
+    unchanged: usize,
+    removed: usize,
+}
+
+/// Waits for `next`, then pushes it back by `delay` for the request after.
+async fn wait_turn(next: &Mutex<Instant>, delay: Duration) {
+    let mut next = next.lock().await;
+    tokio::time::sleep_until(*next).await;
+    *next = Instant::now() + delay;
+}
+
+/// Removes the items generated from the file at `path`, so they are generated again.
+fn remove_items(path: &Path) {
+    for extension in ["jsonl", "json"] {
+        let qa = qa_path(path, extension);
+        if qa.exists() {
+            if let Err(e) = std::fs::remove_file(&qa) {
+                warn!("Couldn't remove {:?}: {}", qa, e);
+            }
+        }
+    }
+}
+
+/// The pages of a website, crawled from a start page through its links: pages on the same
+/// host under the start page's directory, saved as `<host>/<path>`. The crawl follows
+/// robots.txt and the site's robots directives, identifies as `crawl.user_agent`, spaces
+/// its requests by `crawl.delay_ms` (or the site's `Crawl-delay`) with at most
+/// `crawl.concurrency` in flight, and stops after `crawl.max_pages` pages or
+/// `crawl.max_mb` megabytes. Pages an earlier crawl saved are only fetched again when
+/// they changed, and items are only generated again for those that did.
+pub struct CrawlSource {
+    start: Url,
+    /// Path every crawled page's path starts with.
+    scope: String,
+    config: CrawlConfig,
+    auth: Auth,
+}
+
+impl CrawlSource {
+    pub fn new(url: &str, config: &CrawlConfig, auth: &AuthConfig) -> Result<Self> {
+        let mut start = Url::parse(url.trim())?;
+        if !matches!(start.scheme(), "http" | "https") || start.host_str().is_none() {
+            return Err(anyhow!("Invalid URL to crawl {:?}", url));
+        }
+        start.set_fragment(None);
+        let scope = start.path()[..start.path().rfind('/').map_or(0, |slash| slash + 1)].to_string();
+        Ok(Self {
+            start,
+            scope,
+            config: config.clone(),
+            auth: Auth::load(auth)?,
+        })
+    }
+
+    fn in_scope(&self, url: &Url) -> bool {
+        url.scheme() == self.start.scheme()
+            && url.host_str() == self.start.host_str()
+            && url.port_or_known_default() == self.start.port_or_known_default()
+            && url.path().starts_with(&self.scope)
+    }
+
+    /// `url`, when it is worth requesting: in scope and not a file such as an image.
+    fn candidate(&self, url: &Url) -> bool {
+        let extension = url.path().rsplit('/').next().and_then(|name| name.rsplit_once('.')).map(|(_, ext)| ext.to_lowercase());
+        self.in_scope(url) && !extension.is_some_and(|ext| SKIPPED_EXTENSIONS.contains(&ext.as_str()))
+    }
+
+    fn client(&self) -> Result<Client> {
+        let host = self.start.host_str().unwrap_or("").to_string();
+        // Redirects stay on the site, so its limits are the only ones that apply
+        let policy = Policy::custom(move |attempt| match attempt.url().host_str() == Some(host.as_str()) && attempt.previous().len() < MAX_REDIRECTS {
+            true => attempt.follow(),
+            false => attempt.stop(),
+        });
+        Ok(Client::builder()
+            .user_agent(&self.config.user_agent)
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(policy)
+            .build()?)
+    }
+
+    /// The site's robots.txt rules for the crawler. A robots.txt that is missing allows
+    /// everything; one that can't be read because of a server error allows nothing.
+    async fn robots(&self, client: &Client) -> Robots {
+        let url = self.start.join("/robots.txt").expect("an absolute URL joins a path");
+        match self.auth.apply(client.get(url.clone()), &url).send().await {
+            Ok(response) if response.status().is_success() => Robots::parse(&response.text().await.unwrap_or_default(), &self.config.user_agent),
+            Ok(response) if response.status().is_client_error() => Robots::default(),
+            Ok(response) => {
+                warn!("{} answered {}; crawling nothing, as robots.txt asks for a server error", url, response.status());
+                Robots::disallow_all()
+            }
+            Err(e) => {
+                warn!("Couldn't read {}: {}; crawling nothing", url, e);
+                Robots::disallow_all()
+            }
+        }
+    }
+
+    /// Fetches `url` once `next` allows, then pushes `next` back by `delay`. A page the
+    /// last crawl `saved` is only sent again if it changed since.
+    async fn fetch(&self, client: &Client, url: Url, saved: Option<SavedPage>, next: &Mutex<Instant>, delay: Duration) -> (Url, Option<SavedPage>, Result<Fetched>) {
+        wait_turn(next, delay).await;
+        let page = async {
+            let mut request = self.auth.apply(client.get(url.clone()), &url);
+            if let Some(etag) = saved.as_ref().and_then(|saved| saved.etag.as_ref()) {
+                request = request.header("If-None-Match", etag);
+            }
+            if let Some(modified) = saved.as_ref().and_then(|saved| saved.last_modified.as_ref()) {
+                request = request.header("If-Modified-Since", modified);
+            }
+            let response = send_waiting(request).await?;
+            match response.status() {
+                StatusCode::OK => {}
+                StatusCode::NOT_MODIFIED => return Ok(Fetched::NotModified),
+                StatusCode::NOT_FOUND | StatusCode::GONE => return Ok(Fetched::Gone),
+                status if status.is_success() => return Ok(Fetched::Skipped),
+                status => return Err(anyhow!("Failed to fetch {}: {}", response.url(), status)),
+            }
+            let content_type = response
+                .headers()
+                .get("Content-Type")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("text/html")
+                .to_lowercase();
+            if !content_type.starts_with("text/") {
+                debug!("Skipping {}: {}", url, content_type);
+                return Ok(Fetched::Skipped);
+            }
+            let directives = response
+                .headers()
+                .get("X-Robots-Tag")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("")
+                .to_lowercase();
+            let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+            let (etag, last_modified) = (header("ETag"), header("Last-Modified"));
+            let modified = last_modified
+                .as_deref()
+                .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+                .map(SystemTime::from);
+            let url = response.url().clone();
+            let body = response.bytes().await?.to_vec();
+            let html = content_type.starts_with("text/html");
+            let meta = match html {
+                true => META_ROBOTS.find(&String::from_utf8_lossy(&body)).map(|tag| tag.as_str().to_lowercase()).unwrap_or_default(),
+                false => String::new(),
+            };
+            let directives = format!("{} {}", directives, meta);
+            Ok(Fetched::Page(Page {
+                url,
+                body,
+                html,
+                modified,
+                index: !directives.contains("noindex") && !directives.contains("none"),
+                follow: !directives.contains("nofollow") && !directives.contains("none"),
+                etag,
+                last_modified,
+            }))
+        };
+        let page = page.await;
+        (url, saved, page)
+    }
+
+    /// The pages of the site's sitemaps in the crawl's scope, with their `<lastmod>`:
+    /// those of the sitemaps robots.txt lists on the crawl's host, or of `/sitemap.xml`.
+    /// Sitemap indexes are followed.
+    async fn sitemap(&self, client: &Client, robots: &Robots, next: &Mutex<Instant>, delay: Duration) -> Vec<(Url, Option<String>)> {
+        let mut sitemaps: VecDeque<Url> = robots
+            .sitemaps
+            .iter()
+            .filter_map(|sitemap| Url::parse(sitemap).ok())
+            .filter(|sitemap| sitemap.host_str() == self.start.host_str())
+            .collect();
+        if sitemaps.is_empty() {
+            sitemaps.push_back(self.start.join("/sitemap.xml").expect("an absolute URL joins a path"));
+        }
+        let mut read = HashSet::new();
+        let mut pages = Vec::new();
+        while let Some(url) = sitemaps.pop_front() {
+            if read.len() == MAX_SITEMAPS || !read.insert(url.clone()) {
+                continue;
+            }
+            wait_turn(next, delay).await;
+            let text = match send_waiting(self.auth.apply(client.get(url.clone()), &url)).await {
+                Ok(response) if response.status().is_success() => response.text().await.unwrap_or_default(),
+                Ok(response) => {
+                    debug!("No sitemap at {}: {}", url, response.status());
+                    continue;
+                }
+                Err(e) => {
+                    debug!("Couldn't read sitemap {}: {}", url, e);
+                    continue;
+                }
+            };
+            for entry in SITEMAP_ENTRY.captures_iter(&text) {
+                let Some(location) = SITEMAP_LOCATION.captures(&entry[2]).and_then(|location| Url::parse(html::decode_entities(location[1].trim()).as_str()).ok()) else {
+                    continue;
+                };
+                if entry[1].eq_ignore_ascii_case("sitemap") {
+                    if location.host_str() == self.start.host_str() {
+                        sitemaps.push_back(location);
+                    }
+                } else if self.candidate(&location) {
+                    pages.push((location, SITEMAP_LASTMOD.captures(&entry[2]).map(|lastmod| lastmod[1].trim().to_string())));
+                }
+            }
+        }
+        debug!("{} pages in scope in {} sitemaps", pages.len(), read.len());
+        pages
+    }
+
+    /// Queues the links of `page` that weren't seen yet, unless it asks not to follow them.
+    fn follow(&self, page: &Page, seen: &mut HashSet<String>, queue: &mut VecDeque<Url>) {
+        if !page.html || !page.follow {
+            return;
+        }
+        for link in self.links(page) {
+            if seen.insert(link.to_string()) {
+                queue.push_back(link);
+            }
+        }
+    }
+
+    /// The links of `page` to crawl, without fragments.
+    fn links(&self, page: &Page) -> Vec<Url> {
+        let text = String::from_utf8_lossy(&page.body);
+        LINK.captures_iter(&text)
+            .filter(|captures| !captures[0].to_lowercase().contains("nofollow"))
+            .filter_map(|captures| captures.get(1).or(captures.get(2)).or(captures.get(3)))
+            .filter_map(|href| page.url.join(&html::decode_entities(href.as_str().trim())).ok())
+            .map(|mut url| {
+                url.set_fragment(None);
+                url
+            })
+            .filter(|url| self.candidate(url))
+            .collect()
+    }
+}
+
+/// The directory the pages of `url`'s host are saved in.
+fn site_dir(output_dir: &Path, url: &Url) -> PathBuf {
+    output_dir.join(url.host_str().unwrap_or("site"))
+}
+
+/// Where the page at `url` is saved: `<host>/<path>`, with `index.html` for directories,
+/// `.html` added to HTML pages without an extension and the query, if any, appended to
+/// the name, as a hash when it is too long for one.
+fn page_path(output_dir: &Path, url: &Url, html: bool) -> PathBuf {
+    let mut path = site_dir(output_dir, url);
+    let segments: Vec<&str> = url.path_segments().map(|segments| segments.collect()).unwrap_or_default();
+    let (name, dirs) = segments.split_last().unwrap_or((&"", &[]));
+    for dir in dirs.iter().filter(|dir| !dir.is_empty() && **dir != "." && **dir != "..") {
+        path.push(dir);
+    }
+    let mut name = match name.is_empty() {
+        true => "index.html".to_string(),
+        false => name.to_string(),
+    };
+    if let Some(query) = url.query() {
+        let query: String = match query.len() > MAX_QUERY_LEN {
+            true => format!("{:016x}", dataset::stable_hash(query)),
+            false => query.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect(),
+        };
+        name = match name.rsplit_once('.') {
+            Some((stem, ext)) => format!("{}_{}.{}", stem, query, ext),
+            None => format!("{}_{}", name, query),
+        };
+    }
+    if html && !name.contains('.') {
+        name.push_str(".html");
+    }
+    path.join(name)
+}
+
+/// Saves `page` at `path`, counting it in `changes`. Items of a page that changed are
+/// generated again; those of the same page are reused.
+fn save_page(path: &Path, page: &Page, changes: &mut Changes) -> std::io::Result<()> {
+    match std::fs::read(path) {
+        Ok(before) if before == page.body => changes.unchanged += 1,
+        before => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, &page.body)?;
+            remove_items(path);
+            match before {
+                Ok(_) => changes.changed += 1,
+                Err(_) => changes.new += 1,
+            }
+        }
+    }
+    // Dated by the server, for freshness
+    if let Some(modified) = page.modified {
+        std::fs::File::options().write(true).open(path)?.set_modified(modified)?;
+    }
+    Ok(())
+}
+
+/// Adds `path` to `files` and sends it on to be generated from; false once nothing
+/// receives files any more.
+fn keep(files: &mut Vec<PathBuf>, path: PathBuf, sender: Option<&mpsc::UnboundedSender<PathBuf>>) -> bool {
+    let open = sender.is_none_or(|sender| sender.send(path.clone()).is_ok());
+    files.push(path);
+    open
+}
+
+#[async_trait]
+impl DataSource for CrawlSource {
+    async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        self.crawl(output_dir, None).await
+    }
+
+    async fn stream(&self, output_dir: &Path, files: mpsc::UnboundedSender<PathBuf>) -> Result<()> {
+        self.crawl(output_dir, Some(&files)).await.map(|_| ())
+    }
+}
+
+impl CrawlSource {
+    /// Crawls the site into `output_dir`, sending each page on `sender` as it is saved.
+    async fn crawl(&self, output_dir: &Path, sender: Option<&mpsc::UnboundedSender<PathBuf>>) -> Result<Vec<PathBuf>> {
+        let client = self.client()?;
+        let robots = match self.config.robots {
+            true => self.robots(&client).await,
+            false => Robots::default(),
+        };
+        let delay = robots.delay.unwrap_or_default().max(Duration::from_millis(self.config.delay_ms));
+        let max_bytes = self.config.max_mb.saturating_mul(1024 * 1024);
+        info!("Crawling {} ({:?} between requests, {} at a time)", self.start, delay, self.config.concurrency);
+
+        let state_path = site_dir(output_dir, &self.start).join(STATE_FILE);
+        let mut state = CrawlState::load(&state_path);
+        let next = Mutex::new(Instant::now());
+        let sitemap = match self.config.sitemap && robots.allows(&self.start) {
+            true => self.sitemap(&client, &robots, &next, delay).await,
+            false => Vec::new(),
+        };
+        let lastmods: HashMap<String, Option<String>> = sitemap.iter().map(|(url, lastmod)| (url.to_string(), lastmod.clone())).collect();
+        // Pages only the sitemap links to are crawled too
+        let mut seen = HashSet::from([self.start.to_string()]);
+        let mut queue = VecDeque::from([self.start.clone()]);
+        for (url, _) in sitemap {
+            if seen.insert(url.to_string()) {
+                queue.push_back(url);
+            }
+        }
+
+        let mut in_flight = FuturesUnordered::new();
+        let (mut requested, mut bytes, mut disallowed) = (0, 0u64, 0);
+        let mut changes = Changes::default();
+        let mut files = Vec::new();
+        let mut stopped = false;
+        loop {
+            if stopped {
+                info!("Stopped crawling {}: no more pages are needed", self.start);
+                break;
+            }
+            while in_flight.len() < self.config.concurrency && requested < self.config.max_pages && bytes < max_bytes {
+                let Some(url) = queue.pop_front() else {
+                    break;
+                };
+                if !robots.allows(&url) {
+                    debug!("robots.txt disallows {}", url);
+                    disallowed += 1;
+                    continue;
+                }
+                let saved = state
+                    .pages
+                    .get(url.as_str())
+                    .filter(|saved| self.config.incremental && output_dir.join(&saved.path).is_file())
+                    .cloned();
+                // Dated the same in the sitemap as last time, so not asked for at all
+                let lastmod = lastmods.get(url.as_str()).cloned().flatten();
+                let unchanged = saved.as_ref().filter(|saved| lastmod.is_some() && saved.lastmod == lastmod);
+                if let Some((saved, page)) = unchanged.and_then(|saved| Some((saved, saved.read(output_dir, &url)?))) {
+                    changes.unchanged += 1;
+                    self.follow(&page, &mut seen, &mut queue);
+                    stopped = !keep(&mut files, output_dir.join(&saved.path), sender);
+                    if stopped {
+                        break;
+                    }
+                    continue;
+                }
+                requested += 1;
+                in_flight.push(self.fetch(&client, url, saved, &next, delay));
+            }
+            let Some((url, saved, fetched)) = in_flight.next().await else {
+                break;
+            };
+            let lastmod = lastmods.get(url.as_str()).cloned().flatten();
+            let page = match fetched {
+                Ok(Fetched::Page(page)) => page,
+                Ok(Fetched::NotModified) => {
+                    let Some(page) = saved.as_ref().and_then(|saved| saved.read(output_dir, &url)) else {
+                        continue;
+                    };
+                    changes.unchanged += 1;
+                    self.follow(&page, &mut seen, &mut queue);
+                    if let Some(saved) = state.pages.get_mut(url.as_str()) {
+                        saved.lastmod = lastmod;
+                        stopped = !keep(&mut files, output_dir.join(&saved.path), sender);
+                    }
+                    continue;
+                }
+                Ok(Fetched::Gone) => {
+                    match state.pages.remove(url.as_str()) {
+                        Some(saved) => {
+                            let path = output_dir.join(&saved.path);
+                            info!("{} is gone; removing {:?} and its items", url, path);
+                            changes.removed += 1;
+                            remove_items(&path);
+                            let _ = std::fs::remove_file(&path);
+                        }
+                        None => warn!("Skipping {}: not found", url),
+                    }
+                    continue;
+                }
+                Ok(Fetched::Skipped) => continue,
+                Err(e) => {
+                    warn!("Skipping {}: {}", url, e);
+                    continue;
+                }
+            };
+            bytes += page.body.len() as u64;
+            seen.insert(page.url.to_string());
+            self.follow(&page, &mut seen, &mut queue);
+            // Redirected out of the crawl's directory, or no longer to be indexed
+            if !page.index || !self.in_scope(&page.url) {
+                if let Some(saved) = state.pages.remove(url.as_str()) {
+                    let path = output_dir.join(&saved.path);
+                    changes.removed += 1;
+                    remove_items(&path);
+                    let _ = std::fs::remove_file(&path);
+                }
+                continue;
+            }
+            let path = page_path(output_dir, &page.url, page.html);
+            if files.contains(&path) {
+                continue;
+            }
+            if let Err(e) = save_page(&path, &page, &mut changes) {
+                warn!("Skipping {}: couldn't save it to {:?}: {}", url, path, e);
+                continue;
+            }
+            let saved = SavedPage {
+                path: path.strip_prefix(output_dir).unwrap_or(&path).to_path_buf(),
+                html: page.html,
+                follow: page.follow,
+                redirect: (page.url != url).then(|| page.url.to_string()),
+                etag: page.etag,
+                last_modified: page.last_modified,
+                lastmod,
+            };
+            state.pages.insert(url.to_string(), saved);
+            stopped = !keep(&mut files, path, sender);
+        }
+        if !state.pages.is_empty() || state_path.exists() {
+            state.save(&state_path)?;
+        }
+
+        if requested >= self.config.max_pages && !queue.is_empty() {
+            info!("Stopped crawling at crawl.max_pages ({} pages)", self.config.max_pages);
+        } else if bytes >= max_bytes {
+            info!("Stopped crawling at crawl.max_mb ({} MiB)", self.config.max_mb);
+        }
+        if disallowed > 0 {
+            info!("Left out {} pages robots.txt disallows", disallowed);
+        }
+        info!(
+            "Saved {} pages of {} ({:.1} MiB): {} new, {} changed, {} unchanged, {} removed",
+            files.len(),
+            self.start,
+            bytes as f64 / (1024.0 * 1024.0),
+            changes.new,
+            changes.changed,
+            changes.unchanged,
+            changes.removed
+        );
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "User-agent: *\n\
+        Disallow: /\n\
+        \n\
+        # Crawlers whose name merely contains ours don't get our rules\n\
+        User-agent: llm-dataset-builder-extra\n\
+        Allow: /\n\
+        \n\
+        User-agent: LLM-Dataset-Builder/2.0\n\
+        User-agent: otherbot\n\
+        Disallow: /private/\n\
+        Allow: /private/docs/\n\
+        Disallow: /*.pdf$\n\
+        Crawl-delay: 1.5\n\
+        \n\
+        Sitemap: https://example.com/sitemap.xml\n";
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn source(start: &str) -> CrawlSource {
+        CrawlSource::new(start, &CrawlConfig::default(), &AuthConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn robots_rules_come_from_the_group_naming_the_crawler() {
+        let robots = Robots::parse(ROBOTS, "llm-dataset-builder/0.1 (+https://example.com)");
+        assert!(robots.allows(&url("https://example.com/guide/")));
+        assert!(!robots.allows(&url("https://example.com/private/keys")));
+        assert_eq!(robots.delay, Some(Duration::from_millis(1500)));
+        assert_eq!(robots.sitemaps, ["https://example.com/sitemap.xml"]);
+
+        // Crawlers no group names get the `*` rules
+        let robots = Robots::parse(ROBOTS, "llm");
+        assert!(!robots.allows(&url("https://example.com/guide/")));
+        assert_eq!(robots.delay, None);
+    }
+
+    #[test]
+    fn the_longest_robots_rule_decides() {
+        let robots = Robots::parse(ROBOTS, "otherbot");
+        assert!(robots.allows(&url("https://example.com/private/docs/setup")));
+        assert!(!robots.allows(&url("https://example.com/private/notes")));
+        assert!(!robots.allows(&url("https://example.com/guide.pdf")));
+        assert!(robots.allows(&url("https://example.com/guide.pdf?download=1")));
+
+        // Allowing on a tie
+        let robots = Robots::parse("User-agent: *\nDisallow: /docs\nAllow: /docs\n", "bot");
+        assert!(robots.allows(&url("https://example.com/docs/a")));
+        assert!(!Robots::disallow_all().allows(&url("https://example.com/")));
+        assert!(Robots::default().allows(&url("https://example.com/")));
+    }
+
+    #[test]
+    fn crawls_stay_on_the_origin_and_under_the_start() {
+        let source = source("https://example.com/docs/guide/intro#top");
+        assert_eq!(source.scope, "/docs/guide/");
+        assert!(source.candidate(&url("https://example.com/docs/guide/setup")));
+        assert!(source.candidate(&url("https://example.com:443/docs/guide/")));
+        assert!(!source.candidate(&url("https://example.com/docs/other")));
+        assert!(!source.candidate(&url("http://example.com/docs/guide/setup")));
+        assert!(!source.candidate(&url("https://example.com:8443/docs/guide/setup")));
+        assert!(!source.candidate(&url("https://docs.example.com/docs/guide/setup")));
+        assert!(!source.candidate(&url("https://example.com/docs/guide/diagram.PNG")));
+        assert!(CrawlSource::new("ftp://example.com/", &CrawlConfig::default(), &AuthConfig::default()).is_err());
+    }
+
+    #[test]
+    fn pages_are_saved_under_their_host() {
+        let dir = Path::new("out");
+        assert_eq!(page_path(dir, &url("https://example.com/docs/"), true), Path::new("out/example.com/docs/index.html"));
+        assert_eq!(page_path(dir, &url("https://example.com/docs/setup"), true), Path::new("out/example.com/docs/setup.html"));
+        assert_eq!(page_path(dir, &url("https://example.com/notes.txt"), false), Path::new("out/example.com/notes.txt"));
+        assert_eq!(page_path(dir, &url("https://example.com/a/../../b/c.html"), true), Path::new("out/example.com/b/c.html"));
+        assert_eq!(page_path(dir, &url("https://example.com/search.html?q=a+b&page=2"), true), Path::new("out/example.com/search_q_a_b_page_2.html"));
+
+        let long = format!("https://example.com/search?q={}", "a".repeat(300));
+        let path = page_path(dir, &url(&long), true);
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), "search_".len() + 16 + ".html".len());
+        assert_ne!(path, page_path(dir, &url(&format!("{}b", long)), true));
+    }
+
+    #[test]
+    fn saved_pages_are_counted_and_failures_reported() {
+        let dir = std::env::temp_dir().join(format!("llmds-crawl-{:016x}", rand::random::<u64>()));
+        let page = |body: &str| Page {
+            url: url("https://example.com/"),
+            body: body.as_bytes().to_vec(),
+            html: true,
+            modified: None,
+            index: true,
+            follow: true,
+            etag: None,
+            last_modified: None,
+        };
+        let mut changes = Changes::default();
+        let path = dir.join("example.com/index.html");
+        save_page(&path, &page("one"), &mut changes).unwrap();
+        save_page(&path, &page("one"), &mut changes).unwrap();
+        save_page(&path, &page("two"), &mut changes).unwrap();
+        assert_eq!((changes.new, changes.unchanged, changes.changed), (1, 1, 1));
+        // A page under a file can't be saved
+        assert!(save_page(&path.join("setup.html"), &page("three"), &mut changes).is_err());
+        assert_eq!(changes.new, 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::config::{CurriculumConfig, SchemaConfig};
+use crate::processor::ProcessedItem;
+
+/// Interleaving by this name spreads out the items of each source file instead of the
+/// values of a field.
+pub const SOURCE_TOPIC: &str = "source";
+
+/// How a dataset is ordered for curriculum fine-tuning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurriculumOrder {
+    /// Left in the order generated
+    #[default]
+    None,
+    /// By difficulty label, easiest first, and by length within a level
+    Difficulty,
+    /// From the shortest question and answer to the longest
+    Length,
+}
+
+/// The difficulty levels of `config`, easiest first: `levels`, or the `enum` of the
+/// labelled field in `[output.schema.fields]`.
+pub fn levels(config: &CurriculumConfig, schema: &SchemaConfig) -> Result<Vec<String>> {
+    if !config.levels.is_empty() {
+        return Ok(config.levels.clone());
+    }
+    schema
+        .fields
+        .get(&config.field)
+        .and_then(|field| field.get("enum"))
+        .and_then(Value::as_array)
+        .map(|values| values.iter().map(label).collect())
+        .filter(|levels: &Vec<String>| !levels.is_empty())
+        .ok_or_else(|| {
+            anyhow!(
+                "Ordering by difficulty needs curriculum.levels or an enum for output.schema.fields.{}",
+                config.field
+            )
+        })
+}
+
+fn label(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Words of the item's question and answer.
+fn length(item: &ProcessedItem) -> usize {
+    item.question.split_whitespace().count() + item.answer.split_whitespace().count()
+}
+
+/// The topic `item` is interleaved by: its source file for `source`, otherwise the
+/// value of the field, empty when it has none.
+fn topic(item: &ProcessedItem, field: &str) -> String {
+    match field {
+        SOURCE_TOPIC => item.source.as_ref().map(|source| source.file.clone()).unwrap_or_default(),
+        field => item.extra.get(field).map(label).unwrap_or_default(),
+    }
+}
+
+/// Orders `items` by `config.order`, keeping the generated order among equals. Items
+/// without one of the levels come after the hardest. With `interleave`, the items of
+/// each topic are spread evenly through every level, so each part of the curriculum
+/// covers every topic.
+pub fn order(items: &mut Vec<ProcessedItem>, config: &CurriculumConfig, schema: &SchemaConfig) -> Result<()> {
+    let levels = match config.order {
+        CurriculumOrder::None => return Ok(()),
+        CurriculumOrder::Difficulty => levels(config, schema)?,
+        CurriculumOrder::Length => Vec::new(),
+    };
+    let level = |item: &ProcessedItem| match config.order {
+        CurriculumOrder::Difficulty => item
+            .extra
+            .get(&config.field)
+            .map(label)
+            .and_then(|value| levels.iter().position(|level| level.eq_ignore_ascii_case(value.trim())))
+            .unwrap_or(levels.len()),
+        _ => 0,
+    };
+    let mut keyed: Vec<(usize, f64, ProcessedItem)> = items.drain(..).map(|item| (level(&item), 0.0, item)).collect();
+    keyed.sort_by_key(|(level, _, item)| (*level, length(item)));
+
+    // Position of each item among the items of its topic in its level, from 0 to 1
+    if let Some(field) = &config.interleave {
+        let mut counts: HashMap<(usize, String), usize> = HashMap::new();
+        for (level, _, item) in &keyed {
+            *counts.entry((*level, topic(item, field))).or_default() += 1;
+        }
+        let mut seen: HashMap<(usize, String), usize> = HashMap::new();
+        for (level, position, item) in &mut keyed {
+            let key = (*level, topic(item, field));
+            let index = seen.entry(key.clone()).or_default();
+            *position = (*index as f64 + 0.5) / counts[&key] as f64;
+            *index += 1;
+        }
+        keyed.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    }
+    items.extend(keyed.into_iter().map(|(_, _, item)| item));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn item(question: &str, difficulty: Option<&str>, file: &str) -> ProcessedItem {
+        let mut item: ProcessedItem = serde_json::from_value(json!({
+            "question": question,
+            "answer": "Yes.",
+            "source": { "file": file, "section": 0 },
+        }))
+        .unwrap();
+        if let Some(difficulty) = difficulty {
+            item.extra.insert("difficulty".to_string(), json!(difficulty));
+        }
+        item
+    }
+
+    fn questions(items: &[ProcessedItem]) -> Vec<&str> {
+        items.iter().map(|item| item.question.as_str()).collect()
+    }
+
+    fn schema() -> SchemaConfig {
+        SchemaConfig {
+            fields: [("difficulty".to_string(), json!({ "enum": ["easy", "medium", "hard"] }))].into(),
+            required: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn items_are_ordered_by_difficulty_then_length() {
+        let mut items = vec![
+            item("Why does the long hard one fail?", Some("hard"), "a.md"),
+            item("Unlabelled?", None, "a.md"),
+            item("Is the medium one longer than this?", Some("Medium"), "a.md"),
+            item("Hard?", Some("hard"), "a.md"),
+            item("Easy?", Some(" easy "), "a.md"),
+            item("Medium?", Some("medium"), "a.md"),
+        ];
+        let config = CurriculumConfig { order: CurriculumOrder::Difficulty, ..CurriculumConfig::default() };
+        order(&mut items, &config, &schema()).unwrap();
+        assert_eq!(questions(&items), ["Easy?", "Medium?", "Is the medium one longer than this?", "Hard?", "Why does the long hard one fail?", "Unlabelled?"]);
+        assert!(order(&mut items, &config, &SchemaConfig::default()).is_err());
+    }
+
+    #[test]
+    fn topics_are_spread_through_each_level() {
+        let mut items = vec![
+            item("A one?", None, "a.md"),
+            item("A two, longer?", None, "a.md"),
+            item("A three, the longest?", None, "a.md"),
+            item("B one, the shortest of b?", None, "b.md"),
+        ];
+        let config = CurriculumConfig {
+            order: CurriculumOrder::Length,
+            interleave: Some(SOURCE_TOPIC.to_string()),
+            ..CurriculumConfig::default()
+        };
+        order(&mut items, &config, &SchemaConfig::default()).unwrap();
+        assert_eq!(questions(&items), ["A one?", "A two, longer?", "B one, the shortest of b?", "A three, the longest?"]);
+    }
+}
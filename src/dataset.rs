@@ -0,0 +1,531 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::compression::{self, CompressedFile, Compression};
+use crate::config::OutputFormat;
+use crate::follow_ups;
+use crate::noise;
+use crate::processor::ProcessedItem;
+
+/// Record layouts a dataset can be written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordFormat {
+    /// `{"question", "answer", ...}` as generated
+    #[default]
+    Qa,
+    /// `{"conversations": [{"from": "human"|"gpt", "value"}]}`
+    Sharegpt,
+    /// `{"messages": [{"role": "user"|"assistant", "content"}]}`
+    Openai,
+}
+
+impl RecordFormat {
+    /// The item as a record of this format; the chat formats start with a `system` turn
+    /// when one is given and continue with the item's follow-ups.
+    pub fn to_record(self, item: &ProcessedItem, system: Option<&str>) -> Result<Value> {
+        let exchanges = || {
+            std::iter::once((item.question.clone(), item.answer.clone()))
+                .chain(follow_ups::of(item).into_iter().map(|follow_up| (follow_up.question, follow_up.answer)))
+        };
+        Ok(match self {
+            RecordFormat::Qa => serde_json::to_value(item)?,
+            RecordFormat::Sharegpt => {
+                let mut turns: Vec<Value> = exchanges()
+                    .flat_map(|(question, answer)| [json!({ "from": "human", "value": question }), json!({ "from": "gpt", "value": answer })])
+                    .collect();
+                if let Some(system) = system {
+                    turns.insert(0, json!({ "from": "system", "value": system }));
+                }
+                json!({ "conversations": turns })
+            }
+            RecordFormat::Openai => {
+                let mut turns: Vec<Value> = exchanges()
+                    .flat_map(|(question, answer)| [json!({ "role": "user", "content": question }), json!({ "role": "assistant", "content": answer })])
+                    .collect();
+                if let Some(system) = system {
+                    turns.insert(0, json!({ "role": "system", "content": system }));
+                }
+                json!({ "messages": turns })
+            }
+        })
+    }
+
+    /// Problems with one record of this format; empty when it is valid. `required` lists
+    /// extra fields every question/answer record must carry.
+    pub fn check(self, record: &Value, required: &[String]) -> Vec<String> {
+        let Some(object) = record.as_object() else {
+            return vec!["record is not a JSON object".to_string()];
+        };
+        match self {
+            RecordFormat::Qa => ["question", "answer"]
+                .iter()
+                .filter_map(|field| match object.get(*field).and_then(Value::as_str) {
+                    Some(text) if !text.trim().is_empty() => None,
+                    Some(_) => Some(format!("empty \"{}\"", field)),
+                    None => Some(format!("missing string \"{}\"", field)),
+                })
+                .chain(
+                    required
+                        .iter()
+                        .filter(|field| !object.contains_key(field.as_str()))
+                        .map(|field| format!("missing required field \"{}\"", field)),
+                )
+                .collect(),
+            RecordFormat::Sharegpt => match object.get("conversations").and_then(Value::as_array) {
+                Some(turns) => check_turns(turns, "conversations", "from", "value", ["system", "human", "gpt"]),
+                None => vec!["missing array \"conversations\"".to_string()],
+            },
+            RecordFormat::Openai => match object.get("messages").and_then(Value::as_array) {
+                Some(turns) => check_turns(turns, "messages", "role", "content", ["system", "user", "assistant"]),
+                None => vec!["missing array \"messages\"".to_string()],
+            },
+        }
+    }
+}
+
+/// Versions of the question/answer record schema, for `migrate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Schema {
+    /// Plain `question` and `answer`, as the first versions wrote them
+    V1,
+    /// With an `id`, a `hash` and a `source` naming the file, section and chunk
+    V2,
+}
+
+/// System turns of chat-format records: one prompt, or a pool each record's prompt is
+/// picked from. Prompts are templates rendered with the item's fields, e.g.
+/// `{{ source.file }}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "OneOrMany")]
+pub struct SystemPrompts(pub Vec<String>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for SystemPrompts {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(prompt) => SystemPrompts(vec![prompt]),
+            OneOrMany::Many(prompts) => SystemPrompts(prompts),
+        }
+    }
+}
+
+impl SystemPrompts {
+    /// The system prompt of `item`, if there are any. The pick depends only on the
+    /// question, so an item keeps its prompt across runs and outputs.
+    pub fn for_item(&self, item: &ProcessedItem) -> Result<Option<String>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let template = &self.0[(stable_hash(&item.question) % self.0.len() as u64) as usize];
+        let prompt = minijinja::Environment::new()
+            .render_str(template, item)
+            .map_err(|e| anyhow!("Failed to render system prompt {:?}: {}", template, e))?;
+        Ok(Some(prompt))
+    }
+}
+
+/// Renamed and added top-level fields of written records, to match the schema a training
+/// framework expects, e.g. `rename = { question = "prompt", answer = "completion" }`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FieldMapping {
+    /// New names of record fields, by their original name.
+    pub rename: BTreeMap<String, String>,
+    /// Fields added to every record with a fixed value.
+    pub constants: BTreeMap<String, Value>,
+}
+
+impl FieldMapping {
+    pub fn is_empty(&self) -> bool {
+        self.rename.is_empty() && self.constants.is_empty()
+    }
+
+    /// Fails when two fields would end up with the same name.
+    pub fn check(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for name in self.rename.values().chain(self.constants.keys()) {
+            if !names.insert(name) {
+                return Err(anyhow!("Output field {:?} is mapped more than once", name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, mut record: Value) -> Value {
+        if self.is_empty() {
+            return record;
+        }
+        if let Some(object) = record.as_object_mut() {
+            let renamed: Vec<_> = self.rename.iter().filter_map(|(from, to)| Some((to, object.remove(from)?))).collect();
+            for (name, value) in renamed {
+                object.insert(name.clone(), value);
+            }
+            for (name, value) in &self.constants {
+                object.insert(name.clone(), value.clone());
+            }
+        }
+        record
+    }
+}
+
+/// Checks a conversation's turns: an optional leading system turn, then strictly
+/// alternating `user`/`assistant` roles starting with `user` and ending with `assistant`.
+fn check_turns(turns: &[Value], list: &str, role_key: &str, text_key: &str, roles: [&str; 3]) -> Vec<String> {
+    let [system, user, assistant] = roles;
+    let mut problems = Vec::new();
+    if turns.is_empty() {
+        problems.push(format!("\"{}\" is empty", list));
+        return problems;
+    }
+
+    let mut expected = user;
+    for (i, turn) in turns.iter().enumerate() {
+        let role = turn.get(role_key).and_then(Value::as_str);
+        match turn.get(text_key).and_then(Value::as_str) {
+            Some(text) if !text.trim().is_empty() => {}
+            Some(_) => problems.push(format!("{}[{}]: empty \"{}\"", list, i, text_key)),
+            None => problems.push(format!("{}[{}]: missing string \"{}\"", list, i, text_key)),
+        }
+        match role {
+            Some(role) if i == 0 && role == system => continue,
+            Some(role) if role == expected => {}
+            Some(role) if [system, user, assistant].contains(&role) => {
+                problems.push(format!("{}[{}]: expected role {:?} but found {:?}", list, i, expected, role))
+            }
+            Some(role) => problems.push(format!("{}[{}]: unknown role {:?}", list, i, role)),
+            None => problems.push(format!("{}[{}]: missing string \"{}\"", list, i, role_key)),
+        }
+        expected = if role == Some(user) { assistant } else { user };
+    }
+    if turns.last().and_then(|t| t.get(role_key)).and_then(Value::as_str) != Some(assistant) {
+        problems.push(format!("{} must end with a {:?} turn", list, assistant));
+    }
+    problems
+}
+
+/// Reads question/answer items from a JSONL file, or a JSON array when the file starts with
+/// `[`; `.gz` and `.zst` files are decompressed. Items without an id get one, and every
+/// hash is refreshed, so items edited by hand show up as changed.
+pub fn read_items(path: &Path) -> Result<Vec<ProcessedItem>> {
+    let mut items = read_stored_items(path)?;
+    items.iter_mut().for_each(identify);
+    Ok(items)
+}
+
+/// Reads items like `read_items`, as they are stored, without giving them ids.
+pub fn read_stored_items(path: &Path) -> Result<Vec<ProcessedItem>> {
+    let content = compression::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+    if content.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(&content)?);
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| anyhow!("{}:{}: {}", path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+/// Writes `items` as `format` records, either one per line or as a pretty JSON array.
+pub fn write_items(path: &Path, items: &[ProcessedItem], layout: OutputFormat, format: RecordFormat) -> Result<()> {
+    write_records(RecordWriter::create(path, layout, format)?, items)
+}
+
+/// Writes `items` with `writer` and finishes the file.
+pub fn write_records(mut writer: RecordWriter, items: &[ProcessedItem]) -> Result<()> {
+    for item in items {
+        writer.write(item)?;
+    }
+    writer.finish()
+}
+
+/// Writes records one at a time, in the same layout as `write_items`, so a dataset never
+/// has to be in memory as a whole. Paths ending in `.gz` or `.zst` are compressed.
+/// Records go to `<path>.tmp`, which replaces `path` on `finish`, so an interrupted
+/// write leaves the previous file in place.
+pub struct RecordWriter {
+    out: BufWriter<CompressedFile>,
+    path: PathBuf,
+    temp_path: PathBuf,
+    layout: OutputFormat,
+    format: RecordFormat,
+    system_prompts: SystemPrompts,
+    mapping: FieldMapping,
+    count: usize,
+}
+
+impl RecordWriter {
+    /// Starts writing `path`, creating any missing parent directories.
+    pub fn create(path: &Path, layout: OutputFormat, format: RecordFormat) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        Ok(Self {
+            out: BufWriter::new(CompressedFile::create_with(&temp_path, Compression::for_path(path))?),
+            path: path.to_path_buf(),
+            temp_path,
+            layout,
+            format,
+            system_prompts: SystemPrompts::default(),
+            mapping: FieldMapping::default(),
+            count: 0,
+        })
+    }
+
+    /// System turns of chat-format records.
+    pub fn with_system_prompts(mut self, system_prompts: SystemPrompts) -> Self {
+        self.system_prompts = system_prompts;
+        self
+    }
+
+    /// Renames and adds fields of every record written.
+    pub fn with_mapping(mut self, mapping: FieldMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn write(&mut self, item: &ProcessedItem) -> Result<()> {
+        let system = self.system_prompts.for_item(item)?;
+        let record = self.mapping.apply(self.format.to_record(item, system.as_deref())?);
+        match self.layout {
+            OutputFormat::Jsonl => writeln!(self.out, "{}", serde_json::to_string(&record)?)?,
+            OutputFormat::Json => {
+                // Elements of a pretty-printed array, indented one level
+                self.out.write_all(if self.count == 0 { b"[\n" } else { b",\n" })?;
+                let pretty = serde_json::to_string_pretty(&record)?;
+                for (i, line) in pretty.lines().enumerate() {
+                    if i > 0 {
+                        self.out.write_all(b"\n")?;
+                    }
+                    write!(self.out, "  {}", line)?;
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Number of records written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Closes the JSON array, if any, ends the compressed stream and moves the file into
+    /// place.
+    pub fn finish(mut self) -> Result<()> {
+        if self.layout == OutputFormat::Json {
+            self.out.write_all(if self.count == 0 { b"[]" } else { b"\n]" })?;
+        }
+        self.out.into_inner().map_err(|e| e.into_error())?.finish()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Layout implied by a file extension: `.json` (or `.json.gz`, `.json.zst`) is an array,
+/// anything else is JSONL.
+pub fn layout_for(path: &Path) -> OutputFormat {
+    match compression::inner_path(path).extension().and_then(|ext| ext.to_str()) {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Jsonl,
+    }
+}
+
+/// FNV-1a hash of `text`, which unlike the std hasher is stable across Rust versions.
+pub(crate) fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Whether `item` is one of the `fraction` of items picked for `purpose`. The pick depends
+/// only on the question, so an item is picked the same way in every run, and picks for
+/// different purposes are independent.
+pub fn picked(item: &ProcessedItem, fraction: f64, purpose: &str) -> bool {
+    let hash = stable_hash(&format!("{}\0{}", purpose, item.question));
+    (hash % 10_000) as f64 / 10_000.0 < fraction
+}
+
+/// Gives `item` an `id`, unless it has one, and refreshes its `hash`. The id is a hash of
+/// the section the item came from (its chunk id, or else its file and index), the image
+/// it is about and its question, ignoring case, whitespace and punctuation, so the same
+/// question from the same section gets the same id in every run; once given it is kept,
+/// however the item is edited. Noisy copies differ from their item in just case and
+/// punctuation, so theirs covers the question as written and the kinds of noise. The
+/// hash covers the question and answer as written.
+pub fn identify(item: &mut ProcessedItem) {
+    if item.id.is_none() {
+        let source = match &item.source {
+            Some(source) => format!(
+                "{}\0{}",
+                source.chunk.clone().unwrap_or_else(|| format!("{}#{}", source.file, source.section)),
+                source.image.as_deref().unwrap_or("")
+            ),
+            None => String::new(),
+        };
+        let question = match item.extra.get(noise::FIELD) {
+            Some(noise) => format!("{}\0{}", item.question, noise),
+            None => question_key(item),
+        };
+        item.id = Some(format!("{:016x}", stable_hash(&format!("{}\0{}", source, question))));
+    }
+    item.hash = Some(format!("{:016x}", stable_hash(&format!("{}\0{}", item.question, item.answer))));
+}
+
+/// Normalized form of a question used to detect duplicates: lowercase, with punctuation
+/// and runs of whitespace turned into single spaces.
+pub fn question_key(item: &ProcessedItem) -> String {
+    item.question
+        .to_lowercase()
+        .replace(|c: char| c.is_ascii_punctuation() || c.is_whitespace(), " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::ItemSource;
+
+    const ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+    fn turns(roles: &[&str]) -> Vec<Value> {
+        roles.iter().map(|role| json!({ "role": role, "content": "text" })).collect()
+    }
+
+    fn item(question: &str, answer: &str) -> ProcessedItem {
+        serde_json::from_value(json!({ "question": question, "answer": answer })).unwrap()
+    }
+
+    #[test]
+    fn ids_follow_the_section_and_question() {
+        let mut first = item("How do I start it?", "Run it.");
+        let mut second = item("how do I start it", "Run the binary.");
+        identify(&mut first);
+        identify(&mut second);
+        // Case and punctuation don't change the id, but the answer changes the hash
+        assert_eq!(first.id, second.id);
+        assert_ne!(first.hash, second.hash);
+
+        let mut sourced = item("How do I start it?", "Run it.");
+        sourced.source = Some(ItemSource {
+            file: "docs/a.md".to_string(),
+            section: 0,
+            chunk: Some("9f3c2a7b1e04d865".to_string()),
+            image: None,
+            modified: None,
+        });
+        identify(&mut sourced);
+        assert_ne!(sourced.id, first.id);
+        assert_eq!(sourced.hash, first.hash);
+    }
+
+    #[test]
+    fn noisy_copies_get_their_own_ids() {
+        let mut original = item("How do I start it?", "Run it.");
+        let mut casing = item("how do I start it?", "Run it.");
+        casing.extra.insert(noise::FIELD.to_string(), json!(["casing"]));
+        let mut punctuation = item("How do I start it", "Run it.");
+        punctuation.extra.insert(noise::FIELD.to_string(), json!(["punctuation"]));
+        for item in [&mut original, &mut casing, &mut punctuation] {
+            identify(item);
+        }
+        assert_ne!(casing.id, original.id);
+        assert_ne!(punctuation.id, original.id);
+        assert_ne!(punctuation.id, casing.id);
+    }
+
+    #[test]
+    fn ids_are_kept_and_hashes_refreshed() {
+        let mut edited = item("How do I start it?", "Run it.");
+        identify(&mut edited);
+        let (id, hash) = (edited.id.clone(), edited.hash.clone());
+        edited.question = "How is it started?".to_string();
+        identify(&mut edited);
+        assert_eq!(edited.id, id);
+        assert_ne!(edited.hash, hash);
+        assert_eq!(edited.id.unwrap().len(), 16);
+    }
+
+    #[test]
+    fn check_turns_accepts_alternating_conversations() {
+        assert!(check_turns(&turns(&["user", "assistant"]), "messages", "role", "content", ROLES).is_empty());
+        assert!(check_turns(&turns(&["system", "user", "assistant", "user", "assistant"]), "messages", "role", "content", ROLES).is_empty());
+        assert!(check_turns(&turns(&["human", "gpt"]), "conversations", "role", "content", ["system", "human", "gpt"]).is_empty());
+    }
+
+    #[test]
+    fn check_turns_reports_each_problem() {
+        assert_eq!(check_turns(&[], "messages", "role", "content", ROLES), ["\"messages\" is empty"]);
+        assert_eq!(
+            check_turns(&turns(&["assistant", "user"]), "messages", "role", "content", ROLES),
+            [
+                "messages[0]: expected role \"user\" but found \"assistant\"",
+                "messages must end with a \"assistant\" turn",
+            ]
+        );
+        let turns = vec![json!({ "role": "user", "content": " " }), json!({ "role": "bot" }), json!({ "content": "hi" })];
+        assert_eq!(
+            check_turns(&turns, "messages", "role", "content", ROLES),
+            [
+                "messages[0]: empty \"content\"",
+                "messages[1]: missing string \"content\"",
+                "messages[1]: unknown role \"bot\"",
+                "messages[2]: missing string \"role\"",
+                "messages must end with a \"assistant\" turn",
+            ]
+        );
+    }
+
+    #[test]
+    fn records_are_checked_for_their_format() {
+        let record = json!({ "question": "How?", "answer": " " });
+        assert_eq!(RecordFormat::Qa.check(&record, &["topic".to_string()]), ["empty \"answer\"", "missing required field \"topic\""]);
+        assert_eq!(RecordFormat::Openai.check(&record, &[]), ["missing array \"messages\""]);
+        assert_eq!(RecordFormat::Sharegpt.check(&json!([]), &[]), ["record is not a JSON object"]);
+    }
+
+    #[test]
+    fn mapped_fields_are_written_under_their_new_names() {
+        let mapping: FieldMapping = toml::from_str("rename = { question = \"prompt\", answer = \"completion\", missing = \"ignored\" }\nconstants = { split = \"train\", version = 2 }").unwrap();
+        mapping.check().unwrap();
+        let path = std::env::temp_dir().join(format!("llmds-mapping-{:016x}.jsonl", rand::random::<u64>()));
+        let mut writer = RecordWriter::create(&path, OutputFormat::Jsonl, RecordFormat::Qa).unwrap().with_mapping(mapping);
+        writer.write(&item("How do I start it?", "Run it.")).unwrap();
+        writer.write(&item("Is it free?", "Yes.")).unwrap();
+        writer.finish().unwrap();
+
+        let records: Vec<Value> = fs::read_to_string(&path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records[1], json!({ "prompt": "Is it free?", "completion": "Yes.", "split": "train", "version": 2 }));
+        let fields: Vec<&String> = records[0].as_object().unwrap().keys().collect();
+        assert_eq!(fields, ["completion", "prompt", "split", "version"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fields_mapped_twice_are_rejected() {
+        let mapping = FieldMapping {
+            rename: BTreeMap::from([("question".to_string(), "text".to_string())]),
+            constants: BTreeMap::from([("text".to_string(), json!("fixed"))]),
+        };
+        assert!(mapping.check().is_err());
+        assert_eq!(FieldMapping::default().apply(json!({ "question": "Q" })), json!({ "question": "Q" }));
+    }
+}
@@ -1,25 +1,88 @@
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
 use url::Url;
 use regex::Regex;
 use serde::Deserialize;
 use walkdir::WalkDir;
+use tracing::{debug, info, warn};
+use crate::auth::Auth;
+use crate::cli_help;
+use crate::config::{AuthConfig, LOCAL_CONFIG_FILE};
+use crate::config_reference;
+use crate::docs_site;
+use crate::freshness;
+use crate::vault;
+
+/// Tries of a rate-limited request before giving up.
+const RATE_LIMIT_TRIES: usize = 3;
 
 #[async_trait]
-pub trait DataSource {
+pub trait DataSource: Send + Sync {
     async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Sends the files `collect` would return on `files`. Sources that collect them one
+    /// at a time send each as soon as it is ready, so they are generated from while the
+    /// rest are collected, and stop once `files` is closed.
+    async fn stream(&self, output_dir: &Path, files: mpsc::UnboundedSender<PathBuf>) -> Result<()> {
+        for file in self.collect(output_dir).await? {
+            if files.send(file).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends `request`, waiting as long as the server's `Retry-After` asks (10 seconds
+/// without one) and trying again while it is rate limited. Fails on other errors.
+pub(crate) async fn send_rate_limited(request: RequestBuilder) -> Result<Response> {
+    let response = send_waiting(request).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Failed to fetch {}: {}", response.url(), response.status()));
+    }
+    Ok(response)
+}
+
+/// Sends `request` like [`send_rate_limited`], but returns the response whatever its
+/// status once it isn't rate limited.
+pub(crate) async fn send_waiting(request: RequestBuilder) -> Result<Response> {
+    for _ in 0..RATE_LIMIT_TRIES {
+        let response = request
+            .try_clone()
+            .ok_or_else(|| anyhow!("Request can't be sent again"))?
+            .send()
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let wait = response
+                .headers()
+                .get("Retry-After")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(10);
+            warn!("{} is rate limited; trying again in {}s", response.url(), wait);
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+            continue;
+        }
+        return Ok(response);
+    }
+    Err(anyhow!("Still rate limited after {} tries", RATE_LIMIT_TRIES))
 }
 
 pub struct UrlSource {
     url: Url,
+    auth: Auth,
 }
 
 impl UrlSource {
-    pub fn new(url: &str) -> Result<Self> {
+    pub fn new(url: &str, auth: &AuthConfig) -> Result<Self> {
         Ok(Self {
             url: Url::parse(url)?,
+            auth: Auth::load(auth)?,
         })
     }
 }
@@ -28,11 +91,14 @@ impl UrlSource {
 impl DataSource for UrlSource {
     async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
         let client = Client::new();
-        let response = client.get(self.url.as_str()).send().await?;
+        let response = self.auth.apply(client.get(self.url.as_str()), &self.url).send().await?;
+        if matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return Err(anyhow!("{} answered {}; set credentials for {} under [auth]", self.url, response.status(), self.url.host_str().unwrap_or("it")));
+        }
         let content = response.text().await?;
         
         let filename = self.url.path_segments()
-            .and_then(|segments| segments.last())
+            .and_then(|mut segments| segments.next_back())
             .unwrap_or("downloaded_content.txt");
             
         let output_path = output_dir.join(filename);
@@ -64,6 +130,7 @@ impl DataSource for LocalSource {
                 .ok_or_else(|| anyhow!("Invalid filename"))?;
             let dest_path = output_dir.join(filename);
             std::fs::copy(&self.path, &dest_path)?;
+            freshness::preserve(&self.path, &dest_path);
             collected.push(dest_path);
         } else if self.path.is_dir() {
             for entry in WalkDir::new(&self.path).into_iter().filter_map(|e| e.ok()) {
@@ -74,7 +141,12 @@ impl DataSource for LocalSource {
                         std::fs::create_dir_all(parent)?;
                     }
                     std::fs::copy(entry.path(), &dest_path)?;
-                    collected.push(dest_path);
+                    freshness::preserve(entry.path(), &dest_path);
+                    // Directory configs are copied along to apply to their subtree, vault
+                    // settings to tell vaults apart and docs site configs for their navigation
+                    if entry.file_name() != LOCAL_CONFIG_FILE && !vault::is_settings(relative_path) && !docs_site::is_site_file(relative_path) {
+                        collected.push(dest_path);
+                    }
                 }
             }
         }
@@ -97,19 +169,41 @@ pub struct GitHubSource {
     repo: String,
     branch: String,
     path: String,
+    /// Extra extensions to download, for parser plugins.
+    extensions: Vec<String>,
+}
+
+/// A directory listing, or the entry itself when the path is a file.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum GithubContents {
+    Directory(Vec<GithubApiContent>),
+    File(GithubApiContent),
 }
 
 impl GitHubSource {
-    pub fn new(url: &str, _branch: Option<String>, _path: Option<String>) -> Self {
-        let re = Regex::new(r"https://github\.com/([^/]+)/([^/]+)/tree/([^/]+)/(.*)").unwrap();
-        let caps = re.captures(url).expect("Invalid GitHub URL format");
-        
-        Self {
+    /// Parses a `https://github.com/<owner>/<repo>/tree/<branch>[/<path>]` directory URL
+    /// or a `.../blob/<branch>/<path>` file URL.
+    pub fn new(url: &str) -> Result<Self> {
+        let re = Regex::new(r"^https://github\.com/([^/]+)/([^/]+)/(?:tree|blob)/([^/?#]+)/?([^?#]*)")?;
+        let caps = re
+            .captures(url)
+            .ok_or_else(|| anyhow!("Invalid GitHub URL {:?}: expected https://github.com/<owner>/<repo>/tree/<branch>[/<path>]", url))?;
+
+        Ok(Self {
             owner: caps[1].to_string(),
             repo: caps[2].to_string(),
             branch: caps[3].to_string(),
-            path: caps[4].to_string(),
-        }
+            path: caps[4].trim_matches('/').to_string(),
+            extensions: Vec::new(),
+        })
+    }
+
+    /// Also downloads files with these (lowercase) extensions, which parser plugins
+    /// convert to text.
+    pub fn with_extensions(mut self, extensions: impl IntoIterator<Item = String>) -> Self {
+        self.extensions.extend(extensions);
+        self
     }
 
     async fn list_directory_contents(&self, client: &Client) -> Result<Vec<GithubApiContent>> {
@@ -128,16 +222,24 @@ impl GitHubSource {
             return Err(anyhow!("Failed to fetch directory contents: {}", response.status()));
         }
 
-        let contents: Vec<GithubApiContent> = response.json().await?;
-        Ok(contents)
+        Ok(match response.json().await? {
+            GithubContents::Directory(contents) => contents,
+            GithubContents::File(content) => vec![content],
+        })
     }
 
-    fn is_supported_file(filename: &str) -> bool {
+    fn is_supported_file(&self, filename: &str) -> bool {
         let lowercase = filename.to_lowercase();
+        let extension = lowercase.rsplit_once('.').map(|(_, ext)| ext);
         lowercase.ends_with(".md") || 
         lowercase.ends_with(".txt") ||
         lowercase.ends_with(".rst") ||
-        lowercase.ends_with(".markdown")
+        lowercase.ends_with(".markdown") ||
+        lowercase.ends_with(".tf") ||
+        lowercase.ends_with(".sql") ||
+        config_reference::is_config_reference(Path::new(filename)) ||
+        cli_help::is_cli_reference(Path::new(filename)) ||
+        extension.is_some_and(|ext| self.extensions.iter().any(|e| e == ext))
     }
 }
 
@@ -147,23 +249,23 @@ impl DataSource for GitHubSource {
         let client = Client::new();
         let mut collected = Vec::new();
 
-        println!("Fetching contents from GitHub directory...");
+        info!("Fetching contents from GitHub directory...");
         let contents = self.list_directory_contents(&client).await?;
 
         for item in contents {
-            if item.content_type != "file" || !Self::is_supported_file(&item.name) {
+            if item.content_type != "file" || !self.is_supported_file(&item.name) {
                 continue;
             }
 
             if let Some(download_url) = item.download_url {
-                println!("Downloading: {}", item.path);
+                debug!("Downloading: {}", item.path);
                 let response = client.get(&download_url)
                     .header("User-Agent", "rust-github-raw-fetcher")
                     .send()
                     .await?;
 
                 if !response.status().is_success() {
-                    println!("Failed to download {}: {}", item.path, response.status());
+                    warn!("Failed to download {}: {}", item.path, response.status());
                     continue;
                 }
 
@@ -171,14 +273,14 @@ impl DataSource for GitHubSource {
                 let output_path = output_dir.join(&item.name);
                 std::fs::write(&output_path, content)?;
                 collected.push(output_path);
-                println!("Successfully downloaded: {}", item.name);
+                info!("Successfully downloaded: {}", item.name);
             }
         }
 
         if collected.is_empty() {
-            println!("No supported files found in the specified directory.");
+            info!("No supported files found in the specified directory.");
         } else {
-            println!("Downloaded {} files", collected.len());
+            info!("Downloaded {} files", collected.len());
         }
 
         Ok(collected)
@@ -208,7 +310,7 @@ impl DataSource for GitHubReleaseSource {
         let client = Client::new();
         let url = format!("https://api.github.com/repos/{}/releases", self.repo);
         
-        println!("Fetching releases from {}", url);
+        info!("Fetching releases from {}", url);
         let releases: Vec<Release> = client
             .get(&url)
             .header("User-Agent", "llm-dataset-builder")
@@ -222,7 +324,11 @@ impl DataSource for GitHubReleaseSource {
             let filename = format!("{}.md", release.tag_name);
             let file_path = output_dir.join(&filename);
             std::fs::write(&file_path, release.body)?;
-            println!("Saved release notes for version {}", release.tag_name);
+            // Dated by publication, for freshness
+            if let Some(published) = release.published_at {
+                std::fs::File::options().write(true).open(&file_path)?.set_modified(published.into())?;
+            }
+            info!("Saved release notes for version {}", release.tag_name);
             files.push(file_path);
         }
         
@@ -234,4 +340,57 @@ impl DataSource for GitHubReleaseSource {
 struct Release {
     tag_name: String,
     body: String,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(url: &str) -> (String, String, String, String) {
+        let source = GitHubSource::new(url).unwrap();
+        (source.owner, source.repo, source.branch, source.path)
+    }
+
+    #[test]
+    fn github_directory_urls() {
+        assert_eq!(
+            parts("https://github.com/ollama/ollama/tree/main/docs"),
+            ("ollama".into(), "ollama".into(), "main".into(), "docs".into())
+        );
+        assert_eq!(parts("https://github.com/ollama/ollama/tree/main/docs/api/").3, "docs/api");
+    }
+
+    #[test]
+    fn github_tree_roots() {
+        assert_eq!(parts("https://github.com/ollama/ollama/tree/main").3, "");
+        assert_eq!(parts("https://github.com/ollama/ollama/tree/main/").3, "");
+        assert_eq!(parts("https://github.com/ollama/ollama/tree/v0.1.0?tab=readme").2, "v0.1.0");
+    }
+
+    #[test]
+    fn github_blob_urls() {
+        assert_eq!(
+            parts("https://github.com/ollama/ollama/blob/main/docs/api.md"),
+            ("ollama".into(), "ollama".into(), "main".into(), "docs/api.md".into())
+        );
+        assert_eq!(parts("https://github.com/ollama/ollama/blob/main/README.md#install").3, "README.md");
+    }
+
+    #[test]
+    fn github_sources_download_parser_plugin_files() {
+        let source = GitHubSource::new("https://github.com/ollama/ollama/tree/main/docs").unwrap();
+        assert!(source.is_supported_file("api.md"));
+        assert!(!source.is_supported_file("spec.docx"));
+        let source = source.with_extensions(["docx".to_string()]);
+        assert!(source.is_supported_file("Spec.DOCX"));
+        assert!(!source.is_supported_file("docx"));
+    }
+
+    #[test]
+    fn github_urls_without_a_branch_are_rejected() {
+        assert!(GitHubSource::new("https://github.com/ollama/ollama").is_err());
+        assert!(GitHubSource::new("https://github.com/ollama/ollama/tree/").is_err());
+        assert!(GitHubSource::new("https://example.com/ollama/ollama/tree/main").is_err());
+    }
 }
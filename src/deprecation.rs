@@ -0,0 +1,83 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use crate::config::DeprecationsConfig;
+use crate::markdown::{self, BlockKind};
+
+/// Lines passed to the prompt, at most.
+const MAX_NOTICES: usize = 5;
+
+/// Longer lines are cut to this many characters in the prompt.
+const MAX_NOTICE_CHARS: usize = 300;
+
+/// Finds passages about deprecated or removed features by the `[deprecations]` markers.
+pub struct Deprecations {
+    markers: Vec<Regex>,
+}
+
+impl Deprecations {
+    pub fn new(config: &DeprecationsConfig) -> Result<Self> {
+        Ok(Self {
+            markers: config
+                .markers
+                .iter()
+                .map(|marker| {
+                    Regex::new(&format!("(?i){}", marker)).map_err(|e| anyhow!("Invalid deprecation marker {:?}: {}", marker, e))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn is_marked(&self, text: &str) -> bool {
+        self.markers.iter().any(|marker| marker.is_match(text))
+    }
+
+    /// The lines of `text` outside code blocks that carry a marker, for the prompt.
+    pub fn notices(&self, text: &str) -> Vec<String> {
+        let mut notices: Vec<String> = Vec::new();
+        for block in markdown::blocks(text) {
+            if matches!(block.kind, BlockKind::Code { .. } | BlockKind::Frontmatter) {
+                continue;
+            }
+            for line in text[block.range].lines().filter(|line| self.is_marked(line)) {
+                let notice: String = line.trim().chars().take(MAX_NOTICE_CHARS).collect();
+                if !notices.contains(&notice) {
+                    notices.push(notice);
+                }
+            }
+        }
+        notices.truncate(MAX_NOTICES);
+        notices
+    }
+
+    /// `text` without the passages about deprecated features: every section under a
+    /// heading with a marker, up to the next heading of the same or a higher level, and
+    /// every other block with one. Returns the text and how many passages were removed.
+    pub fn strip(&self, text: &str) -> (String, usize) {
+        let mut kept = String::with_capacity(text.len());
+        let mut removed = 0;
+        let mut skipping: Option<usize> = None;
+        let mut from = 0;
+        for block in markdown::blocks(text) {
+            if let BlockKind::Heading { level, title } = &block.kind {
+                if skipping.is_some_and(|skipped| *level <= skipped) {
+                    skipping = None;
+                }
+                if skipping.is_none() && self.is_marked(title) {
+                    skipping = Some(*level);
+                    removed += 1;
+                }
+            }
+            let marked = skipping.is_none()
+                && !matches!(block.kind, BlockKind::Code { .. } | BlockKind::Frontmatter)
+                && self.is_marked(&text[block.range.clone()]);
+            if skipping.is_some() || marked {
+                // Removed along with the blank lines after it
+                kept.push_str(&text[from..block.range.start]);
+                from = text.len() - text[block.range.end..].trim_start_matches(['\n', '\r']).len();
+                removed += usize::from(marked);
+            }
+        }
+        kept.push_str(&text[from..]);
+        (kept, removed)
+    }
+}
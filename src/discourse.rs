@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use url::Url;
+use crate::compression;
+use crate::config::DiscourseConfig;
+use crate::datasource::{send_rate_limited, DataSource};
+use crate::html;
+use crate::processor::{ItemSource, ProcessedItem};
+
+/// Field holding the id of the topic an item was taken from.
+pub const TOPIC_ID_FIELD: &str = "topic_id";
+
+/// Field holding the category of the topic an item was taken from.
+pub const CATEGORY_FIELD: &str = "category";
+
+/// Extension of the files solved topics are saved in.
+const EXTENSION: &str = ".discourse.jsonl";
+
+/// Pages of the topic list read at most, 30 topics each.
+const MAX_PAGES: usize = 10;
+
+/// Wait between requests, within the limits forums set for anonymous clients.
+const REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Whether `path` holds solved topics saved by [`DiscourseSource`].
+pub fn is_export(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    path.to_str().is_some_and(|path| path.to_lowercase().ends_with(EXTENSION))
+}
+
+/// A solved topic as saved: its question and accepted solution as the forum's HTML.
+#[derive(Serialize, Deserialize)]
+struct SolvedTopic {
+    id: u64,
+    title: String,
+    category: String,
+    category_slug: String,
+    url: String,
+    question: String,
+    solution: String,
+    /// Likes of the solution post.
+    likes: u64,
+}
+
+/// The items of the solved topics in `text`, saved by [`DiscourseSource`]: the title and
+/// first post of each topic in `config.categories` answered with its accepted solution,
+/// both as Markdown, when the solution has at least `config.min_likes` likes. Items are
+/// numbered as sections of `file`.
+pub fn items(text: &str, file: &str, config: &DiscourseConfig) -> Result<Vec<ProcessedItem>> {
+    let mut items = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let topic: SolvedTopic = serde_json::from_str(line)?;
+        let in_category = config.categories.is_empty()
+            || config.categories.iter().any(|category| category.eq_ignore_ascii_case(&topic.category) || category.eq_ignore_ascii_case(&topic.category_slug));
+        if !in_category || topic.likes < config.min_likes {
+            continue;
+        }
+        let body = html::to_markdown(&topic.question);
+        let answer = html::to_markdown(&topic.solution);
+        if answer.is_empty() {
+            continue;
+        }
+        let mut item = ProcessedItem {
+            id: None,
+            question: match body.is_empty() {
+                true => topic.title.trim().to_string(),
+                false => format!("{}\n\n{}", topic.title.trim(), body),
+            },
+            answer,
+            citation: None,
+            source: Some(ItemSource {
+                file: file.to_string(),
+                section: items.len(),
+                chunk: None,
+                image: None,
+                modified: None,
+            }),
+            hash: None,
+            extra: serde_json::Map::new(),
+            embedding: None,
+            source_overlap: None,
+        };
+        item.extra.insert(TOPIC_ID_FIELD.to_string(), topic.id.into());
+        item.extra.insert(CATEGORY_FIELD.to_string(), topic.category.into());
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[derive(Deserialize)]
+struct Site {
+    categories: Vec<Category>,
+}
+
+#[derive(Deserialize)]
+struct Category {
+    id: u64,
+    name: String,
+    slug: String,
+}
+
+#[derive(Deserialize)]
+struct TopicListPage {
+    topic_list: TopicList,
+}
+
+#[derive(Deserialize)]
+struct TopicList {
+    topics: Vec<TopicSummary>,
+    more_topics_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TopicSummary {
+    id: u64,
+    #[serde(default)]
+    has_accepted_answer: bool,
+}
+
+#[derive(Deserialize)]
+struct Topic {
+    title: String,
+    category_id: Option<u64>,
+    slug: String,
+    post_stream: PostStream,
+    accepted_answer: Option<AcceptedAnswer>,
+}
+
+#[derive(Deserialize)]
+struct AcceptedAnswer {
+    post_number: u64,
+}
+
+#[derive(Deserialize)]
+struct PostStream {
+    posts: Vec<Post>,
+}
+
+#[derive(Deserialize)]
+struct Post {
+    post_number: u64,
+    cooked: String,
+    #[serde(default)]
+    accepted_answer: bool,
+    like_count: Option<u64>,
+    #[serde(default)]
+    actions_summary: Vec<Action>,
+}
+
+impl Post {
+    fn likes(&self) -> u64 {
+        // Action 2 is a like, on forums whose posts have no like count
+        self.like_count
+            .or_else(|| self.actions_summary.iter().find(|action| action.id == 2).map(|action| action.count))
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Deserialize)]
+struct Action {
+    id: u64,
+    #[serde(default)]
+    count: u64,
+}
+
+/// The solved topics of a Discourse forum, or of one of its categories, pulled from its
+/// API and saved with their accepted solutions as `<host>[-<category>].discourse.jsonl`,
+/// whose topics become items as they are.
+pub struct DiscourseSource {
+    base: Url,
+    /// The category's path, `c/support` or `c/support/4`; the latest topics when unset.
+    category: Option<String>,
+}
+
+impl DiscourseSource {
+    /// Reads a forum URL, `https://forum.example.com`, or a category URL,
+    /// `https://forum.example.com/c/support`.
+    pub fn new(url: &str) -> Result<Self> {
+        let url = Url::parse(url)?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(anyhow!("Invalid Discourse forum URL {:?}", url.as_str()));
+        }
+        let path = url.path().trim_matches('/').trim_end_matches(".json");
+        let category = path.starts_with("c/").then(|| path.to_string());
+        let mut base = url.clone();
+        base.set_path("");
+        base.set_query(None);
+        base.set_fragment(None);
+        Ok(Self { base, category })
+    }
+
+    /// Requests `path` of the forum, then waits before the next request.
+    async fn get<T: DeserializeOwned>(&self, client: &Client, path: &str) -> Result<T> {
+        let request = client
+            .get(self.base.join(path)?)
+            .header("User-Agent", "llm-dataset-builder")
+            .header("Accept", "application/json");
+        let value = send_rate_limited(request).await?.json().await?;
+        tokio::time::sleep(REQUEST_INTERVAL).await;
+        Ok(value)
+    }
+
+    /// `html` with links relative to the forum, such as mentions, made absolute.
+    fn absolute_links(&self, html: &str) -> String {
+        let base = self.base.as_str().trim_end_matches('/');
+        html.replace("href=\"/", &format!("href=\"{}/", base)).replace("src=\"/", &format!("src=\"{}/", base))
+    }
+
+    /// The topic `id` with its accepted solution, or `None` when it has none.
+    async fn solved_topic(&self, client: &Client, id: u64, categories: &HashMap<u64, &Category>) -> Result<Option<SolvedTopic>> {
+        let topic: Topic = self.get(client, &format!("t/{}.json", id)).await?;
+        let Some(question) = topic.post_stream.posts.iter().find(|post| post.post_number == 1).map(|post| post.cooked.clone()) else {
+            return Ok(None);
+        };
+        let accepted = |posts: &[Post]| {
+            posts
+                .iter()
+                .find(|post| post.accepted_answer || topic.accepted_answer.as_ref().is_some_and(|accepted| accepted.post_number == post.post_number))
+                .map(|post| (post.cooked.clone(), post.likes()))
+        };
+        let solution = match (accepted(&topic.post_stream.posts), &topic.accepted_answer) {
+            (Some(solution), _) => Some(solution),
+            // Long topics load the posts around the solution separately
+            (None, Some(answer)) => {
+                let around: Topic = self.get(client, &format!("t/{}/{}.json", id, answer.post_number)).await?;
+                accepted(&around.post_stream.posts)
+            }
+            (None, None) => None,
+        };
+        let Some((solution, likes)) = solution else {
+            return Ok(None);
+        };
+        let category = topic.category_id.and_then(|id| categories.get(&id));
+        Ok(Some(SolvedTopic {
+            id,
+            url: format!("{}t/{}/{}", self.base, topic.slug, id),
+            title: html::decode_entities(&topic.title),
+            category: category.map(|category| category.name.clone()).unwrap_or_default(),
+            category_slug: category.map(|category| category.slug.clone()).unwrap_or_default(),
+            question: self.absolute_links(&question),
+            solution: self.absolute_links(&solution),
+            likes,
+        }))
+    }
+}
+
+#[async_trait]
+impl DataSource for DiscourseSource {
+    async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        let client = Client::new();
+        let site: Site = self.get(&client, "site.json").await?;
+        let categories: HashMap<u64, &Category> = site.categories.iter().map(|category| (category.id, category)).collect();
+        let listing = match &self.category {
+            Some(category) => format!("{}.json", category),
+            None => "latest.json".to_string(),
+        };
+        info!("Fetching solved topics from {}{}", self.base, listing);
+        let mut solved = Vec::new();
+        for page in 0..MAX_PAGES {
+            let list: TopicListPage = self.get(&client, &format!("{}?page={}", listing, page)).await?;
+            for topic in list.topic_list.topics.iter().filter(|topic| topic.has_accepted_answer) {
+                // Pinned topics head every page
+                if !solved.contains(&topic.id) {
+                    solved.push(topic.id);
+                }
+            }
+            if list.topic_list.topics.is_empty() || list.topic_list.more_topics_url.is_none() {
+                break;
+            }
+        }
+        debug!("{} solved topics listed", solved.len());
+
+        let name = match &self.category {
+            Some(category) => format!("{}-{}", self.base.host_str().unwrap_or("forum"), category.trim_start_matches("c/").replace('/', "-")),
+            None => self.base.host_str().unwrap_or("forum").to_string(),
+        };
+        let path = output_dir.join(format!("{}{}", name, EXTENSION));
+        let mut file = std::fs::File::create(&path)?;
+        let mut saved = 0;
+        for id in solved {
+            match self.solved_topic(&client, id, &categories).await {
+                Ok(Some(topic)) => {
+                    writeln!(file, "{}", serde_json::to_string(&topic)?)?;
+                    saved += 1;
+                }
+                Ok(None) => debug!("Topic {} has no accepted solution", id),
+                Err(e) => warn!("Skipping topic {}: {}", id, e),
+            }
+        }
+        info!("Saved {} solved topics to {:?}", saved, path);
+        Ok(vec![path])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A saved topic of `category` whose solution has `likes`.
+    fn topic(id: u64, title: &str, category: &str, likes: u64) -> String {
+        json!({
+            "id": id,
+            "title": title,
+            "category": category,
+            "category_slug": category.to_lowercase().replace(' ', "-"),
+            "url": format!("https://forum.example.com/t/topic/{}", id),
+            "question": "<p>It fails with <code>EACCES</code>.</p>",
+            "solution": "<p>Run it as <strong>root</strong>.</p>",
+            "likes": likes,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn solved_topics_become_items() {
+        let text = format!("{}\n\n{}\n", topic(12, "Install fails", "Help Desk", 3), topic(13, "Feature idea", "Ideas", 0));
+        let items = items(&text, "forum.example.com.discourse.jsonl", &DiscourseConfig::default()).unwrap();
+        let qa: Vec<(&str, &str)> = items.iter().map(|item| (item.question.as_str(), item.answer.as_str())).collect();
+        assert_eq!(qa[0], ("Install fails\n\nIt fails with `EACCES`.", "Run it as **root**."));
+        assert_eq!(qa.len(), 2);
+        assert_eq!(items[0].extra[TOPIC_ID_FIELD], json!(12));
+        assert_eq!(items[0].extra[CATEGORY_FIELD], json!("Help Desk"));
+        assert_eq!(items[1].source.as_ref().unwrap().section, 1);
+        assert!(super::items("not json", "forum.discourse.jsonl", &DiscourseConfig::default()).is_err());
+    }
+
+    #[test]
+    fn topics_are_filtered_by_category_and_likes() {
+        let text = [topic(12, "Install fails", "Help Desk", 3), topic(13, "Feature idea", "Ideas", 5), topic(14, "Upgrade fails", "Help Desk", 0)].join("\n");
+        let config = DiscourseConfig { categories: vec!["help-desk".to_string()], min_likes: 1 };
+        let items = items(&text, "forum.discourse.jsonl", &config).unwrap();
+        let ids: Vec<&serde_json::Value> = items.iter().map(|item| &item.extra[TOPIC_ID_FIELD]).collect();
+        assert_eq!(ids, [&json!(12)]);
+    }
+
+    #[test]
+    fn forums_and_categories_are_read_from_urls() {
+        let forum = DiscourseSource::new("https://forum.example.com/latest?order=views").unwrap();
+        assert_eq!((forum.base.as_str(), forum.category.as_deref()), ("https://forum.example.com/", None));
+        let category = DiscourseSource::new("https://forum.example.com/c/support/4.json").unwrap();
+        assert_eq!(category.category.as_deref(), Some("c/support/4"));
+        assert_eq!(
+            category.absolute_links("<a href=\"/u/alice\">@alice</a> <img src=\"/uploads/a.png\">"),
+            "<a href=\"https://forum.example.com/u/alice\">@alice</a> <img src=\"https://forum.example.com/uploads/a.png\">"
+        );
+        assert!(DiscourseSource::new("ftp://forum.example.com").is_err());
+        assert!(is_export(Path::new("forum.example.com-support.Discourse.jsonl.gz")));
+    }
+}
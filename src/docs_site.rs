@@ -0,0 +1,629 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use regex::Regex;
+use serde_json::{Map, Value};
+use serde_yaml::Value as YamlValue;
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+use crate::frontmatter;
+
+const MKDOCS_CONFIGS: &[&str] = &["mkdocs.yml", "mkdocs.yaml"];
+const DOCUSAURUS_CONFIGS: &[&str] = &["docusaurus.config.js", "docusaurus.config.ts", "docusaurus.config.mjs", "docusaurus.config.cjs"];
+const SIDEBARS: &[&str] = &["sidebars.js", "sidebars.ts", "sidebars.mjs", "sidebars.cjs", "sidebars.json"];
+const CATEGORY_FILES: &[&str] = &["_category_.json", "_category_.yml", "_category_.yaml"];
+
+/// Directory holding the pages, unless the site configures another.
+const DOCS_DIR: &str = "docs";
+
+/// The `01-` a Docusaurus file or directory name may start with to order it, which is
+/// not part of its id or label.
+static NUMBER_PREFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\d+\s*[-_.]+\s*").unwrap());
+
+/// The root of the Docusaurus or MkDocs site `relative_path` is in, searched from its
+/// directory up to `output_dir`; `None` for files outside one.
+pub fn root(output_dir: &Path, relative_path: &Path) -> Option<PathBuf> {
+    relative_path
+        .parent()?
+        .ancestors()
+        .map(|dir| output_dir.join(dir))
+        .find(|dir| MKDOCS_CONFIGS.iter().chain(DOCUSAURUS_CONFIGS).any(|name| dir.join(name).is_file()))
+}
+
+/// Whether `relative_path` configures a site or its navigation rather than being a page:
+/// copied along so the site is recognized, but not generated from.
+pub fn is_site_file(relative_path: &Path) -> bool {
+    let name = relative_path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    MKDOCS_CONFIGS.iter().chain(DOCUSAURUS_CONFIGS).chain(SIDEBARS).chain(CATEGORY_FILES).any(|file| *file == name)
+}
+
+fn is_page(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("mdx"))
+}
+
+/// A page of the site in navigation order, with the categories leading to it.
+struct Page {
+    path: PathBuf,
+    breadcrumbs: Vec<String>,
+}
+
+/// The pages of a Docusaurus or MkDocs site in the order of its navigation: MkDocs'
+/// `nav`, or Docusaurus' sidebars, with the categories each page is under. Sites without
+/// a configured navigation are ordered as the tools generate it from the directories.
+pub struct DocsSite {
+    pages: Vec<Page>,
+    positions: HashMap<PathBuf, usize>,
+}
+
+impl DocsSite {
+    /// Reads the navigation of the site at `root`; parts that can't be read are warned
+    /// about and ordered by directory instead.
+    pub fn load(root: &Path) -> Self {
+        let mut pages = Vec::new();
+        match MKDOCS_CONFIGS.iter().map(|name| root.join(name)).find(|path| path.is_file()) {
+            Some(config) => Self::mkdocs(root, &config, &mut pages),
+            None => Self::docusaurus(root, &mut pages),
+        }
+        // A page in several places keeps the first
+        let mut seen = HashSet::new();
+        pages.retain(|page| seen.insert(page.path.clone()));
+        let positions = pages.iter().enumerate().map(|(index, page)| (page.path.clone(), index)).collect();
+        Self { pages, positions }
+    }
+
+    /// Categories leading to `path` in the navigation, outermost first.
+    pub fn breadcrumbs(&self, path: &Path) -> Option<&[String]> {
+        self.positions.get(path).map(|&index| self.pages[index].breadcrumbs.as_slice())
+    }
+
+    fn position(&self, path: &Path) -> Option<usize> {
+        self.positions.get(path).copied()
+    }
+
+    fn mkdocs(root: &Path, config: &Path, pages: &mut Vec<Page>) {
+        let yaml = std::fs::read_to_string(config)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(serde_yaml::from_str::<YamlValue>(&text)?))
+            .inspect_err(|e| warn!("Ordering {:?} by directory: {}", config, e))
+            .unwrap_or_default();
+        let docs_dir = root.join(yaml.get("docs_dir").and_then(YamlValue::as_str).unwrap_or(DOCS_DIR));
+        match yaml.get("nav").and_then(YamlValue::as_sequence) {
+            Some(nav) => Self::mkdocs_nav(&docs_dir, nav, &[], pages),
+            None => Self::mkdocs_dir(&docs_dir, &[], pages),
+        }
+        debug!("Read the MkDocs navigation of {:?}: {} pages", root, pages.len());
+    }
+
+    /// Entries of an MkDocs `nav`: `page.md`, `Title: page.md` or `Section: [...]`.
+    fn mkdocs_nav(docs_dir: &Path, entries: &[YamlValue], breadcrumbs: &[String], pages: &mut Vec<Page>) {
+        for entry in entries {
+            let (title, target) = match entry {
+                YamlValue::Mapping(mapping) => match mapping.iter().next() {
+                    Some((title, target)) => (title.as_str(), target),
+                    None => continue,
+                },
+                target => (None, target),
+            };
+            match target {
+                YamlValue::String(page) if !page.contains("://") => pages.push(Page {
+                    path: docs_dir.join(page),
+                    breadcrumbs: breadcrumbs.to_vec(),
+                }),
+                YamlValue::Sequence(children) => {
+                    let mut breadcrumbs = breadcrumbs.to_vec();
+                    breadcrumbs.extend(title.map(str::to_string));
+                    Self::mkdocs_nav(docs_dir, children, &breadcrumbs, pages);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The navigation MkDocs makes without a `nav`: the index first, then the pages and
+    /// directories by name, each directory a section titled after it.
+    fn mkdocs_dir(dir: &Path, breadcrumbs: &[String], pages: &mut Vec<Page>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut entries: Vec<PathBuf> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+        entries.sort_by_key(|path| {
+            let name = path.file_stem().and_then(|name| name.to_str()).unwrap_or("").to_lowercase();
+            (!matches!(name.as_str(), "index" | "readme"), name)
+        });
+        for path in entries {
+            if path.is_dir() {
+                let mut breadcrumbs = breadcrumbs.to_vec();
+                breadcrumbs.push(title_case(path.file_name().and_then(|name| name.to_str()).unwrap_or("")));
+                Self::mkdocs_dir(&path, &breadcrumbs, pages);
+            } else if is_page(&path) {
+                pages.push(Page { path, breadcrumbs: breadcrumbs.to_vec() });
+            }
+        }
+    }
+
+    fn docusaurus(root: &Path, pages: &mut Vec<Page>) {
+        let docs_dir = root.join(DOCS_DIR);
+        let sidebars = SIDEBARS.iter().map(|name| root.join(name)).find(|path| path.is_file());
+        let sidebars = sidebars.and_then(|path| match std::fs::read_to_string(&path) {
+            Ok(text) => Some(sidebar_literal(&text)),
+            Err(e) => {
+                warn!("Ordering {:?} by directory: {}", path, e);
+                None
+            }
+        });
+        match sidebars {
+            Some(Literal::Object(sidebars)) => {
+                let ids = Self::doc_ids(&docs_dir);
+                for (_, items) in &sidebars {
+                    Self::sidebar_items(&docs_dir, &ids, items, &[], pages);
+                }
+            }
+            _ => Self::autogenerated(&docs_dir, &[], pages),
+        }
+        debug!("Read the Docusaurus sidebars of {:?}: {} pages", root, pages.len());
+    }
+
+    /// The files of the pages under `docs_dir` by their ids: their paths without number
+    /// prefixes and extension, the last part replaced by an `id` in their frontmatter.
+    fn doc_ids(docs_dir: &Path) -> HashMap<String, PathBuf> {
+        let mut ids = HashMap::new();
+        for entry in WalkDir::new(docs_dir).into_iter().filter_map(|entry| entry.ok()).filter(|entry| entry.file_type().is_file() && is_page(entry.path())) {
+            let path = entry.path();
+            let relative = path.strip_prefix(docs_dir).unwrap_or(path).with_extension("");
+            let mut parts: Vec<String> = relative.iter().map(|part| NUMBER_PREFIX.replace(&part.to_string_lossy(), "").into_owned()).collect();
+            if let Some(id) = front_matter_str(path, "id") {
+                parts.pop();
+                parts.push(id);
+            }
+            ids.insert(parts.join("/"), path.to_path_buf());
+        }
+        ids
+    }
+
+    /// Items of a sidebar: doc ids, `doc` and `category` items, `autogenerated` ones, and
+    /// the shorthand `{ "Category": [...] }` maps.
+    fn sidebar_items(docs_dir: &Path, ids: &HashMap<String, PathBuf>, items: &Literal, breadcrumbs: &[String], pages: &mut Vec<Page>) {
+        match items {
+            Literal::String(id) => doc_in(ids, id, breadcrumbs, pages),
+            Literal::Array(items) => items.iter().for_each(|item| Self::sidebar_items(docs_dir, ids, item, breadcrumbs, pages)),
+            Literal::Object(item) => match items.get_str("type") {
+                Some("doc" | "ref") => doc_in(ids, items.get_str("id").unwrap_or(""), breadcrumbs, pages),
+                Some("category") => {
+                    let mut breadcrumbs = breadcrumbs.to_vec();
+                    breadcrumbs.extend(items.get_str("label").map(str::to_string));
+                    // The page opening the category
+                    if let Some(link) = items.get("link").filter(|link| link.get_str("type") == Some("doc")) {
+                        doc_in(ids, link.get_str("id").unwrap_or(""), &breadcrumbs, pages);
+                    }
+                    if let Some(children) = items.get("items") {
+                        Self::sidebar_items(docs_dir, ids, children, &breadcrumbs, pages);
+                    }
+                }
+                Some("autogenerated") => Self::autogenerated(&docs_dir.join(items.get_str("dirName").unwrap_or(".")), breadcrumbs, pages),
+                Some(_) => {}
+                None => {
+                    for (label, children) in item {
+                        let mut breadcrumbs = breadcrumbs.to_vec();
+                        breadcrumbs.push(label.clone());
+                        Self::sidebar_items(docs_dir, ids, children, &breadcrumbs, pages);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// The sidebar Docusaurus generates from `dir`: pages and directories by their
+    /// `sidebar_position` or `_category_` file's `position`, the others by name after
+    /// them, each directory a category labelled by its `_category_` file or its name.
+    fn autogenerated(dir: &Path, breadcrumbs: &[String], pages: &mut Vec<Page>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut entries: Vec<(Option<f64>, String, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() || (is_page(path) && !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('_'))))
+            .map(|path| {
+                let position = match path.is_dir() {
+                    true => category(&path).get("position").and_then(Value::as_f64),
+                    false => front_matter_str(&path, "sidebar_position").and_then(|position| position.parse().ok()),
+                };
+                (position, path.file_name().unwrap_or_default().to_string_lossy().to_lowercase(), path)
+            })
+            .collect();
+        entries.sort_by(|(a, a_name, _), (b, b_name, _)| match (a, b) {
+            (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal).then_with(|| a_name.cmp(b_name)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a_name.cmp(b_name),
+        });
+        for (_, _, path) in entries {
+            match path.is_dir() {
+                true => {
+                    let mut breadcrumbs = breadcrumbs.to_vec();
+                    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                    let label = category(&path).get("label").and_then(Value::as_str).map(str::to_string);
+                    breadcrumbs.push(label.unwrap_or_else(|| NUMBER_PREFIX.replace(&name, "").into_owned()));
+                    Self::autogenerated(&path, &breadcrumbs, pages);
+                }
+                false => pages.push(Page { path, breadcrumbs: breadcrumbs.to_vec() }),
+            }
+        }
+    }
+}
+
+/// `files` with those of each docs site in the order of its navigation, in the places the
+/// site's files had; pages missing from the navigation follow, in their order. Other
+/// files stay where they are.
+pub fn nav_order(mut files: Vec<PathBuf>, output_dir: &Path) -> Vec<PathBuf> {
+    let mut sites: HashMap<PathBuf, (DocsSite, Vec<usize>)> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        let relative = file.strip_prefix(output_dir).unwrap_or(file);
+        if let Some(root) = root(output_dir, relative) {
+            sites.entry(root).or_insert_with_key(|root| (DocsSite::load(root), Vec::new())).1.push(index);
+        }
+    }
+    for (root, (site, slots)) in sites {
+        debug!("Ordering {} files of {:?} by its navigation", slots.len(), root);
+        let mut ordered: Vec<PathBuf> = slots.iter().map(|&slot| files[slot].clone()).collect();
+        ordered.sort_by_key(|file| site.position(file).unwrap_or(usize::MAX));
+        for (slot, file) in slots.into_iter().zip(ordered) {
+            files[slot] = file;
+        }
+    }
+    files
+}
+
+/// Adds the page of the doc `id` to `pages`.
+fn doc_in(ids: &HashMap<String, PathBuf>, id: &str, breadcrumbs: &[String], pages: &mut Vec<Page>) {
+    match ids.get(id) {
+        Some(path) => pages.push(Page {
+            path: path.clone(),
+            breadcrumbs: breadcrumbs.to_vec(),
+        }),
+        None => debug!("No page for the sidebar doc {:?}", id),
+    }
+}
+
+/// `Getting-started` as `Getting started`, the way MkDocs titles directories.
+fn title_case(name: &str) -> String {
+    let name = name.replace(['-', '_'], " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+/// The `key` of the frontmatter of the page at `path`, as text.
+fn front_matter_str(path: &Path, key: &str) -> Option<String> {
+    let text = std::fs::read_to_string(path).ok()?;
+    match frontmatter::parse(&text).0?.get(key)? {
+        YamlValue::String(value) => Some(value.clone()),
+        YamlValue::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// The `_category_.json` or `_category_.yml` of a Docusaurus directory; empty without one.
+fn category(dir: &Path) -> Map<String, Value> {
+    let Some((path, text)) = CATEGORY_FILES.iter().map(|name| dir.join(name)).find_map(|path| Some((path.clone(), std::fs::read_to_string(&path).ok()?))) else {
+        return Map::new();
+    };
+    let value = match path.extension().is_some_and(|ext| ext == "json") {
+        true => serde_json::from_str(&text).map_err(anyhow::Error::from),
+        false => serde_yaml::from_str(&text).map_err(anyhow::Error::from),
+    };
+    match value {
+        Ok(Value::Object(category)) => category,
+        Ok(_) => Map::new(),
+        Err(e) => {
+            warn!("Ignoring {:?}: {}", path, e);
+            Map::new()
+        }
+    }
+}
+
+/// The object a `sidebars.js` (or `.ts`, or `.json`) file exports: the literal after
+/// `module.exports =` or `export default`, assigned to `sidebars`, or the whole file.
+fn sidebar_literal(text: &str) -> Literal {
+    ["module.exports", "export default", "sidebars", ""]
+        .iter()
+        .filter_map(|marker| {
+            let start = text.find(marker)? + marker.len();
+            let brace = start + text[start..].find('{')?;
+            // `module.exports = sidebars;` exports a name rather than the literal
+            let between = text[start..brace].trim();
+            (!between.contains(';') && (between.is_empty() || between.ends_with('='))).then_some(brace)
+        })
+        .map(|brace| Parser::new(&text[brace..]).value())
+        .find(|literal| matches!(literal, Literal::Object(_)))
+        .unwrap_or(Literal::Null)
+}
+
+/// A JavaScript value as written in a sidebars file, objects keeping the order of their
+/// keys.
+enum Literal {
+    Null,
+    String(String),
+    Array(Vec<Literal>),
+    Object(Vec<(String, Literal)>),
+}
+
+impl Literal {
+    fn get(&self, key: &str) -> Option<&Literal> {
+        match self {
+            Literal::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Literal::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a JavaScript object or array literal: keys may be unquoted, strings
+/// single-quoted or backquoted, commas trailing and comments anywhere. Anything but
+/// objects, arrays and strings, such as a `require(...)`, reads as null.
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Self { chars: text.chars().collect(), at: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_space(&mut self) {
+        loop {
+            match (self.peek(), self.chars.get(self.at + 1)) {
+                (Some(c), _) if c.is_whitespace() => self.at += 1,
+                (Some('/'), Some('/')) => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.at += 1;
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    self.at += 2;
+                    while self.at < self.chars.len() && !(self.chars[self.at] == '*' && self.chars.get(self.at + 1) == Some(&'/')) {
+                        self.at += 1;
+                    }
+                    self.at += 2;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skips the rest of an expression, up to the `,`, `}` or `]` ending it; unmatched
+    /// parentheses are skipped too.
+    fn skip_expression(&mut self) {
+        let mut depth = 0usize;
+        while let Some(c) = self.peek() {
+            match c {
+                '"' | '\'' | '`' => {
+                    self.string();
+                    continue;
+                }
+                '(' | '[' | '{' => depth += 1,
+                ']' | '}' if depth == 0 => return,
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => return,
+                _ => {}
+            }
+            self.at += 1;
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let quote = self.chars[self.at];
+        self.at += 1;
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            self.at += 1;
+            match c {
+                '\\' => {
+                    text.extend(self.peek().map(|escaped| match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        other => other,
+                    }));
+                    self.at += 1;
+                }
+                c if c == quote => break,
+                c => text.push(c),
+            }
+        }
+        text
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '$') {
+            self.at += 1;
+        }
+        self.chars[start..self.at].iter().collect()
+    }
+
+    fn value(&mut self) -> Literal {
+        self.skip_space();
+        match self.peek() {
+            Some('{') => {
+                self.at += 1;
+                let mut object = Vec::new();
+                loop {
+                    self.skip_space();
+                    let key = match self.peek() {
+                        Some('}' | ']') | None => break,
+                        Some('"' | '\'' | '`') => self.string(),
+                        Some(c) if c.is_alphanumeric() || c == '_' || c == '$' => self.word(),
+                        // Spreads and computed keys
+                        Some(_) => {
+                            self.at += 1;
+                            self.skip_expression();
+                            self.skip_separator();
+                            continue;
+                        }
+                    };
+                    self.skip_space();
+                    let value = match self.peek() {
+                        Some(':') => {
+                            self.at += 1;
+                            self.value()
+                        }
+                        _ => Literal::Null,
+                    };
+                    object.push((key, value));
+                    self.skip_expression();
+                    self.skip_separator();
+                }
+                self.at += 1;
+                Literal::Object(object)
+            }
+            Some('[') => {
+                self.at += 1;
+                let mut array = Vec::new();
+                loop {
+                    self.skip_space();
+                    if matches!(self.peek(), Some(']' | '}') | None) {
+                        break;
+                    }
+                    array.push(self.value());
+                    self.skip_expression();
+                    self.skip_separator();
+                }
+                self.at += 1;
+                Literal::Array(array)
+            }
+            Some('"' | '\'' | '`') => Literal::String(self.string()),
+            // Numbers, booleans and other expressions aren't needed
+            _ => Literal::Null,
+        }
+    }
+
+    fn skip_separator(&mut self) {
+        self.skip_space();
+        if self.peek() == Some(',') {
+            self.at += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A site in a new directory with `files`, by path.
+    fn site(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("llmds-site-{:016x}", rand::random::<u64>()));
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    /// The pages of `site` in order, relative to its root, with their breadcrumbs.
+    fn pages(root: &Path) -> Vec<(String, Vec<String>)> {
+        DocsSite::load(root)
+            .pages
+            .iter()
+            .map(|page| (page.path.strip_prefix(root).unwrap().display().to_string(), page.breadcrumbs.clone()))
+            .collect()
+    }
+
+    fn page(path: &str, breadcrumbs: &[&str]) -> (String, Vec<String>) {
+        (path.to_string(), breadcrumbs.iter().map(|crumb| crumb.to_string()).collect())
+    }
+
+    #[test]
+    fn mkdocs_pages_follow_the_nav() {
+        let root = site(&[
+            ("mkdocs.yml", "site_name: Tool\nnav:\n  - index.md\n  - Guides:\n      - Setup: guides/setup.md\n      - Advanced:\n          - guides/tuning.md\n  - GitHub: https://github.com/acme/tool\n"),
+            ("docs/index.md", "# Tool"),
+            ("docs/guides/setup.md", "# Setup"),
+            ("docs/guides/tuning.md", "# Tuning"),
+        ]);
+        assert_eq!(
+            pages(&root),
+            [page("docs/index.md", &[]), page("docs/guides/setup.md", &["Guides"]), page("docs/guides/tuning.md", &["Guides", "Advanced"])]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn mkdocs_sites_without_a_nav_follow_their_directories() {
+        let root = site(&[
+            ("mkdocs.yml", "site_name: Tool\ndocs_dir: pages\n"),
+            ("pages/about.md", ""),
+            ("pages/index.md", ""),
+            ("pages/getting-started/install.md", ""),
+            ("pages/logo.png", ""),
+        ]);
+        assert_eq!(
+            pages(&root),
+            [page("pages/index.md", &[]), page("pages/about.md", &[]), page("pages/getting-started/install.md", &["Getting started"])]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn docusaurus_pages_follow_the_sidebars() {
+        let root = site(&[
+            ("docusaurus.config.js", "module.exports = {};"),
+            (
+                "sidebars.js",
+                "// @ts-check\nconst sidebars = {\n  docs: [\n    'intro',\n    {\n      type: 'category',\n      label: 'Guides',\n      link: { type: 'doc', id: 'guides/overview' },\n      items: ['guides/install', { type: 'autogenerated', dirName: 'reference' }],\n    },\n  ],\n  extra: { 'More': ['faq'] },\n};\nmodule.exports = sidebars;\n",
+            ),
+            ("docs/intro.md", ""),
+            ("docs/02-guides/overview.md", ""),
+            ("docs/02-guides/01-setup.md", "---\nid: install\n---\n# Setup"),
+            ("docs/reference/cli.md", "---\nsidebar_position: 2\n---\n"),
+            ("docs/reference/api.md", "---\nsidebar_position: 1\n---\n"),
+            ("docs/reference/config/_category_.json", "{ \"label\": \"Configuration\", \"position\": 3 }"),
+            ("docs/reference/config/file.md", ""),
+            ("docs/faq.mdx", ""),
+        ]);
+        assert_eq!(
+            pages(&root),
+            [
+                page("docs/intro.md", &[]),
+                page("docs/02-guides/overview.md", &["Guides"]),
+                page("docs/02-guides/01-setup.md", &["Guides"]),
+                page("docs/reference/api.md", &["Guides"]),
+                page("docs/reference/cli.md", &["Guides"]),
+                page("docs/reference/config/file.md", &["Guides", "Configuration"]),
+                page("docs/faq.mdx", &["More"]),
+            ]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn site_files_are_ordered_in_place() {
+        let root = site(&[
+            ("site/mkdocs.yml", "nav:\n  - b.md\n  - a.md\n"),
+            ("site/docs/a.md", ""),
+            ("site/docs/b.md", ""),
+        ]);
+        let files = vec![root.join("notes.md"), root.join("site/docs/a.md"), root.join("other.md"), root.join("site/docs/b.md")];
+        assert_eq!(nav_order(files, &root), [root.join("notes.md"), root.join("site/docs/b.md"), root.join("other.md"), root.join("site/docs/a.md")]);
+        assert!(is_site_file(Path::new("site/docs/guides/_category_.yml")));
+        assert!(!is_site_file(Path::new("site/docs/a.md")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
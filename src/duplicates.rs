@@ -0,0 +1,192 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::{debug, info, warn};
+use crate::embeddings::Embedder;
+use crate::pipeline::{FileWork, Stage};
+
+/// Sections seen so far in the run.
+#[derive(Default)]
+struct SeenSections {
+    hashes: HashSet<u64>,
+    /// Unit-length embeddings, compared by dot product.
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl SeenSections {
+    fn most_similar(&self, embedding: &[f32]) -> f32 {
+        self.embeddings
+            .iter()
+            .map(|seen| seen.iter().zip(embedding).map(|(a, b)| a * b).sum::<f32>())
+            .fold(f32::MIN, f32::max)
+    }
+}
+
+/// Hash of a section's text ignoring case and whitespace, so reflowed copies match.
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for word in text.split_whitespace() {
+        word.to_lowercase().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// `embedding` scaled to unit length, so a dot product gives the cosine similarity.
+pub(crate) fn normalized(mut embedding: Vec<f32>) -> Vec<f32> {
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+    embedding
+}
+
+/// Drops sections that repeat one already processed in the run, in this file or an
+/// earlier one: identical text (ignoring case and whitespace), or, with an embedding
+/// model, an embedding at least `max_similarity` similar. Docs repeating the same
+/// boilerplate then only pay for it once. Streamed files are left alone.
+pub struct DuplicateSectionStage {
+    embedder: Option<Embedder>,
+    max_similarity: f32,
+    seen: Mutex<SeenSections>,
+}
+
+impl DuplicateSectionStage {
+    pub fn new(embedder: Option<Embedder>, max_similarity: f32) -> Self {
+        Self {
+            embedder,
+            max_similarity,
+            seen: Mutex::new(SeenSections::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl Stage for DuplicateSectionStage {
+    fn name(&self) -> &'static str {
+        "dedupe"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        if work.sections.is_empty() {
+            return Ok(());
+        }
+        let embeddings = match &self.embedder {
+            Some(embedder) => {
+                let texts: Vec<&str> = work.sections.iter().map(|section| section.text.as_str()).collect();
+                match embedder.embed(&texts).await {
+                    Ok(embeddings) => embeddings.into_iter().map(normalized).map(Some).collect(),
+                    Err(e) => {
+                        warn!("Failed to embed the sections of {:?}, comparing their text only: {}", work.path, e);
+                        vec![None; work.sections.len()]
+                    }
+                }
+            }
+            None => vec![None; work.sections.len()],
+        };
+
+        let mut keep = Vec::with_capacity(work.sections.len());
+        {
+            let mut seen = self.seen.lock().unwrap();
+            for (section, embedding) in work.sections.iter().zip(embeddings) {
+                if !seen.hashes.insert(text_hash(&section.text)) {
+                    debug!("Section {} repeats an earlier section", section.index + 1);
+                    keep.push(false);
+                    continue;
+                }
+                if let Some(embedding) = embedding {
+                    let similarity = seen.most_similar(&embedding);
+                    if similarity >= self.max_similarity {
+                        debug!("Section {} is {:.3} similar to an earlier section", section.index + 1, similarity);
+                        keep.push(false);
+                        continue;
+                    }
+                    seen.embeddings.push(embedding);
+                }
+                keep.push(true);
+            }
+        }
+        let before = work.sections.len();
+        let mut keep = keep.into_iter();
+        work.sections.retain(|_| keep.next().unwrap_or(true));
+        let duplicates = before - work.sections.len();
+        if duplicates > 0 {
+            info!("Skipping {} of {} sections of {:?} that duplicate earlier ones", duplicates, before, work.path);
+            work.stats.duplicate_sections = duplicates;
+            work.stats.sections = work.sections.len();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::config::EmbeddingsConfig;
+    use crate::pipeline::Section;
+
+    const LICENSE: &str = "## License\n\nReleased under the MIT license.";
+
+    /// A file with a section for each of `texts`.
+    fn file(name: &str, texts: &[&str]) -> FileWork {
+        let mut work = FileWork::new(Path::new(name), Path::new(""));
+        work.sections = texts
+            .iter()
+            .enumerate()
+            .map(|(index, text)| Section { index, text: text.to_string(), target: 1, release: None })
+            .collect();
+        work
+    }
+
+    fn indexes(work: &FileWork) -> Vec<usize> {
+        work.sections.iter().map(|section| section.index).collect()
+    }
+
+    #[test]
+    fn text_hashes_ignore_case_and_whitespace() {
+        assert_eq!(text_hash("Released under\nthe MIT license."), text_hash("released under the  MIT LICENSE."));
+        assert_ne!(text_hash("Released under the MIT license."), text_hash("Released under the BSD license."));
+        assert_eq!(normalized(vec![3.0, 4.0]), [0.6, 0.8]);
+        assert_eq!(normalized(vec![0.0, 0.0]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn similarity_is_to_the_closest_section() {
+        let seen = SeenSections {
+            hashes: HashSet::new(),
+            embeddings: vec![vec![1.0, 0.0], vec![0.6, 0.8]],
+        };
+        assert_eq!(seen.most_similar(&[0.0, 1.0]), 0.8);
+        assert_eq!(SeenSections::default().most_similar(&[1.0, 0.0]), f32::MIN);
+    }
+
+    #[tokio::test]
+    async fn repeated_sections_are_dropped_across_files() {
+        let stage = DuplicateSectionStage::new(None, 0.95);
+        let mut a = file("a.md", &["## Install\n\nRun the installer.", LICENSE]);
+        stage.process(&mut a).await.unwrap();
+        assert_eq!(indexes(&a), [0, 1]);
+        assert_eq!(a.stats.duplicate_sections, 0);
+
+        let mut b = file("b.md", &["## Remove\n\nRun the uninstaller.", &LICENSE.to_uppercase(), "## Remove\n\nRun   the uninstaller."]);
+        stage.process(&mut b).await.unwrap();
+        assert_eq!(indexes(&b), [0]);
+        assert_eq!((b.stats.duplicate_sections, b.stats.sections), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn failed_embeddings_fall_back_to_the_text() {
+        let config = EmbeddingsConfig {
+            model: Some("nomic-embed-text".to_string()),
+            ..EmbeddingsConfig::default()
+        };
+        let stage = DuplicateSectionStage::new(Embedder::new(&config, "http://127.0.0.1:1"), 0.5);
+        let mut work = file("a.md", &[LICENSE, "## Install\n\nRun the installer.", LICENSE]);
+        stage.process(&mut work).await.unwrap();
+        assert_eq!(indexes(&work), [0, 1]);
+        assert!(work.error.is_none());
+    }
+}
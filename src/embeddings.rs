@@ -0,0 +1,283 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+use crate::config::{EmbeddingApi, EmbeddingsConfig};
+use crate::error::Error;
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::ProcessedItem;
+
+/// A section of a source file and its embedding, written to sinks next to the items.
+#[derive(Debug, Clone)]
+pub struct ChunkRecord {
+    pub file: String,
+    pub section: usize,
+    /// See `pipeline::chunk_id`.
+    pub id: String,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Computes embeddings with Ollama's `/api/embed` or an OpenAI-compatible
+/// `/v1/embeddings` endpoint.
+pub struct Embedder {
+    client: Client,
+    api: EmbeddingApi,
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+    batch_size: usize,
+}
+
+impl Embedder {
+    /// An embedder for `config`, or `None` when no embedding model is configured.
+    pub fn new(config: &EmbeddingsConfig, default_endpoint: &str) -> Option<Self> {
+        let model = config.model.clone()?;
+        Some(Self {
+            client: Client::new(),
+            api: config.api,
+            endpoint: config.endpoint.as_deref().unwrap_or(default_endpoint).trim_end_matches('/').to_string(),
+            model,
+            api_key: config.api_key.clone(),
+            batch_size: config.batch_size.max(1),
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// One embedding per text, in order.
+    pub async fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            let mut batch_embeddings = self.embed_batch(batch).await?;
+            if batch_embeddings.len() != batch.len() {
+                return Err(Error::Parse(format!(
+                    "Embedding endpoint returned {} embeddings for {} texts",
+                    batch_embeddings.len(),
+                    batch.len()
+                )));
+            }
+            embeddings.append(&mut batch_embeddings);
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Error> {
+        let path = match self.api {
+            EmbeddingApi::Ollama => "api/embed",
+            EmbeddingApi::Openai => "v1/embeddings",
+        };
+        let mut request = self
+            .client
+            .post(format!("{}/{}", self.endpoint, path))
+            .json(&json!({ "model": self.model, "input": texts }));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = format!("Embedding endpoint returned {}: {}", status, response.text().await?);
+            return Err(match status {
+                StatusCode::TOO_MANY_REQUESTS => Error::Quota(message),
+                _ => Error::Api(message),
+            });
+        }
+        let text = response.text().await?;
+        let parse_error = |e: serde_json::Error| Error::Parse(format!("Invalid embedding response: {}", e));
+        match self.api {
+            EmbeddingApi::Ollama => {
+                #[derive(Deserialize)]
+                struct EmbedResponse {
+                    embeddings: Vec<Vec<f32>>,
+                }
+                Ok(serde_json::from_str::<EmbedResponse>(&text).map_err(parse_error)?.embeddings)
+            }
+            EmbeddingApi::Openai => {
+                #[derive(Deserialize)]
+                struct Embedding {
+                    index: usize,
+                    embedding: Vec<f32>,
+                }
+                #[derive(Deserialize)]
+                struct EmbeddingsResponse {
+                    data: Vec<Embedding>,
+                }
+                let mut data = serde_json::from_str::<EmbeddingsResponse>(&text).map_err(parse_error)?.data;
+                data.sort_by_key(|embedding| embedding.index);
+                Ok(data.into_iter().map(|embedding| embedding.embedding).collect())
+            }
+        }
+    }
+
+    /// Embeds the questions of items that don't have an embedding yet.
+    pub async fn embed_items(&self, items: &mut [ProcessedItem]) -> Result<(), Error> {
+        let missing: Vec<usize> = (0..items.len()).filter(|&i| items[i].embedding.is_none()).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        info!("Embedding {} questions with {}", missing.len(), self.model);
+        let questions: Vec<&str> = missing.iter().map(|&i| items[i].question.as_str()).collect();
+        let embeddings = self.embed(&questions).await?;
+        for (i, embedding) in missing.into_iter().zip(embeddings) {
+            items[i].embedding = Some(embedding);
+        }
+        Ok(())
+    }
+}
+
+/// Embeds the file's sections into `FileWork::chunks`.
+pub struct EmbedStage {
+    embedder: Embedder,
+}
+
+impl EmbedStage {
+    pub fn new(embedder: Embedder) -> Self {
+        Self { embedder }
+    }
+}
+
+#[async_trait]
+impl Stage for EmbedStage {
+    fn name(&self) -> &'static str {
+        "embed"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        let texts: Vec<&str> = work.sections.iter().map(|section| section.text.as_str()).collect();
+        // The chunk table is a by-product; a failed request must not cost the file its items
+        let embeddings = match self.embedder.embed(&texts).await {
+            Ok(embeddings) => embeddings,
+            Err(e) => {
+                warn!("Failed to embed the sections of {:?}, leaving them out of the chunk table: {}", work.path, e);
+                return Ok(());
+            }
+        };
+        debug!("Embedded {} sections of {:?}", embeddings.len(), work.path);
+        let file = work.relative_path.display().to_string();
+        work.chunks = work
+            .sections
+            .iter()
+            .zip(embeddings)
+            .map(|(section, embedding)| ChunkRecord {
+                file: file.clone(),
+                section: section.index,
+                id: section.id(),
+                text: section.text.clone(),
+                embedding,
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use axum::{Json, Router, routing::post};
+    use serde_json::Value;
+    use crate::pipeline::Section;
+
+    /// An Ollama embedding endpoint embedding each text as `[batch size, length]`, and the
+    /// batch sizes it was sent.
+    async fn ollama() -> (String, Arc<Mutex<Vec<usize>>>) {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let seen = batches.clone();
+        let embed = move |Json(request): Json<Value>| async move {
+            let input = request["input"].as_array().cloned().unwrap_or_default();
+            seen.lock().unwrap().push(input.len());
+            let embeddings: Vec<Value> = input
+                .iter()
+                .map(|text| json!([input.len(), text.as_str().unwrap_or("").len()]))
+                .collect();
+            Json(json!({ "embeddings": embeddings }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, Router::new().route("/api/embed", post(embed))).await });
+        (endpoint, batches)
+    }
+
+    fn embedder(endpoint: &str, batch_size: usize) -> Embedder {
+        let config = EmbeddingsConfig {
+            model: Some("nomic-embed-text".to_string()),
+            batch_size,
+            ..EmbeddingsConfig::default()
+        };
+        Embedder::new(&config, endpoint).unwrap()
+    }
+
+    fn item(question: &str, answer: &str) -> ProcessedItem {
+        serde_json::from_value(json!({ "question": question, "answer": answer })).unwrap()
+    }
+
+    fn section(index: usize, text: &str) -> Section {
+        Section { index, text: text.to_string(), target: 1, release: None }
+    }
+
+    #[test]
+    fn embedders_need_a_model() {
+        assert!(Embedder::new(&EmbeddingsConfig::default(), "http://localhost:11434").is_none());
+        let config = EmbeddingsConfig {
+            model: Some("nomic-embed-text".to_string()),
+            endpoint: Some("http://embed:8080/".to_string()),
+            batch_size: 0,
+            ..EmbeddingsConfig::default()
+        };
+        let embedder = Embedder::new(&config, "http://localhost:11434").unwrap();
+        assert_eq!((embedder.model(), embedder.endpoint.as_str(), embedder.batch_size), ("nomic-embed-text", "http://embed:8080", 1));
+    }
+
+    #[tokio::test]
+    async fn items_without_an_embedding_get_one() {
+        let (endpoint, batches) = ollama().await;
+        let mut items = vec![item("Is it free?", "Yes."), item("How do I export it?", "Run export.")];
+        items[0].embedding = Some(vec![0.5]);
+        embedder(&endpoint, 8).embed_items(&mut items).await.unwrap();
+        assert_eq!(items[0].embedding, Some(vec![0.5]));
+        assert_eq!(items[1].embedding, Some(vec![1.0, 19.0]));
+
+        // Nothing left to embed, nothing sent
+        embedder(&endpoint, 8).embed_items(&mut items).await.unwrap();
+        assert_eq!(*batches.lock().unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn sections_are_embedded_in_batches() {
+        let (endpoint, batches) = ollama().await;
+        let mut work = FileWork::new(Path::new("out/docs/guide.md"), Path::new("out"));
+        work.sections = vec![section(0, "Run export."), section(1, "Run import."), section(2, "Reset it.")];
+        EmbedStage::new(embedder(&endpoint, 2)).process(&mut work).await.unwrap();
+
+        assert_eq!(*batches.lock().unwrap(), [2, 1]);
+        let chunks: Vec<(&str, usize, &str, &[f32])> = work
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.file.as_str(), chunk.section, chunk.text.as_str(), chunk.embedding.as_slice()))
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                ("docs/guide.md", 0, "Run export.", [2.0, 11.0].as_slice()),
+                ("docs/guide.md", 1, "Run import.", [2.0, 11.0].as_slice()),
+                ("docs/guide.md", 2, "Reset it.", [1.0, 9.0].as_slice()),
+            ]
+        );
+        assert_eq!(work.chunks[0].id, work.sections[0].id());
+    }
+
+    #[tokio::test]
+    async fn failed_requests_leave_the_file_alone() {
+        let mut work = FileWork::new(Path::new("guide.md"), Path::new(""));
+        work.sections = vec![section(0, "Run export.")];
+        EmbedStage::new(embedder("http://127.0.0.1:1", 8)).process(&mut work).await.unwrap();
+        assert!(work.chunks.is_empty());
+        assert!(work.error.is_none());
+    }
+}
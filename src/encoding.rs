@@ -0,0 +1,144 @@
+use std::path::Path;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use crate::compression;
+
+/// Bytes looked at to tell text from binary content and to spot BOM-less UTF-16.
+const SNIFF_LEN: usize = 8192;
+
+/// Share of control characters above which content is treated as binary.
+const MAX_CONTROL_RATIO: f64 = 0.1;
+
+/// What a file turned out to contain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    /// Text transcoded to UTF-8. `encoding` names the source encoding when it wasn't
+    /// UTF-8 already.
+    Text { text: String, encoding: Option<&'static str> },
+    Binary,
+}
+
+/// Reads the file at `path` as text in whatever encoding it uses, decompressing `.gz` and
+/// `.zst` files.
+pub fn read(path: &Path) -> std::io::Result<Content> {
+    Ok(decode(&compression::read(path)?))
+}
+
+/// Decodes `bytes` in the encoding `detect` finds for them.
+pub fn decode(bytes: &[u8]) -> Content {
+    let Some(encoding) = detect(bytes, true) else {
+        return Content::Binary;
+    };
+    let (text, encoding, _) = encoding.decode(bytes);
+    Content::Text {
+        text: text.into_owned(),
+        encoding: (encoding != UTF_8).then(|| encoding.name()),
+    }
+}
+
+/// The encoding of content starting with `bytes`: given by its BOM, UTF-16 when every
+/// other byte is zero, UTF-8 when valid, and otherwise the legacy encoding (Latin-1,
+/// Windows-1252, Shift JIS, ...) it looks most like. `None` for binary content, such as
+/// images or archives. Pass `complete: false` when `bytes` is only the start of a file.
+pub fn detect(bytes: &[u8], complete: bool) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+    if let Some(encoding) = sniff_utf16(bytes) {
+        return Some(encoding);
+    }
+    if is_binary(bytes) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => Some(UTF_8),
+        // A multi-byte character cut off at the end of the sample
+        Err(e) if !complete && e.error_len().is_none() => Some(UTF_8),
+        Err(_) => Some(detect_legacy(bytes, complete)),
+    }
+}
+
+/// UTF-16 without a BOM: nearly all ASCII-range characters, so one byte of each pair is
+/// zero.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN) & !1];
+    let pairs = sample.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let zeros = |offset: usize| sample.iter().skip(offset).step_by(2).filter(|&&b| b == 0).count();
+    let (even, odd) = (zeros(0), zeros(1));
+    if odd * 10 >= pairs * 9 && even == 0 {
+        Some(UTF_16LE)
+    } else if even * 10 >= pairs * 9 && odd == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Binary content has NUL bytes or many other control characters near the start.
+fn is_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    if sample.is_empty() {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    control as f64 / sample.len() as f64 > MAX_CONTROL_RATIO
+}
+
+fn detect_legacy(bytes: &[u8], complete: bool) -> &'static Encoding {
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, complete);
+    detector.guess(None, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    #[test]
+    fn detects_utf8_and_boms() {
+        assert_eq!(detect("Grüße aus Köln".as_bytes(), true), Some(UTF_8));
+        assert_eq!(detect(b"\xEF\xBB\xBFplain", true), Some(UTF_8));
+        assert_eq!(detect(b"\xFF\xFEh\0i\0", true), Some(UTF_16LE));
+        assert_eq!(detect(b"\xFE\xFF\0h\0i", true), Some(UTF_16BE));
+    }
+
+    #[test]
+    fn detects_utf16_without_a_bom() {
+        let le: Vec<u8> = "hello world".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = "hello world".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(detect(&le, true), Some(UTF_16LE));
+        assert_eq!(detect(&be, true), Some(UTF_16BE));
+    }
+
+    #[test]
+    fn detects_legacy_encodings() {
+        let (latin, _, _) = WINDOWS_1252.encode("Der Bär läuft über die Straße, während es regnet.");
+        assert_eq!(detect(&latin, true), Some(WINDOWS_1252));
+        let (japanese, _, _) = SHIFT_JIS.encode("これは日本語のテキストです。設定ファイルを編集してください。");
+        assert_eq!(detect(&japanese, true), Some(SHIFT_JIS));
+    }
+
+    #[test]
+    fn a_character_cut_off_at_the_end_of_a_sample_is_still_utf8() {
+        let text = "naïve".as_bytes();
+        let cut = &text[..3];
+        assert_eq!(detect(cut, false), Some(UTF_8));
+        assert_ne!(detect(cut, true), Some(UTF_8));
+    }
+
+    #[test]
+    fn binary_content_has_no_encoding() {
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", true), None);
+        assert_eq!(decode(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]), Content::Binary);
+    }
+}
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+
+/// Exit codes for runs that failed with a single class of error, after the generic
+/// ones in `report`.
+pub const EXIT_OTHER_ERROR: i32 = 1;
+pub const EXIT_API_ERROR: i32 = 4;
+pub const EXIT_PARSE_ERROR: i32 = 5;
+pub const EXIT_CHUNKING_ERROR: i32 = 6;
+pub const EXIT_IO_ERROR: i32 = 7;
+pub const EXIT_QUOTA_ERROR: i32 = 8;
+pub const EXIT_INTERNAL_ERROR: i32 = 9;
+
+/// Why a file or run failed. Reports and the worker protocol carry it as
+/// `{"kind": "api_error", "message": "..."}`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum Error {
+    /// The model endpoint, a worker or a data source could not be reached or returned
+    /// an error.
+    #[serde(rename = "api_error")]
+    #[error("API error: {0}")]
+    Api(String),
+    /// A file, configuration or model response could not be understood.
+    #[serde(rename = "parse_error")]
+    #[error("parse error: {0}")]
+    Parse(String),
+    /// A file could not be split into sections.
+    #[serde(rename = "chunking_error")]
+    #[error("chunking error: {0}")]
+    Chunking(String),
+    #[serde(rename = "io_error")]
+    #[error("I/O error: {0}")]
+    Io(String),
+    /// The endpoint refused further requests (HTTP 429).
+    #[serde(rename = "quota_error")]
+    #[error("quota exceeded: {0}")]
+    Quota(String),
+    /// A task panicked or was cancelled.
+    #[serde(rename = "internal_error")]
+    #[error("internal error: {0}")]
+    Internal(String),
+    /// Anything that doesn't fit the other classes.
+    #[serde(rename = "other_error")]
+    #[error("{0}")]
+    Other(String),
+}
+
+impl Error {
+    /// The `kind` written to reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Api(_) => "api_error",
+            Error::Parse(_) => "parse_error",
+            Error::Chunking(_) => "chunking_error",
+            Error::Io(_) => "io_error",
+            Error::Quota(_) => "quota_error",
+            Error::Internal(_) => "internal_error",
+            Error::Other(_) => "other_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Api(m)
+            | Error::Parse(m)
+            | Error::Chunking(m)
+            | Error::Io(m)
+            | Error::Quota(m)
+            | Error::Internal(m)
+            | Error::Other(m) => m,
+        }
+    }
+
+    /// Process exit code for a run that failed with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Api(_) => EXIT_API_ERROR,
+            Error::Parse(_) => EXIT_PARSE_ERROR,
+            Error::Chunking(_) => EXIT_CHUNKING_ERROR,
+            Error::Io(_) => EXIT_IO_ERROR,
+            Error::Quota(_) => EXIT_QUOTA_ERROR,
+            Error::Internal(_) => EXIT_INTERNAL_ERROR,
+            Error::Other(_) => EXIT_OTHER_ERROR,
+        }
+    }
+
+    /// The same error with `context` in front of its message.
+    pub fn context(self, context: &str) -> Self {
+        let message = format!("{}: {}", context, self.message());
+        self.with_message(message)
+    }
+
+    /// An error of the same kind with another message.
+    fn with_message(&self, message: String) -> Self {
+        match self {
+            Error::Api(_) => Error::Api(message),
+            Error::Parse(_) => Error::Parse(message),
+            Error::Chunking(_) => Error::Chunking(message),
+            Error::Io(_) => Error::Io(message),
+            Error::Quota(_) => Error::Quota(message),
+            Error::Internal(_) => Error::Internal(message),
+            Error::Other(_) => Error::Other(message),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(reqwest::StatusCode::TOO_MANY_REQUESTS) => Error::Quota(e.to_string()),
+            _ => Error::Api(e.to_string()),
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Error::Internal(e.to_string())
+    }
+}
+
+/// Classifies an error from code that doesn't return this type directly, by the first
+/// `Error`, I/O, HTTP, JSON or task error in its chain; anything else is `Other`.
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let mut class = None;
+        let mut parts = Vec::new();
+        for cause in e.chain() {
+            if let Some(error) = cause.downcast_ref::<Error>() {
+                class.get_or_insert_with(|| error.clone());
+                parts.push(error.message().to_string());
+                continue;
+            }
+            if class.is_none() {
+                if cause.is::<std::io::Error>() {
+                    class = Some(Error::Io(String::new()));
+                } else if cause.is::<serde_json::Error>() {
+                    class = Some(Error::Parse(String::new()));
+                } else if cause.is::<tokio::task::JoinError>() {
+                    class = Some(Error::Internal(String::new()));
+                } else if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+                    class = Some(match error.status() {
+                        Some(reqwest::StatusCode::TOO_MANY_REQUESTS) => Error::Quota(String::new()),
+                        _ => Error::Api(String::new()),
+                    });
+                }
+            }
+            // Some errors already include their sources in their message
+            let part = cause.to_string();
+            if !parts.last().is_some_and(|last: &String| last.contains(&part)) {
+                parts.push(part);
+            }
+        }
+        class.unwrap_or(Error::Other(String::new())).with_message(parts.join(": "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn unclassified_errors_are_other() {
+        let error = Error::from(anyhow!("something unexpected"));
+        assert_eq!(error, Error::Other("something unexpected".to_string()));
+        assert_eq!(error.exit_code(), EXIT_OTHER_ERROR);
+    }
+
+    #[test]
+    fn errors_are_classified_by_their_chain() {
+        let io = Err::<(), _>(std::io::Error::new(std::io::ErrorKind::NotFound, "missing")).context("reading docs");
+        assert_eq!(Error::from(io.unwrap_err()), Error::Io("reading docs: missing".to_string()));
+
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(Error::from(anyhow::Error::from(json)).kind(), "parse_error");
+
+        let chunking = anyhow::Error::from(Error::Chunking("no sections".to_string())).context("a.md");
+        assert_eq!(Error::from(chunking), Error::Chunking("a.md: no sections".to_string()));
+    }
+
+    #[tokio::test]
+    async fn panicked_tasks_are_internal_errors() {
+        let join = tokio::spawn(async { panic!("boom") }).await.unwrap_err();
+        let error = Error::from(join);
+        assert_eq!(error.kind(), "internal_error");
+        assert_eq!(error.exit_code(), EXIT_INTERNAL_ERROR);
+
+        let join = tokio::spawn(async { panic!("boom") }).await.unwrap_err();
+        assert_eq!(Error::from(anyhow::Error::from(join)).kind(), "internal_error");
+    }
+}
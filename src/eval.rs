@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+use crate::config::Config;
+use crate::dataset;
+use crate::metrics::Metrics;
+use crate::preflight;
+use crate::processor::OllamaProcessor;
+use crate::score::Scorer;
+
+/// How the evaluated model answered one held-out question.
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalResult {
+    pub question: String,
+    pub reference: String,
+    /// `None` when the request failed.
+    pub response: Option<String>,
+    pub exact_match: bool,
+    pub f1: f64,
+    /// The judge's verdict, when a judge was given and could grade the response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correct: Option<bool>,
+}
+
+/// Accuracy of a model on a test split, with every response.
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub model: String,
+    pub items: usize,
+    /// Questions the model couldn't be asked; they count as wrong.
+    pub failed_requests: usize,
+    /// Share of responses equal to the reference after normalization.
+    pub exact_match: f64,
+    /// Mean token-level F1 against the reference.
+    pub f1: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub judge: Option<String>,
+    /// Share of responses the judge graded correct; ungraded ones count as wrong.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub judge_accuracy: Option<f64>,
+    pub results: Vec<EvalResult>,
+}
+
+impl EvalReport {
+    pub fn print(&self) {
+        println!("{} on {} held-out items", self.model, self.items);
+        println!("  exact match:     {:.1}%", self.exact_match * 100.0);
+        println!("  token F1:        {:.3}", self.f1);
+        if let (Some(judge), Some(accuracy)) = (&self.judge, self.judge_accuracy) {
+            println!("  judge accuracy:  {:.1}% (graded by {})", accuracy * 100.0, judge);
+        }
+        if self.failed_requests > 0 {
+            println!("  failed requests: {}", self.failed_requests);
+        }
+    }
+}
+
+/// Asks `model` at `endpoint` every question of the test split at `input` and compares
+/// its responses with the items' answers: exact match and token F1, plus a verdict of
+/// the `judge` model (served at the configured endpoint) when one is given.
+pub async fn evaluate(
+    config: &Config,
+    input: &Path,
+    model: &str,
+    endpoint: &str,
+    system: Option<&str>,
+    judge: Option<&str>,
+) -> Result<EvalReport> {
+    let items = dataset::read_items(input)?;
+    if items.is_empty() {
+        return Err(anyhow!("{:?} has no items to evaluate on", input));
+    }
+    if config.preflight {
+        preflight::check_models(endpoint, [model]).await?;
+        if let Some(judge) = judge {
+            preflight::check_models(&config.endpoint, [judge]).await?;
+        }
+    }
+    // The model under test is only served at `endpoint`, so there is nothing to fail over to
+    let target = Config {
+        endpoint: endpoint.to_string(),
+        backup_endpoints: Vec::new(),
+        ..config.clone()
+    };
+    let processor = OllamaProcessor::new(Arc::new(target), Metrics::global());
+    let scorer = judge.map(|judge| Scorer::new(config).with_model(judge));
+    info!("Evaluating {} on {} items from {:?}", model, items.len(), input);
+
+    let mut results = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let response = match ask(&processor, input, model, system, &item.question, config.seed).await {
+            Ok(response) => Some(response),
+            Err(e) => {
+                warn!("Failed to ask item {}: {}", i + 1, e);
+                None
+            }
+        };
+        let mut correct = None;
+        if let (Some(scorer), Some(response)) = (&scorer, &response) {
+            match scorer.grade(input, &item.question, &item.answer, response).await {
+                Ok(verdict) => correct = Some(verdict),
+                Err(e) => warn!("Failed to grade item {}: {}", i + 1, e),
+            }
+        }
+        let result = EvalResult {
+            question: item.question.clone(),
+            reference: item.answer.clone(),
+            exact_match: response.as_deref().is_some_and(|response| normalize(response) == normalize(&item.answer)),
+            f1: response.as_deref().map_or(0.0, |response| f1(response, &item.answer)),
+            response,
+            correct,
+        };
+        debug!("Item {}/{}: exact {}, F1 {:.2}, correct {:?}", i + 1, items.len(), result.exact_match, result.f1, result.correct);
+        results.push(result);
+    }
+
+    let total = results.len() as f64;
+    Ok(EvalReport {
+        model: model.to_string(),
+        items: results.len(),
+        failed_requests: results.iter().filter(|result| result.response.is_none()).count(),
+        exact_match: results.iter().filter(|result| result.exact_match).count() as f64 / total,
+        f1: results.iter().map(|result| result.f1).sum::<f64>() / total,
+        judge: judge.map(str::to_string),
+        judge_accuracy: judge.map(|_| results.iter().filter(|result| result.correct == Some(true)).count() as f64 / total),
+        results,
+    })
+}
+
+/// The model's answer to a question of the test split `input`, sampled greedily so runs
+/// are comparable.
+async fn ask(processor: &OllamaProcessor, input: &Path, model: &str, system: Option<&str>, question: &str, seed: Option<u64>) -> Result<String> {
+    let mut messages = Vec::new();
+    if let Some(system) = system {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    messages.push(json!({ "role": "user", "content": question }));
+    let mut options = json!({ "temperature": 0 });
+    if let Some(seed) = seed {
+        options["seed"] = json!(seed);
+    }
+    Ok(processor.chat(input, model, json!(messages), None, options).await?.trim().to_string())
+}
+
+/// Lowercase words without punctuation or articles, as in SQuAD scoring.
+fn normalize(text: &str) -> String {
+    tokens(text).join(" ")
+}
+
+fn tokens(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| c.is_whitespace() || (c.is_ascii_punctuation() && c != '\''))
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty() && !matches!(*word, "a" | "an" | "the"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Harmonic mean of the precision and recall of the response's words against the
+/// reference's, counting repeated words as often as they appear in both.
+fn f1(response: &str, reference: &str) -> f64 {
+    let (response, reference) = (tokens(response), tokens(reference));
+    if response.is_empty() || reference.is_empty() {
+        return if response == reference { 1.0 } else { 0.0 };
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for word in &reference {
+        *counts.entry(word).or_default() += 1;
+    }
+    let mut common = 0;
+    for word in &response {
+        if let Some(count) = counts.get_mut(word.as_str()).filter(|count| **count > 0) {
+            *count -= 1;
+            common += 1;
+        }
+    }
+    if common == 0 {
+        return 0.0;
+    }
+    let precision = common as f64 / response.len() as f64;
+    let recall = common as f64 / reference.len() as f64;
+    2.0 * precision * recall / (precision + recall)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_like_squad() {
+        assert_eq!(normalize("The  Answer, is: 'yes'!"), "answer is yes");
+        assert_eq!(normalize("An apple a day"), "apple day");
+        assert_eq!(normalize("don't"), "don't");
+        assert_eq!(normalize("..."), "");
+    }
+
+    #[test]
+    fn f1_counts_shared_words() {
+        assert_eq!(f1("Run the binary.", "run binary"), 1.0);
+        assert_eq!(f1("sunny", "rainy"), 0.0);
+        // 2 of 3 response words are in the 4-word reference
+        let score = f1("press ctrl c", "press ctrl d twice");
+        assert!((score - 2.0 * (2.0 / 3.0) * 0.5 / (2.0 / 3.0 + 0.5)).abs() < 1e-9);
+        // Repeated words count as often as they appear in both
+        assert!((f1("yes yes", "yes no") - 0.5).abs() < 1e-9);
+        assert_eq!(f1("", ""), 1.0);
+        assert_eq!(f1("the", "something"), 0.0);
+    }
+}
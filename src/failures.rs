@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use serde::Serialize;
+
+/// One unsuccessful attempt at getting parseable output from the model.
+#[derive(Debug, Serialize)]
+pub struct FailedAttempt {
+    pub attempt: usize,
+    pub error: String,
+    pub raw_response: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sanitized_response: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FailureRecord<'a> {
+    source_file: &'a Path,
+    model: &'a str,
+    system_prompt: &'a str,
+    user_prompt: &'a str,
+    attempts: &'a [FailedAttempt],
+}
+
+/// Writes one JSON file per section whose model output could never be parsed, so the
+/// raw responses can be inspected after the run instead of scrolling out of the log.
+pub struct FailureArchive {
+    dir: PathBuf,
+    counter: AtomicUsize,
+}
+
+impl FailureArchive {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            counter: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record(
+        &self,
+        source_file: &Path,
+        model: &str,
+        system_prompt: &str,
+        user_prompt: &str,
+        attempts: &[FailedAttempt],
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+
+        let stem = source_file
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown");
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let index = self.counter.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}_{}_{}.json", stem, timestamp, index));
+
+        let record = FailureRecord {
+            source_file,
+            model,
+            system_prompt,
+            user_prompt,
+            attempts,
+        };
+        fs::write(&path, serde_json::to_string_pretty(&record)?)?;
+        Ok(path)
+    }
+}
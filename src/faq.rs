@@ -0,0 +1,246 @@
+use std::sync::LazyLock;
+use regex::Regex;
+use crate::config::FaqConfig;
+use crate::frontmatter;
+use crate::processor::{ItemSource, ProcessedItem};
+use crate::stream::heading_level;
+
+/// Share of a file's words its pairs must hold for it to be read as a FAQ, so a guide
+/// with a few headings phrased as questions is still generated from.
+const MIN_COVERAGE: f64 = 0.5;
+
+/// `Q:`, `**Question:**` and the like, with the question after it.
+static QUESTION_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:\*\*|__)?(?:Q|Question)\s*\d*\s*[:.)](?:\*\*|__)?\s*(.*)$").unwrap()
+});
+
+static ANSWER_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:\*\*|__)?(?:A|Answer)\s*[:.)](?:\*\*|__)?\s*(.*)$").unwrap()
+});
+
+/// A line that is only a question in bold: `**How do I ...?**`.
+static BOLD_QUESTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*(?:\*\*|__)(.+\?)(?:\*\*|__)\s*$").unwrap());
+
+static SUMMARY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^\s*(?:<details[^>]*>\s*)?<summary>(.*?)</summary>\s*(.*)$").unwrap());
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"!?\[([^\]]*)\]\([^)]*\)").unwrap());
+
+static EMPHASIS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\*\*(\S(?:.*?\S)?)\*\*|__(\S(?:.*?\S)?)__|\*(\S(?:[^*\n]*?\S)?)\*").unwrap()
+});
+
+/// How the question of a pair was marked.
+#[derive(Clone, Copy, PartialEq)]
+enum Marker {
+    Heading,
+    /// `Q:` lines, which continue until the `A:` line.
+    Prefix,
+    Bold,
+    Details,
+}
+
+struct Pair {
+    marker: Marker,
+    question: String,
+    /// Whether the answer started: always for markers other than `Q:`.
+    answering: bool,
+    answer: Vec<String>,
+}
+
+impl Pair {
+    fn new(marker: Marker, question: &str) -> Self {
+        Self {
+            marker,
+            question: question.trim().to_string(),
+            answering: marker != Marker::Prefix,
+            answer: Vec::new(),
+        }
+    }
+
+    /// Adds `pair` to `pairs` if it has a question and an answer.
+    fn finish(pair: Option<Self>, pairs: &mut Vec<(String, String)>) {
+        let Some(pair) = pair else {
+            return;
+        };
+        let answer = pair.answer.join("\n").trim().to_string();
+        if !pair.question.is_empty() && !answer.is_empty() {
+            pairs.push((pair.question, answer));
+        }
+    }
+}
+
+/// The question and answer pairs of `text` as written: headings ending in `?` with the
+/// text below them, `Q:`/`A:` lines, bold questions on a line of their own, and
+/// `<details>` blocks with the question as their `<summary>`.
+fn pairs(text: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut current: Option<Pair> = None;
+    let mut fence = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = !fence;
+        }
+        if fence || trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            if let Some(pair) = current.as_mut().filter(|pair| pair.answering) {
+                pair.answer.push(line.to_string());
+            }
+            continue;
+        }
+        if let Some(level) = heading_level(line) {
+            Pair::finish(current.take(), &mut pairs);
+            let heading = trimmed[level..].trim().trim_end_matches('#').trim();
+            if heading.ends_with('?') {
+                current = Some(Pair::new(Marker::Heading, heading));
+            }
+        } else if let Some(captures) = QUESTION_PREFIX.captures(line) {
+            Pair::finish(current.take(), &mut pairs);
+            current = Some(Pair::new(Marker::Prefix, &captures[1]));
+        } else if let Some(captures) = ANSWER_PREFIX.captures(line).filter(|_| current.as_ref().is_some_and(|pair| !pair.answering)) {
+            let pair = current.as_mut().unwrap();
+            pair.answering = true;
+            pair.answer.push(captures[1].to_string());
+        } else if let Some(captures) = BOLD_QUESTION.captures(line).filter(|_| current.as_ref().is_none_or(|pair| pair.marker != Marker::Heading)) {
+            Pair::finish(current.take(), &mut pairs);
+            current = Some(Pair::new(Marker::Bold, &captures[1]));
+        } else if let Some(captures) = SUMMARY.captures(line) {
+            Pair::finish(current.take(), &mut pairs);
+            let mut pair = Pair::new(Marker::Details, &captures[1]);
+            pair.answer.push(captures[2].to_string());
+            current = Some(pair);
+        } else if trimmed.eq_ignore_ascii_case("</details>") {
+            Pair::finish(current.take(), &mut pairs);
+        } else if let Some(pair) = current.as_mut() {
+            match pair.answering {
+                true => pair.answer.push(line.trim_end().trim_end_matches("</details>").to_string()),
+                false if !trimmed.is_empty() => {
+                    pair.question.push(' ');
+                    pair.question.push_str(trimmed);
+                }
+                false => {}
+            }
+        }
+    }
+    Pair::finish(current, &mut pairs);
+    pairs
+}
+
+/// `text` with links reduced to their text, emphasis markers removed and paragraphs
+/// joined on one line each. Code blocks are left as they are.
+fn clean(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| {
+            if paragraph.contains("```") || paragraph.contains("~~~") {
+                return paragraph.trim().to_string();
+            }
+            let paragraph = LINK.replace_all(paragraph, "$1");
+            let paragraph = EMPHASIS.replace_all(&paragraph, "${1}${2}${3}");
+            match paragraph.trim_start().starts_with(['-', '*', '|', '>']) {
+                // Lists, tables and quotes keep their lines
+                true => paragraph.trim().to_string(),
+                false => paragraph.split_whitespace().collect::<Vec<_>>().join(" "),
+            }
+        })
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// The question and answer pairs of `content` as items of `file`, verbatim or with
+/// `faq.cleanup` lightly cleaned, numbered as sections. Empty unless there are at least
+/// `faq.min_pairs` of them and they hold most of the content, so only FAQ-style files
+/// are read this way.
+pub fn items(content: &str, file: &str, config: &FaqConfig) -> Vec<ProcessedItem> {
+    let (_, body) = frontmatter::parse(content);
+    let pairs = pairs(body);
+    let words = |text: &str| text.split_whitespace().count();
+    let covered: usize = pairs.iter().map(|(question, answer)| words(question) + words(answer)).sum();
+    if pairs.len() < config.min_pairs.max(1) || (covered as f64) < words(body) as f64 * MIN_COVERAGE {
+        return Vec::new();
+    }
+    pairs
+        .into_iter()
+        .enumerate()
+        .map(|(index, (question, answer))| ProcessedItem {
+            id: None,
+            question: match config.cleanup {
+                true => clean(&question),
+                false => question,
+            },
+            answer: match config.cleanup {
+                true => clean(&answer),
+                false => answer,
+            },
+            citation: None,
+            source: Some(ItemSource {
+                file: file.to_string(),
+                section: index,
+                chunk: None,
+                image: None,
+                modified: None,
+            }),
+            hash: None,
+            extra: serde_json::Map::new(),
+            embedding: None,
+            source_overlap: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAQ: &str = "---\ntitle: FAQ\n---\n# FAQ\n\n\
+        ## Is it free?\n\nYes, under the [MIT license](LICENSE).\n\n\
+        Q: Which platforms\nare supported?\nA: Linux and **macOS**.\n\n\
+        **Can I run it offline?**\n\nYes, with a local model:\n\n```sh\nllmds --offline\n```\n\n\
+        <details><summary>Where are the logs?</summary>\nIn `~/.llmds/logs`.\n</details>\n";
+
+    fn config(cleanup: bool) -> FaqConfig {
+        FaqConfig { extract: true, min_pairs: 3, cleanup }
+    }
+
+    fn qa(items: &[ProcessedItem]) -> Vec<(&str, &str)> {
+        items.iter().map(|item| (item.question.as_str(), item.answer.as_str())).collect()
+    }
+
+    #[test]
+    fn pairs_are_read_as_written() {
+        assert_eq!(
+            pairs(frontmatter::parse(FAQ).1),
+            [
+                ("Is it free?".to_string(), "Yes, under the [MIT license](LICENSE).".to_string()),
+                ("Which platforms are supported?".to_string(), "Linux and **macOS**.".to_string()),
+                ("Can I run it offline?".to_string(), "Yes, with a local model:\n\n```sh\nllmds --offline\n```".to_string()),
+                ("Where are the logs?".to_string(), "In `~/.llmds/logs`.".to_string()),
+            ]
+        );
+        // Unanswered questions and plain headings aren't pairs
+        assert_eq!(pairs("## Why?\n\n## Install\n\nRun it.\n"), Vec::<(String, String)>::new());
+    }
+
+    #[test]
+    fn faqs_become_items_of_their_sections() {
+        let items = items(FAQ, "docs/faq.md", &config(true));
+        assert_eq!(
+            qa(&items),
+            [
+                ("Is it free?", "Yes, under the MIT license."),
+                ("Which platforms are supported?", "Linux and macOS."),
+                ("Can I run it offline?", "Yes, with a local model:\n\n```sh\nllmds --offline\n```"),
+                ("Where are the logs?", "In `~/.llmds/logs`."),
+            ]
+        );
+        let source = items[3].source.as_ref().unwrap();
+        assert_eq!((source.file.as_str(), source.section), ("docs/faq.md", 3));
+        assert_eq!(super::items(FAQ, "docs/faq.md", &config(false))[0].answer, "Yes, under the [MIT license](LICENSE).");
+    }
+
+    #[test]
+    fn guides_with_a_few_questions_are_left_alone() {
+        let guide = format!("# Guide\n\n## Why?\n\nBecause.\n\n## Install\n\n{}\n", "Run the installer and follow it. ".repeat(20));
+        assert!(items(&guide, "guide.md", &FaqConfig { min_pairs: 1, ..config(false) }).is_empty());
+        assert!(items("## Is it free?\n\nYes.\n", "faq.md", &config(false)).is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use anyhow::Result;
+use regex::Regex;
+use serde::Deserialize;
+use crate::answer_length::{self, AnswerLength};
+use crate::config::{FilterConfig, SteeringConfig};
+use crate::processor::ProcessedItem;
+use crate::sql_schema::TEXT_TO_SQL_FIELD;
+
+/// Post-generation checks that drop low-quality or unwanted items.
+pub struct ItemFilter {
+    min_question_words: usize,
+    min_answer_words: usize,
+    exclude_patterns: Vec<Regex>,
+    keywords: Vec<(String, Regex)>,
+    excluded_topics: Vec<Regex>,
+    require_keyword: bool,
+    require_citation: bool,
+    max_source_overlap: Option<f64>,
+}
+
+/// Shortest run of words shared with the source that counts as copied.
+const COPIED_RUN_WORDS: usize = 5;
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Share (0-1) of the words of `answer` that are part of a run of at least
+/// `COPIED_RUN_WORDS` words appearing in the same order in `source`, ignoring case and
+/// punctuation. Answers shorter than such a run count as not copied.
+pub fn source_overlap(answer: &str, source: &str) -> f64 {
+    let answer = words(answer);
+    let source = words(source);
+    if answer.len() < COPIED_RUN_WORDS {
+        return 0.0;
+    }
+    let runs: HashSet<&[String]> = source.windows(COPIED_RUN_WORDS).collect();
+    let mut copied = vec![false; answer.len()];
+    for (start, run) in answer.windows(COPIED_RUN_WORDS).enumerate() {
+        if runs.contains(run) {
+            copied[start..start + COPIED_RUN_WORDS].fill(true);
+        }
+    }
+    copied.iter().filter(|copied| **copied).count() as f64 / answer.len() as f64
+}
+
+/// Whether `quote` appears in `text`, ignoring differences in whitespace, typographic
+/// quotes, and the quotation marks or ellipses models tend to wrap quotes in.
+pub fn quote_appears_in(quote: &str, text: &str) -> bool {
+    let quote = normalize_quote(quote);
+    let quote = quote.trim_matches(|c: char| matches!(c, '"' | '\'' | '.' | '…' | ' '));
+    !quote.is_empty() && normalize_quote(text).contains(quote)
+}
+
+fn normalize_quote(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(['“', '”'], "\"")
+        .replace(['‘', '’'], "'")
+}
+
+/// Case-insensitive whole-word match for a keyword or topic.
+fn term_regex(term: &str) -> Result<Regex> {
+    Ok(Regex::new(&format!(r"(?i)\b{}\b", regex::escape(term)))?)
+}
+
+impl ItemFilter {
+    pub fn new(config: &FilterConfig, steering: &SteeringConfig) -> Result<Self> {
+        let exclude_patterns = config
+            .exclude_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        let keywords = steering
+            .keywords
+            .iter()
+            .map(|keyword| Ok((keyword.clone(), term_regex(keyword)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let excluded_topics = steering
+            .exclude_topics
+            .iter()
+            .map(|topic| term_regex(topic))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            min_question_words: config.min_question_words,
+            min_answer_words: config.min_answer_words,
+            exclude_patterns,
+            keywords,
+            excluded_topics,
+            require_keyword: steering.require_keyword,
+            require_citation: false,
+            max_source_overlap: config.max_source_overlap,
+        })
+    }
+
+    /// Also drops items without a (verified) citation.
+    pub fn with_required_citation(mut self, required: bool) -> Self {
+        self.require_citation = required;
+        self
+    }
+
+    pub fn accepts(&self, item: &ProcessedItem) -> bool {
+        if item.question.split_whitespace().count() < self.min_question_words
+            || item.answer.split_whitespace().count() < self.min_answer_words
+        {
+            return false;
+        }
+        // Text-to-SQL answers are queries, with nothing to quote
+        if self.require_citation && item.citation.is_none() && !item.extra.contains_key(TEXT_TO_SQL_FIELD) {
+            return false;
+        }
+        // Tagged with the preset the answer was generated and asked again for
+        if let Some(length) = item.extra.get(answer_length::FIELD).and_then(|value| AnswerLength::deserialize(value).ok()) {
+            if !length.fits(&item.answer) {
+                return false;
+            }
+        }
+        if let (Some(max), Some(overlap)) = (self.max_source_overlap, item.source_overlap) {
+            if overlap > max {
+                return false;
+            }
+        }
+
+        let mentions = |re: &Regex| re.is_match(&item.question) || re.is_match(&item.answer);
+
+        if self.exclude_patterns.iter().any(mentions) || self.excluded_topics.iter().any(mentions) {
+            return false;
+        }
+
+        !self.require_keyword || self.keywords.is_empty() || self.keywords.iter().any(|(_, re)| mentions(re))
+    }
+
+    /// Keywords that appear in `source` but in none of `items`.
+    pub fn uncovered_keywords(&self, source: &str, items: &[ProcessedItem]) -> Vec<&str> {
+        self.keywords
+            .iter()
+            .filter(|(_, re)| re.is_match(source))
+            .filter(|(_, re)| !items.iter().any(|item| re.is_match(&item.question) || re.is_match(&item.answer)))
+            .map(|(keyword, _)| keyword.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "Run the builder with --config to read options from a file. Options given on the command line win.";
+
+    #[test]
+    fn source_overlap_counts_copied_runs() {
+        // Every word is in a copied run of five or more
+        assert_eq!(source_overlap("Run the builder with --config.", SOURCE), 1.0);
+        // Five of ten words, ignoring case and punctuation
+        assert_eq!(source_overlap("READ OPTIONS, from a file; it is quick and easy.", SOURCE), 0.5);
+        // Shared words out of order don't count
+        assert_eq!(source_overlap("The file options read from a builder run.", SOURCE), 0.0);
+    }
+
+    #[test]
+    fn short_answers_are_not_copied() {
+        assert_eq!(source_overlap("Run the builder.", SOURCE), 0.0);
+        assert_eq!(source_overlap("", SOURCE), 0.0);
+    }
+}
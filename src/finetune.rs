@@ -0,0 +1,292 @@
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+use crate::compression;
+use crate::dataset::{self, RecordFormat};
+
+/// Fewest training examples OpenAI accepts for a job.
+const MIN_EXAMPLES: usize = 10;
+
+/// Problems listed when a dataset fails validation; the rest are only counted.
+const MAX_REPORTED_PROBLEMS: usize = 10;
+
+/// How often a running job is checked while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Consecutive failed polls after which `wait` gives up; the wait before each retry
+/// doubles, from `POLL_INTERVAL`.
+const MAX_POLL_FAILURES: u32 = 5;
+
+/// Services fine-tuning jobs can be submitted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FinetuneProvider {
+    /// OpenAI, or any service implementing its files and fine-tuning jobs API
+    Openai,
+}
+
+impl FinetuneProvider {
+    pub fn default_url(self) -> &'static str {
+        match self {
+            FinetuneProvider::Openai => "https://api.openai.com/v1",
+        }
+    }
+}
+
+/// What to train on top of which model.
+#[derive(Debug, Clone, Default)]
+pub struct FinetuneRequest {
+    pub base_model: String,
+    /// Added to the fine-tuned model's name.
+    pub suffix: Option<String>,
+    pub epochs: Option<u32>,
+}
+
+/// A fine-tuning job as reported by the provider.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FinetuneJob {
+    pub id: String,
+    /// `validating_files`, `queued`, `running`, `succeeded`, `failed` or `cancelled`.
+    pub status: String,
+    pub model: String,
+    /// Name of the trained model, once the job succeeded.
+    #[serde(default)]
+    pub fine_tuned_model: Option<String>,
+    #[serde(default)]
+    pub trained_tokens: Option<u64>,
+    #[serde(default)]
+    pub error: Option<Value>,
+}
+
+impl FinetuneJob {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), "succeeded" | "failed" | "cancelled")
+    }
+
+    /// The provider's explanation of a failed job.
+    pub fn error_message(&self) -> Option<&str> {
+        self.error.as_ref()?.get("message")?.as_str()
+    }
+}
+
+/// The dataset at `path` as JSONL chat records ready for upload: records already in the
+/// OpenAI `messages` format are kept as they are, question/answer items are converted
+/// (after a `system` turn when given). Fails listing the problems when any record is
+/// malformed or there are too few of them. Returns the file's content and its number of
+/// records.
+pub fn training_file(path: &Path, system: Option<&str>) -> Result<(Vec<u8>, usize)> {
+    let content = compression::read_to_string(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+    let is_chat = content
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .and_then(|line| serde_json::from_str::<Value>(line).ok())
+        .is_some_and(|record| record.get("messages").is_some());
+    let mut problems = Vec::new();
+    // Numbered from 1, skipping blank lines, like records are counted in messages
+    let records: Vec<(usize, Value)> = if is_chat {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .filter_map(|(i, line)| match serde_json::from_str(line) {
+                Ok(record) => Some((i + 1, record)),
+                Err(e) => {
+                    problems.push((i + 1, format!("invalid JSON: {}", e)));
+                    None
+                }
+            })
+            .collect()
+    } else {
+        dataset::read_items(path)?
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                Ok((i + 1, RecordFormat::Openai.to_record(item, system)?))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    for (number, record) in &records {
+        problems.extend(RecordFormat::Openai.check(record, &[]).into_iter().map(|problem| (*number, problem)));
+    }
+    problems.sort_by_key(|(number, _)| *number);
+    if !problems.is_empty() {
+        let mut message = format!("{:?} has {} problems:", path, problems.len());
+        for (number, problem) in problems.iter().take(MAX_REPORTED_PROBLEMS) {
+            message.push_str(&format!("\n  record {}: {}", number, problem));
+        }
+        if problems.len() > MAX_REPORTED_PROBLEMS {
+            message.push_str(&format!("\n  ... and {} more", problems.len() - MAX_REPORTED_PROBLEMS));
+        }
+        return Err(anyhow!(message));
+    }
+    if records.len() < MIN_EXAMPLES {
+        return Err(anyhow!("{:?} has {} records; fine-tuning needs at least {}", path, records.len(), MIN_EXAMPLES));
+    }
+
+    let mut file = Vec::new();
+    for (_, record) in &records {
+        serde_json::to_writer(&mut file, record)?;
+        file.push(b'\n');
+    }
+    Ok((file, records.len()))
+}
+
+/// Connection to a fine-tuning provider's API.
+pub struct FinetuneClient {
+    provider: FinetuneProvider,
+    client: Client,
+    url: String,
+    api_key: String,
+}
+
+impl FinetuneClient {
+    /// `url` is the API base, e.g. `https://api.openai.com/v1`.
+    pub fn new(provider: FinetuneProvider, url: &str, api_key: &str) -> Self {
+        Self {
+            provider,
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(&self, request: RequestBuilder) -> Result<T> {
+        let response = request.bearer_auth(&self.api_key).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!("{:?} API error ({}): {}", self.provider, status, body));
+        }
+        serde_json::from_str(&body).map_err(|e| anyhow!("Unexpected {:?} response {:?}: {}", self.provider, body, e))
+    }
+
+    /// Uploads a JSONL training (or validation) file and returns its id.
+    pub async fn upload(&self, name: &str, content: Vec<u8>) -> Result<String> {
+        #[derive(Deserialize)]
+        struct UploadedFile {
+            id: String,
+        }
+
+        // Built by hand, since the upload is the only multipart request
+        let boundary = format!("llmds-{:016x}", rand::random::<u64>());
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"purpose\"\r\n\r\nfine-tune\r\n\
+             --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+             Content-Type: application/jsonl\r\n\r\n",
+            boundary = boundary,
+            name = name.replace('"', "")
+        )
+        .into_bytes();
+        body.extend(content);
+        body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+
+        let request = self
+            .client
+            .post(format!("{}/files", self.url))
+            .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+            .body(body);
+        let file: UploadedFile = self.send(request).await?;
+        debug!("Uploaded {} as {}", name, file.id);
+        Ok(file.id)
+    }
+
+    /// Starts a job training on the uploaded files.
+    pub async fn create_job(&self, request: &FinetuneRequest, training_file: &str, validation_file: Option<&str>) -> Result<FinetuneJob> {
+        let mut body = json!({
+            "model": request.base_model,
+            "training_file": training_file,
+        });
+        if let Some(file) = validation_file {
+            body["validation_file"] = json!(file);
+        }
+        if let Some(suffix) = &request.suffix {
+            body["suffix"] = json!(suffix);
+        }
+        if let Some(epochs) = request.epochs {
+            body["hyperparameters"] = json!({ "n_epochs": epochs });
+        }
+        self.send(self.client.post(format!("{}/fine_tuning/jobs", self.url)).json(&body)).await
+    }
+
+    pub async fn job(&self, id: &str) -> Result<FinetuneJob> {
+        self.send(self.client.get(format!("{}/fine_tuning/jobs/{}", self.url, id))).await
+    }
+
+    /// Polls the job until it finishes, logging each change of status. Fails when the
+    /// job fails or is cancelled, or when `MAX_POLL_FAILURES` polls in a row fail.
+    pub async fn wait(&self, id: &str) -> Result<FinetuneJob> {
+        let mut status = String::new();
+        let mut failures = 0;
+        loop {
+            let job = match self.job(id).await {
+                Ok(job) => {
+                    failures = 0;
+                    job
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures >= MAX_POLL_FAILURES {
+                        return Err(e.context(format!("Gave up on fine-tuning job {} after {} failed polls", id, failures)));
+                    }
+                    let backoff = POLL_INTERVAL * 2u32.pow(failures - 1);
+                    warn!("Failed to poll fine-tuning job {} (retrying in {}s): {}", id, backoff.as_secs(), e);
+                    tokio::time::sleep(backoff).await;
+                    continue;
+                }
+            };
+            if job.status != status {
+                info!("Fine-tuning job {} is {}", job.id, job.status);
+                status = job.status.clone();
+            }
+            if job.is_finished() {
+                return match job.status.as_str() {
+                    "succeeded" => Ok(job),
+                    _ => Err(anyhow!(
+                        "Fine-tuning job {} {}{}",
+                        job.id,
+                        job.status,
+                        job.error_message().map(|message| format!(": {}", message)).unwrap_or_default()
+                    )),
+                };
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Validates the dataset at `input` (and the optional validation set), uploads it and
+    /// starts a fine-tuning job; waits for the job to finish when `wait` is set.
+    pub async fn submit(
+        &self,
+        input: &Path,
+        validation: Option<&Path>,
+        system: Option<&str>,
+        request: &FinetuneRequest,
+        wait: bool,
+    ) -> Result<FinetuneJob> {
+        let (training, records) = training_file(input, system)?;
+        let validation = validation.map(|path| training_file(path, system).map(|(file, _)| (path, file))).transpose()?;
+        info!("Uploading {} training records from {:?}", records, input);
+
+        let training_id = self.upload(&file_name(input), training).await?;
+        let validation_id = match validation {
+            Some((path, file)) => Some(self.upload(&file_name(path), file).await?),
+            None => None,
+        };
+        let job = self.create_job(request, &training_id, validation_id.as_deref()).await?;
+        info!("Started fine-tuning job {} on {}", job.id, job.model);
+        if !wait {
+            return Ok(job);
+        }
+        info!("Waiting for job {} to finish; stopping here leaves it running", job.id);
+        self.wait(&job.id).await
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().and_then(|name| name.to_str()).unwrap_or("dataset.jsonl").to_string()
+}
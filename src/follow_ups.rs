@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use crate::config::FollowUpsConfig;
+use crate::dataset;
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::{OllamaProcessor, ProcessedItem};
+
+/// Field holding an item's follow-up exchanges, in order.
+pub const FIELD: &str = "follow_ups";
+
+pub const SYSTEM_PROMPT: &str = "You continue conversations for a training dataset about technical documentation. \
+    Given a question, its answer and the content they come from, write the follow-up questions a user would \
+    naturally ask next, each building on the conversation so far, and answer them from the content. Follow-up \
+    questions may refer back to earlier turns (\"it\", \"that option\") as people do in a conversation. Respond as JSON.";
+
+/// One more turn of a conversation that starts with an item's question and answer.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FollowUp {
+    pub question: String,
+    pub answer: String,
+}
+
+/// The follow-ups stored in `item`, if any.
+pub fn of(item: &ProcessedItem) -> Vec<FollowUp> {
+    item.extra
+        .get(FIELD)
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// The user message asking for up to `max` follow-ups to `item`, generated from `section`.
+pub fn user_prompt(item: &ProcessedItem, section: &str, max: usize) -> String {
+    format!(
+        "Write up to {} follow-up questions with answers, continuing this conversation.\n\n\
+        Question: {}\nAnswer: {}\n\nContent:\n{}",
+        max, item.question, item.answer, section
+    )
+}
+
+/// Asks the file's model for follow-up questions and answers to `follow_ups.fraction`
+/// of the file's items, from the section each was generated from, turning them into
+/// multi-turn conversations. Items whose follow-ups fail keep their single exchange.
+/// Streamed files are left alone.
+pub struct FollowUpStage {
+    config: FollowUpsConfig,
+    processor: Arc<OllamaProcessor>,
+}
+
+impl FollowUpStage {
+    pub fn new(config: FollowUpsConfig, processor: Arc<OllamaProcessor>) -> Self {
+        Self { config, processor }
+    }
+}
+
+#[async_trait]
+impl Stage for FollowUpStage {
+    fn name(&self) -> &'static str {
+        "follow-ups"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        if work.stream.is_some() {
+            return Ok(());
+        }
+        let mut conversations = 0;
+        for i in 0..work.items.len() {
+            let item = &work.items[i];
+            if !dataset::picked(item, self.config.fraction, FIELD) || item.extra.contains_key(FIELD) {
+                continue;
+            }
+            let Some(section) = item
+                .source
+                .as_ref()
+                .filter(|source| source.image.is_none())
+                .and_then(|source| work.sections.iter().find(|section| section.index == source.section))
+            else {
+                continue;
+            };
+            match self.processor.generate_follow_ups(work, item, &section.text, self.config.max).await {
+                Ok(follow_ups) if !follow_ups.is_empty() => {
+                    debug!("Added {} follow-ups to item {}", follow_ups.len(), i + 1);
+                    work.items[i].extra.insert(FIELD.to_string(), serde_json::to_value(follow_ups)?);
+                    conversations += 1;
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to generate follow-ups for item {} of {:?}: {}", i + 1, work.path, e),
+            }
+        }
+        if conversations > 0 {
+            info!("Turned {} items of {:?} into conversations with follow-ups", conversations, work.path);
+        }
+        work.usage = self.processor.usage().file_usage(&work.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::Arc;
+    use serde_json::json;
+    use crate::config::Config;
+    use crate::metrics::Metrics;
+    use crate::pipeline::Section;
+    use crate::processor::ItemSource;
+
+    fn item(question: &str, answer: &str) -> ProcessedItem {
+        serde_json::from_value(json!({ "question": question, "answer": answer })).unwrap()
+    }
+
+    #[test]
+    fn follow_ups_are_read_back() {
+        let mut item = item("How do I install it?", "Run the installer.");
+        assert!(of(&item).is_empty());
+        item.extra.insert(FIELD.to_string(), json!([{ "question": "Then?", "answer": "Run it." }]));
+        let follow_ups = of(&item);
+        assert_eq!((follow_ups[0].question.as_str(), follow_ups[0].answer.as_str()), ("Then?", "Run it."));
+        // Anything else in the field is ignored
+        item.extra.insert(FIELD.to_string(), json!("Then?"));
+        assert!(of(&item).is_empty());
+    }
+
+    #[test]
+    fn prompt_holds_the_exchange_and_its_content() {
+        let prompt = user_prompt(&item("How do I install it?", "Run the installer."), "## Install\n\nRun the installer.", 2);
+        assert!(prompt.starts_with("Write up to 2 follow-up questions"));
+        assert!(prompt.contains("Question: How do I install it?\nAnswer: Run the installer.\n"));
+        assert!(prompt.ends_with("Content:\n## Install\n\nRun the installer."));
+    }
+
+    #[tokio::test]
+    async fn items_keep_a_single_exchange_when_follow_ups_fail() {
+        let config = Arc::new(Config {
+            endpoint: "http://127.0.0.1:1".to_string(),
+            ..Config::default()
+        });
+        let mut work = FileWork::new(Path::new("guide.md"), Path::new(""));
+        work.settings = Some(config.settings_for(Path::new("guide.md"), "").unwrap());
+        work.sections = vec![Section { index: 0, text: "## Install\n\nRun the installer.".to_string(), target: 2, release: None }];
+        let mut sourced = item("How do I install it?", "Run the installer.");
+        sourced.source = Some(ItemSource { file: "guide.md".to_string(), section: 0, chunk: None, image: None, modified: None });
+        work.items = vec![sourced];
+
+        let processor = Arc::new(OllamaProcessor::new(config, Arc::new(Metrics::new())));
+        FollowUpStage::new(FollowUpsConfig { fraction: 1.0, max: 2 }, processor).process(&mut work).await.unwrap();
+        assert!(!work.items[0].extra.contains_key(FIELD));
+    }
+}
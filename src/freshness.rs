@@ -0,0 +1,42 @@
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, NaiveDate, Utc};
+use tracing::debug;
+
+/// When the document at `path` last changed: the date of the last commit touching it
+/// when it is tracked by git, its modification time otherwise.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    git_modified(path).or_else(|| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+}
+
+/// The commit time of the last commit touching `path`, asking git in its directory.
+fn git_modified(path: &Path) -> Option<SystemTime> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let output = Command::new("git")
+        .args(["log", "-1", "--format=%ct", "--"])
+        .arg(path.file_name()?)
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let seconds: u64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// The UTC date of `time`.
+pub fn date(time: SystemTime) -> NaiveDate {
+    DateTime::<Utc>::from(time).date_naive()
+}
+
+/// Gives `copy` the modification time of the document it was copied from, so its date
+/// survives the copy into the output directory.
+pub fn preserve(original: &Path, copy: &Path) {
+    let Some(time) = modified(original) else {
+        return;
+    };
+    if let Err(e) = File::options().write(true).open(copy).and_then(|file| file.set_modified(time)) {
+        debug!("Failed to set the modification time of {:?}: {}", copy, e);
+    }
+}
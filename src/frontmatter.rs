@@ -0,0 +1,106 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+/// Frontmatter key under which doc authors steer generation, either nested
+/// (`llmds: {questions: 40}`) or dotted (`llmds.questions: 40`).
+const OVERRIDES_KEY: &str = "llmds";
+
+/// Per-file settings from a document's frontmatter.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    /// Fixed number of questions for the file.
+    pub questions: Option<usize>,
+    /// Leave the file out of the dataset.
+    pub skip: bool,
+    /// Profile to process the file with, instead of the one the rules select.
+    pub prompt: Option<String>,
+}
+
+/// Splits YAML frontmatter (a `---` delimited block at the very start of a document) from
+/// the body. Returns `None` for the mapping when there is no frontmatter or it isn't a
+/// YAML mapping; the body is then the whole document.
+pub fn parse(content: &str) -> (Option<Mapping>, &str) {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return (None, content);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let yaml = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            return match serde_yaml::from_str::<Mapping>(yaml) {
+                Ok(mapping) => (Some(mapping), body),
+                Err(_) => (None, content),
+            };
+        }
+        offset += line.len();
+    }
+
+    (None, content)
+}
+
+/// The `llmds` keys of a document's frontmatter. Fails on unknown keys and invalid
+/// values, so typos don't go unnoticed.
+pub fn overrides(frontmatter: Option<&Mapping>) -> Result<Overrides> {
+    let Some(frontmatter) = frontmatter else {
+        return Ok(Overrides::default());
+    };
+    let mut keys = match frontmatter.get(OVERRIDES_KEY) {
+        Some(Value::Mapping(nested)) => nested.clone(),
+        Some(_) => return Err(anyhow!("Frontmatter key {:?} must be a mapping", OVERRIDES_KEY)),
+        None => Mapping::new(),
+    };
+    for (key, value) in frontmatter {
+        if let Some(name) = key.as_str().and_then(|key| key.strip_prefix(OVERRIDES_KEY)).and_then(|key| key.strip_prefix('.')) {
+            keys.insert(Value::from(name), value.clone());
+        }
+    }
+    let overrides: Overrides = serde_yaml::from_value(Value::Mapping(keys)).map_err(|e| anyhow!("Invalid {} frontmatter: {}", OVERRIDES_KEY, e))?;
+    if overrides.questions == Some(0) {
+        return Err(anyhow!("{}.questions must be at least 1", OVERRIDES_KEY));
+    }
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frontmatter_is_split_from_the_body() {
+        let (frontmatter, body) = parse("---\r\ntitle: Install\r\ntags: [setup]\r\n---\r\n# Install\n");
+        assert_eq!(frontmatter.unwrap().get("title").and_then(Value::as_str), Some("Install"));
+        assert_eq!(body, "# Install\n");
+
+        for content in ["# No frontmatter\n---\n", "---\ntitle: [unclosed\n---\nBody", "---\n- a list\n---\nBody", "---\ntitle: never closed\n"] {
+            assert_eq!(parse(content), (None, content));
+        }
+    }
+
+    #[test]
+    fn overrides_are_read_nested_or_dotted() {
+        let (frontmatter, _) = parse("---\nllmds:\n  questions: 12\nllmds.prompt: reference\ntitle: API\n---\n");
+        let overrides = overrides(frontmatter.as_ref()).unwrap();
+        assert_eq!(overrides.questions, Some(12));
+        assert_eq!(overrides.prompt.as_deref(), Some("reference"));
+        assert!(!overrides.skip);
+
+        let (frontmatter, _) = parse("---\nllmds.skip: true\n---\n");
+        assert!(super::overrides(frontmatter.as_ref()).unwrap().skip);
+        assert!(super::overrides(None).unwrap().questions.is_none());
+    }
+
+    #[test]
+    fn invalid_overrides_are_errors() {
+        for yaml in ["llmds: 3", "llmds.question: 5", "llmds.questions: 0", "llmds:\n  skip: maybe"] {
+            let (frontmatter, _) = parse(&format!("---\n{yaml}\n---\n"));
+            assert!(overrides(frontmatter.as_ref()).is_err(), "{yaml}");
+        }
+    }
+}
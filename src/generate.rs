@@ -0,0 +1,645 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+use tracing::{debug, error, info};
+use crate::budget::ItemBudget;
+use crate::card;
+use crate::cli_help::{self, HelpSource};
+use crate::cluster::ChunkQueue;
+use crate::compression;
+use crate::config::Config;
+use crate::config_reference;
+use crate::crawl::CrawlSource;
+use crate::curriculum::{self, CurriculumOrder};
+use crate::dataset::{self, RecordFormat, RecordWriter};
+use crate::datasource::{DataSource, UrlSource, LocalSource, GitHubSource, GitHubReleaseSource};
+use crate::discourse::{self, DiscourseSource};
+use crate::docs_site;
+use crate::embeddings::{ChunkRecord, Embedder};
+use crate::error::Error;
+use crate::help_center::HelpCenterSource;
+use crate::hooks::{Event, Hooks};
+use crate::logs;
+use crate::metrics::Metrics;
+use crate::ocr;
+use crate::pipeline::{FileWork, Pipeline};
+use crate::plugins::Plugins;
+use crate::preflight;
+use crate::processor::{OllamaProcessor, ProcessedItem};
+use crate::progress::Progress;
+use crate::report::RunReport;
+use crate::stack_exchange::{self, StackExchangeSource};
+use crate::synthesis::{self, CorpusIndex};
+use crate::transcribe;
+use crate::usage;
+use crate::vault;
+use crate::writer::{self, DatasetWriter, ItemStream};
+
+/// Turns a source string into a data source: a command whose `--help` output is read
+/// (`help:git commit`), the solved topics of a Discourse forum
+/// (`discourse:https://forum.example.com`), the articles of a Zendesk or Intercom help
+/// center (`zendesk:https://acme.zendesk.com`, `intercom:`), a GitHub releases page, a
+/// GitHub tree/blob URL, the questions of a Stack Exchange tag, any other URL, or an
+/// existing local path.
+pub fn parse_source(input: &str, config: &Config) -> anyhow::Result<Box<dyn DataSource>> {
+    if let Some(command) = input.strip_prefix("help:") {
+        return Ok(Box::new(HelpSource::new(command)?));
+    }
+    if let Some(forum) = input.strip_prefix("discourse:") {
+        return Ok(Box::new(DiscourseSource::new(forum)?));
+    }
+    if let Some(help_center) = input.strip_prefix("zendesk:") {
+        return Ok(Box::new(HelpCenterSource::zendesk(help_center, &config.help_center, &config.style)?));
+    }
+    if let Some(api) = input.strip_prefix("intercom:") {
+        return Ok(Box::new(HelpCenterSource::intercom(api, &config.help_center, &config.style)?));
+    }
+    if let Some(site) = input.strip_prefix("crawl:") {
+        return Ok(Box::new(CrawlSource::new(site, &config.crawl, &config.auth)?));
+    }
+
+    // Check if it's a GitHub releases URL
+    if input.contains("/releases") {
+        return Ok(Box::new(GitHubReleaseSource::new(input)?));
+    }
+
+    // Check if it's a GitHub URL
+    if input.starts_with("https://github.com/") && (input.contains("/tree/") || input.contains("/blob/")) {
+        return Ok(Box::new(GitHubSource::new(input)?.with_extensions(Plugins::parser_extensions(&config.plugins))));
+    }
+
+    if stack_exchange::is_tagged_url(input) {
+        return Ok(Box::new(StackExchangeSource::new(input)?));
+    }
+
+    // Check if it's a regular URL
+    if input.starts_with("http://") || input.starts_with("https://") {
+        return Ok(Box::new(UrlSource::new(input, &config.auth)?));
+    }
+
+    // Assume it's a local path if it doesn't match the above
+    if Path::new(input).exists() {
+        return Ok(Box::new(LocalSource::new(input)));
+    }
+    Err(anyhow::anyhow!("{:?} is neither a URL nor an existing path", input))
+}
+
+async fn collect_sources(config: &Config) -> Result<Vec<Box<dyn DataSource>>, Error> {
+    let mut sources: Vec<Box<dyn DataSource>> = Vec::new();
+    let mut buffer = String::new();
+
+    loop {
+        println!("\nEnter a data source (press Enter to finish):");
+        println!("- URL (e.g., https://example.com/file.txt)");
+        println!("- Local path (e.g., /path/to/file)");
+        println!("- GitHub URL (e.g., https://github.com/user/repo/tree/branch/path)");
+        println!("- GitHub releases URL (e.g., https://github.com/user/repo/releases)");
+        println!("- A command's --help output (e.g., help:git commit)");
+        println!("- Stack Exchange tag URL (e.g., https://stackoverflow.com/questions/tagged/rust)");
+        println!("- Solved topics of a Discourse forum (e.g., discourse:https://forum.example.com/c/support)");
+        println!("- Help center articles (e.g., zendesk:https://acme.zendesk.com, or intercom: with help_center.token)");
+        println!("- A website, crawled from a page through its links (e.g., crawl:https://docs.example.com/guide/)");
+        print!("> ");
+        std::io::stdout().flush()?;
+        
+        buffer.clear();
+        std::io::stdin().read_line(&mut buffer)?;
+        let input = buffer.trim();
+        
+        if input.is_empty() {
+            break;
+        }
+
+        match parse_source(input, config) {
+            Ok(source) => {
+                sources.push(source);
+                info!("Successfully added source: {}", input);
+            }
+            Err(e) if input.starts_with("http://") || input.starts_with("https://") => {
+                error!("Error adding source: {}", e);
+            }
+            Err(_) => {
+                println!("Invalid input. Please enter:");
+                println!("- A GitHub URL (https://github.com/user/repo/tree/branch/path)");
+                println!("- A GitHub releases URL (https://github.com/user/repo/releases)");
+                println!("- A regular URL (http:// or https://)");
+                println!("- help: followed by a command (help:git commit)");
+                println!("- A Stack Exchange tag URL (https://stackoverflow.com/questions/tagged/rust)");
+                println!("- discourse: followed by a forum or category URL (discourse:https://forum.example.com)");
+                println!("- zendesk: followed by a help center URL (zendesk:https://acme.zendesk.com), or intercom:");
+                println!("- crawl: followed by the URL to start crawling a site from (crawl:https://docs.example.com/guide/)");
+                println!("- A valid local file or directory path");
+            }
+        }
+    }
+    
+    Ok(sources)
+}
+
+/// Applies `[sampling]` to the files of each source: a seeded random fraction of them,
+/// up to a run-wide file limit.
+struct FileSampler {
+    rng: StdRng,
+    sample: Option<f64>,
+    remaining: Option<usize>,
+}
+
+impl FileSampler {
+    fn new(config: &Config) -> Self {
+        Self {
+            rng: match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            sample: config.sampling.sample,
+            remaining: config.sampling.limit_files,
+        }
+    }
+
+    fn select(&mut self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        let total = files.len();
+        let files = self.select_quietly(files);
+        if files.len() < total {
+            info!("Sampling {} of {} files", files.len(), total);
+        }
+        files
+    }
+
+    /// `select` for a batch of a source's files, leaving the sampling to be logged once
+    /// for all of them.
+    fn select_quietly(&mut self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = match self.sample {
+            Some(fraction) => files.into_iter().filter(|_| self.rng.gen_bool(fraction)).collect(),
+            None => files,
+        };
+        if let Some(remaining) = &mut self.remaining {
+            files.truncate(*remaining);
+            *remaining -= files.len();
+        }
+        files
+    }
+
+    /// Whether the file limit is reached, so no more files are needed.
+    fn is_full(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+/// Files to generate from: collected from sources, or already in the output directory.
+enum FileList {
+    Sources(Vec<Box<dyn DataSource>>),
+    Existing(Vec<PathBuf>),
+}
+
+/// Source stage of the pipeline: collects files from each source, puts the pages of
+/// docs sites in navigation order, samples them and sends them on. Files are sent as
+/// the source collects them, so a crawl goes on while its first pages are generated
+/// from. With an item `budget`, every source is collected first so the budget is spread
+/// over all of their files, and no more files are sent once it is used up.
+async fn feed_files(
+    config: Config,
+    files: FileList,
+    progress: Arc<Progress>,
+    budget: Option<Arc<ItemBudget>>,
+    sender: mpsc::Sender<FileWork>,
+) -> anyhow::Result<()> {
+    let output_dir = PathBuf::from(&config.output_dir);
+    let mut sampler = FileSampler::new(&config);
+    let sources = match files {
+        FileList::Existing(files) => {
+            let files = sampler.select(docs_site::nav_order(files, &output_dir));
+            if let Some(budget) = &budget {
+                budget.plan(&files);
+            }
+            return send_files(files, &output_dir, &progress, budget.as_deref(), &sender).await;
+        }
+        FileList::Sources(sources) => sources,
+    };
+    let mut collected = Vec::new();
+    for source in sources {
+        debug!("Processing source...");
+
+        let Some(budget) = &budget else {
+            stream_files(source.as_ref(), &output_dir, &mut sampler, &progress, &sender).await?;
+            continue;
+        };
+        // Collect files from source
+        let files = source.collect(&output_dir).await?;
+        info!("Found {} files", files.len());
+        let files = sampler.select(docs_site::nav_order(files, &output_dir));
+        budget.plan(&files);
+        collected.extend(files);
+    }
+    send_files(collected, &output_dir, &progress, budget.as_deref(), &sender).await
+}
+
+/// Sends the files of `source` on as it collects them, in batches of those ready at
+/// once, each put in navigation order and sampled. The source is stopped once the file
+/// limit is reached or the pipeline closes.
+async fn stream_files(
+    source: &dyn DataSource,
+    output_dir: &Path,
+    sampler: &mut FileSampler,
+    progress: &Progress,
+    sender: &mpsc::Sender<FileWork>,
+) -> anyhow::Result<()> {
+    let (files, mut ready) = mpsc::unbounded_channel();
+    let sending = async {
+        let (mut found, mut sampled) = (0, 0);
+        while let Some(file) = ready.recv().await {
+            let mut batch = vec![file];
+            while let Ok(file) = ready.try_recv() {
+                batch.push(file);
+            }
+            found += batch.len();
+            let batch = sampler.select_quietly(docs_site::nav_order(batch, output_dir));
+            sampled += batch.len();
+            send_files(batch, output_dir, progress, None, sender).await?;
+            if sampler.is_full() || sender.is_closed() {
+                break;
+            }
+        }
+        // Closed, so the source stops collecting
+        drop(ready);
+        info!("Found {} files", found);
+        if sampled < found {
+            info!("Sampling {} of {} files", sampled, found);
+        }
+        anyhow::Ok(())
+    };
+    let (streamed, sent) = tokio::join!(source.stream(output_dir, files), sending);
+    streamed?;
+    sent
+}
+
+async fn send_files(
+    files: Vec<PathBuf>,
+    output_dir: &Path,
+    progress: &Progress,
+    budget: Option<&ItemBudget>,
+    sender: &mpsc::Sender<FileWork>,
+) -> anyhow::Result<()> {
+    progress.add_files(files.len());
+    let total = files.len();
+    for (sent, file_path) in files.into_iter().enumerate() {
+        if budget.is_some_and(|budget| budget.is_exhausted()) {
+            info!("Item budget used up; skipping the remaining {} files", total - sent);
+            break;
+        }
+        if sender.send(FileWork::new(&file_path, output_dir)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// A sink receiving a run's items. A sink that fails is logged and gets no more items,
+/// without failing the run.
+struct OpenSink<'a> {
+    writer: &'a dyn DatasetWriter,
+    stream: Option<Box<dyn ItemStream + 'a>>,
+    items: usize,
+}
+
+impl<'a> OpenSink<'a> {
+    async fn open(writer: &'a dyn DatasetWriter) -> Self {
+        let stream = writer
+            .open()
+            .await
+            .inspect_err(|e| error!("Failed to open {}: {}", writer.describe(), e))
+            .ok();
+        Self { writer, stream, items: 0 }
+    }
+
+    async fn append(&mut self, items: &[ProcessedItem]) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        match stream.append(items).await {
+            Ok(()) => self.items += items.len(),
+            Err(e) => {
+                error!("Failed to write items to {}: {}", self.writer.describe(), e);
+                self.stream = None;
+            }
+        }
+    }
+
+    /// Completes the items and writes the run's embedded sections.
+    async fn finish(self, chunks: &[ChunkRecord]) {
+        let Some(stream) = self.stream else {
+            return;
+        };
+        match stream.finish().await {
+            Ok(()) => info!("Wrote {} items to {}", self.items, self.writer.describe()),
+            Err(e) => error!("Failed to write items to {}: {}", self.writer.describe(), e),
+        }
+        if !chunks.is_empty() {
+            if let Err(e) = self.writer.write_chunks(chunks).await {
+                error!("Failed to write chunks to {}: {}", self.writer.describe(), e);
+            }
+        }
+    }
+}
+
+/// Field numbering the repeats of an oversampled item, from 1; the item itself has none.
+pub const REPEAT_FIELD: &str = "repeat";
+
+/// `items` each written `repeat` times, for a file with an oversampling weight. The
+/// fraction of a weight repeats that share of the items once more, spread evenly, so
+/// a weight below 1 keeps only part of them. Repeats are tagged with their number and
+/// get an id derived from their item's, so sinks keyed by id keep every row.
+fn oversampled(items: Vec<ProcessedItem>, repeat: f64) -> Vec<ProcessedItem> {
+    let mut total = 0.0_f64;
+    let mut result = Vec::new();
+    for mut item in items {
+        let before = total.floor();
+        total += repeat;
+        let copies = (total.floor() - before) as usize;
+        if copies == 0 {
+            continue;
+        }
+        dataset::identify(&mut item);
+        let id = item.id.clone().unwrap_or_default();
+        let repeats: Vec<ProcessedItem> = (1..copies)
+            .map(|n| {
+                let mut copy = item.clone();
+                copy.id = Some(format!("{:016x}", dataset::stable_hash(&format!("{}\0{}\0{}", id, REPEAT_FIELD, n))));
+                copy.extra.insert(REPEAT_FIELD.to_string(), n.into());
+                copy
+            })
+            .collect();
+        result.push(item);
+        result.extend(repeats);
+    }
+    result
+}
+
+/// Writes a file's items to the combined file and the sinks, embedding their questions
+/// first when `embedder` is set and repeating them by the file's oversampling weight.
+async fn write_file_items(
+    mut items: Vec<ProcessedItem>,
+    repeat: f64,
+    embedder: Option<&Embedder>,
+    combined: &mut RecordWriter,
+    sinks: &mut [OpenSink<'_>],
+) -> Result<(), Error> {
+    if let Some(embedder) = embedder {
+        if let Err(e) = embedder.embed_items(&mut items).await {
+            error!("Failed to embed questions: {}", e);
+        }
+    }
+    if repeat != 1.0 {
+        items = oversampled(items, repeat);
+    }
+    for item in &items {
+        combined.write(item)?;
+    }
+    for sink in sinks {
+        sink.append(&items).await;
+    }
+    Ok(())
+}
+
+/// Asks for data sources on the terminal, then generates from them.
+pub async fn run(config: &Config, progress: Arc<Progress>) -> Result<Option<RunReport>, Error> {
+    let sources = collect_sources(config).await?;
+    run_sources(config, sources, progress, None, Hooks::new(&config.hooks)).await
+}
+
+/// Generates question/answer pairs from `sources`, or from the files already in the
+/// output directory when there are none. With `chunks`, sections are generated by
+/// distributed workers; `hooks` receive the run's events. Returns `None` when there was
+/// nothing to do.
+pub async fn run_sources(
+    config: &Config,
+    sources: Vec<Box<dyn DataSource>>,
+    progress: Arc<Progress>,
+    chunks: Option<Arc<ChunkQueue>>,
+    hooks: Hooks,
+) -> Result<Option<RunReport>, Error> {
+    // Create output directory if it doesn't exist
+    fs::create_dir_all(&config.output_dir)?;
+    
+    // Load per-model pricing if provided
+    let pricing = match &config.pricing {
+        Some(path) => usage::load_pricing(Path::new(path))?,
+        None => Default::default(),
+    };
+
+    // Fail before collecting files when Ollama can't serve the run; distributed
+    // sections are generated by the workers' own endpoints
+    if config.preflight && chunks.is_none() {
+        preflight::check(config).await?;
+    }
+
+    // Initialize processor
+    progress.start();
+    let metrics = Metrics::global();
+    if let Some(addr) = config.metrics_addr {
+        metrics.serve(addr).await?;
+    }
+    let mut processor = OllamaProcessor::new(Arc::new(config.clone()), metrics.clone())
+        .with_failure_archive(Path::new(&config.output_dir).join("failures"));
+    if let Some(queue) = chunks {
+        processor = processor.with_chunk_queue(queue);
+    }
+    let processor = Arc::new(processor);
+    let hooks = Arc::new(hooks);
+    let budget = ItemBudget::new(config).map(Arc::new);
+    let pipeline = Pipeline::standard(
+        Arc::new(config.clone()),
+        processor.clone(),
+        progress.clone(),
+        metrics.clone(),
+        hooks.clone(),
+        budget.clone(),
+    )?;
+
+    // If no sources added, check existing files
+    let files = if sources.is_empty() {
+        info!("No new sources added. Processing existing files in output directory...");
+        // Markdown, Org-mode, text and crawled web pages, plus whatever parser plugins turn into text, possibly compressed,
+        // audio when it can be transcribed, PDFs and images when they can be read, configuration references, help text and man pages,
+        // Terraform, SQL schemas, logs, ticket exports and the posts of Stack Exchange and
+        // Discourse, but not the settings of note vaults or docs sites
+        let mut extensions = vec!["md".to_string(), "org".to_string(), "txt".to_string(), "html".to_string(), "tf".to_string(), "sql".to_string(), "csv".to_string()];
+        extensions.extend(config.plugins.iter().flat_map(|p| p.extensions.iter().map(|ext| ext.to_lowercase())));
+        if config.transcription.endpoint.is_some() {
+            extensions.extend(transcribe::AUDIO_EXTENSIONS.iter().map(|ext| ext.to_string()));
+        }
+        if config.ocr.engine.is_some() {
+            extensions.push("pdf".to_string());
+            if config.ocr.images {
+                extensions.extend(ocr::IMAGE_EXTENSIONS.iter().map(|ext| ext.to_string()));
+            }
+        }
+        let mut existing_files = Vec::new();
+        for entry in WalkDir::new(Path::new(&config.output_dir))
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.file_name() != card::DATASET_CARD_FILE)
+            .filter(|e| {
+                let relative = e.path().strip_prefix(&config.output_dir).unwrap_or(e.path());
+                !vault::is_settings(relative) && !docs_site::is_site_file(relative)
+            })
+            .filter(|e| {
+                compression::inner_path(e.path())
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| extensions.contains(&ext.to_lowercase()))
+                    .unwrap_or(false)
+                    || config_reference::is_config_reference(e.path())
+                    || cli_help::is_cli_reference(e.path())
+                    || logs::is_log(e.path())
+                    || stack_exchange::is_dump(e.path())
+                    || discourse::is_export(e.path())
+            })
+        {
+            existing_files.push(entry.path().to_path_buf());
+        }
+
+        if existing_files.is_empty() {
+            info!("No markdown or text files found in output directory to process.");
+            return Ok(None);
+        }
+
+        info!("Found {} markdown/text files to process.", existing_files.len());
+        FileList::Existing(existing_files)
+    } else {
+        FileList::Sources(sources)
+    };
+
+    // Items go to the combined file and the sinks as each file finishes, so the run
+    // never holds all of them
+    let output_file = Path::new(&config.output_dir).join(config.output.combined_file());
+    let mut combined = RecordWriter::create(&output_file, config.output.format, RecordFormat::Qa)?;
+    let writers: Vec<Box<dyn DatasetWriter>> = config
+        .output
+        .sinks
+        .iter()
+        .map(|sink| writer::from_config(sink, Path::new(&config.output_dir)))
+        .collect();
+    let mut sinks = Vec::new();
+    for writer in &writers {
+        sinks.push(OpenSink::open(writer.as_ref()).await);
+    }
+    // Embeddings only go to sinks, so don't compute them without any
+    let embedder = Embedder::new(&config.embeddings, &config.endpoint)
+        .filter(|_| config.embeddings.questions && !sinks.is_empty());
+
+    // Synthesized answers draw on the whole corpus, so items are held until every file
+    // has been indexed; the coordinator of a distributed run doesn't call Ollama itself
+    let synthesis = Embedder::new(&config.embeddings, &config.endpoint)
+        .filter(|_| config.synthesis.enabled && !processor.is_distributed());
+    let mut index = CorpusIndex::new();
+    let mut held = Vec::new();
+
+    // Feed files into the pipeline while earlier ones are still being processed
+    let started = Instant::now();
+    let (sender, receiver) = mpsc::channel(1);
+    let feeder = tokio::spawn(feed_files(config.clone(), files, progress.clone(), budget, sender));
+    let mut finished = pipeline.run(receiver);
+
+    let mut all_chunks = Vec::new();
+    let mut file_reports = Vec::new();
+    while let Some(work) = finished.recv().await {
+        if let Some(e) = &work.error {
+            progress.add_failure();
+            error!("Error processing file {:?}: {}", work.path, e);
+        }
+        progress.finish_file();
+        metrics.files_processed.inc();
+        let report = work.report();
+        hooks.emit(Event::FileCompleted(&report)).await;
+        file_reports.push(report);
+
+        let repeat = work.settings.as_ref().map_or(1.0, |settings| settings.repeat);
+        if synthesis.is_some() {
+            index.add(&work.chunks);
+            held.push((work.items, repeat));
+        } else {
+            write_file_items(work.items, repeat, embedder.as_ref(), &mut combined, &mut sinks).await?;
+        }
+        if config.embeddings.chunks {
+            all_chunks.extend(work.chunks);
+        }
+    }
+    feeder.await??;
+    
+    progress.finish();
+
+    if let Some(synthesis) = &synthesis {
+        let counts: Vec<(usize, f64)> = held.iter().map(|(items, repeat)| (items.len(), *repeat)).collect();
+        let mut items: Vec<ProcessedItem> = held.into_iter().flat_map(|(items, _)| items).collect();
+        if let Err(e) = synthesis::synthesize(&processor, Path::new(&config.output_dir), synthesis, &index, &config.synthesis, &mut items).await {
+            error!("Failed to synthesize answers: {}", e);
+        }
+        let mut items = items.into_iter();
+        for (count, repeat) in counts {
+            let file_items = items.by_ref().take(count).collect();
+            write_file_items(file_items, repeat, embedder.as_ref(), &mut combined, &mut sinks).await?;
+        }
+    }
+
+    let saved = combined.count();
+    combined.finish()?;
+    // Ordering needs every item, so the combined file is rewritten once it is complete
+    if config.curriculum.order != CurriculumOrder::None {
+        let mut items = dataset::read_items(&output_file)?;
+        curriculum::order(&mut items, &config.curriculum, &config.output.schema)?;
+        dataset::write_items(&output_file, &items, config.output.format, RecordFormat::Qa)?;
+        info!("Ordered the combined dataset by {:?}", config.curriculum.order);
+    }
+    info!("Saved {} question-answer pairs to {:?}", saved, output_file);
+    info!("Individual file results saved as [filename]_qa.jsonl in the output directory");
+    for sink in sinks {
+        sink.finish(&all_chunks).await;
+    }
+
+    processor.usage().print_summary(&pricing);
+
+    let estimated_cost = processor.usage().estimated_cost(&pricing);
+    let report = RunReport::new(processor.model(), started.elapsed().as_secs_f64(), file_reports, estimated_cost)
+        .with_seed(config.seed);
+    let report_path = report.write(Path::new(&config.output_dir))?;
+    info!("Run report written to {:?} (status: {})", report_path, report.status);
+    if config.output.dataset_card {
+        let card_path = card::write(config, &report)?;
+        info!("Dataset card written to {:?}", card_path);
+    }
+    hooks.emit(Event::RunCompleted(&report)).await;
+
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn item(question: &str) -> ProcessedItem {
+        serde_json::from_value(serde_json::json!({ "question": question, "answer": "Yes." })).unwrap()
+    }
+
+    #[test]
+    fn repeats_are_tagged_with_their_own_ids() {
+        let items = oversampled(vec![item("Is it free?"), item("Is it open source?")], 1.5);
+        let repeats: Vec<(&str, Option<u64>)> =
+            items.iter().map(|item| (item.question.as_str(), item.extra.get(REPEAT_FIELD).and_then(|n| n.as_u64()))).collect();
+        assert_eq!(repeats, [("Is it free?", None), ("Is it open source?", None), ("Is it open source?", Some(1))]);
+        assert_ne!(items[1].id, items[2].id);
+        assert_eq!(items[1].hash, items[2].hash);
+
+        let items = oversampled(vec![item("Is it free?")], 3.0);
+        let ids: HashSet<&Option<String>> = items.iter().map(|item| &item.id).collect();
+        assert_eq!((items.len(), ids.len()), (3, 3));
+        assert!(oversampled(vec![item("Is it free?")], 0.5).is_empty());
+    }
+}
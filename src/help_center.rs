@@ -0,0 +1,368 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use url::Url;
+use crate::config::{HelpCenterConfig, StyleConfig, LOCAL_CONFIG_FILE};
+use crate::datasource::{send_rate_limited, DataSource};
+use crate::html;
+
+const INTERCOM_API: &str = "https://api.intercom.io";
+
+/// API version requested from Intercom, whose responses change between versions.
+const INTERCOM_VERSION: &str = "2.11";
+
+/// Longest article slug in file names.
+const MAX_SLUG_LEN: usize = 60;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Platform {
+    Zendesk,
+    Intercom,
+}
+
+/// An article as saved: a Markdown file dated by its last update.
+struct Article {
+    id: String,
+    title: String,
+    body: String,
+    updated: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ZendeskArticles {
+    articles: Vec<ZendeskArticle>,
+    next_page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ZendeskArticle {
+    id: u64,
+    title: String,
+    body: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ZendeskMacros {
+    macros: Vec<ZendeskMacro>,
+    next_page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ZendeskMacro {
+    title: String,
+    actions: Vec<MacroAction>,
+}
+
+#[derive(Deserialize)]
+struct MacroAction {
+    field: String,
+    value: serde_json::Value,
+}
+
+impl ZendeskMacro {
+    /// The comment the macro adds to a ticket, as Markdown; `None` for macros that only
+    /// change ticket fields.
+    fn reply(&self) -> Option<String> {
+        let reply = self.actions.iter().find_map(|action| match (action.field.as_str(), &action.value) {
+            ("comment_value_html", serde_json::Value::String(value)) => Some(html::to_markdown(value)),
+            ("comment_value", serde_json::Value::String(value)) => Some(value.trim().to_string()),
+            // `["channel:all", "text"]`
+            ("comment_value", serde_json::Value::Array(values)) => values.last().and_then(|value| value.as_str()).map(|value| value.trim().to_string()),
+            _ => None,
+        })?;
+        Some(reply).filter(|reply| !reply.is_empty())
+    }
+}
+
+#[derive(Deserialize)]
+struct IntercomArticles {
+    data: Vec<IntercomArticle>,
+    pages: Option<IntercomPages>,
+}
+
+#[derive(Deserialize)]
+struct IntercomArticle {
+    id: String,
+    title: String,
+    body: Option<String>,
+    state: String,
+    updated_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct IntercomPages {
+    page: u64,
+    total_pages: u64,
+}
+
+/// A directory config holding the macros as style references for the articles.
+#[derive(Serialize)]
+struct StyleFile {
+    style: StyleConfig,
+}
+
+/// The published articles of a Zendesk or Intercom help center, saved as Markdown files
+/// in a directory of their own and generated from like any docs. With
+/// `help_center.macros`, Zendesk macros are saved alongside as the answer style
+/// references of that directory.
+pub struct HelpCenterSource {
+    platform: Platform,
+    base: Url,
+    config: HelpCenterConfig,
+    examples: usize,
+}
+
+impl HelpCenterSource {
+    /// The help center of a Zendesk account, e.g. `https://acme.zendesk.com`.
+    pub fn zendesk(url: &str, config: &HelpCenterConfig, style: &StyleConfig) -> Result<Self> {
+        Self {
+            platform: Platform::Zendesk,
+            base: Self::base(url)?,
+            config: config.clone(),
+            examples: style.examples,
+        }
+        .checked()
+    }
+
+    /// The help center of the Intercom workspace `help_center.token` belongs to, through
+    /// the API at `url` (`https://api.intercom.io` when empty, or a regional one such as
+    /// `https://api.eu.intercom.io`).
+    pub fn intercom(url: &str, config: &HelpCenterConfig, style: &StyleConfig) -> Result<Self> {
+        if config.token.is_none() {
+            return Err(anyhow!("Fetching Intercom articles needs an access token in help_center.token"));
+        }
+        Self {
+            platform: Platform::Intercom,
+            base: Self::base(if url.trim().is_empty() { INTERCOM_API } else { url })?,
+            config: config.clone(),
+            examples: style.examples,
+        }
+        .checked()
+    }
+
+    /// The source, unless it has credentials that must not be sent to its host.
+    fn checked(self) -> Result<Self> {
+        if self.config.token.is_some() && !self.may_authorize() {
+            return Err(anyhow!(
+                "Not sending help_center credentials to {}; they are only sent over https to {}, or to the help center in help_center.url",
+                self.base,
+                match self.platform {
+                    Platform::Zendesk => "*.zendesk.com",
+                    Platform::Intercom => "api*.intercom.io",
+                }
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Whether the credentials may be sent to the help center: over https, to the
+    /// platform's own hosts or the one configured in `help_center.url`.
+    fn may_authorize(&self) -> bool {
+        if self.base.scheme() != "https" {
+            return false;
+        }
+        let host = self.base.host_str().unwrap_or_default().to_lowercase();
+        let platform_host = match self.platform {
+            Platform::Zendesk => host.ends_with(".zendesk.com"),
+            Platform::Intercom => host.starts_with("api") && host.ends_with(".intercom.io"),
+        };
+        let configured = self
+            .config
+            .url
+            .as_deref()
+            .and_then(|url| Url::parse(url.trim()).ok())
+            .is_some_and(|url| url.origin() == self.base.origin());
+        platform_host || configured
+    }
+
+    fn base(url: &str) -> Result<Url> {
+        let mut base = Url::parse(url.trim())?;
+        if !matches!(base.scheme(), "http" | "https") || base.host_str().is_none() {
+            return Err(anyhow!("Invalid help center URL {:?}", url));
+        }
+        base.set_path("");
+        base.set_query(None);
+        Ok(base)
+    }
+
+    /// A GET of `url` with the configured credentials: the agent's email and API token,
+    /// or an OAuth token alone. Fails for URLs outside the help center, such as a
+    /// `next_page` pointing elsewhere, so the credentials never leave it.
+    fn get(&self, client: &Client, url: &str) -> Result<RequestBuilder> {
+        let url = Url::parse(url)?;
+        if url.origin() != self.base.origin() {
+            return Err(anyhow!("Help center page {} is outside {}", url, self.base));
+        }
+        let request = client.get(url).header("User-Agent", "llm-dataset-builder").header("Accept", "application/json");
+        Ok(match (&self.config.email, &self.config.token, self.platform) {
+            (Some(email), Some(token), Platform::Zendesk) => request.basic_auth(format!("{}/token", email), Some(token)),
+            (_, Some(token), Platform::Zendesk) => request.bearer_auth(token),
+            (_, Some(token), Platform::Intercom) => request.bearer_auth(token).header("Intercom-Version", INTERCOM_VERSION),
+            (_, None, _) => request,
+        })
+    }
+
+    async fn zendesk_articles(&self, client: &Client) -> Result<Vec<Article>> {
+        let mut articles = Vec::new();
+        let mut next = Some(self.base.join("api/v2/help_center/articles.json?per_page=100")?.to_string());
+        while let Some(url) = next {
+            let page: ZendeskArticles = send_rate_limited(self.get(client, &url)?).await?.json().await?;
+            articles.extend(page.articles.into_iter().filter(|article| !article.draft).map(|article| Article {
+                id: article.id.to_string(),
+                title: article.title,
+                body: article.body.unwrap_or_default(),
+                updated: article.updated_at,
+            }));
+            next = page.next_page;
+        }
+        Ok(articles)
+    }
+
+    async fn zendesk_macros(&self, client: &Client) -> Result<Vec<String>> {
+        let mut replies = Vec::new();
+        let mut next = Some(self.base.join("api/v2/macros/active.json?per_page=100")?.to_string());
+        while let Some(url) = next {
+            let page: ZendeskMacros = send_rate_limited(self.get(client, &url)?).await?.json().await?;
+            for item in page.macros {
+                match item.reply() {
+                    Some(reply) => replies.push(reply),
+                    None => debug!("Macro {:?} adds no reply", item.title),
+                }
+            }
+            next = page.next_page;
+        }
+        Ok(replies)
+    }
+
+    async fn intercom_articles(&self, client: &Client) -> Result<Vec<Article>> {
+        let mut articles = Vec::new();
+        let mut page = 1;
+        loop {
+            let url = self.base.join(&format!("articles?page={}&per_page=50", page))?;
+            let response: IntercomArticles = send_rate_limited(self.get(client, url.as_str())?).await?.json().await?;
+            articles.extend(response.data.into_iter().filter(|article| article.state == "published").map(|article| Article {
+                id: article.id,
+                title: article.title,
+                body: article.body.unwrap_or_default(),
+                updated: article.updated_at.and_then(|seconds| DateTime::from_timestamp(seconds, 0)),
+            }));
+            match response.pages {
+                Some(pages) if pages.page < pages.total_pages => page = pages.page + 1,
+                _ => break,
+            }
+        }
+        Ok(articles)
+    }
+}
+
+/// `title` as a file name: lowercase words joined by dashes.
+fn slug(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    slug.chars().take(MAX_SLUG_LEN).collect::<String>().trim_end_matches('-').to_string()
+}
+
+#[async_trait]
+impl DataSource for HelpCenterSource {
+    async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        let client = Client::new();
+        let host = self.base.host_str().unwrap_or("help-center");
+        info!("Fetching help center articles from {}", host);
+        let articles = match self.platform {
+            Platform::Zendesk => self.zendesk_articles(&client).await?,
+            Platform::Intercom => self.intercom_articles(&client).await?,
+        };
+        let dir = output_dir.join(match self.platform {
+            Platform::Zendesk => host,
+            Platform::Intercom => "intercom",
+        });
+        std::fs::create_dir_all(&dir)?;
+
+        // Saved before the articles are returned, so their settings have the macros
+        if self.config.macros {
+            match self.platform {
+                Platform::Intercom => warn!("Intercom's API has no macros; help_center.macros only imports Zendesk macros"),
+                Platform::Zendesk if self.config.token.is_none() => warn!("Importing Zendesk macros needs help_center.email and help_center.token"),
+                Platform::Zendesk => {
+                    let references = self.zendesk_macros(&client).await?;
+                    info!("Saving {} macros as answer style references", references.len());
+                    let style = StyleFile {
+                        style: StyleConfig {
+                            references,
+                            examples: self.examples,
+                        },
+                    };
+                    std::fs::write(dir.join(LOCAL_CONFIG_FILE), toml::to_string(&style)?)?;
+                }
+            }
+        }
+
+        let mut files = Vec::new();
+        for article in articles {
+            let body = html::to_markdown(&article.body);
+            if body.is_empty() {
+                debug!("Article {} has no text", article.id);
+                continue;
+            }
+            let path = dir.join(format!("{}-{}.md", article.id, slug(&article.title)));
+            std::fs::write(&path, format!("# {}\n\n{}\n", article.title.trim(), body))?;
+            // Dated by their last update, for freshness
+            if let Some(updated) = article.updated {
+                std::fs::File::options().write(true).open(&path)?.set_modified(updated.into())?;
+            }
+            files.push(path);
+        }
+        info!("Saved {} help center articles to {:?}", files.len(), dir);
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(token: Option<&str>, url: Option<&str>) -> HelpCenterConfig {
+        HelpCenterConfig {
+            token: token.map(str::to_string),
+            url: url.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn credentials_only_go_to_trusted_hosts() {
+        let style = StyleConfig::default();
+        let token = config(Some("secret"), None);
+        assert!(HelpCenterSource::zendesk("https://acme.zendesk.com", &token, &style).is_ok());
+        assert!(HelpCenterSource::zendesk("http://acme.zendesk.com", &token, &style).is_err());
+        assert!(HelpCenterSource::zendesk("https://evil.example.com", &token, &style).is_err());
+        assert!(HelpCenterSource::zendesk("https://acme.zendesk.com.evil.com", &token, &style).is_err());
+        assert!(HelpCenterSource::intercom("", &token, &style).is_ok());
+        assert!(HelpCenterSource::intercom("https://api.eu.intercom.io", &token, &style).is_ok());
+        assert!(HelpCenterSource::intercom("https://intercom.io.evil.com", &token, &style).is_err());
+        let custom = config(Some("secret"), Some("https://help.acme.com/"));
+        assert!(HelpCenterSource::zendesk("https://help.acme.com", &custom, &style).is_ok());
+        assert!(HelpCenterSource::zendesk("https://docs.acme.com", &custom, &style).is_err());
+        assert!(HelpCenterSource::zendesk("http://localhost:8080", &config(None, None), &style).is_ok());
+    }
+
+    #[test]
+    fn pages_outside_the_help_center_are_not_requested() {
+        let source = HelpCenterSource::zendesk("https://acme.zendesk.com", &config(Some("secret"), None), &StyleConfig::default()).unwrap();
+        let client = Client::new();
+        assert!(source.get(&client, "https://acme.zendesk.com/api/v2/help_center/articles.json?page=2").is_ok());
+        assert!(source.get(&client, "https://evil.example.com/api/v2/help_center/articles.json?page=2").is_err());
+        assert!(source.get(&client, "http://acme.zendesk.com/api/v2/help_center/articles.json?page=2").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+use crate::config::HooksConfig;
+use crate::processor::ProcessedItem;
+use crate::report::{FileReport, RunReport};
+
+/// Something that happened during a run.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// An item passed the filters.
+    ItemGenerated { file: &'a Path, item: &'a ProcessedItem },
+    /// An item was dropped by the filters or steering settings.
+    ItemRejected { file: &'a Path, item: &'a ProcessedItem },
+    /// A file left the pipeline, successfully or not.
+    FileCompleted(&'a FileReport),
+    /// The run finished and its report was written.
+    RunCompleted(&'a RunReport),
+}
+
+impl Event<'_> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ItemGenerated { .. } => "item_generated",
+            Event::ItemRejected { .. } => "item_rejected",
+            Event::FileCompleted(_) => "file_completed",
+            Event::RunCompleted(_) => "run_completed",
+        }
+    }
+
+    /// The JSON passed to hook commands: `{"event": ..., ...}` with the item and file,
+    /// the file report or the run report.
+    pub fn payload(&self) -> Value {
+        let mut payload = match self {
+            Event::ItemGenerated { file, item } | Event::ItemRejected { file, item } => {
+                json!({ "file": file, "item": item })
+            }
+            Event::FileCompleted(report) => json!({ "file": report }),
+            Event::RunCompleted(report) => json!({ "report": report }),
+        };
+        payload["event"] = json!(self.name());
+        payload
+    }
+}
+
+type Callback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Runs the configured hook commands and library callbacks for each event. Failing
+/// commands are logged and never fail the run.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    commands: HooksConfig,
+    callbacks: Vec<Callback>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("commands", &self.commands)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub fn new(commands: &HooksConfig) -> Self {
+        Self {
+            commands: commands.clone(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Calls `callback` for every event, before the hook command runs. Callbacks run on
+    /// the pipeline's tasks, so they should return quickly.
+    pub fn with_callback<F: Fn(&Event) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.callbacks.push(Arc::new(callback));
+        self
+    }
+
+    pub async fn emit(&self, event: Event<'_>) {
+        for callback in &self.callbacks {
+            callback(&event);
+        }
+        let command = match event {
+            Event::ItemGenerated { .. } => &self.commands.item_generated,
+            Event::ItemRejected { .. } => &self.commands.item_rejected,
+            Event::FileCompleted(_) => &self.commands.file_completed,
+            Event::RunCompleted(_) => &self.commands.run_completed,
+        };
+        if let Some(command) = command {
+            let timeout = Duration::from_secs(self.commands.timeout_secs);
+            if let Err(e) = Self::run_command(command, &event, timeout).await {
+                warn!("{} hook failed: {}", event.name(), e);
+            }
+        }
+    }
+
+    /// Runs `command` with `sh -c`, the event name in `LLM_DATASET_EVENT` and the payload
+    /// on stdin, killing it when it takes longer than `timeout`.
+    async fn run_command(command: &str, event: &Event<'_>, timeout: Duration) -> Result<()> {
+        let payload = event.payload().to_string();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("LLM_DATASET_EVENT", event.name())
+            .stdin(Stdio::piped())
+            // In a process group of its own, so whatever the shell started is killed with it
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child.stdin.take();
+        let finished = tokio::time::timeout(timeout, async {
+            if let Some(stdin) = &mut stdin {
+                // A command that doesn't read its input closes the pipe early; that's fine
+                let _ = stdin.write_all(payload.as_bytes()).await;
+            }
+            drop(stdin);
+            child.wait().await
+        })
+        .await;
+        let status = match finished {
+            Ok(status) => status?,
+            Err(_) => {
+                if let Some(pid) = child.id() {
+                    // SAFETY: signals the process group created for the hook above
+                    unsafe { libc::kill(-(pid as i32), libc::SIGKILL) };
+                }
+                let _ = child.kill().await;
+                return Err(anyhow!("{:?} was killed after {}s", command, timeout.as_secs()));
+            }
+        };
+        if !status.success() {
+            return Err(anyhow!("{:?} exited with {}", command, status));
+        }
+        Ok(())
+    }
+}
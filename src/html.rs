@@ -0,0 +1,289 @@
+use std::sync::LazyLock;
+use regex::{Captures, Regex};
+
+/// A tag, comment or doctype.
+pub(crate) static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->|<!?(/?)([a-zA-Z][a-zA-Z0-9]*)((?:[^>\x22']|\x22[^\x22]*\x22|'[^']*')*)>").unwrap());
+
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"([a-zA-Z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).unwrap());
+
+static ENTITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&(#[xX][0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+[0-9]*);").unwrap());
+
+/// The language of a code block from its class, as in `lang-rust` or `language-rust`.
+static LANGUAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\blang(?:uage)?-([\w+#-]+)").unwrap());
+
+/// `text` with character references (`&amp;`, `&#39;`, `&#x2014;`) replaced by their
+/// characters. Unknown names are left as they are.
+pub fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    ENTITY
+        .replace_all(text, |captures: &Captures| {
+            let reference = &captures[1];
+            let code = match reference.strip_prefix('#') {
+                Some(number) => match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                },
+                None => None,
+            };
+            if let Some(c) = code.and_then(char::from_u32) {
+                return c.to_string();
+            }
+            let named = match reference {
+                "amp" => "&",
+                "lt" => "<",
+                "gt" => ">",
+                "quot" => "\"",
+                "apos" => "'",
+                "nbsp" => " ",
+                "hellip" => "…",
+                "mdash" => "—",
+                "ndash" => "–",
+                "lsquo" => "‘",
+                "rsquo" => "’",
+                "ldquo" => "“",
+                "rdquo" => "”",
+                "copy" => "©",
+                "reg" => "®",
+                "trade" => "™",
+                "times" => "×",
+                "rarr" => "→",
+                "larr" => "←",
+                _ => return captures[0].to_string(),
+            };
+            named.to_string()
+        })
+        .into_owned()
+}
+
+/// The value of attribute `name` in the attributes of a tag, decoded.
+pub(crate) fn attribute(attributes: &str, name: &str) -> Option<String> {
+    ATTRIBUTE
+        .captures_iter(attributes)
+        .find(|captures| captures[1].eq_ignore_ascii_case(name))
+        .map(|captures| decode_entities(captures.get(2).or(captures.get(3)).or(captures.get(4)).map_or("", |value| value.as_str())))
+}
+
+/// Whether the attributes of a tag have `name`, with a value or as a bare boolean attribute.
+pub(crate) fn has_attribute(attributes: &str, name: &str) -> bool {
+    attribute(attributes, name).is_some() || ATTRIBUTE.replace_all(attributes, " ").split(|c: char| c.is_whitespace() || c == '/').any(|word| word.eq_ignore_ascii_case(name))
+}
+
+struct List {
+    ordered: bool,
+    items: usize,
+}
+
+/// Writes Markdown into a stack of buffers, one per open block quote.
+struct Converter {
+    buffers: Vec<String>,
+    lists: Vec<List>,
+    /// Targets of the open links.
+    links: Vec<Option<String>>,
+    /// Inside `<pre>`, where text is kept as it is.
+    pre: bool,
+    /// Inside `<script>` or `<style>`, whose text is dropped.
+    hidden: usize,
+}
+
+impl Converter {
+    fn out(&mut self) -> &mut String {
+        self.buffers.last_mut().unwrap()
+    }
+
+    /// Ends the current block with a blank line.
+    fn block(&mut self) {
+        let out = self.out();
+        let trimmed = out.trim_end_matches([' ', '\n']).len();
+        out.truncate(trimmed);
+        if !out.is_empty() {
+            out.push_str("\n\n");
+        }
+    }
+
+    fn line(&mut self) {
+        let out = self.out();
+        let trimmed = out.trim_end_matches(' ').len();
+        out.truncate(trimmed);
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.hidden > 0 {
+            return;
+        }
+        let text = decode_entities(text);
+        if self.pre {
+            self.out().push_str(&text);
+            return;
+        }
+        let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let out = self.out();
+        let at_start = out.is_empty() || out.ends_with(['\n', ' ']);
+        if text.starts_with(char::is_whitespace) && !at_start {
+            out.push(' ');
+        }
+        out.push_str(&collapsed);
+        if text.ends_with(char::is_whitespace) && !collapsed.is_empty() {
+            out.push(' ');
+        }
+    }
+
+    fn tag(&mut self, closing: bool, name: &str, attributes: &str) {
+        match (name, closing) {
+            ("script" | "style" | "head", false) => self.hidden += 1,
+            ("script" | "style" | "head", true) => self.hidden = self.hidden.saturating_sub(1),
+            ("p" | "div" | "section" | "article" | "table" | "dl", _) => self.block(),
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                self.block();
+                let level = name[1..].parse().unwrap_or(1);
+                self.out().push_str(&format!("{} ", "#".repeat(level)));
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => self.block(),
+            ("br", _) => self.line(),
+            ("hr", _) => {
+                self.block();
+                self.out().push_str("---");
+                self.block();
+            }
+            ("pre", false) => {
+                self.block();
+                let language = LANGUAGE.captures(attributes).map(|captures| captures[1].to_string()).unwrap_or_default();
+                self.out().push_str(&format!("```{}\n", language));
+                self.pre = true;
+            }
+            ("pre", true) => {
+                self.pre = false;
+                let out = self.out();
+                let trimmed = out.trim_end_matches('\n').len();
+                out.truncate(trimmed);
+                out.push_str("\n```");
+                self.block();
+            }
+            ("code", false) if self.pre => {
+                // `<pre><code class="language-rust">` names the language on the code
+                if let Some(captures) = LANGUAGE.captures(attributes) {
+                    let out = self.out();
+                    if out.ends_with("```\n") {
+                        out.truncate(out.len() - 1);
+                        out.push_str(&captures[1]);
+                        out.push('\n');
+                    }
+                }
+            }
+            ("code" | "kbd" | "samp" | "tt", _) if !self.pre => self.out().push('`'),
+            ("strong" | "b", _) if !self.pre => self.out().push_str("**"),
+            ("em" | "i", _) if !self.pre => self.out().push('*'),
+            ("a", false) => {
+                let href = attribute(attributes, "href").filter(|href| !href.starts_with('#') && !href.starts_with("javascript:"));
+                if href.is_some() {
+                    self.out().push('[');
+                }
+                self.links.push(href);
+            }
+            ("a", true) => {
+                if let Some(href) = self.links.pop().flatten() {
+                    self.out().push_str(&format!("]({})", href));
+                }
+            }
+            ("img", _) => {
+                if let Some(src) = attribute(attributes, "src") {
+                    let alt = attribute(attributes, "alt").unwrap_or_default();
+                    self.out().push_str(&format!("![{}]({})", alt, src));
+                }
+            }
+            ("ul" | "ol", false) => {
+                match self.lists.is_empty() {
+                    true => self.block(),
+                    false => self.line(),
+                }
+                self.lists.push(List { ordered: name == "ol", items: 0 });
+            }
+            ("ul" | "ol", true) => {
+                self.lists.pop();
+                match self.lists.is_empty() {
+                    true => self.block(),
+                    false => self.line(),
+                }
+            }
+            ("li", false) => {
+                self.line();
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(list) if list.ordered => {
+                        list.items += 1;
+                        format!("{}. ", list.items)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.out().push_str(&format!("{}{}", "  ".repeat(depth), marker));
+            }
+            ("li" | "tr" | "dt" | "dd", true) => self.line(),
+            ("th" | "td", true) => self.out().push_str(" | "),
+            ("blockquote", false) => {
+                self.block();
+                self.buffers.push(String::new());
+            }
+            ("blockquote", true) if self.buffers.len() > 1 => {
+                let quote = self.buffers.pop().unwrap();
+                let quoted = quote.trim().lines().map(|line| format!("> {}", line).trim_end().to_string()).collect::<Vec<_>>().join("\n");
+                self.out().push_str(&quoted);
+                self.block();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `html` as Markdown: paragraphs, headings, lists, links, emphasis, block quotes and
+/// code, with code blocks kept as they are and any other markup dropped.
+pub fn to_markdown(html: &str) -> String {
+    let mut converter = Converter {
+        buffers: vec![String::new()],
+        lists: Vec::new(),
+        links: Vec::new(),
+        pre: false,
+        hidden: 0,
+    };
+    let mut from = 0;
+    for captures in TAG.captures_iter(html) {
+        let whole = captures.get(0).unwrap();
+        converter.text(&html[from..whole.start()]);
+        from = whole.end();
+        if let Some(name) = captures.get(2) {
+            converter.tag(!captures[1].is_empty(), &name.as_str().to_lowercase(), &captures[3]);
+        }
+    }
+    converter.text(&html[from..]);
+    while converter.buffers.len() > 1 {
+        converter.tag(true, "blockquote", "");
+    }
+    let markdown = converter.buffers.pop().unwrap();
+    // Lines outside code blocks lose trailing spaces, with at most one blank line in a row
+    let mut cleaned = String::new();
+    let mut fence = false;
+    let mut blank = false;
+    for line in markdown.lines() {
+        if line.starts_with("```") {
+            fence = !fence;
+        }
+        let line = match fence {
+            true => line,
+            false => line.trim_end(),
+        };
+        if line.trim().is_empty() && !fence {
+            blank = !cleaned.is_empty();
+            continue;
+        }
+        if blank {
+            cleaned.push('\n');
+            blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned.trim_end().to_string()
+}
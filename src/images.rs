@@ -0,0 +1,316 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use regex::Regex;
+use reqwest::Client;
+use tracing::{debug, info, warn};
+use crate::config::Config;
+use crate::dataset;
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::{ItemSource, OllamaProcessor, ProcessedItem};
+
+/// `![alt](src "title")`, with the source optionally in angle brackets.
+static REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"!\[([^\]]*)\]\(\s*<?([^)\s>]+)>?(?:\s+"[^"]*")?\s*\)"#).unwrap());
+
+/// Formats vision models read; SVG and other vector formats are left out.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+/// Larger images are skipped rather than sent to the model.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Field marking items generated from an image rather than the text.
+pub const MODALITY_FIELD: &str = "modality";
+pub const IMAGE_DERIVED: &str = "image-derived";
+
+pub const SYSTEM_PROMPT: &str = "You create question and answer pairs for a training dataset from the images in \
+    technical documentation, such as architecture diagrams, UI screenshots and charts. Ask about what the image \
+    shows and answer from the image alone. Respond with JSON.";
+
+pub const DESCRIBE_SYSTEM_PROMPT: &str = "You describe the images in technical documentation, such as architecture \
+    diagrams, UI screenshots and charts, for readers who can't see them. Name the components, labels, settings and \
+    values shown and how they relate, without guessing at anything the image doesn't show.";
+
+/// A Markdown image reference.
+#[derive(Debug, Clone)]
+pub struct ImageRef {
+    /// The reference as written, e.g. `![Architecture](img/arch.png)`.
+    pub markdown: String,
+    pub alt: String,
+    /// Path relative to the file, path from the site root (`/img/arch.png`) or URL.
+    pub src: String,
+}
+
+/// The image references in `text`, in order.
+pub fn references(text: &str) -> Vec<ImageRef> {
+    REFERENCE
+        .captures_iter(text)
+        .map(|captures| ImageRef {
+            markdown: captures[0].to_string(),
+            alt: captures[1].trim().to_string(),
+            src: captures[2].to_string(),
+        })
+        .collect()
+}
+
+/// `text` with every image reference replaced by its alt text, so prompts don't spend
+/// tokens on paths the model can't open.
+pub fn strip_references(text: &str) -> Cow<'_, str> {
+    REFERENCE.replace_all(text, "$1")
+}
+
+/// What the image is, for the prompts: `The attached image is from the document "Setup",
+/// captioned "Architecture"`.
+fn image_context(reference: &ImageRef, doc_title: &str) -> String {
+    let mut context = format!("The attached image is from the document \"{}\"", doc_title);
+    if !reference.alt.is_empty() {
+        context.push_str(&format!(", captioned \"{}\"", reference.alt));
+    }
+    context
+}
+
+/// The user prompt asking for a description of the attached image.
+pub fn describe_prompt(reference: &ImageRef, doc_title: &str) -> String {
+    format!("{}. Describe what it shows in one or two paragraphs.", image_context(reference, doc_title))
+}
+
+/// The question a description of the image answers.
+pub fn describe_question(reference: &ImageRef, doc_title: &str) -> String {
+    match reference.alt.as_str() {
+        "" => format!("What does the image in \"{}\" show?", doc_title),
+        alt => format!("What does the \"{}\" image in \"{}\" show?", alt, doc_title),
+    }
+}
+
+/// The user prompt asking for `count` items about the attached image, given its
+/// `description` when there is one.
+pub fn user_prompt(reference: &ImageRef, doc_title: &str, description: Option<&str>, count: usize, extra_fields: &str) -> String {
+    let mut prompt = format!("{}.", image_context(reference, doc_title));
+    match description {
+        Some(description) => prompt.push_str(&format!(" It was described as follows:\n{}\n\n", description)),
+        None => prompt.push(' '),
+    }
+    prompt.push_str(&format!(
+        "Generate {} questions a reader could ask about what it shows, such as the parts of a diagram, \
+         the steps or settings in a screenshot or the values in a chart, each with an answer. \
+         Format as JSON array with 'question' and 'answer' fields.",
+        count
+    ));
+    if !extra_fields.is_empty() {
+        prompt.push_str(&format!(" Also include these fields for each item: {}.", extra_fields));
+    }
+    prompt
+}
+
+/// `path` with `..` and `.` components folded away, e.g. `docs/../img/a.png` as `img/a.png`.
+fn normalized(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Asks the vision model under `[images]` for items about every image the file's
+/// sections reference, once per image, and with `describe` for a description of it too.
+/// Items are tagged `modality: image-derived` and their source names the image; with
+/// citations they cite the image reference. Streamed files are left alone.
+pub struct ImageStage {
+    config: Arc<Config>,
+    processor: Arc<OllamaProcessor>,
+    client: Client,
+}
+
+impl ImageStage {
+    pub fn new(config: Arc<Config>, processor: Arc<OllamaProcessor>) -> Self {
+        Self {
+            config,
+            processor,
+            client: Client::new(),
+        }
+    }
+
+    /// The image a reference in `work` points to, with its path relative to the output
+    /// directory (or its URL); `None` when it isn't a supported format or too large.
+    async fn load(&self, work: &FileWork, src: &str) -> Result<Option<(String, Vec<u8>)>> {
+        let path = src.split(['?', '#']).next().unwrap_or(src);
+        let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        if !IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            return Ok(None);
+        }
+        let (location, bytes) = if src.starts_with("http://") || src.starts_with("https://") {
+            let response = self.client.get(src).send().await?;
+            if !response.status().is_success() {
+                return Err(anyhow!("{} returned {}", src, response.status()));
+            }
+            (src.to_string(), response.bytes().await?.to_vec())
+        } else {
+            let (relative, path) = match path.strip_prefix('/') {
+                Some(path) => (PathBuf::from(path), Path::new(&self.config.output_dir).join(path)),
+                None => (
+                    work.relative_path.parent().unwrap_or(Path::new("")).join(path),
+                    work.path.parent().unwrap_or(Path::new(".")).join(path),
+                ),
+            };
+            let bytes = std::fs::read(&path).map_err(|e| anyhow!("{:?}: {}", path, e))?;
+            (normalized(&relative).display().to_string(), bytes)
+        };
+        Ok(Some((location, bytes)).filter(|(_, bytes)| bytes.len() <= MAX_IMAGE_BYTES))
+    }
+
+    /// The description item and the generated items for one image.
+    async fn image_items(&self, work: &FileWork, reference: &ImageRef, image: Vec<u8>) -> Result<Vec<ProcessedItem>> {
+        let image = BASE64.encode(image);
+        let description = match self.config.images.describe {
+            true => Some(self.processor.describe_image(work, reference, &image).await?),
+            false => None,
+        };
+        let mut items = self.processor.generate_image(work, reference, image, description.as_deref()).await?;
+        if let Some(description) = description.filter(|description| !description.is_empty()) {
+            items.insert(0, ProcessedItem {
+                id: None,
+                question: describe_question(reference, &work.title),
+                answer: description,
+                citation: None,
+                source: None,
+                hash: None,
+                extra: Default::default(),
+                embedding: None,
+                source_overlap: None,
+            });
+        }
+        Ok(items)
+    }
+}
+
+#[async_trait]
+impl Stage for ImageStage {
+    fn name(&self) -> &'static str {
+        "images"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        if work.stream.is_some() {
+            return Ok(());
+        }
+        let file = work.relative_path.display().to_string();
+        let mut questions: HashSet<String> = work.items.iter().chain(&work.existing).map(dataset::question_key).collect();
+        let mut sources = HashSet::new();
+        let mut images = 0;
+        let mut items = Vec::new();
+        for section in &work.sections {
+            for reference in references(&section.text) {
+                if !sources.insert(reference.src.clone()) {
+                    continue;
+                }
+                let (location, image) = match self.load(work, &reference.src).await {
+                    Ok(Some(image)) => image,
+                    Ok(None) => {
+                        debug!("Skipping image {:?}: unsupported format or too large", reference.src);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Failed to read image {:?} of {:?}: {}", reference.src, work.path, e);
+                        continue;
+                    }
+                };
+                let generated = match self.image_items(work, &reference, image).await {
+                    Ok(generated) => generated,
+                    Err(e) => {
+                        warn!("Failed to generate items for image {:?} of {:?}: {}", reference.src, work.path, e);
+                        continue;
+                    }
+                };
+                images += 1;
+                for mut item in generated {
+                    if !questions.insert(dataset::question_key(&item)) {
+                        continue;
+                    }
+                    item.extra.insert(MODALITY_FIELD.to_string(), IMAGE_DERIVED.into());
+                    items.push(ProcessedItem {
+                        citation: self.config.output.citations.then(|| reference.markdown.clone()),
+                        source: Some(ItemSource {
+                            file: file.clone(),
+                            section: section.index,
+                            chunk: Some(section.id()),
+                            image: Some(location.clone()),
+                            modified: work.modified,
+                        }),
+                        ..item
+                    });
+                }
+            }
+        }
+        if images > 0 {
+            info!("Generated {} items from {} images of {:?}", items.len(), images, work.path);
+        }
+        work.items.extend(items);
+        work.usage = self.processor.usage().file_usage(&work.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn references_are_found_in_order() {
+        let references = references("See ![Main view](<shots/main.png> \"Main\") and ![](/img/b.jpg).");
+        let found: Vec<(&str, &str)> = references.iter().map(|r| (r.alt.as_str(), r.src.as_str())).collect();
+        assert_eq!(found, [("Main view", "shots/main.png"), ("", "/img/b.jpg")]);
+        assert_eq!(strip_references("Open ![the menu](menu.png) first."), "Open the menu first.");
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalized(Path::new("docs/../img/./a.png")), Path::new("img/a.png"));
+        assert_eq!(normalized(Path::new("../a.png")), Path::new("../a.png"));
+    }
+
+    #[test]
+    fn questions_name_the_caption() {
+        let captioned = &references("![Architecture](arch.png)")[0];
+        let plain = &references("![](arch.png)")[0];
+        assert_eq!(describe_question(captioned, "Setup"), "What does the \"Architecture\" image in \"Setup\" show?");
+        assert_eq!(describe_question(plain, "Setup"), "What does the image in \"Setup\" show?");
+    }
+
+    #[tokio::test]
+    async fn images_are_loaded_relative_to_the_file_or_the_root() {
+        let dir = std::env::temp_dir().join(format!("llmds-images-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(dir.join("docs/img")).unwrap();
+        fs::create_dir_all(dir.join("img")).unwrap();
+        fs::write(dir.join("docs/img/arch.png"), "png").unwrap();
+        fs::write(dir.join("docs/img/arch.svg"), "<svg/>").unwrap();
+        fs::write(dir.join("img/logo.png"), "logo").unwrap();
+        let config = Arc::new(Config {
+            output_dir: dir.display().to_string(),
+            ..Config::default()
+        });
+        let processor = Arc::new(OllamaProcessor::new(config.clone(), Arc::new(Metrics::new())));
+        let stage = ImageStage::new(config, processor);
+        let work = FileWork::new(&dir.join("docs/setup.md"), &dir);
+
+        let (location, bytes) = stage.load(&work, "img/arch.png?raw#top").await.unwrap().unwrap();
+        assert_eq!((location.as_str(), bytes.as_slice()), ("docs/img/arch.png", b"png".as_slice()));
+        let (location, _) = stage.load(&work, "/img/logo.png").await.unwrap().unwrap();
+        assert_eq!(location, "img/logo.png");
+        assert!(stage.load(&work, "img/arch.svg").await.unwrap().is_none());
+        assert!(stage.load(&work, "img/missing.png").await.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
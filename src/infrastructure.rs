@@ -0,0 +1,410 @@
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use serde_yaml::Value;
+use crate::compression;
+
+/// Top-level Terraform blocks, in the order they are summarized.
+const TERRAFORM_BLOCKS: &[&str] = &["terraform", "provider", "variable", "locals", "data", "resource", "module", "output"];
+
+fn file_name(path: &Path) -> String {
+    let path = compression::inner_path(path);
+    path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_lowercase()
+}
+
+fn is_terraform(path: &Path) -> bool {
+    file_name(path).ends_with(".tf")
+}
+
+/// Whether `path` is infrastructure code: Terraform (`.tf`), or a YAML file of
+/// Kubernetes manifests, recognized by their top-level `apiVersion` and `kind` keys.
+pub fn is_infrastructure(path: &Path, content: &str) -> bool {
+    let name = file_name(path);
+    if is_terraform(path) {
+        return true;
+    }
+    (name.ends_with(".yaml") || name.ends_with(".yml"))
+        && content.lines().any(|line| line.starts_with("apiVersion:"))
+        && content.lines().any(|line| line.starts_with("kind:"))
+}
+
+/// `text`, the infrastructure code at `path`, as Markdown: a summary of everything the
+/// file defines, then a section for each resource, module, variable and output (or
+/// Kubernetes object) with its code, so questions can be asked about the whole and
+/// about each part.
+pub fn render(path: &Path, text: &str) -> Result<String> {
+    let name = file_name(path);
+    match is_terraform(path) {
+        true => terraform(name.trim_end_matches(".tf"), text),
+        false => kubernetes(name.trim_end_matches(".yaml").trim_end_matches(".yml"), text),
+    }
+}
+
+/// A top-level Terraform block, e.g. `resource "aws_instance" "web" { ... }`.
+struct Block {
+    kind: String,
+    labels: Vec<String>,
+    /// The comment lines right above the block.
+    comment: String,
+    /// The block as written.
+    code: String,
+    body: String,
+}
+
+impl Block {
+    /// `aws_instance.web` for resources, `data.aws_ami.ubuntu` for data sources and the
+    /// label for anything else.
+    fn address(&self) -> String {
+        match self.kind.as_str() {
+            "data" => format!("data.{}", self.labels.join(".")),
+            _ => self.labels.join("."),
+        }
+    }
+
+    /// The expression assigned to `key` at the top level of the block, on one line.
+    fn attribute(&self, key: &str) -> Option<String> {
+        let mut depth = 0i32;
+        for line in self.body.lines() {
+            let trimmed = line.trim();
+            if depth == 0 {
+                if let Some((name, value)) = trimmed.split_once('=') {
+                    if name.trim() == key && !value.starts_with('=') {
+                        return Some(value.trim().trim_matches('"').to_string());
+                    }
+                }
+            }
+            depth += trimmed.matches(['{', '[', '(']).count() as i32 - trimmed.matches(['}', ']', ')']).count() as i32;
+        }
+        None
+    }
+}
+
+/// The index of the `}` closing the block whose body starts at `start`, skipping strings,
+/// comments and heredocs.
+fn block_end(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 1;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'#' => i = text[i..].find('\n').map_or(bytes.len(), |end| i + end),
+            b'/' if bytes.get(i + 1) == Some(&b'/') => i = text[i..].find('\n').map_or(bytes.len(), |end| i + end),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = text[i..].find("*/").map_or(bytes.len(), |end| i + end + 1),
+            b'<' if text[i..].starts_with("<<") => {
+                let marker: String = text[i + 2..].trim_start_matches('-').chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+                if !marker.is_empty() {
+                    // The heredoc runs to a line holding only its marker
+                    let mut offset = text[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+                    while offset < bytes.len() {
+                        let line_end = text[offset..].find('\n').map_or(bytes.len(), |end| offset + end);
+                        if text[offset..line_end].trim() == marker {
+                            break;
+                        }
+                        offset = line_end + 1;
+                    }
+                    i = offset;
+                }
+            }
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The top-level blocks of a Terraform file.
+fn terraform_blocks(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut comment: Vec<&str> = Vec::new();
+    let mut offset = 0;
+    while offset < text.len() {
+        let line_end = text[offset..].find('\n').map_or(text.len(), |end| offset + end);
+        let line = &text[offset..line_end];
+        let trimmed = line.trim();
+        let kind = trimmed.split(|c: char| c.is_whitespace() || c == '{').next().unwrap_or("");
+        if let Some(text_comment) = trimmed.strip_prefix('#').or_else(|| trimmed.strip_prefix("//")) {
+            comment.push(text_comment.trim());
+        } else if TERRAFORM_BLOCKS.contains(&kind) && line.starts_with(kind) && trimmed.contains('{') {
+            let open = offset + line.find('{').unwrap_or(0);
+            let labels = line[kind.len()..line.find('{').unwrap_or(line.len())]
+                .split('"')
+                .map(str::trim)
+                .filter(|label| !label.is_empty())
+                .map(str::to_string)
+                .collect();
+            let end = block_end(text, open + 1).unwrap_or(text.len() - 1);
+            blocks.push(Block {
+                kind: kind.to_string(),
+                labels,
+                comment: comment.join(" "),
+                code: text[offset..=end.min(text.len() - 1)].to_string(),
+                body: text[open + 1..end].to_string(),
+            });
+            comment.clear();
+            offset = text[end..].find('\n').map_or(text.len(), |next| end + next + 1);
+            continue;
+        } else {
+            comment.clear();
+        }
+        offset = line_end + 1;
+    }
+    blocks
+}
+
+fn terraform(stem: &str, text: &str) -> Result<String> {
+    let blocks = terraform_blocks(text);
+    if blocks.is_empty() {
+        return Err(anyhow!("No Terraform blocks found"));
+    }
+    let mut markdown = format!("# {} Terraform configuration\n\n## Summary\n\n", stem);
+    let listed = |kind: &str| -> Vec<String> {
+        blocks.iter().filter(|block| block.kind == kind).map(|block| format!("`{}`", block.address())).collect()
+    };
+    for (kind, label) in [
+        ("resource", "Resources created"),
+        ("data", "Data sources read"),
+        ("module", "Modules called"),
+        ("variable", "Input variables"),
+        ("output", "Outputs"),
+        ("provider", "Providers configured"),
+    ] {
+        let listed = listed(kind);
+        if !listed.is_empty() {
+            markdown.push_str(&format!("- {} ({}): {}\n", label, listed.len(), listed.join(", ")));
+        }
+    }
+    markdown.push('\n');
+    let mut ordered: Vec<&Block> = blocks.iter().collect();
+    ordered.sort_by_key(|block| TERRAFORM_BLOCKS.iter().position(|kind| *kind == block.kind));
+    for block in ordered {
+        match block.labels.is_empty() {
+            true => markdown.push_str(&format!("## `{}`\n\n", block.kind)),
+            false => markdown.push_str(&format!("## {} `{}`\n\n", block.kind, block.address())),
+        }
+        if !block.comment.is_empty() {
+            markdown.push_str(&format!("{}\n\n", block.comment));
+        }
+        let facts: &[(&str, &str)] = match block.kind.as_str() {
+            "variable" => &[("description", "Description"), ("type", "Type"), ("default", "Default")],
+            "output" => &[("description", "Description"), ("value", "Value")],
+            "module" => &[("source", "Source"), ("version", "Version")],
+            "resource" | "data" => &[("count", "Count"), ("for_each", "For each"), ("provider", "Provider")],
+            _ => &[],
+        };
+        let facts: Vec<String> = facts
+            .iter()
+            .filter_map(|(key, label)| block.attribute(key).map(|value| format!("- {}: {}", label, value)))
+            .collect();
+        if !facts.is_empty() {
+            markdown.push_str(&format!("{}\n\n", facts.join("\n")));
+        }
+        markdown.push_str(&format!("```hcl\n{}\n```\n\n", block.code.trim_end()));
+    }
+    Ok(markdown)
+}
+
+fn get<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "null".to_string(),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    }
+}
+
+/// The facts most questions about a Kubernetes object are about: replicas, containers
+/// and their images, ports and resources, service ports and ingress rules.
+fn object_facts(kind: &str, object: &Value) -> Vec<String> {
+    let mut facts = Vec::new();
+    if let Some(namespace) = get(object, &["metadata", "namespace"]) {
+        facts.push(format!("Namespace: {}", scalar(namespace)));
+    }
+    if let Some(Value::Mapping(labels)) = get(object, &["metadata", "labels"]) {
+        let labels: Vec<String> = labels.iter().map(|(key, value)| format!("{}={}", scalar(key), scalar(value))).collect();
+        facts.push(format!("Labels: {}", labels.join(", ")));
+    }
+    let pod = match kind {
+        "Deployment" | "StatefulSet" | "ReplicaSet" => {
+            // Unset replicas default to 1
+            let replicas = get(object, &["spec", "replicas"]).map_or("1 (default)".to_string(), scalar);
+            facts.push(format!("Replicas: {}", replicas));
+            get(object, &["spec", "template", "spec"])
+        }
+        "DaemonSet" | "Job" => get(object, &["spec", "template", "spec"]),
+        "CronJob" => {
+            if let Some(schedule) = get(object, &["spec", "schedule"]) {
+                facts.push(format!("Schedule: {}", scalar(schedule)));
+            }
+            get(object, &["spec", "jobTemplate", "spec", "template", "spec"])
+        }
+        "Pod" => get(object, &["spec"]),
+        _ => None,
+    };
+    if let Some(Value::Sequence(containers)) = pod.and_then(|pod| pod.get("containers")) {
+        for container in containers {
+            let name = container.get("name").map(scalar).unwrap_or_default();
+            let mut fact = format!("Container {}: image {}", name, container.get("image").map(scalar).unwrap_or_default());
+            if let Some(Value::Sequence(ports)) = container.get("ports") {
+                let ports: Vec<String> = ports.iter().filter_map(|port| port.get("containerPort")).map(scalar).collect();
+                fact.push_str(&format!(", ports {}", ports.join(", ")));
+            }
+            for (key, label) in [("requests", "requests"), ("limits", "limits")] {
+                if let Some(Value::Mapping(resources)) = get(container, &["resources", key]) {
+                    let resources: Vec<String> = resources.iter().map(|(key, value)| format!("{} {}", scalar(key), scalar(value))).collect();
+                    fact.push_str(&format!(", {} {}", label, resources.join(" and ")));
+                }
+            }
+            facts.push(fact);
+        }
+    }
+    match kind {
+        "Service" => {
+            facts.push(format!("Type: {}", get(object, &["spec", "type"]).map_or("ClusterIP".to_string(), scalar)));
+            if let Some(Value::Sequence(ports)) = get(object, &["spec", "ports"]) {
+                for port in ports {
+                    let target = port.get("targetPort").or_else(|| port.get("port")).map(scalar).unwrap_or_default();
+                    facts.push(format!("Port {} to target port {}", port.get("port").map(scalar).unwrap_or_default(), target));
+                }
+            }
+            if let Some(Value::Mapping(selector)) = get(object, &["spec", "selector"]) {
+                let selector: Vec<String> = selector.iter().map(|(key, value)| format!("{}={}", scalar(key), scalar(value))).collect();
+                facts.push(format!("Selector: {}", selector.join(", ")));
+            }
+        }
+        "Ingress" => {
+            if let Some(Value::Sequence(rules)) = get(object, &["spec", "rules"]) {
+                for rule in rules {
+                    let host = rule.get("host").map(scalar).unwrap_or_else(|| "any host".to_string());
+                    let Some(Value::Sequence(paths)) = get(rule, &["http", "paths"]) else {
+                        continue;
+                    };
+                    for path in paths {
+                        let service = get(path, &["backend", "service", "name"]).or_else(|| get(path, &["backend", "serviceName"]));
+                        facts.push(format!(
+                            "Route {}{} to service {}",
+                            host,
+                            path.get("path").map(scalar).unwrap_or_else(|| "/".to_string()),
+                            service.map(scalar).unwrap_or_default()
+                        ));
+                    }
+                }
+            }
+        }
+        "ConfigMap" | "Secret" => {
+            let keys: Vec<String> = ["data", "stringData"]
+                .iter()
+                .filter_map(|key| object.get(key).and_then(Value::as_mapping))
+                .flat_map(|data| data.keys().map(scalar))
+                .collect();
+            if !keys.is_empty() {
+                facts.push(format!("Keys: {}", keys.join(", ")));
+            }
+        }
+        _ => {}
+    }
+    facts
+}
+
+/// A Secret with its values replaced, so they stay out of the dataset.
+fn redacted(secret: &Value) -> Value {
+    let mut secret = secret.clone();
+    for key in ["data", "stringData"] {
+        if let Some(Value::Mapping(data)) = secret.get_mut(key) {
+            for (_, value) in data.iter_mut() {
+                *value = Value::String("<redacted>".to_string());
+            }
+        }
+    }
+    secret
+}
+
+fn kubernetes(stem: &str, text: &str) -> Result<String> {
+    let mut objects = Vec::new();
+    // Documents are split by hand so each object's own YAML can be shown
+    let mut document = String::new();
+    for line in text.lines().chain(std::iter::once("---")) {
+        if line.starts_with("---") {
+            let value: Value = serde_yaml::from_str(&document).map_err(|e| anyhow!("Invalid YAML: {}", e))?;
+            if let Some(kind) = value.get("kind").map(scalar) {
+                objects.push((kind, value, document.trim().to_string()));
+            }
+            document.clear();
+        } else {
+            document.push_str(line);
+            document.push('\n');
+        }
+    }
+    if objects.is_empty() {
+        return Err(anyhow!("No Kubernetes objects found"));
+    }
+    let name = |object: &Value| get(object, &["metadata", "name"]).map(scalar).unwrap_or_default();
+    let mut markdown = format!("# {} Kubernetes manifests\n\n## Summary\n\nThe manifests define {} objects:\n", stem, objects.len());
+    for (kind, object, _) in &objects {
+        markdown.push_str(&format!("- {} `{}`\n", kind, name(object)));
+    }
+    markdown.push('\n');
+    for (kind, object, code) in &objects {
+        markdown.push_str(&format!("## {} `{}`\n\n", kind, name(object)));
+        let facts = object_facts(kind, object);
+        if !facts.is_empty() {
+            let facts: Vec<String> = facts.iter().map(|fact| format!("- {}", fact)).collect();
+            markdown.push_str(&format!("{}\n\n", facts.join("\n")));
+        }
+        match kind.as_str() {
+            "Secret" => markdown.push_str(&format!("```yaml\n{}```\n\n", serde_yaml::to_string(&redacted(object))?)),
+            _ => markdown.push_str(&format!("```yaml\n{}\n```\n\n", code)),
+        }
+    }
+    Ok(markdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terraform_is_summarized_a_section_per_block() {
+        let text = "output \"ip\" {\n  value = aws_instance.web.public_ip\n}\n\n# The web server\nresource \"aws_instance\" \"web\" {\n  count = 2\n  user_data = <<-EOT\n    echo }\n  EOT\n  tags = { Name = \"web\" }\n}\n\nvariable \"region\" {\n  type    = string\n  default = \"eu-west-1\"\n}\n";
+        let markdown = render(Path::new("infra/main.tf"), text).unwrap();
+        assert_eq!(
+            markdown,
+            "# main Terraform configuration\n\n## Summary\n\n\
+             - Resources created (1): `aws_instance.web`\n- Input variables (1): `region`\n- Outputs (1): `ip`\n\n\
+             ## variable `region`\n\n- Type: string\n- Default: eu-west-1\n\n\
+             ```hcl\nvariable \"region\" {\n  type    = string\n  default = \"eu-west-1\"\n}\n```\n\n\
+             ## resource `aws_instance.web`\n\nThe web server\n\n- Count: 2\n\n\
+             ```hcl\nresource \"aws_instance\" \"web\" {\n  count = 2\n  user_data = <<-EOT\n    echo }\n  EOT\n  tags = { Name = \"web\" }\n}\n```\n\n\
+             ## output `ip`\n\n- Value: aws_instance.web.public_ip\n\n\
+             ```hcl\noutput \"ip\" {\n  value = aws_instance.web.public_ip\n}\n```\n\n"
+        );
+        assert!(render(Path::new("empty.tf"), "# nothing here\n").is_err());
+    }
+
+    #[test]
+    fn kubernetes_objects_are_summarized_with_secrets_redacted() {
+        let text = "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\nspec:\n  template:\n    spec:\n      containers:\n        - name: api\n          image: acme/api:1.2\n          ports:\n            - containerPort: 8080\n---\napiVersion: v1\nkind: Secret\nmetadata:\n  name: api-token\nstringData:\n  token: hunter2\n";
+        assert!(is_infrastructure(Path::new("deploy/api.yaml"), text));
+        assert!(!is_infrastructure(Path::new("deploy/values.yaml"), "replicas: 2\n"));
+        let markdown = render(Path::new("deploy/api.yaml"), text).unwrap();
+        assert!(markdown.starts_with("# api Kubernetes manifests\n\n## Summary\n\nThe manifests define 2 objects:\n- Deployment `api`\n- Secret `api-token`\n\n"), "{markdown}");
+        assert!(markdown.contains("## Deployment `api`\n\n- Replicas: 1 (default)\n- Container api: image acme/api:1.2, ports 8080\n\n```yaml\napiVersion: apps/v1\n"), "{markdown}");
+        assert!(markdown.contains("- Keys: token\n"));
+        assert!(markdown.contains("token: <redacted>"));
+        assert!(!markdown.contains("hunter2"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::{info, warn};
+use crate::config::InstructionsConfig;
+use crate::dataset;
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::OllamaProcessor;
+
+/// Field marking items whose question was rewritten as an instruction.
+pub const PHRASING_FIELD: &str = "phrasing";
+pub const INSTRUCTION: &str = "instruction";
+
+pub const SYSTEM_PROMPT: &str = "You rewrite questions as imperative instructions with the same meaning, the way \
+    a user would phrase a request to an assistant: \"How do I configure X?\" becomes \"Explain how to configure X.\", \
+    \"What does Y return?\" becomes \"Describe what Y returns.\" Keep every name, value and detail of the question, \
+    and rewrite each question on its own, in order. Respond as JSON.";
+
+/// The user message asking for `questions` as instructions.
+pub fn user_prompt(questions: &[&str]) -> String {
+    let mut prompt = format!("Rewrite these {} questions as instructions:\n", questions.len());
+    for (i, question) in questions.iter().enumerate() {
+        prompt.push_str(&format!("{}. {}\n", i + 1, question));
+    }
+    prompt
+}
+
+/// Rewrites the questions of `instructions.fraction` of the file's items as imperative
+/// instructions ("Explain how to configure X."), in one request per file, so the dataset
+/// covers both phrasings. Rewritten items are tagged `phrasing: instruction`; when the
+/// request fails, the file's items keep their questions.
+pub struct InstructionStage {
+    config: InstructionsConfig,
+    processor: Arc<OllamaProcessor>,
+}
+
+impl InstructionStage {
+    pub fn new(config: InstructionsConfig, processor: Arc<OllamaProcessor>) -> Self {
+        Self { config, processor }
+    }
+}
+
+#[async_trait]
+impl Stage for InstructionStage {
+    fn name(&self) -> &'static str {
+        "instructions"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        let picked: Vec<usize> = (0..work.items.len())
+            .filter(|&i| {
+                let item = &work.items[i];
+                dataset::picked(item, self.config.fraction, INSTRUCTION) && !item.extra.contains_key(PHRASING_FIELD)
+            })
+            .collect();
+        if picked.is_empty() {
+            return Ok(());
+        }
+        let count = picked.len();
+        let questions: Vec<&str> = picked.iter().map(|&i| work.items[i].question.as_str()).collect();
+        match self.processor.rewrite_as_instructions(work, &questions).await {
+            Ok(instructions) => {
+                for (i, instruction) in picked.into_iter().zip(instructions) {
+                    let item = &mut work.items[i];
+                    item.question = instruction;
+                    item.extra.insert(PHRASING_FIELD.to_string(), INSTRUCTION.into());
+                }
+                info!("Rewrote {} questions of {:?} as instructions", count, work.path);
+            }
+            Err(e) => warn!("Failed to rewrite the questions of {:?} as instructions: {}", work.path, e),
+        }
+        work.usage = self.processor.usage().file_usage(&work.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::config::Config;
+    use crate::metrics::Metrics;
+    use crate::processor::ProcessedItem;
+
+    fn item(question: &str) -> ProcessedItem {
+        serde_json::from_value(serde_json::json!({ "question": question, "answer": "Run the installer." })).unwrap()
+    }
+
+    #[test]
+    fn prompt_numbers_the_questions() {
+        assert_eq!(user_prompt(&["How?", "Why?"]), "Rewrite these 2 questions as instructions:\n1. How?\n2. Why?\n");
+    }
+
+    #[tokio::test]
+    async fn questions_are_kept_when_rewriting_fails() {
+        let config = Arc::new(Config {
+            endpoint: "http://127.0.0.1:1".to_string(),
+            ..Config::default()
+        });
+        let mut work = FileWork::new(Path::new("guide.md"), Path::new(""));
+        work.settings = Some(config.settings_for(Path::new("guide.md"), "").unwrap());
+        let mut rewritten = item("Describe the installer.");
+        rewritten.extra.insert(PHRASING_FIELD.to_string(), INSTRUCTION.into());
+        work.items = vec![item("How do I install it?"), rewritten];
+
+        let processor = Arc::new(OllamaProcessor::new(config, Arc::new(Metrics::new())));
+        InstructionStage::new(InstructionsConfig { fraction: 1.0 }, processor).process(&mut work).await.unwrap();
+        assert_eq!(work.items[0].question, "How do I install it?");
+        assert!(!work.items[0].extra.contains_key(PHRASING_FIELD));
+        assert_eq!(work.items[1].extra[PHRASING_FIELD], INSTRUCTION);
+    }
+}
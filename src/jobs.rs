@@ -0,0 +1,437 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Result, anyhow};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn, Instrument};
+use crate::cluster::ChunkQueue;
+use crate::config::Config;
+use crate::datasource::{DataSource, LocalSource};
+use crate::generate;
+use crate::hooks::Hooks;
+use crate::progress::Progress;
+
+/// How long an idle worker waits before looking for queued jobs again.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a running job's owner renews its lease.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a running job's lease lasts without a heartbeat; after that its owner is
+/// presumed dead and the job is queued again.
+const LEASE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+impl JobStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Finished => "finished",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "running" => JobStatus::Running,
+            "finished" => JobStatus::Finished,
+            "failed" => JobStatus::Failed,
+            _ => JobStatus::Queued,
+        }
+    }
+}
+
+/// A file uploaded with a job request.
+#[derive(Debug, Deserialize)]
+pub struct UploadedFile {
+    pub name: String,
+    pub content: String,
+}
+
+/// What to generate from: source URLs or server-side paths, and/or uploaded files.
+#[derive(Debug, Default, Deserialize)]
+pub struct JobRequest {
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<UploadedFile>,
+    /// Overrides the configured model for this job.
+    pub model: Option<String>,
+    /// Content profile used for every file of the job instead of the rules.
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: i64,
+    pub status: JobStatus,
+    pub sources: Vec<String>,
+    pub files: Vec<String>,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    /// Run report once the job has finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    uploads: Option<PathBuf>,
+}
+
+/// Identifies this process as the owner of the jobs it runs.
+fn owner() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    format!("{}-{}", host, std::process::id())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Job queue persisted in SQLite, so queued work survives restarts and can be shared by
+/// `serve`, `daemon` and `enqueue` processes.
+pub struct JobStore {
+    conn: Mutex<Connection>,
+}
+
+const JOB_COLUMNS: &str =
+    "id, status, sources, files, model, profile, created_at, started_at, finished_at, report, error, uploads";
+
+impl JobStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                status TEXT NOT NULL,
+                sources TEXT NOT NULL,
+                files TEXT NOT NULL,
+                model TEXT NOT NULL,
+                profile TEXT,
+                created_at INTEGER NOT NULL,
+                started_at INTEGER,
+                finished_at INTEGER,
+                report TEXT,
+                error TEXT,
+                uploads TEXT,
+                owner TEXT,
+                heartbeat INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS jobs_status ON jobs (status, id);",
+        )?;
+        // Queues created before leases lack their columns
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info('jobs')")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (column, kind) in [("owner", "TEXT"), ("heartbeat", "INTEGER")] {
+            if !columns.iter().any(|c| c == column) {
+                conn.execute(&format!("ALTER TABLE jobs ADD COLUMN {} {}", column, kind), [])?;
+            }
+        }
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn job_from_row(row: &Row) -> rusqlite::Result<Job> {
+        let json_list = |index: usize| -> rusqlite::Result<Vec<String>> {
+            Ok(serde_json::from_str(&row.get::<_, String>(index)?).unwrap_or_default())
+        };
+        Ok(Job {
+            id: row.get(0)?,
+            status: JobStatus::parse(&row.get::<_, String>(1)?),
+            sources: json_list(2)?,
+            files: json_list(3)?,
+            model: row.get(4)?,
+            profile: row.get(5)?,
+            created_at: row.get(6)?,
+            started_at: row.get(7)?,
+            finished_at: row.get(8)?,
+            report: row
+                .get::<_, Option<String>>(9)?
+                .and_then(|report| serde_json::from_str(&report).ok()),
+            error: row.get(10)?,
+            uploads: row.get::<_, Option<String>>(11)?.map(PathBuf::from),
+        })
+    }
+
+    fn insert(&self, sources: &[String], files: &[String], model: &str, profile: Option<&str>, uploads: Option<&Path>) -> Result<Job> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO jobs (status, sources, files, model, profile, created_at, uploads) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                JobStatus::Queued.as_str(),
+                serde_json::to_string(sources)?,
+                serde_json::to_string(files)?,
+                model,
+                profile,
+                now(),
+                uploads.map(|p| p.display().to_string()),
+            ],
+        )?;
+        let id = conn.last_insert_rowid();
+        Ok(conn.query_row(&format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS), [id], Self::job_from_row)?)
+    }
+
+    pub fn list(&self) -> Result<Vec<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!("SELECT {} FROM jobs ORDER BY id", JOB_COLUMNS))?;
+        let jobs = statement.query_map([], Self::job_from_row)?.collect::<rusqlite::Result<_>>()?;
+        Ok(jobs)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Job>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(&format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS), [id], Self::job_from_row)
+            .optional()?)
+    }
+
+    /// Atomically marks the oldest queued job as running under `owner`'s lease and
+    /// returns it.
+    fn claim_next(&self, owner: &str) -> Result<Option<Job>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                &format!(
+                    "UPDATE jobs SET status = 'running', started_at = ?1, owner = ?2, heartbeat = ?1
+                     WHERE id = (SELECT id FROM jobs WHERE status = 'queued' ORDER BY id LIMIT 1)
+                     RETURNING {}",
+                    JOB_COLUMNS
+                ),
+                params![now(), owner],
+                Self::job_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Renews the lease `owner` holds on a running job.
+    fn heartbeat(&self, id: i64, owner: &str) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE jobs SET heartbeat = ?1 WHERE id = ?2 AND owner = ?3 AND status = 'running'",
+            params![now(), id, owner],
+        )?;
+        Ok(())
+    }
+
+    fn complete(&self, id: i64, status: JobStatus, report: Option<&serde_json::Value>, error: Option<&str>) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE jobs SET status = ?1, finished_at = ?2, report = ?3, error = ?4 WHERE id = ?5",
+            params![status.as_str(), now(), report.map(|r| r.to_string()), error, id],
+        )?;
+        Ok(())
+    }
+
+    /// Puts running jobs whose lease expired, because the process running them stopped,
+    /// back in the queue. Jobs of live processes keep renewing their lease.
+    fn requeue_expired(&self) -> Result<usize> {
+        let expired = now().saturating_sub(LEASE.as_secs());
+        Ok(self.conn.lock().unwrap().execute(
+            "UPDATE jobs SET status = 'queued', started_at = NULL, owner = NULL, heartbeat = NULL
+             WHERE status = 'running' AND (heartbeat IS NULL OR heartbeat < ?1)",
+            [expired],
+        )?)
+    }
+}
+
+/// Accepts generation jobs into the queue and runs them with a bounded number of
+/// workers, each job in its own directory under `<output_dir>/jobs/<id>`.
+pub struct JobManager {
+    config: Config,
+    store: JobStore,
+    chunks: Option<Arc<ChunkQueue>>,
+    owner: String,
+}
+
+impl JobManager {
+    pub fn new(config: Config) -> Result<Self> {
+        let store = JobStore::open(&Path::new(&config.output_dir).join("jobs").join("jobs.sqlite"))?;
+        Ok(Self { config, store, chunks: None, owner: owner() })
+    }
+
+    /// Runs jobs on distributed workers that pull their sections from `queue`.
+    pub fn with_chunk_queue(mut self, queue: Arc<ChunkQueue>) -> Self {
+        self.chunks = Some(queue);
+        self
+    }
+
+    fn jobs_dir(&self) -> PathBuf {
+        Path::new(&self.config.output_dir).join("jobs")
+    }
+
+    /// Output directory of a job; the generated files and combined dataset end up here.
+    pub fn output_dir(&self, id: i64) -> PathBuf {
+        self.jobs_dir().join(id.to_string()).join("output")
+    }
+
+    pub fn list(&self) -> Result<Vec<Job>> {
+        self.store.list()
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Job>> {
+        self.store.get(id)
+    }
+
+    /// Validates a request, stores its uploads and queues it. Relative local paths are
+    /// made absolute, as the job may run in a process with another working directory.
+    pub fn submit(&self, mut request: JobRequest) -> Result<Job> {
+        if request.sources.is_empty() && request.files.is_empty() {
+            return Err(anyhow!("A job needs at least one source or file"));
+        }
+        for source in &mut request.sources {
+            generate::parse_source(source, &self.config)?;
+            if Path::new(source).is_relative() && Path::new(source).exists() {
+                *source = fs::canonicalize(&*source)?.display().to_string();
+            }
+        }
+        if let Some(profile) = &request.profile {
+            if !self.config.has_profile(profile) {
+                return Err(anyhow!("Unknown profile {:?}", profile));
+            }
+        }
+
+        let mut file_names = Vec::new();
+        let uploads = if request.files.is_empty() {
+            None
+        } else {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+            let uploads = self.jobs_dir().join("uploads").join(format!("{}-{}", nanos, std::process::id()));
+            fs::create_dir_all(&uploads)?;
+            for file in &request.files {
+                // Only keep the file name so uploads can't escape the upload directory
+                let name = Path::new(&file.name)
+                    .file_name()
+                    .ok_or_else(|| anyhow!("Invalid file name {:?}", file.name))?;
+                fs::write(uploads.join(name), &file.content)?;
+                file_names.push(name.to_string_lossy().into_owned());
+            }
+            Some(uploads)
+        };
+
+        let model = request.model.unwrap_or_else(|| self.config.model.clone());
+        let job = self.store.insert(&request.sources, &file_names, &model, request.profile.as_deref(), uploads.as_deref())?;
+        info!("Queued job {}", job.id);
+        Ok(job)
+    }
+
+    /// Starts `concurrency` workers that run queued jobs until the process exits, and
+    /// requeue the jobs of processes that stopped while running them.
+    pub fn start_workers(self: &Arc<Self>, concurrency: usize) -> Result<()> {
+        if concurrency == 0 {
+            return Ok(());
+        }
+        self.requeue_expired()?;
+        for worker in 0..concurrency {
+            let manager = self.clone();
+            tokio::spawn(async move { manager.work().await }.instrument(tracing::info_span!("worker", worker)));
+        }
+        info!("Started {} job workers", concurrency);
+        Ok(())
+    }
+
+    fn requeue_expired(&self) -> Result<()> {
+        let requeued = self.store.requeue_expired()?;
+        if requeued > 0 {
+            warn!("Requeued {} jobs whose worker stopped", requeued);
+        }
+        Ok(())
+    }
+
+    async fn work(&self) {
+        loop {
+            if let Err(e) = self.requeue_expired() {
+                error!("Failed to requeue expired jobs: {}", e);
+            }
+            match self.store.claim_next(&self.owner) {
+                Ok(Some(job)) => {
+                    let id = job.id;
+                    self.run(job).instrument(tracing::info_span!("job", id)).await;
+                }
+                Ok(None) => tokio::time::sleep(POLL_INTERVAL).await,
+                Err(e) => {
+                    error!("Failed to read the job queue: {}", e);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    async fn run(&self, job: Job) {
+        info!("Running job {}", job.id);
+        let mut config = self.config.clone();
+        config.output_dir = self.output_dir(job.id).display().to_string();
+        config.model = job.model.clone();
+        if job.profile.is_some() {
+            config.profile = job.profile.clone();
+        }
+
+        let generation = async {
+            match job
+                .sources
+                .iter()
+                .map(|source| generate::parse_source(source, &config))
+                .collect::<Result<Vec<Box<dyn DataSource>>>>()
+            {
+                Ok(mut sources) => {
+                    if let Some(uploads) = &job.uploads {
+                        sources.push(Box::new(LocalSource::new(uploads)));
+                    }
+                    generate::run_sources(&config, sources, Arc::new(Progress::new(true)), self.chunks.clone(), Hooks::new(&config.hooks))
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        };
+        // Keep the lease while generating, so other processes leave the job alone
+        let heartbeat = async {
+            loop {
+                tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+                if let Err(e) = self.store.heartbeat(job.id, &self.owner) {
+                    warn!("Failed to renew the lease of job {}: {}", job.id, e);
+                }
+            }
+        };
+        let result = tokio::select! {
+            result = generation => result,
+            _ = heartbeat => unreachable!(),
+        };
+
+        let stored = match result {
+            Ok(report) => {
+                info!("Job {} finished", job.id);
+                let report = report.and_then(|report| serde_json::to_value(report).ok());
+                self.store.complete(job.id, JobStatus::Finished, report.as_ref(), None)
+            }
+            Err(e) => {
+                error!("Job {} failed: {}", job.id, e);
+                self.store.complete(job.id, JobStatus::Failed, None, Some(&e))
+            }
+        };
+        if let Err(e) = stored {
+            error!("Failed to record the result of job {}: {}", job.id, e);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Generates question/answer datasets from documentation with a local Ollama model.
+//!
+//! [`DatasetBuilder`] runs the same pipeline as the `generate` command; [`Chunker`],
+//! [`Generator`], [`Filter`] and [`Writer`] expose its steps for embedding them
+//! separately; [`DatasetWriter`] is implemented by every output sink.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let report = llm_dataset_builder::DatasetBuilder::new()
+//!     .with_model("llama3.1")
+//!     .with_output_dir("output")
+//!     .with_source("docs/")
+//!     .run()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod annotation;
+pub mod answer_length;
+pub mod auth;
+pub mod benchmark;
+pub mod budget;
+mod builder;
+pub mod card;
+pub mod changelog;
+pub mod cli_help;
+pub mod cluster;
+pub mod commands;
+pub mod compression;
+pub mod config;
+pub mod config_reference;
+pub mod crawl;
+pub mod curriculum;
+pub mod dataset;
+pub mod datasource;
+mod deprecation;
+pub mod discourse;
+pub mod docs_site;
+pub mod duplicates;
+pub mod embeddings;
+pub mod encoding;
+pub mod error;
+pub mod eval;
+mod failures;
+pub mod faq;
+pub mod filter;
+pub mod finetune;
+pub mod follow_ups;
+pub mod freshness;
+mod frontmatter;
+pub mod generate;
+pub mod help_center;
+pub mod hooks;
+mod html;
+pub mod images;
+pub mod infrastructure;
+pub mod instructions;
+pub mod jobs;
+pub mod logging;
+pub mod logs;
+mod markdown;
+pub mod metrics;
+pub mod noise;
+pub mod notify;
+pub mod ocr;
+pub mod org;
+pub mod pipeline;
+pub mod plugins;
+pub mod preflight;
+pub mod processor;
+pub mod progress;
+mod prompt;
+pub mod reading_level;
+mod readability;
+pub mod refusals;
+pub mod report;
+pub mod review;
+pub mod score;
+pub mod serve;
+pub mod sql_schema;
+pub mod stack_exchange;
+pub mod stream;
+mod strip;
+pub mod synthesis;
+mod telemetry;
+mod terms;
+pub mod tickets;
+pub mod transcribe;
+pub mod usage;
+pub mod vault;
+pub mod writer;
+
+pub use builder::{Chunker, DatasetBuilder, Filter, Generator};
+pub use config::{Config, OutputFormat};
+pub use dataset::RecordFormat;
+pub use error::Error;
+pub use processor::{ItemSource, ProcessedItem};
+pub use report::RunReport;
+pub use writer::{DatasetWriter, Writer};
@@ -0,0 +1,56 @@
+use std::io::IsTerminal;
+use anyhow::Result;
+use opentelemetry_sdk::trace::TracerProvider;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+use crate::progress::Progress;
+use crate::telemetry;
+
+/// Installs the global tracing subscriber. Verbosity maps to `info` by default, `debug`
+/// with `-v` and `trace` with `-vv`; `--quiet` only reports warnings and errors.
+/// `RUST_LOG` overrides all of these when set. When an OTLP endpoint is given, spans are
+/// also exported there regardless of the console verbosity, and the returned provider
+/// has to be shut down at the end of the run.
+pub fn init(verbose: u8, quiet: bool, json: bool, otlp_endpoint: Option<&str>, progress: &Progress) -> Result<Option<TracerProvider>> {
+    let level = if quiet {
+        "warn"
+    } else {
+        match verbose {
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        }
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("llm_dataset_builder={}", level)));
+
+    let fmt_layer = if json {
+        fmt::layer()
+            .json()
+            .with_writer(progress.writer())
+            .boxed()
+    } else {
+        fmt::layer()
+            .with_target(false)
+            .without_time()
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_writer(progress.writer())
+            .boxed()
+    };
+
+    let registry = tracing_subscriber::registry().with(fmt_layer.with_filter(filter));
+
+    match otlp_endpoint {
+        Some(endpoint) => {
+            let (otel_layer, provider) = telemetry::otlp_layer(endpoint)?;
+            registry
+                .with(otel_layer.with_filter(EnvFilter::new("llm_dataset_builder=trace")))
+                .init();
+            Ok(Some(provider))
+        }
+        None => {
+            registry.init();
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::cmp::Reverse;
+use std::path::Path;
+use std::sync::LazyLock;
+use anyhow::{Result, anyhow};
+use regex::{Captures, Regex};
+use crate::compression;
+
+/// Field holding the log lines an item's section quotes, so the item carries the
+/// excerpt it is about.
+pub const EXCERPT_FIELD: &str = "log_excerpt";
+
+/// Lines before an error kept in its excerpt, for what led up to it.
+const CONTEXT_LINES: usize = 3;
+
+/// Most lines of one error entry, its stack trace included.
+const MAX_ENTRY_LINES: usize = 30;
+
+/// Distinct errors given a section, the most frequent first.
+const MAX_ERRORS: usize = 100;
+
+/// Other wordings of an error listed under its excerpt.
+const MAX_VARIANTS: usize = 2;
+
+/// Longest signature, in characters.
+const MAX_SIGNATURE_LEN: usize = 160;
+
+static TIMESTAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\[?(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?|[A-Z][a-z]{2} +\d{1,2} \d{2}:\d{2}:\d{2}|\d{2}:\d{2}:\d{2}(?:[.,]\d+)?)\]?")
+        .unwrap()
+});
+
+/// The start of a log entry: a timestamp, a level, a JSON record or a logfmt key.
+static ENTRY_START: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\[?\d{4}-\d{2}-\d{2}|\[?\d{2}:\d{2}:\d{2}|[A-Z][a-z]{2} +\d{1,2} \d{2}:|\[?(TRACE|DEBUG|INFO|NOTICE|WARN|WARNING|ERROR|FATAL|CRITICAL)\b|\{|\w+=|(?i:error)(\[\w+\])?:)").unwrap()
+});
+
+static ERROR_LEVEL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\b(ERROR|FATAL|CRITICAL|CRIT|SEVERE|PANIC|EMERG|ALERT)\b|\blevel=(?i:error|fatal|critical)\b|"level":\s*"(?i:error|fatal|critical)"|^(?i:error)(\[\w+\])?:|^Traceback \(most recent call last\)|^Exception in thread|\bpanicked at\b"#)
+        .unwrap()
+});
+
+/// A line naming an exception or error type, as in stack traces.
+static EXCEPTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:Caused by: )?([A-Za-z_][\w.$]*(?:Error|Exception|Panic))\b:?(.*)$").unwrap()
+});
+
+static ERROR_CODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b([A-Z][A-Z0-9]*-\d{3,}|[A-Z]{1,4}\d{3,5}|E(?:CONNREFUSED|CONNRESET|CONNABORTED|NOENT|ACCES|PERM|ADDRINUSE|TIMEDOUT|PIPE|HOSTUNREACH|NETUNREACH|EXIST|NOSPC|MFILE)|[A-Z]+_[A-Z][A-Z0-9_]{2,})\b").unwrap()
+});
+
+/// Variable parts of a message, replaced so occurrences of an error share a signature.
+static VARIABLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?x)
+        (?P<id>\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b|\b[0-9a-f]{12,}\b)
+        |(?P<time>\d{4}-\d{2}-\d{2}[T\ ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?)
+        |(?P<ip>\b\d{1,3}(?:\.\d{1,3}){3}(?::\d+)?\b)
+        |(?P<hex>\b0x[0-9a-fA-F]+\b)
+        |(?P<n>\b\d+(?:\.\d+)?(?:ms|s|m|h|[kKMG]i?B)?\b)
+    ").unwrap()
+});
+
+/// Whether `path` is a log file: `app.log`, or rotated as `app.log.1`.
+pub fn is_log(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_lowercase();
+    match name.rsplit_once(".log") {
+        Some((stem, rest)) => !stem.is_empty() && (rest.is_empty() || rest.strip_prefix('.').is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))),
+        None => false,
+    }
+}
+
+/// One error and every entry logging it.
+struct ErrorGroup {
+    signature: String,
+    level: Option<String>,
+    codes: Vec<String>,
+    /// The last exception line of its first stack trace.
+    exception: Option<String>,
+    count: usize,
+    first: Option<String>,
+    last: Option<String>,
+    /// The first entry, with the lines before it.
+    excerpt: Vec<String>,
+    /// First lines of later entries with other values, and the messages seen so far.
+    variants: Vec<String>,
+    messages: Vec<String>,
+}
+
+/// `line` without its timestamp, level and bracketed prefixes such as `[main]`.
+fn message(line: &str) -> &str {
+    let mut rest = line.trim();
+    if let Some(timestamp) = TIMESTAMP.find(rest) {
+        rest = &rest[timestamp.end()..];
+    }
+    if let Some(level) = ERROR_LEVEL.find(rest).filter(|level| level.start() < 40) {
+        rest = &rest[level.end()..];
+    }
+    loop {
+        rest = rest.trim_start_matches([' ', ':', '-', ']', '|', '\t']);
+        match rest.strip_prefix('[').and_then(|inner| inner.split_once(']')) {
+            Some((_, after)) => rest = after,
+            None => return rest.trim_end(),
+        }
+    }
+}
+
+/// `message` with its ids, times, addresses and numbers replaced by placeholders.
+/// Numbers in error codes such as `ORA-00942` or `E123` are kept.
+fn normalize(message: &str) -> String {
+    let normalized = VARIABLE.replace_all(message, |caps: &Captures| {
+        let whole = caps.get(0).unwrap();
+        let before = &message[..whole.start()];
+        if caps.name("n").is_some() && before.ends_with('-') && before[..before.len() - 1].ends_with(|c: char| c.is_ascii_uppercase()) {
+            return whole.as_str().to_string();
+        }
+        let name = ["id", "time", "ip", "hex", "n"].into_iter().find(|name| caps.name(name).is_some()).unwrap_or("n");
+        format!("<{}>", name)
+    });
+    let normalized = normalized.split_whitespace().collect::<Vec<_>>().join(" ");
+    match normalized.char_indices().nth(MAX_SIGNATURE_LEN) {
+        Some((end, _)) => format!("{}…", &normalized[..end]),
+        None => normalized,
+    }
+}
+
+/// The error entries of a log: each error line with the lines continuing it, such as a
+/// stack trace, up to the next entry or a blank line, and the lines before it.
+fn error_entries<'a>(lines: &[&'a str]) -> Vec<(Vec<&'a str>, Vec<&'a str>)> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if !ERROR_LEVEL.is_match(lines[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i + 1;
+        while end < lines.len() && end - start < MAX_ENTRY_LINES && !lines[end].trim().is_empty() && !ENTRY_START.is_match(lines[end]) {
+            end += 1;
+        }
+        let context = lines[start.saturating_sub(CONTEXT_LINES)..start].iter().copied().filter(|line| !line.trim().is_empty()).collect();
+        entries.push((context, lines[start..end].to_vec()));
+        i = end;
+    }
+    entries
+}
+
+/// The errors of a log, the most frequent first.
+fn error_groups(text: &str) -> (usize, Vec<ErrorGroup>) {
+    let lines: Vec<&str> = text.lines().collect();
+    let entries = error_entries(&lines);
+    let mut groups: Vec<ErrorGroup> = Vec::new();
+    for (context, entry) in &entries {
+        let first = entry[0];
+        let exception = entry[1..].iter().rev().find_map(|line| EXCEPTION.captures(line)).map(|caps| caps[0].trim().to_string());
+        let mut signature = normalize(message(first));
+        if let Some(class) = exception.as_ref().and_then(|line| EXCEPTION.captures(line)).map(|caps| caps[1].to_string()) {
+            signature = format!("{} ({})", signature, class);
+        }
+        let timestamp = TIMESTAMP.captures(first.trim()).map(|caps| caps[1].to_string());
+        match groups.iter_mut().find(|group| group.signature == signature) {
+            Some(group) => {
+                group.count += 1;
+                group.last = timestamp.or(group.last.take());
+                let text = message(first).to_string();
+                if group.variants.len() < MAX_VARIANTS && !group.messages.contains(&text) {
+                    group.variants.push(first.trim().to_string());
+                    group.messages.push(text);
+                }
+            }
+            None => {
+                let mut codes: Vec<String> = Vec::new();
+                for line in std::iter::once(message(first)).chain(exception.as_deref()) {
+                    for code in ERROR_CODE.find_iter(line) {
+                        if !codes.iter().any(|c| c == code.as_str()) {
+                            codes.push(code.as_str().to_string());
+                        }
+                    }
+                }
+                // `ERROR`, `level=error` and `"level": "error"` are all ERROR
+                let level = ERROR_LEVEL.find(first).and_then(|level| {
+                    let word = level.as_str().trim_end_matches(':').rsplit(['=', ':']).next()?.trim_matches(['"', ' ']);
+                    word.chars().all(|c| c.is_ascii_alphabetic()).then(|| word.to_uppercase())
+                });
+                groups.push(ErrorGroup {
+                    signature,
+                    level,
+                    codes,
+                    exception,
+                    count: 1,
+                    first: timestamp.clone(),
+                    last: timestamp,
+                    excerpt: context.iter().chain(entry).map(|line| line.to_string()).collect(),
+                    variants: Vec::new(),
+                    messages: vec![message(first).to_string()],
+                });
+            }
+        }
+    }
+    // Stable, so errors as frequent as each other stay in the order they first occurred
+    groups.sort_by_key(|group| Reverse(group.count));
+    (entries.len(), groups)
+}
+
+fn times(count: usize) -> String {
+    match count {
+        1 => "once".to_string(),
+        n => format!("{} times", n),
+    }
+}
+
+/// `text`, the log at `path`, as Markdown for troubleshooting: a summary of its errors
+/// by frequency, then a section per distinct error with its level, error codes, how often
+/// and when it occurred, and an excerpt of its first occurrence with the lines before it.
+/// Occurrences are grouped by signature: the message with ids, times, addresses and
+/// numbers replaced, plus the exception type of a stack trace.
+pub fn render(path: &Path, text: &str) -> Result<String> {
+    let (entries, mut groups) = error_groups(text);
+    if groups.is_empty() {
+        return Err(anyhow!("No error entries found"));
+    }
+    let inner = compression::inner_path(path);
+    let name = inner.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let stem = name.split(".log").next().unwrap_or(name);
+    let mut markdown = format!("# {} log\n\n## Summary\n\n", stem);
+    markdown.push_str(&format!(
+        "- {} lines, {} error entries, {} distinct errors\n",
+        text.lines().count(),
+        entries,
+        groups.len()
+    ));
+    let omitted = groups.len().saturating_sub(MAX_ERRORS);
+    groups.truncate(MAX_ERRORS);
+    markdown.push_str("- Errors by frequency:\n");
+    for group in &groups {
+        markdown.push_str(&format!("  - `{}` ({})\n", group.signature, times(group.count)));
+    }
+    if omitted > 0 {
+        markdown.push_str(&format!("  - {} less frequent errors not shown\n", omitted));
+    }
+    markdown.push('\n');
+    for group in &groups {
+        markdown.push_str(&format!("## Error: `{}`\n\n", group.signature));
+        if let Some(level) = &group.level {
+            markdown.push_str(&format!("- Level: {}\n", level));
+        }
+        if !group.codes.is_empty() {
+            let codes: Vec<String> = group.codes.iter().map(|code| format!("`{}`", code)).collect();
+            markdown.push_str(&format!("- Error codes: {}\n", codes.join(", ")));
+        }
+        if let Some(exception) = &group.exception {
+            markdown.push_str(&format!("- Exception: `{}`\n", exception));
+        }
+        markdown.push_str(&format!("- Occurred {}", times(group.count)));
+        match (&group.first, &group.last) {
+            (Some(first), Some(last)) if group.count > 1 && first != last => {
+                markdown.push_str(&format!(", first at {}, last at {}", first, last))
+            }
+            (Some(first), _) => markdown.push_str(&format!(", at {}", first)),
+            _ => {}
+        }
+        markdown.push_str(&format!("\n\nLog excerpt:\n\n```text\n{}\n```\n\n", group.excerpt.join("\n")));
+        if !group.variants.is_empty() {
+            markdown.push_str(&format!("Also logged as:\n\n```text\n{}\n```\n\n", group.variants.join("\n")));
+        }
+    }
+    Ok(markdown)
+}
+
+/// The log excerpt of a section rendered by `render`, if it has one.
+pub fn excerpt(section: &str) -> Option<String> {
+    let (_, rest) = section.split_once("Log excerpt:\n\n```text\n")?;
+    let (excerpt, _) = rest.split_once("\n```")?;
+    Some(excerpt.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+2024-05-01 10:00:00 INFO Starting worker
+2024-05-01 10:00:01 INFO Connecting to 10.0.0.5:5432
+2024-05-01 10:00:02 ERROR [db] Connection to 10.0.0.5:5432 failed after 3000ms: ECONNREFUSED
+2024-05-01 10:05:00 ERROR [db] Connection to 10.0.0.6:5432 failed after 1500ms: ECONNREFUSED
+2024-05-01 10:06:00 ERROR Job 42 crashed
+Traceback (most recent call last):
+  File \"job.py\", line 7, in run
+ValueError: bad input
+2024-05-01 10:07:00 INFO Done
+";
+
+    #[test]
+    fn errors_are_grouped_by_signature() {
+        let markdown = render(Path::new("logs/worker.log.1.gz"), LOG).unwrap();
+        assert!(markdown.starts_with(
+            "# worker log\n\n## Summary\n\n- 9 lines, 3 error entries, 2 distinct errors\n- Errors by frequency:\n\
+             \x20 - `Connection to <ip> failed after <n>: ECONNREFUSED` (2 times)\n\
+             \x20 - `Job <n> crashed (ValueError)` (once)\n\n"
+        ));
+        let sections: Vec<&str> = markdown.split("## Error: ").skip(1).collect();
+        assert_eq!(
+            sections[0],
+            "`Connection to <ip> failed after <n>: ECONNREFUSED`\n\n- Level: ERROR\n- Error codes: `ECONNREFUSED`\n\
+             - Occurred 2 times, first at 2024-05-01 10:00:02, last at 2024-05-01 10:05:00\n\n\
+             Log excerpt:\n\n```text\n2024-05-01 10:00:00 INFO Starting worker\n2024-05-01 10:00:01 INFO Connecting to 10.0.0.5:5432\n\
+             2024-05-01 10:00:02 ERROR [db] Connection to 10.0.0.5:5432 failed after 3000ms: ECONNREFUSED\n```\n\n\
+             Also logged as:\n\n```text\n2024-05-01 10:05:00 ERROR [db] Connection to 10.0.0.6:5432 failed after 1500ms: ECONNREFUSED\n```\n\n"
+        );
+        assert!(sections[1].contains("- Exception: `ValueError: bad input`\n- Occurred once, at 2024-05-01 10:06:00\n"), "{}", sections[1]);
+        assert_eq!(
+            excerpt(sections[1]).unwrap().lines().skip(3).collect::<Vec<_>>(),
+            ["2024-05-01 10:06:00 ERROR Job 42 crashed", "Traceback (most recent call last):", "  File \"job.py\", line 7, in run", "ValueError: bad input"]
+        );
+    }
+
+    #[test]
+    fn logs_without_errors_are_not_rendered() {
+        assert!(render(Path::new("app.log"), "2024-05-01 10:00:00 INFO Fine\n").is_err());
+        assert!(is_log(Path::new("app.log")) && is_log(Path::new("app.log.12")) && is_log(Path::new("app.log.gz")));
+        assert!(!is_log(Path::new(".log")) && !is_log(Path::new("app.logger")) && !is_log(Path::new("changelog.md")));
+    }
+}
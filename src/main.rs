@@ -1,186 +1,755 @@
-use std::fs;
-use std::io::Write;
-use std::path::Path;
-use clap::Parser;
-use walkdir::WalkDir;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+use tracing::{error, info};
 
-mod datasource;
-mod processor;
-
-use datasource::{DataSource, UrlSource, LocalSource, GitHubSource, GitHubReleaseSource};
-use processor::OllamaProcessor;
+use llm_dataset_builder::annotation::{AnnotationClient, AnnotationTool};
+use llm_dataset_builder::answer_length::AnswerLength;
+use llm_dataset_builder::config::{Config, SinkConfig};
+use llm_dataset_builder::curriculum::CurriculumOrder;
+use llm_dataset_builder::dataset::{self, RecordFormat, Schema, SystemPrompts};
+use llm_dataset_builder::finetune::{FinetuneClient, FinetuneJob, FinetuneProvider, FinetuneRequest};
+use llm_dataset_builder::jobs::{JobManager, JobRequest};
+use llm_dataset_builder::metrics::Metrics;
+use llm_dataset_builder::notify::Notifier;
+use llm_dataset_builder::progress::Progress;
+use llm_dataset_builder::reading_level::ReadingLevel;
+use llm_dataset_builder::{benchmark, cluster, commands, eval, generate, logging, preflight, review, score, serve};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-    /// Output directory for collected data
-    #[arg(short = 'd', long, default_value = "output")]
-    output_dir: String,
-
-    /// Ollama API endpoint
-    #[arg(short = 'e', long, default_value = "http://localhost:11434")]
-    ollama_endpoint: String,
-}
-
-async fn collect_sources() -> Result<Vec<Box<dyn DataSource>>, Box<dyn std::error::Error>> {
-    let mut sources: Vec<Box<dyn DataSource>> = Vec::new();
-    let mut buffer = String::new();
-
-    loop {
-        println!("\nEnter a data source (press Enter to finish):");
-        println!("- URL (e.g., https://example.com/file.txt)");
-        println!("- Local path (e.g., /path/to/file)");
-        println!("- GitHub URL (e.g., https://github.com/user/repo/tree/branch/path)");
-        println!("- GitHub releases URL (e.g., https://github.com/user/repo/releases)");
-        print!("> ");
-        std::io::stdout().flush()?;
-        
-        buffer.clear();
-        std::io::stdin().read_line(&mut buffer)?;
-        let input = buffer.trim();
-        
-        if input.is_empty() {
-            break;
-        }
-
-        // Check if it's a GitHub releases URL
-        if input.contains("/releases") {
-            println!("Processing GitHub releases: {}", input);
-            match GitHubReleaseSource::new(input) {
-                Ok(source) => {
-                    sources.push(Box::new(source) as Box<dyn DataSource>);
-                    println!("Successfully added GitHub releases source: {}", input);
-                }
-                Err(e) => println!("Error adding GitHub releases source: {}", e),
-            }
-            continue;
-        }
-
-        // Check if it's a GitHub URL
-        if input.starts_with("https://github.com/") && (input.contains("/tree/") || input.contains("/blob/")) {
-            println!("Processing GitHub source: {}", input);
-            sources.push(Box::new(GitHubSource::new(input, None, None)) as Box<dyn DataSource>);
-            println!("Successfully added GitHub source: {}", input);
-            continue;
-        }
-        
-        // Check if it's a regular URL
-        if input.starts_with("http://") || input.starts_with("https://") {
-            println!("Processing URL source: {}", input);
-            match UrlSource::new(input) {
-                Ok(source) => {
-                    sources.push(Box::new(source) as Box<dyn DataSource>);
-                    println!("Successfully added URL source: {}", input);
-                }
-                Err(e) => println!("Error adding URL source: {}", e),
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+// Options shared by every subcommand.
+#[derive(Args, Debug)]
+struct GlobalArgs {
+    /// Configuration file (defaults to dataset-builder.toml/.yaml in the working directory)
+    #[arg(short = 'c', long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Output directory for collected data [default: output]
+    #[arg(short = 'd', long, global = true)]
+    output_dir: Option<String>,
+
+    /// Ollama API endpoint [default: http://localhost:11434]
+    #[arg(short = 'e', long, global = true)]
+    ollama_endpoint: Option<String>,
+
+    /// Endpoint to fail over to when the current one goes down (repeatable, tried in order)
+    #[arg(long = "backup-endpoint", value_name = "URL", global = true)]
+    backup_endpoints: Vec<String>,
+
+    /// Model used to generate questions [default: m/qwen2514bmax]
+    #[arg(short = 'm', long, global = true)]
+    model: Option<String>,
+
+    /// Seed for the model and for sampling decisions, so runs can be reproduced
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Disable progress bars and print every step as plain log lines (useful in CI)
+    #[arg(long, global = true)]
+    plain: bool,
+
+    /// Increase log verbosity (-v for debug, -vv for trace)
+    #[arg(short = 'v', long, action = clap::ArgAction::Count, conflicts_with = "quiet", global = true)]
+    verbose: u8,
+
+    /// Only log warnings and errors
+    #[arg(short = 'q', long, global = true)]
+    quiet: bool,
+
+    /// Emit logs as JSON lines (implies --plain)
+    #[arg(long, global = true)]
+    log_json: bool,
+
+    /// Don't check the Ollama endpoint and models before generating
+    #[arg(long, global = true)]
+    skip_preflight: bool,
+
+    /// Export file/section/request spans to an OTLP collector (e.g. http://localhost:4317)
+    #[arg(long, value_name = "URL", global = true)]
+    otlp_endpoint: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Collect sources interactively and generate question/answer pairs from them
+    Generate(GenerateArgs),
+    /// Check that the Ollama endpoint is reachable, supports structured output and serves
+    /// the configured models
+    Check,
+    /// Concatenate datasets into one file
+    Merge {
+        inputs: Vec<PathBuf>,
+        #[arg(short = 'o', long)]
+        output: PathBuf,
+    },
+    /// Remove items with duplicate questions, keeping the first
+    Dedupe {
+        input: PathBuf,
+        /// Where to write the result [default: overwrite the input]
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Upgrade a dataset written by an older version to the current schema, or write it
+    /// as plain question/answer records with `--to-schema v1`
+    Migrate {
+        input: PathBuf,
+        /// Where to write the result [default: overwrite the input]
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value = "v2")]
+        to_schema: Schema,
+    },
+    /// Print item counts and length statistics
+    Stats {
+        inputs: Vec<PathBuf>,
+    },
+    /// Rewrite a dataset in another record format (`.json` outputs are written as an array)
+    Convert {
+        input: PathBuf,
+        #[arg(short = 'o', long)]
+        output: PathBuf,
+        #[arg(long, value_enum, default_value = "qa")]
+        to: RecordFormat,
+        /// System turn for sharegpt and openai records, rendered with the item's fields;
+        /// repeat to pick one per item from a pool
+        #[arg(long = "system-prompt", value_name = "TEMPLATE")]
+        system_prompts: Vec<String>,
+    },
+    /// Check that every record of a JSONL dataset is well-formed; exits with 1 if not
+    Validate {
+        input: PathBuf,
+        #[arg(long, value_enum, default_value = "qa")]
+        format: RecordFormat,
+    },
+    /// Accept, reject or edit items interactively, next to their source section
+    Review {
+        input: PathBuf,
+    },
+    /// Send items to an Argilla or Label Studio project for annotation
+    Export {
+        input: PathBuf,
+        #[command(flatten)]
+        project: AnnotationArgs,
+    },
+    /// Pull accepted (and corrected) records back from an Argilla or Label Studio project
+    Import {
+        #[command(flatten)]
+        project: AnnotationArgs,
+        #[arg(short = 'o', long)]
+        output: PathBuf,
+    },
+    /// Rate every item with the model and store it in a `score` field
+    Score {
+        input: PathBuf,
+        /// Where to write the result [default: overwrite the input]
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// Drop items rated below this (1-5)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+        min_score: Option<u8>,
+    },
+    /// Ask the model every question without its source and flag the ones it can't
+    /// answer or answers differently, storing the outcome in a `self_check` field
+    SelfCheck {
+        input: PathBuf,
+        /// Where to write the result [default: overwrite the input]
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// Drop flagged items instead of keeping them
+        #[arg(long)]
+        drop_flagged: bool,
+    },
+    /// Generate from the same sample of sections with several models, rate the items
+    /// with a judge model and compare quality, parse failures and speed
+    Benchmark {
+        /// Models to compare, comma-separated
+        #[arg(long, required = true, value_delimiter = ',')]
+        models: Vec<String>,
+        /// File or directory of documents to draw sections from
+        #[arg(long)]
+        sample: PathBuf,
+        /// Model rating the items [default: the configured model]
+        #[arg(long)]
+        judge: Option<String>,
+        /// Maximum number of sections every model generates from
+        #[arg(long, default_value_t = 20)]
+        sections: usize,
+        /// Also write the results as JSON
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Generate from the same sample of sections with two user prompt templates, rate the
+    /// items with a judge model and show them side by side
+    ComparePrompts {
+        #[arg(long)]
+        prompt_a: PathBuf,
+        #[arg(long)]
+        prompt_b: PathBuf,
+        /// File or directory of documents to draw sections from
+        #[arg(long)]
+        sample: PathBuf,
+        /// Model rating the items [default: the configured model]
+        #[arg(long)]
+        judge: Option<String>,
+        /// Maximum number of sections each prompt generates from
+        #[arg(long, default_value_t = 10)]
+        sections: usize,
+        /// Also write both prompts' items and scores as JSON
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Ask a (fine-tuned) model every question of a held-out split and report how well
+    /// its answers match the reference answers
+    Eval {
+        /// Test split, e.g. the `test.jsonl` written by `split`
+        input: PathBuf,
+        /// Model to evaluate
+        #[arg(long)]
+        eval_model: String,
+        /// Ollama endpoint serving that model [default: --ollama-endpoint]
+        #[arg(long, value_name = "URL")]
+        eval_endpoint: Option<String>,
+        /// System prompt sent with every question, e.g. the one used in training
+        #[arg(long)]
+        system: Option<String>,
+        /// Also have this model (at --ollama-endpoint) grade every response against the reference
+        #[arg(long)]
+        judge: Option<String>,
+        /// Also write the report with every response as JSON
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Submit a dataset for fine-tuning and follow the job
+    Finetune {
+        #[command(subcommand)]
+        command: FinetuneCommand,
+    },
+    /// Shuffle a dataset into train/validation/test files
+    Split {
+        input: PathBuf,
+        /// Directory for the split files [default: next to the input]
+        #[arg(short = 'o', long)]
+        out_dir: Option<PathBuf>,
+        #[arg(long, default_value_t = 0.8)]
+        train: f64,
+        #[arg(long, default_value_t = 0.1)]
+        validation: f64,
+        #[arg(long, default_value_t = 0.1)]
+        test: f64,
+    },
+    /// Order a dataset for curriculum fine-tuning, easy or short items first
+    Order {
+        input: PathBuf,
+        /// Where to write the result [default: overwrite the input]
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// What to order by [default: curriculum.order, or difficulty when that is none]
+        #[arg(long, value_enum)]
+        by: Option<CurriculumOrder>,
+        /// Spread the items of each value of this field (or each source file with
+        /// `source`) evenly through the order
+        #[arg(long, value_name = "FIELD")]
+        interleave: Option<String>,
+    },
+    /// Serve the datasets in the output directory and accept generation jobs over HTTP
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Also host a web app for browsing, searching, editing and approving items
+        #[arg(long)]
+        review: bool,
+        /// Number of jobs run at the same time; 0 leaves them to a `daemon`
+        #[arg(long, default_value_t = 1)]
+        concurrency: usize,
+        /// Hand the sections of each job to `worker` processes instead of generating locally
+        #[arg(long)]
+        distribute: bool,
+    },
+    /// Generate items for chunks pulled from a `serve --distribute` coordinator
+    Worker {
+        /// Base URL of the coordinator, e.g. http://gpu-coordinator:8080
+        #[arg(long)]
+        coordinator: String,
+        /// Number of chunks processed at the same time
+        #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        concurrency: usize,
+        /// Name reported to the coordinator (default: host name and process id)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Run queued generation jobs in the background until stopped
+    Daemon {
+        /// Number of jobs run at the same time
+        #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        concurrency: usize,
+    },
+    /// Add a generation job to the queue processed by `daemon` or `serve`
+    Enqueue {
+        /// URLs, GitHub URLs or local directories and files
+        #[arg(required = true)]
+        sources: Vec<String>,
+        /// Content profile used for every file instead of the rules
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FinetuneCommand {
+    /// Validate a dataset, upload it, start a fine-tuning job and wait for it to finish
+    Submit {
+        /// Question/answer dataset, or chat records in the OpenAI `messages` format
+        input: PathBuf,
+        /// Held-out records the provider reports validation loss on
+        #[arg(long)]
+        validation: Option<PathBuf>,
+        /// Model to fine-tune
+        #[arg(long, default_value = "gpt-4o-mini-2024-07-18")]
+        base_model: String,
+        /// Added to the name of the fine-tuned model
+        #[arg(long)]
+        suffix: Option<String>,
+        /// Training epochs [default: chosen by the provider]
+        #[arg(long)]
+        epochs: Option<u32>,
+        /// System prompt added to every converted question/answer item
+        #[arg(long)]
+        system: Option<String>,
+        /// Return once the job is created instead of waiting for it
+        #[arg(long)]
+        no_wait: bool,
+        #[command(flatten)]
+        provider: FinetuneArgs,
+    },
+    /// Show the status of a fine-tuning job
+    Status {
+        job: String,
+        /// Wait for the job to finish
+        #[arg(long)]
+        wait: bool,
+        #[command(flatten)]
+        provider: FinetuneArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+struct FinetuneArgs {
+    /// Fine-tuning service
+    #[arg(long, value_enum, default_value = "openai")]
+    provider: FinetuneProvider,
+
+    /// Base URL of the provider's API [default: https://api.openai.com/v1]
+    #[arg(long, value_name = "URL")]
+    api_url: Option<String>,
+
+    /// API key
+    #[arg(long, env = "OPENAI_API_KEY", hide_env_values = true)]
+    api_key: String,
+}
+
+impl FinetuneArgs {
+    fn client(&self) -> FinetuneClient {
+        let url = self.api_url.as_deref().unwrap_or(self.provider.default_url());
+        FinetuneClient::new(self.provider, url, &self.api_key)
+    }
+}
+
+fn print_job(job: &FinetuneJob) {
+    println!("{}: {}", job.id, job.status);
+    if let Some(model) = &job.fine_tuned_model {
+        println!("  fine-tuned model: {}", model);
+    }
+    if let Some(tokens) = job.trained_tokens {
+        println!("  trained tokens:   {}", tokens);
+    }
+    if let Some(message) = job.error_message() {
+        println!("  error:            {}", message);
+    }
+}
+
+#[derive(Args, Debug)]
+struct AnnotationArgs {
+    /// Annotation platform
+    #[arg(long, value_enum)]
+    tool: AnnotationTool,
+
+    /// Base URL of the platform, e.g. http://localhost:6900
+    #[arg(long)]
+    url: String,
+
+    /// Argilla dataset id or Label Studio project id
+    #[arg(long)]
+    project: String,
+
+    /// API key
+    #[arg(long, env = "ANNOTATION_API_KEY", hide_env_values = true)]
+    api_key: String,
+}
+
+impl AnnotationArgs {
+    fn client(&self) -> AnnotationClient {
+        AnnotationClient::new(self.tool, &self.url, &self.project, &self.api_key)
+    }
+}
+
+#[derive(Args, Debug)]
+struct GenerateArgs {
+    /// Generate a fixed number of questions per file instead of deriving it from word count
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    questions_per_file: Option<usize>,
+
+    /// Content profile used for every file instead of selecting one through the rules
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Process at most N files
+    #[arg(long, value_name = "N")]
+    limit_files: Option<usize>,
+
+    /// Process at most N sections of each file
+    #[arg(long, value_name = "N")]
+    limit_sections: Option<usize>,
+
+    /// Process a random fraction of the files, e.g. 0.1 (repeatable with --seed)
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    sample: Option<f64>,
+
+    /// Keep at most N items in the whole run, scaling down each file's questions to fit
+    /// and stopping once the budget is used up
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_total_items: Option<usize>,
+
+    /// Order the combined dataset for curriculum fine-tuning, easy or short items first
+    #[arg(long, value_enum, value_name = "ORDER")]
+    curriculum: Option<CurriculumOrder>,
+
+    /// Ask for terse, standard or detailed answers, asking again for answers of the wrong
+    /// length and dropping those still off
+    #[arg(long, value_enum, value_name = "PRESET")]
+    answer_length: Option<AnswerLength>,
+
+    /// Write answers for beginners, a general audience or experts, asking again for
+    /// answers whose readability is far off
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    reading_level: Option<ReadingLevel>,
+
+    /// Skip files that last changed before this date (from git or their modification
+    /// time), e.g. 2024-01-01
+    #[arg(long, value_name = "DATE")]
+    since: Option<NaiveDate>,
+
+    /// JSON file with per-model token prices, used to report the cost of a run
+    #[arg(long)]
+    pricing: Option<String>,
+
+    /// Expose Prometheus metrics at http://<ADDR>/metrics while the run is in progress
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Webhook (e.g. a Slack incoming webhook) that receives a summary when the run ends
+    #[arg(long, value_name = "URL")]
+    notify_url: Option<String>,
+
+    /// Notification message template; supports {status}, {items}, {files}, {failed_files},
+    /// {duration}, {model} and {error}
+    #[arg(long)]
+    notify_template: Option<String>,
+
+    /// Embed questions (and, with `embeddings.chunks`, sections) with this model for the
+    /// SQLite, Parquet and Arrow sinks
+    #[arg(long, value_name = "MODEL")]
+    embed_model: Option<String>,
+
+    /// Ask for a verbatim quote supporting each answer, stored as `citation`; items whose
+    /// quote isn't in the source section are rejected
+    #[arg(long)]
+    citations: bool,
+
+    /// Write a DATASET_CARD.md describing sources, counts, model, prompts and known
+    /// limitations to the output directory
+    #[arg(long)]
+    dataset_card: bool,
+
+    /// Also write the items to a sink: jsonl:PATH, parquet:PATH, arrow:PATH, sqlite:PATH,
+    /// http:URL or stdout (repeatable)
+    #[arg(long = "sink", value_name = "SINK")]
+    sinks: Vec<SinkConfig>,
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("{:?} is not a fraction in (0, 1]", value)),
+    }
+}
+
+/// Loads the configuration file and applies command-line flags on top of it.
+fn load_config(args: &GlobalArgs) -> anyhow::Result<Config> {
+    let mut config = Config::load(args.config.as_deref())?;
+    if let Some(output_dir) = &args.output_dir {
+        config.output_dir = output_dir.clone();
+    }
+    if let Some(endpoint) = &args.ollama_endpoint {
+        config.endpoint = endpoint.clone();
+    }
+    if !args.backup_endpoints.is_empty() {
+        config.backup_endpoints = args.backup_endpoints.clone();
+    }
+    if let Some(model) = &args.model {
+        config.model = model.clone();
+    }
+    if args.seed.is_some() {
+        config.seed = args.seed;
+    }
+    config.plain |= args.plain;
+    config.log_json |= args.log_json;
+    config.preflight &= !args.skip_preflight;
+    if args.otlp_endpoint.is_some() {
+        config.otlp_endpoint = args.otlp_endpoint.clone();
+    }
+    Ok(config)
+}
+
+impl GenerateArgs {
+    fn apply(&self, config: &mut Config) -> anyhow::Result<()> {
+        if self.questions_per_file.is_some() {
+            config.density.questions_per_file = self.questions_per_file;
+        }
+        if self.limit_files.is_some() {
+            config.sampling.limit_files = self.limit_files;
+        }
+        if self.limit_sections.is_some() {
+            config.sampling.limit_sections = self.limit_sections;
+        }
+        if self.sample.is_some() {
+            config.sampling.sample = self.sample;
+        }
+        if self.max_total_items.is_some() {
+            config.sampling.max_total_items = self.max_total_items;
+        }
+        if self.since.is_some() {
+            config.sampling.since = self.since;
+        }
+        if self.answer_length.is_some() {
+            config.answer_length.preset = self.answer_length;
+        }
+        if self.reading_level.is_some() {
+            config.reading_level.level = self.reading_level;
+        }
+        if let Some(order) = self.curriculum {
+            config.curriculum.order = order;
+        }
+        if self.pricing.is_some() {
+            config.pricing = self.pricing.clone();
+        }
+        if self.metrics_addr.is_some() {
+            config.metrics_addr = self.metrics_addr;
+        }
+        if let Some(profile) = &self.profile {
+            if !config.has_profile(profile) {
+                return Err(anyhow::anyhow!("Unknown profile {:?}", profile));
             }
-            continue;
+            config.profile = Some(profile.clone());
         }
-
-        // Assume it's a local path if it doesn't match the above
-        if Path::new(input).exists() {
-            println!("Processing local source: {}", input);
-            sources.push(Box::new(LocalSource::new(input)) as Box<dyn DataSource>);
-            println!("Successfully added local source: {}", input);
-        } else {
-            println!("Invalid input. Please enter:");
-            println!("- A GitHub URL (https://github.com/user/repo/tree/branch/path)");
-            println!("- A GitHub releases URL (https://github.com/user/repo/releases)");
-            println!("- A regular URL (http:// or https://)");
-            println!("- A valid local file or directory path");
+        if self.notify_url.is_some() {
+            config.notify.url = self.notify_url.clone();
         }
+        if self.notify_template.is_some() {
+            config.notify.template = self.notify_template.clone();
+        }
+        if self.embed_model.is_some() {
+            config.embeddings.model = self.embed_model.clone();
+        }
+        config.output.citations |= self.citations;
+        config.output.dataset_card |= self.dataset_card;
+        config.output.sinks.extend(self.sinks.iter().cloned());
+        Ok(())
     }
-    
-    Ok(sources)
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    
-    // Create output directory if it doesn't exist
-    fs::create_dir_all(&args.output_dir)?;
-    
-    // Initialize processor
-    let processor = OllamaProcessor::new(args.ollama_endpoint.clone());
-    
-    // Collect data sources
-    let sources = collect_sources().await?;
-    
-    // Process each source
-    let mut all_items = Vec::new();
-
-    // If no sources added, check existing files
-    if sources.is_empty() {
-        println!("No new sources added. Processing existing files in output directory...");
-        let mut existing_files = Vec::new();
-        for entry in WalkDir::new(Path::new(&args.output_dir))
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .filter(|e| {
-                e.path()
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| ext == "md" || ext == "txt")
-                    .unwrap_or(false)
-            })
-        {
-            existing_files.push(entry.path().to_path_buf());
-        }
-
-        if existing_files.is_empty() {
-            println!("No markdown or text files found in output directory to process.");
-            return Ok(());
-        }
-
-        println!("Found {} markdown/text files to process.", existing_files.len());
-        for file_path in existing_files {
-            println!("Processing file: {:?}", file_path);
-            match processor.process_file(&file_path).await {
-                Ok(items) => {
-                    all_items.extend(items);
-                }
-                Err(e) => {
-                    eprintln!("Error processing file {:?}: {}", file_path, e);
-                }
+/// Runs a subcommand; returns the process exit code.
+async fn run_command(command: Command, config: &Config, progress: Arc<Progress>) -> Result<i32, Box<dyn std::error::Error>> {
+    match command {
+        Command::Generate(_) => return generate_and_notify(config, progress).await,
+        Command::Check => match preflight::check(config).await {
+            Ok(server) => println!("Ollama {} at {} is ready ({} models)", server.version, server.endpoint, server.models.len()),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Ok(e.exit_code());
+            }
+        },
+        Command::Merge { inputs, output } => commands::merge(&inputs, &output)?,
+        Command::Dedupe { input, output } => commands::dedupe(&input, output.as_ref().unwrap_or(&input))?,
+        Command::Migrate { input, output, to_schema } => commands::migrate(config, &input, output.as_ref().unwrap_or(&input), to_schema).await?,
+        Command::Stats { inputs } => commands::stats(&inputs)?,
+        Command::Convert { input, output, to, system_prompts } => {
+            commands::convert(&input, &output, to, &SystemPrompts(system_prompts))?
+        }
+        Command::Validate { input, format } => {
+            if commands::validate(&input, format, &config.output.schema.required)? > 0 {
+                return Ok(1);
+            }
+        }
+        Command::Review { input } => review::review(config, &input).await?,
+        Command::Export { input, project } => project.client().export(&dataset::read_items(&input)?).await?,
+        Command::Import { project, output } => {
+            let items = project.client().import().await?;
+            dataset::write_items(&output, &items, dataset::layout_for(&output), RecordFormat::Qa)?;
+        }
+        Command::Score { input, output, min_score } => {
+            score::score_file(config, &input, output.as_ref().unwrap_or(&input), min_score).await?
+        }
+        Command::SelfCheck { input, output, drop_flagged } => {
+            score::self_check_file(config, &input, output.as_ref().unwrap_or(&input), drop_flagged).await?
+        }
+        Command::Benchmark { models, sample, judge, sections, output } => {
+            let results = benchmark::run(config, &models, judge.as_deref().unwrap_or(&config.model), &sample, sections).await?;
+            benchmark::print_table("Model", &results);
+            if let Some(output) = output {
+                std::fs::write(output, serde_json::to_string_pretty(&results)?)?;
             }
         }
-    } else {
-        // Process new sources
-        for source in sources {
-            println!("\nProcessing source...");
-            
-            // Collect files from source
-            let files = source.collect(Path::new(&args.output_dir)).await?;
-            println!("Found {} files", files.len());
-            
-            for file_path in files {
-                println!("Processing file: {:?}", file_path);
-                match processor.process_file(&file_path).await {
-                    Ok(items) => {
-                        all_items.extend(items);
-                    }
-                    Err(e) => {
-                        eprintln!("Error processing file {:?}: {}", file_path, e);
-                    }
-                }
+        Command::ComparePrompts { prompt_a, prompt_b, sample, judge, sections, output } => {
+            let judge = judge.as_deref().unwrap_or(&config.model);
+            let comparison = benchmark::compare_prompts(config, [&prompt_a, &prompt_b], judge, &sample, sections).await?;
+            benchmark::print_comparison(&comparison);
+            if let Some(output) = output {
+                std::fs::write(output, serde_json::to_string_pretty(&comparison)?)?;
             }
         }
+        Command::Eval { input, eval_model, eval_endpoint, system, judge, output } => {
+            let endpoint = eval_endpoint.as_deref().unwrap_or(&config.endpoint);
+            let report = eval::evaluate(config, &input, &eval_model, endpoint, system.as_deref(), judge.as_deref()).await?;
+            report.print();
+            if let Some(output) = output {
+                std::fs::write(output, serde_json::to_string_pretty(&report)?)?;
+            }
+        }
+        Command::Finetune { command } => match command {
+            FinetuneCommand::Submit { input, validation, base_model, suffix, epochs, system, no_wait, provider } => {
+                let request = FinetuneRequest { base_model, suffix, epochs };
+                let client = provider.client();
+                print_job(&client.submit(&input, validation.as_deref(), system.as_deref(), &request, !no_wait).await?);
+            }
+            FinetuneCommand::Status { job, wait, provider } => {
+                let client = provider.client();
+                print_job(&if wait { client.wait(&job).await? } else { client.job(&job).await? });
+            }
+        },
+        Command::Split { input, out_dir, train, validation, test } => {
+            let out_dir = out_dir.unwrap_or_else(|| input.parent().map(PathBuf::from).unwrap_or_default());
+            commands::split(&input, &out_dir, [train, validation, test], config.seed)?
+        }
+        Command::Order { input, output, by, interleave } => {
+            let mut curriculum = config.curriculum.clone();
+            curriculum.order = by.unwrap_or(match curriculum.order {
+                CurriculumOrder::None => CurriculumOrder::Difficulty,
+                order => order,
+            });
+            curriculum.interleave = interleave.or(curriculum.interleave);
+            commands::order(&input, output.as_ref().unwrap_or(&input), &curriculum, &config.output.schema)?
+        }
+        Command::Serve { addr, review, concurrency, distribute } => {
+            serve::serve(config.clone(), addr, review, concurrency, distribute).await?
+        }
+        Command::Worker { coordinator, concurrency, name } => {
+            let name = name.unwrap_or_else(|| {
+                let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+                format!("{}-{}", host, std::process::id())
+            });
+            cluster::run_worker(config, &coordinator, concurrency, &name).await?
+        }
+        Command::Daemon { concurrency } => {
+            if let Some(addr) = config.metrics_addr {
+                Metrics::global().serve(addr).await?;
+            }
+            Arc::new(JobManager::new(config.clone())?).start_workers(concurrency)?;
+            tokio::signal::ctrl_c().await?;
+            info!("Stopping; running jobs will be requeued once their lease expires");
+        }
+        Command::Enqueue { sources, profile } => {
+            let job = JobManager::new(config.clone())?.submit(JobRequest {
+                sources,
+                profile,
+                model: None,
+                files: Vec::new(),
+            })?;
+            println!("{}", job.id);
+        }
+    }
+    Ok(0)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let mut config = load_config(&cli.global)?;
+    if let Command::Generate(args) = &cli.command {
+        args.apply(&mut config)?;
+    }
+
+    let progress = Arc::new(Progress::new(config.plain || config.log_json));
+    let tracer_provider = logging::init(
+        cli.global.verbose,
+        cli.global.quiet,
+        config.log_json,
+        config.otlp_endpoint.as_deref(),
+        &progress,
+    )?;
+
+    let exit_code = run_command(cli.command, &config, progress).await;
+
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            error!("Failed to flush OpenTelemetry spans: {}", e);
+        }
+    }
+
+    let exit_code = exit_code?;
+    if exit_code != 0 {
+        std::process::exit(exit_code);
     }
-    
-    // Save combined results
-    let output_file = Path::new(&args.output_dir).join("all_qa.jsonl");
-    let mut output = String::new();
-    for item in &all_items {
-        if let Ok(json_line) = serde_json::to_string(item) {
-            output.push_str(&json_line);
-            output.push('\n');
-        }
-    }
-    fs::write(&output_file, output)?;
-    println!("Saved {} question-answer pairs to {:?}", all_items.len(), output_file);
-    println!("Individual file results saved as [filename]_qa.jsonl in the output directory");
     
     Ok(())
 }
+
+/// Runs the generation pipeline and sends the completion notification, if configured.
+async fn generate_and_notify(config: &Config, progress: Arc<Progress>) -> Result<i32, Box<dyn std::error::Error>> {
+    let notifier = config
+        .notify
+        .url
+        .clone()
+        .map(|url| Notifier::new(url, config.notify.template.clone()));
+
+    let result = generate::run(config, progress).await;
+
+    if let Some(notifier) = &notifier {
+        let sent = match &result {
+            Ok(Some(report)) => notifier.run_finished(report).await,
+            Ok(None) => Ok(()),
+            Err(e) => notifier.run_failed(&config.model, &e.to_string()).await,
+        };
+        if let Err(e) = sent {
+            error!("Failed to send notification: {}", e);
+        }
+    }
+
+    match result {
+        Ok(report) => Ok(report.map_or(0, |report| report.exit_code)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Ok(e.exit_code())
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::LazyLock;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use regex::Regex;
+
+/// An HTML heading on a line of its own, e.g. `<h2 id="install">Install</h2>`.
+static HTML_HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)^\s*<h([1-6])\b[^>]*>(.*?)</h[1-6]\s*>\s*$").unwrap());
+
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// Tables and YAML frontmatter on top of CommonMark.
+const OPTIONS: Options = Options::ENABLE_TABLES.union(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+
+/// What a top-level Markdown block is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockKind {
+    /// An ATX heading (`## Install`), a setext heading (`Install` underlined with `---`)
+    /// or an HTML heading on a line of its own (`<h2>Install</h2>`).
+    Heading { level: usize, title: String },
+    Paragraph,
+    /// A bullet or numbered list, with everything nested in its items.
+    List { ordered: bool, items: usize },
+    Table,
+    /// A fenced or indented code block, with the language of a fenced one.
+    Code { language: Option<String> },
+    Quote,
+    Html,
+    /// A thematic break (`---`, `***`).
+    Rule,
+    /// YAML frontmatter at the start of the document.
+    Frontmatter,
+}
+
+/// A top-level block of a Markdown document.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub kind: BlockKind,
+    /// Bytes of the text the block takes up, from the start of its first line to the
+    /// end of its last, line break included.
+    pub range: Range<usize>,
+}
+
+impl Block {
+    /// For a heading, the heading as an ATX heading line without the line break: the line
+    /// as written for ATX headings, `## Install` for the others.
+    pub fn heading_line<'a>(&self, text: &'a str) -> Option<Cow<'a, str>> {
+        let BlockKind::Heading { level, title } = &self.kind else {
+            return None;
+        };
+        let written = text[self.range.clone()].trim_end_matches(['\n', '\r']);
+        Some(match written.trim_start().starts_with('#') {
+            true => written.into(),
+            false => format!("{} {}", "#".repeat(*level), title).into(),
+        })
+    }
+}
+
+/// The top-level blocks of `text`, in order, from a CommonMark parser. Lines that only
+/// look like headings, such as `#` comments in code blocks, are part of their blocks,
+/// and `===` or `---` lines only make a heading when they underline a paragraph.
+/// Headings in lists and block quotes belong to them. Blank lines and link reference
+/// definitions are left between blocks.
+pub fn blocks(text: &str) -> Vec<Block> {
+    let lines = |range: Range<usize>| line_start(text, range.start)..line_end(text, range.end);
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut depth = 0;
+    // Whether the block being read is a heading, whose text makes its title
+    let mut in_heading = false;
+    for (event, range) in Parser::new_ext(text, OPTIONS).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Item) if depth == 1 => {
+                if let Some(BlockKind::List { items, .. }) = blocks.last_mut().map(|block| &mut block.kind) {
+                    *items += 1;
+                }
+                depth += 1;
+            }
+            Event::Start(tag) => {
+                if depth == 0 {
+                    let kind = match tag {
+                        Tag::Heading { level, .. } => Some(BlockKind::Heading { level: level as usize, title: String::new() }),
+                        Tag::Paragraph => Some(BlockKind::Paragraph),
+                        Tag::List(start) => Some(BlockKind::List { ordered: start.is_some(), items: 0 }),
+                        Tag::Table(_) => Some(BlockKind::Table),
+                        Tag::CodeBlock(CodeBlockKind::Fenced(info)) => Some(BlockKind::Code {
+                            language: info.split_whitespace().next().map(str::to_string),
+                        }),
+                        Tag::CodeBlock(CodeBlockKind::Indented) => Some(BlockKind::Code { language: None }),
+                        Tag::BlockQuote(_) => Some(BlockKind::Quote),
+                        Tag::MetadataBlock(_) => Some(BlockKind::Frontmatter),
+                        // HTML blocks are read line by line below, for the headings in them
+                        _ => None,
+                    };
+                    in_heading = matches!(kind, Some(BlockKind::Heading { .. }));
+                    if let Some(kind) = kind {
+                        blocks.push(Block { kind, range: lines(range) });
+                    }
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth -= 1,
+            Event::Rule if depth == 0 => blocks.push(Block { kind: BlockKind::Rule, range: lines(range) }),
+            Event::Text(_) | Event::Code(_) | Event::SoftBreak | Event::HardBreak if in_heading && depth > 0 => {
+                if let Some(BlockKind::Heading { title, .. }) = blocks.last_mut().map(|block| &mut block.kind) {
+                    match event {
+                        Event::Text(fragment) | Event::Code(fragment) => title.push_str(&fragment),
+                        _ => title.push(' '),
+                    }
+                }
+            }
+            Event::Html(line) if depth == 1 => {
+                let range = lines(range);
+                if let Some(captures) = HTML_HEADING.captures(&line) {
+                    let title = HTML_TAG.replace_all(&captures[2], "");
+                    blocks.push(Block {
+                        kind: BlockKind::Heading {
+                            level: captures[1].parse().unwrap(),
+                            title: title.split_whitespace().collect::<Vec<_>>().join(" "),
+                        },
+                        range,
+                    });
+                } else if let Some(block) = blocks.last_mut().filter(|block| block.kind == BlockKind::Html && block.range.end == range.start) {
+                    block.range.end = range.end;
+                } else {
+                    blocks.push(Block { kind: BlockKind::Html, range });
+                }
+            }
+            _ => {}
+        }
+    }
+    for block in &mut blocks {
+        if let BlockKind::Heading { title, .. } = &mut block.kind {
+            *title = title.trim().to_string();
+        }
+    }
+    blocks
+}
+
+/// The largest list of `text`, when it has at least `min_items` items and lists make up
+/// at least half of the words outside headings: `(ordered, items)`.
+pub fn main_list(text: &str, min_items: usize) -> Option<(bool, usize)> {
+    let blocks = blocks(text);
+    let words = |block: &Block| text[block.range.clone()].split_whitespace().count();
+    let total: usize = blocks.iter().filter(|block| !matches!(block.kind, BlockKind::Heading { .. })).map(words).sum();
+    let lists: Vec<&Block> = blocks.iter().filter(|block| matches!(block.kind, BlockKind::List { .. })).collect();
+    if lists.iter().map(|list| words(list)).sum::<usize>() * 2 < total {
+        return None;
+    }
+    lists.into_iter().max_by_key(|list| words(list)).and_then(|list| match list.kind {
+        BlockKind::List { ordered, items } if items >= min_items => Some((ordered, items)),
+        _ => None,
+    })
+}
+
+/// Number of words of `text` outside headings, links, images and frontmatter: the words
+/// a section has to ask about. Text in HTML counts without its tags.
+pub fn prose_words(text: &str) -> usize {
+    let mut words = 0;
+    // Depth of the headings, links, images and frontmatter being read
+    let mut skipped = 0;
+    for event in Parser::new_ext(text, OPTIONS) {
+        match event {
+            Event::Start(Tag::Heading { .. } | Tag::Link { .. } | Tag::Image { .. } | Tag::MetadataBlock(_)) => skipped += 1,
+            Event::End(TagEnd::Heading(_) | TagEnd::Link | TagEnd::Image | TagEnd::MetadataBlock(_)) => skipped -= 1,
+            Event::Text(fragment) | Event::Code(fragment) if skipped == 0 => words += fragment.split_whitespace().count(),
+            Event::Html(html) | Event::InlineHtml(html) if skipped == 0 => {
+                words += HTML_TAG.replace_all(&html, " ").split_whitespace().count();
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// The top-level headings of `text`, in order.
+pub fn headings(text: &str) -> impl Iterator<Item = Block> {
+    blocks(text).into_iter().filter(|block| matches!(block.kind, BlockKind::Heading { .. }))
+}
+
+fn line_start(text: &str, at: usize) -> usize {
+    text[..at].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+/// The end of the line `at` is on, or of the last line before it that isn't blank
+/// when it is at the start of a line, as lists end after the blank lines following them.
+fn line_end(text: &str, at: usize) -> usize {
+    let end = match at > 0 && text.as_bytes()[at - 1] == b'\n' {
+        true => at,
+        false => text[at..].find('\n').map_or(text.len(), |newline| at + newline + 1),
+    };
+    let kept = text[..end].trim_end().len();
+    text[kept..end].find('\n').map_or(end, |newline| kept + newline + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headings_of(text: &str) -> Vec<(usize, String, String)> {
+        headings(text)
+            .map(|block| match &block.kind {
+                BlockKind::Heading { level, title } => (*level, title.clone(), block.heading_line(text).unwrap().into_owned()),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_atx_setext_and_html_headings() {
+        let text = "# Guide\n\nInstall\n-------\n\n<h3 id=\"linux\">On <em>Linux</em></h3>\n\nText.\n";
+        assert_eq!(
+            headings_of(text),
+            [
+                (1, "Guide".to_string(), "# Guide".to_string()),
+                (2, "Install".to_string(), "## Install".to_string()),
+                (3, "On Linux".to_string(), "### On Linux".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_lines_that_only_look_like_headings() {
+        let text = "```sh\n# a comment\n```\n\n- # in a list\n\n> # in a quote\n\n---\n\n#hashtag\n";
+        assert!(headings_of(text).is_empty());
+    }
+
+    #[test]
+    fn heading_ranges_cover_their_lines() {
+        let text = "Intro.\n\n## `run` command ##\n\nText.\n";
+        let heading = headings(text).next().unwrap();
+        assert_eq!(&text[heading.range.clone()], "## `run` command ##\n");
+        assert_eq!(heading.kind, BlockKind::Heading { level: 2, title: "run command".to_string() });
+    }
+}
@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use anyhow::Result;
+use axum::{extract::State, routing::get, Router};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
+use tracing::{error, info};
+
+/// Prometheus counters and histograms for a generation run.
+pub struct Metrics {
+    registry: Registry,
+    pub requests: IntCounter,
+    pub request_failures: IntCounter,
+    pub parse_failures: IntCounter,
+    pub endpoint_failovers: IntCounter,
+    pub items_generated: IntCounter,
+    pub files_processed: IntCounter,
+    pub request_duration: Histogram,
+    serving: AtomicBool,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests = IntCounter::new("llmds_requests_total", "Chat requests sent to the model").unwrap();
+        let request_failures = IntCounter::new("llmds_request_failures_total", "Chat requests that returned an error").unwrap();
+        let parse_failures = IntCounter::new("llmds_parse_failures_total", "Model responses that could not be parsed").unwrap();
+        let endpoint_failovers = IntCounter::new("llmds_endpoint_failovers_total", "Switches to another endpoint after one became unreachable").unwrap();
+        let items_generated = IntCounter::new("llmds_items_generated_total", "Question-answer items generated").unwrap();
+        let files_processed = IntCounter::new("llmds_files_processed_total", "Input files processed").unwrap();
+        let request_duration = Histogram::with_opts(
+            HistogramOpts::new("llmds_request_duration_seconds", "Latency of chat requests")
+                .buckets(vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(request_failures.clone())).unwrap();
+        registry.register(Box::new(parse_failures.clone())).unwrap();
+        registry.register(Box::new(endpoint_failovers.clone())).unwrap();
+        registry.register(Box::new(items_generated.clone())).unwrap();
+        registry.register(Box::new(files_processed.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+
+        Self {
+            registry,
+            requests,
+            request_failures,
+            parse_failures,
+            endpoint_failovers,
+            items_generated,
+            files_processed,
+            request_duration,
+            serving: AtomicBool::new(false),
+        }
+    }
+
+    /// The metrics shared by every run in this process, so jobs run by `serve` and
+    /// `daemon` add up in one registry.
+    pub fn global() -> Arc<Self> {
+        static GLOBAL: OnceLock<Arc<Metrics>> = OnceLock::new();
+        GLOBAL.get_or_init(|| Arc::new(Self::new())).clone()
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    pub fn router(self: &Arc<Self>) -> Router {
+        Router::new()
+            .route("/metrics", get(|State(metrics): State<Arc<Metrics>>| async move { metrics.render() }))
+            .with_state(self.clone())
+    }
+
+    /// Serves `/metrics` on `addr` in the background for the lifetime of the process.
+    /// Only the first call binds; later runs in the same process reuse that server.
+    pub async fn serve(self: &Arc<Self>, addr: SocketAddr) -> Result<()> {
+        if self.serving.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                self.serving.store(false, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        };
+        info!("Serving Prometheus metrics on http://{}/metrics", addr);
+        let router = self.router();
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                error!("Metrics server stopped: {}", e);
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use tracing::info;
+use crate::budget::ItemBudget;
+use crate::config::NoiseConfig;
+use crate::dataset;
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::ProcessedItem;
+
+/// Field naming the changes made to a noisy copy of an item's question.
+pub const FIELD: &str = "noise";
+
+/// Attempts at a variant that differs from the question and the other variants.
+const MAX_ATTEMPTS: usize = 5;
+
+/// A kind of mistake in noisy copies of questions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseKind {
+    /// Swapped, dropped or doubled letters in a word
+    Typos,
+    /// All lowercase, or a lowercase first letter
+    Casing,
+    /// No final question mark, commas or apostrophes
+    Punctuation,
+}
+
+impl NoiseKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NoiseKind::Typos => "typos",
+            NoiseKind::Casing => "casing",
+            NoiseKind::Punctuation => "punctuation",
+        }
+    }
+
+    fn apply(self, text: &str, rng: &mut StdRng) -> String {
+        match self {
+            NoiseKind::Typos => {
+                let mut text = text.to_string();
+                for _ in 0..rng.gen_range(1..=2) {
+                    text = typo(&text, rng);
+                }
+                text
+            }
+            NoiseKind::Casing => match rng.gen_bool(0.5) {
+                true => text.to_lowercase(),
+                false => {
+                    let mut chars = text.chars();
+                    chars.next().map(|first| first.to_lowercase().chain(chars).collect()).unwrap_or_default()
+                }
+            },
+            NoiseKind::Punctuation => {
+                let text = text.trim_end().trim_end_matches(['?', '.', '!']);
+                match rng.gen_bool(0.5) {
+                    true => text.replace([',', '\''], ""),
+                    false => text.to_string(),
+                }
+            }
+        }
+    }
+}
+
+/// `text` with one letter of a random word of four or more letters swapped with the
+/// next, dropped or doubled; unchanged when it has no such word.
+fn typo(text: &str, rng: &mut StdRng) -> String {
+    let words: Vec<(usize, &str)> = text
+        .split(' ')
+        .scan(0, |start, word| {
+            let at = *start;
+            *start += word.len() + 1;
+            Some((at, word))
+        })
+        .filter(|(_, word)| word.chars().filter(|c| c.is_alphabetic()).count() >= 4)
+        .collect();
+    let Some(&(start, word)) = words.choose(rng) else {
+        return text.to_string();
+    };
+    let mut chars: Vec<char> = word.chars().collect();
+    let letters: Vec<usize> = (0..chars.len()).filter(|&i| chars[i].is_alphabetic()).collect();
+    let i = letters[rng.gen_range(1..letters.len())];
+    match rng.gen_range(0..3) {
+        0 if i + 1 < chars.len() && chars[i + 1].is_alphabetic() => chars.swap(i, i + 1),
+        1 => {
+            chars.remove(i);
+        }
+        _ => chars.insert(i, chars[i]),
+    }
+    format!("{}{}{}", &text[..start], chars.into_iter().collect::<String>(), &text[start + word.len()..])
+}
+
+/// Adds noisy copies of `noise.fraction` of the file's items: `variants` copies each, whose
+/// questions have realistic typos, casing changes or missing punctuation and whose answers
+/// are the same, so fine-tunes cope with messy input. Copies are tagged with the changes
+/// made in a `noise` field. The changes are random, but the same for an item in every run
+/// with the same seed.
+pub struct NoiseStage {
+    config: NoiseConfig,
+    seed: u64,
+    budget: Option<Arc<ItemBudget>>,
+}
+
+impl NoiseStage {
+    pub fn new(config: NoiseConfig, seed: Option<u64>) -> Self {
+        Self { config, seed: seed.unwrap_or(0), budget: None }
+    }
+
+    /// Keeps only the copies that fit in the run's `budget`.
+    pub fn with_budget(mut self, budget: Option<Arc<ItemBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Up to `variants` noisy copies of `item`, with distinct questions.
+    fn variants(&self, item: &ProcessedItem) -> Vec<ProcessedItem> {
+        let mut rng = StdRng::seed_from_u64(self.seed ^ dataset::stable_hash(&item.question));
+        let mut questions = vec![item.question.clone()];
+        let mut variants = Vec::new();
+        for _ in 0..self.config.variants {
+            for _ in 0..MAX_ATTEMPTS {
+                let count = rng.gen_range(1..=self.config.kinds.len());
+                let mut kinds: Vec<NoiseKind> = self.config.kinds.choose_multiple(&mut rng, count).copied().collect();
+                kinds.sort_by_key(|kind| self.config.kinds.iter().position(|k| k == kind));
+                let question = kinds.iter().fold(item.question.clone(), |text, kind| kind.apply(&text, &mut rng));
+                if question.trim().is_empty() || questions.contains(&question) {
+                    continue;
+                }
+                questions.push(question.clone());
+                let mut variant = ProcessedItem {
+                    id: None,
+                    question,
+                    embedding: None,
+                    ..item.clone()
+                };
+                let kinds: Vec<&str> = kinds.iter().map(|kind| kind.as_str()).collect();
+                variant.extra.insert(FIELD.to_string(), kinds.into());
+                variants.push(variant);
+                break;
+            }
+        }
+        variants
+    }
+}
+
+#[async_trait]
+impl Stage for NoiseStage {
+    fn name(&self) -> &'static str {
+        "noise"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        let variants: Vec<Vec<ProcessedItem>> = work
+            .items
+            .iter()
+            .map(|item| match dataset::picked(item, self.config.fraction, FIELD) && !item.extra.contains_key(FIELD) {
+                true => self.variants(item),
+                false => Vec::new(),
+            })
+            .collect();
+        let planned = variants.iter().map(Vec::len).sum();
+        let mut allowed = match &self.budget {
+            Some(budget) => budget.take(planned),
+            None => planned,
+        };
+        let added = allowed;
+        let mut items = Vec::with_capacity(work.items.len() + added);
+        for (item, mut variants) in work.items.drain(..).zip(variants) {
+            variants.truncate(allowed);
+            allowed -= variants.len();
+            items.push(item);
+            items.extend(variants);
+        }
+        work.items = items;
+        if added > 0 {
+            info!("Added {} noisy copies of questions of {:?}", added, work.path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::config::Config;
+    use crate::pipeline::WriteStage;
+
+    fn item(question: &str, answer: &str) -> ProcessedItem {
+        serde_json::from_value(serde_json::json!({ "question": question, "answer": answer })).unwrap()
+    }
+
+    fn noisy(config: NoiseConfig, budget: Option<Arc<ItemBudget>>) -> NoiseStage {
+        NoiseStage::new(config, Some(7)).with_budget(budget)
+    }
+
+    async fn processed(stage: &NoiseStage) -> Vec<ProcessedItem> {
+        let mut work = FileWork::new(Path::new("docs/guide.md"), Path::new("docs"));
+        work.items = vec![
+            item("How do I install the command-line tool?", "Run the installer."),
+            item("Where does the configuration file live?", "In your home directory."),
+        ];
+        stage.process(&mut work).await.unwrap();
+        work.items
+    }
+
+    #[test]
+    fn typos_change_a_long_word() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let changed = typo("a big elephant", &mut rng);
+            assert!(changed.starts_with("a big "));
+            assert_ne!(changed, "a big elephant");
+        }
+        assert_eq!(typo("a big cat", &mut rng), "a big cat");
+    }
+
+    #[test]
+    fn punctuation_and_casing_are_dropped() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10 {
+            assert!(!NoiseKind::Punctuation.apply("Isn't it, though?", &mut rng).ends_with('?'));
+            assert!(NoiseKind::Casing.apply("Is it Rust?", &mut rng).starts_with('i'));
+        }
+    }
+
+    #[tokio::test]
+    async fn copies_follow_their_item() {
+        let config = NoiseConfig { fraction: 1.0, variants: 2, ..NoiseConfig::default() };
+        let items = processed(&noisy(config.clone(), None)).await;
+        assert_eq!(items.len(), 6);
+        for copies in items.chunks(3) {
+            let [item, copies @ ..] = copies else { unreachable!() };
+            assert!(!item.extra.contains_key(FIELD));
+            assert_ne!(copies[0].question, copies[1].question);
+            for copy in copies {
+                assert_ne!(copy.question, item.question);
+                assert_eq!(copy.answer, item.answer);
+                assert!(copy.extra[FIELD].as_array().is_some_and(|kinds| !kinds.is_empty()));
+            }
+        }
+        // The same seed makes the same copies
+        let again: Vec<String> = processed(&noisy(config, None)).await.into_iter().map(|item| item.question).collect();
+        assert_eq!(again, items.iter().map(|item| item.question.clone()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn copies_are_taken_from_the_budget() {
+        let config = NoiseConfig { fraction: 1.0, variants: 2, ..NoiseConfig::default() };
+        let mut run = Config::default();
+        run.sampling.max_total_items = Some(3);
+        let budget = Arc::new(ItemBudget::new(&run).unwrap());
+        let items = processed(&noisy(config, Some(budget.clone()))).await;
+        let copies: Vec<bool> = items.iter().map(|item| item.extra.contains_key(FIELD)).collect();
+        assert_eq!(copies, [false, true, true, false, true]);
+        assert!(budget.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn copies_are_written_with_their_own_ids() {
+        let dir = std::env::temp_dir().join(format!("llmds-noise-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = NoiseConfig { fraction: 1.0, variants: 1, kinds: vec![NoiseKind::Casing] };
+        let original = item("Where does the configuration file live?", "In your home directory.");
+        let copy = noisy(config, None).variants(&original).remove(0);
+        assert_eq!(dataset::question_key(&copy), dataset::question_key(&original));
+
+        let mut work = FileWork::new(&dir.join("guide.md"), &dir);
+        work.items = vec![original, copy];
+        WriteStage.process(&mut work).await.unwrap();
+        let written = dataset::read_items(&dir.join("guide_qa.jsonl")).unwrap();
+        assert_eq!(written.len(), 2);
+        assert!(written.iter().all(|item| item.id.is_some()));
+        assert_ne!(written[0].id, written[1].id);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
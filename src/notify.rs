@@ -0,0 +1,73 @@
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use serde_json::json;
+use crate::report::RunReport;
+
+const DEFAULT_TEMPLATE: &str =
+    "llm_dataset_builder run {status}: {items} items from {files} files ({failed_files} failed) in {duration}s{error}";
+
+/// Posts a run summary to a webhook. Slack incoming webhooks receive a plain `text`
+/// payload; any other URL also gets the status and run totals as JSON fields.
+pub struct Notifier {
+    url: String,
+    template: String,
+    client: Client,
+}
+
+impl Notifier {
+    pub fn new(url: String, template: Option<String>) -> Self {
+        Self {
+            url,
+            template: template.unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            client: Client::new(),
+        }
+    }
+
+    pub async fn run_finished(&self, report: &RunReport) -> Result<()> {
+        let text = self.render(&[
+            ("status", report.status.to_string()),
+            ("items", report.totals.items.to_string()),
+            ("files", report.totals.files.to_string()),
+            ("failed_files", report.totals.failed_files.to_string()),
+            ("duration", format!("{:.0}", report.duration_secs)),
+            ("model", report.model.clone()),
+            ("error", String::new()),
+        ]);
+        self.post(&text, report.status, Some(serde_json::to_value(&report.totals)?)).await
+    }
+
+    pub async fn run_failed(&self, model: &str, error: &str) -> Result<()> {
+        let text = self.render(&[
+            ("status", "failed".to_string()),
+            ("items", "0".to_string()),
+            ("files", "0".to_string()),
+            ("failed_files", "0".to_string()),
+            ("duration", "0".to_string()),
+            ("model", model.to_string()),
+            ("error", format!(": {}", error)),
+        ]);
+        self.post(&text, "failed", None).await
+    }
+
+    fn render(&self, vars: &[(&str, String)]) -> String {
+        let mut text = self.template.clone();
+        for (name, value) in vars {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+
+    async fn post(&self, text: &str, status: &str, totals: Option<serde_json::Value>) -> Result<()> {
+        let payload = if self.url.contains("hooks.slack.com") {
+            json!({ "text": text })
+        } else {
+            json!({ "text": text, "status": status, "totals": totals })
+        };
+
+        let response = self.client.post(&self.url).json(&payload).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Notification webhook returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tokio::process::Command;
+use tracing::{debug, info};
+use crate::config::{Config, OcrEngine};
+use crate::metrics::Metrics;
+use crate::processor::OllamaProcessor;
+
+/// Image formats read with `images = true`.
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff", "bmp", "gif", "webp"];
+
+/// Field marking items generated from recognized text.
+pub const OCR_FIELD: &str = "ocr";
+
+/// PDF pages with fewer words in their text layer are taken for scans and recognized.
+const TEXT_LAYER_WORDS: usize = 10;
+
+const SYSTEM_PROMPT: &str = "You transcribe scanned documents. Reply with all the text of the page, in reading \
+    order, as Markdown: headings as headings, lists as lists and tables as tables. Leave out page numbers and \
+    running headers and footers, and don't describe or comment on the page.";
+
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Where the text of `path` recognized by `reader` is kept, so later runs with the same
+/// engine and model don't read it again: `manual.pdf.vision-llava-13b.ocr` next to it.
+fn text_path(path: &Path, reader: &str) -> PathBuf {
+    let reader: String = reader
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '-' })
+        .collect();
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.ocr", reader));
+    path.with_file_name(name)
+}
+
+/// The text of a PDF or image, and whether any of it was recognized rather than read
+/// from a text layer.
+pub struct Recognized {
+    pub text: String,
+    pub ocr: bool,
+}
+
+/// Reads PDFs, recognizing the pages without a text layer, and images of documents,
+/// with the tesseract CLI or a vision model on the endpoints of the run. PDF pages are
+/// read and rendered with poppler's `pdftotext` and `pdftoppm`.
+pub struct Ocr {
+    processor: Arc<OllamaProcessor>,
+    engine: OcrEngine,
+    command: String,
+    language: String,
+    model: String,
+    images: bool,
+    dpi: u32,
+}
+
+impl Ocr {
+    /// A reader for the `[ocr]` settings of `config`, or `None` when no engine is
+    /// configured.
+    pub fn new(config: Arc<Config>) -> Option<Self> {
+        let ocr = &config.ocr;
+        Some(Self {
+            engine: ocr.engine?,
+            command: ocr.command.clone(),
+            language: ocr.language.clone(),
+            model: ocr.model.clone().unwrap_or_default(),
+            images: ocr.images,
+            dpi: ocr.dpi,
+            processor: Arc::new(OllamaProcessor::new(config, Metrics::global())),
+        })
+    }
+
+    /// Asks the vision model through `processor`, so its tokens count towards the run.
+    pub fn with_processor(mut self, processor: Arc<OllamaProcessor>) -> Self {
+        self.processor = processor;
+        self
+    }
+
+    /// What recognizes the text: the engine with its language or model.
+    fn reader(&self) -> String {
+        match self.engine {
+            OcrEngine::Tesseract => format!("tesseract-{}", self.language),
+            OcrEngine::Vision => format!("vision-{}", self.model),
+        }
+    }
+
+    /// Whether `path` is a PDF, or an image when images are read too.
+    pub fn reads(&self, path: &Path) -> bool {
+        is_pdf(path) || (self.images && is_image(path))
+    }
+
+    /// The text of the PDF or image at `path`, from an earlier run when its `.ocr` file
+    /// is newer than it.
+    pub async fn read(&self, path: &Path) -> Result<Recognized> {
+        let cached = text_path(path, &self.reader());
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        if modified(&cached).is_some_and(|cached| modified(path).is_none_or(|file| cached >= file)) {
+            debug!("Using the recognized text of {:?} from {:?}", path, cached);
+            return Ok(Recognized {
+                text: fs::read_to_string(&cached)?,
+                ocr: true,
+            });
+        }
+
+        let recognized = match is_pdf(path) {
+            true => self.pdf(path).await?,
+            false => {
+                info!("Recognizing the text of {:?}", path);
+                Recognized {
+                    text: self.recognize(path, path).await?,
+                    ocr: true,
+                }
+            }
+        };
+        // A PDF that only has text layers is quick to read again
+        if recognized.ocr {
+            fs::write(&cached, &recognized.text).map_err(|e| anyhow!("Failed to write {:?}: {}", cached, e))?;
+        }
+        Ok(recognized)
+    }
+
+    /// The pages of a PDF: their text layer, or the text recognized on them when it has
+    /// too few words.
+    async fn pdf(&self, path: &Path) -> Result<Recognized> {
+        let layers = run(Command::new("pdftotext").arg(path).arg("-"), "pdftotext").await?;
+        let mut pages: Vec<String> = layers.split('\x0c').map(str::to_string).collect();
+        // Every page ends with a form feed
+        if pages.last().is_some_and(|page| page.trim().is_empty()) {
+            pages.pop();
+        }
+
+        let scanned: Vec<usize> = (0..pages.len()).filter(|&page| pages[page].split_whitespace().count() < TEXT_LAYER_WORDS).collect();
+        if !scanned.is_empty() {
+            info!("Recognizing the text of {} of {} pages of {:?}", scanned.len(), pages.len(), path);
+            let dir = std::env::temp_dir().join(format!("llmds-ocr-{:016x}", rand::random::<u64>()));
+            fs::create_dir_all(&dir)?;
+            let result = self.scanned_pages(path, &dir, &scanned, &mut pages).await;
+            let _ = fs::remove_dir_all(&dir);
+            result?;
+        }
+        let text: Vec<&str> = pages.iter().map(|page| page.trim()).filter(|page| !page.is_empty()).collect();
+        Ok(Recognized {
+            text: text.join("\n\n") + "\n",
+            ocr: !scanned.is_empty(),
+        })
+    }
+
+    /// Renders the `scanned` pages of the PDF at `path` into `dir` and recognizes them.
+    async fn scanned_pages(&self, path: &Path, dir: &Path, scanned: &[usize], pages: &mut [String]) -> Result<()> {
+        for &page in scanned {
+            let number = (page + 1).to_string();
+            let prefix = dir.join(format!("page-{}", number));
+            let mut render = Command::new("pdftoppm");
+            render.args(["-r", &self.dpi.to_string(), "-f", &number, "-l", &number, "-png", "-singlefile"]).arg(path).arg(&prefix);
+            run(&mut render, "pdftoppm").await?;
+            debug!("Recognizing page {} of {:?}", number, path);
+            pages[page] = self.recognize(&prefix.with_extension("png"), path).await?;
+        }
+        Ok(())
+    }
+
+    /// The text of the image at `path`, a page of the document `file` or the document itself.
+    async fn recognize(&self, path: &Path, file: &Path) -> Result<String> {
+        match self.engine {
+            OcrEngine::Tesseract => run(Command::new(&self.command).arg(path).arg("stdout").args(["-l", &self.language]), &self.command).await,
+            OcrEngine::Vision => self.ask(path, file).await,
+        }
+    }
+
+    /// The text of the image at `path`, from the vision model.
+    async fn ask(&self, path: &Path, file: &Path) -> Result<String> {
+        let image = fs::read(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        let messages = serde_json::json!([
+            { "role": "system", "content": SYSTEM_PROMPT },
+            { "role": "user", "content": "Transcribe this page.", "images": [BASE64.encode(image)] }
+        ]);
+        let text = self
+            .processor
+            .chat(file, &self.model, messages, None, serde_json::json!({ "temperature": 0 }))
+            .await
+            .map_err(|e| e.context(format!("Recognizing the text of {:?} failed", path)))?;
+        Ok(text.trim().to_string() + "\n")
+    }
+}
+
+/// The standard output of `command`, named `name` in errors.
+async fn run(command: &mut Command, name: &str) -> Result<String> {
+    let output = command.output().await.map_err(|e| anyhow!("Failed to run {}: {}", name, e))?;
+    if !output.status.success() {
+        return Err(anyhow!("{} failed ({}): {}", name, output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognized_text_is_kept_per_reader() {
+        assert_eq!(text_path(Path::new("docs/manual.pdf"), "vision-llava:13b"), Path::new("docs/manual.pdf.vision-llava-13b.ocr"));
+        assert_eq!(text_path(Path::new("scan.png"), "tesseract-eng+deu"), Path::new("scan.png.tesseract-eng-deu.ocr"));
+    }
+}
@@ -0,0 +1,245 @@
+use std::path::Path;
+use std::sync::LazyLock;
+use regex::{Captures, Regex};
+use crate::compression;
+
+/// `* TODO [#A] Heading :tag1:tag2:`: the stars, and the title without its keyword,
+/// priority and tags.
+static HEADING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\*+)\s+(?:(?:TODO|NEXT|WAITING|HOLD|DONE|CANCELED|CANCELLED)\s+)?(?:\[#[A-Za-z0-9]\]\s+)?(.*?)(?:\s+:[\w@#%:]+:)?\s*$").unwrap()
+});
+
+/// `#+KEY: value` lines setting options of the file.
+static KEYWORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*#\+(\w+):\s*(.*)$").unwrap());
+
+/// The start of a drawer, such as `:PROPERTIES:` or `:LOGBOOK:`, on a line of its own.
+static DRAWER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*:[\w-]+:\s*$").unwrap());
+
+/// `SCHEDULED:`, `DEADLINE:` and `CLOSED:` lines under a heading.
+static PLANNING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*(?:SCHEDULED|DEADLINE|CLOSED):").unwrap());
+
+/// `#+BEGIN_SRC python :results output` and the other blocks: the block's type and
+/// what follows it.
+static BLOCK_BEGIN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^\s*#\+begin_(\w+)\s*(.*)$").unwrap());
+
+/// `[[target][description]]` and `[[target]]`.
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\[([^\]]+)\](?:\[([^\]]+)\])?\]").unwrap());
+
+/// `- term :: description` items of a description list.
+static DESCRIPTION_ITEM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\s*[-+] )(.+?) :: (.*)$").unwrap());
+
+/// `*bold*`, `/italic/`, `+strike-through+`, `=verbatim=` and `~code~`, after the start
+/// of the line, a space or an opening bracket or quote.
+static EMPHASIS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(^|[\s(\[{"'])(?:\*([^\s*](?:[^*]*[^\s*])?)\*|/([^\s/](?:[^/]*[^\s/])?)/|\+([^\s+](?:[^+]*[^\s+])?)\+)"#).unwrap()
+});
+static VERBATIM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(^|[\s(\[{"'])(?:=([^\s=](?:[^=]*[^\s=])?)=|~([^\s~](?:[^~]*[^\s~])?)~)"#).unwrap()
+});
+
+/// Whether `path` is an Org-mode file.
+pub fn is_org(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("org"))
+}
+
+/// `text`, an Org-mode document, as Markdown, so it is split into sections at its
+/// headings like any docs: `#+TITLE` becomes the top heading, `*` headings lose their
+/// TODO keywords, priorities and tags, source and example blocks become code fences and
+/// quotes become block quotes. Drawers, planning lines, comments and export settings
+/// are left out.
+pub fn render(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut title = None;
+    // The type of the block the line is in, and the drawer it is in
+    let mut block: Option<String> = None;
+    let mut drawer = false;
+    let mut fixed_width = false;
+    for line in text.lines() {
+        if let Some(kind) = &block {
+            if line.trim().eq_ignore_ascii_case(&format!("#+end_{}", kind)) {
+                if matches!(kind.as_str(), "src" | "example") {
+                    lines.push("```".to_string());
+                }
+                block = None;
+                continue;
+            }
+            match kind.as_str() {
+                // Org escapes lines starting with `*` or `#+` in blocks with a comma
+                "src" | "example" => lines.push(line.strip_prefix(',').filter(|rest| rest.starts_with('*') || rest.starts_with("#+")).unwrap_or(line).to_string()),
+                "quote" => lines.push(format!("> {}", inline(line.trim())).trim_end().to_string()),
+                "comment" | "export" => {}
+                _ => lines.push(inline(line)),
+            }
+            continue;
+        }
+        if drawer {
+            drawer = !line.trim().eq_ignore_ascii_case(":end:");
+            continue;
+        }
+
+        // `: text` lines are fixed-width, like code
+        let fixed = line.trim_start().strip_prefix(": ").or_else(|| (line.trim() == ":").then_some(""));
+        if fixed.is_some() != fixed_width {
+            lines.push("```".to_string());
+            fixed_width = fixed.is_some();
+        }
+        if let Some(fixed) = fixed {
+            lines.push(fixed.to_string());
+            continue;
+        }
+
+        if let Some(captures) = BLOCK_BEGIN.captures(line) {
+            let kind = captures[1].to_lowercase();
+            if matches!(kind.as_str(), "src" | "example") {
+                let language = match kind.as_str() {
+                    "src" => captures[2].split_whitespace().next().unwrap_or(""),
+                    _ => "",
+                };
+                lines.push(format!("```{}", language));
+            }
+            block = Some(kind);
+        } else if let Some(captures) = KEYWORD.captures(line) {
+            if captures[1].eq_ignore_ascii_case("title") && title.is_none() {
+                title = Some(inline(captures[2].trim()));
+            }
+        } else if let Some(captures) = HEADING.captures(line) {
+            let level = captures[1].len();
+            lines.push(format!("{} {}", "#".repeat(level), inline(&captures[2])));
+        } else if DRAWER.is_match(line) {
+            drawer = true;
+        } else if PLANNING.is_match(line) || line.trim_start() == "#" || line.trim_start().starts_with("# ") {
+            continue;
+        } else if line.trim_start().starts_with('|') {
+            lines.push(table_row(line));
+        } else if let Some(captures) = DESCRIPTION_ITEM.captures(line) {
+            lines.push(format!("{}**{}**: {}", &captures[1], inline(&captures[2]), inline(&captures[3])));
+        } else {
+            lines.push(inline(line));
+        }
+    }
+    if fixed_width || block.as_deref().is_some_and(|kind| matches!(kind, "src" | "example")) {
+        lines.push("```".to_string());
+    }
+
+    let body = lines.join("\n");
+    match title.filter(|title| !title.is_empty()) {
+        // Headings move down a level under the title
+        Some(title) => format!("# {}\n\n{}\n", title, shift_headings(&body)),
+        None => format!("{}\n", body.trim()),
+    }
+}
+
+/// `body` with every heading outside code fences a level deeper.
+fn shift_headings(body: &str) -> String {
+    let mut fence = false;
+    let lines: Vec<String> = body
+        .lines()
+        .map(|line| {
+            if line.starts_with("```") {
+                fence = !fence;
+            }
+            match !fence && line.starts_with('#') {
+                true => format!("#{}", line),
+                false => line.to_string(),
+            }
+        })
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+/// An Org table row as a Markdown one; the `|---+---|` rule under the header becomes
+/// the Markdown separator.
+fn table_row(line: &str) -> String {
+    let row = line.trim();
+    match row.starts_with("|-") {
+        true => row.replace('+', "|"),
+        false => inline(row),
+    }
+}
+
+/// `text` with Org links and emphasis as Markdown. Verbatim and code spans are turned
+/// into code first, and left alone after that.
+fn inline(text: &str) -> String {
+    let text = LINK.replace_all(text, |captures: &Captures| {
+        let target = &captures[1];
+        let description = captures.get(2).map(|description| description.as_str());
+        match (target.contains("://"), description) {
+            (true, Some(description)) => format!("[{}]({})", description, target),
+            (true, None) => format!("<{}>", target),
+            // Links to files, headings and ids only show their text
+            (false, Some(description)) => description.to_string(),
+            (false, None) => target.trim_start_matches("file:").trim_start_matches(['*', '#']).to_string(),
+        }
+    });
+    let text = VERBATIM.replace_all(&text, |captures: &Captures| {
+        format!("{}`{}`", &captures[1], captures.get(2).or(captures.get(3)).map_or("", |code| code.as_str()))
+    });
+    // Between code spans, at the even indexes
+    text.split('`')
+        .enumerate()
+        .map(|(index, part)| match index % 2 {
+            0 => EMPHASIS
+                .replace_all(part, |captures: &Captures| match (captures.get(2), captures.get(3), captures.get(4)) {
+                    (Some(bold), _, _) => format!("{}**{}**", &captures[1], bold.as_str()),
+                    (_, Some(italic), _) => format!("{}*{}*", &captures[1], italic.as_str()),
+                    (_, _, Some(strike)) => format!("{}~~{}~~", &captures[1], strike.as_str()),
+                    _ => captures[0].to_string(),
+                })
+                .into_owned(),
+            _ => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("`")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUIDE: &str = "#+TITLE: The *Tool* Guide\n#+OPTIONS: toc:nil\n\n\
+        * TODO [#A] Install :setup:linux:\n\
+        SCHEDULED: <2024-05-01 Wed>\n\
+        :PROPERTIES:\n:ID: install\n:END:\n\
+        Run the /installer/ from [[https://example.com/download][the site]], then =tool --version=.\n\
+        # a comment\n\
+        ** Check it\n\
+        #+BEGIN_SRC sh :results output\ntool check\n,* not a heading\n#+END_SRC\n\
+        * Reference\n\
+        - timeout :: seconds to wait, see [[*Install]]\n\
+        | Flag | Meaning |\n|------+---------|\n| -v | verbose |\n\
+        #+BEGIN_QUOTE\nIt just works.\n#+END_QUOTE\n\
+        : $ tool --help\n";
+
+    #[test]
+    fn documents_are_rendered_as_markdown() {
+        assert_eq!(
+            render(GUIDE),
+            "# The **Tool** Guide\n\n\
+             ## Install\n\
+             Run the *installer* from [the site](https://example.com/download), then `tool --version`.\n\
+             ### Check it\n\
+             ```sh\ntool check\n* not a heading\n```\n\
+             ## Reference\n\
+             - **timeout**: seconds to wait, see Install\n\
+             | Flag | Meaning |\n|------|---------|\n| -v | verbose |\n\
+             > It just works.\n\
+             ```\n$ tool --help\n```\n"
+        );
+    }
+
+    #[test]
+    fn documents_without_a_title_keep_their_levels() {
+        assert_eq!(render("* DONE Setup\nSee [[file:notes.org]] and [[https://example.com]].\n"), "# Setup\nSee notes.org and <https://example.com>.\n");
+        assert_eq!(render("#+begin_example\nunterminated"), "```\nunterminated\n```\n");
+    }
+
+    #[test]
+    fn code_spans_keep_their_markers() {
+        assert_eq!(inline("~a *b* c~ and *d* and a/b/c"), "`a *b* c` and **d** and a/b/c");
+        assert_eq!(inline("+gone+ and 2*3*4"), "~~gone~~ and 2*3*4");
+        assert!(is_org(Path::new("notes/todo.ORG")));
+        assert!(is_org(Path::new("notes/todo.org.gz")));
+        assert!(!is_org(Path::new("notes/todo.md")));
+    }
+}
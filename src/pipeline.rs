@@ -0,0 +1,1400 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use futures::stream::{self, StreamExt};
+use tokio::sync::mpsc;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+use crate::budget::ItemBudget;
+use crate::changelog::{self, ChangelogVersion, Release, ReleaseTracker};
+use crate::cli_help;
+use crate::compression;
+use crate::config::{Config, DeprecationMode, FileSettings};
+use crate::config_reference;
+use crate::dataset;
+use crate::deprecation::Deprecations;
+use crate::discourse;
+use crate::docs_site::{self, DocsSite};
+use crate::duplicates::DuplicateSectionStage;
+use crate::embeddings::{ChunkRecord, EmbedStage, Embedder};
+use crate::encoding::{self, Content};
+use crate::error::Error;
+use crate::faq;
+use crate::filter::ItemFilter;
+use crate::follow_ups::FollowUpStage;
+use crate::freshness;
+use crate::frontmatter;
+use crate::hooks::{Event, Hooks};
+use crate::images::ImageStage;
+use crate::infrastructure;
+use crate::instructions::InstructionStage;
+use crate::logs;
+use crate::metrics::Metrics;
+use crate::noise::NoiseStage;
+use crate::ocr::{self, Ocr};
+use crate::org;
+use crate::plugins::Plugins;
+use crate::processor::{ItemSource, OllamaProcessor, ProcessedItem};
+use crate::progress::Progress;
+use crate::prompt;
+use crate::refusals::RefusalStage;
+use crate::report::{FileReport, FileStats};
+use crate::sql_schema::{self, TextToSqlStage};
+use crate::stack_exchange;
+use crate::stream::StreamedFile;
+use crate::strip::{self, Stripper};
+use crate::terms::TermNormalizer;
+use crate::tickets;
+use crate::transcribe::{self, Transcriber};
+use crate::usage::TokenUsage;
+use crate::vault::{self, Vault};
+
+/// Files buffered between two stages, so fast stages can work ahead of slow ones
+/// without reading the whole corpus into memory.
+const STAGE_BUFFER: usize = 4;
+
+/// A section of a file and the number of questions it should yield.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub index: usize,
+    pub text: String,
+    pub target: usize,
+    /// Release the section belongs to, for release notes.
+    pub release: Option<Release>,
+}
+
+impl Section {
+    /// A section whose target is its share, by word count, of the file's questions.
+    fn new(index: usize, text: String, file_questions: usize, file_words: usize) -> Self {
+        let words = OllamaProcessor::count_words(&text);
+        let target = (file_questions as f64 * (words as f64 / file_words as f64)).ceil() as usize;
+        debug!("Section {} ({} words, target {} questions)", index + 1, words, target);
+        Self { index, text, target, release: None }
+    }
+
+    fn with_release(mut self, releases: &mut Option<ReleaseTracker>) -> Self {
+        self.release = releases.as_mut().and_then(|releases| releases.section(&self.text));
+        self
+    }
+
+    pub fn id(&self) -> String {
+        chunk_id(&self.text)
+    }
+}
+
+/// The id of a section with `text`: a hash of its words, so it stays the same across runs
+/// and when the file is moved or renamed, and changes when the text does (but not when
+/// it is only reflowed). Sections with the same text in different files share it.
+pub fn chunk_id(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    format!("{:016x}", dataset::stable_hash(&words.join(" ")))
+}
+
+/// A file on its way through the pipeline; each stage fills in its part.
+pub struct FileWork {
+    pub path: PathBuf,
+    /// Path relative to the output directory, used to resolve settings and tag items.
+    pub relative_path: PathBuf,
+    /// The file's text; only its first `stream::HEAD_LEN` bytes when it is streamed.
+    pub content: String,
+    /// Set for files above `stream_threshold_mb`, whose sections are read from disk
+    /// while generating instead of being held in `sections`.
+    pub stream: Option<StreamedFile>,
+    /// Resolved by the parse stage.
+    pub settings: Option<FileSettings>,
+    pub title: String,
+    /// Tags of a note in an Obsidian or Logseq vault, recorded on its items.
+    pub tags: Vec<String>,
+    /// Whether some of the text was recognized on scanned pages or an image, recorded
+    /// on its items.
+    pub ocr: bool,
+    /// Categories leading to a page of a Docusaurus or MkDocs site in its navigation.
+    pub breadcrumbs: Vec<String>,
+    /// When the file last changed, from git or its modification time.
+    pub modified: Option<NaiveDate>,
+    pub sections: Vec<Section>,
+    pub items: Vec<ProcessedItem>,
+    /// Items of an earlier run's QA file that had too few of them; generation only tops
+    /// them up, and they are kept next to the new items.
+    pub existing: Vec<ProcessedItem>,
+    /// Embedded sections, when `embeddings.chunks` or `[synthesis]` is enabled.
+    pub chunks: Vec<ChunkRecord>,
+    pub stats: FileStats,
+    pub usage: TokenUsage,
+    pub error: Option<Error>,
+    started: Instant,
+    span: Span,
+}
+
+impl FileWork {
+    pub fn new(path: &Path, output_dir: &Path) -> Self {
+        Self {
+            relative_path: path.strip_prefix(output_dir).unwrap_or(path).to_path_buf(),
+            path: path.to_path_buf(),
+            content: String::new(),
+            stream: None,
+            settings: None,
+            title: String::new(),
+            tags: Vec::new(),
+            ocr: false,
+            breadcrumbs: Vec::new(),
+            modified: None,
+            sections: Vec::new(),
+            items: Vec::new(),
+            existing: Vec::new(),
+            chunks: Vec::new(),
+            stats: FileStats::default(),
+            usage: TokenUsage::default(),
+            error: None,
+            started: Instant::now(),
+            span: info_span!("file", path = %path.display(), profile = field::Empty, items = field::Empty),
+        }
+    }
+
+    /// Words in the whole file, including the parts of a streamed file not in `content`.
+    pub fn word_count(&self) -> usize {
+        match &self.stream {
+            Some(stream) => stream.words,
+            None => OllamaProcessor::count_words(&self.content),
+        }
+    }
+
+    /// When the file is being topped up, the questions of the existing items from the
+    /// section at `index` or from an unknown section, which the model is asked not to
+    /// repeat.
+    pub fn existing_questions(&self, index: usize) -> Option<Vec<String>> {
+        if self.existing.is_empty() {
+            return None;
+        }
+        Some(
+            self.existing
+                .iter()
+                .filter(|item| item.source.as_ref().is_none_or(|source| source.section == index))
+                .map(|item| item.question.clone())
+                .collect(),
+        )
+    }
+
+    pub fn settings(&self) -> Result<&FileSettings> {
+        self.settings
+            .as_ref()
+            .ok_or_else(|| anyhow!("{:?} has not been parsed", self.path))
+    }
+
+    /// True once the file failed, was skipped or its existing items were reused, so the
+    /// remaining stages leave it alone.
+    pub fn is_finished(&self) -> bool {
+        self.error.is_some() || self.stats.skipped.is_some() || self.stats.reused_existing
+    }
+
+    pub fn report(&self) -> FileReport {
+        self.span.record("items", self.items.len());
+        FileReport {
+            path: self.path.clone(),
+            items: self.items.len(),
+            stats: self.stats.clone(),
+            duration_secs: self.started.elapsed().as_secs_f64(),
+            requests: self.usage.requests,
+            prompt_tokens: self.usage.prompt_tokens,
+            completion_tokens: self.usage.completion_tokens,
+            error: self.error.clone(),
+        }
+    }
+}
+
+/// One step of processing a file.
+#[async_trait]
+pub trait Stage: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &'static str;
+
+    async fn process(&self, work: &mut FileWork) -> Result<()>;
+}
+
+/// Stages that files pass through in order, each running in its own task and
+/// connected to the next by a channel.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stage<S: Stage + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    /// parse → chunk → (dedupe) → generate → (images) → (text-to-SQL) → filter → (refusals) → (instructions) →
+    /// (follow-ups) → (noise) → (embed) → write, as run by `generate`. Fails when a configured
+    /// plugin or the refusal policy can't be loaded.
+    pub fn standard(
+        config: Arc<Config>,
+        processor: Arc<OllamaProcessor>,
+        progress: Arc<Progress>,
+        metrics: Arc<Metrics>,
+        hooks: Arc<Hooks>,
+        budget: Option<Arc<ItemBudget>>,
+    ) -> Result<Self> {
+        let plugins = Arc::new(Plugins::load(&config.plugins)?);
+        let embedder = Embedder::new(&config.embeddings, &config.endpoint).filter(|_| config.embeddings.chunks || config.synthesis.enabled);
+        let mut pipeline = Self::new()
+            .with_stage(ParseStage::new(config.clone(), plugins.clone())?.with_processor(processor.clone()))
+            .with_stage(ChunkStage::new(config.clone()).with_budget(budget.clone()));
+        if config.section_dedupe.enabled {
+            let embedder = Embedder::new(&config.embeddings, &config.endpoint);
+            pipeline = pipeline.with_stage(DuplicateSectionStage::new(embedder, config.section_dedupe.max_similarity));
+        }
+        pipeline = pipeline.with_stage(GenerateStage::new(processor.clone(), progress.clone()).with_budget(budget.clone()));
+        // The coordinator doesn't call Ollama itself, so distributed runs leave images out
+        if config.images.model.is_some() && !processor.is_distributed() {
+            pipeline = pipeline.with_stage(ImageStage::new(config.clone(), processor.clone()));
+        }
+        // Text-to-SQL items are filtered and counted against the budget like generated ones
+        if config.sql.text_to_sql > 0 && !processor.is_distributed() {
+            pipeline = pipeline.with_stage(TextToSqlStage::new(config.sql.clone(), processor.clone()));
+        }
+        let mut pipeline = pipeline.with_stage(FilterStage::new(config.clone(), progress, metrics, hooks, plugins).with_budget(budget.clone()));
+        if config.refusals.fraction > 0.0 && !processor.is_distributed() {
+            pipeline = pipeline.with_stage(RefusalStage::new(&config.refusals, processor.clone())?.with_budget(budget.clone()));
+        }
+        if config.instructions.fraction > 0.0 && !processor.is_distributed() {
+            pipeline = pipeline.with_stage(InstructionStage::new(config.instructions.clone(), processor.clone()));
+        }
+        if config.follow_ups.fraction > 0.0 && !processor.is_distributed() {
+            pipeline = pipeline.with_stage(FollowUpStage::new(config.follow_ups.clone(), processor));
+        }
+        if config.noise.fraction > 0.0 {
+            pipeline = pipeline.with_stage(NoiseStage::new(config.noise.clone(), config.seed).with_budget(budget));
+        }
+        if let Some(embedder) = embedder {
+            pipeline = pipeline.with_stage(EmbedStage::new(embedder));
+        }
+        Ok(pipeline.with_stage(WriteStage))
+    }
+
+    async fn run_stage(stage: &dyn Stage, work: &mut FileWork) {
+        if work.is_finished() {
+            return;
+        }
+        let span = work.span.clone();
+        if let Err(e) = stage.process(work).instrument(span).await {
+            work.error = Some(Error::from(e).context(&format!("{} stage", stage.name())));
+        }
+    }
+
+    /// Runs a single file through every stage in the current task.
+    pub async fn process(&self, mut work: FileWork) -> FileWork {
+        for stage in &self.stages {
+            Self::run_stage(stage.as_ref(), &mut work).await;
+        }
+        work
+    }
+
+    /// Starts a task per stage and returns the receiving end of the last one. Files
+    /// come out in the order they were sent in.
+    pub fn run(&self, input: mpsc::Receiver<FileWork>) -> mpsc::Receiver<FileWork> {
+        let mut input = input;
+        for stage in &self.stages {
+            let (sender, output) = mpsc::channel(STAGE_BUFFER);
+            let stage = stage.clone();
+            let mut receiver = input;
+            tokio::spawn(async move {
+                while let Some(mut work) = receiver.recv().await {
+                    Self::run_stage(stage.as_ref(), &mut work).await;
+                    if sender.send(work).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            input = output;
+        }
+        input
+    }
+}
+
+pub(crate) fn qa_path(file_path: &Path, extension: &str) -> PathBuf {
+    let file_stem = compression::inner_path(file_path);
+    let file_stem = file_stem
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown");
+    file_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(format!("{}_qa.{}", file_stem, extension))
+}
+
+/// Items found in a file's QA file from an earlier run.
+enum ExistingQa {
+    /// Enough to skip generating the file.
+    Reuse(Vec<ProcessedItem>),
+    /// Too few; the file is generated again for the missing questions only.
+    TopUp(Vec<ProcessedItem>),
+}
+
+/// `text`, the content of `path`, as Markdown when it is infrastructure code, a
+/// configuration reference, the help text or man page of a command, a SQL schema, a log
+/// or an Org-mode document; `None` for anything else.
+pub fn render_structured(path: &Path, text: &str) -> Option<Result<String>> {
+    if infrastructure::is_infrastructure(path, text) {
+        Some(infrastructure::render(path, text))
+    } else if config_reference::is_config_reference(path) {
+        Some(config_reference::render(path, text))
+    } else if cli_help::is_cli_reference(path) {
+        Some(cli_help::render(path, text))
+    } else if sql_schema::is_sql(path) {
+        Some(sql_schema::render(path, text))
+    } else if logs::is_log(path) {
+        Some(logs::render(path, text))
+    } else if org::is_org(path) {
+        Some(Ok(org::render(text)))
+    } else {
+        None
+    }
+}
+
+/// How the parse stage read a file.
+enum Read {
+    Whole,
+    /// Only the head is in the content; the rest is read from disk in this encoding.
+    Streamed(&'static encoding_rs::Encoding),
+    /// The file has nothing to read, with the reason in its stats.
+    Skipped,
+}
+
+/// A file's text as generation sees it, with the settings for it.
+pub struct PreparedContent {
+    pub content: String,
+    pub settings: FileSettings,
+}
+
+impl PreparedContent {
+    /// The texts of the sections the chunk stage splits the content into, before thin
+    /// ones are left out, so the `section` of an item's source indexes them.
+    pub fn sections(&self) -> Vec<String> {
+        let changelog = self.settings.is_release_notes().then(|| changelog::parse_keep_a_changelog(&self.content)).flatten();
+        match changelog {
+            Some(versions) => changelog::sections(&versions).into_iter().map(|(text, _)| text).collect(),
+            None => OllamaProcessor::split_into_sections(&self.content, &self.settings.chunking),
+        }
+    }
+}
+
+/// Reads the file, through a parser plugin for its extension if there is one, resolves
+/// its settings and reuses a previous `_qa` file that already has enough items, or keeps
+/// the items of one that has too few to be topped up.
+pub struct ParseStage {
+    config: Arc<Config>,
+    plugins: Arc<Plugins>,
+    stripper: Stripper,
+    deprecations: Deprecations,
+    transcriber: Option<Transcriber>,
+    ocr: Option<Ocr>,
+    /// Vaults notes were read from, by root, indexed once each.
+    vaults: Mutex<HashMap<PathBuf, Arc<Vault>>>,
+    /// Docs sites pages were read from, by root, their navigation read once each.
+    sites: Mutex<HashMap<PathBuf, Arc<DocsSite>>>,
+}
+
+impl ParseStage {
+    /// Fails when the `[strip]` rules or the deprecation markers are invalid.
+    pub fn new(config: Arc<Config>, plugins: Arc<Plugins>) -> Result<Self> {
+        let stripper = Stripper::new(&config.strip)?;
+        let deprecations = Deprecations::new(&config.deprecations)?;
+        let transcriber = Transcriber::new(&config.transcription);
+        let ocr = Ocr::new(config.clone());
+        Ok(Self {
+            config,
+            plugins,
+            stripper,
+            deprecations,
+            transcriber,
+            ocr,
+            vaults: Mutex::default(),
+            sites: Mutex::default(),
+        })
+    }
+
+    /// Recognizes text with the run's `processor`, so OCR tokens count towards its usage.
+    pub fn with_processor(mut self, processor: Arc<OllamaProcessor>) -> Self {
+        self.ocr = self.ocr.map(|ocr| ocr.with_processor(processor));
+        self
+    }
+
+    /// The vault the file is in when it is a Markdown note of one.
+    fn vault(&self, work: &FileWork) -> Option<Arc<Vault>> {
+        let note = compression::inner_path(&work.path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md"));
+        let root = vault::root(Path::new(&self.config.output_dir), &work.relative_path).filter(|_| note)?;
+        let mut vaults = self.vaults.lock().unwrap();
+        let vault = vaults.entry(root).or_insert_with_key(|root| {
+            info!("Reading the vault at {:?}", root);
+            Arc::new(Vault::load(root))
+        });
+        Some(vault.clone())
+    }
+
+    /// The docs site the file is a page of, if any.
+    fn docs_site(&self, work: &FileWork) -> Option<Arc<DocsSite>> {
+        let root = docs_site::root(Path::new(&self.config.output_dir), &work.relative_path)?;
+        let mut sites = self.sites.lock().unwrap();
+        let site = sites.entry(root).or_insert_with_key(|root| {
+            info!("Reading the navigation of the docs site at {:?}", root);
+            Arc::new(DocsSite::load(root))
+        });
+        Some(site.clone())
+    }
+
+    /// Reads the text of `work`'s file into its content: from a parser plugin, a
+    /// transcript, recognized text or the file itself, rendered when it is structured.
+    /// With `stream`, files above `stream_threshold_mb` are streamed and only their head
+    /// is read.
+    async fn read(&self, work: &mut FileWork, stream: bool) -> Result<Read> {
+        let threshold = match stream {
+            true => self.config.stream_threshold_mb.saturating_mul(1024 * 1024),
+            false => u64::MAX,
+        };
+        let mut streamed = None;
+        let mut parsed = self.plugins.parse(&work.path)?;
+        // Audio is transcribed unless a parser plugin handles it
+        if let (None, Some(transcriber)) = (&parsed, &self.transcriber) {
+            if transcribe::is_audio(&work.path) {
+                parsed = Some(transcriber.transcript(&work.path).await?);
+            }
+        }
+        // And so are scanned PDFs and images of documents
+        if let (None, Some(ocr)) = (&parsed, &self.ocr) {
+            if ocr.reads(&work.path) {
+                let recognized = ocr.read(&work.path).await?;
+                if OllamaProcessor::count_words(&recognized.text) < self.config.ocr.min_words {
+                    info!("Skipping {:?}: fewer than {} words of text", work.path, self.config.ocr.min_words);
+                    work.stats.skipped = Some("too little text".to_string());
+                    return Ok(Read::Skipped);
+                }
+                work.ocr = recognized.ocr;
+                parsed = Some(recognized.text);
+            }
+        }
+        work.content = match parsed {
+            Some(text) => text,
+            None if fs::metadata(&work.path)?.len() > threshold => match StreamedFile::head(&work.path)? {
+                Some((head, encoding)) => {
+                    info!("Streaming {:?} ({} MiB threshold)", work.path, self.config.stream_threshold_mb);
+                    streamed = Some(encoding);
+                    head
+                }
+                None => {
+                    Self::skip_binary(work);
+                    return Ok(Read::Skipped);
+                }
+            },
+            None => match encoding::read(&work.path)? {
+                Content::Text { text, encoding } => {
+                    if let Some(encoding) = encoding {
+                        info!("Transcoding {:?} from {} to UTF-8", work.path, encoding);
+                        work.stats.encoding = Some(encoding.to_string());
+                    }
+                    match render_structured(&work.path, &text) {
+                        Some(Ok(rendered)) => rendered,
+                        Some(Err(e)) => {
+                            warn!("Reading {:?} as plain text: {}", work.path, e);
+                            text
+                        }
+                        None => text,
+                    }
+                }
+                Content::Binary => {
+                    Self::skip_binary(work);
+                    return Ok(Read::Skipped);
+                }
+            },
+        };
+        Ok(match streamed {
+            Some(encoding) => Read::Streamed(encoding),
+            None => Read::Whole,
+        })
+    }
+
+    /// Prepares the text read into `work` for chunking, returning the settings for it: a
+    /// vault note's links are resolved, boilerplate is stripped and, with
+    /// `deprecations.mode = "skip"`, passages about deprecated features are left out.
+    /// Streamed files are only read section by section, so they are left as they are.
+    fn prepare(&self, work: &mut FileWork, streamed: bool) -> Result<FileSettings> {
+        if let Some(vault) = self.vault(work).filter(|_| !streamed) {
+            let note = vault.note(&work.content);
+            work.content = note.text;
+            work.tags = note.tags;
+        }
+
+        if (!self.stripper.is_empty() || strip::is_html(&work.content)) && !streamed {
+            let words = OllamaProcessor::count_words(&work.content);
+            work.content = self.stripper.strip(&work.content);
+            debug!("Stripped boilerplate: {} of {} words left", OllamaProcessor::count_words(&work.content), words);
+        }
+
+        // Resolve directory overrides and the content profile for this file
+        let settings = self.config.settings_for(&work.relative_path, &work.content)?;
+        debug!("Using profile {:?} with model {}", settings.profile, settings.model);
+        if self.config.deprecations.mode == DeprecationMode::Skip && !settings.is_release_notes() && !streamed {
+            let (content, removed) = self.deprecations.strip(&work.content);
+            if removed > 0 {
+                info!("Leaving out {} passages about deprecated features of {:?}", removed, work.path);
+                work.content = content;
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The text of `file`, relative to the output directory, as generation sees it, with
+    /// the settings for it: read and prepared as in the parse stage, but whole even above
+    /// `stream_threshold_mb`. `None` when the file is binary or has too little text.
+    pub async fn prepared_content(&self, file: &str) -> Result<Option<PreparedContent>> {
+        let output_dir = Path::new(&self.config.output_dir);
+        let mut work = FileWork::new(&output_dir.join(file), output_dir);
+        if let Read::Skipped = self.read(&mut work, false).await? {
+            return Ok(None);
+        }
+        let settings = self.prepare(&mut work, false)?;
+        Ok(Some(PreparedContent {
+            content: work.content,
+            settings,
+        }))
+    }
+
+    fn skip_binary(work: &mut FileWork) {
+        warn!("Skipping {:?}: binary content", work.path);
+        work.stats.skipped = Some("binary content".to_string());
+    }
+
+    /// Takes the resolved tickets of a ticket export as its items; false when the file
+    /// doesn't have the columns `[tickets]` maps, to read it as text instead.
+    fn extract_tickets(&self, work: &mut FileWork) -> Result<bool> {
+        let file = work.relative_path.display().to_string();
+        let items = match tickets::items(&work.content, &file, &self.config.tickets) {
+            Ok(items) => items,
+            Err(e) => {
+                warn!("Reading {:?} as plain text: {}", work.path, e);
+                return Ok(false);
+            }
+        };
+        info!("Read {} resolved tickets from {:?}", items.len(), work.path);
+        self.take_file_items(work, items)?;
+        Ok(true)
+    }
+
+    /// Takes the questions of a Stack Exchange dump with their accepted answers as its
+    /// items, reading it a line at a time since dumps run to gigabytes.
+    fn extract_stack_exchange(&self, work: &mut FileWork) -> Result<()> {
+        let file = work.relative_path.display().to_string();
+        let reader = BufReader::new(compression::open(&work.path)?);
+        let items = stack_exchange::items(reader, &file, &self.config.stack_exchange)?;
+        info!("Read {} answered questions from {:?}", items.len(), work.path);
+        work.modified = freshness::modified(&work.path).map(freshness::date);
+        self.take_file_items(work, items)
+    }
+
+    /// Takes the solved topics pulled from a Discourse forum as its items.
+    fn extract_discourse(&self, work: &mut FileWork) -> Result<()> {
+        let file = work.relative_path.display().to_string();
+        let items = discourse::items(&compression::read_to_string(&work.path)?, &file, &self.config.discourse)?;
+        info!("Read {} solved topics from {:?}", items.len(), work.path);
+        work.modified = freshness::modified(&work.path).map(freshness::date);
+        self.take_file_items(work, items)
+    }
+
+    /// Makes `items`, converted from records of the file rather than its text, its items,
+    /// with the settings of its path and its name as title.
+    fn take_file_items(&self, work: &mut FileWork, items: Vec<ProcessedItem>) -> Result<()> {
+        let settings = self.config.settings_for(&work.relative_path, "")?;
+        work.span.record("profile", settings.profile.as_str());
+        work.stats.profile = Some(settings.profile.clone());
+        work.settings = Some(settings);
+        let name = compression::inner_path(&work.path);
+        work.title = name.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_string();
+        Self::take_extracted(work, items);
+        Ok(())
+    }
+
+    /// Makes `items`, taken from the file as they are, its items, with a section each
+    /// holding the question and answer so later stages have their source.
+    fn take_extracted(work: &mut FileWork, mut items: Vec<ProcessedItem>) {
+        for (index, item) in items.iter_mut().enumerate() {
+            let section = Section {
+                index,
+                text: format!("{}\n\n{}", item.question, item.answer),
+                target: 0,
+                release: None,
+            };
+            if let Some(source) = &mut item.source {
+                source.chunk = Some(section.id());
+                source.modified = work.modified;
+            }
+            work.sections.push(section);
+        }
+        work.stats.sections = work.sections.len();
+        work.items = items;
+        work.stats.extracted = true;
+    }
+
+    fn convert_json_to_jsonl(&self, json_path: &Path, jsonl_path: &Path) -> Result<Vec<ProcessedItem>> {
+        debug!("Converting {:?} to JSONL format at {:?}", json_path, jsonl_path);
+        let content = fs::read_to_string(json_path)?;
+        let items: Vec<ProcessedItem> = serde_json::from_str(&content)?;
+
+        let mut output = String::new();
+        for item in &items {
+            if let Ok(json_line) = serde_json::to_string(item) {
+                output.push_str(&json_line);
+                output.push('\n');
+            }
+        }
+        fs::write(jsonl_path, output)?;
+        Ok(items)
+    }
+
+    fn check_existing_qa(&self, work: &FileWork) -> Result<Option<ExistingQa>> {
+        let file_path = work.path.as_path();
+        let settings = work.settings()?;
+        let word_count = work.word_count();
+        let (_, _, min_acceptable) = OllamaProcessor::calculate_question_targets(word_count, &settings.density);
+
+        // First check for JSONL file
+        let jsonl_path = qa_path(file_path, "jsonl");
+
+        if jsonl_path.exists() {
+            debug!("Found existing JSONL file: {:?}", jsonl_path);
+            if let Ok(content) = fs::read_to_string(&jsonl_path) {
+                let mut items = Vec::new();
+                for line in content.lines() {
+                    if let Ok(item) = serde_json::from_str::<ProcessedItem>(line) {
+                        items.push(item);
+                    }
+                }
+                if !items.is_empty() {
+                    if items.len() >= min_acceptable {
+                        info!("Found existing JSONL file with {} questions (minimum acceptable: {}), skipping...",
+                            items.len(), min_acceptable);
+                        return Ok(Some(ExistingQa::Reuse(items)));
+                    } else {
+                        info!("Found existing JSONL file but only has {} questions (minimum needed: {}), topping up...",
+                            items.len(), min_acceptable);
+                        return Ok(Some(ExistingQa::TopUp(items)));
+                    }
+                } else {
+                    debug!("No valid items found in existing JSONL file");
+                }
+            }
+        } else {
+            // Check for JSON file if JSONL doesn't exist
+            let json_path = qa_path(file_path, "json");
+            if json_path.exists() {
+                debug!("Found existing JSON file: {:?}", json_path);
+                if let Ok(content) = fs::read_to_string(&json_path) {
+                    if let Ok(items) = serde_json::from_str::<Vec<ProcessedItem>>(&content) {
+                        if items.len() >= min_acceptable {
+                            info!("Found existing JSON file with {} questions (minimum acceptable: {}), converting to JSONL...",
+                                items.len(), min_acceptable);
+                            // Convert to JSONL format
+                            match self.convert_json_to_jsonl(&json_path, &jsonl_path) {
+                                Ok(items) => {
+                                    debug!("Successfully converted to JSONL format");
+                                    return Ok(Some(ExistingQa::Reuse(items)));
+                                }
+                                Err(e) => {
+                                    warn!("Failed to convert to JSONL format: {}", e);
+                                }
+                            }
+                        } else {
+                            info!("Found existing JSON file but only has {} questions (minimum needed: {}), topping up...",
+                                items.len(), min_acceptable);
+                            return Ok(Some(ExistingQa::TopUp(items)));
+                        }
+                    }
+                }
+            } else {
+                debug!("No existing QA file found");
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Stage for ParseStage {
+    fn name(&self) -> &'static str {
+        "parse"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        info!("Processing file: {:?}", work.path);
+        if stack_exchange::is_dump(&work.path) {
+            return self.extract_stack_exchange(work);
+        }
+        if discourse::is_export(&work.path) {
+            return self.extract_discourse(work);
+        }
+        let streamed = match self.read(work, true).await? {
+            Read::Skipped => return Ok(()),
+            Read::Whole => None,
+            Read::Streamed(encoding) => Some(encoding),
+        };
+
+        if frontmatter::overrides(frontmatter::parse(&work.content).0.as_ref())?.skip {
+            info!("Skipping {:?}: llmds.skip is set in its frontmatter", work.path);
+            work.stats.skipped = Some("llmds.skip".to_string());
+            return Ok(());
+        }
+
+        work.modified = freshness::modified(&work.path).map(freshness::date);
+        if let Some(since) = self.config.sampling.since.filter(|since| work.modified.is_some_and(|modified| modified < *since)) {
+            info!("Skipping {:?}: last changed before {}", work.path, since);
+            work.stats.skipped = Some(format!("last changed before {}", since));
+            return Ok(());
+        }
+
+        if tickets::is_ticket_export(&work.path) && streamed.is_none() && self.extract_tickets(work)? {
+            return Ok(());
+        }
+
+        let mut settings = self.prepare(work, streamed.is_some())?;
+        work.span.record("profile", settings.profile.as_str());
+        work.stats.profile = Some(settings.profile.clone());
+        if let Some(breadcrumbs) = self.docs_site(work).as_deref().and_then(|site| site.breadcrumbs(&work.path)) {
+            work.breadcrumbs = breadcrumbs.to_vec();
+        }
+        let name = compression::inner_path(&work.path);
+        work.title = prompt::doc_title(&work.content, name.file_stem().and_then(|s| s.to_str()).unwrap_or(""));
+        if let Some(encoding) = streamed {
+            if encoding != encoding_rs::UTF_8 {
+                work.stats.encoding = Some(encoding.name().to_string());
+            }
+            let stream = StreamedFile::scan(&work.path, encoding, &settings.chunking)?;
+            debug!("{:?} has {} words in {} sections", work.path, stream.words, stream.sections);
+            // A fixed per-file count was spread over the head's words only
+            settings.density = settings.density.for_file(stream.words);
+            work.stream = Some(stream);
+        }
+        work.settings = Some(settings);
+
+        if self.config.faq.extract && work.stream.is_none() {
+            let items = faq::items(&work.content, &work.relative_path.display().to_string(), &self.config.faq);
+            if !items.is_empty() {
+                info!("Extracted {} question and answer pairs from {:?}", items.len(), work.path);
+                Self::take_extracted(work, items);
+                return Ok(());
+            }
+        }
+
+        // Check if we already have enough questions
+        match self.check_existing_qa(work)? {
+            Some(ExistingQa::Reuse(items)) => {
+                work.items = items;
+                work.stats.reused_existing = true;
+            }
+            Some(ExistingQa::TopUp(items)) => work.existing = items,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Splits the file into sections and spreads the file's question target over them by
+/// word count.
+pub struct ChunkStage {
+    config: Arc<Config>,
+    budget: Option<Arc<ItemBudget>>,
+}
+
+impl ChunkStage {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config, budget: None }
+    }
+
+    /// Scales the file's question target to the run's share of `budget`.
+    pub fn with_budget(mut self, budget: Option<Arc<ItemBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// A Keep a Changelog file as a section per version, which gets its share of the
+    /// file's questions, and a section per entry asking for one question each.
+    fn changelog_sections(versions: &[ChangelogVersion], file_questions: usize, file_words: usize) -> Vec<Section> {
+        let sections = changelog::sections(versions);
+        debug!("Parsed {} versions into {} sections", versions.len(), sections.len());
+        sections
+            .into_iter()
+            .enumerate()
+            .map(|(index, (text, release))| match release.change_type {
+                Some(_) => Section {
+                    index,
+                    text,
+                    target: 1,
+                    release: Some(release),
+                },
+                None => Section {
+                    release: Some(release),
+                    ..Section::new(index, text, file_questions, file_words)
+                },
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Stage for ChunkStage {
+    fn name(&self) -> &'static str {
+        "chunk"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        let settings = work.settings().map_err(|e| Error::Chunking(e.to_string()))?;
+        // Extracted items are a section each already
+        if work.stats.extracted {
+            return Ok(());
+        }
+
+        // Count total words to determine total questions needed
+        let total_words = work.word_count();
+        let (_, total_questions_needed, _) = OllamaProcessor::calculate_question_targets(total_words, &settings.density);
+        // A short QA file from an earlier run only needs the missing questions
+        let total_questions_needed = total_questions_needed.saturating_sub(work.existing.len());
+        let total_questions_needed = match &self.budget {
+            Some(budget) => budget.scaled(total_questions_needed),
+            None => total_questions_needed,
+        };
+
+        // Streamed files are split while generating; only their section count is known
+        if let Some(stream) = &work.stream {
+            work.stats.sections = match self.config.sampling.limit_sections.filter(|limit| *limit < stream.sections) {
+                Some(limit) => {
+                    info!("Limiting {:?} to {} of {} sections", work.path, limit, stream.sections);
+                    limit
+                }
+                None => stream.sections,
+            };
+            return Ok(());
+        }
+
+        if settings.is_release_notes() {
+            if let Some(versions) = changelog::parse_keep_a_changelog(&work.content) {
+                work.sections = Self::changelog_sections(&versions, total_questions_needed, total_words);
+                if let Some(limit) = self.config.sampling.limit_sections.filter(|limit| *limit < work.sections.len()) {
+                    info!("Limiting {:?} to {} of {} sections", work.path, limit, work.sections.len());
+                    work.sections.truncate(limit);
+                }
+                work.stats.sections = work.sections.len();
+                return Ok(());
+            }
+        }
+
+        let mut sections = OllamaProcessor::split_into_sections(&work.content, &settings.chunking);
+        if let Some(limit) = self.config.sampling.limit_sections.filter(|limit| *limit < sections.len()) {
+            info!("Limiting {:?} to {} of {} sections", work.path, limit, sections.len());
+            sections.truncate(limit);
+        }
+
+        let mut releases = settings.release_tracker(&work.path);
+        let mut thin = 0;
+        work.sections = sections
+            .into_iter()
+            .enumerate()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(index, text)| Section::new(index, text, total_questions_needed, total_words).with_release(&mut releases))
+            .filter(|section| {
+                let is_thin = OllamaProcessor::is_thin(&section.text, &settings.chunking);
+                if is_thin {
+                    debug!("Section {} has too little to ask about", section.index + 1);
+                    thin += 1;
+                }
+                !is_thin
+            })
+            .collect();
+        if thin > 0 {
+            info!("Skipping {} sections of {:?} with too little to ask about", thin, work.path);
+            work.stats.thin_sections = thin;
+        }
+        work.stats.sections = work.sections.len();
+        Ok(())
+    }
+}
+
+/// Sections of a streamed file handed to distributed workers at the same time.
+const STREAMED_IN_FLIGHT: usize = 32;
+
+/// Generates items for every section, locally or through distributed workers.
+pub struct GenerateStage {
+    processor: Arc<OllamaProcessor>,
+    progress: Arc<Progress>,
+    budget: Option<Arc<ItemBudget>>,
+}
+
+impl GenerateStage {
+    pub fn new(processor: Arc<OllamaProcessor>, progress: Arc<Progress>) -> Self {
+        Self { processor, progress, budget: None }
+    }
+
+    /// Skips the remaining sections once the run's `budget` is used up, and scales the
+    /// question targets of streamed files to the run's share of it.
+    pub fn with_budget(mut self, budget: Option<Arc<ItemBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+}
+
+#[async_trait]
+impl Stage for GenerateStage {
+    fn name(&self) -> &'static str {
+        "generate"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        if work.stats.extracted {
+            return Ok(());
+        }
+        self.progress.start_file(&work.path.display().to_string());
+        self.progress.set_sections(work.stats.sections);
+
+        // Distributed workers get all sections of the file at once (up to a limit for
+        // streamed files, which would otherwise be read into memory); locally they are
+        // generated one after the other
+        let in_flight = match (self.processor.is_distributed(), &work.stream) {
+            (false, _) => 1,
+            (true, None) => work.sections.len().max(1),
+            (true, Some(_)) => STREAMED_IN_FLIGHT,
+        };
+        let sections: Box<dyn Iterator<Item = Result<Section>> + Send + '_> = match &work.stream {
+            None => Box::new(work.sections.iter().cloned().map(Ok)),
+            Some(stream) => {
+                let settings = work.settings()?;
+                let (_, questions, _) = OllamaProcessor::calculate_question_targets(stream.words, &settings.density);
+                let questions = questions.saturating_sub(work.existing.len());
+                let questions = match &self.budget {
+                    Some(budget) => budget.scaled(questions),
+                    None => questions,
+                };
+                let mut releases = settings.release_tracker(&work.path);
+                Box::new(
+                    stream
+                        .sections(&work.path, &settings.chunking)?
+                        .take(work.stats.sections)
+                        .enumerate()
+                        .map(move |(index, text)| Ok(Section::new(index, text?, questions, stream.words).with_release(&mut releases))),
+                )
+            }
+        };
+        let work_ref = &*work;
+        // Sections of streamed files are only checked for content here
+        let thin = &AtomicUsize::new(0);
+        let results: Vec<Result<(usize, String, Result<Vec<ProcessedItem>>)>> = stream::iter(sections)
+            .map(|section| async move {
+                let section = section?;
+                if self.budget.as_ref().is_some_and(|budget| budget.is_exhausted()) {
+                    debug!("Skipping section {}: the item budget is used up", section.index + 1);
+                    return Ok((section.index, section.id(), Ok(Vec::new())));
+                }
+                if work_ref.stream.is_some() && OllamaProcessor::is_thin(&section.text, &work_ref.settings()?.chunking) {
+                    debug!("Section {} has too little to ask about", section.index + 1);
+                    thin.fetch_add(1, Ordering::Relaxed);
+                    return Ok((section.index, section.id(), Ok(Vec::new())));
+                }
+                Ok((section.index, section.id(), self.processor.generate_section(work_ref, &section).await))
+            })
+            .buffered(in_flight)
+            .inspect(|_| self.progress.finish_section())
+            .collect()
+            .await;
+
+        if work.stream.is_some() {
+            work.stats.thin_sections = thin.load(Ordering::Relaxed);
+        }
+
+        let file = work.relative_path.display().to_string();
+        // Sections and the passes of the recursive fallback ask about the same content,
+        // so questions repeat within a file (and repeat the existing ones when topping up)
+        let mut seen: HashSet<String> = work.existing.iter().map(dataset::question_key).collect();
+        let mut duplicates = 0;
+        let mut last_error = None;
+        for result in results {
+            let (index, chunk, result) = result?;
+            match result {
+                Ok(items) => {
+                    for item in items {
+                        if !seen.insert(dataset::question_key(&item)) {
+                            duplicates += 1;
+                            continue;
+                        }
+                        work.items.push(ProcessedItem {
+                            source: Some(ItemSource {
+                                file: file.clone(),
+                                section: index,
+                                chunk: Some(chunk.clone()),
+                                image: None,
+                                modified: work.modified,
+                            }),
+                            ..item
+                        });
+                    }
+                }
+                Err(e) => {
+                    self.progress.add_failure();
+                    work.stats.failed_sections += 1;
+                    error!("Error processing section: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if !work.tags.is_empty() {
+            for item in &mut work.items {
+                item.extra.insert(vault::TAGS_FIELD.to_string(), work.tags.clone().into());
+            }
+        }
+        if work.ocr {
+            for item in &mut work.items {
+                item.extra.insert(ocr::OCR_FIELD.to_string(), true.into());
+            }
+        }
+        if duplicates > 0 {
+            info!("Removed {} duplicate questions from {:?}", duplicates, work.path);
+        }
+        if !work.existing.is_empty() {
+            info!("Topping up {} existing questions of {:?} with {} new ones",
+                work.existing.len(), work.path, work.items.len());
+        }
+
+        work.usage = self.processor.usage().file_usage(&work.path);
+        if work.usage.requests > 0 {
+            debug!("Token usage for {:?}: {} prompt + {} completion tokens over {} requests",
+                work.path, work.usage.prompt_tokens, work.usage.completion_tokens, work.usage.requests);
+        }
+
+        // A file none of whose sections could be generated failed, for the same reason
+        match last_error {
+            Some(e) if work.stats.failed_sections == work.stats.sections => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Drops items rejected by the file's filters and the steering settings, and warns about
+/// keywords the remaining items don't cover. Filter plugins see the items the built-in
+/// filters keep. Emits `item_generated` for every kept item and `item_rejected` for every
+/// dropped one.
+pub struct FilterStage {
+    config: Arc<Config>,
+    progress: Arc<Progress>,
+    metrics: Arc<Metrics>,
+    hooks: Arc<Hooks>,
+    plugins: Arc<Plugins>,
+    budget: Option<Arc<ItemBudget>>,
+}
+
+impl FilterStage {
+    pub fn new(config: Arc<Config>, progress: Arc<Progress>, metrics: Arc<Metrics>, hooks: Arc<Hooks>, plugins: Arc<Plugins>) -> Self {
+        Self { config, progress, metrics, hooks, plugins, budget: None }
+    }
+
+    /// Keeps only the items that fit in the run's `budget`, counting the file's existing
+    /// items first.
+    pub fn with_budget(mut self, budget: Option<Arc<ItemBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+}
+
+#[async_trait]
+impl Stage for FilterStage {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        let settings = work.settings()?;
+        // Items reused from an earlier run may predate citations, and extracted ones have none
+        let filter = ItemFilter::new(&settings.filters, &self.config.steering)?
+            .with_required_citation(self.config.output.citations && !work.stats.reused_existing && !work.stats.extracted);
+        let terms = TermNormalizer::new(&self.config.terms, settings.product_name.as_deref())?;
+        if !terms.is_empty() {
+            let normalized = work.items.iter_mut().map(|item| terms.apply(item)).filter(|changed| *changed).count();
+            if normalized > 0 {
+                debug!("Normalized term spellings in {} of {} questions", normalized, work.items.len());
+            }
+        }
+        let generated = work.items.len();
+        let mut rejected = Vec::new();
+        {
+            let mut plugin_filters = self.plugins.filters()?;
+            for item in std::mem::take(&mut work.items) {
+                if filter.accepts(&item) && plugin_filters.accepts(&item)? {
+                    work.items.push(item);
+                } else {
+                    rejected.push(item);
+                }
+            }
+        }
+        if !rejected.is_empty() {
+            debug!("Filtered out {} of {} questions", rejected.len(), generated);
+            work.stats.rejected_items += rejected.len();
+        }
+        if let Some(budget) = &self.budget {
+            let kept = budget.take(work.existing.len() + work.items.len()).saturating_sub(work.existing.len());
+            if kept < work.items.len() {
+                info!("Item budget used up: keeping {} of {} new items of {:?}", kept, work.items.len(), work.path);
+                work.items.truncate(kept);
+            }
+        }
+        for item in &rejected {
+            self.hooks.emit(Event::ItemRejected { file: &work.relative_path, item }).await;
+        }
+        for item in &work.items {
+            self.hooks.emit(Event::ItemGenerated { file: &work.relative_path, item }).await;
+        }
+        self.progress.add_items(work.items.len());
+        self.metrics.items_generated.inc_by(work.items.len() as u64);
+
+        // Items kept from the earlier run were filtered then
+        if !work.existing.is_empty() {
+            let new_items = std::mem::take(&mut work.items);
+            work.items = std::mem::take(&mut work.existing);
+            work.items.extend(new_items);
+        }
+
+        let uncovered = filter.uncovered_keywords(&work.content, &work.items);
+        if !uncovered.is_empty() {
+            warn!("Keywords present in {:?} but not covered by any item: {}", work.path, uncovered.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// Gives the file's items their ids and saves them next to it as `<name>_qa.jsonl`.
+pub struct WriteStage;
+
+#[async_trait]
+impl Stage for WriteStage {
+    fn name(&self) -> &'static str {
+        "write"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        if work.items.is_empty() {
+            return Ok(());
+        }
+        work.items.iter_mut().for_each(dataset::identify);
+        let path = qa_path(&work.path, "jsonl");
+        debug!("Saving {} questions to {:?}", work.items.len(), path);
+
+        let mut file = fs::File::create(&path)?;
+        for item in &work.items {
+            writeln!(file, "{}", serde_json::to_string(item)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{Value, json};
+    use crate::config::{ChunkingConfig, FilterConfig, HooksConfig};
+
+    const GUIDE: &str = "# Guide\n\n## Install\n\nDownload the installer and run it to install the tool on your machine.\n\n\
+        ## Configure\n\nEdit the config file to set the endpoint and the model the tool uses.\n\n\
+        ## See also\n\n- [Home](index.md)\n";
+
+    /// An Ollama stand-in answering each section with a question about the part of the
+    /// guide it covers, and one every section gets. Returns its endpoint.
+    async fn ollama() -> String {
+        let chat = |Json(request): Json<Value>| async move {
+            let section = request["messages"][1]["content"].as_str().unwrap_or("");
+            let mut questions = vec![json!({ "question": "What does the guide cover?", "answer": "Installing and configuring the tool." })];
+            if section.contains("## Install") {
+                questions.push(json!({ "question": "How do I install the tool?", "answer": "Download the installer and run it." }));
+            }
+            if section.contains("## Configure") {
+                questions.push(json!({ "question": "How do I set the endpoint?", "answer": "Edit the config file." }));
+            }
+            let content = json!({ "questions": questions }).to_string();
+            Json(json!({ "message": { "role": "assistant", "content": content }, "prompt_eval_count": 10, "eval_count": 5 }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, Router::new().route("/api/chat", post(chat))).await });
+        endpoint
+    }
+
+    /// `config` with a new output directory holding `guide.md`.
+    fn output_dir(config: Config) -> Arc<Config> {
+        let dir = std::env::temp_dir().join(format!("llmds-pipeline-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("guide.md"), GUIDE).unwrap();
+        Arc::new(Config {
+            output_dir: dir.display().to_string(),
+            ..config
+        })
+    }
+
+    /// `guide.md` after the parse stage, and the chunk stage with `chunk`.
+    async fn guide(config: &Arc<Config>, chunk: bool) -> FileWork {
+        let output_dir = Path::new(&config.output_dir);
+        let mut work = FileWork::new(&output_dir.join("guide.md"), output_dir);
+        let mut pipeline = Pipeline::new().with_stage(ParseStage::new(config.clone(), Arc::new(Plugins::load(&config.plugins).unwrap())).unwrap());
+        if chunk {
+            pipeline = pipeline.with_stage(ChunkStage::new(config.clone()));
+        }
+        work = pipeline.process(work).await;
+        assert!(work.error.is_none());
+        work
+    }
+
+    fn item(question: &str, answer: &str) -> ProcessedItem {
+        serde_json::from_value(json!({ "question": question, "answer": answer })).unwrap()
+    }
+
+    fn questions(work: &FileWork) -> Vec<&str> {
+        work.items.iter().map(|item| item.question.as_str()).collect()
+    }
+
+    /// A stage that always fails.
+    struct Failing;
+
+    #[async_trait]
+    impl Stage for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn process(&self, _: &mut FileWork) -> Result<()> {
+            Err(anyhow!("no luck"))
+        }
+    }
+
+    #[test]
+    fn chunk_ids_ignore_reflowing() {
+        assert_eq!(chunk_id("Run the\n  installer."), chunk_id("Run the installer.\n"));
+        assert_ne!(chunk_id("Run the installer."), chunk_id("Run the uninstaller."));
+        assert_eq!(chunk_id("").len(), 16);
+    }
+
+    #[tokio::test]
+    async fn parse_reads_the_file_and_its_settings() {
+        let config = output_dir(Config::default());
+        let work = guide(&config, false).await;
+        assert_eq!(work.content, GUIDE);
+        assert_eq!(work.title, "Guide");
+        assert_eq!(work.relative_path, Path::new("guide.md"));
+        assert!(work.settings.is_some());
+        assert!(!work.is_finished());
+        fs::remove_dir_all(&config.output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn chunk_splits_by_headings_and_spreads_the_target() {
+        let config = output_dir(Config::default());
+        let work = guide(&config, true).await;
+        // "See also" has nothing but a link
+        assert_eq!(work.stats.thin_sections, 1);
+        assert_eq!(work.stats.sections, 2);
+        let indexes: Vec<usize> = work.sections.iter().map(|section| section.index).collect();
+        assert_eq!(indexes, [0, 1]);
+        assert!(work.sections[0].text.starts_with("# Guide\n## Install"));
+        assert!(work.sections.iter().all(|section| section.target >= 1));
+        fs::remove_dir_all(&config.output_dir).unwrap();
+
+        let config = output_dir(Config {
+            chunking: ChunkingConfig { heading_level: 1, ..ChunkingConfig::default() },
+            ..Config::default()
+        });
+        let work = guide(&config, true).await;
+        assert_eq!(work.sections.len(), 1);
+        assert_eq!(work.sections[0].text, GUIDE);
+        fs::remove_dir_all(&config.output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn generate_tags_items_with_their_section() {
+        let config = output_dir(Config {
+            endpoint: ollama().await,
+            ..Config::default()
+        });
+        let mut work = guide(&config, true).await;
+        let processor = Arc::new(OllamaProcessor::new(config.clone(), Arc::new(Metrics::new())));
+        GenerateStage::new(processor, Arc::new(Progress::new(true))).process(&mut work).await.unwrap();
+
+        // The question both sections get is kept once
+        assert_eq!(questions(&work), ["What does the guide cover?", "How do I install the tool?", "How do I set the endpoint?"]);
+        let sections: Vec<usize> = work.items.iter().map(|item| item.source.as_ref().unwrap().section).collect();
+        assert_eq!(sections, [0, 0, 1]);
+        assert!(work.items.iter().all(|item| item.source.as_ref().unwrap().file == "guide.md"));
+        assert!(work.usage.requests > 0);
+        fs::remove_dir_all(&config.output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn filter_drops_rejected_items_and_keeps_existing_ones() {
+        let config = output_dir(Config {
+            filters: FilterConfig { min_answer_words: 3, ..FilterConfig::default() },
+            ..Config::default()
+        });
+        let mut work = guide(&config, false).await;
+        work.existing = vec![item("What is it?", "A tool.")];
+        work.items = vec![item("How do I install it?", "Run the installer."), item("Is it free?", "Yes.")];
+        let hooks = Arc::new(Hooks::new(&HooksConfig::default()));
+        let stage = FilterStage::new(config.clone(), Arc::new(Progress::new(true)), Arc::new(Metrics::new()), hooks, Arc::new(Plugins::load(&config.plugins).unwrap()));
+        stage.process(&mut work).await.unwrap();
+
+        assert_eq!(work.stats.rejected_items, 1);
+        assert_eq!(questions(&work), ["What is it?", "How do I install it?"]);
+        assert!(work.existing.is_empty());
+        fs::remove_dir_all(&config.output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_saves_identified_items_next_to_the_file() {
+        let config = output_dir(Config::default());
+        let qa = Path::new(&config.output_dir).join("guide_qa.jsonl");
+        let mut work = guide(&config, false).await;
+        WriteStage.process(&mut work).await.unwrap();
+        assert!(!qa.exists());
+
+        work.items = vec![item("How do I install it?", "Run the installer.")];
+        WriteStage.process(&mut work).await.unwrap();
+        let items = dataset::read_items(&qa).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, work.items[0].id);
+        assert!(items[0].id.is_some());
+        fs::remove_dir_all(&config.output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_files_skip_the_remaining_stages() {
+        let config = output_dir(Config::default());
+        let output_dir = Path::new(&config.output_dir);
+        let pipeline = Pipeline::new()
+            .with_stage(ParseStage::new(config.clone(), Arc::new(Plugins::load(&config.plugins).unwrap())).unwrap())
+            .with_stage(Failing)
+            .with_stage(ChunkStage::new(config.clone()));
+        let (sender, receiver) = mpsc::channel(1);
+        let mut output = pipeline.run(receiver);
+        sender.send(FileWork::new(&output_dir.join("guide.md"), output_dir)).await.unwrap();
+        drop(sender);
+
+        let work = output.recv().await.unwrap();
+        assert_eq!(work.error, Some(Error::Other("failing stage: no luck".to_string())));
+        assert!(work.sections.is_empty());
+        assert!(!work.content.is_empty());
+        assert!(output.recv().await.is_none());
+        fs::remove_dir_all(&config.output_dir).unwrap();
+    }
+}
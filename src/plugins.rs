@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use tracing::{debug, info};
+use wasmtime::{Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+use crate::compression;
+use crate::config::{PluginConfig, PluginKind};
+use crate::processor::ProcessedItem;
+
+/// Instructions a plugin may execute per call, so a runaway module fails the item or
+/// file instead of hanging the run.
+const FUEL_PER_CALL: u64 = 1_000_000_000;
+
+/// Linear memory a plugin instance may grow to, so a leaking or hostile module fails
+/// the file instead of exhausting the host.
+const MAX_MEMORY: usize = 256 * 1024 * 1024;
+
+/// A compiled plugin module. Plugins are core WebAssembly modules (`.wasm`, or `.wat`
+/// text) without imports that export:
+///
+/// - `memory`, and `alloc(len: i32) -> i32` returning space for the input;
+/// - filters: `filter(ptr: i32, len: i32) -> i32` receiving an item as JSON and
+///   returning non-zero to keep it;
+/// - parsers: `parse(ptr: i32, len: i32) -> i64` receiving the file's bytes and
+///   returning `ptr << 32 | len` of the UTF-8 text to generate from.
+struct Plugin {
+    path: PathBuf,
+    module: Module,
+    extensions: Vec<String>,
+}
+
+impl Plugin {
+    /// Instantiates the module in a fresh store with a memory limit.
+    fn instantiate(&self, engine: &Engine) -> Result<PluginInstance<'_>> {
+        let mut store = Store::new(engine, StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build());
+        store.limiter(|limits| limits);
+        let instance: Instance = Linker::new(engine).instantiate(&mut store, &self.module)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("{:?} does not export memory", self.path))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        Ok(PluginInstance { plugin: self, store, instance, memory, alloc })
+    }
+}
+
+/// A plugin module instantiated once and called any number of times, e.g. for every
+/// item of a file.
+struct PluginInstance<'a> {
+    plugin: &'a Plugin,
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+impl PluginInstance<'_> {
+    /// Copies `input` into the instance's memory and calls `export` with the pointer and
+    /// length, with a fresh fuel allowance.
+    fn call<R: wasmtime::WasmResults>(&mut self, export: &str, input: &[u8]) -> Result<R> {
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        let len = i32::try_from(input.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, input)?;
+        let func = self.instance.get_typed_func::<(i32, i32), R>(&mut self.store, export)?;
+        func.call(&mut self.store, (ptr, len))
+    }
+}
+
+/// The filter plugins instantiated for one file.
+pub struct FilterInstances<'a> {
+    instances: Vec<PluginInstance<'a>>,
+}
+
+impl FilterInstances<'_> {
+    /// Whether every filter plugin keeps `item`.
+    pub fn accepts(&mut self, item: &ProcessedItem) -> Result<bool> {
+        if self.instances.is_empty() {
+            return Ok(true);
+        }
+        let input = serde_json::to_vec(item)?;
+        for instance in &mut self.instances {
+            let keep = instance
+                .call::<i32>("filter", &input)
+                .map_err(|e| anyhow!("Filter plugin {:?} failed: {}", instance.plugin.path, e))?;
+            if keep == 0 {
+                debug!("Filter plugin {:?} rejected {:?}", instance.plugin.path, item.question);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The filter and parser plugins configured under `[[plugins]]`.
+pub struct Plugins {
+    engine: Engine,
+    filters: Vec<Plugin>,
+    parsers: Vec<Plugin>,
+}
+
+impl Plugins {
+    /// Compiles every configured module; fails on the first one that can't be loaded.
+    pub fn load(configs: &[PluginConfig]) -> Result<Self> {
+        let mut wasm_config = wasmtime::Config::new();
+        wasm_config.consume_fuel(true);
+        let engine = Engine::new(&wasm_config)?;
+        let mut plugins = Self {
+            engine,
+            filters: Vec::new(),
+            parsers: Vec::new(),
+        };
+        for config in configs {
+            let module = Module::from_file(&plugins.engine, &config.path)
+                .map_err(|e| anyhow!("Failed to load plugin {:?}: {}", config.path, e))?;
+            if module.imports().len() > 0 {
+                return Err(anyhow!("Plugin {:?} must not import anything", config.path));
+            }
+            info!("Loaded {:?} plugin {:?}", config.kind, config.path);
+            let plugin = Plugin {
+                path: config.path.clone(),
+                module,
+                extensions: config.extensions.iter().map(|ext| ext.to_lowercase()).collect(),
+            };
+            match config.kind {
+                PluginKind::Filter => plugins.filters.push(plugin),
+                PluginKind::Parser => plugins.parsers.push(plugin),
+            }
+        }
+        Ok(plugins)
+    }
+
+    /// Instantiates every filter plugin, to be called for the items of one file.
+    pub fn filters(&self) -> Result<FilterInstances<'_>> {
+        let instances = self
+            .filters
+            .iter()
+            .map(|plugin| {
+                plugin
+                    .instantiate(&self.engine)
+                    .map_err(|e| anyhow!("Filter plugin {:?} failed: {}", plugin.path, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(FilterInstances { instances })
+    }
+
+    /// Extensions of the files parser plugins convert, lowercased.
+    pub fn parser_extensions(configs: &[PluginConfig]) -> impl Iterator<Item = String> + '_ {
+        configs
+            .iter()
+            .filter(|config| config.kind == PluginKind::Parser)
+            .flat_map(|config| config.extensions.iter().map(|ext| ext.to_lowercase()))
+    }
+
+    fn parser_for(&self, path: &Path) -> Option<&Plugin> {
+        let ext = compression::inner_path(path).extension()?.to_str()?.to_lowercase();
+        self.parsers.iter().find(|p| p.extensions.contains(&ext))
+    }
+
+    /// Text of the file at `path` converted by the parser for its extension, or `None`
+    /// when no parser handles it.
+    pub fn parse(&self, path: &Path) -> Result<Option<String>> {
+        let Some(plugin) = self.parser_for(path) else {
+            return Ok(None);
+        };
+        let input = compression::read(path)?;
+        let mut instance = plugin
+            .instantiate(&self.engine)
+            .map_err(|e| anyhow!("Parser plugin {:?} failed: {}", plugin.path, e))?;
+        let packed = instance
+            .call::<i64>("parse", &input)
+            .map_err(|e| anyhow!("Parser plugin {:?} failed: {}", plugin.path, e))?;
+        let (ptr, len) = ((packed as u64 >> 32) as usize, (packed as u64 & 0xffff_ffff) as usize);
+        let mut output = vec![0; len];
+        instance.memory.read(&instance.store, ptr, &mut output)?;
+        debug!("Parser plugin {:?} turned {:?} into {} bytes of text", plugin.path, path, len);
+        let text = String::from_utf8(output).map_err(|_| anyhow!("Parser plugin {:?} returned invalid UTF-8", plugin.path))?;
+        Ok(Some(text))
+    }
+}
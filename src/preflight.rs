@@ -0,0 +1,191 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, warn};
+use crate::config::{Config, EmbeddingApi};
+use crate::error::Error;
+
+/// First Ollama release that accepts a JSON schema as `format`.
+const MIN_STRUCTURED_OUTPUT_VERSION: (u64, u64, u64) = (0, 5, 0);
+
+/// How long to wait for the endpoint before calling it unreachable.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the endpoint reported during the checks.
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    pub endpoint: String,
+    pub version: String,
+    pub models: Vec<String>,
+}
+
+/// Checks that the Ollama endpoint is reachable and new enough for structured output.
+pub async fn check_server(endpoint: &str) -> Result<String, Error> {
+    #[derive(Deserialize)]
+    struct VersionResponse {
+        version: String,
+    }
+
+    let client = Client::builder().timeout(TIMEOUT).build()?;
+    let endpoint = endpoint.trim_end_matches('/');
+    let response = client.get(format!("{}/api/version", endpoint)).send().await.map_err(|e| {
+        Error::Api(format!(
+            "Cannot reach Ollama at {}: {}. Start it with `ollama serve` or point --ollama-endpoint at a running server",
+            endpoint, e
+        ))
+    })?;
+    if !response.status().is_success() {
+        return Err(Error::Api(format!(
+            "{} answered /api/version with {}; is it an Ollama server?",
+            endpoint,
+            response.status()
+        )));
+    }
+    let version = response
+        .json::<VersionResponse>()
+        .await
+        .map_err(|e| Error::Api(format!("{} returned an invalid /api/version response: {}", endpoint, e)))?
+        .version;
+
+    match parse_version(&version) {
+        // Builds from source report 0.0.0; assume they're recent
+        Some((0, 0, 0)) | None => warn!("Unknown Ollama version {:?}; assuming it supports structured output", version),
+        Some(parsed) if parsed < MIN_STRUCTURED_OUTPUT_VERSION => {
+            let (major, minor, patch) = MIN_STRUCTURED_OUTPUT_VERSION;
+            return Err(Error::Api(format!(
+                "Ollama {} at {} does not support structured output; upgrade to {}.{}.{} or later",
+                version, endpoint, major, minor, patch
+            )));
+        }
+        Some(_) => {}
+    }
+    Ok(version)
+}
+
+/// Runs every check before a run against the endpoint and each backup endpoint. Passes
+/// when at least one of them is ready, warning about the others, so a run can start on a
+/// backup while the primary is down; otherwise returns the primary endpoint's error.
+pub async fn check(config: &Config) -> Result<ServerInfo, Error> {
+    let mut first_error = None;
+    let mut ready = None;
+    for endpoint in std::iter::once(&config.endpoint).chain(&config.backup_endpoints) {
+        match check_endpoint(config, endpoint).await {
+            Ok(server) => {
+                ready.get_or_insert(server);
+            }
+            Err(e) if config.backup_endpoints.is_empty() => return Err(e),
+            Err(e) => {
+                warn!("{}", e.message());
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    ready.ok_or_else(|| first_error.expect("at least one endpoint was checked"))
+}
+
+/// The endpoint is reachable and new enough, and serves the generation models of every
+/// profile and the vision model (and the embedding model, when Ollama computes embeddings at this endpoint).
+async fn check_endpoint(config: &Config, endpoint: &str) -> Result<ServerInfo, Error> {
+    let mut required: BTreeSet<&str> = BTreeSet::from([config.model.as_str()]);
+    required.extend(config.profiles.values().filter_map(|profile| profile.model.as_deref()));
+    required.extend(config.images.model.as_deref());
+    let embeddings = &config.embeddings;
+    let embedding_endpoint = embeddings.endpoint.as_deref().unwrap_or(&config.endpoint);
+    if embeddings.api == EmbeddingApi::Ollama && embedding_endpoint.trim_end_matches('/') == endpoint.trim_end_matches('/') {
+        required.extend(embeddings.model.as_deref());
+    }
+    check_models(endpoint, required).await
+}
+
+/// The endpoint is reachable and new enough, and serves every model of `required`.
+pub async fn check_models<'a>(endpoint: &str, required: impl IntoIterator<Item = &'a str>) -> Result<ServerInfo, Error> {
+    #[derive(Deserialize)]
+    struct Model {
+        name: String,
+    }
+    #[derive(Deserialize)]
+    struct TagsResponse {
+        models: Vec<Model>,
+    }
+
+    let version = check_server(endpoint).await?;
+    let endpoint = endpoint.trim_end_matches('/');
+    let client = Client::builder().timeout(TIMEOUT).build()?;
+    let models = client
+        .get(format!("{}/api/tags", endpoint))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TagsResponse>()
+        .await
+        .map_err(|e| Error::Api(format!("{} returned an invalid model list: {}", endpoint, e)))?
+        .models
+        .into_iter()
+        .map(|model| model.name)
+        .collect::<Vec<_>>();
+
+    let required: BTreeSet<&str> = required.into_iter().collect();
+    let missing: Vec<&str> = required.into_iter().filter(|model| !has_model(&models, model)).collect();
+    if !missing.is_empty() {
+        let pulls: Vec<String> = missing.iter().map(|model| format!("`ollama pull {}`", model)).collect();
+        return Err(Error::Api(format!(
+            "{} not available at {}; run {}",
+            match missing.as_slice() {
+                [model] => format!("Model {} is", model),
+                _ => format!("Models {} are", missing.join(", ")),
+            },
+            endpoint,
+            pulls.join(" and ")
+        )));
+    }
+
+    info!("Ollama {} at {} serves {} models", version, endpoint, models.len());
+    Ok(ServerInfo {
+        endpoint: endpoint.to_string(),
+        version,
+        models,
+    })
+}
+
+/// Whether `model` is in the list, where an untagged name means `:latest`.
+fn has_model(models: &[String], model: &str) -> bool {
+    models.iter().any(|name| {
+        name == model || (!model.contains(':') && name.strip_suffix(":latest") == Some(model))
+    })
+}
+
+/// `major.minor.patch` from a version like `0.5.7` or `0.6.0-rc1`.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim_start_matches('v').splitn(3, '.').map(|part| {
+        let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse::<u64>().ok()
+    });
+    Some((parts.next()??, parts.next()??, parts.next().flatten().unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse_version("0.5.7"), Some((0, 5, 7)));
+        assert_eq!(parse_version("v0.6.0-rc1"), Some((0, 6, 0)));
+        assert_eq!(parse_version("1.2"), Some((1, 2, 0)));
+        assert_eq!(parse_version("0.0.0"), Some((0, 0, 0)));
+        assert_eq!(parse_version("dev"), None);
+        assert_eq!(parse_version("1"), None);
+    }
+
+    #[test]
+    fn untagged_models_mean_latest() {
+        let models = ["llama3:latest".to_string(), "qwen2.5:14b".to_string()];
+        assert!(has_model(&models, "llama3"));
+        assert!(has_model(&models, "llama3:latest"));
+        assert!(has_model(&models, "qwen2.5:14b"));
+        assert!(!has_model(&models, "qwen2.5"));
+        assert!(!has_model(&models, "llama3:8b"));
+        assert!(!has_model(&models, "llama"));
+    }
+}
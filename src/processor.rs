@@ -1,28 +1,254 @@
-use std::fs;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use anyhow::{Result, anyhow};
-use reqwest::Client;
+use chrono::NaiveDate;
+use reqwest::{Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use regex::Regex;
+use std::sync::Arc;
+use crate::answer_length::{self, AnswerLength};
+use crate::changelog::Release;
+use crate::cluster::{Chunk, ChunkQueue, ChunkResult};
+use crate::config::{ChunkingConfig, Config, DensityConfig, DeprecationMode, FallbackStrategy, FileSettings};
+use crate::dataset;
+use crate::deprecation::Deprecations;
+use crate::embeddings::ChunkRecord;
+use crate::error::Error;
+use crate::failures::{FailedAttempt, FailureArchive};
+use crate::filter;
+use crate::follow_ups::{self, FollowUp};
+use crate::images::{self, ImageRef};
+use crate::instructions;
+use crate::logs;
+use crate::markdown;
+use crate::metrics::Metrics;
+use crate::pipeline::{FileWork, Section};
+use crate::prompt::{self, PromptVars};
+use crate::reading_level::{self, ReadingLevel};
+use crate::refusals;
+use crate::sql_schema;
+use crate::stream::{heading_level, SectionReader};
+use crate::synthesis;
+use crate::usage::UsageTracker;
+use tracing::{debug, error, field, info_span, instrument, warn, Instrument, Span};
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Where an item was generated from: the source file, relative to the output
+/// directory, and the index of its section within that file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ItemSource {
+    pub file: String,
+    pub section: usize,
+    /// Id of the section, derived from its text (see `pipeline::chunk_id`), so it names
+    /// the same section across runs and when the file moves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<String>,
+    /// The image the item is about, relative to the output directory or a URL, for items
+    /// from the vision model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// When the source file last changed, from git or its modification time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ProcessedItem {
+    /// Stable identity of the item (see `dataset::identify`), kept when it is edited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub question: String,
     pub answer: String,
+    /// Verbatim quote from the section that supports the answer, with `output.citations`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub citation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ItemSource>,
+    /// Hash of the question and answer, which changes when either is edited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Fields added through `[output.schema]`, written out alongside question and answer.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// Embedding of the question, when `[embeddings]` is enabled. Only written to sinks
+    /// with an embedding column, never to JSONL.
+    #[serde(skip)]
+    pub embedding: Option<Vec<f32>>,
+    /// Share of the answer's words copied verbatim from the source section, measured
+    /// when `filters.max_source_overlap` is set. Never written out.
+    #[serde(skip)]
+    pub source_overlap: Option<f64>,
 }
 
+/// Per-file state threaded through section processing.
+struct FileContext<'a> {
+    path: &'a Path,
+    settings: &'a FileSettings,
+    title: String,
+    /// Categories leading to the page in the navigation of its docs site.
+    breadcrumbs: Vec<String>,
+    /// Release of the section being generated, for release notes.
+    release: Option<Release>,
+    /// Questions the section already has from an earlier run, when topping it up.
+    existing_questions: Option<Vec<String>>,
+}
+
+/// Items of a section gathered over several passes, without repeated questions.
+#[derive(Default)]
+struct UniqueItems {
+    items: Vec<ProcessedItem>,
+    seen: HashSet<String>,
+}
+
+impl UniqueItems {
+    /// Adds the items whose question isn't there yet; returns how many were added.
+    fn add(&mut self, items: Vec<ProcessedItem>) -> usize {
+        let before = self.items.len();
+        for item in items {
+            if self.seen.insert(dataset::question_key(&item)) {
+                self.items.push(item);
+            }
+        }
+        self.items.len() - before
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn into_items(self) -> Vec<ProcessedItem> {
+        self.items
+    }
+}
+
+pub const DEFAULT_MODEL: &str = "m/qwen2514bmax";
+
+/// How the citation field is described to the model.
+const CITATION_FIELD: &str = "citation (a short quote copied word for word from the content that supports the answer)";
+
 pub struct OllamaProcessor {
-    endpoint: String,
+    config: Arc<Config>,
     client: Client,
+    /// `endpoint` followed by `backup_endpoints`.
+    endpoints: Vec<String>,
+    /// Index of the endpoint requests currently go to.
+    active: AtomicUsize,
+    usage: UsageTracker,
+    metrics: Arc<Metrics>,
+    failures: Option<FailureArchive>,
+    chunks: Option<Arc<ChunkQueue>>,
+    /// Set with `deprecations.mode = "frame"`.
+    deprecations: Option<Deprecations>,
 }
 
 impl OllamaProcessor {
-    pub fn new(endpoint: String) -> Self {
+    pub fn new(config: Arc<Config>, metrics: Arc<Metrics>) -> Self {
+        let endpoints = std::iter::once(&config.endpoint)
+            .chain(&config.backup_endpoints)
+            .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+            .collect();
+        // Markers were checked when the config was validated
+        let deprecations = Deprecations::new(&config.deprecations)
+            .ok()
+            .filter(|_| config.deprecations.mode == DeprecationMode::Frame);
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.request_timeout_secs))
+            .build()
+            .expect("Failed to build the HTTP client");
         Self {
-            endpoint,
-            client: Client::new(),
+            config,
+            client,
+            endpoints,
+            active: AtomicUsize::new(0),
+            usage: UsageTracker::default(),
+            metrics,
+            failures: None,
+            chunks: None,
+            deprecations,
+        }
+    }
+
+    /// Hands sections to distributed workers through `queue` instead of calling Ollama.
+    pub fn with_chunk_queue(mut self, queue: Arc<ChunkQueue>) -> Self {
+        self.chunks = Some(queue);
+        self
+    }
+
+    /// Archive unparseable model output under `dir` instead of only logging it.
+    pub fn with_failure_archive<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.failures = Some(FailureArchive::new(dir));
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    /// The endpoint requests currently go to.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoints[self.active.load(Ordering::SeqCst)]
+    }
+
+    /// Posts `body` to `/api/chat`. When the active endpoint can't be reached, drops the
+    /// connection or sits behind a proxy that reports it down (502, 503, 504), the next
+    /// configured endpoint takes over for this and every later request; each endpoint is
+    /// tried at most once per request.
+    async fn post_chat(&self, body: &serde_json::Value) -> reqwest::Result<Response> {
+        let mut attempts = 0;
+        loop {
+            let active = self.active.load(Ordering::SeqCst);
+            let endpoint = &self.endpoints[active];
+            attempts += 1;
+            let result = self.client.post(format!("{}/api/chat", endpoint)).json(body).send().await;
+            let reason = match &result {
+                _ if attempts >= self.endpoints.len() => return result,
+                Err(e) if !e.is_builder() => e.to_string(),
+                Ok(response) if matches!(
+                    response.status(),
+                    StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+                ) => response.status().to_string(),
+                _ => return result,
+            };
+            let next = (active + 1) % self.endpoints.len();
+            // Concurrent requests hit the same outage; only the first one switches
+            if self.active.compare_exchange(active, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                self.metrics.endpoint_failovers.inc();
+                warn!("{} is unavailable ({}); failing over to {}", endpoint, reason, self.endpoints[next]);
+            }
+        }
+    }
+
+    /// The reply of `model` to `messages`, for requests outside generation such as OCR
+    /// and judging: constrained to the JSON schema `format` when given, and sampled with
+    /// `options`. Its tokens count towards the usage of `file`.
+    pub async fn chat(&self, file: &Path, model: &str, messages: serde_json::Value, format: Option<serde_json::Value>, options: serde_json::Value) -> Result<String> {
+        let mut body = serde_json::json!({
+            "model": model,
+            "messages": messages,
+            "stream": false,
+            "options": options
+        });
+        if let Some(format) = format {
+            body["format"] = format;
+        }
+        self.metrics.requests.inc();
+        let timer = self.metrics.request_duration.start_timer();
+        let response = self.post_chat(&body).await.inspect_err(|_| self.metrics.request_failures.inc())?;
+        timer.observe_duration();
+        if !response.status().is_success() {
+            self.metrics.request_failures.inc();
+            let status = response.status();
+            return Err(Error::Api(format!("Ollama returned {}: {}", status, response.text().await?)).into());
         }
+        let response: ChatResponse = response.json().await.map_err(|e| Error::Parse(format!("Failed to parse chat response: {}", e)))?;
+        self.usage.record(file, model, response.prompt_eval_count, response.eval_count);
+        Ok(response.message.content)
     }
 
     fn sanitize_json(json: &str) -> String {
@@ -96,61 +322,100 @@ impl OllamaProcessor {
         result
     }
 
-    fn count_words(text: &str) -> usize {
+    pub(crate) fn count_words(text: &str) -> usize {
         text.split_whitespace().count()
     }
 
-    fn calculate_question_targets(word_count: usize) -> (usize, usize, usize) {
-        // Base goal: 1 question per 10 words
-        let base_goal = (word_count as f64 / 10.0).ceil() as usize;
+    /// Whether a section has too little to ask about under `chunking.min_words` and
+    /// `chunking.skip_link_only`, such as a heading over a single line or a list of links.
+    pub(crate) fn is_thin(text: &str, chunking: &ChunkingConfig) -> bool {
+        let words = markdown::prose_words(text);
+        words < chunking.min_words || (words == 0 && chunking.skip_link_only)
+    }
+
+    pub(crate) fn calculate_question_targets(word_count: usize, density: &DensityConfig) -> (usize, usize, usize) {
+        // Base goal: 1 question per `words_per_question` words (10 by default)
+        let base_goal = (word_count as f64 / density.words_per_question).ceil() as usize;
         
         // For small sections, ensure at least 2 questions
         let base_goal = base_goal.max(2);
         
-        // Calculate extra questions (25% of base goal, minimum of 2)
-        let extra_questions = (base_goal as f64 * 0.25).ceil() as usize;
-        let extra_questions = extra_questions.max(2);
+        // Calculate extra questions (25% of base goal, minimum of 2, by default)
+        let extra_questions = (base_goal as f64 * density.buffer_ratio).ceil() as usize;
+        let extra_questions = extra_questions.max(density.min_buffer);
         let generation_target = base_goal + extra_questions;
         
-        // Minimum acceptable is 80% of base goal, but at least 2
-        let min_acceptable = ((base_goal as f64 * 0.8).ceil() as usize).max(2);
+        // Minimum acceptable is 80% of base goal by default, but at least 2
+        let min_acceptable = ((base_goal as f64 * density.min_ratio).ceil() as usize).max(2);
         
-        println!("Question targets for {} words:", word_count);
-        println!("  Base goal: {} questions", base_goal);
-        println!("  Generating: {} questions (+{} extra)", generation_target, extra_questions);
-        println!("  Minimum acceptable: {} questions", min_acceptable);
+        debug!("Question targets for {} words:", word_count);
+        debug!("  Base goal: {} questions", base_goal);
+        debug!("  Generating: {} questions (+{} extra)", generation_target, extra_questions);
+        debug!("  Minimum acceptable: {} questions", min_acceptable);
         
         (base_goal, generation_target, min_acceptable)
     }
 
-    fn split_into_sections(&self, content: &str) -> Vec<String> {
-        let mut sections = Vec::new();
-        let mut current_section = String::new();
-        let header_regex = Regex::new(r"(?m)^#\s|^##\s").unwrap();
-        
-        // Add first section if content doesn't start with a header
-        if !header_regex.is_match(content.lines().next().unwrap_or("")) {
-            current_section = String::new();
+    pub fn split_into_sections(content: &str, chunking: &ChunkingConfig) -> Vec<String> {
+        // Reading from memory can't fail
+        let sections: Vec<String> = SectionReader::new(content.as_bytes(), chunking.heading_level)
+            .with_parent_headings(chunking.parent_headings)
+            .map_while(Result::ok)
+            .collect();
+
+        // If no sections were created (the content is blank), use the whole content
+        if sections.is_empty() {
+            return vec![content.to_string()];
         }
+        sections
+    }
 
-        for line in content.lines() {
-            if header_regex.is_match(line) {
-                // Save previous section if not empty
-                if !current_section.trim().is_empty() {
-                    sections.push(current_section);
-                }
-                current_section = String::new();
+    /// Splits a section at every heading in it, keeping the hierarchy when parent
+    /// headings are on.
+    fn split_by_headings(content: &str, chunking: &ChunkingConfig) -> Vec<String> {
+        Self::split_into_sections(content, &ChunkingConfig { heading_level: 6, ..chunking.clone() })
+    }
+
+    /// Splits a section into paragraphs. With parent headings, each paragraph starts
+    /// with the headings the section starts with.
+    fn split_by_paragraphs(content: &str, chunking: &ChunkingConfig) -> Vec<String> {
+        let paragraphs = Self::split_paragraphs(content, chunking.paragraph_break_lines);
+        let headings: String = content.split_inclusive('\n').take_while(|line| heading_level(line).is_some()).collect();
+        if !chunking.parent_headings || headings.is_empty() {
+            return paragraphs;
+        }
+        let paragraphs: Vec<String> = paragraphs
+            .into_iter()
+            .filter(|paragraph| paragraph.lines().any(|line| !line.trim().is_empty() && heading_level(line).is_none()))
+            .map(|paragraph| match paragraph.starts_with(&headings) {
+                true => paragraph,
+                false => format!("{}{}", headings, paragraph.trim_start_matches('\n')),
+            })
+            .collect();
+        if paragraphs.is_empty() {
+            return vec![content.to_string()];
+        }
+        paragraphs
+    }
+
+    /// Splits `content` between top-level blocks separated by `break_lines` blank lines,
+    /// so code blocks, lists and tables are never cut in two.
+    fn split_paragraphs(content: &str, break_lines: usize) -> Vec<String> {
+        let mut sections = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        for block in markdown::blocks(content) {
+            let empty_lines = content[end..block.range.start].lines().filter(|line| line.trim().is_empty()).count();
+            if empty_lines >= break_lines.max(1) && !content[start..end].trim().is_empty() {
+                sections.push(content[start..end].to_string());
+                start = end;
             }
-            current_section.push_str(line);
-            current_section.push('\n');
+            end = block.range.end;
         }
-        
-        // Add the last section
-        if !current_section.trim().is_empty() {
-            sections.push(current_section);
+        if !content[start..].trim().is_empty() {
+            sections.push(content[start..].to_string());
         }
 
-        // If no sections were created (no headers found), use the whole content
         if sections.is_empty() {
             sections.push(content.to_string());
         }
@@ -158,252 +423,691 @@ impl OllamaProcessor {
         sections
     }
 
-    fn split_by_headings(&self, content: &str) -> Vec<String> {
-        let mut sections = Vec::new();
-        let mut current_section = String::new();
-        
-        for line in content.lines() {
-            if line.starts_with('#') {
-                if !current_section.trim().is_empty() {
-                    sections.push(current_section);
-                    current_section = String::new();
-                }
-            }
-            current_section.push_str(line);
-            current_section.push('\n');
+    pub fn is_distributed(&self) -> bool {
+        self.chunks.is_some()
+    }
+
+    /// Generates items for a section of a parsed file, locally or through a distributed
+    /// worker.
+    pub async fn generate_section(&self, work: &FileWork, section: &Section) -> Result<Vec<ProcessedItem>> {
+        let settings = work.settings()?;
+        let file = FileContext {
+            path: &work.path,
+            settings,
+            title: work.title.clone(),
+            breadcrumbs: work.breadcrumbs.clone(),
+            release: section.release.clone(),
+            existing_questions: work.existing_questions(section.index),
+        };
+        let Some(queue) = &self.chunks else {
+            return self.process_section_recursive(&section.text, &file, section.target).await;
+        };
+        let result: ChunkResult = queue
+            .dispatch(Chunk {
+                id: 0,
+                file: work.relative_path.display().to_string(),
+                title: work.title.clone(),
+                breadcrumbs: work.breadcrumbs.clone(),
+                section: section.text.clone(),
+                target: section.target,
+                settings: settings.clone(),
+                release: section.release.clone(),
+                existing_questions: file.existing_questions.clone(),
+            })
+            .await;
+        self.usage.record_usage(&work.path, &settings.model, &result.usage);
+        match result.error {
+            Some(e) => Err(e.context("worker").into()),
+            None => Ok(result.items),
         }
-        
-        if !current_section.trim().is_empty() {
-            sections.push(current_section);
+    }
+
+    /// Worker side of distributed generation: items for a chunk handed out by the
+    /// coordinator, before filtering.
+    pub async fn generate_chunk(&self, chunk: &Chunk) -> Result<Vec<ProcessedItem>> {
+        let file = FileContext {
+            path: Path::new(&chunk.file),
+            settings: &chunk.settings,
+            title: chunk.title.clone(),
+            breadcrumbs: chunk.breadcrumbs.clone(),
+            release: chunk.release.clone(),
+            existing_questions: chunk.existing_questions.clone(),
+        };
+        self.process_section_recursive(&chunk.section, &file, chunk.target).await
+    }
+
+    #[instrument(name = "section", skip_all, fields(words = Self::count_words(section), target = target_questions))]
+    async fn process_section_recursive(&self, section: &str, file: &FileContext<'_>, target_questions: usize) -> Result<Vec<ProcessedItem>> {
+        // First try processing the whole section
+        let items = self.process_section(section, file, target_questions).await?;
+        debug!("Got {} questions from full section (target: {})", items.len(), target_questions);
+
+        if items.len() >= target_questions || !file.settings.chunking.recursive_fallback {
+            return Ok(items);
         }
-        
-        if sections.is_empty() {
-            sections.push(content.to_string());
+
+        let mut unique = UniqueItems::default();
+        unique.add(items);
+        let items = match file.settings.chunking.fallback_strategy {
+            FallbackStrategy::Union => self.fallback_union(section, file, target_questions, unique).await,
+            FallbackStrategy::Best => self.fallback_best(section, file, target_questions, unique).await,
+        };
+        if items.len() < target_questions {
+            warn!("Could not generate enough questions. Got {} out of {}", items.len(), target_questions);
         }
-        
-        sections
+        Ok(items)
     }
-    
-    fn split_by_paragraphs(&self, content: &str) -> Vec<String> {
-        let mut sections = Vec::new();
-        let mut current_section = String::new();
-        let mut empty_lines = 0;
-        
-        for line in content.lines() {
-            if line.trim().is_empty() {
-                empty_lines += 1;
-                if empty_lines >= 2 && !current_section.trim().is_empty() {
-                    sections.push(current_section);
-                    current_section = String::new();
-                    empty_lines = 0;
+
+    /// Parts of a section that aren't too thin to ask about, each with its share of the section's target by word count.
+    fn with_targets(parts: Vec<String>, section: &str, target_questions: usize, chunking: &ChunkingConfig) -> Vec<(String, usize)> {
+        let section_words = Self::count_words(section).max(1) as f64;
+        parts
+            .into_iter()
+            .filter(|part| !Self::is_thin(part, chunking))
+            .map(|part| {
+                let words_ratio = Self::count_words(&part) as f64 / section_words;
+                let target = (target_questions as f64 * words_ratio).ceil() as usize;
+                (part, target)
+            })
+            .collect()
+    }
+
+    /// Adds the questions of `parts` of a section to `unique`, one part after the other,
+    /// until `goal` questions were added or `unique` has `stop_at`. Returns how many
+    /// were added.
+    async fn add_parts(
+        &self,
+        parts: &[(String, usize)],
+        kind: &str,
+        file: &FileContext<'_>,
+        unique: &mut UniqueItems,
+        goal: usize,
+        stop_at: usize,
+    ) -> usize {
+        let mut added = 0;
+        for (i, (part, part_target)) in parts.iter().enumerate() {
+            if added >= goal || unique.len() >= stop_at {
+                break;
+            }
+            debug!("Processing {} section {}/{} (target {} questions)", kind, i + 1, parts.len(), part_target);
+            match self.process_section(part, file, *part_target).await {
+                Ok(items) => {
+                    let count = items.len();
+                    let new = unique.add(items);
+                    debug!("  Got {} questions, {} of them new", count, new);
+                    added += new;
                 }
-            } else {
-                empty_lines = 0;
+                Err(e) => warn!("Error processing {} section: {}", kind, e),
             }
-            current_section.push_str(line);
-            current_section.push('\n');
         }
-        
-        if !current_section.trim().is_empty() {
-            sections.push(current_section);
+        added
+    }
+
+    /// Splits a section that fell short by headings, and each heading section that
+    /// falls short of its share by paragraphs, keeping every new question of every
+    /// pass; stops as soon as the section has its target.
+    async fn fallback_union(&self, section: &str, file: &FileContext<'_>, target_questions: usize, mut unique: UniqueItems) -> Vec<ProcessedItem> {
+        let chunking = &file.settings.chunking;
+        let heading_sections = Self::split_by_headings(section, chunking);
+        if heading_sections.len() <= 1 {
+            debug!("Splitting section by paragraphs...");
+            let paragraphs = Self::with_targets(Self::split_by_paragraphs(section, chunking), section, target_questions, chunking);
+            if paragraphs.len() > 1 {
+                self.add_parts(&paragraphs, "paragraph", file, &mut unique, usize::MAX, target_questions).await;
+            }
+            return unique.into_items();
         }
-        
-        if sections.is_empty() {
-            sections.push(content.to_string());
+
+        debug!("Splitting section by headings...");
+        let heading_sections = Self::with_targets(heading_sections, section, target_questions, chunking);
+        for (i, (subsection, subsection_target)) in heading_sections.iter().enumerate() {
+            if unique.len() >= target_questions {
+                debug!("Got enough questions: {}", unique.len());
+                break;
+            }
+            debug!("Processing heading section {}/{} (target {} questions)", i + 1, heading_sections.len(), subsection_target);
+            let added = match self.process_section(subsection, file, *subsection_target).await {
+                Ok(items) => {
+                    let count = items.len();
+                    let new = unique.add(items);
+                    debug!("  Got {} questions, {} of them new", count, new);
+                    new
+                }
+                Err(e) => {
+                    warn!("Error processing heading section: {}", e);
+                    0
+                }
+            };
+            if added >= *subsection_target {
+                continue;
+            }
+            // Only the paragraphs of this heading section are asked for the rest of its share
+            let paragraphs = Self::with_targets(Self::split_by_paragraphs(subsection, chunking), section, target_questions, chunking);
+            if paragraphs.len() > 1 {
+                debug!("Heading section {} fell short ({} of {}), splitting it by paragraphs...", i + 1, added, subsection_target);
+                self.add_parts(&paragraphs, "paragraph", file, &mut unique, subsection_target - added, target_questions).await;
+            }
         }
-        
-        sections
+        unique.into_items()
     }
 
-    async fn process_section_recursive(&self, section: &str, _file_path: &Path, target_questions: usize) -> Result<Vec<ProcessedItem>> {
-        let mut all_items = Vec::new();
-        
-        // First try processing the whole section
-        let items = self.process_section(section, _file_path).await?;
-        println!("Got {} questions from full section (target: {})", items.len(), target_questions);
-        
-        if items.len() >= target_questions {
-            return Ok(items);
+    /// Splits a section that fell short by headings, then by paragraphs, and keeps the
+    /// pass with the most unique questions; stops at the first pass that reaches the
+    /// section's target.
+    async fn fallback_best(&self, section: &str, file: &FileContext<'_>, target_questions: usize, mut best: UniqueItems) -> Vec<ProcessedItem> {
+        let passes = [
+            ("heading", Self::split_by_headings(section, &file.settings.chunking)),
+            ("paragraph", Self::split_by_paragraphs(section, &file.settings.chunking)),
+        ];
+        for (kind, parts) in passes {
+            if best.len() >= target_questions {
+                break;
+            }
+            if parts.len() <= 1 {
+                continue;
+            }
+            debug!("Splitting section by {}s...", kind);
+            let mut pass = UniqueItems::default();
+            let parts = Self::with_targets(parts, section, target_questions, &file.settings.chunking);
+            self.add_parts(&parts, kind, file, &mut pass, usize::MAX, usize::MAX).await;
+            debug!("Got {} questions from {} sections", pass.len(), kind);
+            if pass.len() > best.len() {
+                best = pass;
+            }
+        }
+        best.into_items()
+    }
+
+    #[instrument(name = "generate", skip_all, fields(words = Self::count_words(section), retries = 0))]
+    async fn process_section(&self, section: &str, file: &FileContext<'_>, target: usize) -> Result<Vec<ProcessedItem>> {
+        let word_count = Self::count_words(section);
+        let (_, mut generation_target, _) = Self::calculate_question_targets(word_count, &file.settings.density);
+        // Topping up a section from an earlier run: ask only for the missing questions
+        if file.existing_questions.is_some() {
+            generation_target = generation_target.min(target.max(1));
         }
         
-        // If not enough questions, try splitting by headings
-        println!("Splitting section by headings...");
-        let heading_sections = self.split_by_headings(section);
-        if heading_sections.len() > 1 {
-            for (i, subsection) in heading_sections.iter().enumerate() {
-                println!("Processing heading section {}/{}", i + 1, heading_sections.len());
-                let words_ratio = Self::count_words(subsection) as f64 / Self::count_words(section) as f64;
-                let subsection_target = (target_questions as f64 * words_ratio).ceil() as usize;
-                println!("  Target {} questions ({:.1}% of content)", subsection_target, words_ratio * 100.0);
-                
-                match self.process_section(subsection, _file_path).await {
-                    Ok(mut items) => {
-                        println!("  Got {} questions", items.len());
-                        all_items.append(&mut items);
-                    },
-                    Err(e) => println!("Error processing heading section: {}", e),
-                }
+        let prompt_section = match self.config.images.strip {
+            true => images::strip_references(section),
+            false => Cow::Borrowed(section),
+        };
+        // Release notes and references keep their own prompts for their lists
+        let own_prompt = file.settings.is_release_notes()
+            || file.settings.is_config_reference()
+            || file.settings.is_cli()
+            || file.settings.is_infrastructure()
+            || file.settings.is_sql()
+            || file.settings.is_logs();
+        let list = match self.config.lists.enabled && !own_prompt {
+            true => markdown::main_list(&prompt_section, self.config.lists.min_items),
+            false => None,
+        };
+        let prompt = match list {
+            Some((ordered, items)) => {
+                debug!("  Section is a {} list of {} items", if ordered { "numbered" } else { "bullet" }, items);
+                &file.settings.list_prompt
             }
-            
-            if all_items.len() >= target_questions {
-                println!("Got enough questions from heading sections: {}", all_items.len());
-                return Ok(all_items);
+            None => &file.settings.prompt,
+        };
+        let deprecations = match &self.deprecations {
+            Some(deprecations) if !file.settings.is_release_notes() => deprecations.notices(&prompt_section),
+            _ => Vec::new(),
+        };
+        if !deprecations.is_empty() {
+            debug!("  Section mentions {} deprecated features", deprecations.len());
+        }
+        let style_examples = prompt::style_examples(&file.settings.style.references, &prompt_section, file.settings.style.examples);
+        let vars = PromptVars {
+            target_count: generation_target,
+            section: &prompt_section,
+            doc_title: &file.title,
+            breadcrumbs: &file.breadcrumbs,
+            file_name: file.path.file_name().and_then(|n| n.to_str()).unwrap_or(""),
+            product_name: file.settings.product_name.as_deref(),
+            extra_fields: &self.extra_fields(),
+            keywords: &self.config.steering.keywords,
+            exclude_topics: &self.config.steering.exclude_topics,
+            version: file.release.as_ref().map(|release| release.version.as_str()),
+            release_date: file.release.as_ref().and_then(|release| release.date.as_deref()),
+            change_type: file.release.as_ref().and_then(|release| release.change_type.as_deref()),
+            paraphrase: file.settings.filters.max_source_overlap.is_some(),
+            answer_length: self.config.answer_length.preset.map(AnswerLength::instruction),
+            reading_level: self.config.reading_level.level.map(ReadingLevel::instruction),
+            style_examples: &style_examples,
+            existing_questions: file.existing_questions.as_deref().unwrap_or_default(),
+            deprecations: &deprecations,
+            ordered_list: list.is_some_and(|(ordered, _)| ordered),
+            list_items: list.map_or(0, |(_, items)| items),
+        };
+        let system_msg = prompt::render(&prompt.system, &vars)?;
+        let user_msg = prompt::render(&prompt.user, &vars)?;
+
+        let mut items = self.chat_items(file, &system_msg, &user_msg, &[], self.config.output.citations, generation_target).await?;
+        if let Some(length) = self.config.answer_length.preset {
+            self.fit_answers(file, &mut items, length, &prompt_section).await;
+        }
+        if let Some(level) = self.config.reading_level.level {
+            self.fit_reading_level(file, &mut items, level, &prompt_section).await;
+        }
+        // Checked against the text the model saw
+        if self.config.output.citations {
+            Self::verify_citations(&mut items, &prompt_section);
+        }
+        if file.settings.filters.max_source_overlap.is_some() {
+            for item in &mut items {
+                item.source_overlap = Some(filter::source_overlap(&item.answer, &prompt_section));
             }
         }
-        
-        // If still not enough, try splitting by paragraphs
-        println!("Splitting section by paragraphs...");
-        all_items.clear();
-        let paragraph_sections = self.split_by_paragraphs(section);
-        if paragraph_sections.len() > 1 {
-            for (i, subsection) in paragraph_sections.iter().enumerate() {
-                println!("Processing paragraph section {}/{}", i + 1, paragraph_sections.len());
-                let words_ratio = Self::count_words(subsection) as f64 / Self::count_words(section) as f64;
-                let subsection_target = (target_questions as f64 * words_ratio).ceil() as usize;
-                println!("  Target {} questions ({:.1}% of content)", subsection_target, words_ratio * 100.0);
-                
-                match self.process_section(subsection, _file_path).await {
-                    Ok(mut items) => {
-                        println!("  Got {} questions", items.len());
-                        all_items.append(&mut items);
-                    },
-                    Err(e) => println!("Error processing paragraph section: {}", e),
+        if let Some(release) = &file.release {
+            Self::tag_release(&mut items, release);
+        }
+        if let Some(excerpt) = logs::excerpt(&prompt_section).filter(|_| file.settings.is_logs()) {
+            for item in &mut items {
+                item.extra.insert(logs::EXCERPT_FIELD.to_string(), excerpt.clone().into());
+            }
+        }
+        Ok(items)
+    }
+
+    /// Asks again, up to `answer_length.reasks` times each, for answers that don't have
+    /// the words of `length`, and tags every item with it. Answers still off are left
+    /// for the filter to drop.
+    async fn fit_answers(&self, file: &FileContext<'_>, items: &mut [ProcessedItem], length: AnswerLength, section: &str) {
+        for item in items.iter_mut() {
+            for attempt in 0..self.config.answer_length.reasks {
+                if length.fits(&item.answer) {
+                    break;
+                }
+                debug!("  Answer of {} words is not {}; asking again", item.answer.split_whitespace().count(), length.as_str());
+                let user_msg = length.fit_prompt(&item.question, &item.answer, section);
+                match self.rewrite(file, answer_length::FIT_SYSTEM_PROMPT, &user_msg, attempt).await {
+                    Ok(answer) if !answer.is_empty() => item.answer = answer,
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Failed to ask again for a {} answer: {}", length.as_str(), e);
+                        break;
+                    }
                 }
             }
-            
-            if all_items.len() >= target_questions {
-                println!("Got enough questions from paragraph sections: {}", all_items.len());
-                return Ok(all_items);
+            item.extra.insert(answer_length::FIELD.to_string(), length.as_str().into());
+        }
+    }
+
+    /// Reads through the items' answers for `reading_level.level`, asking again up to
+    /// `regenerations` times for those whose grade is too far off and keeping the closest
+    /// answer. Every item records the level and its answer's grade.
+    async fn fit_reading_level(&self, file: &FileContext<'_>, items: &mut [ProcessedItem], level: ReadingLevel, section: &str) {
+        let config = &self.config.reading_level;
+        let length = self.config.answer_length.preset.map(AnswerLength::instruction);
+        for item in items.iter_mut() {
+            let mut grade = reading_level::grade(&item.answer);
+            for attempt in 0..config.regenerations {
+                if level.fits(grade, config.tolerance) {
+                    break;
+                }
+                debug!("  Answer reads at grade {:.1}, not {}; asking again", grade, level.as_str());
+                let user_msg = level.rewrite_prompt(&item.question, &item.answer, section, length);
+                match self.rewrite(file, reading_level::REWRITE_SYSTEM_PROMPT, &user_msg, attempt).await {
+                    Ok(answer) if !answer.is_empty() => {
+                        let candidate = reading_level::grade(&answer);
+                        if level.closer(candidate, grade) {
+                            item.answer = answer;
+                            grade = candidate;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Failed to ask again for a {} answer: {}", level.as_str(), e);
+                        break;
+                    }
+                }
             }
+            item.extra.insert(reading_level::FIELD.to_string(), level.as_str().into());
+            item.extra.insert(reading_level::GRADE_FIELD.to_string(), ((grade * 10.0).round() / 10.0).into());
         }
-        
-        // If we still don't have enough questions, return what we have
-        println!("Could not generate enough questions. Got {} out of {}", all_items.len(), target_questions);
-        Ok(all_items)
     }
 
-    async fn process_section(&self, section: &str, _file_path: &Path) -> Result<Vec<ProcessedItem>> {
-        let word_count = Self::count_words(section);
-        let (_, generation_target, _) = Self::calculate_question_targets(word_count);
-        
-        let prompt_text = if section.contains("# Release Notes") || section.contains("# Changelog") {
-            format!(
-                "Generate exactly {} unique questions and answers from these release notes. \
-                 Focus on specific changes, features, and improvements. \
-                 Format as JSON array with 'question' and 'answer' fields. \
-                 Questions should be detailed and specific to the version mentioned in the notes.",
-                generation_target
-            )
-        } else {
-            format!(
-                "Generate exactly {} unique questions and answers from this documentation. \
-                 Focus on key concepts, features, and usage. \
-                 Format as JSON array with 'question' and 'answer' fields.",
-                generation_target
-            )
+    /// The reply of the file's model to one exchange, as plain text.
+    async fn rewrite(&self, file: &FileContext<'_>, system_msg: &str, user_msg: &str, attempt: usize) -> Result<String> {
+        self.metrics.requests.inc();
+        let response = self
+            .post_chat(&serde_json::json!({
+                "model": file.settings.model,
+                "messages": [
+                    { "role": "system", "content": system_msg },
+                    { "role": "user", "content": user_msg }
+                ],
+                "stream": false,
+                "options": self.model_options(attempt)
+            }))
+            .await
+            .inspect_err(|_| self.metrics.request_failures.inc())?;
+        if !response.status().is_success() {
+            self.metrics.request_failures.inc();
+            let status = response.status();
+            return Err(Error::Api(format!("Ollama returned {}: {}", status, response.text().await?)).into());
+        }
+        let response: ChatResponse = response.json().await.map_err(|e| Error::Parse(format!("Failed to parse chat response: {}", e)))?;
+        self.usage.record(file.path, &file.settings.model, response.prompt_eval_count, response.eval_count);
+        Ok(response.message.content.trim().to_string())
+    }
+
+    /// Settings of `work` with the vision model under `[images]`.
+    fn image_settings(&self, work: &FileWork) -> Result<FileSettings> {
+        let model = self.config.images.model.clone().ok_or_else(|| anyhow!("No vision model under [images]"))?;
+        Ok(FileSettings {
+            model,
+            ..work.settings()?.clone()
+        })
+    }
+
+    /// A description of an image referenced by a file, from the vision model under
+    /// `[images]`. `image` is the base64-encoded image file.
+    pub async fn describe_image(&self, work: &FileWork, reference: &ImageRef, image: &str) -> Result<String> {
+        let settings = self.image_settings(work)?;
+        self.metrics.requests.inc();
+        let response = self
+            .post_chat(&serde_json::json!({
+                "model": settings.model,
+                "messages": [
+                    { "role": "system", "content": images::DESCRIBE_SYSTEM_PROMPT },
+                    { "role": "user", "content": images::describe_prompt(reference, &work.title), "images": [image] }
+                ],
+                "stream": false,
+                "options": self.model_options(0)
+            }))
+            .await
+            .inspect_err(|_| self.metrics.request_failures.inc())?;
+        if !response.status().is_success() {
+            self.metrics.request_failures.inc();
+            let status = response.status();
+            return Err(Error::Api(format!("Ollama returned {}: {}", status, response.text().await?)).into());
+        }
+        let response: ChatResponse = response.json().await.map_err(|e| Error::Parse(format!("Failed to parse chat response: {}", e)))?;
+        self.usage.record(&work.path, &settings.model, response.prompt_eval_count, response.eval_count);
+        Ok(response.message.content.trim().to_string())
+    }
+
+    /// An answer to `question` from the related `passages` of the corpus, for the
+    /// `[synthesis]` pass; usage is recorded for `file`.
+    pub async fn synthesize_answer(&self, file: &Path, question: &str, passages: &[&ChunkRecord]) -> Result<String> {
+        self.metrics.requests.inc();
+        let response = self
+            .post_chat(&serde_json::json!({
+                "model": self.config.model,
+                "messages": [
+                    { "role": "system", "content": synthesis::SYSTEM_PROMPT },
+                    { "role": "user", "content": synthesis::user_prompt(question, passages) }
+                ],
+                "stream": false,
+                "options": self.model_options(0)
+            }))
+            .await
+            .inspect_err(|_| self.metrics.request_failures.inc())?;
+        if !response.status().is_success() {
+            self.metrics.request_failures.inc();
+            let status = response.status();
+            return Err(Error::Api(format!("Ollama returned {}: {}", status, response.text().await?)).into());
+        }
+        let response: ChatResponse = response.json().await.map_err(|e| Error::Parse(format!("Failed to parse chat response: {}", e)))?;
+        self.usage.record(file, &self.config.model, response.prompt_eval_count, response.eval_count);
+        Ok(response.message.content.trim().to_string())
+    }
+
+    /// Up to `max` follow-up questions and answers continuing `item`, from the `section`
+    /// of `work` it was generated from.
+    pub async fn generate_follow_ups(&self, work: &FileWork, item: &ProcessedItem, section: &str, max: usize) -> Result<Vec<FollowUp>> {
+        #[derive(Deserialize)]
+        struct FollowUpResponse {
+            follow_ups: Vec<FollowUp>,
+        }
+
+        let settings = work.settings()?;
+        self.metrics.requests.inc();
+        let response = self
+            .post_chat(&serde_json::json!({
+                "model": settings.model,
+                "messages": [
+                    { "role": "system", "content": follow_ups::SYSTEM_PROMPT },
+                    { "role": "user", "content": follow_ups::user_prompt(item, section, max) }
+                ],
+                "stream": false,
+                "format": {
+                    "type": "object",
+                    "required": ["follow_ups"],
+                    "properties": {
+                        "follow_ups": {
+                            "type": "array",
+                            "maxItems": max,
+                            "items": {
+                                "type": "object",
+                                "required": ["question", "answer"],
+                                "properties": { "question": { "type": "string" }, "answer": { "type": "string" } }
+                            }
+                        }
+                    }
+                },
+                "options": self.model_options(0)
+            }))
+            .await
+            .inspect_err(|_| self.metrics.request_failures.inc())?;
+        if !response.status().is_success() {
+            self.metrics.request_failures.inc();
+            let status = response.status();
+            return Err(Error::Api(format!("Ollama returned {}: {}", status, response.text().await?)).into());
+        }
+        let response: ChatResponse = response.json().await.map_err(|e| Error::Parse(format!("Failed to parse chat response: {}", e)))?;
+        self.usage.record(&work.path, &settings.model, response.prompt_eval_count, response.eval_count);
+        let parsed: FollowUpResponse = serde_json::from_str(&Self::sanitize_json(&response.message.content)).map_err(|e| {
+            self.metrics.parse_failures.inc();
+            Error::Parse(format!("Unparseable follow-ups: {}", e))
+        })?;
+        let mut follow_ups: Vec<FollowUp> = parsed
+            .follow_ups
+            .into_iter()
+            .filter(|follow_up| !follow_up.question.trim().is_empty() && !follow_up.answer.trim().is_empty())
+            .collect();
+        follow_ups.truncate(max);
+        Ok(follow_ups)
+    }
+
+    /// `questions` of `work` rewritten as imperative instructions, in order.
+    pub async fn rewrite_as_instructions(&self, work: &FileWork, questions: &[&str]) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct InstructionResponse {
+            instructions: Vec<String>,
+        }
+
+        let settings = work.settings()?;
+        self.metrics.requests.inc();
+        let response = self
+            .post_chat(&serde_json::json!({
+                "model": settings.model,
+                "messages": [
+                    { "role": "system", "content": instructions::SYSTEM_PROMPT },
+                    { "role": "user", "content": instructions::user_prompt(questions) }
+                ],
+                "stream": false,
+                "format": {
+                    "type": "object",
+                    "required": ["instructions"],
+                    "properties": {
+                        "instructions": {
+                            "type": "array",
+                            "minItems": questions.len(),
+                            "maxItems": questions.len(),
+                            "items": { "type": "string" }
+                        }
+                    }
+                },
+                "options": self.model_options(0)
+            }))
+            .await
+            .inspect_err(|_| self.metrics.request_failures.inc())?;
+        if !response.status().is_success() {
+            self.metrics.request_failures.inc();
+            let status = response.status();
+            return Err(Error::Api(format!("Ollama returned {}: {}", status, response.text().await?)).into());
+        }
+        let response: ChatResponse = response.json().await.map_err(|e| Error::Parse(format!("Failed to parse chat response: {}", e)))?;
+        self.usage.record(&work.path, &settings.model, response.prompt_eval_count, response.eval_count);
+        let parsed: InstructionResponse = serde_json::from_str(&Self::sanitize_json(&response.message.content)).map_err(|e| {
+            self.metrics.parse_failures.inc();
+            Error::Parse(format!("Unparseable instructions: {}", e))
+        })?;
+        let instructions: Vec<String> = parsed.instructions.iter().map(|instruction| instruction.trim().to_string()).collect();
+        if instructions.len() != questions.len() || instructions.iter().any(String::is_empty) {
+            self.metrics.parse_failures.inc();
+            return Err(Error::Parse(format!("Got {} instructions for {} questions", instructions.len(), questions.len())).into());
+        }
+        Ok(instructions)
+    }
+
+    /// Items about an image referenced by a file, from the vision model under `[images]`.
+    /// `image` is the base64-encoded image file; a `description` of it from
+    /// `describe_image` is passed along.
+    pub async fn generate_image(&self, work: &FileWork, reference: &ImageRef, image: String, description: Option<&str>) -> Result<Vec<ProcessedItem>> {
+        let settings = self.image_settings(work)?;
+        let file = FileContext {
+            path: &work.path,
+            settings: &settings,
+            title: work.title.clone(),
+            breadcrumbs: work.breadcrumbs.clone(),
+            release: None,
+            existing_questions: None,
         };
+        let target = self.config.images.questions_per_image;
+        let user_msg = images::user_prompt(reference, &work.title, description, target, &self.config.output.schema.describe());
+        self.chat_items(&file, images::SYSTEM_PROMPT, &user_msg, &[image], false, target).await
+    }
+
+    /// `count` requests the assistant must decline under `policy`, answered with refusals,
+    /// from the file's model.
+    pub async fn generate_refusals(&self, work: &FileWork, policy: &str, count: usize) -> Result<Vec<ProcessedItem>> {
+        let file = FileContext {
+            path: &work.path,
+            settings: work.settings()?,
+            title: work.title.clone(),
+            breadcrumbs: work.breadcrumbs.clone(),
+            release: None,
+            existing_questions: None,
+        };
+        let user_msg = refusals::user_prompt(policy, &work.title, count, &self.config.output.schema.describe());
+        self.chat_items(&file, refusals::SYSTEM_PROMPT, &user_msg, &[], false, count).await
+    }
 
+    /// `count` questions about the data of the SQL schema `schema`, each answered with a
+    /// query on it.
+    pub async fn generate_text_to_sql(&self, work: &FileWork, schema: &str, count: usize) -> Result<Vec<ProcessedItem>> {
+        let file = FileContext {
+            path: &work.path,
+            settings: work.settings()?,
+            title: work.title.clone(),
+            breadcrumbs: work.breadcrumbs.clone(),
+            release: None,
+            existing_questions: None,
+        };
+        let user_msg = sql_schema::text_to_sql_prompt(schema, count, &self.config.output.schema.describe());
+        self.chat_items(&file, sql_schema::TEXT_TO_SQL_SYSTEM_PROMPT, &user_msg, &[], false, count).await
+    }
+
+    /// Asks the file's model for `target` items, with `images` attached to the user
+    /// message, retrying responses that can't be parsed.
+    async fn chat_items(
+        &self,
+        file: &FileContext<'_>,
+        system_msg: &str,
+        user_msg: &str,
+        images: &[String],
+        citations: bool,
+        target: usize,
+    ) -> Result<Vec<ProcessedItem>> {
         const MAX_RETRIES: usize = 3;
         let mut retries = 0;
+        let mut failed_attempts = Vec::new();
+        let mut user = serde_json::json!({ "role": "user", "content": user_msg });
+        if !images.is_empty() {
+            user["images"] = serde_json::json!(images);
+        }
 
         while retries < MAX_RETRIES {
-            // Use different prompts based on content type
-            let (system_msg, user_msg) = if section.contains("# Release Notes") || section.contains("# Changelog") {
-                (
-                    "You are a helpful assistant that generates questions and answers about software release notes. \
-                     Format your response as JSON. Keep answers concise and factual. \
-                     Focus on the specific changes and improvements in this version.",
-                    format!("{}\nContent: {}", prompt_text, section)
-                )
-            } else {
-                (
-                    "You are a helpful assistant that generates questions and answers about technical documentation. \
-                     Format your response as JSON. Keep answers concise and factual. \
-                     Focus on the technical details and functionality being described.",
-                    format!("{}\nContent: {}", prompt_text, section)
-                )
-            };
-
-            println!("Requesting {} questions from Ollama...", generation_target);
-            let response = self.client
-                .post(&format!("{}/api/chat", self.endpoint))
-                .json(&serde_json::json!({
-                    "model": "m/qwen2514bmax",
+            debug!("Requesting {} questions from Ollama...", target);
+            self.metrics.requests.inc();
+            let timer = self.metrics.request_duration.start_timer();
+            let request_span = info_span!(
+                "request",
+                model = %file.settings.model,
+                attempt = retries + 1,
+                prompt_tokens = field::Empty,
+                completion_tokens = field::Empty,
+            );
+            let response = self
+                .post_chat(&serde_json::json!({
+                    "model": file.settings.model,
                     "messages": [
                         {
                             "role": "system",
                             "content": system_msg
                         },
-                        {
-                            "role": "user",
-                            "content": user_msg
-                        }
+                        user
                     ],
                     "stream": false, 
-                    "format": {
-                        "type": "object", 
-                        "required": ["questions"],
-                        "properties": {
-                            "questions": {
-                                "type": "array",
-                                "items": {
-                                    "type": "object",
-                                    "required": ["question", "answer"],
-                                    "properties": {
-                                        "question": {
-                                            "type": "string"
-                                        },
-                                        "answer": {
-                                            "type": "string"
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+                    "format": self.response_format(citations),
+                    "options": self.model_options(retries)
                 }))
-                .send()
-                .await?;
+                .instrument(request_span.clone())
+                .await
+                .inspect_err(|_| self.metrics.request_failures.inc())?;
+            timer.observe_duration();
 
             // Check response status first
             if !response.status().is_success() {
+                self.metrics.request_failures.inc();
+                let status = response.status();
                 let error_text = response.text().await?;
-                println!("Ollama API error: {}", error_text);
-                return Err(anyhow!("Ollama API error: {}", error_text));
+                error!("Ollama API error ({}): {}", status, error_text);
+                let message = format!("Ollama returned {}: {}", status, error_text);
+                return Err(match status {
+                    StatusCode::TOO_MANY_REQUESTS => Error::Quota(message),
+                    _ => Error::Api(message),
+                }
+                .into());
             }
 
-            let response_text = response.text().await?;
-            println!("Received response from Ollama");
-            
-            // Parse the chat response to get the message content
-            #[derive(Debug, Deserialize)]
-            struct ChatMessage {
-                content: String,
-            }
-            
-            #[derive(Debug, Deserialize)]
-            struct ChatResponse {
-                message: ChatMessage,
-            }
+            let response_text = response.text().instrument(request_span.clone()).await?;
+            debug!("Received response from Ollama");
 
+            // Parse the chat response to get the message content
             match serde_json::from_str::<ChatResponse>(&response_text) {
                 Ok(chat_response) => {
+                    self.usage.record(file.path, &file.settings.model, chat_response.prompt_eval_count, chat_response.eval_count);
+                    request_span.record("prompt_tokens", chat_response.prompt_eval_count);
+                    request_span.record("completion_tokens", chat_response.eval_count);
 
                     // Now parse the actual content as our question-answer JSON
                     let sanitized = Self::sanitize_json(&chat_response.message.content);
 
                     match serde_json::from_str::<QuestionResponse>(&sanitized) {
                         Ok(parsed) => {
-                            println!("Received {} questions (requested {})", parsed.questions.len(), generation_target);
+                            debug!("Received {} questions (requested {})", parsed.questions.len(), target);
                             return Ok(parsed.questions);
                         }
                         Err(e) => {
-                            println!("Failed to parse as JSON (attempt {}/{}): {}", retries + 1, MAX_RETRIES, e);
-                            println!("Raw response: {}", response_text);
-                            println!("Sanitized response: {}", sanitized);
+                            self.metrics.parse_failures.inc();
+                            warn!("Failed to parse as JSON (attempt {}/{}): {}", retries + 1, MAX_RETRIES, e);
+                            debug!("Raw response: {}", response_text);
+                            debug!("Sanitized response: {}", sanitized);
+                            failed_attempts.push(FailedAttempt {
+                                attempt: retries + 1,
+                                error: e.to_string(),
+                                raw_response: response_text,
+                                sanitized_response: Some(sanitized),
+                            });
                             retries += 1;
+                            Span::current().record("retries", retries);
                             if retries == MAX_RETRIES {
-                                return Err(anyhow!("Failed to parse Ollama response after {} attempts", MAX_RETRIES));
+                                self.archive_failure(file.path, &file.settings.model, system_msg, user_msg, &failed_attempts);
+                                return Err(Error::Parse(format!("Failed to parse Ollama response after {} attempts", MAX_RETRIES)).into());
                             }
                             // Short delay before retry
                             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
@@ -411,11 +1115,20 @@ impl OllamaProcessor {
                     }
                 }
                 Err(e) => {
-                    println!("Failed to parse chat response (attempt {}/{}): {}", retries + 1, MAX_RETRIES, e);
-                    println!("Raw response: {}", response_text);
+                    self.metrics.parse_failures.inc();
+                    warn!("Failed to parse chat response (attempt {}/{}): {}", retries + 1, MAX_RETRIES, e);
+                    debug!("Raw response: {}", response_text);
+                    failed_attempts.push(FailedAttempt {
+                        attempt: retries + 1,
+                        error: e.to_string(),
+                        raw_response: response_text,
+                        sanitized_response: None,
+                    });
                     retries += 1;
+                    Span::current().record("retries", retries);
                     if retries == MAX_RETRIES {
-                        return Err(anyhow!("Failed to parse chat response after {} attempts", MAX_RETRIES));
+                        self.archive_failure(file.path, &file.settings.model, system_msg, user_msg, &failed_attempts);
+                        return Err(Error::Parse(format!("Failed to parse chat response after {} attempts", MAX_RETRIES)).into());
                     }
                     // Short delay before retry
                     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
@@ -423,156 +1136,100 @@ impl OllamaProcessor {
             }
         }
 
-        Err(anyhow!("Failed to process section after {} attempts", MAX_RETRIES))
+        Err(Error::Parse(format!("Failed to process section after {} attempts", MAX_RETRIES)).into())
     }
 
-    fn get_qa_path(&self, file_path: &Path, extension: &str) -> PathBuf {
-        let file_stem = file_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown");
-        file_path
-            .parent()
-            .unwrap_or(Path::new("."))
-            .join(format!("{}_qa.{}", file_stem, extension))
+    /// Drops citations that don't appear in the section they were generated from, which
+    /// gets the item rejected by the filter stage.
+    fn verify_citations(items: &mut [ProcessedItem], section: &str) {
+        for item in items {
+            if let Some(citation) = &item.citation {
+                if !filter::quote_appears_in(citation, section) {
+                    debug!("Citation not found in section: {:?}", citation);
+                    item.citation = None;
+                }
+            }
+        }
     }
 
-    fn convert_json_to_jsonl(&self, json_path: &Path, jsonl_path: &Path) -> Result<Vec<ProcessedItem>> {
-        println!("Converting {:?} to JSONL format at {:?}", json_path, jsonl_path);
-        let content = fs::read_to_string(json_path)?;
-        let items: Vec<ProcessedItem> = serde_json::from_str(&content)?;
-        
-        let mut output = String::new();
-        for item in &items {
-            if let Ok(json_line) = serde_json::to_string(item) {
-                output.push_str(&json_line);
-                output.push('\n');
+    /// Records the release items were generated from as `version`, `release_date` and
+    /// `change_type`.
+    fn tag_release(items: &mut [ProcessedItem], release: &Release) {
+        for item in items {
+            item.extra.insert("version".to_string(), release.version.clone().into());
+            if let Some(date) = &release.date {
+                item.extra.insert("release_date".to_string(), date.clone().into());
+            }
+            if let Some(change_type) = &release.change_type {
+                item.extra.insert("change_type".to_string(), change_type.clone().into());
             }
         }
-        fs::write(jsonl_path, output)?;
-        Ok(items)
     }
 
-    fn check_existing_qa(&self, file_path: &Path, _required_questions: usize) -> Result<Option<Vec<ProcessedItem>>> {
-        // First check for JSONL file
-        let jsonl_path = self.get_qa_path(file_path, "jsonl");
-        
-        if jsonl_path.exists() {
-            println!("Found existing JSONL file: {:?}", jsonl_path);
-            if let Ok(content) = fs::read_to_string(&jsonl_path) {
-                let mut items = Vec::new();
-                for line in content.lines() {
-                    if let Ok(item) = serde_json::from_str::<ProcessedItem>(line) {
-                        items.push(item);
-                    }
-                }
-                if !items.is_empty() {
-                    let content = fs::read_to_string(file_path)?;
-                    let word_count = Self::count_words(&content);
-                    let (_, _, min_acceptable) = Self::calculate_question_targets(word_count);
-                    
-                    if items.len() >= min_acceptable {
-                        println!("Found existing JSONL file with {} questions (minimum acceptable: {}), skipping...", 
-                            items.len(), min_acceptable);
-                        return Ok(Some(items));
-                    } else {
-                        println!("Found existing JSONL file but only has {} questions (minimum needed: {}), regenerating with extra buffer...", 
-                            items.len(), min_acceptable);
-                    }
-                } else {
-                    println!("No valid items found in existing JSONL file");
-                }
-            }
-        } else {
-            // Check for JSON file if JSONL doesn't exist
-            let json_path = self.get_qa_path(file_path, "json");
-            if json_path.exists() {
-                println!("Found existing JSON file: {:?}", json_path);
-                if let Ok(content) = fs::read_to_string(&json_path) {
-                    if let Ok(items) = serde_json::from_str::<Vec<ProcessedItem>>(&content) {
-                        let content = fs::read_to_string(file_path)?;
-                        let word_count = Self::count_words(&content);
-                        let (_, _, min_acceptable) = Self::calculate_question_targets(word_count);
-                        
-                        if items.len() >= min_acceptable {
-                            println!("Found existing JSON file with {} questions (minimum acceptable: {}), converting to JSONL...", 
-                                items.len(), min_acceptable);
-                            // Convert to JSONL format
-                            match self.convert_json_to_jsonl(&json_path, &jsonl_path) {
-                                Ok(items) => {
-                                    println!("Successfully converted to JSONL format");
-                                    return Ok(Some(items));
-                                }
-                                Err(e) => {
-                                    println!("Failed to convert to JSONL format: {}", e);
-                                }
-                            }
-                        } else {
-                            println!("Found existing JSON file but only has {} questions (minimum needed: {}), regenerating with extra buffer...", 
-                                items.len(), min_acceptable);
-                        }
-                    }
-                }
-            } else {
-                println!("No existing QA file found");
+    /// Extra fields the prompt asks for: those from `[output.schema]` and the citation.
+    fn extra_fields(&self) -> String {
+        let mut fields = self.config.output.schema.describe();
+        if self.config.output.citations {
+            if !fields.is_empty() {
+                fields.push_str(", ");
             }
+            fields.push_str(CITATION_FIELD);
         }
-        Ok(None)
+        fields
     }
 
-    pub async fn process_file(&self, file_path: &Path) -> Result<Vec<ProcessedItem>> {
-        // Read the file content
-        let content = fs::read_to_string(file_path)?;
-        
-        // Count total words to determine total questions needed
-        let total_words = Self::count_words(&content);
-        let (_, total_questions_needed, _) = Self::calculate_question_targets(total_words);
+    /// Ollama model options. With a seed, each retry gets its own derived seed so a
+    /// failed attempt isn't reproduced verbatim.
+    fn model_options(&self, retry: usize) -> serde_json::Value {
+        match self.config.seed {
+            Some(seed) => serde_json::json!({ "seed": seed.wrapping_add(retry as u64) }),
+            None => serde_json::json!({}),
+        }
+    }
+
+    /// JSON schema passed as Ollama's `format`: a `questions` array whose items have
+    /// `question`, `answer`, any extra fields from `[output.schema]` and, with
+    /// `citations`, the citation.
+    fn response_format(&self, citations: bool) -> serde_json::Value {
+        let schema = &self.config.output.schema;
 
-        // Check if we already have enough questions
-        if let Some(existing_items) = self.check_existing_qa(file_path, total_questions_needed)? {
-            return Ok(existing_items);
+        let mut properties = serde_json::Map::new();
+        properties.insert("question".to_string(), serde_json::json!({ "type": "string" }));
+        properties.insert("answer".to_string(), serde_json::json!({ "type": "string" }));
+        for (name, field) in &schema.fields {
+            properties.insert(name.clone(), field.clone());
         }
 
-        let mut all_items = Vec::new();
-        
-        // Process each section
-        let sections = self.split_into_sections(&content);
-        for (i, section) in sections.iter().enumerate() {
-            if section.trim().is_empty() {
-                continue;
-            }
-            
-            // Calculate target questions for this section based on its proportion of total words
-            let section_words = Self::count_words(section);
-            let section_target = (total_questions_needed as f64 * 
-                (section_words as f64 / total_words as f64)).ceil() as usize;
-            
-            println!("\nProcessing section {}/{} ({} words, target {} questions)", 
-                i + 1, sections.len(), section_words, section_target);
-            
-            match self.process_section_recursive(section, file_path, section_target).await {
-                Ok(questions) => {
-                    all_items.extend(questions);
-                    println!("Total questions so far: {}/{}", all_items.len(), total_questions_needed);
-                }
-                Err(e) => {
-                    println!("Error processing section: {}", e);
+        let mut required = vec!["question".to_string(), "answer".to_string()];
+        required.extend(schema.required.iter().cloned());
+        if citations {
+            properties.insert("citation".to_string(), serde_json::json!({ "type": "string" }));
+            required.push("citation".to_string());
+        }
+
+        serde_json::json!({
+            "type": "object",
+            "required": ["questions"],
+            "properties": {
+                "questions": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": required,
+                        "properties": properties
+                    }
                 }
             }
-        }
+        })
+    }
 
-        // Save the results
-        if !all_items.is_empty() {
-            let qa_path = self.get_qa_path(file_path, "jsonl");
-            println!("Saving {} questions to {:?}", all_items.len(), qa_path);
-            
-            let mut file = fs::File::create(&qa_path)?;
-            for item in &all_items {
-                writeln!(file, "{}", serde_json::to_string(item)?)?;
+    fn archive_failure(&self, file_path: &Path, model: &str, system_msg: &str, user_msg: &str, attempts: &[FailedAttempt]) {
+        if let Some(archive) = &self.failures {
+            match archive.record(file_path, model, system_msg, user_msg, attempts) {
+                Ok(path) => warn!("Saved unparseable responses to {:?}", path),
+                Err(e) => warn!("Failed to archive unparseable responses: {}", e),
             }
         }
-
-        Ok(all_items)
     }
 }
 
@@ -580,3 +1237,159 @@ impl OllamaProcessor {
 struct QuestionResponse {
     questions: Vec<ProcessedItem>,
 }
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    message: ChatMessage,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use serde_json::{Value, json};
+
+    /// Three paragraphs under one heading, without headings to split them by.
+    const PETS: &str = "## Pets\n\nFeed the zebra daily.\n\n\nBrush the quokka weekly.\n\n\nBathe the axolotl monthly.\n";
+
+    /// An Ollama endpoint asking about every pet the section mentions, or once about all
+    /// of them when it mentions them all.
+    async fn ollama() -> String {
+        let chat = |Json(request): Json<Value>| async move {
+            let section = request["messages"][1]["content"].as_str().unwrap_or("");
+            let pets: Vec<&str> = ["zebra", "quokka", "axolotl"].into_iter().filter(|pet| section.contains(pet)).collect();
+            let questions: Vec<Value> = match pets.len() {
+                3 => vec![json!({ "question": "How are the pets cared for?", "answer": "Each on its own schedule." })],
+                _ => pets
+                    .iter()
+                    .map(|pet| json!({ "question": format!("How is the {} cared for?", pet), "answer": "On schedule." }))
+                    .collect(),
+            };
+            let content = json!({ "questions": questions }).to_string();
+            Json(json!({ "message": { "role": "assistant", "content": content }, "prompt_eval_count": 10, "eval_count": 5 }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, Router::new().route("/api/chat", post(chat))).await });
+        endpoint
+    }
+
+    /// `pets.md` with its settings, and a processor asking the mock endpoint.
+    async fn pets() -> (FileWork, OllamaProcessor) {
+        let config = Config {
+            endpoint: ollama().await,
+            ..Config::default()
+        };
+        let mut work = FileWork::new(Path::new("pets.md"), Path::new(""));
+        work.settings = Some(config.settings_for(&work.path, "").unwrap());
+        work.title = "Pets".to_string();
+        (work, OllamaProcessor::new(Arc::new(config), Arc::new(Metrics::new())))
+    }
+
+    fn file_context(work: &FileWork) -> FileContext<'_> {
+        FileContext {
+            path: &work.path,
+            settings: work.settings().unwrap(),
+            title: work.title.clone(),
+            breadcrumbs: Vec::new(),
+            release: None,
+            existing_questions: None,
+        }
+    }
+
+    fn item(question: &str, answer: &str) -> ProcessedItem {
+        serde_json::from_value(json!({ "question": question, "answer": answer })).unwrap()
+    }
+
+    fn questions(items: &[ProcessedItem]) -> Vec<&str> {
+        items.iter().map(|item| item.question.as_str()).collect()
+    }
+
+    #[test]
+    fn unique_items_skip_repeated_questions() {
+        let mut unique = UniqueItems::default();
+        assert_eq!(unique.add(vec![item("How do I start it?", "Run it."), item("How do I stop it?", "Stop it.")]), 2);
+        assert_eq!(unique.add(vec![item("how do I start it", "Start it."), item("Where are the logs?", "In /var/log.")]), 1);
+        assert_eq!(unique.len(), 3);
+        let items = unique.into_items();
+        assert_eq!(questions(&items), ["How do I start it?", "How do I stop it?", "Where are the logs?"]);
+        assert_eq!(items[0].answer, "Run it.");
+    }
+
+    #[test]
+    fn parts_get_their_share_of_the_target() {
+        let chunking = ChunkingConfig { min_words: 2, ..ChunkingConfig::default() };
+        let parts = vec!["one two three four five six".to_string(), "seven eight".to_string(), "nine".to_string()];
+        let section = "one two three four five six seven eight nine";
+        assert_eq!(
+            OllamaProcessor::with_targets(parts, section, 6, &chunking),
+            [("one two three four five six".to_string(), 4), ("seven eight".to_string(), 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn union_keeps_the_questions_of_every_pass() {
+        let (work, processor) = pets().await;
+        let file = file_context(&work);
+        let mut unique = UniqueItems::default();
+        unique.add(processor.process_section(PETS, &file, 4).await.unwrap());
+        let items = processor.fallback_union(PETS, &file, 4, unique).await;
+        assert_eq!(
+            questions(&items),
+            ["How are the pets cared for?", "How is the zebra cared for?", "How is the quokka cared for?", "How is the axolotl cared for?"]
+        );
+    }
+
+    #[tokio::test]
+    async fn union_stops_at_the_target() {
+        let (work, processor) = pets().await;
+        let file = file_context(&work);
+        let mut unique = UniqueItems::default();
+        unique.add(processor.process_section(PETS, &file, 2).await.unwrap());
+        let items = processor.fallback_union(PETS, &file, 2, unique).await;
+        assert_eq!(questions(&items), ["How are the pets cared for?", "How is the zebra cared for?"]);
+    }
+
+    #[tokio::test]
+    async fn best_keeps_the_pass_with_most_questions() {
+        let (work, processor) = pets().await;
+        let file = file_context(&work);
+        let mut best = UniqueItems::default();
+        best.add(processor.process_section(PETS, &file, 4).await.unwrap());
+        let items = processor.fallback_best(PETS, &file, 4, best).await;
+        assert_eq!(questions(&items), ["How is the zebra cared for?", "How is the quokka cared for?", "How is the axolotl cared for?"]);
+    }
+
+    #[tokio::test]
+    async fn generating_falls_back_on_the_configured_strategy() {
+        let (mut work, processor) = pets().await;
+        let section = Section { index: 0, text: PETS.to_string(), target: 4, release: None };
+        assert_eq!(processor.generate_section(&work, &section).await.unwrap().len(), 4);
+
+        work.settings.as_mut().unwrap().chunking.fallback_strategy = FallbackStrategy::Best;
+        assert_eq!(processor.generate_section(&work, &section).await.unwrap().len(), 3);
+        work.settings.as_mut().unwrap().chunking.recursive_fallback = false;
+        assert_eq!(processor.generate_section(&work, &section).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn chat_replies_count_towards_the_usage_of_their_file() {
+        let (work, processor) = pets().await;
+        let messages = json!([{ "role": "user", "content": "Transcribe this page." }]);
+        processor.chat(&work.path, "llava", messages.clone(), None, json!({})).await.unwrap();
+        processor.chat(&work.path, "llava", messages, None, json!({})).await.unwrap();
+
+        let usage = processor.usage().file_usage(&work.path);
+        assert_eq!((usage.requests, usage.prompt_tokens, usage.completion_tokens), (2, 20, 10));
+        assert_eq!(processor.usage().total().total_tokens(), 30);
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Live view of a run: a files bar with ETA, a sections bar for the current file, and
+/// running counts of generated items and failures. Bars stay hidden until `start` is
+/// called, and are never drawn in plain mode, which keeps CI logs readable.
+pub struct Progress {
+    enabled: bool,
+    multi: MultiProgress,
+    files: ProgressBar,
+    sections: ProgressBar,
+    items: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new(plain: bool) -> Self {
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+
+        let files = multi.add(ProgressBar::new(0));
+        files.set_style(
+            ProgressStyle::with_template("{prefix:>8} [{bar:30.cyan/blue}] {pos}/{len} files (ETA {eta}) {msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        files.set_prefix("Files");
+
+        let sections = multi.add(ProgressBar::new(0));
+        sections.set_style(
+            ProgressStyle::with_template("{prefix:>8} [{bar:30.green/white}] {pos}/{len} sections {wide_msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        sections.set_prefix("Sections");
+
+        Self {
+            enabled: !plain,
+            multi,
+            files,
+            sections,
+            items: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts drawing the bars; called once interactive source collection is done.
+    pub fn start(&self) {
+        if self.enabled {
+            self.multi.set_draw_target(ProgressDrawTarget::stderr());
+        }
+    }
+
+    /// Log writer that temporarily clears the bars so log lines don't tear them.
+    pub fn writer(&self) -> ProgressWriter {
+        ProgressWriter {
+            multi: self.multi.clone(),
+        }
+    }
+
+    pub fn add_files(&self, count: usize) {
+        self.files.inc_length(count as u64);
+    }
+
+    pub fn start_file(&self, name: &str) {
+        self.sections.set_position(0);
+        self.sections.set_length(0);
+        self.sections.set_message(name.to_string());
+    }
+
+    pub fn finish_file(&self) {
+        self.files.inc(1);
+    }
+
+    pub fn set_sections(&self, count: usize) {
+        self.sections.set_length(count as u64);
+    }
+
+    pub fn finish_section(&self) {
+        self.sections.inc(1);
+    }
+
+    pub fn add_items(&self, count: usize) {
+        self.items.fetch_add(count, Ordering::Relaxed);
+        self.update_counts();
+    }
+
+    pub fn add_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        self.update_counts();
+    }
+
+    pub fn items(&self) -> usize {
+        self.items.load(Ordering::Relaxed)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn update_counts(&self) {
+        self.files.set_message(format!("| {} items, {} failures", self.items(), self.failures()));
+    }
+
+    pub fn finish(&self) {
+        self.sections.finish_and_clear();
+        self.files.finish();
+    }
+}
+
+#[derive(Clone)]
+pub struct ProgressWriter {
+    multi: MultiProgress,
+}
+
+impl Write for ProgressWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.multi.suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for ProgressWriter {
+    type Writer = ProgressWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
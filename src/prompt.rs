@@ -0,0 +1,73 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use anyhow::Result;
+use minijinja::Environment;
+use serde::Serialize;
+use crate::markdown::{self, BlockKind};
+
+/// Variables available to prompt templates.
+#[derive(Debug, Serialize)]
+pub struct PromptVars<'a> {
+    pub target_count: usize,
+    pub section: &'a str,
+    pub doc_title: &'a str,
+    /// Categories leading to the page in its docs site's navigation, outermost first.
+    pub breadcrumbs: &'a [String],
+    pub file_name: &'a str,
+    pub product_name: Option<&'a str>,
+    /// Extra output fields from `[output.schema]`, described for the model; empty if none.
+    pub extra_fields: &'a str,
+    pub keywords: &'a [String],
+    pub exclude_topics: &'a [String],
+    /// Release a section of release notes belongs to, from its headings or file name.
+    pub version: Option<&'a str>,
+    pub release_date: Option<&'a str>,
+    /// Type of change (`Added`, `Fixed`, ...) of a single Keep a Changelog entry.
+    pub change_type: Option<&'a str>,
+    /// Whether answers must be paraphrased, with `filters.max_source_overlap`.
+    pub paraphrase: bool,
+    /// How long answers should be, from `answer_length.preset`.
+    pub answer_length: Option<&'a str>,
+    /// The audience answers are written for, from `reading_level.level`.
+    pub reading_level: Option<&'a str>,
+    /// Replies answers should read like, from `[style]`.
+    pub style_examples: &'a [&'a str],
+    /// Questions the section already has, when a short QA file is being topped up.
+    pub existing_questions: &'a [String],
+    /// Lines of the section that mark features as deprecated or removed, with
+    /// `deprecations.mode = "frame"`.
+    pub deprecations: &'a [String],
+    /// For a section that is mostly a list, whether it is numbered, and its items.
+    pub ordered_list: bool,
+    pub list_items: usize,
+}
+
+pub fn render(template: &str, vars: &PromptVars) -> Result<String> {
+    Ok(Environment::new().render_str(template, vars)?)
+}
+
+/// The `count` of `references` sharing the most words with `section`, to show as
+/// examples of how answers should read.
+pub fn style_examples<'a>(references: &'a [String], section: &str, count: usize) -> Vec<&'a str> {
+    let words = |text: &str| -> HashSet<String> {
+        text.split(|c: char| !c.is_alphanumeric()).filter(|word| word.len() > 3).map(str::to_lowercase).collect()
+    };
+    let section = words(section);
+    let mut scored: Vec<(usize, &str)> = references
+        .iter()
+        .map(|reference| (words(reference).intersection(&section).count(), reference.as_str()))
+        .collect();
+    // Stable, so ties keep the order of the references
+    scored.sort_by_key(|(shared, _)| Reverse(*shared));
+    scored.into_iter().take(count).map(|(_, reference)| reference).collect()
+}
+
+/// Title of a document: its first level 1 heading, or `fallback` when it has none.
+pub fn doc_title(content: &str, fallback: &str) -> String {
+    markdown::headings(content)
+        .find_map(|heading| match heading.kind {
+            BlockKind::Heading { level: 1, title } => Some(title),
+            _ => None,
+        })
+        .unwrap_or_else(|| fallback.to_string())
+}
@@ -0,0 +1,385 @@
+use std::ops::Range;
+use std::sync::LazyLock;
+use regex::Regex;
+use crate::html::{self, attribute, has_attribute, TAG};
+use crate::strip::VOID_ELEMENTS;
+
+/// Elements left out of the main content with everything in them.
+const DROPPED_TAGS: &[&str] = &["head", "script", "style", "noscript", "template", "nav", "footer", "aside", "form", "iframe", "svg", "canvas", "object", "button", "select", "dialog"];
+
+/// Elements whose text is read as it is, without looking for tags in it.
+const RAW_TEXT_TAGS: &[&str] = &["script", "style", "textarea"];
+
+/// Elements closed by the next one of their kind when their closing tag is left out.
+const IMPLIED_END_TAGS: &[&str] = &["p", "li", "dt", "dd", "tr", "td", "th", "option"];
+
+/// Elements a `div` holding none of is scored as a paragraph.
+const BLOCK_TAGS: &[&str] = &["blockquote", "dl", "div", "img", "ol", "p", "pre", "table", "ul"];
+
+/// Elements removed from the main content when they are mostly links.
+const CLEANED_TAGS: &[&str] = &["div", "section", "ul", "ol", "dl", "table"];
+
+/// Shortest text, in characters, of a paragraph that counts towards its ancestors.
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+
+/// Ancestors of a paragraph that share its score.
+const SCORED_ANCESTORS: usize = 5;
+
+/// Classes and ids of navigation, comments, sidebars and other page furniture.
+static UNLIKELY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)-ad-|ai2html|banner|breadcrumb|combx|comment|community|cover-wrap|disqus|extra|footer|gdpr|header|legends|menu|related|remark|replies|rss|shoutbox|sidebar|skyscraper|social|sponsor|supplemental|ad-break|agegate|pagination|pager|popup|yom-remote|navbar|\btoc\b|table-of-contents|cookie|share").unwrap()
+});
+
+/// Classes and ids that keep an element matching `UNLIKELY` in the running.
+static MAYBE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)and|article|body|column|content|main|shadow").unwrap());
+
+static POSITIVE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)article|body|content|entry|hentry|h-entry|main|page|post|text|blog|story|markdown|prose").unwrap());
+
+static NEGATIVE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)-ad-|hidden|^hid$| hid$| hid |^hid |banner|combx|comment|com-|contact|foot|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget|breadcrumb|menu|\bnav|\btoc\b").unwrap()
+});
+
+/// ARIA roles of navigation and other landmarks that aren't the content.
+static DROPPED_ROLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^(navigation|menu|menubar|complementary|banner|contentinfo|search|dialog|alertdialog)$").unwrap());
+
+/// The closing tag of a raw text element.
+static RAW_TEXT_END: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)</(script|style|textarea)\s*>").unwrap());
+
+/// A title's site name, after the last ` | `, ` - ` or similar separator.
+static TITLE_SEPARATOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+[|\-–—·»:]{1,2}\s+").unwrap());
+
+struct Element {
+    name: String,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// From the start of the opening tag to the end of the closing one.
+    range: Range<usize>,
+    /// From the class, id and tag: positive for content, negative for page furniture.
+    weight: f64,
+    /// Left out with everything in it, or inside such an element.
+    dropped: bool,
+    /// Characters of text in it, of them in links, and commas, counting those of its
+    /// children once they are added up.
+    text: usize,
+    links: usize,
+    commas: usize,
+    has_pre: bool,
+    score: Option<f64>,
+}
+
+impl Element {
+    fn link_density(&self) -> f64 {
+        match self.text {
+            0 => 0.0,
+            text => self.links as f64 / text as f64,
+        }
+    }
+
+    /// The score an element starts with when a paragraph in it is first scored.
+    fn initial_score(&self) -> f64 {
+        let score = match self.name.as_str() {
+            "div" | "article" | "main" => 5.0,
+            "pre" | "td" | "blockquote" => 3.0,
+            "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" => -3.0,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+            _ => 0.0,
+        };
+        score + self.weight
+    }
+}
+
+/// The weight of an element from its tag, class and id.
+fn weight(name: &str, attributes: &str) -> f64 {
+    let mut weight = match name {
+        "article" | "main" => 25.0,
+        _ => 0.0,
+    };
+    for value in [attribute(attributes, "class"), attribute(attributes, "id")].into_iter().flatten() {
+        if NEGATIVE.is_match(&value) {
+            weight -= 25.0;
+        }
+        if POSITIVE.is_match(&value) {
+            weight += 25.0;
+        }
+    }
+    weight
+}
+
+/// Whether an element is page furniture or hidden, and left out with what it holds.
+fn drops(name: &str, attributes: &str) -> bool {
+    if DROPPED_TAGS.contains(&name) || attribute(attributes, "role").is_some_and(|role| DROPPED_ROLE.is_match(role.trim())) {
+        return true;
+    }
+    let hidden = has_attribute(attributes, "hidden")
+        || attribute(attributes, "aria-hidden").is_some_and(|value| value == "true")
+        || attribute(attributes, "style").is_some_and(|style| style.replace(' ', "").to_lowercase().contains("display:none"));
+    let names = format!("{} {}", attribute(attributes, "class").unwrap_or_default(), attribute(attributes, "id").unwrap_or_default());
+    hidden || (!matches!(name, "html" | "body" | "article" | "main" | "a") && UNLIKELY.is_match(&names) && !MAYBE.is_match(&names))
+}
+
+/// The elements of `html`, in document order, with the text in each counted.
+fn parse(html: &str) -> Vec<Element> {
+    let mut elements: Vec<Element> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    let mut text_start = 0;
+    let mut at = 0;
+    while let Some(captures) = TAG.captures_at(html, at) {
+        let whole = captures.get(0).unwrap();
+        add_text(&mut elements, &open, &html[text_start..whole.start()]);
+        at = whole.end();
+        text_start = whole.end();
+        // Comments and the doctype
+        let Some(name) = captures.get(2).filter(|_| !whole.as_str().starts_with("<!")) else {
+            continue;
+        };
+        let name = name.as_str().to_lowercase();
+        if !captures[1].is_empty() {
+            if let Some(position) = open.iter().rposition(|&index| elements[index].name == name) {
+                // Elements still open inside it end where it does
+                for &index in &open[position + 1..] {
+                    elements[index].range.end = whole.start();
+                }
+                elements[open[position]].range.end = whole.end();
+                open.truncate(position);
+            }
+            continue;
+        }
+
+        if let Some(&top) = open.last().filter(|&&top| elements[top].name == name && IMPLIED_END_TAGS.contains(&name.as_str())) {
+            elements[top].range.end = whole.start();
+            open.pop();
+        }
+        let attributes = &captures[3];
+        let parent = open.last().copied();
+        let index = elements.len();
+        elements.push(Element {
+            dropped: parent.is_some_and(|parent| elements[parent].dropped) || drops(&name, attributes),
+            weight: weight(&name, attributes),
+            has_pre: name == "pre",
+            name,
+            parent,
+            children: Vec::new(),
+            range: whole.start()..html.len(),
+            text: 0,
+            links: 0,
+            commas: 0,
+            score: None,
+        });
+        if let Some(parent) = parent {
+            elements[parent].children.push(index);
+        }
+        let name = elements[index].name.as_str();
+        if RAW_TEXT_TAGS.contains(&name) {
+            let end = RAW_TEXT_END
+                .captures_iter(&html[at..])
+                .find(|captures| captures[1].eq_ignore_ascii_case(name))
+                .map_or(html.len(), |captures| at + captures.get(0).unwrap().end());
+            elements[index].range.end = end;
+            at = end;
+            text_start = end;
+        } else if !VOID_ELEMENTS.contains(&name) && !attributes.trim_end().ends_with('/') {
+            open.push(index);
+        }
+    }
+    add_text(&mut elements, &open, &html[text_start..]);
+
+    // Children's counts go to their parents, except for those left out
+    for index in (0..elements.len()).rev() {
+        let element = &elements[index];
+        let (Some(parent), false) = (element.parent, element.dropped) else {
+            continue;
+        };
+        let (text, links, commas, has_pre) = (element.text, element.links, element.commas, element.has_pre);
+        let parent = &mut elements[parent];
+        parent.text += text;
+        parent.links += links;
+        parent.commas += commas;
+        parent.has_pre |= has_pre;
+    }
+    elements
+}
+
+/// Counts `text` towards the innermost open element, and as link text inside a link.
+fn add_text(elements: &mut [Element], open: &[usize], text: &str) {
+    let Some(&owner) = open.last() else {
+        return;
+    };
+    let length: usize = text.split_whitespace().map(|word| word.chars().count() + 1).sum();
+    if length == 0 {
+        return;
+    }
+    let in_link = open.iter().any(|&index| elements[index].name == "a");
+    let element = &mut elements[owner];
+    element.text += length;
+    element.commas += text.matches([',', '，']).count();
+    if in_link {
+        element.links += length;
+    }
+}
+
+/// Scores the ancestors of every paragraph by the paragraph's length and commas, like
+/// Readability: a paragraph's parent gets its whole score, its grandparent half, and
+/// older ancestors less.
+fn score(elements: &mut [Element]) {
+    for index in 0..elements.len() {
+        let element = &elements[index];
+        let paragraph = match element.name.as_str() {
+            "p" | "pre" | "td" => true,
+            "div" => element.children.iter().all(|&child| !BLOCK_TAGS.contains(&elements[child].name.as_str())),
+            _ => false,
+        };
+        if element.dropped || !paragraph || element.text < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        let score = 1.0 + element.commas as f64 + (element.text / 100).min(3) as f64;
+        let mut ancestor = element.parent;
+        for level in 0..SCORED_ANCESTORS {
+            let Some(index) = ancestor else {
+                break;
+            };
+            let divider = match level {
+                0 => 1.0,
+                1 => 2.0,
+                _ => level as f64 * 3.0,
+            };
+            let element = &mut elements[index];
+            let initial = element.initial_score();
+            *element.score.get_or_insert(initial) += score / divider;
+            ancestor = element.parent;
+        }
+    }
+}
+
+/// Whether an element in the main content is a block of links or page furniture to
+/// remove; code is always kept.
+fn cleaned(element: &Element) -> bool {
+    CLEANED_TAGS.contains(&element.name.as_str()) && !element.has_pre && (element.weight + element.score.unwrap_or(0.0) < 0.0 || element.link_density() > 0.5)
+}
+
+/// Ranges of the elements under `index` left out of the main content, in order.
+fn removed(elements: &[Element], index: usize, ranges: &mut Vec<Range<usize>>) {
+    for &child in &elements[index].children {
+        let element = &elements[child];
+        match element.dropped || cleaned(element) {
+            true => ranges.push(element.range.clone()),
+            false => removed(elements, child, ranges),
+        }
+    }
+}
+
+/// The text of the first element named `name` that isn't left out, without its markup.
+fn text_of(html: &str, elements: &[Element], name: &str) -> Option<String> {
+    let element = elements.iter().find(|element| element.name == name && (name == "title" || !element.dropped))?;
+    let text = html::to_markdown(&html[element.range.clone()]);
+    let text = text.trim_start_matches('#').trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// The main content of the web page `html`, as HTML: the element holding the most
+/// paragraph text, scored the way Mozilla's Readability scores it, with the siblings
+/// that score close to it, and without navigation, sidebars, footers, hidden elements
+/// and blocks that are mostly links. Headed by the page's title when the content has no
+/// `<h1>` of its own. The whole page, less the same furniture, when no element stands out.
+pub fn main_content(html: &str) -> String {
+    let mut elements = parse(html);
+    score(&mut elements);
+    let top = elements
+        .iter()
+        .enumerate()
+        .filter_map(|(index, element)| element.score.map(|score| (index, score * (1.0 - element.link_density()))))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    let included: Vec<usize> = match top {
+        Some((top, top_score)) => {
+            // Siblings scoring near the top element, and paragraphs of prose next to it
+            let threshold = (top_score * 0.2).max(10.0);
+            let siblings = match elements[top].parent {
+                Some(parent) => elements[parent].children.clone(),
+                None => vec![top],
+            };
+            siblings
+                .into_iter()
+                .filter(|&index| {
+                    let element = &elements[index];
+                    let score = element.score.map(|score| score * (1.0 - element.link_density()));
+                    index == top
+                        || (!element.dropped && score.is_some_and(|score| score >= threshold))
+                        || (!element.dropped && element.name == "p" && element.text > 80 && element.link_density() < 0.25)
+                })
+                .collect()
+        }
+        None => match elements.iter().position(|element| element.name == "body") {
+            Some(body) => vec![body],
+            None => elements.iter().enumerate().filter(|(_, element)| element.parent.is_none() && !element.dropped).map(|(index, _)| index).collect(),
+        },
+    };
+
+    let mut content = String::new();
+    for &index in &included {
+        let mut ranges = Vec::new();
+        removed(&elements, index, &mut ranges);
+        let range = elements[index].range.clone();
+        let mut from = range.start;
+        for removed in ranges {
+            content.push_str(&html[from..removed.start]);
+            from = removed.end;
+        }
+        content.push_str(&html[from..range.end]);
+        content.push('\n');
+    }
+
+    if content.to_lowercase().contains("<h1") {
+        return content;
+    }
+    // The page's heading, or its title without the site's name
+    let title = text_of(html, &elements, "h1").or_else(|| {
+        let title = text_of(html, &elements, "title")?;
+        let page = TITLE_SEPARATOR.split(&title).next().unwrap_or(&title).trim().to_string();
+        Some(if page.is_empty() { title } else { page })
+    });
+    match title {
+        Some(title) => format!("<h1>{}</h1>\n{}", title.replace('&', "&amp;").replace('<', "&lt;"), content),
+        None => content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAGRAPH: &str = "Pulling a model downloads its layers once, and later pulls only fetch the layers that changed since.";
+
+    #[test]
+    fn main_content_leaves_out_page_furniture() {
+        let html = format!(
+            "<html><head><title>Pulling models | Acme Docs</title><style>p {{ color: red }}</style></head><body>\
+             <nav><a href=\"/\">Home</a> <a href=\"/docs\">Docs</a></nav>\
+             <div class=\"sidebar\"><p>{PARAGRAPH}</p></div>\
+             <div class=\"content\"><p>{PARAGRAPH}</p><p>{PARAGRAPH}</p><pre><code>acme pull llama</code></pre>\
+             <div class=\"links\"><a href=\"/a\">Previous page in the guide</a> <a href=\"/b\">Next page in the guide</a></div>\
+             <p hidden>Only shown to subscribers.</p></div>\
+             <footer>Copyright Acme</footer></body></html>"
+        );
+        let content = main_content(&html);
+        assert!(content.starts_with("<h1>Pulling models</h1>\n<div class=\"content\">"), "{content}");
+        assert_eq!(content.matches(PARAGRAPH).count(), 2);
+        assert!(content.contains("acme pull llama"));
+        for furniture in ["Home", "color: red", "Next page", "subscribers", "Copyright"] {
+            assert!(!content.contains(furniture), "{furniture} in {content}");
+        }
+    }
+
+    #[test]
+    fn main_content_keeps_its_own_heading() {
+        let html = format!("<title>Ignored</title><article><h1>Install</h1><p>{PARAGRAPH}</p></article>");
+        let content = main_content(&html);
+        assert!(content.starts_with("<article><h1>Install</h1>"), "{content}");
+        assert!(!content.contains("Ignored"));
+    }
+
+    #[test]
+    fn pages_without_prose_are_kept_whole() {
+        let content = main_content("<body><ul><li>One</li><li>Two</li></ul><nav>Menu</nav></body>");
+        assert_eq!(content, "<body><ul><li>One</li><li>Two</li></ul></body>\n");
+    }
+}
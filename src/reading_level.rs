@@ -0,0 +1,124 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Field recording the level an item's answer was written for.
+pub const FIELD: &str = "reading_level";
+
+/// Field holding the Flesch-Kincaid grade of an item's answer.
+pub const GRADE_FIELD: &str = "reading_grade";
+
+pub const REWRITE_SYSTEM_PROMPT: &str = "You rewrite answers in a question and answer dataset for a given audience. \
+    Keep every fact of the answer and don't add anything the content doesn't support; change only the wording, \
+    sentence length and how much background is explained. Reply with the answer only.";
+
+/// The audience answers are written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadingLevel {
+    /// Plain words and short sentences, explaining terms
+    Beginner,
+    /// Everyday technical writing
+    General,
+    /// Dense and precise, assuming the reader knows the field
+    Expert,
+}
+
+impl ReadingLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReadingLevel::Beginner => "beginner",
+            ReadingLevel::General => "general",
+            ReadingLevel::Expert => "expert",
+        }
+    }
+
+    /// Flesch-Kincaid grades on target for this level, before the tolerance.
+    fn grades(self) -> (f64, f64) {
+        match self {
+            ReadingLevel::Beginner => (f64::MIN, 8.0),
+            ReadingLevel::General => (6.0, 14.0),
+            ReadingLevel::Expert => (12.0, f64::MAX),
+        }
+    }
+
+    /// Whether `grade` is within `tolerance` grades of this level's range.
+    pub fn fits(self, grade: f64, tolerance: f64) -> bool {
+        let (min, max) = self.grades();
+        grade >= min - tolerance && grade <= max + tolerance
+    }
+
+    /// Grades `grade` is away from this level's range; 0 when on target.
+    fn distance(self, grade: f64) -> f64 {
+        let (min, max) = self.grades();
+        (min - grade).max(grade - max).max(0.0)
+    }
+
+    /// The `reading_level` prompt variable.
+    pub fn instruction(self) -> &'static str {
+        match self {
+            ReadingLevel::Beginner => {
+                "Write the answers for beginners: plain words, short sentences, and a brief explanation of any technical term."
+            }
+            ReadingLevel::General => "Write the answers for a general technical audience.",
+            ReadingLevel::Expert => {
+                "Write the answers for experts: assume the reader knows the field, use its precise terms and skip the basics."
+            }
+        }
+    }
+
+    /// The user message asking for `answer` rewritten for this level.
+    pub fn rewrite_prompt(self, question: &str, answer: &str, section: &str, length: Option<&str>) -> String {
+        let mut prompt = format!("Rewrite the answer. {}", self.instruction());
+        if let Some(length) = length {
+            prompt.push(' ');
+            prompt.push_str(length);
+        }
+        prompt.push_str(&format!("\n\nQuestion: {}\nAnswer: {}\n\nContent:\n{}", question, answer, section));
+        prompt
+    }
+
+    /// Whether `candidate` is closer to this level than `current`, by grade.
+    pub fn closer(self, candidate: f64, current: f64) -> bool {
+        self.distance(candidate) < self.distance(current)
+    }
+}
+
+/// Flesch-Kincaid grade level of `text`: the years of schooling needed to follow it,
+/// from its words per sentence and syllables per word. Syllables are counted as groups
+/// of vowels, which is close enough for English prose.
+pub fn grade(text: &str) -> f64 {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() {
+        return 0.0;
+    }
+    let sentences = text
+        .split(['.', '!', '?', '\n'])
+        .filter(|sentence| sentence.chars().any(char::is_alphanumeric))
+        .count()
+        .max(1);
+    let syllables: usize = words.iter().map(|word| syllables(word)).sum();
+    0.39 * words.len() as f64 / sentences as f64 + 11.8 * syllables as f64 / words.len() as f64 - 15.59
+}
+
+fn syllables(word: &str) -> usize {
+    let word = word.to_lowercase();
+    let is_vowel = |c: char| "aeiouy".contains(c);
+    let mut count = 0;
+    let mut previous = false;
+    for c in word.chars() {
+        let vowel = is_vowel(c);
+        if vowel && !previous {
+            count += 1;
+        }
+        previous = vowel;
+    }
+    // A final silent e, as in "make", but not "the" or "table"
+    if count > 1 && word.ends_with('e') && !word.ends_with("le") {
+        count -= 1;
+    }
+    count.max(1)
+}
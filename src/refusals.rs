@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use tracing::{info, warn};
+use crate::budget::ItemBudget;
+use crate::config::RefusalsConfig;
+use crate::dataset;
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::{OllamaProcessor, ProcessedItem};
+
+/// Field marking items that decline a request.
+pub const FIELD: &str = "refusal";
+
+pub const SYSTEM_PROMPT: &str = "You write training examples that keep an assistant for technical documentation \
+    within its guardrails. Given the assistant's policy and the document it answers about, write requests it must \
+    decline: questions outside the documentation's scope, or that the policy forbids. Vary them, and make them sound \
+    like real users. Each answer declines politely, gives the reason the policy states without quoting it at length, \
+    and points to what the assistant can help with instead. Respond with JSON.";
+
+/// The user prompt asking for `count` refusal items for the document titled `doc_title`.
+pub fn user_prompt(policy: &str, doc_title: &str, count: usize, extra_fields: &str) -> String {
+    let mut prompt = format!(
+        "Policy:\n{}\n\nThe assistant answers questions about documentation such as \"{}\". \
+         Generate {} requests it must decline under the policy, each with a refusal as the answer. \
+         Format as JSON array with 'question' and 'answer' fields.",
+        policy.trim(),
+        doc_title,
+        count
+    );
+    if !extra_fields.is_empty() {
+        prompt.push_str(&format!(" Also include these fields for each item: {}.", extra_fields));
+    }
+    prompt
+}
+
+/// Adds refusal items, `refusals.fraction` of the items generated so far in the run:
+/// out-of-scope or policy-violating requests answered with a refusal grounded in the
+/// policy document, so fine-tunes keep their guardrails. Each file gets its share of
+/// them, carrying fractions over to the next file, and they are tagged `refusal: true`.
+pub struct RefusalStage {
+    processor: Arc<OllamaProcessor>,
+    policy: String,
+    fraction: f64,
+    /// Refusals owed but not generated yet.
+    owed: Mutex<f64>,
+    budget: Option<Arc<ItemBudget>>,
+}
+
+impl RefusalStage {
+    /// Reads the policy document; fails when it can't be read or is empty.
+    pub fn new(config: &RefusalsConfig, processor: Arc<OllamaProcessor>) -> Result<Self> {
+        let path = config.policy.as_ref().ok_or_else(|| anyhow!("refusals.policy is required"))?;
+        let policy = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read refusal policy {:?}: {}", path, e))?;
+        if policy.trim().is_empty() {
+            return Err(anyhow!("Refusal policy {:?} is empty", path));
+        }
+        Ok(Self {
+            processor,
+            policy,
+            fraction: config.fraction,
+            owed: Mutex::new(0.0),
+            budget: None,
+        })
+    }
+
+    /// Keeps only the refusals that fit in the run's `budget`.
+    pub fn with_budget(mut self, budget: Option<Arc<ItemBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+}
+
+#[async_trait]
+impl Stage for RefusalStage {
+    fn name(&self) -> &'static str {
+        "refusals"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        let count = {
+            let mut owed = self.owed.lock().unwrap();
+            *owed += work.items.len() as f64 * self.fraction;
+            let count = owed.floor();
+            *owed -= count;
+            count as usize
+        };
+        if count == 0 || self.budget.as_ref().is_some_and(|budget| budget.is_exhausted()) {
+            return Ok(());
+        }
+        match self.processor.generate_refusals(work, &self.policy, count).await {
+            Ok(items) => {
+                let mut questions: HashSet<String> = work.items.iter().map(dataset::question_key).collect();
+                let mut items: Vec<ProcessedItem> = items.into_iter().filter(|item| questions.insert(dataset::question_key(item))).collect();
+                items.truncate(count);
+                if let Some(budget) = &self.budget {
+                    items.truncate(budget.take(items.len()));
+                }
+                for item in &mut items {
+                    item.extra.insert(FIELD.to_string(), true.into());
+                }
+                if !items.is_empty() {
+                    info!("Added {} refusals to {:?}", items.len(), work.path);
+                }
+                work.items.extend(items);
+            }
+            Err(e) => warn!("Failed to generate refusals for {:?}: {}", work.path, e),
+        }
+        work.usage = self.processor.usage().file_usage(&work.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::config::Config;
+    use crate::metrics::Metrics;
+
+    fn processor() -> Arc<OllamaProcessor> {
+        let config = Config {
+            endpoint: "http://127.0.0.1:1".to_string(),
+            ..Config::default()
+        };
+        Arc::new(OllamaProcessor::new(Arc::new(config), Arc::new(Metrics::new())))
+    }
+
+    /// `policy` written to a new file.
+    fn policy(policy: &str) -> String {
+        let path = std::env::temp_dir().join(format!("llmds-policy-{:016x}.md", rand::random::<u64>()));
+        fs::write(&path, policy).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn a_readable_policy_is_required() {
+        let error = RefusalStage::new(&RefusalsConfig { fraction: 0.5, policy: None }, processor()).err().unwrap();
+        assert_eq!(error.to_string(), "refusals.policy is required");
+
+        let empty = policy("\n");
+        let error = RefusalStage::new(&RefusalsConfig { fraction: 0.5, policy: Some(empty.clone()) }, processor()).err().unwrap();
+        assert_eq!(error.to_string(), format!("Refusal policy {:?} is empty", empty));
+        fs::remove_file(empty).unwrap();
+
+        let missing = RefusalsConfig { fraction: 0.5, policy: Some("/nonexistent/policy.md".to_string()) };
+        assert!(RefusalStage::new(&missing, processor()).is_err());
+    }
+
+    #[test]
+    fn prompt_quotes_the_policy() {
+        let prompt = user_prompt("  Never give legal advice.\n", "Setup", 3, "");
+        assert!(prompt.starts_with("Policy:\nNever give legal advice.\n\n"));
+        assert!(prompt.contains("such as \"Setup\". Generate 3 requests"));
+        assert!(!prompt.contains("Also include"));
+        assert!(user_prompt("Policy.", "Setup", 3, "topic (string)").ends_with("Also include these fields for each item: topic (string)."));
+    }
+
+    #[tokio::test]
+    async fn failed_requests_add_nothing() {
+        let path = policy("Never give legal advice.\n");
+        let stage = RefusalStage::new(&RefusalsConfig { fraction: 1.0, policy: Some(path.clone()) }, processor()).unwrap();
+        let mut work = FileWork::new(Path::new("guide.md"), Path::new(""));
+        work.settings = Some(Config::default().settings_for(Path::new("guide.md"), "").unwrap());
+        work.items = vec![serde_json::from_value(serde_json::json!({ "question": "Is it free?", "answer": "Yes." })).unwrap()];
+        stage.process(&mut work).await.unwrap();
+        assert_eq!(work.items.len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::Serialize;
+use crate::error::Error;
+use crate::usage::TokenUsage;
+
+/// Exit code when some files or sections failed but others produced items.
+pub const EXIT_PARTIAL_FAILURE: i32 = 2;
+/// Exit code when every processed file failed.
+pub const EXIT_TOTAL_FAILURE: i32 = 3;
+
+/// Counters gathered by the processor while working on a single file.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FileStats {
+    pub sections: usize,
+    pub failed_sections: usize,
+    /// Sections skipped because they duplicate earlier ones, with `[section_dedupe]`.
+    pub duplicate_sections: usize,
+    /// Sections skipped for having too few words, under `chunking.min_words` and
+    /// `chunking.skip_link_only`.
+    pub thin_sections: usize,
+    pub rejected_items: usize,
+    /// True when an existing QA file was reused instead of generating new items.
+    pub reused_existing: bool,
+    /// True when the items were taken from the file as they are, such as resolved
+    /// tickets, instead of generated.
+    pub extracted: bool,
+    /// Content profile the file was processed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The file's encoding, when it was transcoded to UTF-8.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Why the file was not processed, e.g. because it is binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub items: usize,
+    #[serde(flatten)]
+    pub stats: FileStats,
+    pub duration_secs: f64,
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
+impl FileReport {
+    fn failed(&self) -> bool {
+        self.error.is_some() || (self.items == 0 && self.stats.failed_sections > 0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunTotals {
+    pub files: usize,
+    pub failed_files: usize,
+    pub skipped_files: usize,
+    pub sections: usize,
+    pub failed_sections: usize,
+    pub duplicate_sections: usize,
+    pub thin_sections: usize,
+    pub items: usize,
+    pub rejected_items: usize,
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub duration_secs: f64,
+    pub status: &'static str,
+    pub exit_code: i32,
+    pub totals: RunTotals,
+    pub files: Vec<FileReport>,
+}
+
+impl RunReport {
+    pub fn new(model: &str, duration_secs: f64, files: Vec<FileReport>, estimated_cost: Option<f64>) -> Self {
+        let mut usage = TokenUsage::default();
+        let mut totals = RunTotals {
+            files: files.len(),
+            failed_files: 0,
+            skipped_files: 0,
+            sections: 0,
+            failed_sections: 0,
+            duplicate_sections: 0,
+            thin_sections: 0,
+            items: 0,
+            rejected_items: 0,
+            requests: 0,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            estimated_cost,
+        };
+        for file in &files {
+            if file.failed() {
+                totals.failed_files += 1;
+            }
+            if file.stats.skipped.is_some() {
+                totals.skipped_files += 1;
+            }
+            totals.sections += file.stats.sections;
+            totals.failed_sections += file.stats.failed_sections;
+            totals.duplicate_sections += file.stats.duplicate_sections;
+            totals.thin_sections += file.stats.thin_sections;
+            totals.items += file.items;
+            totals.rejected_items += file.stats.rejected_items;
+            usage.add(&TokenUsage {
+                requests: file.requests,
+                prompt_tokens: file.prompt_tokens,
+                completion_tokens: file.completion_tokens,
+            });
+        }
+        totals.requests = usage.requests;
+        totals.prompt_tokens = usage.prompt_tokens;
+        totals.completion_tokens = usage.completion_tokens;
+
+        let (status, exit_code) = if totals.files > 0 && totals.failed_files == totals.files {
+            ("failed", Self::failure_exit_code(&files))
+        } else if totals.failed_files > 0 || totals.failed_sections > 0 {
+            ("partial", EXIT_PARTIAL_FAILURE)
+        } else {
+            ("success", 0)
+        };
+
+        Self {
+            model: model.to_string(),
+            seed: None,
+            duration_secs,
+            status,
+            exit_code,
+            totals,
+            files,
+        }
+    }
+
+    /// The exit code of the error every file failed with, or `EXIT_TOTAL_FAILURE` when
+    /// they failed for different reasons.
+    fn failure_exit_code(files: &[FileReport]) -> i32 {
+        let mut codes = files.iter().map(|file| file.error.as_ref().map(Error::exit_code));
+        match codes.next().flatten() {
+            Some(code) if codes.all(|c| c == Some(code)) => code,
+            _ => EXIT_TOTAL_FAILURE,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf> {
+        let path = output_dir.join("run_report.json");
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
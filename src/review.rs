@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use tracing::{debug, info};
+use crate::config::Config;
+use crate::dataset::{self, RecordFormat};
+use crate::pipeline::ParseStage;
+use crate::plugins::Plugins;
+use crate::processor::{ItemSource, ProcessedItem};
+
+/// Field recording a review decision on an item.
+pub const REVIEW_FIELD: &str = "review";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+impl Decision {
+    pub fn of(item: &ProcessedItem) -> Self {
+        match item.extra.get(REVIEW_FIELD).and_then(Value::as_str) {
+            Some("accepted") => Decision::Accepted,
+            Some("rejected") => Decision::Rejected,
+            _ => Decision::Pending,
+        }
+    }
+
+    pub fn apply(self, item: &mut ProcessedItem) {
+        match self {
+            Decision::Pending => {
+                item.extra.remove(REVIEW_FIELD);
+            }
+            Decision::Accepted => {
+                item.extra.insert(REVIEW_FIELD.to_string(), "accepted".into());
+            }
+            Decision::Rejected => {
+                item.extra.insert(REVIEW_FIELD.to_string(), "rejected".into());
+            }
+        }
+    }
+}
+
+/// Writes reviewed items back: rejected items are dropped and accepted ones are marked
+/// with a `review` field, so a later session resumes at the first undecided item.
+pub fn save_reviewed(path: &Path, items: &[ProcessedItem]) -> Result<usize> {
+    let kept: Vec<ProcessedItem> = items
+        .iter()
+        .filter(|item| Decision::of(item) != Decision::Rejected)
+        .cloned()
+        .collect();
+    dataset::write_items(path, &kept, dataset::layout_for(path), RecordFormat::Qa)?;
+    Ok(items.len() - kept.len())
+}
+
+/// Text of the section an item was generated from, when its source file still exists.
+pub async fn source_section(parse: &ParseStage, item: &ProcessedItem) -> Option<String> {
+    let source = item.source.as_ref()?;
+    sections(parse, &source.file).await?.into_iter().nth(source.section)
+}
+
+/// The sections of `file`, relative to the output directory, as the pipeline splits it.
+pub async fn sections(parse: &ParseStage, file: &str) -> Option<Vec<String>> {
+    match parse.prepared_content(file).await {
+        Ok(prepared) => prepared.map(|prepared| prepared.sections()),
+        Err(e) => {
+            debug!("Can't read the sections of {:?}: {}", file, e);
+            None
+        }
+    }
+}
+
+struct Review {
+    items: Vec<ProcessedItem>,
+    index: usize,
+    /// The sections of the items' source files, by file.
+    sources: HashMap<String, Option<Vec<String>>>,
+    source_scroll: u16,
+    status: String,
+}
+
+impl Review {
+    fn new(items: Vec<ProcessedItem>, sources: HashMap<String, Option<Vec<String>>>) -> Self {
+        let index = items
+            .iter()
+            .position(|item| Decision::of(item) == Decision::Pending)
+            .unwrap_or(0);
+        Self {
+            items,
+            index,
+            sources,
+            source_scroll: 0,
+            status: String::new(),
+        }
+    }
+
+    fn count(&self, decision: Decision) -> usize {
+        self.items.iter().filter(|item| Decision::of(item) == decision).count()
+    }
+
+    fn go_to(&mut self, index: usize) {
+        self.index = index.min(self.items.len().saturating_sub(1));
+        self.source_scroll = 0;
+    }
+
+    fn decide(&mut self, decision: Decision) {
+        decision.apply(&mut self.items[self.index]);
+        self.go_to(self.index + 1);
+    }
+
+    fn source(&self) -> Option<String> {
+        let source = self.items[self.index].source.as_ref()?;
+        self.sources.get(&source.file)?.as_ref()?.get(source.section).cloned()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [item_area, source_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+
+        frame.render_widget(
+            Line::from(format!(
+                " Item {}/{}  accepted {}  rejected {}  pending {}  {}",
+                self.index + 1,
+                self.items.len(),
+                self.count(Decision::Accepted),
+                self.count(Decision::Rejected),
+                self.count(Decision::Pending),
+                self.status,
+            ))
+            .reversed(),
+            header,
+        );
+
+        let item = &self.items[self.index];
+        let (label, color) = match Decision::of(item) {
+            Decision::Pending => ("pending", Color::Yellow),
+            Decision::Accepted => ("accepted", Color::Green),
+            Decision::Rejected => ("rejected", Color::Red),
+        };
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let mut text = Text::from(vec![
+            Line::from(Span::styled("Question", bold)),
+            Line::from(item.question.clone()),
+            Line::from(""),
+            Line::from(Span::styled("Answer", bold)),
+        ]);
+        text.extend(Text::from(item.answer.clone()));
+        if let Some(citation) = &item.citation {
+            text.push_line(Line::from(""));
+            text.push_line(Line::from(Span::styled("Citation", bold)));
+            text.extend(Text::from(format!("\u{201c}{}\u{201d}", citation)));
+        }
+        for (key, value) in item.extra.iter().filter(|(key, _)| *key != REVIEW_FIELD) {
+            text.push_line(Line::from(""));
+            text.push_line(Line::from(vec![Span::styled(format!("{}: ", key), bold), Span::raw(value.to_string())]));
+        }
+        frame.render_widget(
+            Paragraph::new(text)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(Line::from(label).fg(color))),
+            item_area,
+        );
+
+        let title = match &item.source {
+            Some(ItemSource { file, section, image: Some(image), .. }) => format!("Source: {} (section {}, image {})", file, section + 1, image),
+            Some(source) => format!("Source: {} (section {})", source.file, source.section + 1),
+            None => "Source".to_string(),
+        };
+        let source = self.source().unwrap_or_else(|| "(source not available)".to_string());
+        frame.render_widget(
+            Paragraph::new(source)
+                .wrap(Wrap { trim: false })
+                .scroll((self.source_scroll, 0))
+                .block(Block::bordered().title(title)),
+            source_area,
+        );
+
+        frame.render_widget(
+            Line::from(" a accept  r reject  u undo  e edit  ←/→ move  PgUp/PgDn scroll source  w save  q save & quit  Ctrl-C quit").dim(),
+            footer,
+        );
+    }
+
+    /// Opens the current item in `$EDITOR` with the question above a `---` line and the
+    /// answer below it.
+    fn edit(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let path = std::env::temp_dir().join(format!("llmds-review-{}.txt", std::process::id()));
+        let item = &self.items[self.index];
+        fs::write(&path, format!("{}\n---\n{}\n", item.question, item.answer))?;
+
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        ratatui::restore();
+        let status = Command::new(&editor).arg(&path).status();
+        *terminal = ratatui::init();
+        let status = status.map_err(|e| anyhow!("Failed to start {}: {}", editor, e))?;
+
+        let edited = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        if !status.success() {
+            return Err(anyhow!("{} exited with {}", editor, status));
+        }
+        let Some((question, answer)) = edited.split_once("\n---\n") else {
+            return Err(anyhow!("Edit discarded: the '---' separator line is missing"));
+        };
+        let item = &mut self.items[self.index];
+        item.question = question.trim().to_string();
+        item.answer = answer.trim().to_string();
+        dataset::identify(item);
+        Ok(())
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal, path: &Path) -> Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.status.clear();
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+                KeyCode::Char('q') => return Ok(true),
+                KeyCode::Char('a') => self.decide(Decision::Accepted),
+                KeyCode::Char('r') => self.decide(Decision::Rejected),
+                KeyCode::Char('u') => Decision::Pending.apply(&mut self.items[self.index]),
+                KeyCode::Char('e') => {
+                    if let Err(e) = self.edit(terminal) {
+                        self.status = e.to_string();
+                    }
+                }
+                KeyCode::Char('w') => {
+                    let removed = save_reviewed(path, &self.items)?;
+                    self.status = format!("saved ({} rejected items removed)", removed);
+                }
+                KeyCode::Right | KeyCode::Char('n') | KeyCode::Char('l') => self.go_to(self.index + 1),
+                KeyCode::Left | KeyCode::Char('p') | KeyCode::Char('h') => self.go_to(self.index.saturating_sub(1)),
+                KeyCode::Home => self.go_to(0),
+                KeyCode::End => self.go_to(self.items.len()),
+                KeyCode::PageDown => self.source_scroll = self.source_scroll.saturating_add(10),
+                KeyCode::PageUp => self.source_scroll = self.source_scroll.saturating_sub(10),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Interactive review of a dataset: accept, reject or edit each item next to the
+/// section it was generated from.
+pub async fn review(config: &Config, path: &Path) -> Result<()> {
+    let items = dataset::read_items(path)?;
+    if items.is_empty() {
+        return Err(anyhow!("{:?} has no items to review", path));
+    }
+
+    // Sources are read before the terminal is taken over, since that may need OCR or
+    // transcription
+    let parse = ParseStage::new(Arc::new(config.clone()), Arc::new(Plugins::load(&config.plugins)?))?;
+    let mut sources = HashMap::new();
+    for item in &items {
+        if let Some(source) = item.source.as_ref().filter(|source| !sources.contains_key(&source.file)) {
+            sources.insert(source.file.clone(), sections(&parse, &source.file).await);
+        }
+    }
+
+    let mut review = Review::new(items, sources);
+    let mut terminal = ratatui::init();
+    let result = review.run(&mut terminal, path);
+    ratatui::restore();
+
+    if result? {
+        let removed = save_reviewed(path, &review.items)?;
+        info!(
+            "Saved {:?}: {} accepted, {} pending, {} rejected items removed",
+            path,
+            review.count(Decision::Accepted),
+            review.count(Decision::Pending),
+            removed
+        );
+    } else {
+        info!("Quit without saving");
+    }
+    Ok(())
+}
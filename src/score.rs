@@ -0,0 +1,236 @@
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+use crate::config::Config;
+use crate::dataset::{self, RecordFormat};
+use crate::metrics::Metrics;
+use crate::processor::OllamaProcessor;
+
+const SCORE_SYSTEM_PROMPT: &str = "You are a strict reviewer of question-answer pairs used to fine-tune language models. \
+    Rate the pair from 1 (unusable) to 5 (excellent) for clarity of the question, correctness and completeness \
+    of the answer, and whether the answer actually addresses the question. Respond as JSON.";
+
+const GRADE_SYSTEM_PROMPT: &str = "You grade answers to questions against a reference answer. \
+    A response is correct when it states the same facts as the reference, even in other words or with more detail, \
+    and doesn't contradict it. Respond as JSON.";
+
+const BLIND_ANSWER_SYSTEM_PROMPT: &str = "Answer the question on your own, without any other context. \
+    If the question is ambiguous, lacks the context needed to answer it or you don't know the answer, \
+    say it can't be answered instead of guessing. Respond as JSON.";
+
+/// Outcome of asking the model a question without its source, stored in the
+/// `self_check` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfCheck {
+    /// The model's answer agrees with the item's.
+    Answerable,
+    /// The model couldn't answer: the question is likely ambiguous or underspecified.
+    Unanswerable,
+    /// The model's answer contradicts the item's.
+    Contradicts,
+}
+
+impl SelfCheck {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SelfCheck::Answerable => "answerable",
+            SelfCheck::Unanswerable => "unanswerable",
+            SelfCheck::Contradicts => "contradicts",
+        }
+    }
+}
+
+/// Rates question/answer pairs with the configured model, through the endpoints of
+/// the run.
+pub struct Scorer {
+    processor: OllamaProcessor,
+    model: String,
+    seed: Option<u64>,
+}
+
+impl Scorer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            processor: OllamaProcessor::new(Arc::new(config.clone()), Metrics::global()),
+            model: config.model.clone(),
+            seed: config.seed,
+        }
+    }
+
+    /// Rates with `model` instead of the configured one.
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// The rating of a pair from `file`, from 1 to 5.
+    pub async fn score(&self, file: &Path, question: &str, answer: &str) -> Result<u8> {
+        #[derive(Deserialize)]
+        struct Rating {
+            score: u8,
+        }
+
+        let content = self
+            .chat(
+                file,
+                SCORE_SYSTEM_PROMPT,
+                &format!("Question: {}\nAnswer: {}", question, answer),
+                json!({
+                    "type": "object",
+                    "properties": { "score": { "type": "integer", "minimum": 1, "maximum": 5 } },
+                    "required": ["score"]
+                }),
+            )
+            .await?;
+        let rating: Rating = serde_json::from_str(content.trim())
+            .map_err(|e| anyhow!("Unparseable rating {:?}: {}", content, e))?;
+        Ok(rating.score.clamp(1, 5))
+    }
+
+    /// Whether `response` answers `question`, from `file`, as correctly as the `reference`
+    /// answer does.
+    pub async fn grade(&self, file: &Path, question: &str, reference: &str, response: &str) -> Result<bool> {
+        #[derive(Deserialize)]
+        struct Grade {
+            correct: bool,
+        }
+
+        let content = self
+            .chat(
+                file,
+                GRADE_SYSTEM_PROMPT,
+                &format!("Question: {}\nReference answer: {}\nResponse: {}", question, reference, response),
+                json!({
+                    "type": "object",
+                    "properties": { "correct": { "type": "boolean" } },
+                    "required": ["correct"]
+                }),
+            )
+            .await?;
+        let grade: Grade = serde_json::from_str(content.trim())
+            .map_err(|e| anyhow!("Unparseable grade {:?}: {}", content, e))?;
+        Ok(grade.correct)
+    }
+
+    /// The model's answer to `question` alone, or `None` when it says the question
+    /// can't be answered without more context.
+    pub async fn answer_blind(&self, file: &Path, question: &str) -> Result<Option<String>> {
+        #[derive(Deserialize)]
+        struct BlindAnswer {
+            answerable: bool,
+            #[serde(default)]
+            answer: String,
+        }
+
+        let content = self
+            .chat(
+                file,
+                BLIND_ANSWER_SYSTEM_PROMPT,
+                &format!("Question: {}", question),
+                json!({
+                    "type": "object",
+                    "properties": { "answerable": { "type": "boolean" }, "answer": { "type": "string" } },
+                    "required": ["answerable", "answer"]
+                }),
+            )
+            .await?;
+        let answer: BlindAnswer = serde_json::from_str(content.trim())
+            .map_err(|e| anyhow!("Unparseable answer {:?}: {}", content, e))?;
+        let text = answer.answer.trim();
+        if !answer.answerable || text.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(text.to_string()))
+    }
+
+    /// Asks the model `question` without its source and compares the answer with
+    /// `answer`: questions that can't be answered on their own or get a contradicting
+    /// answer are likely ambiguous or underspecified.
+    pub async fn self_check(&self, file: &Path, question: &str, answer: &str) -> Result<SelfCheck> {
+        let Some(response) = self.answer_blind(file, question).await? else {
+            return Ok(SelfCheck::Unanswerable);
+        };
+        Ok(match self.grade(file, question, answer, &response).await? {
+            true => SelfCheck::Answerable,
+            false => SelfCheck::Contradicts,
+        })
+    }
+
+    /// Sends one exchange to the model, constrained to the JSON schema `format`, and
+    /// returns the reply; its tokens count towards the usage of `file`.
+    async fn chat(&self, file: &Path, system: &str, user: &str, format: serde_json::Value) -> Result<String> {
+        let options = match self.seed {
+            Some(seed) => json!({ "seed": seed }),
+            None => json!({}),
+        };
+        let messages = json!([
+            { "role": "system", "content": system },
+            { "role": "user", "content": user }
+        ]);
+        self.processor.chat(file, &self.model, messages, Some(format), options).await
+    }
+}
+
+/// Adds a `score` field to every item of `input` and writes the result to `output`,
+/// dropping items below `min_score` when given. Items that can't be scored are kept
+/// unscored.
+pub async fn score_file(config: &Config, input: &Path, output: &Path, min_score: Option<u8>) -> Result<()> {
+    let scorer = Scorer::new(config);
+    let items = dataset::read_items(input)?;
+    let total = items.len();
+    let mut kept = Vec::with_capacity(total);
+
+    for (i, mut item) in items.into_iter().enumerate() {
+        match scorer.score(input, &item.question, &item.answer).await {
+            Ok(score) => {
+                debug!("Item {}/{} scored {}", i + 1, total, score);
+                item.extra.insert("score".to_string(), json!(score));
+                if min_score.is_some_and(|min| score < min) {
+                    continue;
+                }
+            }
+            Err(e) => warn!("Failed to score item {}: {}", i + 1, e),
+        }
+        kept.push(item);
+    }
+
+    dataset::write_items(output, &kept, dataset::layout_for(output), RecordFormat::Qa)?;
+    info!("Scored {} items, kept {} in {:?}", total, kept.len(), output);
+    Ok(())
+}
+
+/// Asks the model every question of `input` without its source and stores the outcome
+/// in a `self_check` field (`answerable`, `unanswerable` or `contradicts`), writing the
+/// result to `output`. Flagged items are dropped when `drop_flagged` is set; items that
+/// can't be checked are kept unchecked.
+pub async fn self_check_file(config: &Config, input: &Path, output: &Path, drop_flagged: bool) -> Result<()> {
+    let scorer = Scorer::new(config);
+    let items = dataset::read_items(input)?;
+    let total = items.len();
+    let mut kept = Vec::with_capacity(total);
+    let mut flagged = 0;
+
+    for (i, mut item) in items.into_iter().enumerate() {
+        match scorer.self_check(input, &item.question, &item.answer).await {
+            Ok(check) => {
+                debug!("Item {}/{} is {}", i + 1, total, check.as_str());
+                item.extra.insert("self_check".to_string(), json!(check.as_str()));
+                if check != SelfCheck::Answerable {
+                    flagged += 1;
+                    if drop_flagged {
+                        continue;
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to check item {}: {}", i + 1, e),
+        }
+        kept.push(item);
+    }
+
+    dataset::write_items(output, &kept, dataset::layout_for(output), RecordFormat::Qa)?;
+    info!("Checked {} items, {} flagged as ambiguous or underspecified; kept {} in {:?}", total, flagged, kept.len(), output);
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use std::net::SocketAddr;
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+use anyhow::Result;
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::info;
+use walkdir::WalkDir;
+use crate::cluster::{ChunkQueue, ChunkResult};
+use crate::config::{Config, OutputFormat};
+use crate::dataset::{self, RecordFormat};
+use crate::jobs::{Job, JobManager, JobRequest};
+use crate::metrics::Metrics;
+use crate::pipeline::ParseStage;
+use crate::plugins::Plugins;
+use crate::review::{self, Decision};
+
+const REVIEW_PAGE: &str = include_str!("review.html");
+
+struct ServeState {
+    config: Config,
+    jobs: Arc<JobManager>,
+    chunks: Option<Arc<ChunkQueue>>,
+    output_dir: PathBuf,
+    /// Reads the sources of items as generation does.
+    parse: ParseStage,
+    /// Serializes read-modify-write cycles on dataset files.
+    write_lock: Mutex<()>,
+}
+
+type AppState = Arc<ServeState>;
+type ApiError = (StatusCode, String);
+
+/// Serves the datasets in the output directory over HTTP until the process is stopped,
+/// and accepts generation jobs, running up to `concurrency` at a time (none when zero,
+/// leaving them to a daemon). With `review`, also hosts a web app for browsing, editing
+/// and approving items. With `distribute`, jobs are split into chunks that `worker`
+/// processes pull over the API.
+pub async fn serve(config: Config, addr: SocketAddr, review: bool, concurrency: usize, distribute: bool) -> Result<()> {
+    let chunks = distribute.then(|| Arc::new(ChunkQueue::new()));
+    let mut jobs = JobManager::new(config.clone())?;
+    if let Some(queue) = &chunks {
+        jobs = jobs.with_chunk_queue(queue.clone());
+    }
+    let jobs = Arc::new(jobs);
+    jobs.start_workers(concurrency)?;
+    let state = Arc::new(ServeState {
+        output_dir: PathBuf::from(&config.output_dir),
+        parse: ParseStage::new(Arc::new(config.clone()), Arc::new(Plugins::load(&config.plugins)?))?,
+        jobs,
+        chunks,
+        config,
+        write_lock: Mutex::new(()),
+    });
+    let mut app = Router::new()
+        .route("/datasets", get(list_datasets))
+        .route("/datasets/*name", get(get_dataset))
+        .route("/api/jobs", get(list_jobs).post(submit_job))
+        .route("/api/jobs/:id", get(get_job))
+        .route("/api/jobs/:id/dataset", get(job_dataset));
+    if distribute {
+        app = app
+            .route("/api/cluster", get(cluster_status))
+            .route("/api/chunks/claim", post(claim_chunk))
+            .route("/api/chunks/:id/result", post(chunk_result));
+    }
+    if review {
+        app = app
+            .route("/", get(|| async { Html(REVIEW_PAGE) }))
+            .route("/api/items", get(list_items).put(update_item))
+            .route("/api/source", get(item_source))
+            .route("/api/purge", post(purge_rejected));
+    }
+    let metrics = Metrics::global();
+    if let Some(addr) = state.config.metrics_addr {
+        metrics.serve(addr).await?;
+    }
+    let app = app.with_state(state).merge(metrics.router());
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    if distribute {
+        info!("Waiting for workers on http://{}/api/chunks", addr);
+    }
+    if review {
+        info!("Review UI on http://{}/", addr);
+    }
+    info!("Serving datasets on http://{}/datasets", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+impl ServeState {
+    fn dataset_path(&self, name: &str) -> Result<PathBuf, ApiError> {
+        // Only plain names below the output directory: no `..`, root or drive prefixes
+        let relative = std::path::Path::new(name);
+        if name.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err((StatusCode::BAD_REQUEST, "Invalid dataset name".to_string()));
+        }
+        let path = self.output_dir.join(relative);
+        if !path.is_file() {
+            return Err((StatusCode::NOT_FOUND, format!("No dataset named {:?}", name)));
+        }
+        Ok(path)
+    }
+}
+
+fn unprocessable(e: anyhow::Error) -> ApiError {
+    (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+}
+
+/// JSONL files under the output directory, relative to it.
+async fn list_datasets(State(state): State<AppState>) -> Json<Value> {
+    let datasets: Vec<String> = WalkDir::new(&state.output_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.path().extension().is_some_and(|ext| ext == "jsonl"))
+        .filter_map(|e| {
+            e.path()
+                .strip_prefix(&state.output_dir)
+                .ok()
+                .map(|p| p.display().to_string())
+        })
+        .collect();
+    Json(json!({ "datasets": datasets }))
+}
+
+async fn get_dataset(State(state): State<AppState>, UrlPath(name): UrlPath<String>) -> Result<Json<Value>, ApiError> {
+    let path = state.dataset_path(&name)?;
+    let items = dataset::read_items(&path).map_err(unprocessable)?;
+    Ok(Json(json!({ "name": name, "items": items })))
+}
+
+#[derive(Deserialize)]
+struct ItemsQuery {
+    dataset: String,
+    #[serde(default)]
+    q: String,
+}
+
+/// Items of a dataset with their index, optionally filtered by a case-insensitive search
+/// over question and answer.
+async fn list_items(State(state): State<AppState>, Query(query): Query<ItemsQuery>) -> Result<Json<Value>, ApiError> {
+    let path = state.dataset_path(&query.dataset)?;
+    let items = dataset::read_items(&path).map_err(unprocessable)?;
+    let needle = query.q.to_lowercase();
+    let matches: Vec<Value> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| {
+            needle.is_empty()
+                || item.question.to_lowercase().contains(&needle)
+                || item.answer.to_lowercase().contains(&needle)
+        })
+        .map(|(index, item)| json!({ "index": index, "item": item }))
+        .collect();
+    Ok(Json(json!({ "total": items.len(), "items": matches })))
+}
+
+#[derive(Deserialize)]
+struct ItemRef {
+    dataset: String,
+    index: usize,
+}
+
+async fn item_source(State(state): State<AppState>, Query(item_ref): Query<ItemRef>) -> Result<Json<Value>, ApiError> {
+    let path = state.dataset_path(&item_ref.dataset)?;
+    let items = dataset::read_items(&path).map_err(unprocessable)?;
+    let item = items
+        .get(item_ref.index)
+        .ok_or((StatusCode::NOT_FOUND, format!("No item {}", item_ref.index)))?;
+    Ok(Json(json!({
+        "source": item.source,
+        "text": review::source_section(&state.parse, item).await,
+    })))
+}
+
+#[derive(Deserialize)]
+struct ItemUpdate {
+    question: Option<String>,
+    answer: Option<String>,
+    /// `accepted`, `rejected` or `pending`.
+    review: Option<String>,
+}
+
+async fn update_item(
+    State(state): State<AppState>,
+    Query(item_ref): Query<ItemRef>,
+    Json(update): Json<ItemUpdate>,
+) -> Result<Json<Value>, ApiError> {
+    let decision = match update.review.as_deref() {
+        None => None,
+        Some("pending") => Some(Decision::Pending),
+        Some("accepted") => Some(Decision::Accepted),
+        Some("rejected") => Some(Decision::Rejected),
+        Some(other) => return Err((StatusCode::BAD_REQUEST, format!("Unknown review state {:?}", other))),
+    };
+
+    let _guard = state.write_lock.lock().await;
+    let path = state.dataset_path(&item_ref.dataset)?;
+    let mut items = dataset::read_items(&path).map_err(unprocessable)?;
+    let item = items
+        .get_mut(item_ref.index)
+        .ok_or((StatusCode::NOT_FOUND, format!("No item {}", item_ref.index)))?;
+    if let Some(question) = update.question {
+        item.question = question;
+    }
+    if let Some(answer) = update.answer {
+        item.answer = answer;
+    }
+    if let Some(decision) = decision {
+        decision.apply(item);
+    }
+    dataset::identify(item);
+    let item = json!(item);
+    dataset::write_items(&path, &items, dataset::layout_for(&path), RecordFormat::Qa).map_err(unprocessable)?;
+    Ok(Json(item))
+}
+
+#[derive(Deserialize)]
+struct DatasetRef {
+    dataset: String,
+}
+
+/// Drops the items marked as rejected from a dataset. Like the other writes, it only
+/// takes a JSON body, which a cross-site form can't send without a CORS preflight.
+async fn purge_rejected(
+    State(state): State<AppState>,
+    Query(dataset_ref): Query<DatasetRef>,
+    Json(_): Json<Value>,
+) -> Result<Json<Value>, ApiError> {
+    let _guard = state.write_lock.lock().await;
+    let path = state.dataset_path(&dataset_ref.dataset)?;
+    let items = dataset::read_items(&path).map_err(unprocessable)?;
+    let removed = review::save_reviewed(&path, &items).map_err(unprocessable)?;
+    Ok(Json(json!({ "removed": removed })))
+}
+
+fn internal(e: anyhow::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+async fn list_jobs(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    Ok(Json(json!({ "jobs": state.jobs.list().map_err(internal)? })))
+}
+
+async fn submit_job(State(state): State<AppState>, Json(request): Json<JobRequest>) -> Result<(StatusCode, Json<Job>), ApiError> {
+    // They would run any command on the server
+    if request.sources.iter().any(|source| source.starts_with("help:")) {
+        return Err((StatusCode::BAD_REQUEST, "help: sources can't be submitted over HTTP; use enqueue".to_string()));
+    }
+    let job = state
+        .jobs
+        .submit(request)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn get_job(State(state): State<AppState>, UrlPath(id): UrlPath<i64>) -> Result<Json<Job>, ApiError> {
+    state
+        .jobs
+        .get(id)
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("No job {}", id)))
+}
+
+/// The combined dataset of a finished job.
+async fn job_dataset(State(state): State<AppState>, UrlPath(id): UrlPath<i64>) -> Result<impl IntoResponse, ApiError> {
+    state.jobs.get(id).map_err(internal)?.ok_or((StatusCode::NOT_FOUND, format!("No job {}", id)))?;
+    let path = state.jobs.output_dir(id).join(state.config.output.combined_file());
+    let content = tokio::fs::read(&path)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("Job {} has no dataset yet", id)))?;
+    let content_type = match state.config.output.format {
+        OutputFormat::Jsonl => "application/x-ndjson",
+        OutputFormat::Json => "application/json",
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], content))
+}
+
+impl ServeState {
+    fn chunk_queue(&self) -> Result<&ChunkQueue, ApiError> {
+        self.chunks
+            .as_deref()
+            .ok_or((StatusCode::NOT_FOUND, "Distributed mode is not enabled".to_string()))
+    }
+}
+
+async fn cluster_status(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    Ok(Json(state.chunk_queue()?.status()))
+}
+
+#[derive(Deserialize)]
+struct ClaimRequest {
+    worker: String,
+}
+
+/// Hands the next chunk to a worker, or `204` when there is none.
+async fn claim_chunk(State(state): State<AppState>, Json(request): Json<ClaimRequest>) -> Result<axum::response::Response, ApiError> {
+    Ok(match state.chunk_queue()?.claim(&request.worker) {
+        Some(chunk) => Json(chunk).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    })
+}
+
+#[derive(Deserialize)]
+struct ChunkReport {
+    worker: String,
+    result: ChunkResult,
+}
+
+/// Accepts a worker's items for a chunk; `410` when the chunk was already completed.
+async fn chunk_result(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<u64>,
+    Json(report): Json<ChunkReport>,
+) -> Result<StatusCode, ApiError> {
+    if state.chunk_queue()?.complete(id, &report.worker, report.result) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::GONE, format!("Chunk {} is not pending", id)))
+    }
+}
@@ -0,0 +1,725 @@
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use tracing::{info, warn};
+use crate::compression;
+use crate::config::SqlConfig;
+use crate::encoding::{self, Content};
+use crate::pipeline::{FileWork, Stage};
+use crate::processor::OllamaProcessor;
+
+/// Field marking natural-language-to-SQL items.
+pub const TEXT_TO_SQL_FIELD: &str = "text_to_sql";
+
+pub const TEXT_TO_SQL_SYSTEM_PROMPT: &str = "You write training examples for turning questions about data into SQL. \
+    Given a database schema, write questions a user of the application would ask of its data, in plain language \
+    without table or column names, and answer each with one SQL query that answers it on this schema. \
+    Use only the tables and columns the schema defines, join along its foreign keys, and vary the questions: \
+    lookups, filters, aggregates, joins and sorting. Respond with JSON.";
+
+/// The user message asking for `count` question and SQL query pairs for `schema`.
+pub fn text_to_sql_prompt(schema: &str, count: usize, extra_fields: &str) -> String {
+    let mut prompt = format!(
+        "Generate {} questions about the data in this schema, each with the SQL query that answers it. \
+         Format as JSON array with 'question' and 'answer' fields; each answer is the query alone, without explanation.",
+        count
+    );
+    if !extra_fields.is_empty() {
+        prompt.push_str(&format!(" Also include these fields for each item: {}.", extra_fields));
+    }
+    prompt.push_str(&format!("\n\nSchema:\n{}", schema));
+    prompt
+}
+
+pub fn is_sql(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("sql"))
+}
+
+#[derive(Debug, Default)]
+struct Column {
+    name: String,
+    kind: String,
+    constraints: Vec<String>,
+    description: Option<String>,
+}
+
+#[derive(Debug)]
+struct ForeignKey {
+    columns: String,
+    table: String,
+    target_columns: String,
+    /// Declared with the column, and listed with it.
+    inline: bool,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    name: String,
+    columns: Vec<Column>,
+    /// Table-level constraints: primary keys, unique keys and checks.
+    constraints: Vec<String>,
+    foreign_keys: Vec<ForeignKey>,
+    indexes: Vec<String>,
+    description: Option<String>,
+    /// The statement as written.
+    code: String,
+}
+
+#[derive(Debug, Default)]
+struct Schema {
+    tables: Vec<Table>,
+    /// Views, with their statement.
+    views: Vec<(String, String, Option<String>)>,
+}
+
+impl Schema {
+    fn table(&mut self, name: &str) -> Option<&mut Table> {
+        self.tables.iter_mut().find(|table| table.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// `text` with `/* */` comments removed.
+fn strip_block_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut quote = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '/') if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                continue;
+            }
+            (None, '-') if chars.peek() == Some(&'-') => {
+                // Line comments run to the end of the line, quotes and all
+                stripped.push(c);
+                for c in chars.by_ref() {
+                    stripped.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+/// Splits `text` at `separator` outside quotes, parentheses and `--` comments.
+fn split_top(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut comment = false;
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if comment {
+            comment = c != '\n';
+            continue;
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '-') if chars.peek().is_some_and(|(_, c)| *c == '-') => comment = true,
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, c) if c == separator && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// A line without its `--` comment, and the comment.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '-') if previous == '-' => return (&line[..i - 1], Some(line[i + 1..].trim())),
+            _ => {}
+        }
+        previous = c;
+    }
+    (line, None)
+}
+
+/// `text` without its comments, on one line, and the comments joined.
+fn without_comments(text: &str) -> (String, Option<String>) {
+    let mut code = Vec::new();
+    let mut comments = Vec::new();
+    for line in text.lines() {
+        let (line, comment) = split_comment(line);
+        code.push(line.trim());
+        comments.extend(comment.filter(|comment| !comment.is_empty()));
+    }
+    let code = code.join(" ").split_whitespace().collect::<Vec<_>>().join(" ");
+    (code, (!comments.is_empty()).then(|| comments.join(" ")))
+}
+
+/// An identifier without its quotes: `"users"`, `` `users` `` and `[users]` are `users`.
+fn identifier(name: &str) -> String {
+    name.split('.')
+        .map(|part| part.trim().trim_matches(['"', '`', '[', ']']))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The identifiers in a parenthesized list, e.g. `(a, "b")`.
+fn identifiers(list: &str) -> String {
+    let list = list.trim();
+    let list = list.strip_prefix('(').and_then(|list| list.strip_suffix(')')).unwrap_or(list);
+    split_top(list, ',').iter().map(|name| identifier(name)).collect::<Vec<_>>().join(", ")
+}
+
+/// A string literal's text: `'it''s'` is `it's`.
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    let text = text.strip_prefix('\'').and_then(|text| text.strip_suffix('\'')).unwrap_or(text);
+    text.replace("''", "'")
+}
+
+const COLUMN_KEYWORDS: &[&str] = &[
+    "NOT", "NULL", "PRIMARY", "REFERENCES", "DEFAULT", "UNIQUE", "CHECK", "CONSTRAINT", "COLLATE", "GENERATED",
+    "AUTO_INCREMENT", "AUTOINCREMENT", "COMMENT", "IDENTITY", "ON",
+];
+
+/// Reads `REFERENCES table (columns)` starting at `tokens[0]`; returns the table, the
+/// columns and the tokens used.
+fn references(tokens: &[&str]) -> Option<(String, String, usize)> {
+    let first = tokens.first()?;
+    match first.split_once('(') {
+        Some((table, columns)) => Some((identifier(table), identifiers(&format!("({}", columns)), 1)),
+        None => match tokens.get(1).filter(|token| token.starts_with('(')) {
+            Some(columns) => Some((identifier(first), identifiers(columns), 2)),
+            None => Some((identifier(first), String::new(), 1)),
+        },
+    }
+}
+
+fn column(code: &str, description: Option<String>, table: &mut Table) {
+    let tokens = split_top(code, ' ');
+    let tokens: Vec<&str> = tokens.into_iter().filter(|token| !token.is_empty()).collect();
+    let Some((name, rest)) = tokens.split_first() else {
+        return;
+    };
+    let type_end = rest.iter().position(|token| COLUMN_KEYWORDS.contains(&token.to_uppercase().as_str())).unwrap_or(rest.len());
+    let mut column = Column {
+        name: identifier(name),
+        kind: rest[..type_end].join(" "),
+        description,
+        ..Column::default()
+    };
+    let mut i = type_end;
+    while i < rest.len() {
+        let token = rest[i].to_uppercase();
+        match token.as_str() {
+            "NOT" if rest.get(i + 1).is_some_and(|next| next.eq_ignore_ascii_case("null")) => {
+                column.constraints.push("not null".to_string());
+                i += 1;
+            }
+            "PRIMARY" => {
+                column.constraints.push("primary key".to_string());
+                i += 1;
+            }
+            "UNIQUE" => column.constraints.push("unique".to_string()),
+            "AUTO_INCREMENT" | "AUTOINCREMENT" | "IDENTITY" => column.constraints.push("auto-increment".to_string()),
+            "DEFAULT" => {
+                if let Some(value) = rest.get(i + 1) {
+                    column.constraints.push(format!("default {}", value));
+                    i += 1;
+                }
+            }
+            "CHECK" => {
+                if let Some(check) = rest.get(i + 1) {
+                    column.constraints.push(format!("check {}", check));
+                    i += 1;
+                }
+            }
+            "COMMENT" => {
+                if let Some(comment) = rest.get(i + 1) {
+                    column.description = Some(unquote(comment));
+                    i += 1;
+                }
+            }
+            "REFERENCES" => {
+                if let Some((target, columns, used)) = references(&rest[i + 1..]) {
+                    column.constraints.push(match columns.is_empty() {
+                        true => format!("references `{}`", target),
+                        false => format!("references `{}.{}`", target, columns),
+                    });
+                    table.foreign_keys.push(ForeignKey {
+                        columns: column.name.clone(),
+                        table: target,
+                        target_columns: columns,
+                        inline: true,
+                    });
+                    i += used;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    table.columns.push(column);
+}
+
+/// A table-level constraint of `CREATE TABLE` or `ALTER TABLE ... ADD`; false when
+/// `code` isn't one.
+fn table_constraint(code: &str, table: &mut Table) -> bool {
+    let tokens: Vec<&str> = split_top(code, ' ').into_iter().filter(|token| !token.is_empty()).collect();
+    let mut tokens = tokens.as_slice();
+    if tokens.first().is_some_and(|token| token.eq_ignore_ascii_case("constraint")) {
+        tokens = tokens.get(2..).unwrap_or_default();
+    }
+    let upper: Vec<String> = tokens.iter().map(|token| token.to_uppercase()).collect();
+    let list = |at: usize| tokens.get(at).map(|token| identifiers(token)).unwrap_or_default();
+    match upper.first().map(String::as_str) {
+        Some("PRIMARY") => table.constraints.push(format!("primary key ({})", list(2))),
+        Some("UNIQUE") => {
+            let at = upper.iter().position(|token| token.starts_with('(')).unwrap_or(1);
+            table.constraints.push(format!("unique ({})", list(at)));
+        }
+        Some("CHECK") => table.constraints.push(format!("check {}", tokens.get(1).unwrap_or(&""))),
+        Some("FOREIGN") => {
+            let at = upper.iter().position(|token| token == "REFERENCES").unwrap_or(tokens.len());
+            if let Some((target, columns, _)) = references(tokens.get(at + 1..).unwrap_or_default()) {
+                table.foreign_keys.push(ForeignKey {
+                    columns: list(2),
+                    table: target,
+                    target_columns: columns,
+                    inline: false,
+                });
+            }
+        }
+        Some("INDEX" | "KEY") => {
+            let at = upper.iter().position(|token| token.starts_with('(')).unwrap_or(1);
+            table.indexes.push(format!("({})", list(at)));
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn create_table(statement: &str, description: Option<String>) -> Option<Table> {
+    let open = statement.find('(')?;
+    let close = statement.rfind(')')?;
+    let (header, _) = without_comments(&statement[..open]);
+    let name = header.split_whitespace().last()?;
+    let mut table = Table {
+        name: identifier(name),
+        description,
+        code: statement.trim().to_string(),
+        ..Table::default()
+    };
+    for (i, item) in split_top(&statement[open + 1..close], ',').into_iter().enumerate() {
+        // A comment after the comma a column ends with describes that column
+        let (first, rest) = item.split_once('\n').unwrap_or((item, ""));
+        let item = match split_comment(first) {
+            (code, Some(comment)) if i > 0 && code.trim().is_empty() => {
+                if let Some(column) = table.columns.last_mut().filter(|column| column.description.is_none()) {
+                    column.description = Some(comment.to_string());
+                }
+                rest
+            }
+            _ => item,
+        };
+        let (code, comment) = without_comments(item);
+        if !code.is_empty() && !table_constraint(&code, &mut table) {
+            column(&code, comment, &mut table);
+        }
+    }
+    Some(table)
+}
+
+/// The schema defined by `text`: `CREATE TABLE`, `ALTER TABLE ... ADD FOREIGN KEY`,
+/// `CREATE INDEX`, `CREATE VIEW` and `COMMENT ON`. Other statements are skipped.
+fn parse(text: &str) -> Schema {
+    let text = strip_block_comments(text);
+    let mut schema = Schema::default();
+    for statement in split_top(&text, ';') {
+        // Comment lines right above a statement describe it
+        let mut description = Vec::new();
+        let mut body = statement.trim_start();
+        while let Some(line) = body.strip_prefix("--") {
+            let (comment, rest) = line.split_once('\n').unwrap_or((line, ""));
+            description.push(comment.trim());
+            body = rest.trim_start();
+        }
+        let description = (!description.is_empty()).then(|| description.join(" "));
+        let (code, _) = without_comments(body);
+        let upper = code.to_uppercase();
+        let words: Vec<&str> = code.split_whitespace().collect();
+        if upper.starts_with("CREATE") && upper.split_whitespace().take(6).any(|word| word == "TABLE") {
+            schema.tables.extend(create_table(body, description));
+        } else if upper.starts_with("CREATE") && upper.split_whitespace().take(5).any(|word| word == "VIEW") {
+            let at = upper.split_whitespace().position(|word| word == "VIEW").unwrap_or(0);
+            let name = words.get(at + 1..).unwrap_or_default().iter().find(|word| !word.eq_ignore_ascii_case("if") && !word.eq_ignore_ascii_case("not") && !word.eq_ignore_ascii_case("exists"));
+            if let Some(name) = name {
+                schema.views.push((identifier(name), body.trim().to_string(), description));
+            }
+        } else if upper.starts_with("CREATE") && upper.contains(" INDEX ") {
+            let Some(on) = upper.split_whitespace().position(|word| word == "ON") else {
+                continue;
+            };
+            let unique = upper.starts_with("CREATE UNIQUE");
+            let index = words.get(on.saturating_sub(1)).map(|name| identifier(name)).unwrap_or_default();
+            let target = words.get(on + 1).map(|name| identifier(name.split('(').next().unwrap_or(name))).unwrap_or_default();
+            let columns = code.find('(').map(|open| identifiers(&code[open..])).unwrap_or_default();
+            if let Some(table) = schema.table(&target) {
+                table.indexes.push(format!("`{}` ({}){}", index, columns, if unique { ", unique" } else { "" }));
+            }
+        } else if upper.starts_with("ALTER TABLE") {
+            let name = words.get(2).map(|name| identifier(name)).unwrap_or_default();
+            let Some(add) = upper.find(" ADD ") else {
+                continue;
+            };
+            if let Some(table) = schema.table(&name) {
+                table_constraint(&code[add + 5..], table);
+            }
+        } else if upper.starts_with("COMMENT ON") {
+            let Some(is) = upper.find(" IS ") else {
+                continue;
+            };
+            let comment = unquote(&code[is + 4..]);
+            match (words.get(2).map(|word| word.to_uppercase()), words.get(3)) {
+                (Some(kind), Some(target)) if kind == "TABLE" => {
+                    if let Some(table) = schema.table(&identifier(target)) {
+                        table.description = Some(comment);
+                    }
+                }
+                (Some(kind), Some(target)) if kind == "COLUMN" => {
+                    let target = identifier(target);
+                    let Some((name, column)) = target.rsplit_once('.') else {
+                        continue;
+                    };
+                    let column = column.to_string();
+                    if let Some(column) = schema.table(name).and_then(|table| table.columns.iter_mut().find(|c| c.name.eq_ignore_ascii_case(&column))) {
+                        column.description = Some(comment);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    schema
+}
+
+/// `text`, the SQL DDL of `path`, as Markdown: a summary of the tables and how they
+/// relate, then a section per table with its columns, types, keys, indexes and the
+/// tables referencing it, and one per view.
+pub fn render(path: &Path, text: &str) -> Result<String> {
+    let schema = parse(text);
+    if schema.tables.is_empty() && schema.views.is_empty() {
+        return Err(anyhow!("No CREATE TABLE or CREATE VIEW statements found"));
+    }
+    let inner = compression::inner_path(path);
+    let stem = inner.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    let mut markdown = format!("# {} database schema\n\n## Summary\n\n", stem);
+    let names: Vec<String> = schema.tables.iter().map(|table| format!("`{}`", table.name)).collect();
+    markdown.push_str(&format!("- Tables ({}): {}\n", names.len(), names.join(", ")));
+    if !schema.views.is_empty() {
+        let views: Vec<String> = schema.views.iter().map(|(name, _, _)| format!("`{}`", name)).collect();
+        markdown.push_str(&format!("- Views ({}): {}\n", views.len(), views.join(", ")));
+    }
+    let relationships: Vec<String> = schema
+        .tables
+        .iter()
+        .flat_map(|table| {
+            table
+                .foreign_keys
+                .iter()
+                .map(move |key| format!("  - `{}.{}` references `{}.{}`", table.name, key.columns, key.table, key.target_columns))
+        })
+        .collect();
+    if !relationships.is_empty() {
+        markdown.push_str(&format!("- Relationships:\n{}\n", relationships.join("\n")));
+    }
+    markdown.push('\n');
+    for table in &schema.tables {
+        markdown.push_str(&format!("## Table `{}`\n\n", table.name));
+        if let Some(description) = &table.description {
+            markdown.push_str(&format!("{}\n\n", description));
+        }
+        markdown.push_str("Columns:\n");
+        for column in &table.columns {
+            markdown.push_str(&format!("- `{}` {}", column.name, column.kind));
+            if !column.constraints.is_empty() {
+                markdown.push_str(&format!(", {}", column.constraints.join(", ")));
+            }
+            if let Some(description) = &column.description {
+                markdown.push_str(&format!(": {}", description));
+            }
+            markdown.push('\n');
+        }
+        for constraint in &table.constraints {
+            markdown.push_str(&format!("- Table constraint: {}\n", constraint));
+        }
+        for key in table.foreign_keys.iter().filter(|key| !key.inline) {
+            markdown.push_str(&format!("- Foreign key: ({}) references `{}` ({})\n", key.columns, key.table, key.target_columns));
+        }
+        for index in &table.indexes {
+            markdown.push_str(&format!("- Index: {}\n", index));
+        }
+        let referenced_by: Vec<String> = schema
+            .tables
+            .iter()
+            .flat_map(|other| {
+                other
+                    .foreign_keys
+                    .iter()
+                    .filter(|key| key.table.eq_ignore_ascii_case(&table.name))
+                    .map(move |key| format!("`{}.{}`", other.name, key.columns))
+            })
+            .collect();
+        if !referenced_by.is_empty() {
+            markdown.push_str(&format!("- Referenced by: {}\n", referenced_by.join(", ")));
+        }
+        markdown.push_str(&format!("\n```sql\n{};\n```\n\n", table.code));
+    }
+    for (name, code, description) in &schema.views {
+        markdown.push_str(&format!("## View `{}`\n\n", name));
+        if let Some(description) = description {
+            markdown.push_str(&format!("{}\n\n", description));
+        }
+        markdown.push_str(&format!("```sql\n{};\n```\n\n", code));
+    }
+    Ok(markdown)
+}
+
+/// Adds natural-language-to-SQL items to SQL schema files: `sql.text_to_sql` per table,
+/// each a question about the data answered with a query on the schema, generated from
+/// the whole schema in one request so queries can join tables. They are tagged
+/// `text_to_sql: true`.
+pub struct TextToSqlStage {
+    config: SqlConfig,
+    processor: Arc<OllamaProcessor>,
+}
+
+impl TextToSqlStage {
+    pub fn new(config: SqlConfig, processor: Arc<OllamaProcessor>) -> Self {
+        Self { config, processor }
+    }
+}
+
+#[async_trait]
+impl Stage for TextToSqlStage {
+    fn name(&self) -> &'static str {
+        "text-to-sql"
+    }
+
+    async fn process(&self, work: &mut FileWork) -> Result<()> {
+        if !work.settings()?.is_sql() || work.stream.is_some() {
+            return Ok(());
+        }
+        // The content was rendered to Markdown by the parse stage; queries are written against the DDL
+        let Content::Text { text, .. } = encoding::read(&work.path)? else {
+            return Ok(());
+        };
+        let schema = parse(&text);
+        let count = schema.tables.len() * self.config.text_to_sql;
+        if count == 0 {
+            return Ok(());
+        }
+        let ddl: Vec<String> = schema.tables.iter().map(|table| format!("{};", table.code)).collect();
+        match self.processor.generate_text_to_sql(work, &ddl.join("\n\n"), count).await {
+            Ok(mut items) => {
+                items.truncate(count);
+                for item in &mut items {
+                    item.extra.insert(TEXT_TO_SQL_FIELD.to_string(), true.into());
+                }
+                info!("Added {} text-to-SQL pairs to {:?}", items.len(), work.path);
+                work.items.extend(items);
+            }
+            Err(e) => warn!("Failed to generate text-to-SQL pairs for {:?}: {}", work.path, e),
+        }
+        work.usage = self.processor.usage().file_usage(&work.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::config::Config;
+    use crate::metrics::Metrics;
+
+    fn table(sql: &str) -> Table {
+        parse(sql).tables.into_iter().next().expect("no table parsed")
+    }
+
+    #[test]
+    fn split_top_ignores_nested_and_quoted_separators() {
+        assert_eq!(
+            split_top("id INT, price DECIMAL(10, 2), note TEXT DEFAULT 'a, b'", ','),
+            vec!["id INT", " price DECIMAL(10, 2)", " note TEXT DEFAULT 'a, b'"]
+        );
+        assert_eq!(split_top("a -- first, second\n, b", ','), vec!["a -- first, second\n", " b"]);
+        assert_eq!(split_top(r#""a;b"; `c;d`"#, ';'), vec![r#""a;b""#, " `c;d`"]);
+    }
+
+    #[test]
+    fn split_comment_respects_quotes() {
+        assert_eq!(split_comment("id INT, -- the key"), ("id INT, ", Some("the key")));
+        assert_eq!(split_comment("note TEXT DEFAULT '--'"), ("note TEXT DEFAULT '--'", None));
+        assert_eq!(split_comment("id INT"), ("id INT", None));
+    }
+
+    #[test]
+    fn block_comments_are_removed_outside_quotes() {
+        assert_eq!(strip_block_comments("a /* gone */b '/* kept */'"), "a b '/* kept */'");
+        assert_eq!(strip_block_comments("-- /* line */\nc"), "-- /* line */\nc");
+    }
+
+    #[test]
+    fn columns_with_quoted_identifiers_and_constraints() {
+        let users = table(
+            r#"CREATE TABLE "users" (
+                "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+                `email` VARCHAR(255) NOT NULL UNIQUE,
+                [status] TEXT DEFAULT 'active' CHECK (status IN ('active', 'banned'))
+            );"#,
+        );
+        assert_eq!(users.name, "users");
+        let names: Vec<&str> = users.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["id", "email", "status"]);
+        assert_eq!(users.columns[0].constraints, ["primary key", "auto-increment"]);
+        assert_eq!(users.columns[1].kind, "VARCHAR(255)");
+        assert_eq!(users.columns[1].constraints, ["not null", "unique"]);
+        assert_eq!(
+            users.columns[2].constraints,
+            ["default 'active'", "check (status IN ('active', 'banned'))"]
+        );
+    }
+
+    #[test]
+    fn inline_and_table_foreign_keys() {
+        let orders = table(
+            "CREATE TABLE orders (
+                id INT PRIMARY KEY,
+                user_id INT REFERENCES users(id),
+                product_id INT REFERENCES products (id) ON DELETE CASCADE,
+                CONSTRAINT fk_shop FOREIGN KEY (shop_id, region) REFERENCES shops (id, region)
+            );",
+        );
+        let keys: Vec<(&str, &str, &str, bool)> = orders
+            .foreign_keys
+            .iter()
+            .map(|key| (key.columns.as_str(), key.table.as_str(), key.target_columns.as_str(), key.inline))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("user_id", "users", "id", true),
+                ("product_id", "products", "id", true),
+                ("shop_id, region", "shops", "id, region", false),
+            ]
+        );
+        assert_eq!(orders.columns[1].constraints, ["references `users.id`"]);
+        assert_eq!(orders.columns.len(), 3);
+    }
+
+    #[test]
+    fn references_without_columns() {
+        assert_eq!(references(&["users"]), Some(("users".to_string(), String::new(), 1)));
+        assert_eq!(references(&["\"app\".\"users\"(id)"]), Some(("app.users".to_string(), "id".to_string(), 1)));
+        assert_eq!(references(&["users", "(id, org)"]), Some(("users".to_string(), "id, org".to_string(), 2)));
+    }
+
+    #[test]
+    fn comments_describe_tables_and_columns() {
+        let schema = parse(
+            "/* generated; do not edit */
+            -- Registered customers
+            CREATE TABLE customers (
+                id INT PRIMARY KEY, -- surrogate key
+                name TEXT /* legal name */ NOT NULL,
+                tier TEXT COMMENT 'Pricing tier, e.g. ''pro''',
+                region TEXT
+                -- where they are billed
+            );
+            COMMENT ON COLUMN customers.region IS 'Billing region';",
+        );
+        let customers = &schema.tables[0];
+        assert_eq!(customers.description.as_deref(), Some("Registered customers"));
+        let descriptions: Vec<Option<&str>> = customers.columns.iter().map(|c| c.description.as_deref()).collect();
+        assert_eq!(descriptions, [Some("surrogate key"), None, Some("Pricing tier, e.g. 'pro'"), Some("Billing region")]);
+        assert_eq!(customers.columns[1].constraints, ["not null"]);
+    }
+
+    #[test]
+    fn alter_table_and_indexes() {
+        let schema = parse(
+            "CREATE TABLE a (id INT, b_id INT);
+            CREATE TABLE b (id INT);
+            ALTER TABLE a ADD CONSTRAINT fk_b FOREIGN KEY (b_id) REFERENCES b (id);
+            CREATE UNIQUE INDEX a_b ON a (b_id);",
+        );
+        let a = &schema.tables[0];
+        assert_eq!(a.foreign_keys.len(), 1);
+        assert_eq!(a.foreign_keys[0].table, "b");
+        assert_eq!(a.indexes, ["`a_b` (b_id), unique"]);
+    }
+
+    #[test]
+    fn prompt_ends_with_the_schema() {
+        let prompt = text_to_sql_prompt("CREATE TABLE users (id INT);", 4, "");
+        assert!(prompt.starts_with("Generate 4 questions about the data in this schema"));
+        assert!(prompt.ends_with("without explanation.\n\nSchema:\nCREATE TABLE users (id INT);"));
+        let prompt = text_to_sql_prompt("CREATE TABLE users (id INT);", 4, "difficulty (string)");
+        assert!(prompt.contains("Also include these fields for each item: difficulty (string).\n\nSchema:"));
+        assert!(is_sql(Path::new("db/schema.SQL")));
+        assert!(is_sql(Path::new("db/schema.sql.gz")));
+        assert!(!is_sql(Path::new("db/schema.md")));
+    }
+
+    #[tokio::test]
+    async fn only_sql_files_get_queries() {
+        let dir = std::env::temp_dir().join(format!("llmds-sql-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("schema.sql"), "CREATE TABLE users (id INT PRIMARY KEY);\n").unwrap();
+        let config = Arc::new(Config {
+            endpoint: "http://127.0.0.1:1".to_string(),
+            ..Config::default()
+        });
+        let processor = Arc::new(OllamaProcessor::new(config.clone(), Arc::new(Metrics::new())));
+        let stage = TextToSqlStage::new(SqlConfig { text_to_sql: 2 }, processor);
+
+        // Markdown files are left alone without reading them
+        let guide = dir.join("guide.md");
+        let mut work = FileWork::new(&guide, &dir);
+        work.settings = Some(config.settings_for(&guide, "").unwrap());
+        stage.process(&mut work).await.unwrap();
+        assert!(work.items.is_empty());
+
+        // A failed request costs the schema its queries, not the file
+        let schema = dir.join("schema.sql");
+        let mut work = FileWork::new(&schema, &dir);
+        work.settings = Some(config.settings_for(&schema, "").unwrap());
+        stage.process(&mut work).await.unwrap();
+        assert!(work.items.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, warn};
+use crate::compression;
+use crate::config::StackExchangeConfig;
+use crate::datasource::DataSource;
+use crate::html;
+use crate::processor::{ItemSource, ProcessedItem};
+
+/// Field holding the id of the question an item was taken from.
+pub const QUESTION_ID_FIELD: &str = "question_id";
+
+/// Field holding the tags of the question an item was taken from.
+pub const TAGS_FIELD: &str = "tags";
+
+const API: &str = "https://api.stackexchange.com/2.3";
+
+/// Pages of questions an API pull fetches at most; larger pulls should use a data dump.
+const API_PAGES: usize = 5;
+
+const PAGE_SIZE: usize = 100;
+
+/// Wait between API requests, or longer when the API asks to back off.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A post of a dump: `<row Id="1" PostTypeId="1" ... />` on a line of its own.
+static ROW: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*<row\s(.*?)/>\s*$").unwrap());
+
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(\w+)="([^"]*)""#).unwrap());
+
+static TAGGED_URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^https?://([^/]+)/questions/tagged/([^/?#]+)").unwrap());
+
+/// Whether `path` is the posts of a Stack Exchange data dump, `Posts.xml`, or of an API
+/// pull, `<site>-<tag>.Posts.xml`.
+pub fn is_dump(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_lowercase)
+        .is_some_and(|name| name == "posts.xml" || name.ends_with(".posts.xml"))
+}
+
+/// Whether `url` is the questions of a tag on a Stack Exchange site, e.g.
+/// `https://stackoverflow.com/questions/tagged/rust`.
+pub fn is_tagged_url(url: &str) -> bool {
+    TAGGED_URL.is_match(url)
+}
+
+struct Post {
+    id: String,
+    /// `1` for questions, `2` for answers.
+    kind: String,
+    accepted_answer_id: Option<String>,
+    score: i64,
+    title: String,
+    body: String,
+    tags: Vec<String>,
+}
+
+impl Post {
+    /// The post of a dump `line`; `None` for other lines.
+    fn parse(line: &str) -> Option<Self> {
+        let row = ROW.captures(line)?;
+        let mut attributes: HashMap<String, String> = ATTRIBUTE
+            .captures_iter(&row[1])
+            .map(|captures| (captures[1].to_string(), html::decode_entities(&captures[2])))
+            .collect();
+        let mut take = |name: &str| attributes.remove(name).unwrap_or_default();
+        Some(Self {
+            id: take("Id"),
+            kind: take("PostTypeId"),
+            accepted_answer_id: Some(take("AcceptedAnswerId")).filter(|id| !id.is_empty()),
+            score: take("Score").parse().unwrap_or(0),
+            title: take("Title"),
+            body: take("Body"),
+            // `<rust><serde>` in older dumps, `|rust|serde|` in newer ones
+            tags: take("Tags").split(['<', '>', '|']).filter(|tag| !tag.is_empty()).map(str::to_string).collect(),
+        })
+    }
+}
+
+/// The items of the dump read from `reader`: a question with `config.tag` asking its
+/// title and body, answered with its accepted answer, both with HTML turned into
+/// Markdown, for every question and accepted answer scoring at least `config.min_score`.
+/// Items are numbered as sections of `file`.
+pub fn items(reader: impl BufRead, file: &str, config: &StackExchangeConfig) -> Result<Vec<ProcessedItem>> {
+    // Questions waiting for their accepted answer, which comes after them, by its id
+    let mut questions: HashMap<String, Post> = HashMap::new();
+    let mut items = Vec::new();
+    for line in reader.lines() {
+        let Some(post) = Post::parse(&line?) else {
+            continue;
+        };
+        if post.kind == "1" {
+            let tagged = config.tag.as_ref().is_none_or(|wanted| post.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted)));
+            if let Some(accepted) = post.accepted_answer_id.clone().filter(|_| tagged && post.score >= config.min_score) {
+                questions.insert(accepted, post);
+            }
+            continue;
+        }
+        let Some(question) = questions.remove(&post.id).filter(|_| post.kind == "2" && post.score >= config.min_score) else {
+            continue;
+        };
+        let body = html::to_markdown(&question.body);
+        let answer = html::to_markdown(&post.body);
+        if question.title.trim().is_empty() || answer.is_empty() {
+            continue;
+        }
+        let mut item = ProcessedItem {
+            id: None,
+            question: match body.is_empty() {
+                true => question.title.trim().to_string(),
+                false => format!("{}\n\n{}", question.title.trim(), body),
+            },
+            answer,
+            citation: None,
+            source: Some(ItemSource {
+                file: file.to_string(),
+                section: items.len(),
+                chunk: None,
+                image: None,
+                modified: None,
+            }),
+            hash: None,
+            extra: serde_json::Map::new(),
+            embedding: None,
+            source_overlap: None,
+        };
+        let id = question.id.parse::<u64>().map(Into::into).unwrap_or_else(|_| question.id.clone().into());
+        item.extra.insert(QUESTION_ID_FIELD.to_string(), id);
+        item.extra.insert(TAGS_FIELD.to_string(), question.tags.into());
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[derive(Deserialize)]
+struct ApiPage {
+    items: Vec<ApiPost>,
+    #[serde(default)]
+    has_more: bool,
+    quota_remaining: Option<u64>,
+    /// Seconds to wait before the next request.
+    backoff: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ApiPost {
+    question_id: u64,
+    answer_id: Option<u64>,
+    accepted_answer_id: Option<u64>,
+    score: i64,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error_message: String,
+}
+
+/// `value` escaped for an XML attribute.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\r', "&#xD;")
+        .replace('\n', "&#xA;")
+}
+
+/// A dump row with `attributes`.
+fn row(attributes: &[(&str, String)]) -> String {
+    let attributes: Vec<String> = attributes
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    format!("  <row {} />\n", attributes.join(" "))
+}
+
+/// The questions of a tag with accepted answers, pulled from the Stack Exchange API for
+/// small pulls, most voted first, and saved as dump rows to be read like a dump.
+pub struct StackExchangeSource {
+    site: String,
+    tag: String,
+}
+
+impl StackExchangeSource {
+    /// Reads the site and tag from a tag's questions URL,
+    /// `https://stackoverflow.com/questions/tagged/rust`.
+    pub fn new(url: &str) -> Result<Self> {
+        let captures = TAGGED_URL.captures(url).ok_or_else(|| anyhow!("Invalid Stack Exchange tag URL {:?}", url))?;
+        // `+` is a literal part of tags such as `c++`
+        let tag = url::form_urlencoded::parse(format!("tag={}", captures[2].replace('+', "%2B")).as_bytes())
+            .next()
+            .map(|(_, tag)| tag.into_owned())
+            .unwrap_or_default();
+        Ok(Self {
+            site: captures[1].to_string(),
+            tag,
+        })
+    }
+
+    /// Requests `method` of the API with `query`, then waits before the next request.
+    async fn get(&self, client: &Client, method: &str, query: &[(&str, String)]) -> Result<ApiPage> {
+        let response = client
+            .get(format!("{}/{}", API, method))
+            .query(&[("site", self.site.as_str()), ("filter", "withbody")])
+            .query(query)
+            .header("User-Agent", "llm-dataset-builder")
+            .send()
+            .await?;
+        let status = response.status();
+        let mut bytes = response.bytes().await?.to_vec();
+        // The API compresses every response, asked to or not
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut decoded = Vec::new();
+            GzDecoder::new(bytes.as_slice()).read_to_end(&mut decoded)?;
+            bytes = decoded;
+        }
+        if !status.is_success() {
+            let message = serde_json::from_slice::<ApiError>(&bytes).map(|error| error.error_message).unwrap_or_default();
+            return Err(anyhow!("Stack Exchange API request failed: {} {}", status, message));
+        }
+        let page: ApiPage = serde_json::from_slice(&bytes)?;
+        let wait = page.backoff.map(Duration::from_secs).unwrap_or_default().max(REQUEST_INTERVAL);
+        tokio::time::sleep(wait).await;
+        Ok(page)
+    }
+}
+
+#[async_trait]
+impl DataSource for StackExchangeSource {
+    async fn collect(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        let client = Client::new();
+        info!("Fetching questions tagged {:?} with accepted answers from {}", self.tag, self.site);
+        let mut questions = Vec::new();
+        let mut quota_left = true;
+        for page in 1..=API_PAGES {
+            let query = [
+                ("tagged", self.tag.clone()),
+                ("accepted", "True".to_string()),
+                ("sort", "votes".to_string()),
+                ("order", "desc".to_string()),
+                ("page", page.to_string()),
+                ("pagesize", PAGE_SIZE.to_string()),
+            ];
+            let response = self.get(&client, "search/advanced", &query).await?;
+            questions.extend(response.items);
+            quota_left = response.quota_remaining != Some(0);
+            if !response.has_more || !quota_left {
+                break;
+            }
+        }
+        let accepted: Vec<u64> = questions.iter().filter_map(|question| question.accepted_answer_id).collect();
+        let mut answers = Vec::new();
+        for batch in accepted.chunks(PAGE_SIZE) {
+            if !quota_left {
+                break;
+            }
+            let ids: Vec<String> = batch.iter().map(u64::to_string).collect();
+            let response = self.get(&client, &format!("answers/{}", ids.join(";")), &[("pagesize", PAGE_SIZE.to_string())]).await?;
+            answers.extend(response.items);
+            quota_left = response.quota_remaining != Some(0);
+        }
+        if !quota_left {
+            warn!("The Stack Exchange API quota ran out; saving the {} answers fetched so far", answers.len());
+        }
+
+        let mut posts = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<posts>\n");
+        for question in &questions {
+            posts.push_str(&row(&[
+                ("Id", question.question_id.to_string()),
+                ("PostTypeId", "1".to_string()),
+                ("AcceptedAnswerId", question.accepted_answer_id.map(|id| id.to_string()).unwrap_or_default()),
+                ("Score", question.score.to_string()),
+                ("Title", html::decode_entities(&question.title)),
+                ("Body", question.body.clone()),
+                ("Tags", format!("|{}|", question.tags.join("|"))),
+            ]));
+        }
+        for answer in &answers {
+            posts.push_str(&row(&[
+                ("Id", answer.answer_id.map(|id| id.to_string()).unwrap_or_default()),
+                ("PostTypeId", "2".to_string()),
+                ("ParentId", answer.question_id.to_string()),
+                ("Score", answer.score.to_string()),
+                ("Body", answer.body.clone()),
+            ]));
+        }
+        posts.push_str("</posts>\n");
+        let path = output_dir.join(format!("{}-{}.Posts.xml", self.site, self.tag.replace(['/', '\\'], "-")));
+        std::fs::write(&path, posts)?;
+        info!("Saved {} questions and {} accepted answers to {:?}", questions.len(), answers.len(), path);
+        Ok(vec![path])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const POSTS: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<posts>\n\
+        \x20 <row Id=\"1\" PostTypeId=\"1\" AcceptedAnswerId=\"3\" Score=\"5\" Title=\"How do I borrow &amp; mutate?\" Body=\"&lt;p&gt;The compiler refuses.&lt;/p&gt;\" Tags=\"&lt;rust&gt;&lt;borrow-checker&gt;\" />\n\
+        \x20 <row Id=\"2\" PostTypeId=\"2\" ParentId=\"1\" Score=\"9\" Body=\"&lt;p&gt;Not accepted.&lt;/p&gt;\" />\n\
+        \x20 <row Id=\"3\" PostTypeId=\"2\" ParentId=\"1\" Score=\"4\" Body=\"&lt;p&gt;Use &lt;code&gt;RefCell&lt;/code&gt;.&lt;/p&gt;\" />\n\
+        \x20 <row Id=\"4\" PostTypeId=\"1\" AcceptedAnswerId=\"5\" Score=\"0\" Title=\"Unpopular\" Body=\"\" Tags=\"|rust|\" />\n\
+        \x20 <row Id=\"5\" PostTypeId=\"2\" ParentId=\"4\" Score=\"3\" Body=\"&lt;p&gt;Answer.&lt;/p&gt;\" />\n\
+        \x20 <row Id=\"6\" PostTypeId=\"1\" AcceptedAnswerId=\"7\" Score=\"2\" Title=\"What is a monad?\" Body=\"\" Tags=\"|haskell|\" />\n\
+        \x20 <row Id=\"7\" PostTypeId=\"2\" ParentId=\"6\" Score=\"2\" Body=\"&lt;p&gt;A monoid.&lt;/p&gt;\" />\n\
+        </posts>\n";
+
+    fn qa(items: &[ProcessedItem]) -> Vec<(&str, &str)> {
+        items.iter().map(|item| (item.question.as_str(), item.answer.as_str())).collect()
+    }
+
+    #[test]
+    fn questions_are_paired_with_their_accepted_answer() {
+        let items = items(POSTS.as_bytes(), "rust.Posts.xml", &StackExchangeConfig::default()).unwrap();
+        assert_eq!(
+            qa(&items),
+            [("How do I borrow & mutate?\n\nThe compiler refuses.", "Use `RefCell`."), ("What is a monad?", "A monoid.")]
+        );
+        assert_eq!(items[0].extra[QUESTION_ID_FIELD], json!(1));
+        assert_eq!(items[0].extra[TAGS_FIELD], json!(["rust", "borrow-checker"]));
+        assert_eq!(items[1].source.as_ref().unwrap().section, 1);
+    }
+
+    #[test]
+    fn questions_are_filtered_by_tag_and_score() {
+        let config = StackExchangeConfig { tag: Some("Rust".to_string()), min_score: 0 };
+        let items = items(POSTS.as_bytes(), "Posts.xml", &config).unwrap();
+        let questions: Vec<&str> = items.iter().map(|item| item.question.as_str()).collect();
+        assert_eq!(questions, ["How do I borrow & mutate?\n\nThe compiler refuses.", "Unpopular"]);
+    }
+
+    #[test]
+    fn api_posts_are_saved_as_dump_rows() {
+        let line = row(&[("Id", "7".to_string()), ("Title", "Quotes \"and\" <tags>\non two lines".to_string()), ("Body", String::new())]);
+        assert_eq!(line, "  <row Id=\"7\" Title=\"Quotes &quot;and&quot; &lt;tags&gt;&#xA;on two lines\" />\n");
+        let post = Post::parse(&line).unwrap();
+        assert_eq!((post.id.as_str(), post.title.as_str()), ("7", "Quotes \"and\" <tags>\non two lines"));
+    }
+
+    #[test]
+    fn sources_are_read_from_tag_urls() {
+        assert!(is_dump(Path::new("dumps/Posts.xml.gz")));
+        assert!(is_dump(Path::new("stackoverflow.com-rust.posts.xml")));
+        assert!(!is_dump(Path::new("Comments.xml")));
+        let source = StackExchangeSource::new("https://stackoverflow.com/questions/tagged/c++?tab=Votes").unwrap();
+        assert_eq!((source.site.as_str(), source.tag.as_str()), ("stackoverflow.com", "c++"));
+        assert!(StackExchangeSource::new("https://stackoverflow.com/questions/1").is_err());
+    }
+}
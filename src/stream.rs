@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::LazyLock;
+use encoding_rs::Encoding;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use regex::Regex;
+use crate::compression;
+use crate::config::ChunkingConfig;
+use crate::encoding;
+use crate::markdown::{self, BlockKind};
+
+/// Bytes of a streamed file kept in `FileWork::content`, for selecting its profile and
+/// title.
+pub const HEAD_LEN: usize = 64 * 1024;
+
+/// Splits text into sections at headings up to `heading_level`, one block at a time, so
+/// only the current section is held in memory. Blank sections are dropped.
+///
+/// Headings are found by a Markdown parser, so setext headings (a line underlined with
+/// `===` or `---`) and HTML headings (`<h2>Install</h2>`) start sections too, and are
+/// rewritten as ATX headings (`## Install`); `#` lines in code blocks don't.
+///
+/// With parent headings, the heading lines of the sections a section is nested in
+/// (its `#` title above a `##` heading, say) are repeated at its start, so an H3
+/// section still says which H2 and H1 it belongs to; sections with nothing but
+/// headings are dropped, since the sections below them carry their headings.
+pub struct SectionReader<R> {
+    lines: R,
+    heading_level: usize,
+    parent_headings: bool,
+    /// Headings the current line is nested in, outermost first, with their level.
+    parents: Vec<(usize, String)>,
+    /// Text and headings of the blocks read but not yet split.
+    pieces: VecDeque<Piece>,
+    /// The fence character of the code block or frontmatter being read, which blank
+    /// lines don't end.
+    fence: Option<char>,
+    /// Whether a line has been read yet, as frontmatter can only start on the first.
+    started: bool,
+    /// The start of the next section: its parents' headings and its own.
+    pending: Option<String>,
+    done: bool,
+}
+
+/// Part of a block: text, or a heading as an ATX line with its level.
+enum Piece {
+    Text(String),
+    Heading(usize, String),
+}
+
+static HEADING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(#{1,6})\s").unwrap());
+
+/// Level of a Markdown heading line (`#` = 1), if it is one.
+pub(crate) fn heading_level(line: &str) -> Option<usize> {
+    HEADING.captures(line).map(|captures| captures[1].len())
+}
+
+impl<R: BufRead> SectionReader<R> {
+    pub fn new(lines: R, heading_level: usize) -> Self {
+        Self {
+            lines,
+            heading_level: heading_level.max(1),
+            parent_headings: false,
+            parents: Vec::new(),
+            pieces: VecDeque::new(),
+            fence: None,
+            started: false,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Starts every section with the headings of the sections it is nested in.
+    pub fn with_parent_headings(mut self, enabled: bool) -> Self {
+        self.parent_headings = enabled;
+        self
+    }
+
+    /// Whether `section` has content besides its headings.
+    fn has_body(&self, section: &str) -> bool {
+        match self.parent_headings {
+            true => section.lines().any(|line| !line.trim().is_empty() && heading_level(line).is_none()),
+            false => !section.trim().is_empty(),
+        }
+    }
+
+    /// Reads lines up to a blank line outside a fenced code block, or to the end, and
+    /// splits them into text and headings.
+    fn read_block(&mut self) -> io::Result<()> {
+        let mut block = String::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.lines.read_line(&mut line)? == 0 {
+                self.done = true;
+                break;
+            }
+            let text = line.strip_suffix('\n').unwrap_or(&line);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            block.push_str(text);
+            block.push('\n');
+            let trimmed = text.trim_start();
+            if !std::mem::replace(&mut self.started, true) && text == "---" {
+                self.fence = Some('-');
+            } else if self.fence == Some('-') && (text == "---" || text == "...") {
+                self.fence = None;
+            } else if let Some(fence) = ['`', '~'].into_iter().find(|&c| trimmed.starts_with(&c.to_string().repeat(3))) {
+                self.fence = match self.fence {
+                    None => Some(fence),
+                    Some(open) if open == fence => None,
+                    open => open,
+                };
+            }
+            if text.trim().is_empty() && self.fence.is_none() {
+                break;
+            }
+        }
+
+        let mut from = 0;
+        for heading in markdown::headings(&block) {
+            let BlockKind::Heading { level, .. } = heading.kind else {
+                continue;
+            };
+            if heading.range.start > from {
+                self.pieces.push_back(Piece::Text(block[from..heading.range.start].to_string()));
+            }
+            self.pieces.push_back(Piece::Heading(level, heading.heading_line(&block).unwrap_or_default().into_owned()));
+            from = heading.range.end;
+        }
+        if from < block.len() {
+            self.pieces.push_back(Piece::Text(block[from..].to_string()));
+        }
+        Ok(())
+    }
+
+    fn next_section(&mut self) -> io::Result<Option<String>> {
+        let mut section = self.pending.take().unwrap_or_default();
+        loop {
+            let piece = match self.pieces.pop_front() {
+                Some(piece) => piece,
+                None if self.done => break,
+                None => {
+                    self.read_block()?;
+                    continue;
+                }
+            };
+            let (level, text) = match piece {
+                Piece::Text(text) => {
+                    section.push_str(&text);
+                    continue;
+                }
+                Piece::Heading(level, text) => (level, text),
+            };
+            self.parents.retain(|(parent, _)| *parent < level);
+            let starts_section = level <= self.heading_level && !section.is_empty();
+            if starts_section {
+                let mut start = String::new();
+                if self.parent_headings {
+                    for (_, heading) in &self.parents {
+                        start.push_str(heading);
+                        start.push('\n');
+                    }
+                }
+                start.push_str(&text);
+                start.push('\n');
+                self.pending = Some(start);
+            }
+            if !starts_section {
+                section.push_str(&text);
+                section.push('\n');
+            }
+            self.parents.push((level, text));
+            if !starts_section {
+                continue;
+            } else if self.has_body(&section) {
+                return Ok(Some(section));
+            } else {
+                section = self.pending.take().unwrap_or_default();
+            }
+        }
+        Ok(self.has_body(&section).then_some(section))
+    }
+}
+
+impl<R: BufRead> Iterator for SectionReader<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_section().transpose()
+    }
+}
+
+/// A file decompressed and decoded to UTF-8 while it is read.
+pub type DecodedFile = BufReader<DecodeReaderBytes<Box<dyn Read + Send>, Vec<u8>>>;
+
+/// A file too large to load at once: generation reads it again section by section.
+#[derive(Debug, Clone)]
+pub struct StreamedFile {
+    pub encoding: &'static Encoding,
+    pub words: usize,
+    /// Non-blank sections at the file's heading level.
+    pub sections: usize,
+}
+
+impl StreamedFile {
+    /// The start of the file at `path` as text, with its encoding, or `None` when the file
+    /// is binary. The text ends at the last complete line.
+    pub fn head(path: &Path) -> io::Result<Option<(String, &'static Encoding)>> {
+        let mut bytes = Vec::with_capacity(HEAD_LEN);
+        compression::open(path)?.take(HEAD_LEN as u64).read_to_end(&mut bytes)?;
+        let Some(encoding) = encoding::detect(&bytes, false) else {
+            return Ok(None);
+        };
+        let (text, encoding, _) = encoding.decode(&bytes);
+        let text = match text.rfind('\n') {
+            Some(end) => text[..=end].to_string(),
+            None => text.into_owned(),
+        };
+        Ok(Some((text, encoding)))
+    }
+
+    /// Counts the words and sections of the file in one pass.
+    pub fn scan(path: &Path, encoding: &'static Encoding, chunking: &ChunkingConfig) -> io::Result<Self> {
+        let mut file = Self {
+            encoding,
+            words: 0,
+            sections: 0,
+        };
+        for section in file.sections(path, chunking)? {
+            file.words += section?.split_whitespace().count();
+            file.sections += 1;
+        }
+        Ok(file)
+    }
+
+    /// Reads the file's sections again, decoding it on the way.
+    pub fn sections(&self, path: &Path, chunking: &ChunkingConfig) -> io::Result<SectionReader<DecodedFile>> {
+        let reader = DecodeReaderBytesBuilder::new().encoding(Some(self.encoding)).build(compression::open(path)?);
+        Ok(SectionReader::new(BufReader::new(reader), chunking.heading_level).with_parent_headings(chunking.parent_headings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sections(text: &str, heading_level: usize, parent_headings: bool) -> Vec<String> {
+        SectionReader::new(text.as_bytes(), heading_level)
+            .with_parent_headings(parent_headings)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn splits_at_headings_up_to_the_level() {
+        let text = "Intro.\n\n# Install\n\nRun it.\n\n### Linux\n\nUse apt.\n\n## Usage\n\nCall it.\n";
+        assert_eq!(
+            sections(text, 2, false),
+            ["Intro.\n\n", "# Install\n\nRun it.\n\n### Linux\n\nUse apt.\n\n", "## Usage\n\nCall it.\n"]
+        );
+    }
+
+    #[test]
+    fn code_blocks_hold_their_hash_lines_and_blank_lines() {
+        let text = "# Setup\n\n```sh\n# not a heading\n\necho hi\n```\n\n# Next\n\nMore.\n";
+        assert_eq!(sections(text, 1, false), ["# Setup\n\n```sh\n# not a heading\n\necho hi\n```\n\n", "# Next\n\nMore.\n"]);
+    }
+
+    #[test]
+    fn setext_and_html_headings_become_atx_headings() {
+        let text = "Install\n=======\n\nRun it.\n\n<h1>Usage</h1>\n\nCall it.\n";
+        assert_eq!(sections(text, 1, false), ["# Install\n\nRun it.\n\n", "# Usage\n\nCall it.\n"]);
+    }
+
+    #[test]
+    fn frontmatter_stays_with_the_first_section() {
+        let text = "---\ntitle: Guide\n\nlayout: page\n---\n\n# Guide\n\nRead on.\n";
+        assert_eq!(sections(text, 1, false), ["---\ntitle: Guide\n\nlayout: page\n---\n\n", "# Guide\n\nRead on.\n"]);
+    }
+
+    #[test]
+    fn parent_headings_start_nested_sections() {
+        let text = "# Guide\n\n## Install\n\nRun it.\n\n### Linux\n\nUse apt.\n\n## Usage\n\nCall it.\n";
+        assert_eq!(
+            sections(text, 3, true),
+            [
+                "# Guide\n## Install\n\nRun it.\n\n",
+                "# Guide\n## Install\n### Linux\n\nUse apt.\n\n",
+                "# Guide\n## Usage\n\nCall it.\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_sections_are_dropped() {
+        assert!(sections("\n\n", 1, false).is_empty());
+        assert_eq!(sections("# Empty\n\n# Full\n\nText.\n", 1, true), ["# Full\n\nText.\n"]);
+    }
+}
@@ -0,0 +1,228 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use crate::config::StripConfig;
+use crate::html;
+use crate::markdown::{self, BlockKind};
+use crate::readability;
+
+/// Elements without a closing tag.
+pub(crate) const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+/// A simple CSS selector: a tag name, `.class`, `#id`, or a tag with a class or id.
+struct Selector {
+    tag: Option<String>,
+    class: Option<String>,
+    id: Option<String>,
+}
+
+impl Selector {
+    fn parse(selector: &str) -> Result<Self> {
+        let invalid = || anyhow!("Unsupported selector {:?}: use `tag`, `.class`, `#id`, `tag.class` or `tag#id`", selector);
+        let selector = selector.trim();
+        let split = selector.find(['.', '#']).unwrap_or(selector.len());
+        let (tag, rest) = selector.split_at(split);
+        let name = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        if !tag.is_empty() && !name(tag) {
+            return Err(invalid());
+        }
+        let (class, id) = match rest.chars().next() {
+            None if !tag.is_empty() => (None, None),
+            Some('.') if name(&rest[1..]) => (Some(rest[1..].to_string()), None),
+            Some('#') if name(&rest[1..]) => (None, Some(rest[1..].to_string())),
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            tag: Some(tag.to_lowercase()).filter(|tag| !tag.is_empty()),
+            class,
+            id,
+        })
+    }
+
+    /// Whether an element with tag `name` and the `class` and `id` attributes matches.
+    fn matches(&self, name: &str, class: Option<&str>, id: Option<&str>) -> bool {
+        self.tag.as_deref().is_none_or(|tag| tag.eq_ignore_ascii_case(name))
+            && self.class.as_deref().is_none_or(|wanted| class.is_some_and(|classes| classes.split_whitespace().any(|c| c == wanted)))
+            && self.id.as_deref().is_none_or(|wanted| id == Some(wanted))
+    }
+}
+
+/// Removes navigation, tables of contents, license footers and other boilerplate from a
+/// file's text before it is chunked, so it never reaches the model.
+pub struct Stripper {
+    /// Lowercase heading titles whose sections are dropped.
+    headings: Vec<String>,
+    patterns: Vec<Regex>,
+    selectors: Vec<Selector>,
+    main_content: bool,
+    tag: Regex,
+    /// `class` and `id` attributes of an opening tag.
+    class: Regex,
+    id: Regex,
+}
+
+impl Stripper {
+    pub fn new(config: &StripConfig) -> Result<Self> {
+        Ok(Self {
+            headings: config.headings.iter().map(|heading| heading.trim().to_lowercase()).collect(),
+            patterns: config
+                .patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern).map_err(|e| anyhow!("Invalid strip pattern {:?}: {}", pattern, e)))
+                .collect::<Result<_>>()?,
+            selectors: config.selectors.iter().map(|selector| Selector::parse(selector)).collect::<Result<_>>()?,
+            main_content: config.main_content,
+            tag: Regex::new(r"<(/?)([a-zA-Z][a-zA-Z0-9-]*)([^>]*?)(/?)>").unwrap(),
+            class: Regex::new(r#"(?i)\bclass\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap(),
+            id: Regex::new(r#"(?i)\bid\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headings.is_empty() && self.patterns.is_empty() && self.selectors.is_empty()
+    }
+
+    /// The text without boilerplate: matching HTML elements (when the text is HTML),
+    /// then matches of the patterns, then sections under matching headings. HTML is
+    /// turned into Markdown before the headings are looked for, keeping only the page's
+    /// main content with `main_content`.
+    pub fn strip(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        let html = is_html(&text);
+        if !self.selectors.is_empty() && html {
+            text = self.strip_elements(&text);
+        }
+        for pattern in &self.patterns {
+            text = pattern.replace_all(&text, "").into_owned();
+        }
+        if html {
+            text = match self.main_content {
+                true => html::to_markdown(&readability::main_content(&text)),
+                false => html::to_markdown(&text),
+            };
+        }
+        if !self.headings.is_empty() {
+            text = self.strip_sections(&text);
+        }
+        text
+    }
+
+    fn strip_elements(&self, html: &str) -> String {
+        let mut html = html.to_string();
+        let mut from = 0;
+        while let Some(open) = self.tag.captures_at(&html, from) {
+            let whole = open.get(0).unwrap();
+            let name = open[2].to_lowercase();
+            let is_opening = open[1].is_empty();
+            let attribute = |pattern: &Regex| {
+                pattern
+                    .captures(&open[3])
+                    .and_then(|captures| captures.get(1).or(captures.get(2)).or(captures.get(3)))
+                    .map(|value| value.as_str())
+            };
+            let (class, id) = (attribute(&self.class), attribute(&self.id));
+            if !is_opening || !self.selectors.iter().any(|selector| selector.matches(&name, class, id)) {
+                from = whole.end();
+                continue;
+            }
+            // Remove up to the matching closing tag, or just the tag when it has none
+            let mut end = whole.end();
+            if open[4].is_empty() && !VOID_ELEMENTS.contains(&name.as_str()) {
+                let mut depth = 1;
+                for tag in self.tag.captures_iter(&html[whole.end()..]) {
+                    if !tag[2].eq_ignore_ascii_case(&name) || !tag[4].is_empty() {
+                        continue;
+                    }
+                    depth = if tag[1].is_empty() { depth + 1 } else { depth - 1 };
+                    if depth == 0 {
+                        end = whole.end() + tag.get(0).unwrap().end();
+                        break;
+                    }
+                }
+                if depth > 0 {
+                    end = html.len();
+                }
+            }
+            let start = whole.start();
+            html.replace_range(start..end, "");
+            from = start;
+        }
+        html
+    }
+
+    /// Drops every Markdown heading with a listed title and everything under it, up to
+    /// the next heading of the same or a higher level.
+    fn strip_sections(&self, text: &str) -> String {
+        let mut kept = String::with_capacity(text.len());
+        let mut skipping: Option<usize> = None;
+        let mut from = 0;
+        for heading in markdown::headings(text) {
+            let BlockKind::Heading { level, title } = heading.kind else {
+                continue;
+            };
+            if skipping.is_none() {
+                kept.push_str(&text[from..heading.range.start]);
+            }
+            from = heading.range.start;
+            if skipping.is_some_and(|skipped| level <= skipped) {
+                skipping = None;
+            }
+            if skipping.is_none() && self.headings.contains(&title.to_lowercase()) {
+                skipping = Some(level);
+            }
+        }
+        if skipping.is_none() {
+            kept.push_str(&text[from..]);
+        }
+        kept
+    }
+}
+
+/// Whether `text` is an HTML document rather than Markdown or plain text.
+pub fn is_html(text: &str) -> bool {
+    let start = text.trim_start().get(..100).unwrap_or(text.trim_start()).to_lowercase();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripper(headings: &[&str], patterns: &[&str], selectors: &[&str]) -> Stripper {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        Stripper::new(&StripConfig {
+            headings: strings(headings),
+            patterns: strings(patterns),
+            selectors: strings(selectors),
+            main_content: false,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn sections_under_listed_headings_are_dropped() {
+        let text = "# Tool\n\nIntro.\n\n## Table of Contents\n\n- [Install](#install)\n\n### Nested\n\nStill dropped.\n\n## Install\n\nRun it.\n\n## License\n\nMIT\n";
+        let stripped = stripper(&["table of contents", " License "], &[], &[]).strip(text);
+        assert_eq!(stripped, "# Tool\n\nIntro.\n\n## Install\n\nRun it.\n\n");
+    }
+
+    #[test]
+    fn pattern_matches_are_removed() {
+        let stripper = stripper(&[], &[r"(?m)^\[!\[.*\n", "Edit this page"], &[]);
+        assert_eq!(stripper.strip("[![build](badge.svg)](ci)\n# Tool\nEdit this page\n"), "# Tool\n\n");
+    }
+
+    #[test]
+    fn matching_elements_are_removed_from_html() {
+        let html = "<!DOCTYPE html><html><body><div class=\"banner wide\"><div>Sale</div></div><p>Kept</p><div id=\"toc\"><a href=\"#a\">A</a></div><img class=\"banner\" src=\"x.png\"></body></html>";
+        let stripped = stripper(&[], &[], &[".banner", "div#toc"]).strip(html);
+        assert_eq!(stripped.trim(), "Kept");
+    }
+
+    #[test]
+    fn selectors_are_checked() {
+        assert!(Stripper::new(&StripConfig { selectors: vec!["div > p".to_string()], ..StripConfig::default() }).is_err());
+        assert!(stripper(&[], &[], &[]).is_empty());
+        assert!(is_html("  <!doctype html>\n<title>x</title>"));
+        assert!(!is_html("# <html> in Markdown"));
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use anyhow::Result;
+use tracing::{debug, info, warn};
+use crate::config::SynthesisConfig;
+use crate::dataset;
+use crate::duplicates::normalized;
+use crate::embeddings::{ChunkRecord, Embedder};
+use crate::processor::{OllamaProcessor, ProcessedItem};
+
+pub const SYSTEM_PROMPT: &str = "You answer questions about documentation using the excerpts you are given. \
+    Combine what every relevant excerpt says into one complete, self-contained answer; ignore excerpts that \
+    don't apply. Don't refer to the excerpts themselves, and don't add anything they don't support. \
+    Reply with the answer only.";
+
+/// Field listing the sections an answer was synthesized from, as `file#section`.
+pub const SOURCES_FIELD: &str = "context_sources";
+
+/// The user message asking `question` about `passages`.
+pub fn user_prompt(question: &str, passages: &[&ChunkRecord]) -> String {
+    let mut prompt = String::new();
+    for (i, passage) in passages.iter().enumerate() {
+        prompt.push_str(&format!("Excerpt {} (from {}):\n{}\n\n", i + 1, passage.file, passage.text.trim()));
+    }
+    prompt.push_str(&format!("Question: {}", question));
+    prompt
+}
+
+/// The embedded sections of every file in the run.
+#[derive(Default)]
+pub struct CorpusIndex {
+    /// Sections with unit-length embeddings, compared by dot product.
+    chunks: Vec<ChunkRecord>,
+}
+
+impl CorpusIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, chunks: &[ChunkRecord]) {
+        self.chunks.extend(chunks.iter().cloned().map(|chunk| ChunkRecord {
+            embedding: normalized(chunk.embedding),
+            ..chunk
+        }));
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// The `top_k` sections most similar to the unit-length `embedding`, most similar
+    /// first, leaving out those below `min_similarity`.
+    pub fn related(&self, embedding: &[f32], top_k: usize, min_similarity: f32) -> Vec<&ChunkRecord> {
+        let mut scored: Vec<(f32, &ChunkRecord)> = self
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.embedding.iter().zip(embedding).map(|(a, b)| a * b).sum::<f32>(), chunk))
+            .filter(|(similarity, _)| *similarity >= min_similarity)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().take(top_k).map(|(_, chunk)| chunk).collect()
+    }
+}
+
+/// Answers the questions of `items` again from the sections of `index` most related to
+/// each, replacing the answers and listing the sections in `context_sources`. Items
+/// whose answer can't be synthesized keep the one they were generated with. Usage is
+/// recorded for the items' source files in `output_dir`.
+pub async fn synthesize(
+    processor: &OllamaProcessor,
+    output_dir: &Path,
+    embedder: &Embedder,
+    index: &CorpusIndex,
+    config: &SynthesisConfig,
+    items: &mut [ProcessedItem],
+) -> Result<()> {
+    if items.is_empty() || index.is_empty() {
+        return Ok(());
+    }
+    info!("Answering {} questions from {} sections of the corpus", items.len(), index.len());
+    let questions: Vec<&str> = items.iter().map(|item| item.question.as_str()).collect();
+    let embeddings = embedder.embed(&questions).await?;
+    let total = items.len();
+    let mut synthesized = 0;
+    for (i, (item, embedding)) in items.iter_mut().zip(embeddings).enumerate() {
+        let passages = index.related(&normalized(embedding), config.top_k, config.min_similarity);
+        if passages.is_empty() {
+            debug!("No section is related closely enough to question {}/{}", i + 1, total);
+            continue;
+        }
+        let file = output_dir.join(item.source.as_ref().map(|source| source.file.as_str()).unwrap_or_default());
+        match processor.synthesize_answer(&file, &item.question, &passages).await {
+            Ok(answer) if !answer.is_empty() => {
+                item.answer = answer;
+                dataset::identify(item);
+                let sources: Vec<String> = passages.iter().map(|passage| format!("{}#{}", passage.file, passage.section + 1)).collect();
+                item.extra.insert(SOURCES_FIELD.to_string(), serde_json::json!(sources));
+                synthesized += 1;
+            }
+            Ok(_) => warn!("Empty synthesized answer for question {}/{}", i + 1, total),
+            Err(e) => warn!("Failed to synthesize an answer for question {}/{}: {}", i + 1, total, e),
+        }
+    }
+    info!("Synthesized {} of {} answers", synthesized, total);
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Builds an OTLP (gRPC) span exporter and the tracing layer that feeds it. The returned
+/// provider must be shut down before exit so buffered spans are flushed.
+pub fn otlp_layer<S>(endpoint: &str) -> Result<(OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>, TracerProvider)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", env!("CARGO_PKG_NAME"))]))
+        .build();
+
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+}
@@ -0,0 +1,99 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use crate::config::TermsConfig;
+use crate::processor::ProcessedItem;
+
+/// Characters that make a match part of a URL, path, package or identifier rather than
+/// prose, e.g. `github` in `github.com` or `ollama` in `ollama-python`.
+const IDENTIFIER_CHARS: &[char] = &['/', '\\', '_', '-', '@', '.'];
+
+/// Rewrites the spellings of configured product names, acronyms and other terms in
+/// questions and answers to one canonical form, so the dataset doesn't teach several.
+/// Inline code, URLs and identifiers are left alone.
+pub struct TermNormalizer {
+    /// Pattern matching every spelling of a term, and its canonical form; longest first.
+    rules: Vec<(Regex, String)>,
+}
+
+/// Case-insensitive whole-term match, also accepting spaces or hyphens between the
+/// words of a multi-word term.
+fn spelling_regex(term: &str) -> Result<Regex> {
+    let words: Vec<String> = term.split_whitespace().map(regex::escape).collect();
+    if words.is_empty() {
+        return Err(anyhow!("terms: empty term"));
+    }
+    let word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if word_char(term.trim().chars().next()) { r"\b" } else { "" };
+    let end = if word_char(term.trim().chars().last()) { r"\b" } else { "" };
+    Ok(Regex::new(&format!("(?i){}{}{}", start, words.join(r"[\s-]+"), end))?)
+}
+
+impl TermNormalizer {
+    /// `product_name` (from `prompts.product_name`) is a canonical term too.
+    pub fn new(config: &TermsConfig, product_name: Option<&str>) -> Result<Self> {
+        let mut spellings: Vec<(&str, &str)> = config
+            .canonical
+            .iter()
+            .map(String::as_str)
+            .chain(product_name)
+            .map(|term| (term, term))
+            .chain(config.aliases.iter().map(|(alias, term)| (alias.as_str(), term.as_str())))
+            .collect();
+        // "Ollama Cloud" before "Ollama"
+        spellings.sort_by_key(|(spelling, _)| std::cmp::Reverse(spelling.len()));
+        let rules = spellings
+            .into_iter()
+            .map(|(spelling, term)| Ok((spelling_regex(spelling)?, term.trim().to_string())))
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Normalizes the question and answer of `item`; returns whether either changed.
+    /// Citations are quotes of the source and stay as they are.
+    pub fn apply(&self, item: &mut ProcessedItem) -> bool {
+        let question = self.normalize(&item.question);
+        let answer = self.normalize(&item.answer);
+        let changed = question != item.question || answer != item.answer;
+        item.question = question;
+        item.answer = answer;
+        changed
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (pattern, term) in &self.rules {
+            let mut normalized = String::with_capacity(text.len());
+            let mut last = 0;
+            for found in pattern.find_iter(&text) {
+                normalized.push_str(&text[last..found.start()]);
+                let keep = in_code(&text[..found.start()])
+                    || text[..found.start()].ends_with(IDENTIFIER_CHARS)
+                    || is_identifier_continuation(&text[found.end()..]);
+                normalized.push_str(if keep { found.as_str() } else { term });
+                last = found.end();
+            }
+            normalized.push_str(&text[last..]);
+            text = normalized;
+        }
+        text
+    }
+}
+
+/// Whether text following `before` is inside an inline code span.
+fn in_code(before: &str) -> bool {
+    before.matches('`').count() % 2 == 1
+}
+
+/// Whether `after` continues an identifier, e.g. `.com` or `-python`, but not the end of
+/// a sentence.
+fn is_identifier_continuation(after: &str) -> bool {
+    let mut chars = after.chars();
+    match chars.next() {
+        Some(c) if IDENTIFIER_CHARS.contains(&c) => chars.next().is_some_and(|c| c.is_alphanumeric()),
+        _ => false,
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::Path;
+use std::sync::LazyLock;
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use crate::compression;
+use crate::config::TicketsConfig;
+use crate::processor::{ItemSource, ProcessedItem};
+
+/// Field holding the id of the ticket an item was converted from.
+pub const ID_FIELD: &str = "ticket_id";
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b[\w.+-]+@[\w-]+(?:\.[\w-]+)+\b").unwrap());
+
+/// Card numbers: 13 to 19 digits, in groups separated by spaces or dashes.
+static CARD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(?:\d[ -]?){12,18}\d\b").unwrap());
+
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{2,4}\)[ .-]?|\b\d{2,4}[ .-])\d{3,4}[ .-]\d{3,4}\b").unwrap()
+});
+
+static GREETING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(hi|hello|hey|dear|good (morning|afternoon|evening))\b[^.!?\n]{0,40}[,!]?\s*$").unwrap()
+});
+
+/// The line a signature starts at.
+static SIGN_OFF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(--\s*|(many |thanks|thank you|thx|cheers|best|regards|kind regards|best regards|warm regards|sincerely|all the best)\b[^.?:\n]{0,30}[,!.]?|sent from my \w+.*)$").unwrap()
+});
+
+/// Lines a signature may have after its sign-off: name, title, company, phone.
+const MAX_SIGNATURE_LINES: usize = 4;
+
+/// The line a quoted earlier message starts at, in replies.
+static QUOTE_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(on .{4,80} wrote:|-+ ?original message ?-+|from: .+)$").unwrap()
+});
+
+/// Whether `path` is a CSV file, which is read as a ticket export when it has the
+/// columns `[tickets]` maps.
+pub fn is_ticket_export(path: &Path) -> bool {
+    let path = compression::inner_path(path);
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+/// The records of CSV `text`: fields separated by commas, optionally in double quotes,
+/// which can hold commas, line breaks and doubled quotes.
+fn records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|record| record.iter().any(|field| !field.trim().is_empty()));
+    records
+}
+
+/// `text` without personal data: email addresses, card and phone numbers.
+fn redact(text: &str) -> String {
+    let text = EMAIL.replace_all(text, "<email>");
+    let text = CARD.replace_all(&text, "<card number>");
+    PHONE.replace_all(&text, "<phone>").into_owned()
+}
+
+/// The message in `text`, an email-style ticket body or reply: without the greeting,
+/// the signature and the earlier messages quoted below it. A sign-off only starts the
+/// signature after some of the message and with at most `MAX_SIGNATURE_LINES` after it,
+/// so messages opening with "Thanks for your patience!" keep their content.
+fn message(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if QUOTE_HEADER.is_match(trimmed) {
+            break;
+        }
+        if trimmed.starts_with('>') || (lines.iter().all(|line| line.trim().is_empty()) && GREETING.is_match(trimmed)) {
+            continue;
+        }
+        lines.push(line.trim_end());
+    }
+    let signature = (0..lines.len()).find(|&i| {
+        SIGN_OFF.is_match(lines[i].trim())
+            && lines[..i].iter().any(|line| !line.trim().is_empty())
+            && lines[i + 1..].iter().filter(|line| !line.trim().is_empty()).count() <= MAX_SIGNATURE_LINES
+    });
+    lines.truncate(signature.unwrap_or(lines.len()));
+    let message = lines.join("\n");
+    // At most one blank line between paragraphs
+    let mut cleaned = String::new();
+    for paragraph in message.split("\n\n").map(str::trim).filter(|paragraph| !paragraph.is_empty()) {
+        if !cleaned.is_empty() {
+            cleaned.push_str("\n\n");
+        }
+        cleaned.push_str(paragraph);
+    }
+    cleaned
+}
+
+/// The items of the ticket export `text`: one per resolved ticket, asking the subject
+/// and body of the ticket and answered with its resolution, each cleaned of greetings,
+/// signatures, quoted replies and, with `redact`, personal data. Items are numbered as
+/// sections of `file`. Fails when the header doesn't have the body and resolution columns.
+pub fn items(text: &str, file: &str, config: &TicketsConfig) -> Result<Vec<ProcessedItem>> {
+    let mut records = records(text).into_iter();
+    let header: Vec<String> = records.next().unwrap_or_default().iter().map(|name| name.trim().to_lowercase()).collect();
+    let column = |name: &str| header.iter().position(|column| *column == name.to_lowercase());
+    let (Some(body_column), Some(resolution_column)) = (column(&config.body), column(&config.resolution)) else {
+        return Err(anyhow!(
+            "No {:?} and {:?} columns for tickets.body and tickets.resolution",
+            config.body,
+            config.resolution
+        ));
+    };
+    let subject_column = column(&config.subject);
+    let status_column = column(&config.status);
+    let id_column = column(&config.id);
+    let clean = |text: &str| {
+        let text = message(text);
+        match config.redact {
+            true => redact(&text),
+            false => text,
+        }
+    };
+    let mut items = Vec::new();
+    for record in records {
+        let field = |column: Option<usize>| column.and_then(|column| record.get(column)).map(|field| field.trim()).unwrap_or("");
+        let status = field(status_column);
+        if status_column.is_some() && !config.resolved.iter().any(|resolved| resolved.eq_ignore_ascii_case(status)) {
+            continue;
+        }
+        let answer = clean(field(Some(resolution_column)));
+        let subject = clean(field(subject_column));
+        let body = clean(field(Some(body_column)));
+        let question = match (subject.is_empty(), body.is_empty()) {
+            (_, true) => subject,
+            (true, false) => body,
+            (false, false) if body.to_lowercase().starts_with(&subject.to_lowercase()) => body,
+            (false, false) => format!("{}\n\n{}", subject, body),
+        };
+        if question.is_empty() || answer.is_empty() {
+            continue;
+        }
+        let mut item = ProcessedItem {
+            id: None,
+            question,
+            answer,
+            citation: None,
+            source: Some(ItemSource {
+                file: file.to_string(),
+                section: items.len(),
+                chunk: None,
+                image: None,
+                modified: None,
+            }),
+            hash: None,
+            extra: serde_json::Map::new(),
+            embedding: None,
+            source_overlap: None,
+        };
+        let id = field(id_column);
+        if !id.is_empty() {
+            item.extra.insert(ID_FIELD.to_string(), id.into());
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_drops_greeting_signature_and_quotes() {
+        let body = "Hi Support,\n\n\
+            Since upgrading to 2.4 the export button does nothing.\n\
+            I tried Chrome and Firefox.\n\n\
+            Thanks,\n\
+            Jordan Lee\n\
+            Acme Corp\n\n\
+            On Mon, 3 Jun 2024 at 10:02, Support <help@example.com> wrote:\n\
+            > How can we help?";
+        assert_eq!(
+            message(body),
+            "Since upgrading to 2.4 the export button does nothing.\nI tried Chrome and Firefox."
+        );
+        assert_eq!(message("The sync never finishes.\n\nSent from my iPhone"), "The sync never finishes.");
+        assert_eq!(message("Logs attached.\n-- \nSam"), "Logs attached.");
+    }
+
+    #[test]
+    fn message_keeps_resolutions_opening_with_thanks() {
+        let resolution = "Thanks for your patience!\n\n\
+            The export was blocked by the new popup setting. Allow popups for the app domain \
+            under Settings > Privacy and the export starts again.\n\n\
+            Best regards,\n\
+            Priya";
+        assert_eq!(
+            message(resolution),
+            "Thanks for your patience!\n\nThe export was blocked by the new popup setting. Allow popups for the app domain \
+             under Settings > Privacy and the export starts again."
+        );
+        assert_eq!(
+            message("Thank you for reporting this!\nIt is fixed in 2.4.1, which rolls out this week."),
+            "Thank you for reporting this!\nIt is fixed in 2.4.1, which rolls out this week."
+        );
+    }
+
+    #[test]
+    fn message_keeps_lines_that_only_look_like_sign_offs() {
+        let resolution = "Best way to fix this is to:\n\n1. Sign out\n2. Clear the cache\n3. Sign in again";
+        assert_eq!(message(resolution), "Best way to fix this is to:\n\n1. Sign out\n2. Clear the cache\n3. Sign in again");
+
+        // A sign-off followed by more than a signature's worth of lines is part of the message
+        let resolution = "We reset your password.\n\nThanks!\nPlease also check:\n- the email address\n- the spam folder\n- the SSO settings\n- the audit log";
+        assert_eq!(message(resolution), resolution);
+    }
+
+    #[test]
+    fn items_come_from_resolved_tickets() {
+        let export = "id,subject,description,resolution,status\n\
+            101,Export broken,\"Hello,\n\nExport does nothing since 2.4. Call me on +1 555 123 4567.\n\nCheers,\nJo\",\"Thanks for your patience!\n\nAllow popups for the app domain.\n\nKind regards,\nPriya\",solved\n\
+            102,Still open,Nothing works,,open\n";
+        let items = items(export, "tickets.csv", &TicketsConfig::default()).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].question, "Export broken\n\nExport does nothing since 2.4. Call me on <phone>.");
+        assert_eq!(items[0].answer, "Thanks for your patience!\n\nAllow popups for the app domain.");
+        assert_eq!(items[0].extra[ID_FIELD], "101");
+    }
+}
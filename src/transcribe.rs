@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{debug, info};
+use crate::config::{TranscriptionApi, TranscriptionConfig};
+
+/// Audio formats sent for transcription.
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "m4a", "ogg", "flac"];
+
+/// Words per paragraph of a transcript, so it splits into sections like prose does.
+const PARAGRAPH_WORDS: usize = 150;
+
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Where the transcript of `path` is kept, so later runs don't transcribe it again:
+/// `episode.mp3.transcript` next to it.
+fn transcript_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".transcript");
+    path.with_file_name(name)
+}
+
+/// Transcribes audio files with a whisper.cpp server (`POST /inference`) or an
+/// OpenAI-compatible `/v1/audio/transcriptions` endpoint.
+pub struct Transcriber {
+    client: Client,
+    api: TranscriptionApi,
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+    language: Option<String>,
+}
+
+impl Transcriber {
+    /// A transcriber for `config`, or `None` when no endpoint is configured.
+    pub fn new(config: &TranscriptionConfig) -> Option<Self> {
+        let endpoint = config.endpoint.as_deref()?;
+        Some(Self {
+            client: Client::new(),
+            api: config.api,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+            api_key: config.api_key.clone(),
+            language: config.language.clone(),
+        })
+    }
+
+    /// The transcript of the audio file at `path`, from an earlier run when its
+    /// `.transcript` file is newer than the audio.
+    pub async fn transcript(&self, path: &Path) -> Result<String> {
+        let cached = transcript_path(path);
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        if modified(&cached).is_some_and(|cached| modified(path).is_none_or(|audio| cached >= audio)) {
+            debug!("Using the transcript of {:?} from {:?}", path, cached);
+            return Ok(fs::read_to_string(&cached)?);
+        }
+
+        info!("Transcribing {:?} with {}", path, self.endpoint);
+        let text = paragraphs(&self.transcribe(path).await?);
+        fs::write(&cached, &text).map_err(|e| anyhow!("Failed to write {:?}: {}", cached, e))?;
+        Ok(text)
+    }
+
+    async fn transcribe(&self, path: &Path) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Transcription {
+            text: String,
+        }
+
+        let audio = fs::read(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("audio");
+        let mut fields = vec![("response_format", "json")];
+        let url = match self.api {
+            TranscriptionApi::WhisperCpp => format!("{}/inference", self.endpoint),
+            TranscriptionApi::Openai => {
+                fields.push(("model", self.model.as_str()));
+                format!("{}/v1/audio/transcriptions", self.endpoint)
+            }
+        };
+        if let Some(language) = &self.language {
+            fields.push(("language", language));
+        }
+
+        // Built by hand, like the fine-tuning upload
+        let boundary = format!("llmds-{:016x}", rand::random::<u64>());
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value).into_bytes());
+        }
+        body.extend(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                boundary,
+                name.replace('"', "")
+            )
+            .into_bytes(),
+        );
+        body.extend(audio);
+        body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+
+        let mut request = self
+            .client
+            .post(url)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+            .body(body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!("Transcription of {:?} failed ({}): {}", path, status, body));
+        }
+        let transcription: Transcription =
+            serde_json::from_str(&body).map_err(|e| anyhow!("Unexpected transcription response {:?}: {}", body, e))?;
+        Ok(transcription.text)
+    }
+}
+
+/// `text` as paragraphs of about `PARAGRAPH_WORDS` words, broken after sentences.
+fn paragraphs(text: &str) -> String {
+    let mut result = String::new();
+    let mut words = 0;
+    for word in text.split_whitespace() {
+        if !result.is_empty() {
+            result.push_str(if words == 0 { "\n\n" } else { " " });
+        }
+        result.push_str(word);
+        words += 1;
+        if words >= PARAGRAPH_WORDS && word.ends_with(['.', '?', '!']) {
+            words = 0;
+        }
+    }
+    result.push('\n');
+    result
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+pub struct TokenUsage {
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Price of a model in currency units per one million tokens.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub struct ModelPricing {
+    #[serde(default)]
+    pub prompt_per_million: f64,
+    #[serde(default)]
+    pub completion_per_million: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        usage.prompt_tokens as f64 / 1_000_000.0 * self.prompt_per_million
+            + usage.completion_tokens as f64 / 1_000_000.0 * self.completion_per_million
+    }
+}
+
+/// Loads a pricing file mapping model names to their per-million-token prices, e.g.
+/// `{"gpt-4o-mini": {"prompt_per_million": 0.15, "completion_per_million": 0.6}}`.
+pub fn load_pricing(path: &Path) -> Result<HashMap<String, ModelPricing>> {
+    let content = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[derive(Default)]
+pub struct UsageTracker {
+    per_file: Mutex<BTreeMap<PathBuf, TokenUsage>>,
+    per_model: Mutex<BTreeMap<String, TokenUsage>>,
+}
+
+impl UsageTracker {
+    pub fn record(&self, file_path: &Path, model: &str, prompt_tokens: u64, completion_tokens: u64) {
+        let usage = TokenUsage {
+            requests: 1,
+            prompt_tokens,
+            completion_tokens,
+        };
+        self.per_file.lock().unwrap().entry(file_path.to_path_buf()).or_default().add(&usage);
+        self.per_model.lock().unwrap().entry(model.to_string()).or_default().add(&usage);
+    }
+
+    /// Adds usage reported by someone else, such as a distributed worker.
+    pub fn record_usage(&self, file_path: &Path, model: &str, usage: &TokenUsage) {
+        self.per_file.lock().unwrap().entry(file_path.to_path_buf()).or_default().add(usage);
+        self.per_model.lock().unwrap().entry(model.to_string()).or_default().add(usage);
+    }
+
+    pub fn file_usage(&self, file_path: &Path) -> TokenUsage {
+        self.per_file.lock().unwrap().get(file_path).copied().unwrap_or_default()
+    }
+
+    pub fn total(&self) -> TokenUsage {
+        let mut total = TokenUsage::default();
+        for usage in self.per_file.lock().unwrap().values() {
+            total.add(usage);
+        }
+        total
+    }
+
+    /// Cost of the run across all models, or `None` when no model used has a price.
+    pub fn estimated_cost(&self, pricing: &HashMap<String, ModelPricing>) -> Option<f64> {
+        let per_model = self.per_model.lock().unwrap();
+        let mut cost = None;
+        for (model, usage) in per_model.iter() {
+            if let Some(price) = pricing.get(model) {
+                *cost.get_or_insert(0.0) += price.cost(usage);
+            }
+        }
+        cost
+    }
+
+    pub fn print_summary(&self, pricing: &HashMap<String, ModelPricing>) {
+        let total = self.total();
+        if total.requests == 0 {
+            return;
+        }
+
+        info!("Token usage:");
+        for (path, usage) in self.per_file.lock().unwrap().iter() {
+            info!("  {:?}: {} requests, {} prompt + {} completion tokens",
+                path, usage.requests, usage.prompt_tokens, usage.completion_tokens);
+        }
+        for (model, usage) in self.per_model.lock().unwrap().iter() {
+            info!("  Model {}: {} requests, {} prompt + {} completion tokens",
+                model, usage.requests, usage.prompt_tokens, usage.completion_tokens);
+        }
+        info!("  Total: {} requests, {} prompt + {} completion = {} tokens",
+            total.requests, total.prompt_tokens, total.completion_tokens, total.total_tokens());
+
+        if let Some(cost) = self.estimated_cost(pricing) {
+            info!("  Estimated cost: ${:.4}", cost);
+        }
+    }
+}
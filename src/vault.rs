@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+use regex::{Captures, Regex};
+use serde_yaml::Value;
+use walkdir::WalkDir;
+use crate::frontmatter;
+use crate::stream::heading_level;
+
+/// Field holding the tags of the note an item was generated from.
+pub const TAGS_FIELD: &str = "tags";
+
+/// Directories marking the root of an Obsidian vault and of a Logseq graph, which hold
+/// their settings rather than notes.
+const OBSIDIAN_DIR: &str = ".obsidian";
+const LOGSEQ_DIR: &str = "logseq";
+
+/// Embedded notes resolved inside an embedded note; deeper ones become links.
+const MAX_EMBED_DEPTH: usize = 2;
+
+/// `[[Page]]`, `[[Page|alias]]`, `[[Page#Heading]]` and `[[Page#^block]]`, and embeds of
+/// them with a leading `!`.
+static WIKI_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(!?)\[\[([^\]|#]*)(?:#(\^?)([^\]|]*))?(?:\|([^\]]*))?\]\]").unwrap());
+
+/// A Logseq block reference, `((64f0c2a1-...))`.
+static BLOCK_REF: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\(\(([0-9a-fA-F-]{8,})\)\)").unwrap());
+
+/// An Obsidian block id at the end of a line, ` ^block-id`.
+static BLOCK_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s\^([\w-]+)\s*$").unwrap());
+
+/// A Logseq block property, `key:: value`, on a line of its own.
+static PROPERTY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*(?:- )?([\w-]+):: ?(.*)$").unwrap());
+
+/// `#tag`, `#nested/tag` or `#[[multi word tag]]`; tags have a letter in them, so `#42`
+/// is not one.
+static TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(^|[\s(])#(?:\[\[([^\]]+)\]\]|([\p{L}\p{N}_/-]*\p{L}[\p{L}\p{N}_/-]*))").unwrap()
+});
+
+/// The root of the vault `relative_path` is in, searched from its directory up to
+/// `output_dir`; `None` for files outside a vault.
+pub fn root(output_dir: &Path, relative_path: &Path) -> Option<PathBuf> {
+    relative_path
+        .parent()?
+        .ancestors()
+        .map(|dir| output_dir.join(dir))
+        .find(|dir| dir.join(OBSIDIAN_DIR).is_dir() || dir.join(LOGSEQ_DIR).is_dir())
+}
+
+/// Whether `relative_path` is in the settings directory of a vault, which is copied
+/// along to tell the vault apart but not read as notes.
+pub fn is_settings(relative_path: &Path) -> bool {
+    relative_path.components().any(|component| matches!(component, Component::Normal(name) if name == OBSIDIAN_DIR || name == LOGSEQ_DIR))
+}
+
+/// A note's text with its links resolved, and its tags.
+pub struct Note {
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+/// The notes of an Obsidian vault or Logseq graph, by page name, and their blocks, by id.
+pub struct Vault {
+    pages: HashMap<String, PathBuf>,
+    blocks: HashMap<String, String>,
+}
+
+impl Vault {
+    /// Reads the names and block ids of the notes under `root`.
+    pub fn load(root: &Path) -> Self {
+        let mut pages = HashMap::new();
+        let mut blocks = HashMap::new();
+        let notes = WalkDir::new(root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md")))
+            .filter(|entry| entry.path().strip_prefix(root).is_ok_and(|relative| !is_settings(relative)));
+        for entry in notes {
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(path).with_extension("");
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+            // Logseq writes `a/b` namespaces as `a___b`, or `a%2Fb` in older graphs
+            let name = stem.replace("___", "/").replace("%2F", "/");
+            pages.insert(name.to_lowercase(), path.to_path_buf());
+            pages.entry(relative.to_string_lossy().replace('\\', "/").to_lowercase()).or_insert_with(|| path.to_path_buf());
+            let Ok(text) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            Self::read_blocks(&text, &mut blocks);
+        }
+        Self { pages, blocks }
+    }
+
+    /// Adds the blocks of `text` that have ids: lines ending in ` ^id` (the paragraph
+    /// they end, outside lists) and Logseq blocks with an `id::` property.
+    fn read_blocks(text: &str, blocks: &mut HashMap<String, String>) {
+        let lines: Vec<&str> = text.lines().collect();
+        for (index, line) in lines.iter().enumerate() {
+            if let Some(captures) = BLOCK_ID.captures(line) {
+                let list_item = line.trim_start().starts_with(['-', '*', '+']);
+                let start = match list_item {
+                    true => index,
+                    false => lines[..index].iter().rposition(|line| line.trim().is_empty() || heading_level(line).is_some()).map_or(0, |end| end + 1),
+                };
+                let mut block: Vec<&str> = lines[start..index].to_vec();
+                block.push(&line[..captures.get(0).unwrap().start()]);
+                blocks.insert(captures[1].to_string(), block.join("\n").trim().to_string());
+            } else if let Some(captures) = PROPERTY.captures(line).filter(|captures| &captures[1] == "id") {
+                // The block is the bullet above its properties
+                if let Some(block) = lines[..index].iter().rev().find(|line| !PROPERTY.is_match(line)) {
+                    blocks.insert(captures[2].trim().to_string(), block.trim().trim_start_matches("- ").to_string());
+                }
+            }
+        }
+    }
+
+    fn page(&self, name: &str) -> Option<String> {
+        let name = name.trim().trim_end_matches(".md").to_lowercase();
+        let path = self.pages.get(&name)?;
+        let text = std::fs::read_to_string(path).ok()?;
+        Some(frontmatter::parse(&text).1.to_string())
+    }
+
+    /// The part of `text` under `heading`, down to the next heading of its level or above.
+    fn section(text: &str, heading: &str) -> Option<String> {
+        let mut lines = text.lines().skip_while(|line| heading_level(line).is_none_or(|level| !line[level..].trim().eq_ignore_ascii_case(heading.trim())));
+        let level = heading_level(lines.next()?)?;
+        Some(lines.take_while(|line| heading_level(line).is_none_or(|other| other > level)).collect::<Vec<_>>().join("\n"))
+    }
+
+    /// What an embed of `page` (and `heading` or `^block` in it) shows: the block, the
+    /// section or the whole note, with its own links resolved.
+    fn embed(&self, page: &str, block: bool, anchor: Option<&str>, depth: usize, tags: &mut Vec<String>) -> Option<String> {
+        let text = match (block, anchor) {
+            (true, Some(id)) => self.blocks.get(id)?.clone(),
+            (false, Some(heading)) if !heading.is_empty() => Self::section(&self.page(page)?, heading)?,
+            _ => self.page(page)?,
+        };
+        Some(self.resolve(&text, depth + 1, tags).trim().to_string())
+    }
+
+    /// `text` with embeds and block references replaced by what they show, links by their
+    /// text, tags by their words, and Logseq properties and Obsidian block ids removed.
+    /// Tags are added to `tags`.
+    fn resolve(&self, text: &str, depth: usize, tags: &mut Vec<String>) -> String {
+        let mut lines = Vec::new();
+        let mut fence = false;
+        for line in text.lines() {
+            if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+                fence = !fence;
+            }
+            if fence || line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+                lines.push(line.to_string());
+                continue;
+            }
+            if let Some(captures) = PROPERTY.captures(line) {
+                if matches!(&captures[1], "tags" | "tag") {
+                    for tag in captures[2].split(',') {
+                        add_tag(tags, tag.trim().trim_start_matches('#').trim_start_matches("[[").trim_end_matches("]]"));
+                    }
+                }
+                continue;
+            }
+            let line = BLOCK_ID.replace(line, "");
+            // A line that only lists tags is metadata, not content
+            let tag_line = !line.trim().is_empty() && TAG.replace_all(&line, "").trim().is_empty();
+            let line = TAG.replace_all(&line, |captures: &Captures| {
+                let tag = captures.get(2).or(captures.get(3)).map_or("", |tag| tag.as_str());
+                add_tag(tags, tag);
+                format!("{}{}", &captures[1], tag)
+            });
+            if tag_line {
+                continue;
+            }
+            let line = BLOCK_REF.replace_all(&line, |captures: &Captures| match self.blocks.get(&captures[1]) {
+                Some(block) if depth < MAX_EMBED_DEPTH => self.resolve(block, depth + 1, tags),
+                Some(block) => block.clone(),
+                None => String::new(),
+            });
+            let line = WIKI_LINK.replace_all(&line, |captures: &Captures| {
+                let page = captures[2].trim();
+                let block = captures.get(3).is_some_and(|caret| !caret.is_empty());
+                let anchor = captures.get(4).map(|anchor| anchor.as_str());
+                let text = captures.get(5).map(|alias| alias.as_str().trim()).filter(|alias| !alias.is_empty()).unwrap_or(page);
+                if captures[1].is_empty() || depth >= MAX_EMBED_DEPTH {
+                    return text.to_string();
+                }
+                // Embedded pictures and other attachments have no text to show
+                let attachment = Path::new(page).extension().is_some_and(|ext| !ext.eq_ignore_ascii_case("md"));
+                match attachment {
+                    true => String::new(),
+                    false => self.embed(page, block, anchor, depth, tags).unwrap_or_else(|| text.to_string()),
+                }
+            });
+            lines.push(line.into_owned());
+        }
+        lines.join("\n")
+    }
+
+    /// `content`, a note of the vault, with its links resolved, and its tags from the
+    /// frontmatter, `tags::` properties and the text.
+    pub fn note(&self, content: &str) -> Note {
+        let mut tags = Vec::new();
+        let (frontmatter, body) = frontmatter::parse(content);
+        match frontmatter.as_ref().and_then(|frontmatter| frontmatter.get("tags")) {
+            Some(Value::Sequence(values)) => values.iter().filter_map(Value::as_str).for_each(|tag| add_tag(&mut tags, tag)),
+            Some(Value::String(value)) => value.split([',', ' ']).for_each(|tag| add_tag(&mut tags, tag)),
+            _ => {}
+        }
+        // The frontmatter stays for the settings it holds
+        let text = format!("{}{}", &content[..content.len() - body.len()], self.resolve(body, 0, &mut tags));
+        Note { text, tags }
+    }
+}
+
+/// Adds `tag` to `tags` unless it is empty or already there in any case.
+fn add_tag(tags: &mut Vec<String>, tag: &str) {
+    let tag = tag.trim().trim_start_matches('#');
+    if !tag.is_empty() && !tags.iter().any(|known| known.eq_ignore_ascii_case(tag)) {
+        tags.push(tag.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A vault in a new directory with `notes`, by path.
+    fn vault(notes: &[(&str, &str)]) -> (PathBuf, Vault) {
+        let dir = std::env::temp_dir().join(format!("llmds-vault-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(dir.join(OBSIDIAN_DIR)).unwrap();
+        fs::write(dir.join(OBSIDIAN_DIR).join("app.md"), "Settings, not a note.").unwrap();
+        for (path, text) in notes {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let vault = Vault::load(&dir);
+        (dir, vault)
+    }
+
+    #[test]
+    fn vaults_are_found_above_their_notes() {
+        let (dir, _) = vault(&[("guides/setup.md", "")]);
+        let output_dir = dir.parent().unwrap();
+        let relative = dir.strip_prefix(output_dir).unwrap();
+        assert_eq!(root(output_dir, &relative.join("guides/setup.md")), Some(dir.clone()));
+        assert_eq!(root(output_dir, Path::new("elsewhere/setup.md")), None);
+        assert!(is_settings(&relative.join(".obsidian/app.md")));
+        assert!(!is_settings(&relative.join("guides/setup.md")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn links_and_embeds_are_resolved() {
+        let (dir, vault) = vault(&[
+            ("guides/Setup.md", "---\ntitle: Setup\n---\n# Setup\n\n## Install\n\nRun the installer.\n\n## Configure\n\nEdit the config.\n\nKeep a backup. ^backup\n"),
+            ("Tools___CLI.md", "The CLI takes flags.\n"),
+        ]);
+        let note = vault.note(
+            "Read [[Setup|the setup guide]] and [[Tools/CLI]].\n\n\
+             ![[Setup#Install]]\n\n\
+             Remember: ![[Setup#^backup]]\n\n\
+             ![[diagram.png]]\n\n\
+             ![[Missing page]]\n",
+        );
+        assert_eq!(
+            note.text,
+            "Read the setup guide and Tools/CLI.\n\nRun the installer.\n\nRemember: Keep a backup.\n\n\n\nMissing page"
+        );
+        assert!(vault.note("![[tools/cli]]").text.starts_with("The CLI takes flags."));
+        assert!(vault.page("app").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tags_are_gathered_from_everywhere() {
+        let (dir, vault) = vault(&[("pages/install.md", "- Install it\n  id:: 64f0c2a1-1111-2222-3333-444455556666\n")]);
+        let note = vault.note(
+            "---\ntags: [setup, Linux]\n---\ntags:: [[getting started]], #linux\n#ops #infra\n\
+             Works on #linux, not on #42.\nSee ((64f0c2a1-1111-2222-3333-444455556666)).\n",
+        );
+        assert_eq!(note.tags, ["setup", "Linux", "getting started", "ops", "infra"]);
+        assert_eq!(note.text, "---\ntags: [setup, Linux]\n---\nWorks on linux, not on #42.\nSee Install it.");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
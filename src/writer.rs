@@ -0,0 +1,1110 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use arrow_array::builder::{Float32Builder, ListBuilder};
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::{FileWriter, StreamWriter};
+use arrow_schema::{DataType, Field, SchemaRef};
+use async_trait::async_trait;
+use parquet::arrow::parquet_to_arrow_schema;
+use parquet::data_type::{ByteArray, ByteArrayType, FloatType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::SchemaDescriptor;
+use parquet::schema::parser::parse_message_type;
+use reqwest::Client;
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use crate::config::{OutputFormat, SinkConfig};
+use crate::dataset::{self, FieldMapping, RecordFormat, RecordWriter, SystemPrompts};
+use crate::embeddings::ChunkRecord;
+use crate::processor::ProcessedItem;
+
+/// A destination for a run's items. A run streams its items to the combined dataset
+/// file and every configured sink as files finish.
+#[async_trait]
+pub trait DatasetWriter: Send + Sync {
+    /// Where the items go, for logs.
+    fn describe(&self) -> String;
+
+    /// Replaces the destination's contents with `items`.
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()>;
+
+    /// Starts replacing the destination's contents with items that arrive in batches.
+    /// Sinks that can't write incrementally keep the items and `write` them on finish.
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        Ok(Box::new(BufferedStream { writer: self, items: Vec::new() }))
+    }
+
+    /// Embedded sections of the run's files. Only sinks with somewhere to put them
+    /// implement this.
+    async fn write_chunks(&self, _chunks: &[ChunkRecord]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Items being written to a sink as they are produced; see `DatasetWriter::open`.
+#[async_trait]
+pub trait ItemStream: Send {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()>;
+
+    /// Writes whatever is still pending and completes the output.
+    async fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct BufferedStream<'a, W: ?Sized> {
+    writer: &'a W,
+    items: Vec<ProcessedItem>,
+}
+
+#[async_trait]
+impl<W: DatasetWriter + ?Sized> ItemStream for BufferedStream<'_, W> {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        self.items.extend_from_slice(items);
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        self.writer.write(&self.items).await
+    }
+}
+
+/// Builds the writer for a sink; file paths are resolved against `output_dir`.
+pub fn from_config(sink: &SinkConfig, output_dir: &Path) -> Box<dyn DatasetWriter> {
+    match sink {
+        SinkConfig::Jsonl { path, format, system_prompt, mapping } => Box::new(
+            Writer::new(output_dir.join(path))
+                .with_format(*format)
+                .with_system_prompts(system_prompt.clone())
+                .with_mapping(mapping.clone()),
+        ),
+        SinkConfig::Parquet { path } => Box::new(ParquetWriter::new(output_dir.join(path))),
+        SinkConfig::Arrow { path } => Box::new(ArrowWriter::new(output_dir.join(path))),
+        SinkConfig::Sqlite { path, table } => Box::new(SqliteWriter::new(output_dir.join(path), table)),
+        SinkConfig::Http { url, headers, batch_size, format, system_prompt, mapping } => Box::new(
+            HttpWriter::new(url)
+                .with_headers(headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+                .with_batch_size(*batch_size)
+                .with_format(*format)
+                .with_system_prompts(system_prompt.clone())
+                .with_mapping(mapping.clone()),
+        ),
+        SinkConfig::Stdout { format, system_prompt, mapping } => Box::new(
+            StdoutWriter::new(*format)
+                .with_system_prompts(system_prompt.clone())
+                .with_mapping(mapping.clone()),
+        ),
+    }
+}
+
+/// Writes items to a JSONL file, or a JSON array.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    path: PathBuf,
+    layout: OutputFormat,
+    format: RecordFormat,
+    system_prompts: SystemPrompts,
+    mapping: FieldMapping,
+}
+
+impl Writer {
+    /// A writer for `path`; a `.json` extension writes an array, anything else JSONL. A
+    /// further `.gz` or `.zst` extension compresses the file.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            layout: dataset::layout_for(path.as_ref()),
+            path: path.as_ref().to_path_buf(),
+            format: RecordFormat::Qa,
+            system_prompts: SystemPrompts::default(),
+            mapping: FieldMapping::default(),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: OutputFormat) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Record format: plain question/answer, ShareGPT or OpenAI chat messages.
+    pub fn with_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// System turns of chat-format records.
+    pub fn with_system_prompts(mut self, system_prompts: SystemPrompts) -> Self {
+        self.system_prompts = system_prompts;
+        self
+    }
+
+    /// Renames and adds fields of every record.
+    pub fn with_mapping(mut self, mapping: FieldMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    fn create(&self) -> Result<RecordWriter> {
+        Ok(RecordWriter::create(&self.path, self.layout, self.format)?
+            .with_system_prompts(self.system_prompts.clone())
+            .with_mapping(self.mapping.clone()))
+    }
+}
+
+#[async_trait]
+impl DatasetWriter for Writer {
+    fn describe(&self) -> String {
+        format!("{:?}", self.path)
+    }
+
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()> {
+        dataset::write_records(self.create()?, items)
+    }
+
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        Ok(Box::new(self.create()?))
+    }
+}
+
+#[async_trait]
+impl ItemStream for RecordWriter {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        for item in items {
+            self.write(item)?;
+        }
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        RecordWriter::finish(*self)
+    }
+}
+
+/// Writes items to a Parquet file with `id`, `question`, `answer`, `source_file`,
+/// `source_section`, `source_chunk`, `source_image`, `source_modified`, `extra` (the
+/// remaining fields as JSON), `hash` and `embedding` columns.
+/// Embedded chunks go to `<name>_chunks.parquet` next to it.
+pub struct ParquetWriter {
+    path: PathBuf,
+}
+
+const ITEM_SCHEMA: &str = "
+    message item {
+        OPTIONAL BYTE_ARRAY id (UTF8);
+        REQUIRED BYTE_ARRAY question (UTF8);
+        REQUIRED BYTE_ARRAY answer (UTF8);
+        OPTIONAL BYTE_ARRAY citation (UTF8);
+        OPTIONAL BYTE_ARRAY source_file (UTF8);
+        OPTIONAL INT64 source_section;
+        OPTIONAL BYTE_ARRAY source_chunk (UTF8);
+        OPTIONAL BYTE_ARRAY source_image (UTF8);
+        OPTIONAL BYTE_ARRAY source_modified (UTF8);
+        OPTIONAL BYTE_ARRAY extra (UTF8);
+        OPTIONAL BYTE_ARRAY hash (UTF8);
+        OPTIONAL group embedding (LIST) {
+            REPEATED group list {
+                REQUIRED FLOAT element;
+            }
+        }
+    }
+";
+
+const CHUNK_SCHEMA: &str = "
+    message chunk {
+        REQUIRED BYTE_ARRAY file (UTF8);
+        REQUIRED INT64 section;
+        REQUIRED BYTE_ARRAY chunk_id (UTF8);
+        REQUIRED BYTE_ARRAY text (UTF8);
+        OPTIONAL group embedding (LIST) {
+            REPEATED group list {
+                REQUIRED FLOAT element;
+            }
+        }
+    }
+";
+
+/// Values of one Parquet column, with definition levels for optional columns and
+/// repetition levels for lists.
+enum Column {
+    Text(Vec<ByteArray>, Option<Vec<i16>>),
+    Int(Vec<i64>, Option<Vec<i16>>),
+    Floats(Vec<f32>, Vec<i16>, Vec<i16>),
+}
+
+impl Column {
+    fn text<'a>(values: impl Iterator<Item = &'a str>) -> Self {
+        Column::Text(values.map(ByteArray::from).collect(), None)
+    }
+
+    fn optional_text(values: impl Iterator<Item = Option<String>>) -> Self {
+        let (values, levels) = Self::optional(values);
+        Column::Text(values.into_iter().map(|v| ByteArray::from(v.into_bytes())).collect(), Some(levels))
+    }
+
+    fn optional_int(values: impl Iterator<Item = Option<i64>>) -> Self {
+        let (values, levels) = Self::optional(values);
+        Column::Int(values, Some(levels))
+    }
+
+    /// An optional list of floats per row.
+    fn float_lists<'a>(rows: impl Iterator<Item = Option<&'a [f32]>>) -> Self {
+        let (mut values, mut definitions, mut repetitions) = (Vec::new(), Vec::new(), Vec::new());
+        for row in rows {
+            match row {
+                None => {
+                    definitions.push(0);
+                    repetitions.push(0);
+                }
+                Some([]) => {
+                    definitions.push(1);
+                    repetitions.push(0);
+                }
+                Some(list) => {
+                    for (i, value) in list.iter().enumerate() {
+                        values.push(*value);
+                        definitions.push(2);
+                        repetitions.push(if i == 0 { 0 } else { 1 });
+                    }
+                }
+            }
+        }
+        Column::Floats(values, definitions, repetitions)
+    }
+
+    fn optional<T>(rows: impl Iterator<Item = Option<T>>) -> (Vec<T>, Vec<i16>) {
+        let mut values = Vec::new();
+        let mut levels = Vec::new();
+        for row in rows {
+            match row {
+                Some(v) => {
+                    values.push(v);
+                    levels.push(1);
+                }
+                None => levels.push(0),
+            }
+        }
+        (values, levels)
+    }
+
+    /// The values back in rows, `None` where the definition level is 0.
+    fn rows<T>(values: Vec<T>, levels: Option<Vec<i16>>) -> Vec<Option<T>> {
+        let Some(levels) = levels else {
+            return values.into_iter().map(Some).collect();
+        };
+        let mut values = values.into_iter();
+        levels.iter().map(|level| if *level == 0 { None } else { values.next() }).collect()
+    }
+
+    /// The column as an Arrow array of `field`'s type.
+    fn into_array(self, field: &Field) -> Result<ArrayRef> {
+        Ok(match self {
+            Column::Text(values, levels) => {
+                let rows = Self::rows(values, levels)
+                    .into_iter()
+                    .map(|value| value.map(|value| value.as_utf8().map(str::to_string)).transpose())
+                    .collect::<Result<Vec<_>, _>>()?;
+                Arc::new(StringArray::from(rows))
+            }
+            Column::Int(values, levels) => Arc::new(Int64Array::from(Self::rows(values, levels))),
+            Column::Floats(values, definitions, repetitions) => {
+                let DataType::List(element) = field.data_type() else {
+                    return Err(anyhow!("Arrow field {:?} is not a list", field.name()));
+                };
+                let mut lists = ListBuilder::new(Float32Builder::new()).with_field(element.clone());
+                let mut values = values.into_iter();
+                for (i, (definition, repetition)) in definitions.iter().zip(&repetitions).enumerate() {
+                    // A new row starts at repetition level 0; the previous one ends there
+                    if *repetition == 0 && i > 0 {
+                        lists.append(definitions[i - 1] > 0);
+                    }
+                    if *definition == 2 {
+                        lists.values().append_option(values.next());
+                    }
+                }
+                if let Some(last) = definitions.last() {
+                    lists.append(*last > 0);
+                }
+                Arc::new(lists.finish())
+            }
+        })
+    }
+}
+
+impl ParquetWriter {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self { path: path.as_ref().to_path_buf() }
+    }
+
+    fn chunks_path(&self) -> PathBuf {
+        let stem = self.path.file_stem().and_then(|s| s.to_str()).unwrap_or("items");
+        self.path.with_file_name(format!("{}_chunks.parquet", stem))
+    }
+
+    fn create(path: &Path, schema: &str) -> Result<SerializedFileWriter<fs::File>> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let schema = Arc::new(parse_message_type(schema)?);
+        Ok(SerializedFileWriter::new(fs::File::create(path)?, schema, Arc::new(WriterProperties::default()))?)
+    }
+
+    /// Writes `columns`, in schema order, as one row group.
+    fn write_row_group(writer: &mut SerializedFileWriter<fs::File>, columns: Vec<Column>) -> Result<()> {
+        let mut row_group = writer.next_row_group()?;
+        let mut columns = columns.into_iter();
+        while let Some(mut column_writer) = row_group.next_column()? {
+            let column = columns.next().ok_or_else(|| anyhow!("Parquet schema has more columns than values"))?;
+            match column {
+                Column::Text(values, levels) => {
+                    column_writer.typed::<ByteArrayType>().write_batch(&values, levels.as_deref(), None)?;
+                }
+                Column::Int(values, levels) => {
+                    column_writer.typed::<Int64Type>().write_batch(&values, levels.as_deref(), None)?;
+                }
+                Column::Floats(values, definitions, repetitions) => {
+                    column_writer.typed::<FloatType>().write_batch(&values, Some(&definitions), Some(&repetitions))?;
+                }
+            }
+            column_writer.close()?;
+        }
+        row_group.close()?;
+        Ok(())
+    }
+
+    fn item_columns(items: &[ProcessedItem]) -> Vec<Column> {
+        vec![
+            Column::optional_text(items.iter().map(|item| item.id.clone())),
+            Column::text(items.iter().map(|item| item.question.as_str())),
+            Column::text(items.iter().map(|item| item.answer.as_str())),
+            Column::optional_text(items.iter().map(|item| item.citation.clone())),
+            Column::optional_text(items.iter().map(|item| item.source.as_ref().map(|s| s.file.clone()))),
+            Column::optional_int(items.iter().map(|item| item.source.as_ref().map(|s| s.section as i64))),
+            Column::optional_text(items.iter().map(|item| item.source.as_ref().and_then(|s| s.chunk.clone()))),
+            Column::optional_text(items.iter().map(|item| item.source.as_ref().and_then(|s| s.image.clone()))),
+            Column::optional_text(items.iter().map(|item| item.source.as_ref().and_then(|s| s.modified).map(|d| d.to_string()))),
+            Column::optional_text(items.iter().map(|item| {
+                (!item.extra.is_empty()).then(|| Value::Object(item.extra.clone()).to_string())
+            })),
+            Column::optional_text(items.iter().map(|item| item.hash.clone())),
+            Column::float_lists(items.iter().map(|item| item.embedding.as_deref())),
+        ]
+    }
+
+    fn chunk_columns(chunks: &[ChunkRecord]) -> Vec<Column> {
+        vec![
+            Column::text(chunks.iter().map(|chunk| chunk.file.as_str())),
+            Column::Int(chunks.iter().map(|chunk| chunk.section as i64).collect(), None),
+            Column::text(chunks.iter().map(|chunk| chunk.id.as_str())),
+            Column::text(chunks.iter().map(|chunk| chunk.text.as_str())),
+            Column::float_lists(chunks.iter().map(|chunk| Some(chunk.embedding.as_slice()))),
+        ]
+    }
+}
+
+/// Items per Parquet row group.
+const ROW_GROUP_SIZE: usize = 10_000;
+
+#[async_trait]
+impl DatasetWriter for ParquetWriter {
+    fn describe(&self) -> String {
+        format!("{:?}", self.path)
+    }
+
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()> {
+        let mut writer = Self::create(&self.path, ITEM_SCHEMA)?;
+        for batch in items.chunks(ROW_GROUP_SIZE) {
+            Self::write_row_group(&mut writer, Self::item_columns(batch))?;
+        }
+        writer.close()?;
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        Ok(Box::new(ParquetStream {
+            writer: Self::create(&self.path, ITEM_SCHEMA)?,
+            pending: Vec::new(),
+        }))
+    }
+
+    async fn write_chunks(&self, chunks: &[ChunkRecord]) -> Result<()> {
+        let mut writer = Self::create(&self.chunks_path(), CHUNK_SCHEMA)?;
+        Self::write_row_group(&mut writer, Self::chunk_columns(chunks))?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+/// Collects items into row groups of `ROW_GROUP_SIZE`.
+struct ParquetStream {
+    writer: SerializedFileWriter<fs::File>,
+    pending: Vec<ProcessedItem>,
+}
+
+#[async_trait]
+impl ItemStream for ParquetStream {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        self.pending.extend_from_slice(items);
+        while self.pending.len() >= ROW_GROUP_SIZE {
+            let batch: Vec<ProcessedItem> = self.pending.drain(..ROW_GROUP_SIZE).collect();
+            ParquetWriter::write_row_group(&mut self.writer, ParquetWriter::item_columns(&batch))?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> Result<()> {
+        if !self.pending.is_empty() {
+            ParquetWriter::write_row_group(&mut self.writer, ParquetWriter::item_columns(&self.pending))?;
+        }
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// Writes items to an Arrow IPC file with the same columns as the Parquet writer, so
+/// Python and Polars consumers can memory-map the dataset. A `.arrows` path is written
+/// in the IPC streaming format instead. Embedded chunks go to `<name>_chunks.arrow` (or
+/// `.arrows`).
+pub struct ArrowWriter {
+    path: PathBuf,
+}
+
+/// An Arrow IPC file or stream being written.
+enum IpcWriter {
+    File(FileWriter<BufWriter<fs::File>>),
+    Stream(StreamWriter<BufWriter<fs::File>>),
+}
+
+impl IpcWriter {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            IpcWriter::File(writer) => writer.write(batch)?,
+            IpcWriter::Stream(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        let mut file = match self {
+            IpcWriter::File(writer) => writer.into_inner()?,
+            IpcWriter::Stream(writer) => writer.into_inner()?,
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl ArrowWriter {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self { path: path.as_ref().to_path_buf() }
+    }
+
+    fn chunks_path(&self) -> PathBuf {
+        let stem = self.path.file_stem().and_then(|s| s.to_str()).unwrap_or("items");
+        let extension = self.path.extension().and_then(|s| s.to_str()).unwrap_or("arrow");
+        self.path.with_file_name(format!("{}_chunks.{}", stem, extension))
+    }
+
+    /// The Arrow schema of a Parquet schema, so both sinks write the same columns.
+    fn schema(parquet_schema: &str) -> Result<SchemaRef> {
+        let descriptor = SchemaDescriptor::new(Arc::new(parse_message_type(parquet_schema)?));
+        Ok(Arc::new(parquet_to_arrow_schema(&descriptor, None)?))
+    }
+
+    fn create(path: &Path, schema: &SchemaRef) -> Result<IpcWriter> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = BufWriter::new(fs::File::create(path)?);
+        Ok(match path.extension().and_then(|ext| ext.to_str()) {
+            Some("arrows") => IpcWriter::Stream(StreamWriter::try_new(file, schema)?),
+            _ => IpcWriter::File(FileWriter::try_new(file, schema)?),
+        })
+    }
+
+    /// `columns`, in schema order, as one record batch.
+    fn batch(schema: &SchemaRef, columns: Vec<Column>) -> Result<RecordBatch> {
+        let arrays = columns
+            .into_iter()
+            .zip(schema.fields())
+            .map(|(column, field)| column.into_array(field))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+    }
+}
+
+#[async_trait]
+impl DatasetWriter for ArrowWriter {
+    fn describe(&self) -> String {
+        format!("{:?}", self.path)
+    }
+
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()> {
+        let schema = Self::schema(ITEM_SCHEMA)?;
+        let mut writer = Self::create(&self.path, &schema)?;
+        for batch in items.chunks(ROW_GROUP_SIZE) {
+            writer.write(&Self::batch(&schema, ParquetWriter::item_columns(batch))?)?;
+        }
+        writer.finish()
+    }
+
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        let schema = Self::schema(ITEM_SCHEMA)?;
+        Ok(Box::new(ArrowStream {
+            writer: Self::create(&self.path, &schema)?,
+            schema,
+            pending: Vec::new(),
+        }))
+    }
+
+    async fn write_chunks(&self, chunks: &[ChunkRecord]) -> Result<()> {
+        let schema = Self::schema(CHUNK_SCHEMA)?;
+        let mut writer = Self::create(&self.chunks_path(), &schema)?;
+        writer.write(&Self::batch(&schema, ParquetWriter::chunk_columns(chunks))?)?;
+        writer.finish()
+    }
+}
+
+/// Collects items into record batches of `ROW_GROUP_SIZE`.
+struct ArrowStream {
+    writer: IpcWriter,
+    schema: SchemaRef,
+    pending: Vec<ProcessedItem>,
+}
+
+#[async_trait]
+impl ItemStream for ArrowStream {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        self.pending.extend_from_slice(items);
+        while self.pending.len() >= ROW_GROUP_SIZE {
+            let batch: Vec<ProcessedItem> = self.pending.drain(..ROW_GROUP_SIZE).collect();
+            self.writer.write(&ArrowWriter::batch(&self.schema, ParquetWriter::item_columns(&batch))?)?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.writer.write(&ArrowWriter::batch(&self.schema, ParquetWriter::item_columns(&self.pending))?)?;
+        }
+        self.writer.finish()
+    }
+}
+
+/// Replaces a SQLite table with the items, one row each with the same columns as the
+/// Parquet writer; embeddings are stored as little-endian `f32` blobs. Embedded chunks
+/// go to `<table>_chunks`.
+pub struct SqliteWriter {
+    path: PathBuf,
+    table: String,
+}
+
+impl SqliteWriter {
+    pub fn new<P: AsRef<Path>>(path: P, table: &str) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            table: table.to_string(),
+        }
+    }
+}
+
+fn embedding_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+impl SqliteWriter {
+    /// Opens the database after checking the table name, which can't be a parameter.
+    fn connect(&self) -> Result<Connection> {
+        if !self.table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!("Invalid SQLite table name {:?}", self.table));
+        }
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Connection::open(&self.path)?)
+    }
+
+    fn create_table(conn: &Connection, table: &str) -> Result<()> {
+        conn.execute_batch(&format!(
+            "DROP TABLE IF EXISTS {table};
+             CREATE TABLE {table} (
+                id INTEGER PRIMARY KEY,
+                item_id TEXT,
+                question TEXT NOT NULL,
+                answer TEXT NOT NULL,
+                citation TEXT,
+                source_file TEXT,
+                source_section INTEGER,
+                source_chunk TEXT,
+                source_image TEXT,
+                source_modified TEXT,
+                extra TEXT,
+                hash TEXT,
+                embedding BLOB
+             );"
+        ))?;
+        Ok(())
+    }
+
+    fn insert(conn: &Connection, table: &str, items: &[ProcessedItem]) -> Result<()> {
+        let mut insert = conn.prepare(&format!(
+            "INSERT INTO {} (item_id, question, answer, citation, source_file, source_section, source_chunk, source_image, source_modified, extra, hash, embedding) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            table
+        ))?;
+        for item in items {
+            insert.execute(params![
+                item.id,
+                item.question,
+                item.answer,
+                item.citation,
+                item.source.as_ref().map(|s| s.file.as_str()),
+                item.source.as_ref().map(|s| s.section as i64),
+                item.source.as_ref().and_then(|s| s.chunk.as_deref()),
+                item.source.as_ref().and_then(|s| s.image.as_deref()),
+                item.source.as_ref().and_then(|s| s.modified).map(|d| d.to_string()),
+                (!item.extra.is_empty()).then(|| Value::Object(item.extra.clone()).to_string()),
+                item.hash,
+                item.embedding.as_deref().map(embedding_blob),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DatasetWriter for SqliteWriter {
+    fn describe(&self) -> String {
+        format!("{:?} table {}", self.path, self.table)
+    }
+
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()> {
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        Self::create_table(&tx, &self.table)?;
+        Self::insert(&tx, &self.table, items)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        let conn = self.connect()?;
+        Self::create_table(&conn, &self.table)?;
+        Ok(Box::new(SqliteStream { conn, table: &self.table }))
+    }
+
+    async fn write_chunks(&self, chunks: &[ChunkRecord]) -> Result<()> {
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        tx.execute_batch(&format!(
+            "DROP TABLE IF EXISTS {table}_chunks;
+             CREATE TABLE {table}_chunks (
+                id INTEGER PRIMARY KEY,
+                file TEXT NOT NULL,
+                section INTEGER NOT NULL,
+                chunk_id TEXT NOT NULL,
+                text TEXT NOT NULL,
+                embedding BLOB NOT NULL
+             );",
+            table = self.table
+        ))?;
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT INTO {}_chunks (file, section, chunk_id, text, embedding) VALUES (?1, ?2, ?3, ?4, ?5)",
+                self.table
+            ))?;
+            for chunk in chunks {
+                insert.execute(params![chunk.file, chunk.section as i64, chunk.id, chunk.text, embedding_blob(&chunk.embedding)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Inserts each batch in its own transaction.
+struct SqliteStream<'a> {
+    conn: Connection,
+    table: &'a str,
+}
+
+#[async_trait]
+impl ItemStream for SqliteStream<'_> {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        SqliteWriter::insert(&tx, self.table, items)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// POSTs items as `{"items": [...]}` in batches.
+pub struct HttpWriter {
+    client: Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    batch_size: usize,
+    format: RecordFormat,
+    system_prompts: SystemPrompts,
+    mapping: FieldMapping,
+}
+
+impl HttpWriter {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            batch_size: 100,
+            format: RecordFormat::Qa,
+            system_prompts: SystemPrompts::default(),
+            mapping: FieldMapping::default(),
+        }
+    }
+
+    /// Headers sent with every request, e.g. `Authorization`.
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_system_prompts(mut self, system_prompts: SystemPrompts) -> Self {
+        self.system_prompts = system_prompts;
+        self
+    }
+
+    pub fn with_mapping(mut self, mapping: FieldMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+}
+
+impl HttpWriter {
+    fn record(&self, item: &ProcessedItem) -> Result<Value> {
+        let system = self.system_prompts.for_item(item)?;
+        Ok(self.mapping.apply(self.format.to_record(item, system.as_deref())?))
+    }
+
+    async fn post(&self, records: &[Value]) -> Result<()> {
+        let mut request = self.client.post(&self.url).json(&json!({ "items": records }));
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(anyhow!("{} rejected items ({}): {}", self.url, status, response.text().await?));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DatasetWriter for HttpWriter {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()> {
+        for batch in items.chunks(self.batch_size) {
+            let records = batch
+                .iter()
+                .map(|item| self.record(item))
+                .collect::<Result<Vec<_>>>()?;
+            self.post(&records).await?;
+        }
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        Ok(Box::new(HttpStream { writer: self, pending: Vec::new() }))
+    }
+}
+
+/// Posts a batch whenever `batch_size` records are pending.
+struct HttpStream<'a> {
+    writer: &'a HttpWriter,
+    pending: Vec<Value>,
+}
+
+#[async_trait]
+impl ItemStream for HttpStream<'_> {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        for item in items {
+            self.pending.push(self.writer.record(item)?);
+        }
+        while self.pending.len() >= self.writer.batch_size {
+            let batch: Vec<Value> = self.pending.drain(..self.writer.batch_size).collect();
+            self.writer.post(&batch).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.writer.post(&self.pending).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints items as JSONL records on standard output, for piping into other tools.
+pub struct StdoutWriter {
+    format: RecordFormat,
+    system_prompts: SystemPrompts,
+    mapping: FieldMapping,
+}
+
+impl StdoutWriter {
+    pub fn new(format: RecordFormat) -> Self {
+        Self {
+            format,
+            system_prompts: SystemPrompts::default(),
+            mapping: FieldMapping::default(),
+        }
+    }
+
+    pub fn with_system_prompts(mut self, system_prompts: SystemPrompts) -> Self {
+        self.system_prompts = system_prompts;
+        self
+    }
+
+    pub fn with_mapping(mut self, mapping: FieldMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+}
+
+#[async_trait]
+impl DatasetWriter for StdoutWriter {
+    fn describe(&self) -> String {
+        "stdout".to_string()
+    }
+
+    async fn write(&self, items: &[ProcessedItem]) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        for item in items {
+            let system = self.system_prompts.for_item(item)?;
+            writeln!(stdout, "{}", self.mapping.apply(self.format.to_record(item, system.as_deref())?))?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<Box<dyn ItemStream + '_>> {
+        Ok(Box::new(StdoutStream { writer: self }))
+    }
+}
+
+/// Prints each batch as it arrives.
+struct StdoutStream<'a> {
+    writer: &'a StdoutWriter,
+}
+
+#[async_trait]
+impl ItemStream for StdoutStream<'_> {
+    async fn append(&mut self, items: &[ProcessedItem]) -> Result<()> {
+        self.writer.write(items).await
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use arrow_array::{Array, Float32Array, ListArray};
+    use arrow_ipc::reader::{FileReader, StreamReader};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn items() -> Vec<ProcessedItem> {
+        [("Is it free?", "Yes."), ("How do I export it?", "Run export."), ("Does it run offline?", "It does.")]
+            .iter()
+            .enumerate()
+            .map(|(section, (question, answer))| {
+                serde_json::from_value(json!({
+                    "question": question,
+                    "answer": answer,
+                    "source": { "file": "docs/a.md", "section": section },
+                    "topic": "basics",
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("llmds-writer-{:016x}", rand::random::<u64>()))
+    }
+
+    fn sink(config: Value, output_dir: &Path) -> Box<dyn DatasetWriter> {
+        from_config(&serde_json::from_value(config).unwrap(), output_dir)
+    }
+
+    #[tokio::test]
+    async fn jsonl_sinks_stream_items_to_their_file() {
+        let dir = temp_dir();
+        for path in ["out/items.jsonl", "out/items.json"] {
+            let writer = sink(json!({ "type": "jsonl", "path": path }), &dir);
+            let mut stream = writer.open().await.unwrap();
+            stream.append(&items()[..1]).await.unwrap();
+            stream.append(&items()[1..]).await.unwrap();
+            stream.finish().await.unwrap();
+
+            let read = dataset::read_stored_items(&dir.join(path)).unwrap();
+            let questions: Vec<&str> = read.iter().map(|item| item.question.as_str()).collect();
+            assert_eq!(questions, ["Is it free?", "How do I export it?", "Does it run offline?"], "{path}");
+            assert_eq!(read[2].source.as_ref().map(|source| source.section), Some(2));
+            assert_eq!(read[2].extra["topic"], "basics");
+        }
+        assert!(fs::read_to_string(dir.join("out/items.json")).unwrap().starts_with("[\n  {"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_sinks_replace_their_table() {
+        let dir = temp_dir();
+        let writer = sink(json!({ "type": "sqlite", "path": "items.db", "table": "qa" }), &dir);
+        writer.write(&items()).await.unwrap();
+        writer.write(&items()[..2]).await.unwrap();
+
+        let conn = Connection::open(dir.join("items.db")).unwrap();
+        let mut rows = conn.prepare("SELECT question, source_file, source_section, extra FROM qa ORDER BY id").unwrap();
+        let rows: Vec<(String, String, i64, String)> =
+            rows.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap().map(|row| row.unwrap()).collect();
+        assert_eq!(
+            rows,
+            [
+                ("Is it free?".to_string(), "docs/a.md".to_string(), 0, r#"{"topic":"basics"}"#.to_string()),
+                ("How do I export it?".to_string(), "docs/a.md".to_string(), 1, r#"{"topic":"basics"}"#.to_string()),
+            ]
+        );
+        assert!(sink(json!({ "type": "sqlite", "path": "items.db", "table": "qa; DROP" }), &dir).write(&items()).await.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_sinks_post_batches() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let seen = batches.clone();
+        let receive = move |Json(body): Json<Value>| async move {
+            seen.lock().unwrap().push(body["items"].as_array().cloned().unwrap_or_default());
+            Json(json!({}))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/import", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, Router::new().route("/import", post(receive))).await });
+
+        let writer = sink(json!({ "type": "http", "url": url, "batch_size": 2, "format": "openai" }), Path::new("."));
+        let mut stream = writer.open().await.unwrap();
+        stream.append(&items()).await.unwrap();
+        stream.finish().await.unwrap();
+
+        let batches = batches.lock().unwrap();
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(batches[1][0]["messages"][0], json!({ "role": "user", "content": "Does it run offline?" }));
+    }
+
+    /// A row's question, source section, extra fields and embedding.
+    type Row = (String, Option<i64>, Option<String>, Option<Vec<f32>>);
+
+    fn rows(batches: &[RecordBatch]) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for batch in batches {
+            let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+            let (question, section, extra, embedding) = (column("question"), column("source_section"), column("extra"), column("embedding"));
+            let question = question.as_any().downcast_ref::<StringArray>().unwrap();
+            let section = section.as_any().downcast_ref::<Int64Array>().unwrap();
+            let extra = extra.as_any().downcast_ref::<StringArray>().unwrap();
+            let embedding = embedding.as_any().downcast_ref::<ListArray>().unwrap();
+            for row in 0..batch.num_rows() {
+                let floats = (!embedding.is_null(row)).then(|| {
+                    let values = embedding.value(row);
+                    values.as_any().downcast_ref::<Float32Array>().unwrap().values().to_vec()
+                });
+                rows.push((
+                    question.value(row).to_string(),
+                    (!section.is_null(row)).then(|| section.value(row)),
+                    (!extra.is_null(row)).then(|| extra.value(row).to_string()),
+                    floats,
+                ));
+            }
+        }
+        rows
+    }
+
+    /// `items()`, the last without a source, extra fields or embedding.
+    fn columnar_items() -> Vec<ProcessedItem> {
+        let mut items = items();
+        items[0].embedding = Some(vec![0.5, -1.0]);
+        items[1].embedding = Some(vec![2.0]);
+        items[2].source = None;
+        items[2].extra.clear();
+        items
+    }
+
+    fn expected_rows() -> Vec<Row> {
+        let extra = Some(r#"{"topic":"basics"}"#.to_string());
+        vec![
+            ("Is it free?".to_string(), Some(0), extra.clone(), Some(vec![0.5, -1.0])),
+            ("How do I export it?".to_string(), Some(1), extra, Some(vec![2.0])),
+            ("Does it run offline?".to_string(), None, None, None),
+        ]
+    }
+
+    #[tokio::test]
+    async fn parquet_files_read_back() {
+        let dir = temp_dir();
+        let writer = ParquetWriter::new(dir.join("items.parquet"));
+        let mut stream = writer.open().await.unwrap();
+        stream.append(&columnar_items()).await.unwrap();
+        stream.finish().await.unwrap();
+        writer
+            .write_chunks(&[ChunkRecord { file: "docs/a.md".to_string(), section: 0, id: "9f3c".to_string(), text: "Free.".to_string(), embedding: vec![1.0] }])
+            .await
+            .unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(dir.join("items.parquet")).unwrap()).unwrap().build().unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches[0].schema(), ArrowWriter::schema(ITEM_SCHEMA).unwrap());
+        assert_eq!(rows(&batches), expected_rows());
+        let chunks = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(dir.join("items_chunks.parquet")).unwrap()).unwrap().build().unwrap();
+        assert_eq!(chunks.map(|batch| batch.unwrap().num_rows()).sum::<usize>(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn arrow_files_and_streams_read_back() {
+        let dir = temp_dir();
+        for name in ["items.arrow", "items.arrows"] {
+            ArrowWriter::new(dir.join(name)).write(&columnar_items()).await.unwrap();
+        }
+
+        let file = FileReader::try_new(fs::File::open(dir.join("items.arrow")).unwrap(), None).unwrap();
+        let batches: Vec<RecordBatch> = file.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches[0].schema(), ArrowWriter::schema(ITEM_SCHEMA).unwrap());
+        assert_eq!(rows(&batches), expected_rows());
+        let stream = StreamReader::try_new(fs::File::open(dir.join("items.arrows")).unwrap(), None).unwrap();
+        let batches: Vec<RecordBatch> = stream.map(|batch| batch.unwrap()).collect();
+        assert_eq!(rows(&batches), expected_rows());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}